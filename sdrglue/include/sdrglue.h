@@ -0,0 +1,223 @@
+#ifndef SDRGLUE_H
+#define SDRGLUE_H
+
+/* Generated with cbindgen from src/ffi.rs. Do not edit. */
+
+#include <stddef.h>
+
+/**
+ * Version of the C API, incremented on incompatible changes.
+ */
+#define SDRGLUE_API_VERSION 1
+
+/**
+ * Analysis filter bank splitting a wideband signal into channels.
+ */
+typedef struct SdrglueChannelizer SdrglueChannelizer;
+
+/**
+ * Synthesis filter bank combining channels into a wideband signal.
+ */
+typedef struct SdrglueDechannelizer SdrglueDechannelizer;
+
+/**
+ * Complex sample with interleaved real and imaginary parts,
+ * the same layout as float complex in C.
+ */
+typedef struct SdrglueComplex {
+  float re;
+  float im;
+} SdrglueComplex;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ * Version of the C API implemented by the library.
+ */
+uint32_t sdrglue_api_version(void);
+
+/**
+ * Create a channelizer for input signal with given sample rate,
+ * center frequency and bin spacing in Hertz.
+ * Sample rate should be an integer multiple of 2 * bin spacing.
+ */
+struct SdrglueChannelizer *sdrglue_channelizer_new(double sample_rate,
+                                                   double center_frequency,
+                                                   double bin_spacing);
+
+/**
+ * Add an output channel with given sample rate and center frequency.
+ * Return index of the channel.
+ *
+ * # Safety
+ * channelizer must be a pointer returned by sdrglue_channelizer_new.
+ */
+size_t sdrglue_channelizer_add_channel(struct SdrglueChannelizer *channelizer,
+                                       double sample_rate,
+                                       double center_frequency);
+
+/**
+ * Number of input samples processed at a time.
+ *
+ * # Safety
+ * channelizer must be a pointer returned by sdrglue_channelizer_new.
+ */
+size_t sdrglue_channelizer_input_block_size(const struct SdrglueChannelizer *channelizer);
+
+/**
+ * Push any number of input samples. Output signals produced
+ * from them can be read with sdrglue_channelizer_output.
+ *
+ * # Safety
+ * channelizer must be a pointer returned by sdrglue_channelizer_new
+ * and input must point to length samples.
+ */
+void sdrglue_channelizer_push(struct SdrglueChannelizer *channelizer,
+                              const struct SdrglueComplex *input,
+                              size_t length);
+
+/**
+ * Output signal of a channel produced by the previous push.
+ * The number of samples is written to length.
+ * The returned pointer is valid until the next push.
+ *
+ * # Safety
+ * channelizer must be a pointer returned by sdrglue_channelizer_new,
+ * channel an index returned by sdrglue_channelizer_add_channel
+ * and length must point to writable memory.
+ */
+const struct SdrglueComplex *sdrglue_channelizer_output(const struct SdrglueChannelizer *channelizer,
+                                                        size_t channel,
+                                                        size_t *length);
+
+/**
+ * Number of output samples a channel produces
+ * for each block of input samples.
+ *
+ * # Safety
+ * channelizer must be a pointer returned by sdrglue_channelizer_new
+ * and channel an index returned by sdrglue_channelizer_add_channel.
+ */
+size_t sdrglue_channelizer_channel_block_size(const struct SdrglueChannelizer *channelizer,
+                                              size_t channel);
+
+/**
+ * Process as many input samples as fit in the output space
+ * of every channel. Output of each channel is written to
+ * the buffer in outputs with room for output_space samples,
+ * and the number of samples written to produced.
+ * Return the number of input samples consumed.
+ *
+ * # Safety
+ * channelizer must be a pointer returned by sdrglue_channelizer_new,
+ * input must point to input_length samples, and outputs
+ * and produced must be arrays with an element for each channel.
+ */
+size_t sdrglue_channelizer_work(struct SdrglueChannelizer *channelizer,
+                                const struct SdrglueComplex *input,
+                                size_t input_length,
+                                struct SdrglueComplex *const *outputs,
+                                size_t output_space,
+                                size_t *produced);
+
+/**
+ * Release a channelizer.
+ *
+ * # Safety
+ * channelizer must be a pointer returned by sdrglue_channelizer_new
+ * or NULL, and must not be used afterwards.
+ */
+void sdrglue_channelizer_free(struct SdrglueChannelizer *channelizer);
+
+/**
+ * Create a dechannelizer for output signal with given sample rate,
+ * center frequency and bin spacing in Hertz.
+ * Sample rate should be an integer multiple of 2 * bin spacing.
+ */
+struct SdrglueDechannelizer *sdrglue_dechannelizer_new(double sample_rate,
+                                                       double center_frequency,
+                                                       double bin_spacing);
+
+/**
+ * Add an input channel with given sample rate and center frequency.
+ * Return index of the channel.
+ *
+ * # Safety
+ * dechannelizer must be a pointer returned by sdrglue_dechannelizer_new.
+ */
+size_t sdrglue_dechannelizer_add_channel(struct SdrglueDechannelizer *dechannelizer,
+                                         double sample_rate,
+                                         double center_frequency);
+
+/**
+ * Number of input samples needed from a channel for each output block.
+ *
+ * # Safety
+ * dechannelizer must be a pointer returned by sdrglue_dechannelizer_new
+ * and channel an index returned by sdrglue_dechannelizer_add_channel.
+ */
+size_t sdrglue_dechannelizer_channel_block_size(const struct SdrglueDechannelizer *dechannelizer,
+                                                size_t channel);
+
+/**
+ * Produce a block of output signal. inputs is an array with
+ * a pointer for each channel to its block of input samples,
+ * of the length given by sdrglue_dechannelizer_channel_block_size.
+ * The number of output samples is written to length.
+ * The returned pointer is valid until the next call.
+ *
+ * # Safety
+ * dechannelizer must be a pointer returned by sdrglue_dechannelizer_new,
+ * inputs must point to a valid block for each channel
+ * and length must point to writable memory.
+ */
+const struct SdrglueComplex *sdrglue_dechannelizer_process(struct SdrglueDechannelizer *dechannelizer,
+                                                           const struct SdrglueComplex *const *inputs,
+                                                           size_t *length);
+
+/**
+ * Number of output samples in each output block.
+ *
+ * # Safety
+ * dechannelizer must be a pointer returned by sdrglue_dechannelizer_new.
+ */
+size_t sdrglue_dechannelizer_output_block_size(const struct SdrglueDechannelizer *dechannelizer);
+
+/**
+ * Produce as many blocks of output as there is input for in
+ * every channel and space for in output. inputs is an array
+ * with a pointer to input samples of each channel, and
+ * input_lengths the number of samples available in each.
+ * Output is written to output, with room for output_space samples.
+ * Return the number of blocks produced. Each block consumes
+ * sdrglue_dechannelizer_channel_block_size samples from each
+ * channel and produces sdrglue_dechannelizer_output_block_size
+ * samples of output.
+ *
+ * # Safety
+ * dechannelizer must be a pointer returned by sdrglue_dechannelizer_new,
+ * inputs and input_lengths must be arrays with an element
+ * for each channel, and output must have room for output_space samples.
+ */
+size_t sdrglue_dechannelizer_work(struct SdrglueDechannelizer *dechannelizer,
+                                  const struct SdrglueComplex *const *inputs,
+                                  const size_t *input_lengths,
+                                  struct SdrglueComplex *output,
+                                  size_t output_space);
+
+/**
+ * Release a dechannelizer.
+ *
+ * # Safety
+ * dechannelizer must be a pointer returned by sdrglue_dechannelizer_new
+ * or NULL, and must not be used afterwards.
+ */
+void sdrglue_dechannelizer_free(struct SdrglueDechannelizer *dechannelizer);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* SDRGLUE_H */
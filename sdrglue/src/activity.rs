@@ -0,0 +1,121 @@
+//! Channel activity log for unattended occupancy monitoring.
+//!
+//! Each time squelch of a channel closes, one record is written
+//! with the time squelch opened, channel frequency, how long it
+//! was open and the peak power. If the file name contains
+//! {frequency}, each channel gets its own file, with the
+//! placeholder replaced by the frequency in Hertz.
+
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::time::{Duration, SystemTime};
+
+use crate::events::{Event, EventListener, Value};
+
+/// Log file, written as JSON lines if its name ends with
+/// .json or .jsonl, and as CSV otherwise.
+struct LogFile {
+    json: bool,
+    output: BufWriter<File>,
+}
+
+pub struct ActivityLogger {
+    path: String,
+    files: HashMap<String, LogFile>,
+}
+
+impl ActivityLogger {
+    pub fn new(path: &str) -> Self {
+        Self {
+            path: path.to_string(),
+            files: HashMap::new(),
+        }
+    }
+
+    fn write(&mut self, event: &Event) -> std::io::Result<()> {
+        let field = |key: &str| event.fields.iter().find_map(|(k, value)| match value {
+            Value::Number(number) if *k == key => Some(*number),
+            _ => None,
+        });
+        let (Some(frequency), Some(duration), Some(peak_db)) = (field("frequency"), field("duration"), field("peak_db")) else {
+            return Ok(());
+        };
+        let peak_dbm = field("peak_dbm");
+        let opened = event.time - Duration::from_secs_f64(duration);
+
+        let path = self.path.replace("{frequency}", &format!("{:.0}", frequency));
+        let file = match self.files.entry(path) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                let path = entry.key();
+                // Keep earlier records when restarted.
+                let existing = std::fs::metadata(path).is_ok_and(|metadata| metadata.len() > 0);
+                let mut file = LogFile {
+                    json: path.ends_with(".json") || path.ends_with(".jsonl"),
+                    output: BufWriter::new(OpenOptions::new().create(true).append(true).open(path)?),
+                };
+                if !file.json && !existing {
+                    writeln!(file.output, "time,frequency,duration,peak_db,peak_dbm")?;
+                }
+                entry.insert(file)
+            },
+        };
+        if file.json {
+            let mut record = Event::new("activity")
+                .with("frequency", frequency)
+                .with("duration", duration)
+                .with("peak_db", peak_db);
+            record.time = opened;
+            if let Some(peak_dbm) = peak_dbm {
+                record = record.with("peak_dbm", peak_dbm);
+            }
+            writeln!(file.output, "{}", record.to_json())?;
+        } else {
+            writeln!(file.output, "{:.3},{},{:.3},{:.1},{}",
+                opened.duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default().as_secs_f64(), frequency, duration, peak_db, peak_dbm.map_or(String::new(), |dbm| format!("{:.1}", dbm)))?;
+        }
+        // Flush every record so the log is up to date
+        // even if the program is stopped abruptly.
+        file.output.flush()
+    }
+}
+
+impl EventListener for ActivityLogger {
+    fn event(&mut self, event: &Event) {
+        if event.kind != "squelch_close" {
+            return;
+        }
+        if let Err(err) = self.write(event) {
+            eprintln!("Error writing activity log {}: {}", self.path, err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_activity_log() {
+        let directory = std::env::temp_dir().join(format!("sdrglue-activity-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let path = directory.join("{frequency}.csv");
+        let mut logger = ActivityLogger::new(path.to_str().unwrap());
+        let mut event = Event::new("squelch_close")
+            .with("frequency", 145.5e6)
+            .with("power_db", -60.0)
+            .with("duration", 2.5)
+            .with("peak_db", -31.34);
+        event.time = SystemTime::UNIX_EPOCH + Duration::from_secs(1700000010);
+        logger.event(&event);
+        logger.event(&Event::new("squelch_open").with("frequency", 145.5e6));
+        logger.event(&event);
+        let log = std::fs::read_to_string(directory.join("145500000.csv")).unwrap();
+        std::fs::remove_dir_all(&directory).unwrap();
+        assert_eq!(log, "time,frequency,duration,peak_db,peak_dbm\n\
+            1700000007.500,145500000,2.500,-31.3,\n\
+            1700000007.500,145500000,2.500,-31.3,\n");
+    }
+}
@@ -0,0 +1,102 @@
+//! Automatic frequency control for receive channels.
+//!
+//! Carrier offset is estimated from the mean phase difference
+//! between consecutive samples, which corresponds to the DC offset
+//! of an FM discriminator. This works for FM and FSK signals
+//! whose mean frequency is at the carrier frequency.
+//! The channel is mixed by the estimated offset before it is
+//! processed, so drifting or Doppler shifted transmitters stay
+//! centered in the channel filter.
+
+use crate::{Sample, ComplexSample};
+
+/// Fraction of the estimated offset corrected after each block.
+const LOOP_GAIN: f64 = 0.1;
+/// Minimum correlation between consecutive samples for
+/// a block to be used for estimation.
+/// Noise has a low correlation, so the correction
+/// does not wander around while no signal is received.
+const MIN_CORRELATION: Sample = 0.5;
+
+pub struct Afc {
+    sample_rate: f64,
+    /// Maximum correction in Hertz.
+    range: f64,
+    /// Current correction in Hertz.
+    frequency: f64,
+    /// Mixer phase in cycles.
+    phase: f64,
+    previous_sample: ComplexSample,
+    output: Vec<ComplexSample>,
+}
+
+impl Afc {
+    pub fn new(sample_rate: f64, range: f64) -> Self {
+        Self {
+            sample_rate,
+            range,
+            frequency: 0.0,
+            phase: 0.0,
+            previous_sample: ComplexSample::ZERO,
+            output: Vec::new(),
+        }
+    }
+
+    /// Current frequency offset being corrected, in Hertz.
+    pub fn frequency(&self) -> f64 {
+        self.frequency
+    }
+
+    /// Correct the frequency offset of a block of samples
+    /// and update the estimate.
+    pub fn process(&mut self, input: &[ComplexSample]) -> &[ComplexSample] {
+        let increment = -self.frequency / self.sample_rate;
+        let mut correlation = ComplexSample::ZERO;
+        let mut power: Sample = 0.0;
+        self.output.clear();
+        for &sample in input {
+            let mixed = sample * ComplexSample::from_polar(1.0, (2.0 * std::f64::consts::PI * self.phase) as Sample);
+            self.phase = (self.phase + increment).fract();
+            correlation += mixed * self.previous_sample.conj();
+            power += mixed.norm_sqr();
+            self.previous_sample = mixed;
+            self.output.push(mixed);
+        }
+        if power > 0.0 && correlation.norm() >= MIN_CORRELATION * power {
+            let error = correlation.arg() as f64 * self.sample_rate / (2.0 * std::f64::consts::PI);
+            self.frequency = (self.frequency + LOOP_GAIN * error).clamp(-self.range, self.range);
+        }
+        &self.output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tracking() {
+        let sample_rate = 48000.0;
+        let mut afc = Afc::new(sample_rate, 5000.0);
+        let offset = 1500.0;
+        let mut n = 0;
+        for _ in 0..200 {
+            let block: Vec<ComplexSample> = (0..480).map(|_| {
+                n += 1;
+                ComplexSample::from_polar(1.0, (2.0 * std::f64::consts::PI * offset * n as f64 / sample_rate) as Sample)
+            }).collect();
+            afc.process(&block);
+        }
+        assert!((afc.frequency() - offset).abs() < 1.0);
+
+        // Offsets beyond the range are corrected only up to it.
+        let mut afc = Afc::new(sample_rate, 1000.0);
+        let block: Vec<ComplexSample> = (0..48000).map(|i|
+            ComplexSample::from_polar(1.0, (2.0 * std::f64::consts::PI * offset * i as f64 / sample_rate) as Sample)
+        ).collect();
+        for chunk in block.chunks(480) {
+            afc.process(chunk);
+        }
+        assert_eq!(afc.frequency(), 1000.0);
+    }
+}
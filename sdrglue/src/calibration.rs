@@ -0,0 +1,111 @@
+//! Calibration of power readings to absolute power.
+//!
+//! Power measured from the signal is relative to the
+//! full scale of the SDR (dBFS). A calibration table gives
+//! the gain from antenna connector to SDR samples as a function
+//! of frequency, so readings can be converted to approximate dBm.
+//!
+//! The table is read from a text file with one point per line,
+//! each line containing frequency in Hertz and the value in dB
+//! to add to dBFS readings to get dBm, separated by whitespace
+//! or a comma. Lines starting with # are comments.
+//! Values between points are interpolated linearly.
+//! For example:
+//! ```text
+//! # frequency, dBFS to dBm
+//! 144e6, -38.5
+//! 146e6, -38.0
+//! 430e6, -35.2
+//! ```
+
+use std::sync::OnceLock;
+
+use crate::Sample;
+use crate::configuration;
+
+pub struct Calibration {
+    /// Points of the table sorted by frequency.
+    points: Vec<(f64, Sample)>,
+}
+
+impl Calibration {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut points = Vec::new();
+        for line in text.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut fields = line.split(|c: char| c == ',' || c.is_whitespace()).filter(|f| !f.is_empty());
+            let (Some(frequency), Some(offset), None) = (fields.next(), fields.next(), fields.next()) else {
+                return Err(format!("Invalid calibration line: {}", line));
+            };
+            points.push((
+                frequency.parse::<f64>().map_err(|_| format!("Invalid frequency: {}", frequency))?,
+                offset.parse::<Sample>().map_err(|_| format!("Invalid value: {}", offset))?,
+            ));
+        }
+        if points.is_empty() {
+            return Err("Calibration table is empty".to_string());
+        }
+        points.sort_by(|a, b| a.0.total_cmp(&b.0));
+        Ok(Self { points })
+    }
+
+    /// Value in dB to add to a dBFS reading at given frequency
+    /// to get dBm. Outside the table, the nearest point is used.
+    pub fn offset(&self, frequency: f64) -> Sample {
+        let index = self.points.partition_point(|&(f, _)| f < frequency);
+        if index == 0 {
+            return self.points[0].1;
+        }
+        if index == self.points.len() {
+            return self.points[index - 1].1;
+        }
+        let (f0, v0) = self.points[index - 1];
+        let (f1, v1) = self.points[index];
+        v0 + (v1 - v0) * ((frequency - f0) / (f1 - f0)) as Sample
+    }
+}
+
+static CALIBRATION: OnceLock<Calibration> = OnceLock::new();
+
+/// Load calibration table given on command line, if any.
+pub fn init(cli: &configuration::Cli) {
+    if let Some(path) = &cli.calibration {
+        // TODO: handle errors more nicely
+        let text = std::fs::read_to_string(path)
+            .unwrap_or_else(|e| panic!("Could not read calibration file {}: {}", path, e));
+        let calibration = Calibration::parse(&text)
+            .unwrap_or_else(|e| panic!("Invalid calibration file {}: {}", path, e));
+        let _ = CALIBRATION.set(calibration);
+    }
+}
+
+/// Return true if a calibration table is loaded.
+pub fn loaded() -> bool {
+    CALIBRATION.get().is_some()
+}
+
+/// Value in dB to add to a dBFS reading at given frequency
+/// to get dBm, or None if no calibration table is loaded.
+pub fn dbm_offset(frequency: f64) -> Option<Sample> {
+    CALIBRATION.get().map(|calibration| calibration.offset(frequency))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_interpolation() {
+        let calibration = Calibration::parse("# comment\n200e6 -30\n100e6, -40\n\n300e6\t-35\n").unwrap();
+        assert_eq!(calibration.offset(50e6), -40.0);
+        assert_eq!(calibration.offset(100e6), -40.0);
+        assert_eq!(calibration.offset(150e6), -35.0);
+        assert_eq!(calibration.offset(250e6), -32.5);
+        assert_eq!(calibration.offset(400e6), -35.0);
+        assert!(Calibration::parse("100e6").is_err());
+        assert!(Calibration::parse("").is_err());
+    }
+}
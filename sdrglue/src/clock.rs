@@ -0,0 +1,94 @@
+//! Current time for timestamps in outputs.
+//!
+//! Normally this is the system clock. In reproducible mode,
+//! time is instead counted from processed samples, starting
+//! from SIMULATED_START, so that outputs do not depend on
+//! when or how fast processing was run.
+
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::{Duration, SystemTime};
+
+/// Start of simulated time, 2000-01-01 00:00:00 UTC.
+const SIMULATED_START: Duration = Duration::from_secs(946684800);
+
+static REPRODUCIBLE: AtomicBool = AtomicBool::new(false);
+/// Simulated time elapsed since start in nanoseconds.
+static ELAPSED: AtomicU64 = AtomicU64::new(0);
+
+/// Switch to reproducible mode.
+/// Should be called before any processing is started.
+pub fn set_reproducible() {
+    REPRODUCIBLE.store(true, Ordering::Relaxed);
+}
+
+pub fn reproducible() -> bool {
+    REPRODUCIBLE.load(Ordering::Relaxed)
+}
+
+/// Set simulated time elapsed since start in seconds.
+pub fn set_elapsed(seconds: f64) {
+    ELAPSED.store((seconds * 1e9).round() as u64, Ordering::Relaxed);
+}
+
+pub fn now() -> SystemTime {
+    if reproducible() {
+        SystemTime::UNIX_EPOCH + SIMULATED_START + Duration::from_nanos(ELAPSED.load(Ordering::Relaxed))
+    } else {
+        SystemTime::now()
+    }
+}
+
+/// Current time in seconds since Unix epoch.
+pub fn now_seconds() -> f64 {
+    now().duration_since(SystemTime::UNIX_EPOCH).map(|t| t.as_secs_f64()).unwrap_or(0.0)
+}
+
+/// Split a time in seconds since Unix epoch into UTC year,
+/// month, day and milliseconds since midnight.
+fn utc_date(seconds: f64) -> (i64, i64, i64, i64) {
+    let milliseconds = (seconds * 1e3).floor() as i64;
+    let days = milliseconds.div_euclid(86_400_000);
+    let time = milliseconds.rem_euclid(86_400_000);
+    // Civil date from days since epoch, from
+    // http://howardhinnant.github.io/date_algorithms.html
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z.rem_euclid(146097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day, time)
+}
+
+/// Format a time in seconds since Unix epoch as UTC
+/// in a form usable in file names, like 20240131T235959.123Z.
+pub fn utc_timestamp(seconds: f64) -> String {
+    let (year, month, day, time) = utc_date(seconds);
+    format!("{:04}{:02}{:02}T{:02}{:02}{:02}.{:03}Z", year, month, day,
+        time / 3_600_000, time / 60_000 % 60, time / 1000 % 60, time % 1000)
+}
+
+/// Format a time in seconds since Unix epoch as UTC
+/// in ISO 8601 extended format, like 2024-01-31T23:59:59.123Z.
+pub fn utc_datetime(seconds: f64) -> String {
+    let (year, month, day, time) = utc_date(seconds);
+    format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z", year, month, day,
+        time / 3_600_000, time / 60_000 % 60, time / 1000 % 60, time % 1000)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_utc_timestamp() {
+        assert_eq!(utc_timestamp(0.0), "19700101T000000.000Z");
+        assert_eq!(utc_timestamp(SIMULATED_START.as_secs_f64()), "20000101T000000.000Z");
+        assert_eq!(utc_timestamp(1709251199.5), "20240229T235959.500Z");
+        assert_eq!(utc_timestamp(1709251200.0), "20240301T000000.000Z");
+        assert_eq!(utc_datetime(1709251199.5), "2024-02-29T23:59:59.500Z");
+    }
+}
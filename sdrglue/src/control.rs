@@ -0,0 +1,524 @@
+//! Control API for changing things while running.
+//!
+//! Clients connect to a TCP socket and send commands
+//! as JSON objects, one per line. Each command gets a response
+//! as a JSON object on one line, with "ok" set to true or false
+//! and an "error" message if the command failed.
+//! Malformed commands only get an error response,
+//! so that clients on the network cannot stop the radio.
+//!
+//! Commands are handled in the main loop between signal processing
+//! blocks, so they can change processing state without any locking.
+//!
+//! Available commands:
+//! ```text
+//! {"command":"inject","frequency":145.5e6,"level":-60,"modulation":"fm"}
+//! {"command":"stop_inject"}
+//! {"command":"sensors"}
+//! {"command":"output","action":"preamp_on"}
+//! {"command":"enable","channel":"rx0"}
+//! {"command":"disable","channel":"tx1"}
+//! {"command":"save_profile"}
+//! {"command":"channels"}
+//! {"command":"status"}
+//! {"command":"delay","channel":"rx0","delay":0.0125}
+//! {"command":"arm_challenge"}
+//! {"command":"arm","challenge":"...","response":"...","duration":60}
+//! {"command":"disarm"}
+//! {"command":"protocol","protocol":1}
+//! {"command":"schema"}
+//! ```
+//! The "protocol" command returns the protocol version, which is
+//! the lower of PROTOCOL_VERSION and the version given by the client,
+//! together with the capabilities and commands available, so that
+//! frontends can adapt to the features in use. Fields are only added
+//! to commands and responses within a protocol version, so clients
+//! not sending the command keep working. The "schema" command returns
+//! JSON schemas of all commands and responses, also available in
+//! the file control_schema.json.
+//! Arming commands are described in the interlock module.
+//! A saved profile contains the configuration with changes
+//! made using the control API, and can be restored on startup
+//! with --profile. Profiles are only saved to the file given
+//! with --profile, so clients cannot write other files.
+//!
+//! Commands for other instances of a federation are described
+//! in the federation module. On the hub, "instances" is listed
+//! in the commands and "federation" in the capabilities.
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::mpsc;
+
+use serde_json::{json, Value};
+
+use crate::Sample;
+use crate::configuration;
+use crate::events;
+use crate::federation;
+use crate::http_api;
+use crate::injection;
+use crate::rx_dsp::{self, RxDsp};
+use crate::remote;
+use crate::schedule::ChannelRef;
+use crate::tx_dsp::TxDsp;
+use crate::sensors::SensorMonitor;
+use crate::soapyconfig::SoapyIo;
+use crate::switching;
+use crate::templates::ExpandedChannel;
+
+/// Version of the control protocol, incremented on
+/// changes that are not compatible with older clients.
+pub const PROTOCOL_VERSION: u64 = 1;
+
+/// JSON schemas of commands and responses.
+const SCHEMA: &str = include_str!("control_schema.json");
+
+/// Commands handled by the control API.
+const COMMANDS: &[&str] = &[
+    "protocol", "schema", "inject", "stop_inject", "sensors", "output", "enable", "disable",
+    "delay", "arm_challenge", "arm", "disarm", "save_profile", "channels", "status",
+];
+
+/// Commands handled by the federation hub.
+const HUB_ONLY_COMMANDS: &[&str] = &["instances"];
+
+struct Request {
+    command: Value,
+    /// Channel to send the response to.
+    reply: mpsc::Sender<Value>,
+}
+
+pub struct Control {
+    requests: mpsc::Receiver<Request>,
+    sender: mpsc::Sender<Request>,
+}
+
+/// Sends commands to the main loop from other threads.
+#[derive(Clone)]
+pub struct Requester {
+    sender: mpsc::Sender<Request>,
+}
+
+impl Requester {
+    /// Send a command and wait for the response.
+    /// Returns None if the main loop has stopped.
+    pub fn request(&self, command: Value) -> Option<Value> {
+        if let Some(response) = federation::route(&command, self) {
+            return Some(response);
+        }
+        let (reply, response) = mpsc::channel();
+        self.sender.send(Request { command, reply }).ok()?;
+        response.recv().ok()
+    }
+}
+
+/// Things commands can act on.
+/// Parts that are not in use are None.
+#[derive(Default)]
+pub struct Context<'a> {
+    /// Current configuration, updated by commands changing it.
+    pub config: Option<&'a mut configuration::Cli>,
+    pub rx_dsp: Option<&'a mut RxDsp>,
+    pub tx_dsp: Option<&'a mut TxDsp>,
+    pub sensors: Option<&'a SensorMonitor>,
+    pub sdr: Option<&'a mut SoapyIo>,
+    /// Output actions that can be applied by name.
+    pub outputs: Option<&'a switching::NamedOutputs>,
+    /// Channels added using templates.
+    pub channels: &'a [ExpandedChannel],
+}
+
+impl Control {
+    /// Create a control API without listening for connections.
+    /// Commands can be given using a Requester.
+    pub fn new() -> Self {
+        let (sender, requests) = mpsc::channel();
+        Self { requests, sender }
+    }
+
+    /// Start the control API if it is used by --control, --http-api,
+    /// --remote-head or federation. Has to be called before creating channels
+    /// for remote heads. Returns an error if federation options are missing.
+    pub fn from_cli(cli: &configuration::Cli) -> Result<Option<Self>, String> {
+        if cli.control.is_none() && cli.http_api.is_none() && cli.remote_head.is_none()
+            && cli.federation_hub.is_none() && cli.federation_join.is_none() {
+            return Ok(None);
+        }
+        if cli.federation_hub.is_some() && cli.federation_secret.is_none() {
+            return Err("--federation-hub requires --federation-secret".to_string());
+        }
+        if cli.federation_join.is_some() && cli.instance_name.is_none() {
+            return Err("--federation-join requires --instance-name".to_string());
+        }
+        if cli.federation_join.is_some() && cli.federation_secret.is_none() {
+            return Err("--federation-join requires --federation-secret".to_string());
+        }
+        let control = Self::new();
+        if let Some(address) = &cli.control {
+            control.listen(address);
+        }
+        if let Some(address) = &cli.http_api {
+            http_api::start(address, cli.api_token.clone(), control.requester());
+        }
+        if let Some(address) = &cli.remote_head {
+            remote::start(address, cli.api_token.clone(), control.requester());
+        }
+        if let (Some(address), Some(secret)) = (&cli.federation_hub, &cli.federation_secret) {
+            federation::start_hub(address, cli.instance_name.as_deref().unwrap_or("hub"), secret);
+        }
+        if let (Some(address), Some(name), Some(secret)) = (&cli.federation_join, &cli.instance_name, &cli.federation_secret) {
+            federation::join(address, name, secret, control.requester());
+        }
+        Ok(Some(control))
+    }
+
+    /// Listen for control connections at given TCP address.
+    pub fn listen(&self, address: &str) {
+        // TODO: handle errors more nicely
+        let listener = TcpListener::bind(address)
+            .unwrap_or_else(|e| panic!("Could not listen on control address {}: {}", address, e));
+        let requester = self.requester();
+        std::thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let requester = requester.clone();
+                std::thread::spawn(move || {
+                    if let Err(err) = serve(stream, requester, None) {
+                        eprintln!("Control connection error: {}", err);
+                    }
+                });
+            }
+        });
+    }
+
+    pub fn requester(&self) -> Requester {
+        Requester { sender: self.sender.clone() }
+    }
+
+    /// Handle pending commands.
+    pub fn poll(&mut self, mut context: Context) {
+        while let Ok(request) = self.requests.try_recv() {
+            let response = match handle(&request.command, &mut context) {
+                Ok(mut response) => {
+                    response["ok"] = Value::Bool(true);
+                    response
+                },
+                Err(error) => json!({ "ok": false, "error": error }),
+            };
+            let _ = request.reply.send(response);
+        }
+    }
+}
+
+/// Maximum length of a command line in bytes.
+/// Longer lines close the connection.
+pub const MAX_LINE_LENGTH: usize = 65536;
+
+/// Parse a line received from a client into a command.
+fn parse_line(line: &[u8]) -> Result<Value, String> {
+    let line = std::str::from_utf8(line).map_err(|err| format!("Invalid UTF-8: {}", err))?;
+    serde_json::from_str(line).map_err(|err| format!("Invalid JSON: {}", err))
+}
+
+/// Read commands from a connection, pass them to the main loop
+/// and write responses back. If a list of allowed commands
+/// is given, other commands are refused.
+pub fn serve(stream: TcpStream, requester: Requester, allowed: Option<&[&str]>) -> std::io::Result<()> {
+    let mut writer = stream.try_clone()?;
+    let mut reader = BufReader::new(stream);
+    let mut line = Vec::new();
+    loop {
+        line.clear();
+        (&mut reader).take(MAX_LINE_LENGTH as u64 + 1).read_until(b'\n', &mut line)?;
+        if line.is_empty() {
+            break;
+        }
+        if line.len() > MAX_LINE_LENGTH {
+            writeln!(writer, "{}", json!({ "ok": false, "error": "Too long command" }))?;
+            break;
+        }
+        if line.trim_ascii().is_empty() {
+            continue;
+        }
+        let response = match parse_line(&line) {
+            Ok(command) if allowed.is_some_and(|allowed| !command_allowed(&command, allowed))
+                => json!({ "ok": false, "error": "Command not allowed" }),
+            Ok(command) => match requester.request(command) {
+                Some(response) => response,
+                None => break,
+            },
+            Err(error) => json!({ "ok": false, "error": error }),
+        };
+        writeln!(writer, "{}", response)?;
+    }
+    Ok(())
+}
+
+/// Check whether a command is allowed on a connection limited
+/// to given commands, such as one from a federation hub.
+/// Such connections can never address transmit channels,
+/// so they cannot enable a transmitter.
+pub fn command_allowed(command: &Value, allowed: &[&str]) -> bool {
+    let name_allowed = command.get("command").and_then(Value::as_str)
+        .is_some_and(|name| allowed.contains(&name));
+    let transmit_channel = command.get("channel").and_then(Value::as_str)
+        .is_some_and(|channel| matches!(ChannelRef::parse(channel), Some(ChannelRef::Tx(_))));
+    name_allowed && !transmit_channel
+}
+
+fn handle(command: &Value, context: &mut Context) -> Result<Value, String> {
+    let name = command.get("command").and_then(Value::as_str)
+        .ok_or("Missing command")?;
+    match name {
+        "protocol" => {
+            let protocol = match command.get("protocol") {
+                Some(protocol) => protocol.as_u64().filter(|&protocol| protocol >= 1)
+                    .ok_or("Unsupported protocol version")?
+                    .min(PROTOCOL_VERSION),
+                None => PROTOCOL_VERSION,
+            };
+            let capabilities: Vec<&str> = [
+                ("rx", context.rx_dsp.is_some()),
+                ("tx", context.tx_dsp.is_some()),
+                ("sensors", context.sensors.is_some()),
+                ("outputs", context.sdr.is_some() && context.outputs.is_some_and(|outputs| !outputs.is_empty())),
+                ("interlock", context.tx_dsp.as_deref_mut().and_then(TxDsp::interlock_mut).is_some()),
+                ("profiles", context.config.as_deref().is_some_and(|config| config.profile.is_some())),
+                ("federation", federation::hub_running()),
+            ].into_iter().filter(|&(_, available)| available).map(|(capability, _)| capability).collect();
+            let mut commands = COMMANDS.to_vec();
+            if federation::hub_running() {
+                commands.extend(HUB_ONLY_COMMANDS);
+            }
+            Ok(json!({
+                "protocol": protocol,
+                "version": env!("CARGO_PKG_VERSION"),
+                "capabilities": capabilities,
+                "commands": commands,
+            }))
+        },
+        "schema" => {
+            let schema: Value = serde_json::from_str(SCHEMA).map_err(|err| err.to_string())?;
+            Ok(json!({ "schema": schema }))
+        },
+        "inject" => {
+            let frequency = command.get("frequency").and_then(Value::as_f64)
+                .ok_or("Missing frequency")?;
+            let level = match command.get("level") {
+                Some(level) => level.as_f64().filter(|&level| level <= 0.0).ok_or("Invalid level")?,
+                None => -60.0,
+            };
+            let modulation = match command.get("modulation") {
+                Some(modulation) => modulation.as_str()
+                    .and_then(injection::TestModulation::parse)
+                    .ok_or("Invalid modulation")?,
+                None => injection::TestModulation::Tone,
+            };
+            let rx_dsp = context.rx_dsp.as_deref_mut().ok_or("Receiving is not enabled")?;
+            rx_dsp.inject(frequency, level as Sample, modulation);
+            Ok(json!({}))
+        },
+        "stop_inject" => {
+            let rx_dsp = context.rx_dsp.as_deref_mut().ok_or("Receiving is not enabled")?;
+            rx_dsp.stop_injection();
+            Ok(json!({}))
+        },
+        "sensors" => {
+            let sensors = context.sensors.ok_or("Sensor reading is not enabled")?;
+            let values: serde_json::Map<String, Value> = sensors.values().iter()
+                .map(|(name, value)| (name.clone(), Value::String(value.clone())))
+                .collect();
+            Ok(json!({ "sensors": values }))
+        },
+        "output" => {
+            let name = command.get("action").and_then(Value::as_str)
+                .ok_or("Missing action")?;
+            let actions = context.outputs.and_then(|outputs| outputs.get(name))
+                .ok_or_else(|| format!("Output action {} is not configured", name))?;
+            let armed = context.tx_dsp.as_deref_mut().and_then(TxDsp::interlock_mut)
+                .is_none_or(|interlock| interlock.armed());
+            if !armed && actions.iter().any(switching::OutputAction::needs_arming) {
+                return Err("Transmitting is not armed".to_string());
+            }
+            let sdr = context.sdr.as_deref_mut().ok_or("No SDR in use")?;
+            for action in actions {
+                action.apply(sdr)?;
+            }
+            Ok(json!({}))
+        },
+        "enable" | "disable" => {
+            let enable = name == "enable";
+            let channel = command.get("channel").and_then(Value::as_str)
+                .ok_or("Missing channel")?;
+            let found = match ChannelRef::parse(channel).ok_or("Invalid channel")? {
+                ChannelRef::Rx(index) => context.rx_dsp.as_deref_mut().is_some_and(|dsp| dsp.set_channel_enabled(index, enable)),
+                ChannelRef::Tx(index) => context.tx_dsp.as_deref_mut().is_some_and(|dsp| dsp.set_channel_enabled(index, enable)),
+            };
+            if !found {
+                return Err(format!("Channel {} does not exist", channel));
+            }
+            if let Some(config) = context.config.as_deref_mut() {
+                config.set_channel_enabled(channel, enable);
+            }
+            events::emit(events::Event::new(if enable { "channel_enabled" } else { "channel_disabled" })
+                .with("channel", channel));
+            Ok(json!({}))
+        },
+        "delay" => {
+            let channel = command.get("channel").and_then(Value::as_str)
+                .ok_or("Missing channel")?;
+            let delay = command.get("delay").and_then(Value::as_f64)
+                .filter(|delay| (0.0..=rx_dsp::MAX_CHANNEL_DELAY).contains(delay))
+                .ok_or("Missing or invalid delay")?;
+            let index = match ChannelRef::parse(channel) {
+                Some(ChannelRef::Rx(index)) => index,
+                _ => return Err("Delay is only supported for receive channels".to_string()),
+            };
+            let rx_dsp = context.rx_dsp.as_deref_mut().ok_or("Receiving is not enabled")?;
+            let delay = rx_dsp.set_channel_delay(index, delay)
+                .ok_or_else(|| format!("Channel {} does not exist", channel))?;
+            if let Some(config) = context.config.as_deref_mut() {
+                config.set_channel_delay(channel, delay);
+            }
+            Ok(json!({ "delay": delay }))
+        },
+        "arm_challenge" | "arm" | "disarm" => {
+            let interlock = context.tx_dsp.as_deref_mut().and_then(TxDsp::interlock_mut)
+                .ok_or("Transmit interlock is not enabled")?;
+            match name {
+                "arm_challenge" => Ok(json!({ "challenge": interlock.challenge() })),
+                "arm" => {
+                    let challenge = command.get("challenge").and_then(Value::as_str)
+                        .ok_or("Missing challenge")?;
+                    let response = command.get("response").and_then(Value::as_str)
+                        .ok_or("Missing response")?;
+                    let duration = interlock.arm(challenge, response, command.get("duration").and_then(Value::as_f64))?;
+                    Ok(json!({ "duration": duration }))
+                },
+                _ => {
+                    interlock.disarm();
+                    Ok(json!({}))
+                },
+            }
+        },
+        "save_profile" => {
+            let config = context.config.as_deref().ok_or("No configuration available")?;
+            let path = config.profile.as_deref().ok_or("No profile given with --profile")?;
+            config.save(path)?;
+            Ok(json!({}))
+        },
+        "channels" => {
+            let channels: Vec<Value> = context.channels.iter().map(|channel| json!({
+                "name": channel.name,
+                "template": channel.template,
+                "frequency": channel.frequency,
+                "modulation": channel.modulation,
+                "option": channel.option,
+                "arguments": channel.arguments,
+            })).collect();
+            Ok(json!({ "channels": channels }))
+        },
+        "status" => {
+            let rx: Option<Vec<Value>> = context.rx_dsp.as_deref().map(|dsp| dsp.channels().enumerate()
+                .map(|(index, channel)| json!({
+                    "name": format!("rx{}", index),
+                    "frequency": channel.frequency,
+                    "enabled": channel.enabled,
+                    "afc_offset": channel.afc_offset,
+                })).collect());
+            let tx: Option<Vec<Value>> = context.tx_dsp.as_deref().map(|dsp| dsp.channel_info().iter()
+                .map(|channel| json!({
+                    "name": channel.name,
+                    "frequency": channel.center_frequency,
+                    "modulation": channel.modulation,
+                    "enabled": channel.enabled,
+                })).collect());
+            let armed = context.tx_dsp.as_deref_mut().and_then(TxDsp::interlock_mut)
+                .map(|interlock| interlock.armed());
+            Ok(json!({ "rx": rx, "tx": tx, "armed": armed }))
+        },
+        _ => Err(format!("Unknown command {}", name)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+    use super::*;
+    use crate::fuzz;
+
+    #[test]
+    fn test_malformed_commands() {
+        let inputs = fuzz::inputs(&[
+            r#"{"command":"inject","frequency":145.5e6,"level":-60,"modulation":"fm"}"#,
+            r#"{"command":"output","action":"preamp_on"}"#,
+            r#"{"command":"enable","channel":"rx0"}"#,
+            r#"{"command":"delay","channel":"rx0","delay":0.0125}"#,
+            r#"{"command":"arm","challenge":"abc","response":"abc","duration":60}"#,
+            r#"{"command":"save_profile"}"#,
+        ]);
+        let cli = configuration::Cli::parse_from(["sdrglue",
+            "--demodulate-to-udp", "127.0.0.1:7355", "145.5e6", "FM",
+            "--ber-test-tx", "145.3e6", "1200", "1000",
+            "--tx-interlock", "--tx-allow", "tx0", "145e6:146e6",
+            "--tx-arm-timeout", "10", "--tx-arm-key", "key"]);
+        let mut fft_planner = rustfft::FftPlanner::new();
+        let mut rx_dsp = RxDsp::new(&mut fft_planner, &cli, 1e6, 145.2e6).unwrap();
+        let mut tx_dsp = TxDsp::new(&mut fft_planner, &cli, 1e6, 145.2e6).unwrap();
+        let mut config = cli.clone();
+        let mut context = Context {
+            config: Some(&mut config),
+            rx_dsp: Some(&mut rx_dsp),
+            tx_dsp: Some(&mut tx_dsp),
+            ..Default::default()
+        };
+        for input in inputs.iter() {
+            // Anything may fail, as long as nothing panics.
+            if let Ok(command) = parse_line(input.as_bytes()) {
+                let _ = handle(&command, &mut context);
+            }
+        }
+        assert!(parse_line(b"{\"command\":\xff}").is_err());
+        assert_eq!(handle(&json!({ "command": "delay", "channel": "rx0", "delay": 1e300 }), &mut context),
+            Err("Missing or invalid delay".to_string()));
+        assert_eq!(handle(&json!({ "command": "inject", "frequency": 145e6, "level": 1e300 }), &mut context),
+            Err("Invalid level".to_string()));
+    }
+
+    #[test]
+    fn test_hub_commands() {
+        let allowed = |command: Value| command_allowed(&command, federation::HUB_COMMANDS);
+        assert!(allowed(json!({ "command": "enable", "channel": "rx0" })));
+        assert!(allowed(json!({ "command": "status" })));
+        assert!(!allowed(json!({ "command": "enable", "channel": "tx0" })));
+        assert!(!allowed(json!({ "command": "disable", "channel": "tx1" })));
+        assert!(!allowed(json!({ "command": "inject", "frequency": 145e6 })));
+        assert!(!allowed(json!({ "channel": "rx0" })));
+    }
+
+    #[test]
+    fn test_federation_options() {
+        let from_cli = |args: &[&str]| Control::from_cli(&configuration::Cli::parse_from([&["sdrglue"], args].concat())).err();
+        assert_eq!(from_cli(&["--federation-hub", "127.0.0.1:0"]),
+            Some("--federation-hub requires --federation-secret".to_string()));
+        assert_eq!(from_cli(&["--federation-join", "127.0.0.1:0", "--federation-secret", "s"]),
+            Some("--federation-join requires --instance-name".to_string()));
+        assert_eq!(from_cli(&["--federation-join", "127.0.0.1:0", "--instance-name", "site2"]),
+            Some("--federation-join requires --federation-secret".to_string()));
+    }
+
+    #[test]
+    fn test_protocol() {
+        let mut context = Context::default();
+        let response = handle(&json!({ "command": "protocol", "protocol": 99 }), &mut context).unwrap();
+        assert_eq!(response["protocol"], PROTOCOL_VERSION);
+        assert_eq!(response["capabilities"], json!([]));
+        assert!(handle(&json!({ "command": "protocol", "protocol": 0 }), &mut context).is_err());
+        // Every command should have a schema.
+        let schema = handle(&json!({ "command": "schema" }), &mut context).unwrap();
+        assert_eq!(schema["schema"]["protocol"], PROTOCOL_VERSION);
+        for command in COMMANDS.iter().chain(HUB_ONLY_COMMANDS) {
+            assert!(schema["schema"]["commands"][command]["response"].is_object(), "{}", command);
+        }
+    }
+}
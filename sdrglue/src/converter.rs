@@ -0,0 +1,63 @@
+//! External frequency converters and transverters.
+//!
+//! Frequencies are given everywhere as the actual frequencies
+//! on air, and converted to the intermediate frequency
+//! the SDR is tuned to only when talking to the SDR.
+
+use crate::ComplexSample;
+
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Converter {
+    /// Frequency of the converter local oscillator in Hertz,
+    /// negative for upconverters in front of a receiver.
+    pub offset: f64,
+    /// Whether the converter inverts the spectrum,
+    /// i.e. the local oscillator is above the signal.
+    pub inverted: bool,
+}
+
+impl Converter {
+    /// Convert a frequency on air to SDR frequency.
+    pub fn sdr_frequency(&self, frequency: f64) -> f64 {
+        if self.inverted {
+            self.offset - frequency
+        } else {
+            frequency - self.offset
+        }
+    }
+
+    /// Convert an SDR frequency to frequency on air.
+    pub fn air_frequency(&self, frequency: f64) -> f64 {
+        if self.inverted {
+            self.offset - frequency
+        } else {
+            frequency + self.offset
+        }
+    }
+
+    /// Undo spectrum inversion of signal, if any.
+    pub fn correct_spectrum(&self, samples: &mut [ComplexSample]) {
+        if self.inverted {
+            for sample in samples.iter_mut() {
+                *sample = sample.conj();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_conversion() {
+        // QO-100 downconverter with 9750 MHz LO
+        let converter = Converter { offset: 9750e6, inverted: false };
+        assert_eq!(converter.sdr_frequency(10489.5e6), 739.5e6);
+        assert_eq!(converter.air_frequency(739.5e6), 10489.5e6);
+        // Converter with LO above the signal
+        let converter = Converter { offset: 116e6, inverted: true };
+        assert_eq!(converter.sdr_frequency(28e6), 88e6);
+        assert_eq!(converter.air_frequency(88e6), 28e6);
+    }
+}
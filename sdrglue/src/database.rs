@@ -0,0 +1,84 @@
+//! Logging of events into an SQLite database.
+//!
+//! Every event is stored as a row with its time, type,
+//! frequency (if the event has one) and all fields as JSON,
+//! so history can be queried later, for example:
+//! ```text
+//! sqlite3 events.db "SELECT datetime(time, 'unixepoch'), data FROM events
+//!     WHERE event = 'squelch_open' AND frequency = 145.5e6"
+//! ```
+//! Events older than the retention time are deleted periodically.
+
+use std::time::{Duration, SystemTime};
+
+use crate::events::{Event, EventListener, Value};
+
+/// How often old events are deleted.
+const CLEANUP_INTERVAL: Duration = Duration::from_secs(3600);
+
+pub struct DatabaseLogger {
+    connection: rusqlite::Connection,
+    /// How long events are kept, if limited.
+    retention: Option<Duration>,
+    /// When old events were last deleted.
+    last_cleanup: Option<SystemTime>,
+}
+
+impl DatabaseLogger {
+    /// Open or create a database.
+    /// Retention is given in days.
+    pub fn open(path: &str, retention: Option<f64>) -> Self {
+        // TODO: handle errors more nicely
+        let connection = rusqlite::Connection::open(path)
+            .unwrap_or_else(|e| panic!("Could not open database {}: {}", path, e));
+        connection.execute_batch("
+            PRAGMA journal_mode = WAL;
+            PRAGMA synchronous = NORMAL;
+            CREATE TABLE IF NOT EXISTS events (
+                time REAL NOT NULL,
+                event TEXT NOT NULL,
+                frequency REAL,
+                data TEXT NOT NULL
+            );
+            CREATE INDEX IF NOT EXISTS events_time ON events (time);
+            CREATE INDEX IF NOT EXISTS events_event ON events (event, time);
+        ").unwrap_or_else(|e| panic!("Could not initialize database {}: {}", path, e));
+        Self {
+            connection,
+            retention: retention.map(|days| Duration::from_secs_f64(days * 86400.0)),
+            last_cleanup: None,
+        }
+    }
+
+    /// Delete events older than retention time.
+    fn cleanup(&mut self, now: SystemTime) {
+        let Some(retention) = self.retention else { return };
+        if self.last_cleanup.is_some_and(|time| now.duration_since(time).unwrap_or_default() < CLEANUP_INTERVAL) {
+            return;
+        }
+        self.last_cleanup = Some(now);
+        let limit = now.duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default()
+            .saturating_sub(retention).as_secs_f64();
+        if let Err(err) = self.connection.execute("DELETE FROM events WHERE time < ?1", rusqlite::params![limit]) {
+            eprintln!("Error deleting old events from database: {}", err);
+        }
+    }
+}
+
+impl EventListener for DatabaseLogger {
+    fn event(&mut self, event: &Event) {
+        let time = event.time.duration_since(SystemTime::UNIX_EPOCH)
+            .map(|t| t.as_secs_f64()).unwrap_or(0.0);
+        let frequency = event.fields.iter().find_map(|(key, value)| match value {
+            Value::Number(frequency) if *key == "frequency" => Some(*frequency),
+            _ => None,
+        });
+        if let Err(err) = self.connection.execute(
+            "INSERT INTO events (time, event, frequency, data) VALUES (?1, ?2, ?3, ?4)",
+            rusqlite::params![time, event.kind, frequency, event.to_json()],
+        ) {
+            eprintln!("Error writing event to database: {}", err);
+        }
+        self.cleanup(event.time);
+    }
+}
@@ -0,0 +1,483 @@
+//! Demodulators producing audio from a channel at a fixed sample rate.
+//! These do not depend on any input or output interfaces,
+//! so they can also be used where those are not available.
+
+use alloc::vec::Vec;
+#[cfg(not(any(feature = "std", test)))]
+use crate::num_traits::Float;
+use crate::num_traits::Euclid;
+use crate::{Sample, ComplexSample, sample_consts};
+use crate::filter;
+
+/// Sample rate of demodulator input and output.
+pub const SAMPLE_RATE: f64 = 48000.0;
+
+#[derive(Copy, Clone)]
+pub enum Modulation {
+    FM,
+    USB,
+    LSB,
+    AM,
+    /// Synchronous AM: carrier is tracked by a PLL
+    /// and the signal is demodulated coherently.
+    SyncAM,
+    /// Carrier is mixed to an audible pitch in Hertz
+    /// after a filter with given bandwidth in Hertz.
+    CW { pitch: f64, bandwidth: f64 },
+}
+
+/// Maximum half length of a channel filter,
+/// corresponding to a filter about 0.2 seconds long.
+const MAX_FILTER_HALF_LENGTH: usize = 4096;
+
+/// Channel filter of a demodulator.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ChannelFilter {
+    /// Width of the passband in Hertz. For SSB, the passband
+    /// is centered 1500 Hz from the suppressed carrier.
+    pub bandwidth: f64,
+    /// Half of the number of filter taps. Longer filters
+    /// have steeper transition from passband to stopband.
+    pub half_length: usize,
+}
+
+impl ChannelFilter {
+    /// Default filter for a modulation.
+    pub fn default_for(modulation: Modulation) -> Self {
+        let (bandwidth, half_length) = match modulation {
+            Modulation::FM => (16000.0, 32),
+            Modulation::USB | Modulation::LSB => (2400.0, 128),
+            Modulation::AM | Modulation::SyncAM => (10000.0, 64),
+            Modulation::CW { bandwidth, .. } => (bandwidth, 256),
+        };
+        Self { bandwidth, half_length }
+    }
+
+    /// Parse "bandwidth", "bandwidth:half_length" or ":half_length".
+    /// Without a bandwidth or length, the default for the modulation is used.
+    pub fn parse(text: &str, modulation: Modulation) -> Option<Self> {
+        let (bandwidth, half_length) = match text.split_once(':') {
+            Some((bandwidth, half_length)) => (bandwidth, Some(half_length.parse().ok()?)),
+            None => (text, None),
+        };
+        let default = Self::default_for(modulation);
+        let bandwidth: f64 = if bandwidth.is_empty() && half_length.is_some() { default.bandwidth } else { bandwidth.parse().ok()? };
+        let half_length = half_length.unwrap_or(default.half_length);
+        (bandwidth > 0.0 && bandwidth < SAMPLE_RATE && half_length > 0 && half_length <= MAX_FILTER_HALF_LENGTH).then_some(Self { bandwidth, half_length })
+    }
+}
+
+/// Coefficient of the filter estimating the carrier level
+/// in AM demodulation, giving a time constant of about 20 ms.
+const AM_CARRIER_ALPHA: Sample = 0.001;
+/// Natural frequency of the carrier PLL in synchronous AM
+/// demodulation, in radians per sample, corresponding to 30 Hz.
+const SYNC_AM_PLL_FREQUENCY: f64 = 2.0 * core::f64::consts::PI * 30.0 / SAMPLE_RATE;
+/// Maximum frequency correction of the carrier PLL in Hertz.
+const SYNC_AM_PLL_RANGE: f64 = 1000.0;
+
+/// Demodulator without any output interface.
+/// Produces demodulated audio as floating point samples
+/// scaled so that full scale is 1.0.
+pub struct Demodulator {
+    /// Modulation
+    modulation: Modulation,
+    /// Previous sample, used for FM demodulation
+    previous_sample: ComplexSample,
+    /// Used for SSB demodulation.
+    second_mixer_phase: usize,
+    /// Pitch mixer phase in cycles, used for CW demodulation.
+    cw_mixer_phase: f64,
+    /// Channel filter, used for all modulations
+    /// but with different bandwidth.
+    channel_filter: filter::FirCf32Sym,
+    /// Mean power of channel filter output in the latest block.
+    power: Sample,
+    /// Carrier level estimate, used for AM demodulation.
+    carrier_level: Sample,
+    /// Audio lowpass filter, used for AM demodulation.
+    audio_filter: Option<filter::FirCf32Sym>,
+    /// Carrier PLL phase and frequency in radians per sample,
+    /// used for synchronous AM demodulation.
+    pll_phase: f64,
+    pll_frequency: f64,
+}
+
+impl Demodulator {
+    pub fn new(modulation: Modulation) -> Self {
+        Self::with_filter(modulation, ChannelFilter::default_for(modulation))
+    }
+
+    pub fn with_filter(modulation: Modulation, channel_filter: ChannelFilter) -> Self {
+        Self {
+            modulation,
+            previous_sample: ComplexSample::ZERO,
+            second_mixer_phase: 0,
+            cw_mixer_phase: 0.0,
+            // Channels filters are usually the same for many instances,
+            // so memory use could be reduced (which might be good for cache)
+            // by computing them once and sharing them among demodulators.
+            // This can be done later.
+            channel_filter: filter::FirCf32Sym::new(filter::design_fir_lowpass(
+                SAMPLE_RATE, channel_filter.bandwidth / 2.0, channel_filter.half_length)),
+            power: 0.0,
+            carrier_level: 0.0,
+            audio_filter: match modulation {
+                Modulation::AM | Modulation::SyncAM => Some(filter::FirCf32Sym::new(
+                    filter::design_fir_lowpass(SAMPLE_RATE, 4500.0, 32))),
+                _ => None,
+            },
+            pll_phase: 0.0,
+            pll_frequency: 0.0,
+        }
+    }
+
+    /// Offset between the frequency being demodulated
+    /// and the center frequency of demodulator input.
+    pub fn frequency_offset(modulation: Modulation) -> f64 {
+        match modulation {
+            // CW uses the same idea as Weaver method SSB
+            // but with the passband centered on the carrier.
+            Modulation::FM | Modulation::AM | Modulation::SyncAM | Modulation::CW { .. } => 0.0,
+            // Weaver method SSB: offset downconverter so we can
+            // use a channel filter with real-valued taps.
+            Modulation::USB =>  SSB_WEAVER_OFFSET,
+            Modulation::LSB => -SSB_WEAVER_OFFSET,
+        }
+    }
+
+    /// Demodulate a block of samples.
+    /// Demodulated audio is appended to output.
+    pub fn process(&mut self, samples: &[ComplexSample], output: &mut Vec<Sample>) {
+        let mut power_sum: Sample = 0.0;
+        for &sample in samples {
+            let filtered = self.channel_filter.sample(sample);
+            power_sum += filtered.norm_sqr();
+
+            output.push(match self.modulation {
+                Modulation::FM => {
+                    let out = (filtered * self.previous_sample.conj()).arg() * sample_consts::FRAC_1_PI;
+                    self.previous_sample = filtered;
+                    out
+                },
+                Modulation::USB | Modulation::LSB => {
+                    (filtered * SSB_SECOND_MIXER_TABLE[self.second_mixer_phase]).re
+                },
+                Modulation::AM => {
+                    // Remove the carrier from the envelope
+                    // and scale audio by carrier level,
+                    // so that audio level equals modulation depth.
+                    let envelope = filtered.norm();
+                    self.carrier_level += AM_CARRIER_ALPHA * (envelope - self.carrier_level);
+                    let audio = (envelope - self.carrier_level) / self.carrier_level.max(1e-6);
+                    match &mut self.audio_filter {
+                        Some(audio_filter) => audio_filter.sample(ComplexSample { re: audio, im: 0.0 }).re,
+                        None => audio,
+                    }
+                },
+                Modulation::SyncAM => {
+                    // Mix carrier to DC using the PLL, so that it is
+                    // in the real part and audio sidebands add coherently.
+                    // Unlike an envelope detector, this does not
+                    // distort when selective fading weakens the carrier.
+                    let mixed = filtered * ComplexSample::from_polar(1.0, -self.pll_phase as Sample);
+                    let error = mixed.arg() as f64;
+                    let max_frequency = 2.0 * core::f64::consts::PI * SYNC_AM_PLL_RANGE / SAMPLE_RATE;
+                    self.pll_frequency = (self.pll_frequency + SYNC_AM_PLL_FREQUENCY.powi(2) * error)
+                        .clamp(-max_frequency, max_frequency);
+                    self.pll_phase = Euclid::rem_euclid(
+                        &(self.pll_phase + self.pll_frequency + 2.0 * 0.707 * SYNC_AM_PLL_FREQUENCY * error),
+                        &(2.0 * core::f64::consts::PI));
+                    self.carrier_level += AM_CARRIER_ALPHA * (mixed.re - self.carrier_level);
+                    let audio = (mixed.re - self.carrier_level) / self.carrier_level.max(1e-6);
+                    match &mut self.audio_filter {
+                        Some(audio_filter) => audio_filter.sample(ComplexSample { re: audio, im: 0.0 }).re,
+                        None => audio,
+                    }
+                },
+                Modulation::CW { pitch, .. } => {
+                    let out = (filtered * ComplexSample::from_polar(
+                        1.0, (2.0 * core::f64::consts::PI * self.cw_mixer_phase) as Sample)).re;
+                    self.cw_mixer_phase = (self.cw_mixer_phase + pitch / SAMPLE_RATE).fract();
+                    out
+                },
+            });
+
+            // All this SSB stuff could be cleaned up a bit...
+
+            match self.modulation {
+                Modulation::USB => {
+                    self.second_mixer_phase += 1;
+                    if self.second_mixer_phase >= SSB_SECOND_MIXER_TABLE.len() {
+                        self.second_mixer_phase = 0;
+                    }
+                },
+                Modulation::LSB => {
+                    if self.second_mixer_phase == 0 {
+                        self.second_mixer_phase = SSB_SECOND_MIXER_TABLE.len() - 1;
+                    } else {
+                        self.second_mixer_phase -= 1;
+                    }
+                },
+                _ => {},
+            }
+        }
+        if !samples.is_empty() {
+            self.power = power_sum / samples.len() as Sample;
+        }
+    }
+
+    /// Reset state depending on continuity of the input signal,
+    /// after samples have been lost.
+    /// Carrier level and PLL frequency are kept,
+    /// since they are still valid over a short gap.
+    pub fn reset(&mut self) {
+        self.previous_sample = ComplexSample::ZERO;
+        self.second_mixer_phase = 0;
+        self.cw_mixer_phase = 0.0;
+        self.channel_filter.reset();
+        if let Some(audio_filter) = &mut self.audio_filter {
+            audio_filter.reset();
+        }
+    }
+
+    /// Mean power of the signal within the channel filter
+    /// during the latest processed block.
+    pub fn power(&self) -> Sample {
+        self.power
+    }
+}
+
+/// Default AGC attack time in seconds.
+const AGC_ATTACK: f64 = 0.005;
+/// Default AGC decay time in seconds.
+const AGC_DECAY: f64 = 0.5;
+/// Maximum AGC gain in dB, so that noise is not
+/// amplified without limit when there is no signal.
+const AGC_MAX_GAIN_DB: Sample = 60.0;
+
+/// Settings of audio automatic gain control.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct AgcSettings {
+    /// Target audio level in dB relative to full scale.
+    pub target_db: Sample,
+    /// Time constants in seconds for reducing gain
+    /// when audio gets louder and increasing it
+    /// when audio gets quieter.
+    pub attack: f64,
+    pub decay: f64,
+}
+
+impl AgcSettings {
+    /// Parse "target_db", "target_db:attack" or "target_db:attack:decay".
+    pub fn parse(text: &str) -> Option<Self> {
+        let mut values = text.split(':').map(|value| value.parse::<f64>().ok());
+        let target_db = values.next()?? as Sample;
+        let attack = values.next().unwrap_or(Some(AGC_ATTACK))?;
+        let decay = values.next().unwrap_or(Some(AGC_DECAY))?;
+        (values.next().is_none() && target_db <= 0.0 && attack > 0.0 && decay > 0.0)
+            .then_some(Self { target_db, attack, decay })
+    }
+}
+
+/// Automatic gain control normalizing audio level,
+/// so that SSB and AM audio neither clips nor is
+/// too quiet regardless of signal strength.
+pub struct Agc {
+    target: Sample,
+    attack_alpha: Sample,
+    decay_alpha: Sample,
+    max_gain: Sample,
+    /// Estimate of audio peak level.
+    envelope: Sample,
+}
+
+impl Agc {
+    pub fn new(settings: AgcSettings) -> Self {
+        Self {
+            target: (10.0 as Sample).powf(settings.target_db / 20.0),
+            attack_alpha: (1.0 - (-1.0 / (settings.attack * SAMPLE_RATE)).exp()) as Sample,
+            decay_alpha: (1.0 - (-1.0 / (settings.decay * SAMPLE_RATE)).exp()) as Sample,
+            max_gain: (10.0 as Sample).powf(AGC_MAX_GAIN_DB / 20.0),
+            envelope: 0.0,
+        }
+    }
+
+    /// Apply gain to a block of audio at SAMPLE_RATE.
+    pub fn process(&mut self, audio: &mut [Sample]) {
+        for sample in audio.iter_mut() {
+            let level = sample.abs();
+            let alpha = if level > self.envelope { self.attack_alpha } else { self.decay_alpha };
+            self.envelope += alpha * (level - self.envelope);
+            *sample *= (self.target / self.envelope.max(1e-20)).min(self.max_gain);
+        }
+    }
+}
+
+const SSB_WEAVER_OFFSET: f64 = 1500.0;
+
+/// One cycle of complex sine wave for the second mixer
+/// in Weaver method SSB demodulator.
+/// Computing it at compile time is not possible for floating point
+/// and computing it at run time would unnecessarily complicate the code,
+/// so just put the values here.
+/// Computed in Python with:
+/// import numpy as np
+/// for v in np.exp(1j * np.linspace(0, np.pi*2, 32, endpoint=False)):
+///  print('    ComplexSample { re: %11.8f, im: %11.8f },' % (v.real, v.imag))
+#[allow(clippy::approx_constant, clippy::excessive_precision)]
+const SSB_SECOND_MIXER_TABLE: [ComplexSample; 32] = [
+    ComplexSample { re:  1.00000000, im:  0.00000000 },
+    ComplexSample { re:  0.98078528, im:  0.19509032 },
+    ComplexSample { re:  0.92387953, im:  0.38268343 },
+    ComplexSample { re:  0.83146961, im:  0.55557023 },
+    ComplexSample { re:  0.70710678, im:  0.70710678 },
+    ComplexSample { re:  0.55557023, im:  0.83146961 },
+    ComplexSample { re:  0.38268343, im:  0.92387953 },
+    ComplexSample { re:  0.19509032, im:  0.98078528 },
+    ComplexSample { re:  0.00000000, im:  1.00000000 },
+    ComplexSample { re: -0.19509032, im:  0.98078528 },
+    ComplexSample { re: -0.38268343, im:  0.92387953 },
+    ComplexSample { re: -0.55557023, im:  0.83146961 },
+    ComplexSample { re: -0.70710678, im:  0.70710678 },
+    ComplexSample { re: -0.83146961, im:  0.55557023 },
+    ComplexSample { re: -0.92387953, im:  0.38268343 },
+    ComplexSample { re: -0.98078528, im:  0.19509032 },
+    ComplexSample { re: -1.00000000, im:  0.00000000 },
+    ComplexSample { re: -0.98078528, im: -0.19509032 },
+    ComplexSample { re: -0.92387953, im: -0.38268343 },
+    ComplexSample { re: -0.83146961, im: -0.55557023 },
+    ComplexSample { re: -0.70710678, im: -0.70710678 },
+    ComplexSample { re: -0.55557023, im: -0.83146961 },
+    ComplexSample { re: -0.38268343, im: -0.92387953 },
+    ComplexSample { re: -0.19509032, im: -0.98078528 },
+    ComplexSample { re: -0.00000000, im: -1.00000000 },
+    ComplexSample { re:  0.19509032, im: -0.98078528 },
+    ComplexSample { re:  0.38268343, im: -0.92387953 },
+    ComplexSample { re:  0.55557023, im: -0.83146961 },
+    ComplexSample { re:  0.70710678, im: -0.70710678 },
+    ComplexSample { re:  0.83146961, im: -0.55557023 },
+    ComplexSample { re:  0.92387953, im: -0.38268343 },
+    ComplexSample { re:  0.98078528, im: -0.19509032 },
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_am() {
+        // 1 kHz tone with 50 % modulation depth
+        // should give audio with an amplitude of 0.5
+        // regardless of signal level.
+        for level in [1.0, 0.01] {
+            let mut demodulator = Demodulator::new(Modulation::AM);
+            let input: Vec<ComplexSample> = (0..48000).map(|i| {
+                let t = i as f64 / SAMPLE_RATE;
+                let amplitude = level * (1.0 + 0.5 * (2.0 * core::f64::consts::PI * 1000.0 * t).sin());
+                ComplexSample::from_polar(amplitude as Sample, (t * 1234.0) as Sample)
+            }).collect();
+            let mut output = Vec::new();
+            demodulator.process(&input, &mut output);
+            let peak = output[24000..].iter().fold(0.0 as Sample, |peak, &x| peak.max(x.abs()));
+            assert!((peak - 0.5).abs() < 0.02, "peak {}", peak);
+        }
+    }
+
+    #[test]
+    fn test_sync_am() {
+        // 50 % modulated carrier 200 Hz off the channel center
+        let mut demodulator = Demodulator::new(Modulation::SyncAM);
+        let input: Vec<ComplexSample> = (0..48000).map(|i| {
+            let t = i as f64 / SAMPLE_RATE;
+            let amplitude = 0.1 * (1.0 + 0.5 * (2.0 * core::f64::consts::PI * 1000.0 * t).sin());
+            ComplexSample::from_polar(amplitude as Sample, (2.0 * core::f64::consts::PI * 200.0 * t + 1.0) as Sample)
+        }).collect();
+        let mut output = Vec::new();
+        demodulator.process(&input, &mut output);
+        let peak = output[24000..].iter().fold(0.0 as Sample, |peak, &x| peak.max(x.abs()));
+        assert!((peak - 0.5).abs() < 0.02, "peak {}", peak);
+    }
+
+    #[test]
+    fn test_cw() {
+        let modulation = Modulation::CW { pitch: 600.0, bandwidth: 500.0 };
+        let demodulate = |offset: f64| {
+            let mut demodulator = Demodulator::new(modulation);
+            let input: Vec<ComplexSample> = (0..48000).map(|i|
+                ComplexSample::from_polar(1.0, (2.0 * core::f64::consts::PI * offset * i as f64 / SAMPLE_RATE) as Sample)
+            ).collect();
+            let mut output = Vec::new();
+            demodulator.process(&input, &mut output);
+            output
+        };
+        // Carrier 100 Hz above channel frequency gives a 700 Hz tone.
+        let output = demodulate(100.0);
+        let crossings = output.windows(2).filter(|pair| (pair[0] < 0.0) != (pair[1] < 0.0)).count();
+        assert!((crossings as i32 - 1400).abs() <= 4, "crossings {}", crossings);
+        assert!(output[24000..].iter().all(|x| x.abs() < 1.01));
+        // Signals outside the filter bandwidth are attenuated.
+        let output = demodulate(1000.0);
+        assert!(output[24000..].iter().all(|x| x.abs() < 0.01));
+    }
+
+    #[test]
+    fn test_reset() {
+        // After a reset, demodulator should behave as if it had
+        // just been created, so that a gap in input only causes
+        // a glitch as long as the filter.
+        let input: Vec<ComplexSample> = (0..4800).map(|i|
+            ComplexSample::from_polar(1.0, (i as f64 * 0.3).sin() as Sample)
+        ).collect();
+        for modulation in [Modulation::FM, Modulation::USB, Modulation::LSB,
+                           Modulation::CW { pitch: 600.0, bandwidth: 500.0 }] {
+            let mut demodulator = Demodulator::new(modulation);
+            let mut output = Vec::new();
+            demodulator.process(&input[..1234], &mut output);
+            demodulator.reset();
+            output.clear();
+            demodulator.process(&input[2000..], &mut output);
+            let mut expected = Vec::new();
+            Demodulator::new(modulation).process(&input[2000..], &mut expected);
+            assert_eq!(output, expected);
+        }
+    }
+
+    #[test]
+    fn test_agc() {
+        // Quiet and loud tones are both brought to -10 dBFS.
+        for amplitude in [0.01, 0.9] {
+            let mut agc = Agc::new(AgcSettings::parse("-10").unwrap());
+            let mut audio: Vec<Sample> = (0..48000).map(|i|
+                amplitude * (2.0 * sample_consts::PI * 1000.0 * i as Sample / SAMPLE_RATE as Sample).sin()
+            ).collect();
+            agc.process(&mut audio);
+            let peak = audio[24000..].iter().fold(0.0 as Sample, |peak, &x| peak.max(x.abs()));
+            assert!((20.0 * peak.log10() + 10.0).abs() < 1.0, "peak {}", peak);
+        }
+    }
+
+    #[test]
+    fn test_channel_filter() {
+        assert_eq!(ChannelFilter::parse("2700", Modulation::USB),
+            Some(ChannelFilter { bandwidth: 2700.0, half_length: 128 }));
+        assert_eq!(ChannelFilter::parse("25000:48", Modulation::FM),
+            Some(ChannelFilter { bandwidth: 25000.0, half_length: 48 }));
+        assert_eq!(ChannelFilter::parse("25000:0", Modulation::FM), None);
+        assert_eq!(ChannelFilter::parse(":64", Modulation::USB),
+            Some(ChannelFilter { bandwidth: 2400.0, half_length: 64 }));
+        assert_eq!(ChannelFilter::parse("", Modulation::USB), None);
+        // Signal 10 kHz off center is outside the default FM filter
+        // but inside a 25 kHz wide one.
+        let power = |channel_filter: ChannelFilter| {
+            let mut demodulator = Demodulator::with_filter(Modulation::FM, channel_filter);
+            let input: Vec<ComplexSample> = (0..4800).map(|i|
+                ComplexSample::from_polar(1.0, (2.0 * core::f64::consts::PI * 10000.0 * i as f64 / SAMPLE_RATE) as Sample)
+            ).collect();
+            demodulator.process(&input, &mut Vec::new());
+            demodulator.power()
+        };
+        assert!(power(ChannelFilter::default_for(Modulation::FM)) < 0.01);
+        assert!(power(ChannelFilter { bandwidth: 25000.0, half_length: 32 }) > 0.8);
+    }
+}
@@ -0,0 +1,282 @@
+//! Hardware diagnostics using test tones.
+//!
+//! Test tones are transmitted at several frequencies across
+//! the receive span, or given by a connected signal generator,
+//! and received signal is analyzed for each tone:
+//! image rejection, leakage of the filter bank into channels
+//! next to the tone, and the strongest spurious signal.
+//! Tones outside of the receive span show up as aliases.
+//! Results are written as a CSV report.
+
+use std::io::Write;
+
+use crate::{Sample, ComplexSample};
+use crate::configuration;
+use crate::fcfb;
+use crate::soapyconfig::SoapyIo;
+
+/// Tone offsets from receive center frequency
+/// as fractions of sample rate, used when transmitting.
+const TONE_POSITIONS: [f64; 8] = [-0.4, -0.3, -0.2, -0.1, 0.1, 0.2, 0.3, 0.4];
+/// Amplitude of transmitted tones relative to full scale.
+const TX_AMPLITUDE: Sample = 0.5;
+/// Time to wait after changing tone, in seconds.
+const SETTLE_TIME: f64 = 0.3;
+/// Time to measure each tone, in seconds.
+const MEASURE_TIME: f64 = 1.0;
+/// Measurement bandwidth in filter bank bins.
+/// Channels next to the tone are this far from it,
+/// so their passbands and transition bands do not contain
+/// the tone and any power in them is leakage.
+const MEASUREMENT_BINS: usize = 64;
+
+/// Results for one tone. Levels relative to the tone are in dBc.
+pub struct ToneReport {
+    /// Offset of the tone from receive center frequency.
+    pub offset: f64,
+    /// Whether the tone is outside the receive span
+    /// and was measured at its alias frequency.
+    pub aliased: bool,
+    pub signal_db: f64,
+    pub image_dbc: f64,
+    pub leakage_low_dbc: f64,
+    pub leakage_high_dbc: f64,
+    pub spur_dbc: f64,
+    /// Offset of the strongest spurious signal from center frequency.
+    pub spur_offset: f64,
+}
+
+/// Measures received signal around a tone.
+pub struct Analyzer {
+    parameters: fcfb::AnalysisInputParameters,
+    analysis: fcfb::AnalysisInputProcessor,
+    input_buffer: fcfb::InputBuffer,
+    /// Offset of the tone being measured.
+    offset: f64,
+    /// Measurements at the tone, its image and channels
+    /// below and above the tone, with sum of power in each.
+    /// None for those outside the receive span.
+    measurements: [Option<(fcfb::AnalysisPowerProcessor, Sample)>; 4],
+    spectrum: Vec<Sample>,
+    blocks: usize,
+}
+
+fn to_db(power: Sample) -> f64 {
+    10.0 * (power as f64 + 1e-30).log10()
+}
+
+impl Analyzer {
+    pub fn new(fft_planner: &mut rustfft::FftPlanner<Sample>, sample_rate: f64, bin_spacing: f64) -> Self {
+        let parameters = fcfb::AnalysisInputParameters {
+            fft_size: (sample_rate / bin_spacing).round() as usize,
+            sample_rate,
+            center_frequency: 0.0,
+        };
+        let analysis = fcfb::AnalysisInputProcessor::new(fft_planner, parameters);
+        let input_buffer = analysis.make_input_buffer();
+        Self {
+            parameters,
+            spectrum: vec![0.0; parameters.fft_size],
+            analysis,
+            input_buffer,
+            offset: 0.0,
+            measurements: [None, None, None, None],
+            blocks: 0,
+        }
+    }
+
+    fn measurement_bandwidth(&self) -> f64 {
+        MEASUREMENT_BINS as f64 * self.parameters.sample_rate / self.parameters.fft_size as f64
+    }
+
+    /// Frequency where a tone at given offset is received,
+    /// which differs from the offset for aliased tones.
+    fn received_offset(&self, offset: f64) -> f64 {
+        let sample_rate = self.parameters.sample_rate;
+        (offset + sample_rate / 2.0).rem_euclid(sample_rate) - sample_rate / 2.0
+    }
+
+    /// Start measuring a tone at given offset from center frequency.
+    pub fn start(&mut self, offset: f64) {
+        self.offset = offset;
+        let received = self.received_offset(offset);
+        let bandwidth = self.measurement_bandwidth();
+        let parameters = self.parameters;
+        self.measurements = [received, -received, received - bandwidth, received + bandwidth].map(|frequency|
+            fcfb::channel_fits(parameters.sample_rate, 0.0, bandwidth, frequency).then(||
+                (fcfb::AnalysisPowerProcessor::new_with_frequency(parameters, bandwidth, frequency), 0.0)));
+        self.spectrum.fill(0.0);
+        self.blocks = 0;
+    }
+
+    /// Buffer to write new input samples to.
+    pub fn input(&mut self) -> &mut [ComplexSample] {
+        self.input_buffer.prepare_for_new_samples()
+    }
+
+    /// Process the samples written to input.
+    /// If measure is false, the block is only used to fill
+    /// the filter bank.
+    pub fn process(&mut self, measure: bool) {
+        let ir = self.analysis.process(self.input_buffer.buffer());
+        if measure {
+            for (processor, sum) in self.measurements.iter_mut().flatten() {
+                *sum += processor.process(ir);
+            }
+            ir.add_power_spectrum(&mut self.spectrum);
+            self.blocks += 1;
+        }
+    }
+
+    /// Results of the current measurement.
+    pub fn report(&self) -> ToneReport {
+        let fft_size = self.parameters.fft_size;
+        let bin_spacing = self.parameters.sample_rate / fft_size as f64;
+        let blocks = self.blocks.max(1) as Sample;
+        let [signal_db, image_db, low_db, high_db] = self.measurements.each_ref().map(|measurement|
+            measurement.as_ref().map_or(f64::NAN, |(_, sum)| to_db(sum / blocks)));
+
+        // Strongest bin away from the tone, its image and DC.
+        let received = self.received_offset(self.offset);
+        let half_bandwidth = self.measurement_bandwidth() / 2.0;
+        let excluded = |frequency: f64| [received, -received, 0.0].iter()
+            .any(|&f| (frequency - f).abs() <= half_bandwidth);
+        let (spur_offset, spur_power) = self.spectrum.iter().enumerate()
+            .map(|(bin, &power)| ((bin as f64 - (fft_size / 2) as f64) * bin_spacing, power / blocks))
+            .filter(|&(frequency, _)| !excluded(frequency))
+            .fold((0.0, 0.0), |max, (frequency, power)| if power > max.1 { (frequency, power) } else { max });
+
+        ToneReport {
+            offset: self.offset,
+            aliased: received != self.offset,
+            signal_db,
+            image_dbc: image_db - signal_db,
+            leakage_low_dbc: low_db - signal_db,
+            leakage_high_dbc: high_db - signal_db,
+            spur_dbc: to_db(spur_power) - signal_db,
+            spur_offset,
+        }
+    }
+}
+
+/// Read a full block of samples from SDR.
+fn receive_block(sdr: &mut SoapyIo, buffer: &mut [ComplexSample]) {
+    let mut filled = 0;
+    while filled < buffer.len() {
+        match sdr.receive(&mut buffer[filled..]) {
+            Ok(result) => filled += result.len,
+            // TODO: handle errors more nicely
+            Err(err) => panic!("Error receiving from SDR: {}", err),
+        }
+    }
+}
+
+fn write_report(path: &str, header: &str, reports: &[ToneReport]) -> std::io::Result<()> {
+    let mut file: Box<dyn Write> = if path == "-" {
+        Box::new(std::io::stdout())
+    } else {
+        Box::new(std::fs::File::create(path)?)
+    };
+    writeln!(file, "# {}", header)?;
+    writeln!(file, "offset,aliased,signal_db,image_dbc,leakage_low_dbc,leakage_high_dbc,spur_dbc,spur_offset")?;
+    for r in reports {
+        writeln!(file, "{:.0},{},{:.1},{:.1},{:.1},{:.1},{:.1},{:.0}",
+            r.offset, r.aliased, r.signal_db, r.image_dbc,
+            r.leakage_low_dbc, r.leakage_high_dbc, r.spur_dbc, r.spur_offset)?;
+    }
+    Ok(())
+}
+
+/// Measure test tones and write a report to given path.
+/// Tones from a signal generator are given by --diagnose-tone,
+/// otherwise tones are transmitted.
+pub fn run(
+    cli: &configuration::Cli,
+    fft_planner: &mut rustfft::FftPlanner<Sample>,
+    sdr: &mut SoapyIo,
+    path: &str,
+) {
+    // TODO: handle errors more nicely
+    let sample_rate = sdr.rx_sample_rate().expect("Diagnostics require receiving");
+    let center_frequency = sdr.rx_center_frequency().unwrap();
+    let transmit = cli.diagnose_tone.is_empty();
+    if transmit && !sdr.tx_enabled() {
+        panic!("Diagnostics require transmitting or --diagnose-tone");
+    }
+    let tones: Vec<f64> = if transmit {
+        TONE_POSITIONS.iter().map(|position| center_frequency + position * sample_rate).collect()
+    } else {
+        cli.diagnose_tone.clone()
+    };
+
+    let mut analyzer = Analyzer::new(fft_planner, sample_rate, cli.rx_bin_spacing);
+    let mut tx_buffer: Vec<ComplexSample> = Vec::new();
+    let mut reports = Vec::new();
+    for &frequency in tones.iter() {
+        eprintln!("Measuring tone at {} Hz", frequency);
+        let tx_increment = if transmit {
+            (frequency - sdr.tx_center_frequency().unwrap()) / sdr.tx_sample_rate().unwrap()
+        } else { 0.0 };
+        let mut tx_phase: f64 = 0.0;
+        let mut time = 0.0;
+        analyzer.start(frequency - center_frequency);
+        while time < SETTLE_TIME + MEASURE_TIME {
+            let input = analyzer.input();
+            receive_block(sdr, input);
+            time += input.len() as f64 / sample_rate;
+            if transmit {
+                tx_buffer.clear();
+                for _ in 0..input.len() {
+                    tx_buffer.push(ComplexSample::from_polar(TX_AMPLITUDE, (2.0 * std::f64::consts::PI * tx_phase) as Sample));
+                    tx_phase = (tx_phase + tx_increment).fract();
+                }
+                if let Err(err) = sdr.transmit(&tx_buffer, None) {
+                    eprintln!("Error transmitting to SDR: {}", err);
+                }
+            }
+            analyzer.process(time > SETTLE_TIME);
+        }
+        reports.push(analyzer.report());
+    }
+
+    let worst = |value: fn(&ToneReport) -> f64| reports.iter().map(value)
+        .filter(|v| !v.is_nan()).fold(f64::NEG_INFINITY, f64::max);
+    eprintln!("Worst image rejection: {:.1} dB", -worst(|r| r.image_dbc));
+    eprintln!("Worst filter bank leakage: {:.1} dBc", worst(|r| r.leakage_low_dbc.max(r.leakage_high_dbc)));
+    eprintln!("Strongest spur: {:.1} dBc", worst(|r| r.spur_dbc));
+
+    let header = format!("sdrglue diagnostics: sample rate {} Hz, center frequency {} Hz, bin spacing {} Hz",
+        sample_rate, center_frequency, cli.rx_bin_spacing);
+    if let Err(err) = write_report(path, &header, &reports) {
+        eprintln!("Could not write diagnostics report {}: {}", path, err);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_image() {
+        // Tone with an image 40 dB below it
+        let sample_rate = 1e6;
+        let mut analyzer = Analyzer::new(&mut rustfft::FftPlanner::new(), sample_rate, 500.0);
+        let offset = 200e3;
+        analyzer.start(offset);
+        let mut n = 0;
+        for block in 0..50 {
+            for sample in analyzer.input() {
+                let phase = (2.0 * std::f64::consts::PI * offset * n as f64 / sample_rate) as Sample;
+                *sample = ComplexSample::from_polar(1.0, phase) + ComplexSample::from_polar(0.01, -phase);
+                n += 1;
+            }
+            analyzer.process(block >= 5);
+        }
+        let report = analyzer.report();
+        assert!(!report.aliased);
+        assert!(report.signal_db.abs() < 0.1);
+        assert!((report.image_dbc + 40.0).abs() < 0.1);
+        assert!(report.leakage_low_dbc < -80.0);
+        assert!(report.leakage_high_dbc < -80.0);
+    }
+}
@@ -0,0 +1,234 @@
+//! Events such as squelch opening, and things reacting to them.
+//!
+//! Any part of the program can emit an event using emit().
+//! Events are passed to a separate thread which hands them
+//! to each registered EventListener, so that listeners doing
+//! slow things (like running external commands) do not
+//! disturb signal processing. In reproducible mode,
+//! listeners are called directly instead.
+
+use std::collections::VecDeque;
+use std::sync::{mpsc, Mutex, OnceLock};
+use std::time::SystemTime;
+
+use crate::activity;
+use crate::clock;
+use crate::configuration;
+use crate::influx;
+use crate::watchlist;
+
+/// Value of an event field.
+#[derive(Clone, Debug)]
+pub enum Value {
+    Str(String),
+    Number(f64),
+}
+
+impl From<&str> for Value {
+    fn from(value: &str) -> Self { Value::Str(value.to_string()) }
+}
+impl From<String> for Value {
+    fn from(value: String) -> Self { Value::Str(value) }
+}
+impl From<f64> for Value {
+    fn from(value: f64) -> Self { Value::Number(value) }
+}
+impl From<f32> for Value {
+    fn from(value: f32) -> Self { Value::Number(value as f64) }
+}
+impl From<usize> for Value {
+    fn from(value: usize) -> Self { Value::Number(value as f64) }
+}
+
+#[derive(Clone, Debug)]
+pub struct Event {
+    /// Type of the event, such as "squelch_open".
+    pub kind: &'static str,
+    /// Time when the event happened.
+    pub time: SystemTime,
+    /// Additional information about the event.
+    pub fields: Vec<(&'static str, Value)>,
+}
+
+impl Event {
+    pub fn new(kind: &'static str) -> Self {
+        Self {
+            kind,
+            time: clock::now(),
+            fields: Vec::new(),
+        }
+    }
+
+    /// Add a field to the event.
+    pub fn with(mut self, key: &'static str, value: impl Into<Value>) -> Self {
+        self.fields.push((key, value.into()));
+        self
+    }
+
+    /// Format the event as a single-line JSON object.
+    pub fn to_json(&self) -> String {
+        let time = self.time.duration_since(SystemTime::UNIX_EPOCH)
+            .map(|t| t.as_secs_f64()).unwrap_or(0.0);
+        let mut json = format!("{{\"event\":{},\"time\":{:.3}", json_string(self.kind), time);
+        for (key, value) in self.fields.iter() {
+            json.push(',');
+            json.push_str(&json_string(key));
+            json.push(':');
+            match value {
+                Value::Str(s) => json.push_str(&json_string(s)),
+                Value::Number(n) if n.is_finite() => json.push_str(&n.to_string()),
+                Value::Number(_) => json.push_str("null"),
+            }
+        }
+        json.push('}');
+        json
+    }
+}
+
+/// Quote and escape a string for JSON.
+pub fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"'  => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// Something that reacts to events.
+pub trait EventListener: Send {
+    fn event(&mut self, event: &Event);
+}
+
+static SENDER: OnceLock<mpsc::Sender<Event>> = OnceLock::new();
+/// Listeners called directly by emit in reproducible mode,
+/// so that events are handled in a fixed order with processing.
+static LISTENERS: OnceLock<Mutex<Vec<Box<dyn EventListener>>>> = OnceLock::new();
+/// Events waiting for the listeners in reproducible mode.
+/// Listeners may emit events themselves, so those are
+/// queued and handled after the current event.
+static PENDING: Mutex<VecDeque<Event>> = Mutex::new(VecDeque::new());
+
+/// Emit an event.
+/// Does nothing if there are no event listeners.
+pub fn emit(event: Event) {
+    if let Some(sender) = SENDER.get() {
+        let _ = sender.send(event);
+    } else if let Some(listeners) = LISTENERS.get() {
+        PENDING.lock().unwrap().push_back(event);
+        let Ok(mut listeners) = listeners.try_lock() else {
+            return;
+        };
+        while let Some(event) = PENDING.lock().unwrap().pop_front() {
+            for listener in listeners.iter_mut() {
+                listener.event(&event);
+            }
+        }
+    }
+}
+
+/// Start the event handling thread with listeners
+/// configured on the command line.
+pub fn init(cli: &configuration::Cli) {
+    let mut listeners: Vec<Box<dyn EventListener>> = Vec::new();
+    for args in cli.event_hook.chunks_exact(2) {
+        listeners.push(Box::new(CommandHook {
+            kind: args[0].clone(),
+            command: args[1].clone(),
+        }));
+    }
+    if !cli.alert.is_empty() {
+        listeners.push(Box::new(watchlist::Watchlist::from_args(&cli.alert)));
+    }
+    if let Some(path) = &cli.activity_log {
+        listeners.push(Box::new(activity::ActivityLogger::new(path)));
+    }
+    if let Some(destination) = &cli.influxdb {
+        listeners.push(Box::new(influx::InfluxExporter::new(destination)));
+    }
+    #[cfg(feature = "sqlite")]
+    if let Some(path) = &cli.database {
+        listeners.push(Box::new(crate::database::DatabaseLogger::open(path, cli.database_retention)));
+    }
+    if listeners.is_empty() {
+        return;
+    }
+    if clock::reproducible() {
+        let _ = LISTENERS.set(Mutex::new(listeners));
+        return;
+    }
+
+    let (sender, receiver) = mpsc::channel::<Event>();
+    std::thread::spawn(move || {
+        for event in receiver.iter() {
+            for listener in listeners.iter_mut() {
+                listener.event(&event);
+            }
+        }
+    });
+    let _ = SENDER.set(sender);
+}
+
+
+/// Run an external command when an event happens.
+/// The event is written to standard input of the command as JSON.
+struct CommandHook {
+    /// Type of events to run the command for, or "*" for all events.
+    kind: String,
+    /// Command line, run using the system shell.
+    command: String,
+}
+
+impl EventListener for CommandHook {
+    fn event(&mut self, event: &Event) {
+        if self.kind != "*" && self.kind != event.kind {
+            return;
+        }
+        let mut shell = if cfg!(windows) {
+            let mut c = std::process::Command::new("cmd");
+            c.arg("/C");
+            c
+        } else {
+            let mut c = std::process::Command::new("sh");
+            c.arg("-c");
+            c
+        };
+        let child = shell
+            .arg(&self.command)
+            .env("SDRGLUE_EVENT", event.kind)
+            .stdin(std::process::Stdio::piped())
+            .spawn();
+        match child {
+            Ok(mut child) => {
+                let json = event.to_json();
+                // Write input and wait for the command in another thread,
+                // so a slow command does not delay other events.
+                // In reproducible mode, wait so that commands run in order.
+                let mut run = move || {
+                    use std::io::Write;
+                    if let Some(mut stdin) = child.stdin.take() {
+                        let _ = writeln!(stdin, "{}", json);
+                    }
+                    let _ = child.wait();
+                };
+                if clock::reproducible() {
+                    run();
+                } else {
+                    std::thread::spawn(run);
+                }
+            },
+            Err(err) => {
+                eprintln!("Failed to run event hook {}: {}", self.command, err);
+            },
+        }
+    }
+}
@@ -0,0 +1,228 @@
+//! Convenience wrappers combining the parts of filter banks.
+//!
+//! Channelizer wraps an analysis input processor and
+//! any number of output processors, and Dechannelizer
+//! wraps a synthesis output processor and its input processors,
+//! so that users do not have to deal with input block sizes
+//! or intermediate results.
+
+use crate::{Sample, ComplexSample};
+use super::*;
+
+struct ChannelizerOutput {
+    processor: AnalysisOutputProcessor,
+    /// Output signal produced since the previous push.
+    output: Vec<ComplexSample>,
+}
+
+/// Splits a wideband signal into any number of channels.
+///
+/// Usage:
+/// ```ignore
+/// let mut channelizer = Channelizer::new(&mut planner, 1e6, 0.0, 500.0);
+/// let channel = channelizer.add_channel(&mut planner, 48000.0, 100e3);
+/// channelizer.push(&input_samples);
+/// for (channel, output) in channelizer.outputs() { ... }
+/// ```
+pub struct Channelizer {
+    input_processor: AnalysisInputProcessor,
+    input_buffer: InputBuffer,
+    /// Number of new samples written to the current input block.
+    input_fill: usize,
+    channels: Vec<ChannelizerOutput>,
+}
+
+impl Channelizer {
+    /// Create a channelizer for input signal
+    /// with given sample rate, center frequency and bin spacing.
+    /// Sample rate should be an integer multiple of 2 * bin spacing.
+    pub fn new(
+        fft_planner: &mut rustfft::FftPlanner<Sample>,
+        sample_rate: f64,
+        center_frequency: f64,
+        bin_spacing: f64,
+    ) -> Self {
+        let input_processor = AnalysisInputProcessor::new(fft_planner, AnalysisInputParameters {
+            fft_size: (sample_rate / bin_spacing).round() as usize,
+            sample_rate,
+            center_frequency,
+        });
+        let input_buffer = input_processor.make_input_buffer();
+        Self {
+            input_processor,
+            input_buffer,
+            input_fill: 0,
+            channels: Vec::new(),
+        }
+    }
+
+    /// Add an output channel with given sample rate and center frequency.
+    /// Return index of the channel.
+    pub fn add_channel(
+        &mut self,
+        fft_planner: &mut rustfft::FftPlanner<Sample>,
+        sample_rate: f64,
+        center_frequency: f64,
+    ) -> usize {
+        self.channels.push(ChannelizerOutput {
+            processor: AnalysisOutputProcessor::new_with_frequency(
+                fft_planner,
+                self.input_processor.parameters,
+                sample_rate,
+                center_frequency,
+            ),
+            output: Vec::new(),
+        });
+        self.channels.len() - 1
+    }
+
+    /// Apply frequency masks to a channel.
+    pub fn apply_mask(&mut self, channel: usize, masks: &[FrequencyMask]) {
+        self.channels[channel].processor.apply_mask(masks);
+    }
+
+    /// Center frequency of the input signal.
+    pub fn center_frequency(&self) -> f64 {
+        self.input_processor.parameters.center_frequency
+    }
+
+    /// Number of input samples processed at a time.
+    /// Output is produced each time a whole block has been pushed.
+    pub fn input_block_size(&self) -> usize {
+        self.input_processor.input_block_size().new
+    }
+
+    /// Number of output samples a channel produces for each input block.
+    pub fn channel_block_size(&self, channel: usize) -> usize {
+        self.channels[channel].processor.output_block_size()
+    }
+
+    /// Number of input samples to push to produce
+    /// given number of blocks of output.
+    pub fn input_for_blocks(&self, blocks: usize) -> usize {
+        blocks.saturating_mul(self.input_block_size()).saturating_sub(self.input_fill)
+    }
+
+    /// Push any number of input samples.
+    /// Output signals produced from them can be read with outputs().
+    /// Output from the previous push is cleared.
+    pub fn push(&mut self, mut input: &[ComplexSample]) {
+        for channel in self.channels.iter_mut() {
+            channel.output.clear();
+        }
+        let block_size = self.input_processor.input_block_size().new;
+        while !input.is_empty() {
+            if self.input_fill == 0 {
+                self.input_buffer.prepare_for_new_samples();
+            }
+            let n = input.len().min(block_size - self.input_fill);
+            self.input_buffer.new_samples_mut()[self.input_fill .. self.input_fill + n]
+                .copy_from_slice(&input[..n]);
+            self.input_fill += n;
+            input = &input[n..];
+
+            if self.input_fill == block_size {
+                self.input_fill = 0;
+                let intermediate_result = self.input_processor.process(self.input_buffer.buffer());
+                for channel in self.channels.iter_mut() {
+                    channel.output.extend_from_slice(channel.processor.process(intermediate_result));
+                }
+            }
+        }
+    }
+
+    /// Output signal of a channel produced by the previous push.
+    pub fn output(&self, channel: usize) -> &[ComplexSample] {
+        &self.channels[channel].output
+    }
+
+    /// Iterate over outputs of all channels
+    /// produced by the previous push,
+    /// as pairs of channel index and output signal.
+    pub fn outputs(&self) -> impl Iterator<Item = (usize, &[ComplexSample])> {
+        self.channels.iter().enumerate().map(|(index, channel)| (index, &channel.output[..]))
+    }
+}
+
+
+struct DechannelizerInput {
+    processor: SynthesisInputProcessor,
+    buffer: InputBuffer,
+}
+
+/// Combines any number of channels into a wideband signal.
+///
+/// Usage:
+/// ```ignore
+/// let mut dechannelizer = Dechannelizer::new(&mut planner, 1e6, 0.0, 500.0);
+/// let channel = dechannelizer.add_channel(&mut planner, 48000.0, 100e3);
+/// let output = dechannelizer.process(|channel, input| { /* fill input */ });
+/// ```
+pub struct Dechannelizer {
+    output_processor: SynthesisOutputProcessor,
+    channels: Vec<DechannelizerInput>,
+}
+
+impl Dechannelizer {
+    /// Create a dechannelizer for output signal
+    /// with given sample rate, center frequency and bin spacing.
+    /// Sample rate should be an integer multiple of 2 * bin spacing.
+    pub fn new(
+        fft_planner: &mut rustfft::FftPlanner<Sample>,
+        sample_rate: f64,
+        center_frequency: f64,
+        bin_spacing: f64,
+    ) -> Self {
+        Self {
+            output_processor: SynthesisOutputProcessor::new(fft_planner, SynthesisOutputParameters {
+                ifft_size: (sample_rate / bin_spacing).round() as usize,
+                sample_rate,
+                center_frequency,
+            }),
+            channels: Vec::new(),
+        }
+    }
+
+    /// Add an input channel with given sample rate and center frequency.
+    /// Return index of the channel.
+    pub fn add_channel(
+        &mut self,
+        fft_planner: &mut rustfft::FftPlanner<Sample>,
+        sample_rate: f64,
+        center_frequency: f64,
+    ) -> usize {
+        let processor = SynthesisInputProcessor::new_with_frequency(
+            fft_planner,
+            self.output_processor.parameters,
+            sample_rate,
+            center_frequency,
+        );
+        let buffer = processor.make_input_buffer();
+        self.channels.push(DechannelizerInput { processor, buffer });
+        self.channels.len() - 1
+    }
+
+    /// Number of output samples in each output block.
+    pub fn output_block_size(&self) -> usize {
+        self.output_processor.parameters.ifft_size / 2
+    }
+
+    /// Number of input samples needed from a channel for each output block.
+    pub fn channel_block_size(&self, channel: usize) -> usize {
+        self.channels[channel].processor.input_block_size().new
+    }
+
+    /// Produce a block of output signal.
+    /// fill is called for each channel with the channel index
+    /// and a slice to be filled with new input samples for the channel.
+    pub fn process(
+        &mut self,
+        mut fill: impl FnMut(usize, &mut [ComplexSample]),
+    ) -> &[ComplexSample] {
+        for (index, channel) in self.channels.iter_mut().enumerate() {
+            fill(index, channel.buffer.prepare_for_new_samples());
+            self.output_processor.add(channel.processor.process(channel.buffer.buffer()));
+        }
+        self.output_processor.process()
+    }
+}
@@ -7,7 +7,12 @@ use rustfft;
 use crate::{Sample, ComplexSample, sample_consts};
 use crate::num_traits::Zero;
 
+#[cfg(test)]
 mod sweep;
+mod channelizer;
+pub use channelizer::*;
+mod two_stage;
+pub use two_stage::*;
 
 
 // ------------------------------------------------
@@ -26,30 +31,45 @@ pub struct InputBlockSize {
     pub overlap: usize,
 }
 
-pub struct InputBuffer {
+/// Input buffer for complex samples,
+/// or for real samples as InputBuffer<Sample>.
+pub struct InputBuffer<T = ComplexSample> {
     size: InputBlockSize,
-    buffer: Vec<ComplexSample>,
+    buffer: Vec<T>,
 }
 
-impl InputBuffer {
+impl<T: Copy + Zero> InputBuffer<T> {
     pub fn new(size: InputBlockSize) -> Self {
         Self {
             size,
-            buffer: vec![ComplexSample::ZERO; size.new + size.overlap],
+            buffer: vec![T::zero(); size.new + size.overlap],
         }
     }
 
     /// Prepare buffer for a new input block.
     /// Return a slice for writing new input samples.
-    pub fn prepare_for_new_samples(&mut self) -> &mut [ComplexSample] {
+    pub fn prepare_for_new_samples(&mut self) -> &mut [T] {
         // Move overlapping part from the end of the previous block to the beginning
         self.buffer.copy_within(self.size.new .. self.size.new + self.size.overlap, 0);
         // Return slice for writing new samples
         &mut self.buffer[self.size.overlap .. self.size.new + self.size.overlap]
     }
 
+    /// Return the slice for new input samples
+    /// without moving the overlapping part.
+    /// Can be used to write new samples in smaller pieces
+    /// after calling prepare_for_new_samples.
+    pub fn new_samples_mut(&mut self) -> &mut [T] {
+        &mut self.buffer[self.size.overlap .. self.size.new + self.size.overlap]
+    }
+
+    /// Return the new input samples of the current block.
+    pub fn new_samples(&self) -> &[T] {
+        &self.buffer[self.size.overlap .. self.size.new + self.size.overlap]
+    }
+
     /// Return a slice which can be passed to the process() method of a filter bank.
-    pub fn buffer(&self) -> &[ComplexSample] {
+    pub fn buffer(&self) -> &[T] {
         &self.buffer[..]
     }
 }
@@ -75,6 +95,22 @@ pub struct AnalysisIntermediateResult {
     count: usize,
 }
 
+impl AnalysisIntermediateResult {
+    /// Add power spectrum of the block to output,
+    /// combining FFT bins into output.len() bins
+    /// in order of increasing frequency.
+    /// Power is scaled so that a full scale sinusoid is 1.
+    pub fn add_power_spectrum(&self, output: &mut [Sample]) {
+        let fft_size = self.fft_result.len();
+        let scaling = 1.0 / (fft_size * fft_size) as Sample;
+        for (bin, value) in self.fft_result.iter().enumerate() {
+            // Negative frequencies come first in output.
+            let shifted = (bin + fft_size / 2) % fft_size;
+            output[shifted * output.len() / fft_size] += value.norm_sqr() * scaling;
+        }
+    }
+}
+
 /// Fast-convolution analysis filter bank.
 pub struct AnalysisInputProcessor {
     parameters: AnalysisInputParameters,
@@ -135,6 +171,95 @@ impl AnalysisInputProcessor {
     }
 }
 
+/// Fast-convolution analysis filter bank for real input signal.
+///
+/// Uses a complex FFT of half the size by packing
+/// even and odd input samples into real and imaginary parts.
+/// Produces the same intermediate result as AnalysisInputProcessor
+/// would produce for the same signal converted to complex,
+/// so the same output processors can be used with either.
+/// Parameters are the same as for AnalysisInputProcessor.
+/// Center frequency is the frequency corresponding to 0 Hz
+/// in the real signal, for example 0 for direct sampling.
+pub struct RealAnalysisInputProcessor {
+    parameters: AnalysisInputParameters,
+    fft_plan: Arc<dyn rustfft::Fft<Sample>>,
+    /// Buffer for the half size FFT.
+    buffer: Vec<ComplexSample>,
+    /// Twiddle factors for splitting the half size FFT result.
+    twiddles: Vec<ComplexSample>,
+    result: AnalysisIntermediateResult,
+}
+
+impl RealAnalysisInputProcessor {
+    pub fn new(
+        fft_planner: &mut rustfft::FftPlanner<Sample>,
+        parameters: AnalysisInputParameters,
+    ) -> Self {
+        let fft_size = parameters.fft_size;
+        assert!(fft_size.is_multiple_of(2));
+        Self {
+            parameters,
+            fft_plan: fft_planner.plan_fft_forward(fft_size / 2),
+            buffer: vec![ComplexSample::ZERO; fft_size / 2],
+            twiddles: (0 ..= fft_size / 2).map(|k| ComplexSample::from_polar(
+                1.0,
+                (-2.0 * std::f64::consts::PI * k as f64 / fft_size as f64) as Sample,
+            )).collect(),
+            result: AnalysisIntermediateResult {
+                fft_result: vec![ComplexSample::ZERO; fft_size],
+                count: 1,
+            }
+        }
+    }
+
+    pub fn input_block_size(&self) -> InputBlockSize {
+        InputBlockSize {
+            new: self.parameters.fft_size / 2,
+            overlap: self.parameters.fft_size / 2,
+        }
+    }
+
+    pub fn make_input_buffer(&self) -> InputBuffer<Sample> {
+        InputBuffer::new(self.input_block_size())
+    }
+
+    /// Process a block of real input samples.
+    /// Input should overlap between blocks
+    /// in the same way as for AnalysisInputProcessor.
+    pub fn process(
+        &mut self,
+        input: &[Sample],
+    ) -> &AnalysisIntermediateResult {
+        let half = self.buffer.len();
+        for (z, x) in self.buffer.iter_mut().zip(input.chunks_exact(2)) {
+            *z = ComplexSample::new(x[0], x[1]);
+        }
+        self.fft_plan.process(&mut self.buffer[..]);
+
+        // Separate spectra of even and odd samples
+        // and combine them into the spectrum of the whole block.
+        // The other half of the spectrum is the complex conjugate
+        // of the first half since the input is real.
+        let result = &mut self.result.fft_result;
+        for k in 0 ..= half {
+            let z = self.buffer[k % half];
+            let zc = self.buffer[(half - k) % half].conj();
+            let even = (z + zc) * 0.5;
+            let odd = (z - zc) * ComplexSample::new(0.0, -0.5);
+            let x = even + self.twiddles[k] * odd;
+            result[k] = x;
+            if k != 0 && k != half {
+                result[2 * half - k] = x.conj();
+            }
+        }
+
+        self.result.count = (self.result.count + 1) % 2;
+
+        &self.result
+    }
+}
+
 #[derive(Clone)]
 pub struct AnalysisOutputParameters {
     pub center_bin: isize,
@@ -156,20 +281,35 @@ impl AnalysisOutputParameters {
             / analysis_in_params.sample_rate
         ).round() as usize;
 
-        let center_bin = ((
-            (output_center_frequency - analysis_in_params.center_frequency)
-            * analysis_in_params.fft_size as f64
-            / analysis_in_params.sample_rate
-        ).round() as isize
-        ).rem_euclid(analysis_in_params.fft_size as isize);
-
         Self {
-            center_bin,
+            center_bin: analysis_center_bin(analysis_in_params, output_center_frequency),
             weights: raised_cosine_weights(ifft_size, None, None),
         }
     }
 }
 
+/// Index of the analysis bank bin closest to a frequency.
+fn analysis_center_bin(analysis_in_params: AnalysisInputParameters, frequency: f64) -> isize {
+    ((
+        (frequency - analysis_in_params.center_frequency)
+        * analysis_in_params.fft_size as f64
+        / analysis_in_params.sample_rate
+    ).round() as isize
+    ).rem_euclid(analysis_in_params.fft_size as isize)
+}
+
+/// Gain applied to a range of frequencies inside a channel,
+/// before the channel IFFT.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FrequencyMask {
+    /// Start and end of the range in Hertz,
+    /// relative to channel center frequency.
+    pub start: f64,
+    pub end: f64,
+    /// Linear amplitude gain, 0 to remove the range completely.
+    pub gain: Sample,
+}
+
 pub struct AnalysisOutputProcessor {
     input_parameters: AnalysisInputParameters,
     parameters: AnalysisOutputParameters,
@@ -212,15 +352,36 @@ impl AnalysisOutputProcessor {
 
         let fft_size = self.input_parameters.fft_size;
         let ifft_size = self.buffer.len();
-        let half_size = (ifft_size / 2) as isize;
-
-        // This could probably be optimized a lot.
-        // Now it computes each index using modulos which might be slow.
-        for bin_number in -half_size .. half_size {
-            let bin_index_in = (self.parameters.center_bin + bin_number).rem_euclid(fft_size as isize) as usize;
-            let bin_index_out = bin_number.rem_euclid(ifft_size as isize) as usize;
-            // Apply weight
-            self.buffer[bin_index_out] = self.parameters.weights[bin_index_out] * intermediate_result.fft_result[bin_index_in] * scaling;
+        let half_size = ifft_size / 2;
+        let center_bin = self.parameters.center_bin.rem_euclid(fft_size as isize) as usize;
+
+        // Positive frequencies go to the first half of IFFT input
+        // and negative frequencies to the second half.
+        // Each half is a contiguous range of FFT bins
+        // which may wrap around the end of FFT result once,
+        // so copy them in contiguous pieces instead of computing
+        // a modulo for each bin.
+        for (out_start, in_start) in [
+            (0, center_bin),
+            (half_size, (center_bin + fft_size - half_size % fft_size) % fft_size),
+        ] {
+            let mut out_index = out_start;
+            let mut in_index = in_start;
+            let mut remaining = half_size;
+            while remaining > 0 {
+                let n = remaining.min(fft_size - in_index);
+                for ((out, weight), value) in
+                    self.buffer[out_index .. out_index + n].iter_mut()
+                    .zip(self.parameters.weights[out_index .. out_index + n].iter())
+                    .zip(intermediate_result.fft_result[in_index .. in_index + n].iter())
+                {
+                    // Apply weight
+                    *out = value * (weight * scaling);
+                }
+                out_index += n;
+                in_index = (in_index + n) % fft_size;
+                remaining -= n;
+            }
         }
 
         self.ifft_plan.process(&mut self.buffer);
@@ -229,6 +390,34 @@ impl AnalysisOutputProcessor {
         &self.buffer[ifft_size/4 .. ifft_size/4 * 3]
     }
 
+    /// Number of output samples produced for each input block.
+    pub fn output_block_size(&self) -> usize {
+        self.buffer.len() / 2
+    }
+
+    /// Apply gains to bins within given frequency ranges.
+    /// This can suppress an interferer inside a channel
+    /// without affecting other channels.
+    pub fn apply_mask(&mut self, masks: &[FrequencyMask]) {
+        let bin_spacing = self.input_parameters.sample_rate / self.input_parameters.fft_size as f64;
+        let ifft_size = self.parameters.weights.len();
+        let weights: Vec<Sample> = self.parameters.weights.iter().enumerate().map(|(index, &weight)| {
+            // Second half of weights is for negative frequencies.
+            let bin = if index < ifft_size / 2 { index as isize } else { index as isize - ifft_size as isize };
+            let frequency = bin as f64 * bin_spacing;
+            masks.iter()
+                .filter(|mask| frequency >= mask.start && frequency <= mask.end)
+                .fold(weight, |weight, mask| weight * mask.gain)
+        }).collect();
+        self.parameters.weights = Rc::<[Sample]>::from(weights);
+    }
+
+    /// Move the output to another center frequency.
+    /// Weights, including any masks, are kept.
+    pub fn set_center_frequency(&mut self, center_frequency: f64) {
+        self.parameters.center_bin = analysis_center_bin(self.input_parameters, center_frequency);
+    }
+
     pub fn new_with_frequency(
         fft_planner: &mut rustfft::FftPlanner<Sample>,
         analysis_in_params: AnalysisInputParameters,
@@ -245,6 +434,82 @@ impl AnalysisOutputProcessor {
 
 
 
+/// Measures signal power in a channel directly from
+/// the analysis filter bank FFT result without an IFFT.
+/// This is much cheaper than AnalysisOutputProcessor
+/// for channels which only need power measurements.
+pub struct AnalysisPowerProcessor {
+    input_parameters: AnalysisInputParameters,
+    /// Index of the FFT bin corresponding to the first weight.
+    first_bin: usize,
+    /// Squared and scaled weights in order of increasing frequency,
+    /// with zero weights at the edges left out.
+    weights: Vec<Sample>,
+}
+
+impl AnalysisPowerProcessor {
+    pub fn new(
+        input_parameters: AnalysisInputParameters,
+        parameters: AnalysisOutputParameters,
+    ) -> Self {
+        let fft_size = input_parameters.fft_size as isize;
+        let ifft_size = parameters.weights.len() as isize;
+        // Scaling gives the same power as the mean power
+        // of AnalysisOutputProcessor output.
+        let scaling = 1.0 / (fft_size * fft_size) as Sample;
+        // Reorder weights from the most negative frequency
+        // to the most positive one.
+        let weights: Vec<Sample> = (-ifft_size/2 .. ifft_size/2).map(|k| {
+            let weight = parameters.weights[k.rem_euclid(ifft_size) as usize];
+            weight * weight * scaling
+        }).collect();
+        let first = weights.iter().position(|&w| w != 0.0).unwrap_or(0);
+        let last = weights.iter().rposition(|&w| w != 0.0).unwrap_or(0);
+        Self {
+            input_parameters,
+            first_bin: (parameters.center_bin - ifft_size/2 + first as isize).rem_euclid(fft_size) as usize,
+            weights: weights[first ..= last].to_vec(),
+        }
+    }
+
+    pub fn new_with_frequency(
+        analysis_in_params: AnalysisInputParameters,
+        bandwidth: f64,
+        center_frequency: f64,
+    ) -> Self {
+        Self::new(
+            analysis_in_params,
+            AnalysisOutputParameters::for_frequency(analysis_in_params, bandwidth, center_frequency),
+        )
+    }
+
+    /// Return mean power of channel signal in the block.
+    pub fn process(
+        &self,
+        intermediate_result: &AnalysisIntermediateResult,
+    ) -> Sample {
+        let fft_size = self.input_parameters.fft_size;
+        assert!(intermediate_result.fft_result.len() == fft_size);
+        // Bins are a contiguous range which may wrap around
+        // the end of FFT result once.
+        let mut power = 0.0;
+        let mut weight_index = 0;
+        let mut bin = self.first_bin;
+        while weight_index < self.weights.len() {
+            let n = (self.weights.len() - weight_index).min(fft_size - bin);
+            for (weight, value) in
+                self.weights[weight_index .. weight_index + n].iter()
+                .zip(intermediate_result.fft_result[bin .. bin + n].iter())
+            {
+                power += weight * value.norm_sqr();
+            }
+            weight_index += n;
+            bin = (bin + n) % fft_size;
+        }
+        power
+    }
+}
+
 
 // ----------------------------------------
 //          Synthesis filter bank
@@ -278,13 +543,13 @@ enum SynthesisBufferState {
     /// Buffer is full of zeros.
     /// This is the case if no inputs have been added
     /// since the last call to process.
-    CLEAR,
+    Clear,
     /// Buffer contains inputs to IFFT in frequency domain,
     /// that is, one or more inputs have been added.
-    INPUT,
+    Input,
     /// Buffer contains IFFT output.
     /// A slice of the buffer is used to return output signal.
-    OUTPUT,
+    Output,
 }
 
 pub struct SynthesisIntermediateResult {
@@ -304,7 +569,7 @@ impl SynthesisOutputProcessor {
             parameters,
             ifft_plan: fft_planner.plan_fft_inverse(parameters.ifft_size),
             buffer: vec![ComplexSample::ZERO; parameters.ifft_size],
-            buffer_state: SynthesisBufferState::CLEAR,
+            buffer_state: SynthesisBufferState::Clear,
             count: 0,
         }
     }
@@ -313,7 +578,7 @@ impl SynthesisOutputProcessor {
         for b in self.buffer.iter_mut() {
             *b = ComplexSample::ZERO;
         }
-        self.buffer_state = SynthesisBufferState::CLEAR;
+        self.buffer_state = SynthesisBufferState::Clear;
     }
 
     pub fn add(
@@ -323,7 +588,7 @@ impl SynthesisOutputProcessor {
         // If previous result is still in the buffer, clear it
         // before starting to add inputs.
         // This happens for the first input added to a block.
-        if self.buffer_state == SynthesisBufferState::OUTPUT {
+        if self.buffer_state == SynthesisBufferState::Output {
             self.clear();
         }
 
@@ -343,36 +608,48 @@ impl SynthesisOutputProcessor {
             (intermediate_result.offset % 2 == 1)
             && (self.count == 1);
 
+        // Input bins go to a contiguous range of IFFT input
+        // which may wrap around the end once,
+        // so add them in contiguous pieces.
         let ifft_size = self.buffer.len();
-        for (index, value) in intermediate_result.fft_result.iter().enumerate() {
-            // TODO: handle wrap-around without computing a modulo for each bin
-            let out_index = (intermediate_result.offset + index).rem_euclid(ifft_size);
+        let input = &intermediate_result.fft_result[..];
+        let mut in_index = 0;
+        let mut out_index = intermediate_result.offset % ifft_size;
+        while in_index < input.len() {
+            let n = (input.len() - in_index).min(ifft_size - out_index);
+            let out = &mut self.buffer[out_index .. out_index + n];
             if invert {
-                self.buffer[out_index] -= value;
+                for (o, value) in out.iter_mut().zip(input[in_index .. in_index + n].iter()) {
+                    *o -= value;
+                }
             } else {
-                self.buffer[out_index] += value;
+                for (o, value) in out.iter_mut().zip(input[in_index .. in_index + n].iter()) {
+                    *o += value;
+                }
             }
+            in_index += n;
+            out_index = (out_index + n) % ifft_size;
         }
 
-        self.buffer_state = SynthesisBufferState::INPUT;
+        self.buffer_state = SynthesisBufferState::Input;
     }
 
     pub fn process(
         &mut self,
     ) -> &[ComplexSample] {
         match self.buffer_state {
-            SynthesisBufferState::CLEAR => {
+            SynthesisBufferState::Clear => {
                 // No inputs have been added. Buffer is full of zeros.
                 // IFFT of zeros is still zeros, so we can skip processing
                 // and just return those zeros as the result.
             },
-            SynthesisBufferState::INPUT => {
+            SynthesisBufferState::Input => {
                 // The usual case: buffer contains some inputs and
                 // now it is time to process them to get the result.
                 self.ifft_plan.process(&mut self.buffer);
-                self.buffer_state = SynthesisBufferState::OUTPUT;
+                self.buffer_state = SynthesisBufferState::Output;
             },
-            SynthesisBufferState::OUTPUT => {
+            SynthesisBufferState::Output => {
                 // No inputs have been added since the last call to process.
                 // The buffer still contains the previous result though,
                 // so clear it and return those zeros.
@@ -513,6 +790,20 @@ impl SynthesisInputProcessor {
 // ----------------------------------------
 
 
+/// Check whether a channel with given sample rate and center frequency
+/// fits within the span of a filter bank with given sample rate
+/// and center frequency. Channels that do not fit would wrap
+/// around to the other edge of the span.
+pub fn channel_fits(
+    sample_rate: f64,
+    center_frequency: f64,
+    channel_sample_rate: f64,
+    channel_center_frequency: f64,
+) -> bool {
+    (channel_center_frequency - center_frequency).abs() + channel_sample_rate / 2.0
+        <= sample_rate / 2.0
+}
+
 /// Design raised cosine weights for a given IFFT size,
 /// passband width and transition band width (given as number of bins).
 /// Use None for default values.
@@ -526,7 +817,7 @@ pub fn raised_cosine_weights(
     // so check for that.
     // Maybe returning an error instead of panicing with invalid values
     // would be better though.
-    assert!(ifft_size % 2 == 0);
+    assert!(ifft_size.is_multiple_of(2));
 
     let default_max_transition = 15;
     let transition_bins_ = transition_bins.unwrap_or(default_max_transition.min(ifft_size/2 - 1));
@@ -560,16 +851,46 @@ pub fn raised_cosine_weights(
 
 #[cfg(test)]
 mod tests {
-    use std::io::Write;
     use byteorder::{self, ByteOrder};
 
     use super::*;
     use sweep;
 
+    /// Maximum difference of a sample from reference data.
+    const REFERENCE_TOLERANCE: Sample = 1e-4;
+
+    /// Compare output to reference data in src/fcfb/testdata.
+    /// If the output changes intentionally, regenerate the
+    /// reference data with:
+    /// SDRGLUE_BLESS=1 cargo test fcfb::tests::test_
+    fn compare_to_reference(name: &str, output: &[ComplexSample]) {
+        let path = format!("{}/src/fcfb/testdata/{}.cf32", env!("CARGO_MANIFEST_DIR"), name);
+        if std::env::var_os("SDRGLUE_BLESS").is_some() {
+            let mut data = vec![0u8; output.len() * 8];
+            for (sample, buf) in output.iter().zip(data.chunks_exact_mut(8)) {
+                // Write sample in little-endian interleaved format
+                byteorder::LittleEndian::write_f32(&mut buf[0..4], sample.re);
+                byteorder::LittleEndian::write_f32(&mut buf[4..8], sample.im);
+            }
+            std::fs::write(&path, data).unwrap();
+            return;
+        }
+        let data = std::fs::read(&path).unwrap_or_else(|err| panic!("Could not read {}: {}", path, err));
+        let reference: Vec<ComplexSample> = data.chunks_exact(8).map(|buf| ComplexSample::new(
+            byteorder::LittleEndian::read_f32(&buf[0..4]),
+            byteorder::LittleEndian::read_f32(&buf[4..8]),
+        )).collect();
+        assert_eq!(output.len(), reference.len());
+        for (index, (a, b)) in output.iter().zip(reference.iter()).enumerate() {
+            assert!((a - b).norm() <= REFERENCE_TOLERANCE,
+                "{} differs from reference at sample {}: {} != {}", name, index, a, b);
+        }
+    }
+
     #[test]
     fn test_analysis() {
         let mut fft_planner = rustfft::FftPlanner::new();
-        let sweep_length = 1000000;
+        let sweep_length = 100000;
         let mut sweepgen = sweep::SweepGenerator::new(sweep_length);
         let input_parameters = AnalysisInputParameters {
             fft_size: 1000,
@@ -586,10 +907,7 @@ mod tests {
         let mut an_output = AnalysisOutputProcessor::new(&mut fft_planner, input_parameters, output_parameters);
 
         let mut input_buffer = an.make_input_buffer();
-
-        // Write output to a file so it can be manually inspected.
-        // The result is not automatically checked for anything for now.
-        let mut output_file = std::fs::File::create("test_results/analysis_output.cf32").unwrap();
+        let mut output = Vec::new();
 
         for _ in 0..(sweep_length / (input_parameters.fft_size/2) as u64) {
             for sample in input_buffer.prepare_for_new_samples() {
@@ -598,24 +916,18 @@ mod tests {
 
             let intermediate_result = an.process(input_buffer.buffer());
 
-            let result = an_output.process(intermediate_result);
-
-            for sample in result {
-                // Write sample in little-endian interleaved format
-                let mut buf = [0u8; 8];
-                byteorder::LittleEndian::write_f32(&mut buf[0..4], sample.re);
-                byteorder::LittleEndian::write_f32(&mut buf[4..8], sample.im);
-                output_file.write_all(&buf[..]).unwrap();
-            }
+            output.extend_from_slice(an_output.process(intermediate_result));
         }
+        compare_to_reference("analysis_output", &output);
     }
 
     #[test]
     fn test_synthesis() {
         let mut fft_planner = rustfft::FftPlanner::new();
-        let mut sweepgen = sweep::SweepGenerator::new(100000);
+        let blocks = 200;
+        let mut sweepgen = sweep::SweepGenerator::new(blocks * 10);
         let output_parameters = SynthesisOutputParameters {
-            ifft_size: 1000,
+            ifft_size: 200,
             center_frequency: 0.0,
             sample_rate: 100000.0,
         };
@@ -624,23 +936,184 @@ mod tests {
         let mut sy_input = SynthesisInputProcessor::new_with_frequency(&mut fft_planner, output_parameters, 10000.0, 20100.0);
 
         let mut input_buffer = sy_input.make_input_buffer();
+        let mut output = Vec::new();
 
-        let mut output_file = std::fs::File::create("test_results/synthesis_output.cf32").unwrap();
-
-        for _ in 0..2000 {
+        for _ in 0..blocks {
             for sample in input_buffer.prepare_for_new_samples() {
                 *sample = sweepgen.sample();
             }
 
             sy.add(sy_input.process(input_buffer.buffer()));
-            let result = sy.process();
+            output.extend_from_slice(sy.process());
+        }
+        compare_to_reference("synthesis_output", &output);
+    }
 
-            for sample in result {
-                let mut buf = [0u8; 8];
-                byteorder::LittleEndian::write_f32(&mut buf[0..4], sample.re);
-                byteorder::LittleEndian::write_f32(&mut buf[4..8], sample.im);
-                output_file.write_all(&buf[..]).unwrap();
+    #[test]
+    fn test_channelizer_push_sizes() {
+        // Pushing input in pieces of any size
+        // should give the same output as pushing it all at once.
+        let mut fft_planner = rustfft::FftPlanner::new();
+        let mut sweepgen = sweep::SweepGenerator::new(10000);
+        let input: Vec<ComplexSample> = (0..10000).map(|_| sweepgen.sample()).collect();
+
+        let mut whole = Channelizer::new(&mut fft_planner, 100000.0, 0.0, 500.0);
+        let mut pieces = Channelizer::new(&mut fft_planner, 100000.0, 0.0, 500.0);
+        for channelizer in [&mut whole, &mut pieces] {
+            channelizer.add_channel(&mut fft_planner, 10000.0, 20000.0);
+            channelizer.add_channel(&mut fft_planner, 4000.0, -3000.0);
+        }
+
+        whole.push(&input);
+        let mut outputs = vec![Vec::<ComplexSample>::new(); 2];
+        let mut position = 0;
+        for size in [1, 7, 100, 99, 1000, 3].iter().cycle() {
+            let end = (position + size).min(input.len());
+            pieces.push(&input[position..end]);
+            for (channel, output) in pieces.outputs() {
+                outputs[channel].extend_from_slice(output);
+            }
+            position = end;
+            if position >= input.len() { break; }
+        }
+        for (channel, output) in whole.outputs() {
+            assert_eq!(output.len(), outputs[channel].len());
+            assert!(output.iter().zip(outputs[channel].iter()).all(|(a, b)| a == b));
+        }
+    }
+
+    #[test]
+    fn test_two_stage() {
+        // A tone should come through a two-stage channelizer
+        // with unity gain, and close channels should share a segment.
+        let mut fft_planner = rustfft::FftPlanner::new();
+        let sample_rate = 4e6;
+        let mut channelizer = TwoStageChannelizer::new(&mut fft_planner, sample_rate, 0.0, 10e3, 1e6, 500.0);
+        let tone_channel = channelizer.add_channel(&mut fft_planner, 48000.0, 300e3);
+        let empty_channel = channelizer.add_channel(&mut fft_planner, 48000.0, 350e3);
+        channelizer.add_channel(&mut fft_planner, 48000.0, -500e3);
+        assert_eq!(channelizer.number_of_segments(), 2);
+
+        let tone_frequency = 305e3;
+        let input: Vec<ComplexSample> = (0..200000).map(|i|
+            ComplexSample::from_polar(1.0, (2.0 * std::f64::consts::PI * tone_frequency * i as f64 / sample_rate) as Sample)
+        ).collect();
+        channelizer.push(&input);
+        // Skip the beginning where filters are still filling up
+        let output = channelizer.output(tone_channel);
+        let settled = &output[output.len() / 2 ..];
+        assert!(settled.iter().all(|s| (s.norm() - 1.0).abs() < 0.01));
+        let output = channelizer.output(empty_channel);
+        let settled = &output[output.len() / 2 ..];
+        assert!(settled.iter().all(|s| s.norm() < 0.01));
+    }
+
+    #[test]
+    fn test_mask() {
+        // A tone inside a masked range should be removed
+        // while another tone in the same channel passes.
+        let mut fft_planner = rustfft::FftPlanner::new();
+        let sample_rate = 200000.0;
+        let mut channelizer = Channelizer::new(&mut fft_planner, sample_rate, 0.0, 500.0);
+        let masked = channelizer.add_channel(&mut fft_planner, 48000.0, 10000.0);
+        let unmasked = channelizer.add_channel(&mut fft_planner, 48000.0, 10000.0);
+        channelizer.apply_mask(masked, &[FrequencyMask { start: 2000.0, end: 4000.0, gain: 0.0 }]);
+
+        let tone = |frequency: f64| -> Vec<ComplexSample> { (0..100000).map(|i|
+            ComplexSample::from_polar(1.0, (2.0 * std::f64::consts::PI * frequency * i as f64 / sample_rate) as Sample)
+        ).collect() };
+        channelizer.push(&tone(13000.0));
+        let output = channelizer.output(masked);
+        assert!(output[output.len() / 2 ..].iter().all(|s| s.norm() < 0.01));
+        let output = channelizer.output(unmasked);
+        assert!(output[output.len() / 2 ..].iter().all(|s| (s.norm() - 1.0).abs() < 0.01));
+        channelizer.push(&tone(5000.0));
+        let output = channelizer.output(masked);
+        assert!(output[output.len() / 2 ..].iter().all(|s| (s.norm() - 1.0).abs() < 0.01));
+    }
+
+    #[test]
+    fn test_real_analysis() {
+        // Real input path should give the same result
+        // as the complex path for the same signal.
+        let mut fft_planner = rustfft::FftPlanner::new();
+        let parameters = AnalysisInputParameters {
+            fft_size: 200,
+            sample_rate: 100000.0,
+            center_frequency: 0.0,
+        };
+        let mut complex = AnalysisInputProcessor::new(&mut fft_planner, parameters);
+        let mut real = RealAnalysisInputProcessor::new(&mut fft_planner, parameters);
+        let mut sweepgen = sweep::SweepGenerator::new(1000);
+        for _ in 0..3 {
+            let input: Vec<Sample> = (0..parameters.fft_size).map(|_| sweepgen.sample().re).collect();
+            let complex_input: Vec<ComplexSample> = input.iter().map(|&x| ComplexSample::new(x, 0.0)).collect();
+            let expected = complex.process(&complex_input);
+            let result = real.process(&input);
+            assert_eq!(expected.count, result.count);
+            for (a, b) in expected.fft_result.iter().zip(result.fft_result.iter()) {
+                assert!((a - b).norm() < 1e-3);
+            }
+        }
+    }
+
+    #[test]
+    fn test_power() {
+        // Power measured from FFT result should equal
+        // mean power of filter bank output,
+        // including channels wrapping around the end of FFT.
+        let mut fft_planner = rustfft::FftPlanner::new();
+        let parameters = AnalysisInputParameters {
+            fft_size: 400,
+            sample_rate: 200000.0,
+            center_frequency: 0.0,
+        };
+        let mut analysis = AnalysisInputProcessor::new(&mut fft_planner, parameters);
+        let mut sweepgen = sweep::SweepGenerator::new(400);
+        let input: Vec<ComplexSample> = (0..parameters.fft_size).map(|_| sweepgen.sample()).collect();
+        let ir = analysis.process(&input);
+        for (bandwidth, frequency) in [(20000.0, 0.0), (10000.0, 50000.0), (40000.0, -95000.0)] {
+            let mut output = AnalysisOutputProcessor::new_with_frequency(&mut fft_planner, parameters, bandwidth, frequency);
+            let power = AnalysisPowerProcessor::new_with_frequency(parameters, bandwidth, frequency);
+            // The whole IFFT output is needed to get all of the power
+            output.process(ir);
+            let expected = output.buffer.iter().map(|s| s.norm_sqr()).sum::<Sample>() / output.buffer.len() as Sample;
+            let measured = power.process(ir);
+            assert!(expected > 1e-3);
+            assert!((expected - measured).abs() <= 1e-4 * expected);
+        }
+    }
+
+    /// Measure processing time of analysis bank output processors.
+    /// Run with: cargo test --release -- --ignored --nocapture bench_
+    #[test]
+    #[ignore]
+    fn bench_analysis_output() {
+        let mut fft_planner = rustfft::FftPlanner::new();
+        for (fft_size, ifft_size) in [(4096, 96), (4000, 96), (16384, 512), (20000, 400)] {
+            let input_parameters = AnalysisInputParameters {
+                fft_size,
+                center_frequency: 0.0,
+                sample_rate: fft_size as f64 * 500.0,
+            };
+            let mut an = AnalysisInputProcessor::new(&mut fft_planner, input_parameters);
+            let mut outputs: Vec<AnalysisOutputProcessor> = (0..50).map(|i| AnalysisOutputProcessor::new(
+                &mut fft_planner, input_parameters, AnalysisOutputParameters {
+                    center_bin: (i * 37) as isize,
+                    weights: raised_cosine_weights(ifft_size, None, None),
+                })).collect();
+            let input = vec![ComplexSample::new(0.1, 0.2); fft_size];
+            let blocks = 2000;
+            let start = std::time::Instant::now();
+            for _ in 0..blocks {
+                let ir = an.process(&input);
+                for output in outputs.iter_mut() {
+                    std::hint::black_box(output.process(ir));
+                }
             }
+            let elapsed = start.elapsed();
+            println!("fft_size {:6}, ifft_size {:4}: {:8.2} us per block with 50 outputs",
+                fft_size, ifft_size, elapsed.as_secs_f64() * 1e6 / blocks as f64);
         }
     }
 
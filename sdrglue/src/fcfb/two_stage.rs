@@ -0,0 +1,169 @@
+//! Two-stage channelizer for very wideband signals.
+//!
+//! At high sample rates, a single analysis filter bank
+//! with fine bin spacing needs a huge FFT.
+//! For example, 50 MS/s with 500 Hz bin spacing needs
+//! a 100000-point FFT, which increases latency and memory use.
+//! Instead, the signal can be first split into segments
+//! by a coarse filter bank and channels are then split
+//! from each segment by a fine filter bank.
+//!
+//! Segments should be many coarse bins wide, since the transition
+//! bands of the coarse filter bank take up to 30 bins of each segment.
+
+use crate::{Sample, ComplexSample};
+use super::*;
+
+struct Segment {
+    /// Center frequency of the segment.
+    center_frequency: f64,
+    /// Bin spacing of the fine channelizer.
+    bin_spacing: f64,
+    /// Fine channelizer splitting channels from the segment.
+    channelizer: Channelizer,
+}
+
+/// Splits a wideband signal into channels in two stages.
+///
+/// Segments are created automatically as channels are added,
+/// so that each channel is taken from a segment
+/// containing the whole channel within its passband.
+pub struct TwoStageChannelizer {
+    /// Coarse channelizer splitting the signal into segments.
+    coarse: Channelizer,
+    coarse_bin_spacing: f64,
+    segment_sample_rate: f64,
+    fine_bin_spacing: f64,
+    /// Maximum distance from segment center to the edges
+    /// of its passband.
+    segment_passband_half: f64,
+    segments: Vec<Segment>,
+    /// Segment index and channel index within segment for each channel.
+    channels: Vec<(usize, usize)>,
+}
+
+impl TwoStageChannelizer {
+    /// Create a two-stage channelizer for input signal
+    /// with given sample rate and center frequency.
+    /// Input sample rate should be an integer multiple of
+    /// 2 * coarse bin spacing, and segment sample rate should be
+    /// an integer multiple of both 2 * coarse bin spacing
+    /// and 2 * fine bin spacing.
+    pub fn new(
+        fft_planner: &mut rustfft::FftPlanner<Sample>,
+        sample_rate: f64,
+        center_frequency: f64,
+        coarse_bin_spacing: f64,
+        segment_sample_rate: f64,
+        fine_bin_spacing: f64,
+    ) -> Self {
+        // Segments use default weights, so find their passband
+        // width the same way.
+        let segment_bins = (segment_sample_rate / coarse_bin_spacing).round() as usize;
+        let passband_bins = raised_cosine_weights(segment_bins, None, None)
+            .iter().filter(|&&weight| weight == 1.0).count();
+        Self {
+            coarse: Channelizer::new(fft_planner, sample_rate, center_frequency, coarse_bin_spacing),
+            coarse_bin_spacing,
+            segment_sample_rate,
+            fine_bin_spacing,
+            segment_passband_half: (passband_bins / 2) as f64 * coarse_bin_spacing,
+            segments: Vec::new(),
+            channels: Vec::new(),
+        }
+    }
+
+    /// Add an output channel with given sample rate and center frequency.
+    /// Return index of the channel.
+    pub fn add_channel(
+        &mut self,
+        fft_planner: &mut rustfft::FftPlanner<Sample>,
+        sample_rate: f64,
+        center_frequency: f64,
+    ) -> usize {
+        self.add_channel_with_bin_spacing(fft_planner, sample_rate, center_frequency, self.fine_bin_spacing)
+    }
+
+    /// Add an output channel using a different fine bin spacing
+    /// than the one given in new().
+    /// Channels with different bin spacings are taken from different segments.
+    pub fn add_channel_with_bin_spacing(
+        &mut self,
+        fft_planner: &mut rustfft::FftPlanner<Sample>,
+        sample_rate: f64,
+        center_frequency: f64,
+        bin_spacing: f64,
+    ) -> usize {
+        let fits = |segment: &Segment|
+            segment.bin_spacing == bin_spacing &&
+            (center_frequency - segment.center_frequency).abs() + 0.5 * sample_rate
+            <= self.segment_passband_half;
+        let segment_index = match self.segments.iter().position(fits) {
+            Some(index) => index,
+            None => {
+                // Coarse filter bank can only produce segments
+                // centered at its bin frequencies,
+                // so round the center to the nearest bin.
+                let input_center = self.coarse.center_frequency();
+                let segment_center = input_center
+                    + ((center_frequency - input_center) / self.coarse_bin_spacing).round()
+                    * self.coarse_bin_spacing;
+                self.coarse.add_channel(fft_planner, self.segment_sample_rate, segment_center);
+                self.segments.push(Segment {
+                    center_frequency: segment_center,
+                    bin_spacing,
+                    channelizer: Channelizer::new(
+                        fft_planner,
+                        self.segment_sample_rate,
+                        segment_center,
+                        bin_spacing,
+                    ),
+                });
+                self.segments.len() - 1
+            }
+        };
+        let channel_index = self.segments[segment_index].channelizer
+            .add_channel(fft_planner, sample_rate, center_frequency);
+        self.channels.push((segment_index, channel_index));
+        self.channels.len() - 1
+    }
+
+    /// Apply frequency masks to a channel.
+    pub fn apply_mask(&mut self, channel: usize, masks: &[FrequencyMask]) {
+        let (segment, index) = self.channels[channel];
+        self.segments[segment].channelizer.apply_mask(index, masks);
+    }
+
+    /// Number of input samples processed at a time.
+    pub fn input_block_size(&self) -> usize {
+        self.coarse.input_block_size()
+    }
+
+    /// Number of segments currently in use.
+    pub fn number_of_segments(&self) -> usize {
+        self.segments.len()
+    }
+
+    /// Push any number of input samples.
+    /// Output signals produced from them can be read with outputs().
+    /// Output from the previous push is cleared.
+    pub fn push(&mut self, input: &[ComplexSample]) {
+        self.coarse.push(input);
+        for (index, segment) in self.segments.iter_mut().enumerate() {
+            segment.channelizer.push(self.coarse.output(index));
+        }
+    }
+
+    /// Output signal of a channel produced by the previous push.
+    pub fn output(&self, channel: usize) -> &[ComplexSample] {
+        let (segment, channel) = self.channels[channel];
+        self.segments[segment].channelizer.output(channel)
+    }
+
+    /// Iterate over outputs of all channels
+    /// produced by the previous push,
+    /// as pairs of channel index and output signal.
+    pub fn outputs(&self) -> impl Iterator<Item = (usize, &[ComplexSample])> {
+        (0..self.channels.len()).map(|channel| (channel, self.output(channel)))
+    }
+}
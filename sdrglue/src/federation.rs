@@ -0,0 +1,231 @@
+//! Federation of several sdrglue instances.
+//!
+//! Instances covering different bands or sites join a hub,
+//! which can be any sdrglue instance started with --federation-hub.
+//! The control API of the hub then acts as one API for all of them:
+//! commands with an "instance" field are forwarded to that instance
+//! and the "instances" command lists every instance with the responses
+//! of its "status" and "channels" commands.
+//! ```text
+//! {"command":"instances"}
+//! {"command":"enable","channel":"rx0","instance":"uhf"}
+//! ```
+//!
+//! A joining instance connects to the hub, sends a line with
+//! its name and the shared secret of the federation as
+//! {"instance":"uhf","secret":"..."} and then handles control
+//! commands sent by the hub on the same connection,
+//! using the same line-based JSON protocol as the control API.
+//! Only commands in HUB_COMMANDS are handled, and never for
+//! transmit channels, so that the hub or anyone using its API
+//! cannot transmit or run actions on other instances.
+//!
+//! An instance can rejoin under the same name from the same
+//! address, replacing its old connection. A name in use by
+//! a connected instance at another address is refused.
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, SystemTime};
+
+use serde_json::{json, Value};
+
+use crate::auth;
+use crate::control::{self, Requester};
+
+/// How long to wait for an instance to respond to a command.
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(10);
+/// How long to wait before reconnecting to a hub.
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(5);
+/// Commands a joined instance handles from the hub.
+/// Transmit channels are refused even for these
+/// (see control::command_allowed).
+pub const HUB_COMMANDS: &[&str] = &[
+    "protocol", "schema", "status", "channels", "sensors", "enable", "disable", "delay",
+];
+
+/// An instance connected to the hub.
+struct Member {
+    name: String,
+    address: String,
+    /// Time the instance joined, in seconds since Unix epoch.
+    joined: f64,
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+}
+
+impl Member {
+    fn request(&mut self, command: &Value) -> std::io::Result<Value> {
+        writeln!(self.writer, "{}", command)?;
+        let mut line = String::new();
+        if self.reader.read_line(&mut line)? == 0 {
+            return Err(std::io::ErrorKind::UnexpectedEof.into());
+        }
+        serde_json::from_str(&line).map_err(std::io::Error::other)
+    }
+}
+
+struct Hub {
+    /// Name of the hub instance itself.
+    name: String,
+    /// Shared secret required from joining instances.
+    secret: String,
+    members: Mutex<Vec<Arc<Mutex<Member>>>>,
+}
+
+static HUB: OnceLock<Hub> = OnceLock::new();
+
+fn now() -> f64 {
+    SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).map(|t| t.as_secs_f64()).unwrap_or(0.0)
+}
+
+/// Whether this instance is the hub of a federation.
+pub fn hub_running() -> bool {
+    HUB.get().is_some()
+}
+
+/// Start accepting instances joining at given TCP address.
+pub fn start_hub(address: &str, name: &str, secret: &str) {
+    // TODO: handle errors more nicely
+    let listener = TcpListener::bind(address)
+        .unwrap_or_else(|e| panic!("Could not listen on federation address {}: {}", address, e));
+    let _ = HUB.set(Hub {
+        name: name.to_string(),
+        secret: secret.to_string(),
+        members: Mutex::new(Vec::new()),
+    });
+    std::thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            std::thread::spawn(move || {
+                if let Err(err) = accept(stream) {
+                    eprintln!("Federation connection error: {}", err);
+                }
+            });
+        }
+    });
+}
+
+/// Register a connected instance after reading its name
+/// and checking the secret.
+fn accept(stream: TcpStream) -> std::io::Result<()> {
+    let hub = HUB.get().unwrap();
+    stream.set_read_timeout(Some(RESPONSE_TIMEOUT))?;
+    let peer = stream.peer_addr()?;
+    let address = peer.to_string();
+    let mut writer = stream.try_clone()?;
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+    (&mut reader).take(control::MAX_LINE_LENGTH as u64).read_line(&mut line)?;
+    let hello: Value = serde_json::from_str(&line).map_err(std::io::Error::other)?;
+    let secret = hello.get("secret").and_then(Value::as_str).unwrap_or("");
+    if !auth::secret_matches(secret, &hub.secret) {
+        writeln!(writer, "{}", json!({ "ok": false, "error": "Wrong secret" }))?;
+        return Err(std::io::Error::other(format!("wrong secret from {}", address)));
+    }
+    let name = hello.get("instance").and_then(Value::as_str)
+        .ok_or_else(|| std::io::Error::other("missing instance name"))?
+        .to_string();
+
+    // An instance with the same name elsewhere is only replaced
+    // if it has disconnected, which is noticed when requesting it.
+    let existing = hub.members.lock().unwrap().iter()
+        .find(|member| member.lock().unwrap().name == name).cloned();
+    if let Some(existing) = existing {
+        let same_host = existing.lock().unwrap().address.parse::<std::net::SocketAddr>()
+            .is_ok_and(|existing| existing.ip() == peer.ip());
+        if !same_host && request_member(&existing, &json!({ "command": "status" }))["ok"] == true {
+            writeln!(writer, "{}", json!({ "ok": false, "error": "Instance name in use" }))?;
+            return Err(std::io::Error::other(format!("instance {} already joined, refused from {}", name, address)));
+        }
+    }
+
+    eprintln!("Instance {} joined from {}", name, address);
+    let mut members = hub.members.lock().unwrap();
+    // A reconnecting instance replaces its old connection.
+    members.retain(|member| member.lock().unwrap().name != name);
+    members.push(Arc::new(Mutex::new(Member { name, address, joined: now(), reader, writer })));
+    Ok(())
+}
+
+/// Send a command to a member, removing it if the connection has failed.
+fn request_member(member: &Arc<Mutex<Member>>, command: &Value) -> Value {
+    let result = member.lock().unwrap().request(command);
+    match result {
+        Ok(response) => response,
+        Err(err) => {
+            let name = member.lock().unwrap().name.clone();
+            eprintln!("Instance {} disconnected: {}", name, err);
+            if let Some(hub) = HUB.get() {
+                hub.members.lock().unwrap().retain(|other| !Arc::ptr_eq(other, member));
+            }
+            json!({ "ok": false, "error": format!("Instance {} disconnected", name) })
+        },
+    }
+}
+
+/// Handle commands concerning other instances.
+/// Returns None for commands to be handled locally.
+pub fn route(command: &Value, local: &Requester) -> Option<Value> {
+    let hub = HUB.get()?;
+    // Members are copied so that the list is not locked
+    // while waiting for responses.
+    let members: Vec<Arc<Mutex<Member>>> = hub.members.lock().unwrap().clone();
+
+    if command.get("command").and_then(Value::as_str) == Some("instances") {
+        let status_command = json!({ "command": "status" });
+        let channels_command = json!({ "command": "channels" });
+        let status = local.request(status_command.clone())?;
+        let channels = local.request(channels_command.clone())?;
+        let mut instances = vec![json!({
+            "name": hub.name,
+            "rx": status.get("rx"),
+            "tx": status.get("tx"),
+            "templates": channels.get("channels"),
+        })];
+        for member in members.iter() {
+            let status = request_member(member, &status_command);
+            let channels = request_member(member, &channels_command);
+            let member = member.lock().unwrap();
+            instances.push(json!({
+                "name": member.name,
+                "address": member.address,
+                "joined": member.joined,
+                "rx": status.get("rx"),
+                "tx": status.get("tx"),
+                "templates": channels.get("channels"),
+                "error": status.get("error"),
+            }));
+        }
+        return Some(json!({ "ok": true, "instances": instances }));
+    }
+
+    let name = command.get("instance").and_then(Value::as_str)?;
+    if name == hub.name {
+        return None;
+    }
+    let Some(member) = members.iter().find(|member| member.lock().unwrap().name == name) else {
+        return Some(json!({ "ok": false, "error": format!("Instance {} is not connected", name) }));
+    };
+    let mut command = command.clone();
+    if let Some(command) = command.as_object_mut() {
+        command.remove("instance");
+    }
+    Some(request_member(member, &command))
+}
+
+/// Join a hub at given TCP address, handling commands from it.
+pub fn join(address: &str, name: &str, secret: &str, requester: Requester) {
+    let address = address.to_string();
+    let hello = json!({ "instance": name, "secret": secret });
+    std::thread::spawn(move || loop {
+        let result = TcpStream::connect(&address).and_then(|mut stream| {
+            writeln!(stream, "{}", hello)?;
+            control::serve(stream, requester.clone(), Some(HUB_COMMANDS))
+        });
+        if let Err(err) = result {
+            eprintln!("Federation hub {} connection error: {}", address, err);
+        }
+        std::thread::sleep(RECONNECT_INTERVAL);
+    });
+}
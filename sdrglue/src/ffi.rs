@@ -0,0 +1,420 @@
+//! C API for the fast-convolution filter banks.
+//!
+//! Build with the ffi feature to get a shared or static library
+//! exporting these functions. The header include/sdrglue.h
+//! is generated with cbindgen:
+//! ```text
+//! cargo build --release --lib --features ffi
+//! cbindgen --config cbindgen.toml --output include/sdrglue.h
+//! ```
+//! Objects are created with a _new function and have to be
+//! released with the corresponding _free function.
+//! An object can be used from any thread, but only
+//! from one thread at a time.
+//! Functions do not check their arguments more than
+//! the Rust API does, so invalid arguments may abort.
+//!
+//! The _work functions process streams in pieces limited by
+//! both available input and space for output, like the work
+//! function of a GNU Radio block, so that the filter banks can be
+//! wrapped in an out-of-tree block with one input and an output
+//! for each channel, or the other way around. For example:
+//! ```text
+//! int general_work(int noutput_items, gr_vector_int &ninput_items,
+//!                  gr_vector_const_void_star &input_items,
+//!                  gr_vector_void_star &output_items) {
+//!     std::vector<size_t> produced(output_items.size());
+//!     size_t consumed = sdrglue_channelizer_work(d_channelizer,
+//!         (const SdrglueComplex *)input_items[0], ninput_items[0],
+//!         (SdrglueComplex *const *)output_items.data(), noutput_items, produced.data());
+//!     consume(0, consumed);
+//!     for (size_t i = 0; i < produced.size(); i++)
+//!         produce(i, produced[i]);
+//!     return WORK_CALLED_PRODUCE;
+//! }
+//! ```
+//! Check SDRGLUE_API_VERSION against sdrglue_api_version()
+//! to make sure the library matches the header.
+
+use std::ptr;
+
+use crate::{Sample, ComplexSample};
+use crate::fcfb::{Channelizer, Dechannelizer};
+
+/// Version of the C API, incremented on incompatible changes.
+pub const SDRGLUE_API_VERSION: u32 = 1;
+
+/// Version of the C API implemented by the library.
+#[no_mangle]
+pub extern "C" fn sdrglue_api_version() -> u32 {
+    SDRGLUE_API_VERSION
+}
+
+/// Complex sample with interleaved real and imaginary parts,
+/// the same layout as float complex in C.
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct SdrglueComplex {
+    pub re: f32,
+    pub im: f32,
+}
+
+/// Analysis filter bank splitting a wideband signal into channels.
+pub struct SdrglueChannelizer {
+    fft_planner: rustfft::FftPlanner<Sample>,
+    channelizer: Channelizer,
+}
+
+/// Synthesis filter bank combining channels into a wideband signal.
+pub struct SdrglueDechannelizer {
+    fft_planner: rustfft::FftPlanner<Sample>,
+    dechannelizer: Dechannelizer,
+    /// Pointers to input signals of channels during processing.
+    inputs: Vec<*const SdrglueComplex>,
+}
+
+/// View samples from C as complex samples.
+///
+/// # Safety
+/// samples must point to length valid samples, or length must be 0.
+unsafe fn complex_slice<'a>(samples: *const SdrglueComplex, length: usize) -> &'a [ComplexSample] {
+    if length == 0 {
+        return &[];
+    }
+    // SdrglueComplex has the same layout as Complex<f32>.
+    std::slice::from_raw_parts(samples as *const ComplexSample, length)
+}
+
+/// Create a channelizer for input signal with given sample rate,
+/// center frequency and bin spacing in Hertz.
+/// Sample rate should be an integer multiple of 2 * bin spacing.
+#[no_mangle]
+pub extern "C" fn sdrglue_channelizer_new(
+    sample_rate: f64,
+    center_frequency: f64,
+    bin_spacing: f64,
+) -> *mut SdrglueChannelizer {
+    let mut fft_planner = rustfft::FftPlanner::new();
+    let channelizer = Channelizer::new(&mut fft_planner, sample_rate, center_frequency, bin_spacing);
+    Box::into_raw(Box::new(SdrglueChannelizer { fft_planner, channelizer }))
+}
+
+/// Add an output channel with given sample rate and center frequency.
+/// Return index of the channel.
+///
+/// # Safety
+/// channelizer must be a pointer returned by sdrglue_channelizer_new.
+#[no_mangle]
+pub unsafe extern "C" fn sdrglue_channelizer_add_channel(
+    channelizer: *mut SdrglueChannelizer,
+    sample_rate: f64,
+    center_frequency: f64,
+) -> usize {
+    let channelizer = &mut *channelizer;
+    channelizer.channelizer.add_channel(&mut channelizer.fft_planner, sample_rate, center_frequency)
+}
+
+/// Number of input samples processed at a time.
+///
+/// # Safety
+/// channelizer must be a pointer returned by sdrglue_channelizer_new.
+#[no_mangle]
+pub unsafe extern "C" fn sdrglue_channelizer_input_block_size(channelizer: *const SdrglueChannelizer) -> usize {
+    (*channelizer).channelizer.input_block_size()
+}
+
+/// Push any number of input samples. Output signals produced
+/// from them can be read with sdrglue_channelizer_output.
+///
+/// # Safety
+/// channelizer must be a pointer returned by sdrglue_channelizer_new
+/// and input must point to length samples.
+#[no_mangle]
+pub unsafe extern "C" fn sdrglue_channelizer_push(
+    channelizer: *mut SdrglueChannelizer,
+    input: *const SdrglueComplex,
+    length: usize,
+) {
+    (*channelizer).channelizer.push(complex_slice(input, length));
+}
+
+/// Output signal of a channel produced by the previous push.
+/// The number of samples is written to length.
+/// The returned pointer is valid until the next push.
+///
+/// # Safety
+/// channelizer must be a pointer returned by sdrglue_channelizer_new,
+/// channel an index returned by sdrglue_channelizer_add_channel
+/// and length must point to writable memory.
+#[no_mangle]
+pub unsafe extern "C" fn sdrglue_channelizer_output(
+    channelizer: *const SdrglueChannelizer,
+    channel: usize,
+    length: *mut usize,
+) -> *const SdrglueComplex {
+    let output = (*channelizer).channelizer.output(channel);
+    *length = output.len();
+    output.as_ptr() as *const SdrglueComplex
+}
+
+/// Number of output samples a channel produces
+/// for each block of input samples.
+///
+/// # Safety
+/// channelizer must be a pointer returned by sdrglue_channelizer_new
+/// and channel an index returned by sdrglue_channelizer_add_channel.
+#[no_mangle]
+pub unsafe extern "C" fn sdrglue_channelizer_channel_block_size(
+    channelizer: *const SdrglueChannelizer,
+    channel: usize,
+) -> usize {
+    (*channelizer).channelizer.channel_block_size(channel)
+}
+
+/// Process as many input samples as fit in the output space
+/// of every channel. Output of each channel is written to
+/// the buffer in outputs with room for output_space samples,
+/// and the number of samples written to produced.
+/// Return the number of input samples consumed.
+///
+/// # Safety
+/// channelizer must be a pointer returned by sdrglue_channelizer_new,
+/// input must point to input_length samples, and outputs
+/// and produced must be arrays with an element for each channel.
+#[no_mangle]
+pub unsafe extern "C" fn sdrglue_channelizer_work(
+    channelizer: *mut SdrglueChannelizer,
+    input: *const SdrglueComplex,
+    input_length: usize,
+    outputs: *const *mut SdrglueComplex,
+    output_space: usize,
+    produced: *mut usize,
+) -> usize {
+    let channelizer = &mut (*channelizer).channelizer;
+    let count = channelizer.outputs().count();
+    let blocks = (0..count).map(|channel| output_space / channelizer.channel_block_size(channel))
+        .min().unwrap_or(usize::MAX);
+    let consumed = input_length.min(channelizer.input_for_blocks(blocks));
+    channelizer.push(complex_slice(input, consumed));
+    for (channel, output) in channelizer.outputs() {
+        let destination = *outputs.add(channel) as *mut ComplexSample;
+        ptr::copy_nonoverlapping(output.as_ptr(), destination, output.len());
+        *produced.add(channel) = output.len();
+    }
+    consumed
+}
+
+/// Release a channelizer.
+///
+/// # Safety
+/// channelizer must be a pointer returned by sdrglue_channelizer_new
+/// or NULL, and must not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn sdrglue_channelizer_free(channelizer: *mut SdrglueChannelizer) {
+    if !channelizer.is_null() {
+        drop(Box::from_raw(channelizer));
+    }
+}
+
+/// Create a dechannelizer for output signal with given sample rate,
+/// center frequency and bin spacing in Hertz.
+/// Sample rate should be an integer multiple of 2 * bin spacing.
+#[no_mangle]
+pub extern "C" fn sdrglue_dechannelizer_new(
+    sample_rate: f64,
+    center_frequency: f64,
+    bin_spacing: f64,
+) -> *mut SdrglueDechannelizer {
+    let mut fft_planner = rustfft::FftPlanner::new();
+    let dechannelizer = Dechannelizer::new(&mut fft_planner, sample_rate, center_frequency, bin_spacing);
+    Box::into_raw(Box::new(SdrglueDechannelizer { fft_planner, dechannelizer, inputs: Vec::new() }))
+}
+
+/// Add an input channel with given sample rate and center frequency.
+/// Return index of the channel.
+///
+/// # Safety
+/// dechannelizer must be a pointer returned by sdrglue_dechannelizer_new.
+#[no_mangle]
+pub unsafe extern "C" fn sdrglue_dechannelizer_add_channel(
+    dechannelizer: *mut SdrglueDechannelizer,
+    sample_rate: f64,
+    center_frequency: f64,
+) -> usize {
+    let dechannelizer = &mut *dechannelizer;
+    dechannelizer.inputs.push(ptr::null());
+    dechannelizer.dechannelizer.add_channel(&mut dechannelizer.fft_planner, sample_rate, center_frequency)
+}
+
+/// Number of input samples needed from a channel for each output block.
+///
+/// # Safety
+/// dechannelizer must be a pointer returned by sdrglue_dechannelizer_new
+/// and channel an index returned by sdrglue_dechannelizer_add_channel.
+#[no_mangle]
+pub unsafe extern "C" fn sdrglue_dechannelizer_channel_block_size(
+    dechannelizer: *const SdrglueDechannelizer,
+    channel: usize,
+) -> usize {
+    (*dechannelizer).dechannelizer.channel_block_size(channel)
+}
+
+/// Produce a block of output signal. inputs is an array with
+/// a pointer for each channel to its block of input samples,
+/// of the length given by sdrglue_dechannelizer_channel_block_size.
+/// The number of output samples is written to length.
+/// The returned pointer is valid until the next call.
+///
+/// # Safety
+/// dechannelizer must be a pointer returned by sdrglue_dechannelizer_new,
+/// inputs must point to a valid block for each channel
+/// and length must point to writable memory.
+#[no_mangle]
+pub unsafe extern "C" fn sdrglue_dechannelizer_process(
+    dechannelizer: *mut SdrglueDechannelizer,
+    inputs: *const *const SdrglueComplex,
+    length: *mut usize,
+) -> *const SdrglueComplex {
+    let dechannelizer = &mut *dechannelizer;
+    let count = dechannelizer.inputs.len();
+    dechannelizer.inputs.copy_from_slice(std::slice::from_raw_parts(inputs, count));
+    let channel_inputs = &dechannelizer.inputs;
+    let output = dechannelizer.dechannelizer.process(|channel, input| {
+        input.copy_from_slice(complex_slice(channel_inputs[channel], input.len()));
+    });
+    *length = output.len();
+    output.as_ptr() as *const SdrglueComplex
+}
+
+/// Number of output samples in each output block.
+///
+/// # Safety
+/// dechannelizer must be a pointer returned by sdrglue_dechannelizer_new.
+#[no_mangle]
+pub unsafe extern "C" fn sdrglue_dechannelizer_output_block_size(dechannelizer: *const SdrglueDechannelizer) -> usize {
+    (*dechannelizer).dechannelizer.output_block_size()
+}
+
+/// Produce as many blocks of output as there is input for in
+/// every channel and space for in output. inputs is an array
+/// with a pointer to input samples of each channel, and
+/// input_lengths the number of samples available in each.
+/// Output is written to output, with room for output_space samples.
+/// Return the number of blocks produced. Each block consumes
+/// sdrglue_dechannelizer_channel_block_size samples from each
+/// channel and produces sdrglue_dechannelizer_output_block_size
+/// samples of output.
+///
+/// # Safety
+/// dechannelizer must be a pointer returned by sdrglue_dechannelizer_new,
+/// inputs and input_lengths must be arrays with an element
+/// for each channel, and output must have room for output_space samples.
+#[no_mangle]
+pub unsafe extern "C" fn sdrglue_dechannelizer_work(
+    dechannelizer: *mut SdrglueDechannelizer,
+    inputs: *const *const SdrglueComplex,
+    input_lengths: *const usize,
+    output: *mut SdrglueComplex,
+    output_space: usize,
+) -> usize {
+    let dechannelizer = &mut *dechannelizer;
+    let count = dechannelizer.inputs.len();
+    let lengths = std::slice::from_raw_parts(input_lengths, count);
+    let output_block_size = dechannelizer.dechannelizer.output_block_size();
+    let blocks = lengths.iter().enumerate()
+        .map(|(channel, length)| length / dechannelizer.dechannelizer.channel_block_size(channel))
+        .fold(output_space / output_block_size, usize::min);
+    for block in 0..blocks {
+        let result = dechannelizer.dechannelizer.process(|channel, input| {
+            let start = (*inputs.add(channel)).add(block * input.len());
+            input.copy_from_slice(complex_slice(start, input.len()));
+        });
+        ptr::copy_nonoverlapping(result.as_ptr(), output.add(block * output_block_size) as *mut ComplexSample, result.len());
+    }
+    blocks
+}
+
+/// Release a dechannelizer.
+///
+/// # Safety
+/// dechannelizer must be a pointer returned by sdrglue_dechannelizer_new
+/// or NULL, and must not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn sdrglue_dechannelizer_free(dechannelizer: *mut SdrglueDechannelizer) {
+    if !dechannelizer.is_null() {
+        drop(Box::from_raw(dechannelizer));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ffi() {
+        // A tone through the C API should come out like through the Rust API.
+        unsafe {
+            let dechannelizer = sdrglue_dechannelizer_new(200000.0, 0.0, 500.0);
+            let channel = sdrglue_dechannelizer_add_channel(dechannelizer, 10000.0, 20000.0);
+            let block_size = sdrglue_dechannelizer_channel_block_size(dechannelizer, channel);
+            let input = vec![SdrglueComplex { re: 0.5, im: 0.0 }; block_size];
+            let channelizer = sdrglue_channelizer_new(200000.0, 0.0, 500.0);
+            let output_channel = sdrglue_channelizer_add_channel(channelizer, 10000.0, 20000.0);
+            for _ in 0..20 {
+                let mut length = 0;
+                let output = sdrglue_dechannelizer_process(dechannelizer, [input.as_ptr()].as_ptr(), &mut length);
+                sdrglue_channelizer_push(channelizer, output, length);
+            }
+            let mut length = 0;
+            let output = sdrglue_channelizer_output(channelizer, output_channel, &mut length);
+            let output = complex_slice(output, length);
+            assert!(!output.is_empty());
+            assert!(output.iter().all(|s| (s.norm() - 0.5).abs() < 0.01));
+            sdrglue_channelizer_free(channelizer);
+            sdrglue_dechannelizer_free(dechannelizer);
+        }
+    }
+
+    #[test]
+    fn test_work() {
+        // Streams processed in pieces limited by output space
+        // should give the same output as processing everything at once.
+        unsafe {
+            let dechannelizer = sdrglue_dechannelizer_new(200000.0, 0.0, 500.0);
+            sdrglue_dechannelizer_add_channel(dechannelizer, 10000.0, 20000.0);
+            let input = vec![SdrglueComplex { re: 0.5, im: 0.0 }; 1000];
+            let mut wideband = vec![SdrglueComplex { re: 0.0, im: 0.0 }; 20000];
+            let output_block_size = sdrglue_dechannelizer_output_block_size(dechannelizer);
+            let blocks = sdrglue_dechannelizer_work(dechannelizer,
+                [input.as_ptr()].as_ptr(), [input.len()].as_ptr(), wideband.as_mut_ptr(), wideband.len());
+            // Limited by input: 1000 samples in blocks of 10.
+            assert_eq!(blocks, 100);
+            wideband.truncate(blocks * output_block_size);
+
+            let whole = sdrglue_channelizer_new(200000.0, 0.0, 500.0);
+            let pieces = sdrglue_channelizer_new(200000.0, 0.0, 500.0);
+            sdrglue_channelizer_add_channel(whole, 10000.0, 20000.0);
+            sdrglue_channelizer_add_channel(pieces, 10000.0, 20000.0);
+            sdrglue_channelizer_push(whole, wideband.as_ptr(), wideband.len());
+            let mut length = 0;
+            let expected = complex_slice(sdrglue_channelizer_output(whole, 0, &mut length), length);
+
+            let mut output = Vec::new();
+            let mut position = 0;
+            let mut buffer = vec![SdrglueComplex { re: 0.0, im: 0.0 }; 25];
+            while position < wideband.len() {
+                let mut produced = 0;
+                let consumed = sdrglue_channelizer_work(pieces, wideband[position..].as_ptr(), wideband.len() - position,
+                    [buffer.as_mut_ptr()].as_ptr(), buffer.len(), &mut produced);
+                assert!(produced <= buffer.len());
+                output.extend_from_slice(complex_slice(buffer.as_ptr(), produced));
+                position += consumed;
+            }
+            assert_eq!(output.len(), expected.len());
+            assert!(output.iter().zip(expected.iter()).all(|(a, b)| a == b));
+            sdrglue_channelizer_free(whole);
+            sdrglue_channelizer_free(pieces);
+            sdrglue_dechannelizer_free(dechannelizer);
+        }
+    }
+}
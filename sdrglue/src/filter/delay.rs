@@ -0,0 +1,61 @@
+//! Sample-accurate delay line.
+
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+
+use crate::num_traits::Zero;
+
+/// Delays a signal by a whole number of samples.
+pub struct DelayLine<T> {
+    /// Samples waiting to be output, oldest first.
+    buffer: VecDeque<T>,
+    output: Vec<T>,
+}
+
+impl<T: Copy + Zero> DelayLine<T> {
+    pub fn new(delay: usize) -> Self {
+        Self {
+            buffer: core::iter::repeat_n(T::zero(), delay).collect(),
+            output: Vec::new(),
+        }
+    }
+
+    /// Change the delay.
+    /// Increasing the delay inserts zeros to the signal
+    /// and decreasing it skips samples.
+    pub fn set_delay(&mut self, delay: usize) {
+        while self.buffer.len() < delay {
+            self.buffer.push_front(T::zero());
+        }
+        while self.buffer.len() > delay {
+            self.buffer.pop_front();
+        }
+    }
+
+    pub fn delay(&self) -> usize {
+        self.buffer.len()
+    }
+
+    /// Delay a block of samples.
+    /// Returns a block of the same length.
+    pub fn process(&mut self, input: &[T]) -> &[T] {
+        self.buffer.extend(input.iter().copied());
+        self.output.clear();
+        self.output.extend(self.buffer.drain(..input.len()));
+        &self.output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_delay() {
+        let mut delay = DelayLine::<f32>::new(3);
+        assert_eq!(delay.process(&[1.0, 2.0]), &[0.0, 0.0]);
+        assert_eq!(delay.process(&[3.0, 4.0, 5.0, 6.0]), &[0.0, 1.0, 2.0, 3.0]);
+        delay.set_delay(1);
+        assert_eq!(delay.process(&[7.0]), &[6.0]);
+    }
+}
@@ -1,5 +1,8 @@
 //! Filter design
 
+use alloc::vec::Vec;
+#[cfg(not(any(feature = "std", test)))]
+use crate::num_traits::Float;
 use crate::{Sample, sample_consts};
 use super::fir;
 
@@ -9,7 +12,7 @@ pub fn design_fir_lowpass(
     cutoff: f64,
     half_length: usize,
 ) -> fir::SymmetricRealTaps {
-    let sinc_freq = (std::f64::consts::PI * 2.0 * cutoff / sample_rate) as Sample;
+    let sinc_freq = (core::f64::consts::PI * 2.0 * cutoff / sample_rate) as Sample;
     let window_freq = sample_consts::PI / half_length as Sample;
 
     let mut halftaps = (0..half_length).map(|i| {
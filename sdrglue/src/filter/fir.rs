@@ -3,7 +3,9 @@
 // and make vector size a constant that can be more easily adjusted.
 // This is now directly reused from another project.
 
-use std::rc::Rc;
+use alloc::rc::Rc;
+use alloc::vec;
+use alloc::vec::Vec;
 use wide::f32x4;
 use crate::num_complex::Complex;
 use crate::num_traits as num;
@@ -50,7 +52,15 @@ impl FirCf32Sym {
             history_im:  vec![num::zero(); len],
             reversed_re: vec![num::zero(); len],
             reversed_im: vec![num::zero(); len],
-            taps:        taps,
+            taps,
+        }
+    }
+
+    /// Clear filter history, as if no samples had been processed.
+    pub fn reset(&mut self) {
+        self.i = 0;
+        for history in [&mut self.history_re, &mut self.history_im, &mut self.reversed_re, &mut self.reversed_im] {
+            history.fill(num::zero());
         }
     }
 
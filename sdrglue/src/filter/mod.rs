@@ -2,3 +2,7 @@ mod fir;
 pub use fir::*;
 mod design;
 pub use design::*;
+mod resample;
+pub use resample::*;
+mod delay;
+pub use delay::*;
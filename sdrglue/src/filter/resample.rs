@@ -0,0 +1,91 @@
+//! Integer factor resampling for real-valued signals such as audio.
+
+use alloc::vec::Vec;
+use crate::{Sample, ComplexSample};
+use super::{fir, design};
+
+/// Number of taps per polyphase branch (per half of the filter)
+/// used for resampling filters.
+const TAPS_PER_FACTOR: usize = 8;
+
+/// Design lowpass filter for resampling by a given factor.
+fn design_resampling_filter(factor: usize) -> fir::SymmetricRealTaps {
+    // The actual sample rate does not matter, only the ratio.
+    // Put the cutoff a bit below the Nyquist frequency
+    // of the lower sample rate, so that aliasing is mostly
+    // within the transition band.
+    design::design_fir_lowpass(1.0, 0.45 / factor as f64, TAPS_PER_FACTOR * factor)
+}
+
+/// Decimator for real-valued signals.
+pub struct Decimator {
+    /// Real signal is filtered using the complex filter
+    /// by putting it in the real part.
+    /// This wastes some computation but is simple.
+    filter: fir::FirCf32Sym,
+    factor: usize,
+    /// Number of input samples since the previous output sample.
+    phase: usize,
+}
+
+impl Decimator {
+    pub fn new(factor: usize) -> Self {
+        Self {
+            filter: fir::FirCf32Sym::new(design_resampling_filter(factor)),
+            factor,
+            phase: 0,
+        }
+    }
+
+    /// Decimate a block of samples.
+    /// Decimated signal is appended to output.
+    pub fn process(&mut self, input: &[Sample], output: &mut Vec<Sample>) {
+        for &sample in input {
+            let filtered = self.filter.sample(ComplexSample { re: sample, im: 0.0 });
+            self.phase += 1;
+            if self.phase >= self.factor {
+                self.phase = 0;
+                output.push(filtered.re);
+            }
+        }
+    }
+}
+
+/// Interpolator for real-valued signals.
+pub struct Interpolator {
+    filter: fir::FirCf32Sym,
+    factor: usize,
+    /// Number of output samples since the previous input sample.
+    phase: usize,
+}
+
+impl Interpolator {
+    pub fn new(factor: usize) -> Self {
+        Self {
+            filter: fir::FirCf32Sym::new(design_resampling_filter(factor)),
+            factor,
+            phase: 0,
+        }
+    }
+
+    /// Fill output with interpolated signal.
+    /// New input samples are requested from next_input when needed,
+    /// so output length does not need to be a multiple
+    /// of interpolation factor.
+    pub fn process(&mut self, mut next_input: impl FnMut() -> Sample, output: &mut [Sample]) {
+        for out in output.iter_mut() {
+            // Zero-stuffing followed by a filter.
+            // Multiply by factor to compensate for the zeros.
+            let input = if self.phase == 0 {
+                next_input() * self.factor as Sample
+            } else {
+                0.0
+            };
+            *out = self.filter.sample(ComplexSample { re: input, im: 0.0 }).re;
+            self.phase += 1;
+            if self.phase >= self.factor {
+                self.phase = 0;
+            }
+        }
+    }
+}
@@ -0,0 +1,51 @@
+//! Malformed inputs for testing that parsers of configuration
+//! and control commands return errors instead of panicking.
+//!
+//! Inputs are made by mutating valid examples: parts of them
+//! are removed, repeated or replaced with tokens that often
+//! break parsers, like separators, huge numbers and NaN.
+//! The generator is seeded, so failures can be reproduced.
+
+use crate::loopback::Random;
+
+/// Tokens inserted into inputs.
+const TOKENS: &[&str] = &[
+    "", " ", "-", "+", ":", ",", ";", "=", "/", "{", "}", "\"", "#", "\n", "\0", "ä",
+    "0", "-1", "0x", "1e308", "-1e308", "1e400", "NaN", "inf", "-inf",
+    "18446744073709551616", "9223372036854775807", "65535",
+    "rx", "tx", "{index}", "{port}", "{modulation}", "[", "]", "null", "true",
+];
+
+/// Number of inputs generated from each example.
+const INPUTS_PER_EXAMPLE: usize = 300;
+
+/// Random index below length.
+fn pick(random: &mut Random, length: usize) -> usize {
+    ((random.uniform() * length as f64) as usize).min(length.saturating_sub(1))
+}
+
+/// Return the examples followed by mutated versions of them.
+pub fn inputs(examples: &[&str]) -> Vec<String> {
+    let mut random = Random::new(1);
+    let mut inputs: Vec<String> = examples.iter().map(|example| example.to_string()).collect();
+    for example in examples {
+        for _ in 0..INPUTS_PER_EXAMPLE {
+            let mut parts: Vec<String> = example.chars().map(String::from).collect();
+            for _ in 0..1 + pick(&mut random, 4) {
+                let position = pick(&mut random, parts.len() + 1);
+                let token = TOKENS[pick(&mut random, TOKENS.len())].to_string();
+                match pick(&mut random, 4) {
+                    0 if position < parts.len() => { parts.remove(position); },
+                    1 if position < parts.len() => parts[position] = token,
+                    2 => {
+                        let repeated = parts[position..].to_vec();
+                        parts.extend(repeated);
+                    },
+                    _ => parts.insert(position, token),
+                }
+            }
+            inputs.push(parts.concat());
+        }
+    }
+    inputs
+}
@@ -0,0 +1,111 @@
+//! Description of the processing topology for --dump-graph.
+//!
+//! The graph has nodes for the SDR, filter banks, channels
+//! and channel outputs, with sample rates and frequencies
+//! as properties of each node. It can be written as JSON
+//! or as Graphviz DOT, for example to render it with:
+//! ```text
+//! sdrglue ... --dump-graph dot | dot -Tsvg > graph.svg
+//! ```
+
+use serde_json::{json, Value};
+
+use crate::configuration;
+use crate::rx_dsp::RxDsp;
+use crate::tx_dsp::TxDsp;
+
+struct Node {
+    id: String,
+    kind: &'static str,
+    properties: Value,
+}
+
+#[derive(Default)]
+pub struct Graph {
+    nodes: Vec<Node>,
+    /// Edges as pairs of node IDs.
+    edges: Vec<(String, String)>,
+}
+
+impl Graph {
+    /// Add a node. Properties should be a JSON object.
+    pub fn node(&mut self, id: impl Into<String>, kind: &'static str, properties: Value) {
+        self.nodes.push(Node { id: id.into(), kind, properties });
+    }
+
+    pub fn edge(&mut self, from: impl Into<String>, to: impl Into<String>) {
+        self.edges.push((from.into(), to.into()));
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!({
+            "nodes": self.nodes.iter().map(|node| json!({
+                "id": node.id,
+                "kind": node.kind,
+                "properties": node.properties,
+            })).collect::<Vec<Value>>(),
+            "edges": self.edges.iter().map(|(from, to)| json!({ "from": from, "to": to }))
+                .collect::<Vec<Value>>(),
+        })
+    }
+
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph sdrglue {\n    rankdir=LR;\n    node [shape=box];\n");
+        for node in self.nodes.iter() {
+            let mut label = format!("{}\\n{}", node.id, node.kind);
+            if let Value::Object(properties) = &node.properties {
+                for (key, value) in properties {
+                    let value = match value {
+                        Value::String(text) => text.clone(),
+                        Value::Array(values) => values.iter()
+                            .map(|value| value.as_str().map_or(value.to_string(), str::to_string))
+                            .collect::<Vec<String>>().join(" "),
+                        value => value.to_string(),
+                    };
+                    label.push_str(&format!("\\n{}: {}", key, value.replace('"', "\\\"")));
+                }
+            }
+            dot.push_str(&format!("    \"{}\" [label=\"{}\"];\n", node.id, label));
+        }
+        for (from, to) in self.edges.iter() {
+            dot.push_str(&format!("    \"{}\" -> \"{}\";\n", from, to));
+        }
+        dot.push_str("}\n");
+        dot
+    }
+}
+
+/// Write the graph of receive and transmit processing
+/// to standard output in the format given by --dump-graph.
+pub fn dump(cli: &configuration::Cli, rx_dsp: Option<&RxDsp>, tx_dsp: Option<&TxDsp>) {
+    let mut graph = Graph::default();
+    if let Some(rx_dsp) = rx_dsp {
+        rx_dsp.add_to_graph(cli, &mut graph);
+    }
+    if let Some(tx_dsp) = tx_dsp {
+        tx_dsp.add_to_graph(&mut graph);
+    }
+    // TODO: handle errors more nicely
+    match cli.dump_graph.as_deref() {
+        Some("json") => println!("{}", serde_json::to_string_pretty(&graph.to_json()).unwrap()),
+        Some("dot") => print!("{}", graph.to_dot()),
+        format => panic!("Unknown graph format {:?}, use dot or json", format),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_graph() {
+        let mut graph = Graph::default();
+        graph.node("sdr_rx", "sdr", json!({ "sample_rate": 384000.0 }));
+        graph.node("rx0", "channel", json!({ "option": "demodulate_to_udp" }));
+        graph.edge("sdr_rx", "rx0");
+        assert_eq!(graph.to_json()["edges"][0], json!({ "from": "sdr_rx", "to": "rx0" }));
+        let dot = graph.to_dot();
+        assert!(dot.contains("\"rx0\" [label=\"rx0\\nchannel\\noption: demodulate_to_udp\"];"), "{}", dot);
+        assert!(dot.contains("\"sdr_rx\" -> \"rx0\";"));
+    }
+}
@@ -0,0 +1,158 @@
+//! Health endpoints for container orchestration.
+//!
+//! A small HTTP server, separate from the control API so that
+//! it keeps answering even if the processing loop hangs:
+//! ```text
+//! GET /healthz   200 if the processing loop is running, 503 if it
+//!                has not made progress for LIVENESS_TIMEOUT
+//! GET /readyz    200 if also SDR streams are working, 503 otherwise
+//! GET /metrics   the same state in Prometheus text format
+//! ```
+//! The processing loop counts as running while it is starting,
+//! so slow SDR initialization does not fail liveness probes.
+
+use std::net::{TcpListener, TcpStream};
+use std::sync::OnceLock;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use crate::http_api::{read_request, write_response};
+
+/// Time without progress of the processing loop
+/// after which it is considered hung.
+const LIVENESS_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Time to wait for a client to send its request.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Reference for times below.
+static START: OnceLock<Instant> = OnceLock::new();
+/// Time of latest iteration of the processing loop
+/// in milliseconds since START, or 0 before the first one.
+static HEARTBEAT: AtomicU64 = AtomicU64::new(0);
+/// Whether the latest SDR read or write succeeded.
+static STREAM_OK: AtomicBool = AtomicBool::new(false);
+static STREAM_ERRORS: AtomicU64 = AtomicU64::new(0);
+
+fn start_time() -> Instant {
+    *START.get_or_init(Instant::now)
+}
+
+/// Record an iteration of the processing loop.
+pub fn heartbeat() {
+    // At least 1 to tell it apart from no heartbeat yet.
+    HEARTBEAT.store((start_time().elapsed().as_millis() as u64).max(1), Ordering::Relaxed);
+}
+
+/// Record the result of reading from or writing to the SDR.
+pub fn stream_status(ok: bool) {
+    STREAM_OK.store(ok, Ordering::Relaxed);
+    if !ok {
+        STREAM_ERRORS.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Current health state.
+struct Health {
+    /// Time since latest heartbeat, or None while starting.
+    since_heartbeat: Option<Duration>,
+    stream_ok: bool,
+    stream_errors: u64,
+}
+
+impl Health {
+    fn current() -> Self {
+        let heartbeat = HEARTBEAT.load(Ordering::Relaxed);
+        Self {
+            since_heartbeat: (heartbeat > 0).then(||
+                start_time().elapsed().saturating_sub(Duration::from_millis(heartbeat))),
+            stream_ok: STREAM_OK.load(Ordering::Relaxed),
+            stream_errors: STREAM_ERRORS.load(Ordering::Relaxed),
+        }
+    }
+
+    fn live(&self) -> bool {
+        self.since_heartbeat.is_none_or(|time| time < LIVENESS_TIMEOUT)
+    }
+
+    fn ready(&self) -> bool {
+        self.since_heartbeat.is_some() && self.live() && self.stream_ok
+    }
+
+    fn metrics(&self) -> String {
+        let flag = |value: bool| if value { 1 } else { 0 };
+        let mut text = String::new();
+        for (name, kind, help, value) in [
+            ("sdrglue_live", "gauge", "Whether the processing loop is running.", flag(self.live()).to_string()),
+            ("sdrglue_ready", "gauge", "Whether processing is running with working SDR streams.", flag(self.ready()).to_string()),
+            ("sdrglue_sdr_stream_ok", "gauge", "Whether the latest SDR read or write succeeded.", flag(self.stream_ok).to_string()),
+            ("sdrglue_sdr_errors_total", "counter", "SDR read and write errors.", self.stream_errors.to_string()),
+            ("sdrglue_heartbeat_age_seconds", "gauge", "Time since the latest iteration of the processing loop.",
+                self.since_heartbeat.map_or("NaN".to_string(), |time| time.as_secs_f64().to_string())),
+        ] {
+            text += &format!("# HELP {} {}\n# TYPE {} {}\n{} {}\n", name, help, name, kind, name, value);
+        }
+        text
+    }
+}
+
+/// Listen for HTTP connections at given TCP address.
+pub fn start(address: &str) {
+    start_time();
+    // TODO: handle errors more nicely
+    let listener = TcpListener::bind(address)
+        .unwrap_or_else(|e| panic!("Could not listen on health address {}: {}", address, e));
+    std::thread::spawn(move || {
+        // A client that never sends its request must not keep
+        // probes from other clients waiting, so each connection
+        // gets its own thread.
+        for stream in listener.incoming().flatten() {
+            std::thread::spawn(move || {
+                if let Err(err) = serve(stream) {
+                    eprintln!("Health endpoint connection error: {}", err);
+                }
+            });
+        }
+    });
+}
+
+fn serve(mut stream: TcpStream) -> std::io::Result<()> {
+    stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+    let target = read_request(&stream)?.map(|request| request.target);
+    let health = Health::current();
+    let probe = |ok: bool| if ok { ("200 OK", "ok\n") } else { ("503 Service Unavailable", "fail\n") };
+    match target.as_deref() {
+        Some("/healthz") => {
+            let (status, body) = probe(health.live());
+            write_response(&mut stream, status, "text/plain", body)
+        },
+        Some("/readyz") => {
+            let (status, body) = probe(health.ready());
+            write_response(&mut stream, status, "text/plain", body)
+        },
+        Some("/metrics") => write_response(&mut stream, "200 OK", "text/plain; version=0.0.4", &health.metrics()),
+        _ => write_response(&mut stream, "404 Not Found", "text/plain", "Not found\n"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_health() {
+        let mut health = Health { since_heartbeat: None, stream_ok: false, stream_errors: 0 };
+        // Live but not ready while starting.
+        assert!(health.live() && !health.ready());
+        health.since_heartbeat = Some(Duration::from_millis(100));
+        assert!(health.live() && !health.ready());
+        health.stream_ok = true;
+        assert!(health.live() && health.ready());
+        health.since_heartbeat = Some(LIVENESS_TIMEOUT);
+        assert!(!health.live() && !health.ready());
+        let metrics = health.metrics();
+        assert!(metrics.contains("\nsdrglue_live 0\n"));
+        assert!(metrics.contains("\nsdrglue_sdr_stream_ok 1\n"));
+        assert!(metrics.contains("# TYPE sdrglue_sdr_errors_total counter\nsdrglue_sdr_errors_total 0\n"));
+    }
+}
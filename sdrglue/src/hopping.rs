@@ -0,0 +1,74 @@
+//! Time-multiplexed reception of channels not fitting in one span.
+//!
+//! The SDR is retuned in turn to each center frequency in a list,
+//! staying on each one for a fixed time. Only channels within
+//! the current span are processed.
+
+use std::time::{Duration, Instant};
+
+use crate::configuration;
+use crate::events::{self, Event};
+use crate::fcfb;
+use crate::rx_dsp;
+
+pub struct Hopper {
+    frequencies: Vec<f64>,
+    /// Index of the current frequency.
+    current: usize,
+    dwell: Duration,
+    /// Time to switch to next frequency.
+    next_hop: Instant,
+}
+
+impl Hopper {
+    pub fn from_cli(cli: &configuration::Cli) -> Option<Self> {
+        // TODO: handle errors more nicely
+        let frequencies: Vec<f64> = cli.rx_hop_frequencies.as_ref()?
+            .split(',').map(|f| f.parse().unwrap()).collect();
+        Some(Self {
+            frequencies,
+            current: 0,
+            dwell: Duration::from_secs_f64(cli.rx_hop_dwell),
+            next_hop: Instant::now() + Duration::from_secs_f64(cli.rx_hop_dwell),
+        })
+    }
+
+    /// Center frequency to start receiving at.
+    pub fn first_frequency(&self) -> f64 {
+        self.frequencies[0]
+    }
+
+    /// Check that each receive channel fits in at least one span.
+    /// Print the channels that do not and panic if there are any.
+    pub fn check_channels(&self, cli: &configuration::Cli, sample_rate: f64) {
+        let mut out_of_span = 0;
+        for (index, channel) in rx_dsp::channel_list(cli).unwrap_or_default().iter().enumerate() {
+            // Scanners need each of their frequencies to fit.
+            for &frequency in std::iter::once(&channel.frequency).chain(channel.other_frequencies.iter()) {
+                if !self.frequencies.iter().any(|&center|
+                    fcfb::channel_fits(sample_rate, center, channel.sample_rate, frequency)
+                ) {
+                    eprintln!("Receive channel rx{} at {} Hz is not within any of the spans", index, frequency);
+                    out_of_span += 1;
+                }
+            }
+        }
+        if out_of_span > 0 {
+            // TODO: handle errors more nicely
+            panic!("{} receive channels are outside of SDR spans", out_of_span);
+        }
+    }
+
+    /// Return the next center frequency if it is time to retune.
+    pub fn update(&mut self) -> Option<f64> {
+        let now = Instant::now();
+        if self.frequencies.len() < 2 || now < self.next_hop {
+            return None;
+        }
+        self.next_hop = now + self.dwell;
+        self.current = (self.current + 1) % self.frequencies.len();
+        let frequency = self.frequencies[self.current];
+        events::emit(Event::new("retune").with("frequency", frequency));
+        Some(frequency)
+    }
+}
@@ -0,0 +1,322 @@
+//! HTTP REST API mirroring the control API.
+//!
+//! Each endpoint is translated to a control command, with fields
+//! of a JSON request body and path parameters added to it,
+//! and the response of the command is returned as JSON.
+//! Failed commands get status 400. The API is described in
+//! OpenAPI format in openapi.json, also served at /api/openapi.json.
+//!
+//! Endpoints and the commands they correspond to:
+//! ```text
+//! GET    /api/protocol                    protocol
+//! GET    /api/schema                      schema
+//! GET    /api/status                      status
+//! GET    /api/channels                    channels
+//! GET    /api/sensors                     sensors
+//! POST   /api/channels/{channel}/enable   enable
+//! POST   /api/channels/{channel}/disable  disable
+//! PUT    /api/channels/{channel}/delay    delay
+//! POST   /api/inject                      inject
+//! DELETE /api/inject                      stop_inject
+//! POST   /api/outputs                     output
+//! POST   /api/interlock/challenge         arm_challenge
+//! POST   /api/interlock/arm               arm
+//! POST   /api/interlock/disarm            disarm
+//! POST   /api/profiles                    save_profile
+//! ```
+//! A query parameter instance=NAME forwards the command
+//! to an instance of a federation.
+//!
+//! Requests changing anything have to be sent with
+//! Content-Type: application/json, and requests from web pages
+//! are only accepted from pages served by the API host itself,
+//! so that other web pages open in a browser cannot send commands.
+//! Without --api-token, the Host header has to be an IP address,
+//! localhost or the host in --http-api, since a web page could
+//! otherwise point its own name to the API host to pass as the
+//! same origin. With --api-token, requests need the token
+//! as a bearer token, and any host name may be used.
+//! For example:
+//! ```text
+//! curl -X PUT -H 'Content-Type: application/json' -H 'Authorization: Bearer TOKEN' \
+//!     -d '{"delay":0.0125}' http://127.0.0.1:7358/api/channels/rx0/delay
+//! ```
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{IpAddr, TcpListener, TcpStream};
+use std::time::Duration;
+
+use serde_json::{json, Value};
+
+use crate::auth;
+use crate::control::Requester;
+
+/// OpenAPI description of the endpoints.
+const OPENAPI: &str = include_str!("openapi.json");
+
+/// Maximum length of request line and each header in bytes.
+const MAX_LINE_LENGTH: usize = 8192;
+/// Maximum length of a request body in bytes.
+const MAX_BODY_LENGTH: usize = 65536;
+/// Time to wait for a client to send its request.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Listen for HTTP connections at given TCP address.
+/// If a token is given, requests have to include it.
+pub fn start(address: &str, token: Option<String>, control: Requester) {
+    // TODO: handle errors more nicely
+    let listener = TcpListener::bind(address)
+        .unwrap_or_else(|e| panic!("Could not listen on HTTP API address {}: {}", address, e));
+    let address = address.to_string();
+    std::thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let control = control.clone();
+            let token = token.clone();
+            let address = address.clone();
+            std::thread::spawn(move || {
+                if let Err(err) = serve(stream, &address, token.as_deref(), control) {
+                    eprintln!("HTTP API connection error: {}", err);
+                }
+            });
+        }
+    });
+}
+
+/// Translate a request to a control command.
+/// Return None if there is no such endpoint.
+fn route(method: &str, target: &str, body: Value) -> Option<Value> {
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+    let mut command = match body {
+        Value::Object(fields) => Value::Object(fields),
+        _ => json!({}),
+    };
+    let name = match (method, segments.as_slice()) {
+        ("GET", ["api", name @ ("protocol" | "schema" | "status" | "channels" | "sensors")]) => *name,
+        ("POST", ["api", "channels", channel, name @ ("enable" | "disable")]) => {
+            command["channel"] = json!(channel);
+            *name
+        },
+        ("PUT", ["api", "channels", channel, "delay"]) => {
+            command["channel"] = json!(channel);
+            "delay"
+        },
+        ("POST", ["api", "inject"]) => "inject",
+        ("DELETE", ["api", "inject"]) => "stop_inject",
+        ("POST", ["api", "outputs"]) => "output",
+        ("POST", ["api", "interlock", "challenge"]) => "arm_challenge",
+        ("POST", ["api", "interlock", "arm"]) => "arm",
+        ("POST", ["api", "interlock", "disarm"]) => "disarm",
+        ("POST", ["api", "profiles"]) => "save_profile",
+        _ => return None,
+    };
+    command["command"] = json!(name);
+    if let Some((_, instance)) = query.split('&').filter_map(|parameter| parameter.split_once('=')).find(|(key, _)| *key == "instance") {
+        command["instance"] = json!(instance);
+    }
+    Some(command)
+}
+
+/// Read a line of at most MAX_LINE_LENGTH bytes without line ending.
+fn read_line(reader: &mut impl BufRead) -> std::io::Result<String> {
+    let mut line = Vec::new();
+    reader.take(MAX_LINE_LENGTH as u64).read_until(b'\n', &mut line)?;
+    if !line.ends_with(b"\n") {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "Too long or incomplete line"));
+    }
+    Ok(String::from_utf8_lossy(&line).trim_end().to_string())
+}
+
+/// A received HTTP request.
+pub struct Request {
+    pub method: String,
+    pub target: String,
+    /// Header names and values.
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Request {
+    /// Value of a header, ignoring case of the name.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter().find(|(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// Read a request from a connection.
+/// Return None if the body is longer than MAX_BODY_LENGTH.
+pub fn read_request(stream: &TcpStream) -> std::io::Result<Option<Request>> {
+    let mut reader = BufReader::new(stream);
+    let request_line = read_line(&mut reader)?;
+    let mut headers = Vec::new();
+    let mut content_length = 0;
+    loop {
+        let header = read_line(&mut reader)?;
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                content_length = value.trim().parse().unwrap_or(usize::MAX);
+            }
+            headers.push((name.trim().to_string(), value.trim().to_string()));
+        }
+    }
+    if content_length > MAX_BODY_LENGTH {
+        return Ok(None);
+    }
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body)?;
+    let mut parts = request_line.split(' ');
+    Ok(Some(Request {
+        method: parts.next().unwrap_or("").to_string(),
+        target: parts.next().unwrap_or("").to_string(),
+        headers,
+        body,
+    }))
+}
+
+pub fn write_response(stream: &mut TcpStream, status: &str, content_type: &str, body: &str) -> std::io::Result<()> {
+    write!(stream, "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status, content_type, body.len(), body)
+}
+
+/// Host name or address without port.
+fn host_name(host: &str) -> &str {
+    match host.strip_prefix('[') {
+        Some(rest) => rest.split(']').next().unwrap_or(rest),
+        None => host.rsplit_once(':').map_or(host, |(name, _)| name),
+    }
+}
+
+/// Whether a Host header may refer to the API listening at given address.
+fn host_allowed(host: &str, address: &str) -> bool {
+    let name = host_name(host);
+    name.parse::<IpAddr>().is_ok()
+        || name.eq_ignore_ascii_case("localhost")
+        || name.eq_ignore_ascii_case(host_name(address))
+}
+
+/// Check that a request to the API listening at given address
+/// may be handled. Return the status and error message otherwise.
+fn check_request(request: &Request, address: &str, token: Option<&str>) -> Result<(), (&'static str, &'static str)> {
+    if let Some(token) = token {
+        let given = request.header("authorization").and_then(|value| value.strip_prefix("Bearer "));
+        if !given.is_some_and(|given| auth::secret_matches(given.trim(), token)) {
+            return Err(("401 Unauthorized", "Missing or wrong token"));
+        }
+    } else if request.header("host").is_some_and(|host| !host_allowed(host, address)) {
+        // Other names could be pointed to the API host by
+        // a web page, which would then pass the Origin check.
+        return Err(("403 Forbidden", "Host not allowed, use --api-token to access by other names"));
+    }
+    // Browsers send Origin with requests from web pages.
+    // Only pages served from the API host itself are trusted.
+    if let Some(origin) = request.header("origin") {
+        let host = origin.strip_prefix("http://").or_else(|| origin.strip_prefix("https://"));
+        if host.is_none() || host != request.header("host") {
+            return Err(("403 Forbidden", "Origin not allowed"));
+        }
+    }
+    // Web pages cannot send JSON to other sites without
+    // a CORS preflight, which is never allowed here.
+    if matches!(request.method.as_str(), "POST" | "PUT" | "DELETE") {
+        let content_type = request.header("content-type").unwrap_or("");
+        let media_type = content_type.split(';').next().unwrap_or("").trim();
+        if !media_type.eq_ignore_ascii_case("application/json") {
+            return Err(("415 Unsupported Media Type", "Content-Type has to be application/json"));
+        }
+    }
+    Ok(())
+}
+
+/// Handle one request and close the connection.
+fn serve(mut stream: TcpStream, address: &str, token: Option<&str>, control: Requester) -> std::io::Result<()> {
+    stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+    let Some(request) = read_request(&stream)? else {
+        return write_response(&mut stream, "413 Content Too Large", "application/json",
+            &json!({ "ok": false, "error": "Too long request" }).to_string());
+    };
+    if request.method == "GET" && request.target == "/api/openapi.json" {
+        return write_response(&mut stream, "200 OK", "application/json", OPENAPI);
+    }
+    if let Err((status, error)) = check_request(&request, address, token) {
+        return write_response(&mut stream, status, "application/json",
+            &json!({ "ok": false, "error": error }).to_string());
+    }
+    let Request { method, target, body, .. } = request;
+    let body = if body.trim_ascii().is_empty() {
+        Ok(json!({}))
+    } else {
+        serde_json::from_slice(&body).map_err(|err| format!("Invalid JSON: {}", err))
+    };
+    let (status, response) = match body.map(|body| route(&method, &target, body)) {
+        Ok(Some(command)) => match control.request(command) {
+            Some(response) if response["ok"] == true => ("200 OK", response),
+            Some(response) => ("400 Bad Request", response),
+            None => ("503 Service Unavailable", json!({ "ok": false, "error": "Stopped" })),
+        },
+        Ok(None) => ("404 Not Found", json!({ "ok": false, "error": "Not found" })),
+        Err(error) => ("400 Bad Request", json!({ "ok": false, "error": error })),
+    };
+    write_response(&mut stream, status, "application/json", &response.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_route() {
+        assert_eq!(route("GET", "/api/status", json!({})), Some(json!({ "command": "status" })));
+        assert_eq!(route("PUT", "/api/channels/rx0/delay?instance=site2", json!({ "delay": 0.0125 })),
+            Some(json!({ "command": "delay", "channel": "rx0", "delay": 0.0125, "instance": "site2" })));
+        assert_eq!(route("DELETE", "/api/inject", json!({})), Some(json!({ "command": "stop_inject" })));
+        // Path parameters and command name override body fields.
+        assert_eq!(route("POST", "/api/channels/tx1/disable", json!({ "command": "arm", "channel": "rx0" })),
+            Some(json!({ "command": "disable", "channel": "tx1" })));
+        assert_eq!(route("GET", "/api/inject", json!({})), None);
+        assert_eq!(route("POST", "/api/channels/rx0", json!({})), None);
+
+        // Every endpoint is in the OpenAPI description.
+        let openapi: Value = serde_json::from_str(OPENAPI).unwrap();
+        for (path, paths) in openapi["paths"].as_object().unwrap() {
+            for method in paths.as_object().unwrap().keys() {
+                let path = path.replace("{channel}", "rx0");
+                assert!(path == "/api/openapi.json" || route(&method.to_uppercase(), &path, json!({})).is_some(), "{} {}", method, path);
+            }
+        }
+    }
+
+    #[test]
+    fn test_check_request() {
+        let request = |method: &str, headers: &[(&str, &str)]| Request {
+            method: method.to_string(),
+            target: "/api/inject".to_string(),
+            headers: headers.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect(),
+            body: Vec::new(),
+        };
+        let json = ("Content-Type", "application/json; charset=utf-8");
+        let host = ("Host", "127.0.0.1:7358");
+        let check_request_at = |request: &Request, token| check_request(request, "127.0.0.1:7358", token);
+        assert_eq!(check_request_at(&request("GET", &[]), None), Ok(()));
+        assert_eq!(check_request_at(&request("POST", &[json]), None), Ok(()));
+        assert_eq!(check_request_at(&request("DELETE", &[]), None).unwrap_err().0, "415 Unsupported Media Type");
+        assert_eq!(check_request_at(&request("POST", &[("content-type", "text/plain")]), None).unwrap_err().0, "415 Unsupported Media Type");
+        assert_eq!(check_request_at(&request("POST", &[json, host, ("Origin", "http://127.0.0.1:7358")]), None), Ok(()));
+        assert_eq!(check_request_at(&request("POST", &[json, host, ("Origin", "https://example.com")]), None).unwrap_err().0, "403 Forbidden");
+        assert_eq!(check_request_at(&request("GET", &[host, ("Origin", "null")]), None).unwrap_err().0, "403 Forbidden");
+        assert_eq!(check_request_at(&request("GET", &[]), Some("secret")).unwrap_err().0, "401 Unauthorized");
+        assert_eq!(check_request_at(&request("GET", &[("Authorization", "Bearer wrong")]), Some("secret")).unwrap_err().0, "401 Unauthorized");
+        assert_eq!(check_request_at(&request("GET", &[("Authorization", "Bearer secret")]), Some("secret")), Ok(()));
+
+        // Names other than the listening one may be DNS rebinding.
+        assert_eq!(check_request_at(&request("GET", &[("Host", "localhost:7358")]), None), Ok(()));
+        assert_eq!(check_request_at(&request("GET", &[("Host", "[::1]:7358")]), None), Ok(()));
+        assert_eq!(check_request_at(&request("GET", &[("Host", "attacker.example:7358")]), None).unwrap_err().0, "403 Forbidden");
+        assert_eq!(check_request_at(&request("POST", &[json, ("Host", "attacker.example:7358"), ("Origin", "http://attacker.example:7358")]), None).unwrap_err().0, "403 Forbidden");
+        assert_eq!(check_request(&request("GET", &[("Host", "sdr.lan:7358")]), "sdr.lan:7358", None), Ok(()));
+        assert_eq!(check_request_at(&request("GET", &[("Host", "sdr.lan"), ("Authorization", "Bearer secret")]), Some("secret")), Ok(()));
+    }
+}
@@ -0,0 +1,176 @@
+//! Import of channel lists from other programs.
+//!
+//! Imported channels are added using a channel template,
+//! like channels given with --channel, so any channel option
+//! can be used for them. The modulation of each channel is
+//! mapped to an sdrglue modulation name and available
+//! in templates as the {modulation} placeholder.
+//! Channels with modes that cannot be demodulated are skipped.
+
+/// Channel read from a file.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ImportedChannel {
+    pub name: String,
+    /// Frequency in Hertz, rounded to whole Hertz.
+    pub frequency: f64,
+    pub modulation: &'static str,
+}
+
+/// Split a CSV line into fields, handling quoted fields.
+fn split_csv(line: &str) -> Vec<String> {
+    let mut fields = vec![String::new()];
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                chars.next();
+                fields.last_mut().unwrap().push('"');
+            },
+            '"' => quoted = !quoted,
+            ',' if !quoted => fields.push(String::new()),
+            _ => fields.last_mut().unwrap().push(c),
+        }
+    }
+    fields
+}
+
+/// Map a CHIRP mode to an sdrglue modulation.
+fn chirp_modulation(mode: &str) -> Option<&'static str> {
+    match mode {
+        "FM" | "NFM" => Some("FM"),
+        "AM" | "NAM" => Some("AM"),
+        "USB" => Some("USB"),
+        "LSB" => Some("LSB"),
+        "CW" => Some("CW"),
+        _ => None,
+    }
+}
+
+/// Parse channel memories exported from CHIRP as CSV.
+/// Frequencies are in MHz. Memories without a name
+/// are named after their location.
+pub fn parse_chirp(text: &str) -> Result<Vec<ImportedChannel>, String> {
+    let mut lines = text.lines().filter(|line| !line.trim().is_empty());
+    let header = split_csv(lines.next().ok_or("Empty file")?);
+    let column = |name: &str| header.iter().position(|field| field.trim() == name)
+        .ok_or(format!("Missing column {}", name));
+    let (location, name, frequency, mode) = (column("Location")?, column("Name")?, column("Frequency")?, column("Mode")?);
+
+    let mut channels = Vec::new();
+    for line in lines {
+        let fields = split_csv(line);
+        let field = |index: usize| fields.get(index).map_or("", |field| field.trim());
+        let megahertz: f64 = field(frequency).parse().ok().filter(|f: &f64| f.is_finite())
+            .ok_or_else(|| format!("Invalid frequency {}", field(frequency)))?;
+        let Some(modulation) = chirp_modulation(field(mode)) else {
+            eprintln!("Skipping memory {} with unsupported mode {}", field(location), field(mode));
+            continue;
+        };
+        channels.push(ImportedChannel {
+            name: if field(name).is_empty() { format!("mem{}", field(location)) } else { field(name).to_string() },
+            frequency: (megahertz * 1e6).round(),
+            modulation,
+        });
+    }
+    Ok(channels)
+}
+
+/// Map a gqrx demodulator name to an sdrglue modulation.
+fn gqrx_modulation(mode: &str) -> Option<&'static str> {
+    match mode {
+        "Narrow FM" => Some("FM"),
+        "AM" => Some("AM"),
+        "AM-Sync" => Some("SAM"),
+        "USB" => Some("USB"),
+        "LSB" => Some("LSB"),
+        "CW-L" | "CW-U" => Some("CW"),
+        _ => None,
+    }
+}
+
+/// Parse a gqrx bookmarks file. Bookmarks are lines of
+/// frequency in Hz, name, modulation, bandwidth and tags
+/// separated by semicolons. Tag definitions and comments
+/// starting with # are ignored.
+pub fn parse_gqrx(text: &str) -> Result<Vec<ImportedChannel>, String> {
+    let mut channels = Vec::new();
+    for line in text.lines().filter(|line| !line.trim_start().starts_with('#')) {
+        let fields: Vec<&str> = line.split(';').map(str::trim).collect();
+        // Tag definitions only have a name and a color.
+        if fields.len() < 4 {
+            continue;
+        }
+        let frequency: f64 = fields[0].parse().ok().filter(|f: &f64| f.is_finite())
+            .ok_or_else(|| format!("Invalid frequency {}", fields[0]))?;
+        let Some(modulation) = gqrx_modulation(fields[2]) else {
+            eprintln!("Skipping bookmark {} with unsupported mode {}", fields[1], fields[2]);
+            continue;
+        };
+        channels.push(ImportedChannel {
+            name: fields[1].to_string(),
+            frequency: frequency.round(),
+            modulation,
+        });
+    }
+    Ok(channels)
+}
+
+/// Read a file in given format.
+pub fn read(path: &str, parse: fn(&str) -> Result<Vec<ImportedChannel>, String>) -> Vec<ImportedChannel> {
+    // TODO: handle errors more nicely
+    let text = std::fs::read_to_string(path)
+        .unwrap_or_else(|err| panic!("Could not read {}: {}", path, err));
+    parse(&text).unwrap_or_else(|err| panic!("Could not import {}: {}", path, err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fuzz;
+
+    #[test]
+    fn test_chirp() {
+        let text = "\
+Location,Name,Frequency,Duplex,Offset,Tone,rToneFreq,cToneFreq,DtcsCode,DtcsPolarity,Mode,TStep,Skip,Comment
+0,CALL,145.500000,,0.000000,,88.5,88.5,023,NN,FM,12.50,,
+1,,7.074000,,0.000000,,88.5,88.5,023,NN,USB,1.00,,\"FT8, 40m\"
+2,BCAST,94.200000,,0.000000,,88.5,88.5,023,NN,WFM,100.00,,
+";
+        let channels = parse_chirp(text).unwrap();
+        assert_eq!(channels, vec![
+            ImportedChannel { name: "CALL".to_string(), frequency: 145.5e6, modulation: "FM" },
+            ImportedChannel { name: "mem1".to_string(), frequency: 7.074e6, modulation: "USB" },
+        ]);
+    }
+
+    #[test]
+    fn test_gqrx() {
+        let text = "\
+# Tag name          ;  color
+Untagged            ; #c0c0c0
+Repeaters           ; #ff0000
+
+# Frequency ; Name                     ; Modulation          ;  Bandwidth; Tags
+    145600000; OH2RAA                   ; Narrow FM           ;      10000; Repeaters
+     94200000; Radio                    ; WFM (stereo)        ;     160000; Untagged
+      7074000; FT8                      ; USB                 ;       2800; Untagged
+";
+        let channels = parse_gqrx(text).unwrap();
+        assert_eq!(channels, vec![
+            ImportedChannel { name: "OH2RAA".to_string(), frequency: 145.6e6, modulation: "FM" },
+            ImportedChannel { name: "FT8".to_string(), frequency: 7.074e6, modulation: "USB" },
+        ]);
+    }
+
+    #[test]
+    fn test_malformed_files() {
+        let chirp = fuzz::inputs(&["Location,Name,Frequency,Mode\n0,CALL,145.500000,FM\n1,\"A, B\",7.074,USB\n"]);
+        let gqrx = fuzz::inputs(&["# Frequency ; Name ; Modulation ; Bandwidth; Tags\n145600000; OH2RAA; Narrow FM; 10000; Repeaters\n"]);
+        // Anything may fail, as long as nothing panics
+        // and all imported frequencies are valid.
+        for channels in chirp.iter().map(|text| parse_chirp(text)).chain(gqrx.iter().map(|text| parse_gqrx(text))) {
+            assert!(channels.unwrap_or_default().iter().all(|channel| channel.frequency.is_finite()));
+        }
+    }
+}
@@ -0,0 +1,167 @@
+//! Export of metrics in InfluxDB line protocol.
+//!
+//! Channel metrics, occupancy reports, SDR sensor readings
+//! and system metrics are converted from events into lines
+//! of the form
+//! ```text
+//! sdrglue_channel_metrics,channel=rx0,frequency=145500000 power_db=-92.1,snr_db=3.2 1700000000000000000
+//! ```
+//! String fields become tags and numeric fields become fields,
+//! except that frequency is a tag so series can be grouped by it.
+//!
+//! Lines can be sent to InfluxDB over HTTP (for example
+//! http://localhost:8086/write?db=sdrglue) or UDP
+//! (udp://localhost:8089), or appended to a file.
+//! Other databases such as PostgreSQL with TimescaleDB
+//! can ingest line protocol using Telegraf.
+
+use std::fs::File;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpStream, UdpSocket};
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::events::{Event, EventListener, Value};
+
+/// Types of events exported.
+const EXPORTED_EVENTS: [&str; 4] = ["channel_metrics", "occupancy", "sensor", "system"];
+/// Lines are collected and sent at most this often.
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+enum Destination {
+    Http {
+        /// Host and port.
+        address: String,
+        /// Path with query string.
+        path: String,
+    },
+    Udp(UdpSocket),
+    File(File),
+}
+
+pub struct InfluxExporter {
+    destination: Destination,
+    /// Lines waiting to be sent.
+    lines: String,
+    last_flush: Instant,
+}
+
+/// Escape a tag key, tag value or field key.
+fn escape(text: &str) -> String {
+    text.replace(',', "\\,").replace('=', "\\=").replace(' ', "\\ ")
+}
+
+/// Format an event as a line, or return None if it has no numeric fields.
+fn format_line(event: &Event) -> Option<String> {
+    let mut tags = String::new();
+    let mut fields = Vec::new();
+    for (key, value) in event.fields.iter() {
+        match value {
+            Value::Number(number) if *key == "frequency" => tags.push_str(&format!(",{}={}", key, number)),
+            Value::Number(number) if number.is_finite() => fields.push(format!("{}={}", escape(key), number)),
+            Value::Number(_) => {},
+            Value::Str(text) => {
+                // Sensor values are strings but are usually numbers.
+                match text.trim().parse::<f64>() {
+                    Ok(number) if *key == "value" && number.is_finite() =>
+                        fields.push(format!("{}={}", key, number)),
+                    _ => tags.push_str(&format!(",{}={}", escape(key), escape(text))),
+                }
+            },
+        }
+    }
+    if fields.is_empty() {
+        return None;
+    }
+    let time = event.time.duration_since(SystemTime::UNIX_EPOCH).map(|t| t.as_nanos()).unwrap_or(0);
+    Some(format!("sdrglue_{}{} {} {}\n", event.kind, tags, fields.join(","), time))
+}
+
+impl InfluxExporter {
+    /// Export to an http:// or udp:// URL or to a file.
+    pub fn new(destination: &str) -> Self {
+        // TODO: handle errors more nicely
+        let destination = if let Some(rest) = destination.strip_prefix("http://") {
+            let (address, path) = rest.split_once('/').unwrap_or((rest, "write?db=sdrglue"));
+            Destination::Http { address: address.to_string(), path: format!("/{}", path) }
+        } else if let Some(address) = destination.strip_prefix("udp://") {
+            let socket = UdpSocket::bind("0.0.0.0:0").unwrap();
+            socket.connect(address).unwrap_or_else(|e| panic!("Invalid InfluxDB address {}: {}", address, e));
+            Destination::Udp(socket)
+        } else {
+            Destination::File(File::options().create(true).append(true).open(destination)
+                .unwrap_or_else(|e| panic!("Could not open {}: {}", destination, e)))
+        };
+        Self {
+            destination,
+            lines: String::new(),
+            last_flush: Instant::now(),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match &mut self.destination {
+            Destination::Http { address, path } => {
+                let mut stream = TcpStream::connect(address.as_str())?;
+                stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+                write!(stream,
+                    "POST {} HTTP/1.1\r\n\
+                    Host: {}\r\n\
+                    Content-Type: text/plain\r\n\
+                    Content-Length: {}\r\n\
+                    Connection: close\r\n\
+                    \r\n{}",
+                    path, address, self.lines.len(), self.lines)?;
+                let mut status = String::new();
+                BufReader::new(&stream).read_line(&mut status)?;
+                if !status.contains(" 2") {
+                    return Err(std::io::Error::other(format!("server responded: {}", status.trim())));
+                }
+            },
+            Destination::Udp(socket) => {
+                // Keep datagrams small by sending each line separately.
+                for line in self.lines.lines() {
+                    socket.send(line.as_bytes())?;
+                }
+            },
+            Destination::File(file) => file.write_all(self.lines.as_bytes())?,
+        }
+        Ok(())
+    }
+}
+
+impl EventListener for InfluxExporter {
+    fn event(&mut self, event: &Event) {
+        if !EXPORTED_EVENTS.contains(&event.kind) {
+            return;
+        }
+        if let Some(line) = format_line(event) {
+            self.lines.push_str(&line);
+        }
+        if self.last_flush.elapsed() >= FLUSH_INTERVAL && !self.lines.is_empty() {
+            if let Err(err) = self.flush() {
+                eprintln!("Error exporting metrics: {}", err);
+            }
+            self.lines.clear();
+            self.last_flush = Instant::now();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_line() {
+        let mut event = Event::new("channel_metrics")
+            .with("channel", "rx 0")
+            .with("frequency", 145.5e6)
+            .with("power_db", -92.5);
+        event.time = SystemTime::UNIX_EPOCH + Duration::from_secs(1);
+        assert_eq!(format_line(&event).unwrap(),
+            "sdrglue_channel_metrics,channel=rx\\ 0,frequency=145500000 power_db=-92.5 1000000000\n");
+        let event = Event::new("sensor").with("name", "lms7_temp").with("value", "45.5");
+        assert!(format_line(&event).unwrap().starts_with("sdrglue_sensor,name=lms7_temp value=45.5 "));
+        assert!(format_line(&Event::new("sensor").with("name", "clock_locked").with("value", "true")).is_none());
+    }
+}
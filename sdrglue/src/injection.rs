@@ -0,0 +1,90 @@
+//! Synthetic test signals added to the received signal.
+//!
+//! Test signals can be used to check channel configuration
+//! and level calibration without an external signal generator.
+
+use crate::{Sample, ComplexSample};
+
+/// Frequency of the modulating tone in Hertz.
+const MODULATION_FREQUENCY: f64 = 1000.0;
+/// Peak deviation for FM in Hertz.
+const FM_DEVIATION: f64 = 3000.0;
+/// Modulation depth for AM.
+const AM_DEPTH: f64 = 0.5;
+
+#[derive(Copy, Clone)]
+pub enum TestModulation {
+    /// Unmodulated carrier.
+    Tone,
+    /// Carrier amplitude modulated by a 1 kHz tone.
+    Am,
+    /// Carrier frequency modulated by a 1 kHz tone.
+    Fm,
+}
+
+impl TestModulation {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.to_uppercase().as_str() {
+            "TONE" => Some(TestModulation::Tone),
+            "AM"   => Some(TestModulation::Am),
+            "FM"   => Some(TestModulation::Fm),
+            _ => None,
+        }
+    }
+}
+
+pub struct TestSignal {
+    modulation: TestModulation,
+    /// Carrier amplitude.
+    amplitude: f64,
+    /// Carrier phase in cycles.
+    phase: f64,
+    /// Carrier phase increment per sample in cycles.
+    phase_increment: f64,
+    /// Modulating tone phase in cycles.
+    modulation_phase: f64,
+    /// Modulating tone phase increment per sample in cycles.
+    modulation_phase_increment: f64,
+    /// FM deviation in cycles per sample.
+    deviation: f64,
+}
+
+impl TestSignal {
+    /// Create a test signal at given frequency
+    /// for signal with given sample rate and center frequency.
+    /// Level is the carrier power in dB relative to full scale,
+    /// on the same scale as channel power in squelch levels.
+    pub fn new(
+        sample_rate: f64,
+        center_frequency: f64,
+        frequency: f64,
+        level: Sample,
+        modulation: TestModulation,
+    ) -> Self {
+        Self {
+            modulation,
+            amplitude: 10.0f64.powf(level as f64 / 20.0),
+            phase: 0.0,
+            phase_increment: (frequency - center_frequency) / sample_rate,
+            modulation_phase: 0.0,
+            modulation_phase_increment: MODULATION_FREQUENCY / sample_rate,
+            deviation: FM_DEVIATION / sample_rate,
+        }
+    }
+
+    /// Add the signal to samples.
+    pub fn add_to(&mut self, samples: &mut [ComplexSample]) {
+        use std::f64::consts::TAU;
+        for sample in samples.iter_mut() {
+            let modulating = (TAU * self.modulation_phase).sin();
+            self.modulation_phase = (self.modulation_phase + self.modulation_phase_increment).fract();
+            let (amplitude, increment) = match self.modulation {
+                TestModulation::Tone => (self.amplitude, self.phase_increment),
+                TestModulation::Am => (self.amplitude * (1.0 + AM_DEPTH * modulating), self.phase_increment),
+                TestModulation::Fm => (self.amplitude, self.phase_increment + self.deviation * modulating),
+            };
+            *sample += ComplexSample::from_polar(amplitude as Sample, (TAU * self.phase) as Sample);
+            self.phase = (self.phase + increment).fract();
+        }
+    }
+}
@@ -0,0 +1,218 @@
+//! Transmit safety interlock.
+//!
+//! With --tx-interlock, a transmit channel only radiates if
+//! its frequency is within a range allowed for it by --tx-allow
+//! and its output power does not exceed the limit of that range.
+//! Channels without any allowed range never transmit.
+//!
+//! With --tx-arm-timeout, transmitting also has to be armed
+//! using the control API and is disarmed automatically
+//! after the timeout. Arming uses a one-time challenge,
+//! so that a recorded arm command cannot be replayed later.
+//! The arm command has to include the HMAC-SHA256 of the challenge
+//! keyed with --tx-arm-key as hexadecimal, so that only those
+//! knowing the key can arm, without sending the key itself:
+//! ```text
+//! {"command":"arm_challenge"}
+//! {"command":"arm","challenge":"<value from arm_challenge>","response":"<HMAC>","duration":60}
+//! {"command":"disarm"}
+//! ```
+//! For example, the response can be calculated with
+//! `printf %s CHALLENGE | openssl dgst -sha256 -hmac KEY`.
+//!
+//! Output actions which may key transmitters or amplifiers
+//! are also refused through the control API unless armed.
+
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::{Sample, ComplexSample};
+use crate::auth;
+use crate::configuration;
+use crate::events;
+
+/// How long a challenge can be used for arming.
+const CHALLENGE_LIFETIME: Duration = Duration::from_secs(30);
+
+/// Frequency range a channel is allowed to transmit on.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AllowedRange {
+    pub start: f64,
+    pub end: f64,
+    /// Maximum mean output power of the channel in dBFS.
+    pub max_power_db: Sample,
+}
+
+impl AllowedRange {
+    /// Parse "start:end" or "start:end:max_power_db".
+    pub fn parse(text: &str) -> Option<Self> {
+        let mut values = text.split(':').map(|value| value.parse::<f64>().ok());
+        let start = values.next()??;
+        let end = values.next()??;
+        let max_power_db = values.next().unwrap_or(Some(0.0))? as Sample;
+        (values.next().is_none() && start <= end).then_some(Self { start, end, max_power_db })
+    }
+}
+
+pub struct Interlock {
+    /// Power limit of each channel, or None if the channel
+    /// is not allowed to transmit at its frequency.
+    limits: Vec<Option<Sample>>,
+    /// Whether each channel was blocked in the latest block,
+    /// used to emit events on changes.
+    blocked: Vec<bool>,
+    /// Maximum arming duration. None if arming is not required.
+    arm_timeout: Option<Duration>,
+    /// Key for responses to arming challenges.
+    arm_key: String,
+    armed_until: Option<Instant>,
+    /// Challenge given for the next arm command and its expiry time.
+    challenge: Option<(String, Instant)>,
+}
+
+/// Find allowed ranges given for a transmit channel.
+fn channel_ranges(cli: &configuration::Cli, index: usize) -> Result<Vec<AllowedRange>, String> {
+    let name = format!("tx{}", index);
+    cli.tx_allow.chunks_exact(2).filter(|args| args[0] == name)
+        .flat_map(|args| args[1].split(','))
+        .map(|range| AllowedRange::parse(range).ok_or_else(|| format!("Invalid allowed range {}", range)))
+        .collect()
+}
+
+/// Random value for a challenge.
+fn random_challenge() -> String {
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u128(SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).map_or(0, |t| t.as_nanos()));
+    format!("{:016x}", hasher.finish())
+}
+
+impl Interlock {
+    /// Create the interlock for transmit channels at given frequencies,
+    /// or return None if --tx-interlock is not used.
+    pub fn from_cli(cli: &configuration::Cli, frequencies: &[f64]) -> Result<Option<Self>, String> {
+        if !cli.tx_interlock {
+            return Ok(None);
+        }
+        let limits: Vec<Option<Sample>> = frequencies.iter().enumerate().map(|(index, &frequency)| {
+            let limit = channel_ranges(cli, index)?.iter()
+                .filter(|range| frequency >= range.start && frequency <= range.end)
+                .map(|range| range.max_power_db)
+                .reduce(Sample::max);
+            if limit.is_none() {
+                eprintln!("Transmit channel tx{} at {} Hz is not in any allowed range and will not transmit",
+                    index, frequency);
+            }
+            Ok(limit)
+        }).collect::<Result<_, String>>()?;
+        if cli.tx_arm_timeout.is_some() && cli.tx_arm_key.is_none() {
+            return Err("--tx-arm-timeout requires --tx-arm-key".to_string());
+        }
+        Ok(Some(Self {
+            blocked: vec![false; limits.len()],
+            limits,
+            arm_timeout: cli.tx_arm_timeout.map(Duration::from_secs_f64),
+            arm_key: cli.tx_arm_key.clone().unwrap_or_default(),
+            armed_until: None,
+            challenge: None,
+        }))
+    }
+
+    /// Whether transmitting is currently armed.
+    pub fn armed(&self) -> bool {
+        self.arm_timeout.is_none() || self.armed_until.is_some_and(|until| Instant::now() < until)
+    }
+
+    /// Create a new challenge to be used in the next arm command.
+    pub fn challenge(&mut self) -> String {
+        let challenge = random_challenge();
+        self.challenge = Some((challenge.clone(), Instant::now() + CHALLENGE_LIFETIME));
+        challenge
+    }
+
+    /// Arm transmitting for given duration in seconds,
+    /// limited to the arming timeout, if the response is
+    /// the HMAC of the challenge keyed with the arming key.
+    /// The challenge is used up whether arming succeeds or not.
+    /// Return the duration armed for.
+    pub fn arm(&mut self, challenge: &str, response: &str, duration: Option<f64>) -> Result<f64, String> {
+        let timeout = self.arm_timeout.ok_or("Arming is not required")?;
+        match self.challenge.take() {
+            Some((expected, expiry)) if expected == challenge && Instant::now() < expiry => {},
+            _ => return Err("Invalid or expired challenge".to_string()),
+        }
+        let expected = auth::hmac_sha256_hex(self.arm_key.as_bytes(), challenge.as_bytes());
+        if !auth::secret_matches(&response.to_ascii_lowercase(), &expected) {
+            return Err("Wrong response to challenge".to_string());
+        }
+        let duration = duration.map_or(timeout, |d| Duration::from_secs_f64(d.max(0.0).min(timeout.as_secs_f64())));
+        self.armed_until = Some(Instant::now() + duration);
+        events::emit(events::Event::new("tx_armed").with("duration", duration.as_secs_f64()));
+        Ok(duration.as_secs_f64())
+    }
+
+    pub fn disarm(&mut self) {
+        if self.armed_until.take().is_some() {
+            events::emit(events::Event::new("tx_disarmed"));
+        }
+    }
+
+    /// Check whether a block of channel output may be transmitted.
+    pub fn permits(&mut self, index: usize, frequency: f64, samples: &[ComplexSample]) -> bool {
+        let power = samples.iter().map(|s| s.norm_sqr()).sum::<Sample>() / samples.len().max(1) as Sample;
+        let power_db = 10.0 * (power + 1e-20).log10();
+        let reason = match self.limits.get(index).copied().flatten() {
+            None => Some("frequency"),
+            Some(_) if !self.armed() => Some("not_armed"),
+            Some(limit) if power_db > limit => Some("power"),
+            Some(_) => None,
+        };
+        let blocked = reason.is_some();
+        // Channels not allowed at their frequency are reported
+        // at startup, so only report changes of other reasons.
+        if blocked != self.blocked[index] && reason != Some("frequency") {
+            let mut event = events::Event::new(if blocked { "tx_blocked" } else { "tx_unblocked" })
+                .with("channel", format!("tx{}", index))
+                .with("frequency", frequency);
+            if let Some(reason) = reason {
+                event = event.with("reason", reason).with("power_db", power_db);
+            }
+            events::emit(event);
+        }
+        self.blocked[index] = blocked;
+        !blocked
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+    use super::*;
+
+    #[test]
+    fn test_interlock() {
+        let cli = configuration::Cli::parse_from(["sdrglue", "--tx-interlock",
+            "--tx-allow", "tx0", "144e6:146e6:-10,430e6:440e6",
+            "--tx-arm-timeout", "10", "--tx-arm-key", "Jefe"]);
+        let mut interlock = Interlock::from_cli(&cli, &[145e6, 435e6]).unwrap().unwrap();
+        let quiet = [ComplexSample::new(0.1, 0.0); 10];
+        let loud = [ComplexSample::new(0.5, 0.0); 10];
+        // Not armed yet
+        assert!(!interlock.permits(0, 145e6, &quiet));
+        assert!(interlock.arm("wrong", "", None).is_err());
+        let challenge = interlock.challenge();
+        assert!(interlock.arm(&challenge, &auth::hmac_sha256_hex(b"wrong", challenge.as_bytes()), None).is_err());
+        assert!(!interlock.armed());
+        let challenge = interlock.challenge();
+        let response = auth::hmac_sha256_hex(b"Jefe", challenge.as_bytes());
+        assert_eq!(interlock.arm(&challenge, &response, Some(60.0)), Ok(10.0));
+        assert!(interlock.permits(0, 145e6, &quiet));
+        assert!(!interlock.permits(0, 145e6, &loud));
+        // Channel without allowed ranges
+        assert!(!interlock.permits(1, 435e6, &quiet));
+        // Challenge cannot be used again
+        assert!(interlock.arm(&challenge, &response, None).is_err());
+        interlock.disarm();
+        assert!(!interlock.permits(0, 145e6, &quiet));
+    }
+}
@@ -0,0 +1,119 @@
+//! JACK client with ports for channels.
+//!
+//! All channels share one client named "sdrglue",
+//! created when the first port is registered.
+//! Audio is passed between signal processing and the JACK
+//! process callback through FIFOs, so a late block on either
+//! side only causes a short gap instead of blocking the other.
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+use crate::Sample;
+
+/// Sample rate the JACK server has to run at.
+pub const SAMPLE_RATE: f64 = 48000.0;
+/// Maximum amount of audio buffered in a FIFO, in samples.
+/// If more arrives than is consumed, the oldest audio is dropped
+/// to keep latency bounded.
+const MAX_BUFFERED: usize = 9600;
+
+/// Audio passed between a port and a channel processor.
+pub type AudioFifo = Arc<Mutex<VecDeque<Sample>>>;
+
+#[derive(Default)]
+struct Ports {
+    outputs: Vec<(jack::Port<jack::AudioOut>, AudioFifo)>,
+    inputs: Vec<(jack::Port<jack::AudioIn>, AudioFifo)>,
+}
+
+struct Handler {
+    ports: Arc<Mutex<Ports>>,
+}
+
+impl jack::ProcessHandler for Handler {
+    fn process(&mut self, _: &jack::Client, scope: &jack::ProcessScope) -> jack::Control {
+        // Never wait in the real-time thread.
+        // If ports are being registered, skip this period.
+        let Ok(mut ports) = self.ports.try_lock() else {
+            return jack::Control::Continue;
+        };
+        for (port, fifo) in ports.outputs.iter_mut() {
+            let output = port.as_mut_slice(scope);
+            match fifo.try_lock() {
+                Ok(mut fifo) => for sample in output.iter_mut() {
+                    // Fill gaps in audio with silence.
+                    *sample = fifo.pop_front().unwrap_or(0.0);
+                },
+                Err(_) => output.fill(0.0),
+            }
+        }
+        for (port, fifo) in ports.inputs.iter() {
+            if let Ok(mut fifo) = fifo.try_lock() {
+                push_audio(&mut fifo, port.as_slice(scope).iter().copied());
+            }
+        }
+        jack::Control::Continue
+    }
+}
+
+struct Client {
+    client: jack::AsyncClient<(), Handler>,
+    ports: Arc<Mutex<Ports>>,
+}
+
+static CLIENT: Mutex<Option<Client>> = Mutex::new(None);
+
+/// Run a function with the client, creating it if needed.
+fn with_client<T>(f: impl FnOnce(&Client) -> T) -> T {
+    let mut client = CLIENT.lock().unwrap();
+    let client = client.get_or_insert_with(|| {
+        // TODO: handle errors more nicely
+        let (client, _) = jack::Client::new("sdrglue", jack::ClientOptions::NO_START_SERVER)
+            .unwrap_or_else(|e| panic!("Could not connect to JACK server: {}", e));
+        if client.sample_rate() as f64 != SAMPLE_RATE {
+            panic!("JACK server has to run at {} Hz sample rate", SAMPLE_RATE);
+        }
+        let ports = Arc::new(Mutex::new(Ports::default()));
+        let client = client.activate_async((), Handler { ports: ports.clone() })
+            .unwrap_or_else(|e| panic!("Could not activate JACK client: {}", e));
+        Client { client, ports }
+    });
+    f(client)
+}
+
+/// Append audio to a FIFO, dropping the oldest audio if it is full.
+pub fn push_audio(fifo: &mut VecDeque<Sample>, audio: impl Iterator<Item = Sample>) {
+    for sample in audio {
+        if fifo.len() >= MAX_BUFFERED {
+            fifo.pop_front();
+        }
+        fifo.push_back(sample);
+    }
+}
+
+/// Register an output port and return the FIFO
+/// to write its audio into.
+pub fn output_port(name: &str) -> AudioFifo {
+    with_client(|client| {
+        // TODO: handle errors more nicely
+        let port = client.client.as_client().register_port(name, jack::AudioOut::default())
+            .unwrap_or_else(|e| panic!("Could not register JACK port {}: {}", name, e));
+        let fifo = AudioFifo::default();
+        client.ports.lock().unwrap().outputs.push((port, fifo.clone()));
+        fifo
+    })
+}
+
+/// Register an input port and return the FIFO
+/// to read its audio from.
+pub fn input_port(name: &str) -> AudioFifo {
+    with_client(|client| {
+        // TODO: handle errors more nicely
+        let port = client.client.as_client().register_port(name, jack::AudioIn::default())
+            .unwrap_or_else(|e| panic!("Could not register JACK port {}: {}", name, e));
+        let fifo = AudioFifo::default();
+        client.ports.lock().unwrap().inputs.push((port, fifo.clone()));
+        fifo
+    })
+}
@@ -0,0 +1,39 @@
+//! Signal processing parts of sdrglue that can be used as a library:
+//! fast-convolution filter banks, other filters and demodulators.
+//! The filter banks can also be used from C with the ffi feature.
+//! Without default features, the library does not need SoapySDR
+//! and can be compiled to WebAssembly, where the wasm feature
+//! adds bindings for JavaScript.
+//!
+//! Filters and demodulators only need core and alloc, so they
+//! can be built for targets without std by disabling the std
+//! feature and enabling libm for floating point math:
+//! ```text
+//! cargo rustc --lib --crate-type rlib --no-default-features --features libm --target aarch64-unknown-none
+//! ```
+//! The filter banks need std, since rustfft does.
+
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
+
+#[cfg(not(any(feature = "std", feature = "libm")))]
+compile_error!("Either std or libm feature is needed for floating point math.");
+
+extern crate alloc;
+
+pub use num_complex;
+pub use num_traits;
+/// Floating point type used for signal processing.
+pub type Sample = f32;
+/// Complex floating point type used for signal processing.
+pub type ComplexSample = num_complex::Complex<Sample>;
+/// Mathematical consts for the Sample type.
+pub use core::f32::consts as sample_consts;
+
+#[cfg(feature = "std")]
+pub mod fcfb;
+pub mod filter;
+pub mod demodulation;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+#[cfg(feature = "wasm")]
+pub mod wasm;
@@ -0,0 +1,412 @@
+//! Digital loopback from transmit to receive processing.
+//!
+//! Instead of using an SDR, transmitted signal is fed back
+//! to received signal through a simulated radio channel
+//! with configurable impairments: additive white Gaussian noise,
+//! frequency offset, multipath, Rayleigh fading and lost samples.
+//! This allows testing the whole signal processing chain,
+//! and robustness of demodulators, without any hardware.
+//!
+//! With --loopback-snr, loopback is run once for each
+//! signal-to-noise ratio and bit error rate of BER test
+//! channels is written as CSV, giving a BER versus SNR curve.
+
+use std::collections::VecDeque;
+use std::io::Write;
+use std::time::{Duration, Instant};
+
+use crate::{Sample, ComplexSample};
+use crate::clock;
+use crate::configuration;
+use crate::control;
+use crate::graph;
+use crate::health;
+use crate::rx_dsp::RxDsp;
+use crate::rxthings;
+use crate::schedule;
+use crate::sigmf;
+use crate::tx_dsp::TxDsp;
+
+/// Number of sinusoids used to simulate Rayleigh fading.
+const FADING_OSCILLATORS: usize = 8;
+
+/// Small pseudorandom number generator for noise and fading.
+/// Statistical quality is sufficient for simulating noise,
+/// and this avoids an additional dependency.
+pub struct Random {
+    state: u64,
+}
+
+impl Random {
+    pub fn new(seed: u64) -> Self {
+        Self { state: seed | 1 }
+    }
+
+    /// Uniformly distributed number in range (0, 1].
+    pub fn uniform(&mut self) -> f64 {
+        // xorshift64*
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        let value = self.state.wrapping_mul(0x2545F4914F6CDD1D);
+        ((value >> 11) + 1) as f64 / (1u64 << 53) as f64
+    }
+
+    /// Complex Gaussian number with unit variance.
+    fn complex_gaussian(&mut self) -> ComplexSample {
+        // Box-Muller transform
+        let r = (-self.uniform().ln()).sqrt();
+        let phase = 2.0 * std::f64::consts::PI * self.uniform();
+        ComplexSample::new((r * phase.cos()) as Sample, (r * phase.sin()) as Sample)
+    }
+}
+
+/// Time-varying complex gain with Rayleigh distributed amplitude,
+/// simulated as a sum of sinusoids with random Doppler shifts.
+struct Fading {
+    /// Phase and phase increment per sample of each oscillator, in radians.
+    oscillators: Vec<(f64, f64)>,
+}
+
+impl Fading {
+    fn new(random: &mut Random, doppler: f64, sample_rate: f64) -> Self {
+        Self {
+            oscillators: (0..FADING_OSCILLATORS).map(|_| {
+                let angle = 2.0 * std::f64::consts::PI * random.uniform();
+                (
+                    2.0 * std::f64::consts::PI * random.uniform(),
+                    2.0 * std::f64::consts::PI * doppler * angle.cos() / sample_rate,
+                )
+            }).collect(),
+        }
+    }
+
+    /// Return gain for the next sample.
+    fn next(&mut self) -> ComplexSample {
+        let mut gain = ComplexSample::ZERO;
+        for (phase, increment) in self.oscillators.iter_mut() {
+            gain += ComplexSample::from_polar(1.0, *phase as Sample);
+            *phase = (*phase + *increment) % (2.0 * std::f64::consts::PI);
+        }
+        gain / (FADING_OSCILLATORS as Sample).sqrt()
+    }
+}
+
+struct Path {
+    /// Delay in samples.
+    delay: usize,
+    gain: Sample,
+    fading: Option<Fading>,
+}
+
+/// Simulated radio channel.
+pub struct ChannelSimulator {
+    paths: Vec<Path>,
+    /// Previous input samples for multipath delays.
+    history: VecDeque<ComplexSample>,
+    /// Frequency shift in radians per sample.
+    shift: f64,
+    shift_phase: f64,
+    /// Noise amplitude.
+    noise: Option<Sample>,
+    random: Random,
+}
+
+impl ChannelSimulator {
+    pub fn from_cli(cli: &configuration::Cli, sample_rate: f64, frequency_shift: f64) -> Self {
+        let mut random = Random::new(cli.seed);
+        let doppler = cli.loopback_fading;
+        // TODO: handle errors more nicely
+        let taps: Vec<(f64, Sample)> = match &cli.loopback_multipath {
+            Some(taps) => taps.split(',').map(|tap| {
+                let (delay, gain) = tap.split_once(':').unwrap_or_else(|| panic!("Invalid multipath tap {}", tap));
+                (delay.parse().unwrap(), gain.parse().unwrap())
+            }).collect(),
+            None => vec![(0.0, 0.0)],
+        };
+        let paths: Vec<Path> = taps.iter().map(|&(delay, gain_db)| Path {
+            delay: (delay * 1e-6 * sample_rate).round() as usize,
+            gain: (10.0 as Sample).powf(gain_db / 20.0),
+            fading: doppler.map(|doppler| Fading::new(&mut random, doppler, sample_rate)),
+        }).collect();
+        let max_delay = paths.iter().map(|path| path.delay).max().unwrap_or(0);
+        Self {
+            paths,
+            history: std::iter::repeat_n(ComplexSample::ZERO, max_delay + 1).collect(),
+            shift: 2.0 * std::f64::consts::PI * (frequency_shift + cli.loopback_frequency_offset) / sample_rate,
+            shift_phase: 0.0,
+            noise: cli.loopback_noise.map(|level| 10.0f64.powf(level / 20.0) as Sample),
+            random,
+        }
+    }
+
+    /// Set white Gaussian noise to given total power in dB relative to full scale.
+    pub fn set_noise(&mut self, level: f64) {
+        self.noise = Some(10.0f64.powf(level / 20.0) as Sample);
+    }
+
+    /// Pass signal through the channel, appending result to output.
+    pub fn process(&mut self, input: &[ComplexSample], output: &mut VecDeque<ComplexSample>) {
+        for &sample in input {
+            self.history.pop_back();
+            self.history.push_front(sample);
+            let mut out = ComplexSample::ZERO;
+            for path in self.paths.iter_mut() {
+                let mut value = self.history[path.delay] * path.gain;
+                if let Some(fading) = &mut path.fading {
+                    value *= fading.next();
+                }
+                out += value;
+            }
+            out *= ComplexSample::from_polar(1.0, self.shift_phase as Sample);
+            self.shift_phase = (self.shift_phase + self.shift) % (2.0 * std::f64::consts::PI);
+            if let Some(noise) = self.noise {
+                out += self.random.complex_gaussian() * noise;
+            }
+            output.push_back(out);
+        }
+    }
+}
+
+/// Simulated gaps in received signal, like those caused by SDR overflows.
+pub struct SampleDrops {
+    /// Probability of a gap starting at each sample.
+    probability: f64,
+    /// Number of samples lost in each gap.
+    length: usize,
+    random: Random,
+}
+
+impl SampleDrops {
+    /// Parse gaps per second and gap length in milliseconds
+    /// separated by a colon.
+    pub fn parse(text: &str, sample_rate: f64, seed: u64) -> Option<Self> {
+        let (rate, length) = text.split_once(':')?;
+        let rate: f64 = rate.parse().ok()?;
+        let length: f64 = length.parse().ok()?;
+        Some(Self {
+            probability: rate / sample_rate,
+            length: (length * 1e-3 * sample_rate).round() as usize,
+            // Use a different sequence than noise and fading,
+            // so adding gaps does not change them.
+            random: Random::new(seed ^ 0x9e37_79b9_7f4a_7c15),
+        })
+    }
+
+    /// Decide whether samples are lost within a block of given length.
+    /// Return the position in the block and number of samples lost.
+    pub fn next(&mut self, block_length: usize) -> Option<(usize, usize)> {
+        if self.random.uniform() >= self.probability * block_length as f64 {
+            return None;
+        }
+        let position = ((self.random.uniform() * block_length as f64) as usize).min(block_length - 1);
+        Some((position, self.length))
+    }
+}
+
+/// Feed one block of transmitted signal through the simulated channel
+/// to receive processing. Return the number of samples processed.
+fn process_block(
+    rx_dsp: &mut RxDsp,
+    tx_dsp: &mut TxDsp,
+    simulator: &mut ChannelSimulator,
+    drops: Option<&mut SampleDrops>,
+    buffer: &mut VecDeque<ComplexSample>,
+) -> usize {
+    let input = rx_dsp.prepare_input_buffer();
+    let n = input.len();
+    let drop = drops.and_then(|drops| drops.next(n));
+    let needed = n + drop.map_or(0, |(_, length)| length);
+    while buffer.len() < needed {
+        simulator.process(tx_dsp.process(), buffer);
+    }
+    if let Some((position, length)) = drop {
+        buffer.drain(position..position + length);
+    }
+    for (sample, value) in input.iter_mut().zip(buffer.drain(..n)) {
+        *sample = value;
+    }
+    if drop.is_some() {
+        rx_dsp.discontinuity();
+    }
+    rx_dsp.process();
+    n
+}
+
+/// Loopback sample rate and receive and transmit center frequencies.
+fn parameters(cli: &configuration::Cli) -> (f64, f64, f64) {
+    // TODO: handle errors more nicely
+    let sample_rate = cli.sdr_rx_fs.expect("Loopback requires --sdr-rx-fs");
+    let rx_frequency = cli.sdr_rx_freq.expect("Loopback requires --sdr-rx-freq");
+    let tx_frequency = cli.sdr_tx_freq.expect("Loopback requires --sdr-tx-freq");
+    if cli.sdr_tx_fs.is_some_and(|fs| fs != sample_rate) {
+        panic!("Loopback requires equal receive and transmit sample rates");
+    }
+    (sample_rate, rx_frequency, tx_frequency)
+}
+
+/// Run loopback for --loopback-duration at each --loopback-snr
+/// as fast as possible and write bit error rates as CSV.
+fn sweep_snr(cli: &configuration::Cli, fft_planner: &mut rustfft::FftPlanner<Sample>, snrs: &str) -> Result<(), String> {
+    let (sample_rate, rx_frequency, tx_frequency) = parameters(cli);
+    // TODO: handle errors more nicely
+    let duration = cli.loopback_duration.expect("--loopback-snr requires --loopback-duration");
+    let snrs: Vec<f64> = snrs.split(',')
+        .map(|snr| snr.trim().parse().unwrap_or_else(|_| panic!("Invalid --loopback-snr {}", snr)))
+        .collect();
+    let mut output: Box<dyn Write> = match &cli.loopback_snr_csv {
+        Some(path) => Box::new(std::fs::File::create(path)
+            .unwrap_or_else(|err| panic!("Could not create {}: {}", path, err))),
+        None => Box::new(std::io::stdout()),
+    };
+    writeln!(output, "snr_db,ber,bits,errors").unwrap();
+
+    // Measure transmitted power to set noise level from SNR.
+    // Processing is restarted for each SNR, so that receivers
+    // synchronize again and counts are not mixed.
+    let mut tx_dsp = TxDsp::new(fft_planner, cli, sample_rate, tx_frequency)?;
+    let mut energy = 0.0;
+    let mut samples = 0;
+    while (samples as f64) < sample_rate {
+        let signal = tx_dsp.process();
+        energy += signal.iter().map(|sample| sample.norm_sqr() as f64).sum::<f64>();
+        samples += signal.len();
+    }
+    let signal_db = 10.0 * (energy / samples as f64).log10();
+
+    for snr in snrs {
+        let mut rx_dsp = RxDsp::new(fft_planner, cli, sample_rate, rx_frequency)?;
+        let mut tx_dsp = TxDsp::new(fft_planner, cli, sample_rate, tx_frequency)?;
+        let mut simulator = ChannelSimulator::from_cli(cli, sample_rate, tx_frequency - rx_frequency);
+        simulator.set_noise(signal_db - snr);
+        let mut drops = cli.loopback_drops.as_ref().map(|drops|
+            SampleDrops::parse(drops, sample_rate, cli.seed)
+                .unwrap_or_else(|| panic!("Invalid --loopback-drops {}", drops)));
+        let mut buffer = VecDeque::new();
+        rxthings::ber_test::take_totals();
+        let mut samples_processed: u64 = 0;
+        while (samples_processed as f64) < duration * sample_rate {
+            health::heartbeat();
+            samples_processed += process_block(&mut rx_dsp, &mut tx_dsp, &mut simulator, drops.as_mut(), &mut buffer) as u64;
+        }
+        let (bits, errors) = rxthings::ber_test::take_totals();
+        let ber = if bits > 0 { errors as f64 / bits as f64 } else { f64::NAN };
+        writeln!(output, "{},{},{},{}", snr, ber, bits, errors).unwrap();
+    }
+    Ok(())
+}
+
+/// Run transmit and receive processing in digital loopback
+/// until interrupted or --loopback-duration has passed,
+/// paced to real time unless in reproducible mode.
+/// Returns an error if channels are invalid.
+pub fn run(cli: &configuration::Cli, fft_planner: &mut rustfft::FftPlanner<Sample>) -> Result<(), String> {
+    if let Some(snrs) = &cli.loopback_snr {
+        health::stream_status(true);
+        return sweep_snr(cli, fft_planner, snrs);
+    }
+    let (sample_rate, rx_frequency, tx_frequency) = parameters(cli);
+
+    let reproducible = clock::reproducible();
+    let mut control = if reproducible {
+        if cli.control.is_some() {
+            eprintln!("Control API is not available in reproducible mode");
+        }
+        None
+    } else {
+        control::Control::from_cli(cli)?
+    };
+    let mut rx_dsp = RxDsp::new(fft_planner, cli, sample_rate, rx_frequency)?;
+    let mut tx_dsp = TxDsp::new(fft_planner, cli, sample_rate, tx_frequency)?;
+    if cli.dump_graph.is_some() {
+        graph::dump(cli, Some(&rx_dsp), Some(&tx_dsp));
+        return Ok(());
+    }
+    if let Some(path) = &cli.rx_sigmf {
+        rx_dsp.record_input(sigmf::SigmfRecorder::start(path, sample_rate, rx_frequency, Some("loopback")));
+    }
+    let mut simulator = ChannelSimulator::from_cli(cli, sample_rate, tx_frequency - rx_frequency);
+    let mut schedule = schedule::Schedule::from_args(&cli.schedule);
+    // TODO: handle errors more nicely
+    let mut drops = cli.loopback_drops.as_ref().map(|drops|
+        SampleDrops::parse(drops, sample_rate, cli.seed)
+            .unwrap_or_else(|| panic!("Invalid --loopback-drops {}", drops)));
+
+    let mut buffer = VecDeque::new();
+    let start = Instant::now();
+    let mut samples_processed: u64 = 0;
+    // The simulated radio channel always works.
+    health::stream_status(true);
+    loop {
+        health::heartbeat();
+        schedule.update(Some(&mut rx_dsp), Some(&mut tx_dsp));
+        if let Some(control) = &mut control {
+            control.poll(control::Context {
+                rx_dsp: Some(&mut rx_dsp),
+                tx_dsp: Some(&mut tx_dsp),
+                ..Default::default()
+            });
+        }
+
+        let n = process_block(&mut rx_dsp, &mut tx_dsp, &mut simulator, drops.as_mut(), &mut buffer);
+        samples_processed += n as u64;
+        let processed = samples_processed as f64 / sample_rate;
+        clock::set_elapsed(processed);
+        if cli.loopback_duration.is_some_and(|duration| processed >= duration) {
+            break;
+        }
+
+        // Wait to keep processing in real time,
+        // since outputs like audio streams expect that.
+        let elapsed = start.elapsed();
+        let target = Duration::from_secs_f64(processed);
+        if !reproducible && target > elapsed {
+            std::thread::sleep(target - elapsed);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sample_drops() {
+        let sample_rate = 48000.0;
+        let mut drops = SampleDrops::parse("2:10", sample_rate, 1).unwrap();
+        let block_length = 1000;
+        let seconds = 1000;
+        let gaps: Vec<(usize, usize)> = (0..seconds * sample_rate as usize / block_length)
+            .filter_map(|_| drops.next(block_length)).collect();
+        assert!((gaps.len() as i32 - 2 * seconds as i32).abs() < 200, "{} gaps", gaps.len());
+        assert!(gaps.iter().all(|&(position, length)| position < block_length && length == 480));
+        assert!(SampleDrops::parse("2", sample_rate, 1).is_none());
+    }
+
+    #[test]
+    fn test_noise_power() {
+        let mut random = Random::new(1);
+        let n = 100000;
+        let mean = (0..n).map(|_| random.complex_gaussian()).sum::<ComplexSample>() / n as Sample;
+        let mut random = Random::new(1);
+        let power = (0..n).map(|_| random.complex_gaussian().norm_sqr()).sum::<Sample>() / n as Sample;
+        assert!(mean.norm() < 0.01);
+        assert!((power - 1.0).abs() < 0.02);
+    }
+
+    #[test]
+    fn test_seed() {
+        use clap::Parser;
+        let simulate = |seed: &str| {
+            let cli = configuration::Cli::parse_from(["sdrglue", "--loopback-noise", "-20",
+                "--loopback-fading", "10", "--seed", seed]);
+            let mut simulator = ChannelSimulator::from_cli(&cli, 48000.0, 0.0);
+            let mut output = VecDeque::new();
+            simulator.process(&[ComplexSample::new(1.0, 0.0); 1000], &mut output);
+            output
+        };
+        assert_eq!(simulate("1"), simulate("1"));
+        assert_ne!(simulate("1"), simulate("2"));
+    }
+}
@@ -1,88 +1,325 @@
 
-pub use rustfft::num_complex as num_complex;
-pub use rustfft::num_traits as num_traits;
-/// Floating point type used for signal processing.
-pub type Sample = f32;
-/// Complex floating point type used for signal processing.
-pub type ComplexSample = num_complex::Complex<Sample>;
-/// Mathematical consts for the Sample type.
-pub use std::f32::consts as sample_consts;
+// Signal processing library parts are used through the crate root
+// the same way as modules of the program itself.
+pub use sdrglue::{num_complex, num_traits, Sample, ComplexSample, sample_consts};
+pub use sdrglue::{fcfb, filter, demodulation};
 
+mod activity;
+mod afc;
+mod auth;
 mod configuration;
-use configuration::Parser;
-mod fcfb;
-mod filter;
+mod clock;
+mod calibration;
+mod control;
+mod converter;
+#[cfg(feature = "sqlite")]
+mod database;
+mod diagnostics;
+mod events;
+mod graph;
+mod health;
+mod federation;
+#[cfg(test)]
+mod fuzz;
+mod hopping;
+mod http_api;
+mod import;
+mod watchlist;
 mod rx_dsp;
 mod tx_dsp;
+mod tx_limits;
 mod rxthings;
+mod injection;
+mod interlock;
+mod influx;
+mod loopback;
+mod metrics;
+mod occupancy;
+mod placement;
+mod recommend;
+mod schedule;
+mod templates;
+mod tone_remote;
+mod sensors;
 mod txthings;
 mod soapyconfig;
+mod station_id;
+mod switching;
+mod usrp;
+mod prbs;
+mod pipewire;
+mod sigmf;
+mod remote;
+mod rssi;
+#[cfg(feature = "mumble")]
+mod mumble;
+#[cfg(feature = "icecast")]
+mod icecast;
+#[cfg(any(feature = "icecast", feature = "archive"))]
+mod ogg;
+#[cfg(feature = "jack")]
+mod jack;
+#[cfg(feature = "zmq")]
+mod zmq;
 
 
+/// Report an error in configuration and exit.
+fn exit_with_error(err: String) -> ! {
+    eprintln!("{}", err);
+    std::process::exit(1);
+}
+
 fn main() {
-    let cli = configuration::Cli::parse();
+    let mut cli = configuration::Cli::load().unwrap_or_else(|err| exit_with_error(err));
+    // Configuration as given, updated by control commands
+    // and saved in profiles.
+    let mut config = cli.clone();
+    let channels = templates::expand(&mut cli).unwrap_or_else(|err| exit_with_error(err));
+    if let Err(err) = rx_dsp::channel_list(&cli) {
+        exit_with_error(err);
+    }
+    if let Err(err) = tx_dsp::channel_frequencies(&cli) {
+        exit_with_error(err);
+    }
+    if cli.auto_tune || cli.recommend_parameters {
+        placement::apply(&mut cli);
+    }
+    if cli.recommend_parameters {
+        recommend::run(&cli, &mut rustfft::FftPlanner::new());
+        return;
+    }
+    let mut hopper = hopping::Hopper::from_cli(&cli);
+    if let Some(hopper) = &hopper {
+        cli.sdr_rx_freq = Some(hopper.first_frequency());
+    }
+
+    if cli.reproducible {
+        if !cli.loopback {
+            exit_with_error("Reproducible mode requires --loopback".to_string());
+        }
+        clock::set_reproducible();
+    }
+    events::init(&cli);
+    calibration::init(&cli);
+    if let Some(address) = &cli.health {
+        health::start(address);
+    }
 
     let mut fft_planner = rustfft::FftPlanner::new();
 
+    if cli.loopback {
+        loopback::run(&cli, &mut fft_planner).unwrap_or_else(|err| exit_with_error(err));
+        return;
+    }
+
+    let mut control = control::Control::from_cli(&cli).unwrap_or_else(|err| exit_with_error(err));
+
     let mut sdr = soapyconfig::SoapyIo::init(&cli).unwrap();
 
+    if let Some(path) = &cli.diagnose {
+        diagnostics::run(&cli, &mut fft_planner, &mut sdr, path);
+        return;
+    }
+
     let mut rx_dsp = if sdr.rx_enabled() {
         Some(rx_dsp::RxDsp::new(
             &mut fft_planner,
             &cli,
             sdr.rx_sample_rate().unwrap(),
             sdr.rx_center_frequency().unwrap()
-        ))
+        ).unwrap_or_else(|err| exit_with_error(err)))
     } else {
         None
     };
 
+    if let Some(rx_dsp) = &mut rx_dsp {
+        rx_dsp.discard_input(cli.rx_settle_time * 1e-3);
+    }
+    let mut band_outputs = switching::BandOutputs::from_cli(&cli);
+    let named_outputs = switching::NamedOutputs::from_cli(&cli);
+    if sdr.rx_enabled() {
+        let frequency = sdr.rx_center_frequency().unwrap();
+        band_outputs.update(&mut sdr, frequency);
+    }
+
+    if let Some(hopper) = &hopper {
+        hopper.check_channels(&cli, sdr.rx_sample_rate().unwrap());
+        if !cli.occupancy.is_empty() {
+            eprintln!("Occupancy scanning stops when retuning");
+        }
+    }
+
     let mut tx_dsp = if sdr.tx_enabled() {
         Some(tx_dsp::TxDsp::new(
             &mut fft_planner,
             &cli,
             sdr.tx_sample_rate().unwrap(),
             sdr.tx_center_frequency().unwrap()
-        ))
+        ).unwrap_or_else(|err| exit_with_error(err)))
     } else {
         None
     };
 
+    if cli.dump_graph.is_some() {
+        graph::dump(&cli, rx_dsp.as_ref(), tx_dsp.as_ref());
+        return;
+    }
+
+    if let (Some(rx_dsp), Some(path)) = (&mut rx_dsp, &cli.rx_sigmf) {
+        rx_dsp.record_input(sigmf::SigmfRecorder::start(
+            path,
+            sdr.rx_sample_rate().unwrap(),
+            sdr.rx_center_frequency().unwrap(),
+            Some(&sdr.hardware()),
+        ));
+    }
+
+    let mut tx_recorder = match (&tx_dsp, &cli.tx_record) {
+        (Some(tx_dsp), Some(path)) => {
+            let mut recorder = sigmf::SigmfRecorder::start(
+                path,
+                sdr.tx_sample_rate().unwrap(),
+                sdr.tx_center_frequency().unwrap(),
+                Some(&sdr.hardware()),
+            );
+            recorder.set_channels(tx_dsp.channel_info());
+            Some(recorder)
+        },
+        _ => None,
+    };
+
+    let mut schedule = schedule::Schedule::from_args(&cli.schedule);
+
+
+    let mut sensors = cli.sensor_interval.map(|interval| sensors::SensorMonitor::new(sdr.sensor_reader(), interval));
+
     let mut error_count = 0;
 
-    loop {
+    // Command line values are checked when parsing,
+    // but a profile may still have an invalid one.
+    let mut idle_interval = cli.idle_interval.map(|interval| std::time::Duration::try_from_secs_f64(interval)
+        .unwrap_or_else(|_| exit_with_error(format!("Invalid idle interval {}", interval))));
+    // Whether SDR streams have been deactivated
+    // because all channels are disabled.
+    let mut idle = false;
+
+    // Needed to convert sample counts to timestamps
+    let rx_sample_rate = sdr.rx_sample_rate().unwrap_or(1.0);
+
+    'main: loop {
+        health::heartbeat();
+        schedule.update(rx_dsp.as_mut(), tx_dsp.as_mut());
+        if let Some(sensors) = &mut sensors {
+            sensors.update();
+        }
+        if let Some(control) = &mut control {
+            control.poll(control::Context {
+                config: Some(&mut config),
+                rx_dsp: rx_dsp.as_mut(),
+                tx_dsp: tx_dsp.as_mut(),
+                sensors: sensors.as_ref(),
+                sdr: Some(&mut sdr),
+                outputs: Some(&named_outputs),
+                channels: &channels,
+            });
+        }
+
+        if let Some(interval) = idle_interval {
+            let channels_enabled =
+                rx_dsp.as_ref().is_some_and(|rx_dsp| rx_dsp.channels().any(|channel| channel.enabled)) ||
+                tx_dsp.as_ref().is_some_and(|tx_dsp| tx_dsp.channels_enabled().any(|enabled| enabled));
+            if idle == channels_enabled {
+                match sdr.set_active(channels_enabled) {
+                    Ok(_) => {
+                        idle = !channels_enabled;
+                        events::emit(events::Event::new(if idle { "idle" } else { "active" }));
+                        if let Some(rx_dsp) = rx_dsp.as_mut().filter(|_| !idle) {
+                            rx_dsp.discard_input(cli.rx_settle_time * 1e-3);
+                        }
+                    },
+                    Err(err) => {
+                        // Do not try again on every block if the SDR does not support it.
+                        eprintln!("Error {} SDR streams, not idling anymore: {}",
+                            if channels_enabled { "activating" } else { "deactivating" }, err);
+                        idle_interval = None;
+                    },
+                }
+            }
+            if idle {
+                std::thread::sleep(interval);
+                continue;
+            }
+        }
+
+        if let (Some(hopper), Some(rx_dsp)) = (&mut hopper, &mut rx_dsp) {
+            if let Some(frequency) = hopper.update() {
+                match sdr.set_rx_center_frequency(frequency) {
+                    Ok(_) => {
+                        band_outputs.update(&mut sdr, frequency);
+                        rx_dsp.retune(&mut fft_planner, &cli, frequency);
+                        rx_dsp.discard_input(cli.rx_settle_time * 1e-3);
+                    },
+                    Err(err) => {
+                        eprintln!("Error retuning SDR: {}", err);
+                    },
+                }
+            }
+        }
+
         let mut rx_time: Option<i64> = None;
 
         if let Some(rx_dsp) = &mut rx_dsp {
-            match sdr.receive(rx_dsp.prepare_input_buffer()) {
-                Ok(rx_result) => {
-                    error_count = 0;
-                    rx_time = rx_result.time;
-                    rx_dsp.process();
-                },
-                Err(err) => {
-                    error_count += 1;
-                    eprintln!("Error receiving from SDR ({}): {}", error_count, err);
-                    // Occasional errors might sometimes occur with some SDRs
-                    // even if they would still continue working.
-                    // If too many reads result in an error with no valid reads
-                    // in between, assume the SDR is broken and stop.
-                    if error_count >= 10 {
-                        break
-                    }
-                },
+            // Read until a full block has been received,
+            // since reads may return fewer samples than requested.
+            loop {
+                let filled = rx_dsp.input_filled();
+                match sdr.receive(rx_dsp.input_space()) {
+                    Ok(rx_result) => {
+                        error_count = 0;
+                        health::stream_status(true);
+                        // Keep the timestamp of the first sample of the block
+                        rx_time = rx_result.time.map(|time|
+                            time - (filled as f64 * 1e9 / rx_sample_rate) as i64);
+                        if rx_dsp.input_written(rx_result.len) {
+                            break;
+                        }
+                    },
+                    Err(err) => {
+                        error_count += 1;
+                        health::stream_status(false);
+                        eprintln!("Error receiving from SDR ({}): {}", error_count, err);
+                        // Errors such as overflows usually mean samples were lost.
+                        rx_dsp.discontinuity();
+                        // Occasional errors might sometimes occur with some SDRs
+                        // even if they would still continue working.
+                        // If too many reads result in an error with no valid reads
+                        // in between, assume the SDR is broken and stop.
+                        if error_count >= 10 {
+                            break 'main
+                        }
+                        break;
+                    },
+                }
             }
         }
 
         if let Some(tx_dsp) = &mut tx_dsp {
             let tx_time: Option<i64> = if let Some(rx_time) = rx_time { Some(rx_time + cli.rx_tx_delay) } else { None };
-            match sdr.transmit(tx_dsp.process(), tx_time) {
-                Ok(_) => {},
+            if let Some(recorder) = &mut tx_recorder {
+                recorder.update_channels(tx_dsp.channels_enabled());
+            }
+            let samples = tx_dsp.process();
+            if let Some(recorder) = &mut tx_recorder {
+                recorder.write(samples);
+            }
+            match sdr.transmit(samples, tx_time) {
+                Ok(_) => health::stream_status(true),
                 Err(err) => {
                     error_count += 1;
+                    health::stream_status(false);
                     eprintln!("Error transmitting to SDR ({}): {}", error_count, err);
                     if error_count >= 10 {
-                        break
+                        break 'main
                     }
                 }
             }
@@ -0,0 +1,99 @@
+//! Periodic metrics of receive channels and signal processing.
+//!
+//! Every interval, a "channel_metrics" event is emitted for each
+//! enabled receive channel with its mean power, an estimate of
+//! the noise floor and signal-to-noise ratio, and a "system" event
+//! with the fraction of real time spent in receive processing.
+//!
+//! The noise floor is the lowest power of any block during
+//! the interval, which works for intermittent signals
+//! but overestimates noise under a continuous carrier.
+
+use std::time::{Duration, Instant};
+
+use crate::{Sample, ComplexSample};
+use crate::events::{self, Event};
+
+/// Power statistics of a channel since the previous report.
+#[derive(Default)]
+pub struct ChannelMetrics {
+    power_sum: f64,
+    power_min: Option<Sample>,
+    power_max: Sample,
+    blocks: usize,
+}
+
+impl ChannelMetrics {
+    /// Add a block of channel samples.
+    pub fn add(&mut self, samples: &[ComplexSample]) {
+        if samples.is_empty() {
+            return;
+        }
+        let power = samples.iter().map(|s| s.norm_sqr()).sum::<Sample>() / samples.len() as Sample;
+        self.power_sum += power as f64;
+        self.power_min = Some(self.power_min.map_or(power, |min| min.min(power)));
+        self.power_max = self.power_max.max(power);
+        self.blocks += 1;
+    }
+
+    /// Emit metrics as an event and reset them.
+    fn report(&mut self, channel: &str, frequency: f64) {
+        let Some(power_min) = self.power_min else { return };
+        let db = |power: f64| 10.0 * (power + 1e-20).log10();
+        let power_db = db(self.power_sum / self.blocks as f64);
+        let noise_db = db(power_min as f64);
+        events::emit(Event::new("channel_metrics")
+            .with("channel", channel)
+            .with("frequency", frequency)
+            .with("power_db", power_db)
+            .with("peak_db", db(self.power_max as f64))
+            .with("noise_db", noise_db)
+            .with("snr_db", power_db - noise_db));
+        *self = Self::default();
+    }
+}
+
+pub struct MetricsReporter {
+    interval: Duration,
+    last_report: Instant,
+    /// Time spent processing since the previous report.
+    processing_time: Duration,
+}
+
+impl MetricsReporter {
+    /// Report metrics at given interval in seconds.
+    pub fn new(interval: f64) -> Self {
+        Self {
+            interval: Duration::from_secs_f64(interval),
+            last_report: Instant::now(),
+            processing_time: Duration::ZERO,
+        }
+    }
+
+    /// Account time spent processing a block
+    /// and report metrics if interval has passed.
+    /// Channels are given as name, frequency and metrics.
+    pub fn update<'a>(
+        &mut self,
+        processing_time: Duration,
+        channels: impl Iterator<Item = (String, f64, &'a mut ChannelMetrics)>,
+    ) {
+        self.processing_time += processing_time;
+        let elapsed = self.last_report.elapsed();
+        if elapsed < self.interval {
+            return;
+        }
+        let mut enabled = 0;
+        for (name, frequency, metrics) in channels {
+            if metrics.blocks > 0 {
+                enabled += 1;
+            }
+            metrics.report(&name, frequency);
+        }
+        events::emit(Event::new("system")
+            .with("dsp_load", self.processing_time.as_secs_f64() / elapsed.as_secs_f64())
+            .with("active_channels", enabled as f64));
+        self.last_report = Instant::now();
+        self.processing_time = Duration::ZERO;
+    }
+}
@@ -0,0 +1,297 @@
+//! Minimal Mumble client for streaming audio into a Mumble server.
+//!
+//! Only the parts of the protocol needed to log in, join a channel
+//! and send Opus audio through the TCP control connection
+//! ("UDP tunnel") are implemented.
+//! Each connection runs in its own thread so that a slow or
+//! disconnected server does not stall signal processing.
+//! Protobuf messages are encoded by hand since only
+//! a few fields of a few messages are used.
+
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+
+const MSG_VERSION: u16 = 0;
+const MSG_UDP_TUNNEL: u16 = 1;
+const MSG_AUTHENTICATE: u16 = 2;
+const MSG_PING: u16 = 3;
+const MSG_SERVER_SYNC: u16 = 5;
+const MSG_CHANNEL_STATE: u16 = 7;
+const MSG_USER_STATE: u16 = 9;
+
+/// Protocol version 1.4.0
+const PROTOCOL_VERSION: u64 = (1 << 16) | (4 << 8);
+/// Header byte of a legacy format Opus voice packet
+/// to the normal talking target.
+const VOICE_HEADER_OPUS: u8 = 4 << 5;
+/// Interval of ping messages. Server disconnects after 30 s without one.
+const PING_INTERVAL: Duration = Duration::from_secs(10);
+/// Delay before reconnecting after an error.
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+type TlsStream = native_tls::TlsStream<TcpStream>;
+
+/// Audio frame sent from signal processing to connection thread.
+struct AudioFrame {
+    /// Opus encoded data.
+    data: Vec<u8>,
+    /// Whether this is the last frame of a transmission.
+    end: bool,
+}
+
+/// Handle to a connection to a Mumble server.
+pub struct MumbleConnection {
+    sender: mpsc::Sender<AudioFrame>,
+}
+
+impl MumbleConnection {
+    /// Start a connection to a Mumble server in a new thread.
+    /// The client logs in with the given username
+    /// and joins the channel with the given name.
+    pub fn start(server: &str, username: &str, channel: &str) -> Self {
+        let (sender, receiver) = mpsc::channel();
+        let server = server.to_string();
+        let username = username.to_string();
+        let channel = channel.to_string();
+        std::thread::spawn(move || {
+            loop {
+                if let Err(err) = run_connection(&server, &username, &channel, &receiver) {
+                    eprintln!("Mumble: connection to {} failed: {}", server, err);
+                }
+                // Discard audio produced while disconnected.
+                while receiver.try_recv().is_ok() {}
+                std::thread::sleep(RECONNECT_DELAY);
+            }
+        });
+        Self { sender }
+    }
+
+    /// Send an Opus encoded 20 ms audio frame.
+    pub fn send_audio(&self, data: &[u8], end: bool) {
+        let _ = self.sender.send(AudioFrame { data: data.to_vec(), end });
+    }
+}
+
+fn run_connection(
+    server: &str,
+    username: &str,
+    channel: &str,
+    receiver: &mpsc::Receiver<AudioFrame>,
+) -> std::io::Result<()> {
+    let tcp = TcpStream::connect(server)?;
+    // Mumble servers typically use self-signed certificates.
+    let connector = native_tls::TlsConnector::builder()
+        .danger_accept_invalid_certs(true)
+        .danger_accept_invalid_hostnames(true)
+        .build()
+        .map_err(std::io::Error::other)?;
+    let host = server.rsplit_once(':').map(|(host, _)| host).unwrap_or(server);
+    let mut stream = connector.connect(host, tcp).map_err(std::io::Error::other)?;
+
+    let mut message = Vec::new();
+    put_varint_field(&mut message, 1, PROTOCOL_VERSION);
+    put_string_field(&mut message, 2, "sdrglue");
+    put_string_field(&mut message, 3, std::env::consts::OS);
+    write_message(&mut stream, MSG_VERSION, &message)?;
+
+    message.clear();
+    put_string_field(&mut message, 1, username);
+    // opus = true
+    put_varint_field(&mut message, 5, 1);
+    write_message(&mut stream, MSG_AUTHENTICATE, &message)?;
+
+    // Collect channel list until server tells
+    // the login is complete and which session we got.
+    let mut channels = HashMap::<String, u64>::new();
+    let session = loop {
+        let (message_type, payload) = read_message(&mut stream)?;
+        match message_type {
+            MSG_CHANNEL_STATE => {
+                let fields = parse_fields(&payload);
+                if let (Some(Field::Varint(id)), Some(Field::Bytes(name))) = (fields.get(&1), fields.get(&3)) {
+                    channels.insert(String::from_utf8_lossy(name).into_owned(), *id);
+                }
+            },
+            MSG_SERVER_SYNC => {
+                if let Some(Field::Varint(session)) = parse_fields(&payload).get(&1) {
+                    break *session;
+                }
+            },
+            _ => {},
+        }
+    };
+
+    match channels.get(channel) {
+        Some(&channel_id) => {
+            message.clear();
+            put_varint_field(&mut message, 1, session);
+            put_varint_field(&mut message, 5, channel_id);
+            write_message(&mut stream, MSG_USER_STATE, &message)?;
+        },
+        None => {
+            eprintln!("Mumble: channel {} not found on {}, staying in root channel", channel, server);
+        },
+    }
+    eprintln!("Mumble: connected to {} as {}", server, username);
+
+    stream.get_ref().set_read_timeout(Some(Duration::from_millis(10)))?;
+    let mut sequence: u64 = 0;
+    let mut last_ping = Instant::now();
+    let mut receive_buffer = Vec::<u8>::new();
+    loop {
+        // Incoming messages are not needed for anything,
+        // but they have to be read so the server does not block.
+        let mut buf = [0u8; 4096];
+        match stream.read(&mut buf) {
+            Ok(0) => return Err(std::io::ErrorKind::UnexpectedEof.into()),
+            Ok(n) => {
+                receive_buffer.extend_from_slice(&buf[..n]);
+                skip_complete_messages(&mut receive_buffer);
+            },
+            Err(err) if matches!(err.kind(), std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut) => {},
+            Err(err) => return Err(err),
+        }
+
+        while let Ok(frame) = receiver.try_recv() {
+            message.clear();
+            message.push(VOICE_HEADER_OPUS);
+            put_mumble_varint(&mut message, sequence);
+            put_mumble_varint(&mut message, frame.data.len() as u64 | if frame.end { 0x2000 } else { 0 });
+            message.extend_from_slice(&frame.data);
+            write_message(&mut stream, MSG_UDP_TUNNEL, &message)?;
+            // Sequence number counts 10 ms frames.
+            sequence = if frame.end { 0 } else { sequence + 2 };
+        }
+
+        if last_ping.elapsed() >= PING_INTERVAL {
+            last_ping = Instant::now();
+            message.clear();
+            put_varint_field(&mut message, 1, 0);
+            write_message(&mut stream, MSG_PING, &message)?;
+        }
+    }
+}
+
+fn write_message(stream: &mut TlsStream, message_type: u16, payload: &[u8]) -> std::io::Result<()> {
+    let mut header = [0u8; 6];
+    header[0..2].copy_from_slice(&message_type.to_be_bytes());
+    header[2..6].copy_from_slice(&(payload.len() as u32).to_be_bytes());
+    stream.write_all(&header)?;
+    stream.write_all(payload)?;
+    stream.flush()
+}
+
+fn read_message(stream: &mut TlsStream) -> std::io::Result<(u16, Vec<u8>)> {
+    let mut header = [0u8; 6];
+    stream.read_exact(&mut header)?;
+    let message_type = u16::from_be_bytes([header[0], header[1]]);
+    let len = u32::from_be_bytes([header[2], header[3], header[4], header[5]]) as usize;
+    let mut payload = vec![0u8; len];
+    stream.read_exact(&mut payload)?;
+    Ok((message_type, payload))
+}
+
+/// Remove complete messages from the beginning of the buffer.
+fn skip_complete_messages(buffer: &mut Vec<u8>) {
+    let mut start = 0;
+    while buffer.len() - start >= 6 {
+        let len = u32::from_be_bytes([buffer[start+2], buffer[start+3], buffer[start+4], buffer[start+5]]) as usize;
+        if buffer.len() - start < 6 + len {
+            break;
+        }
+        start += 6 + len;
+    }
+    buffer.drain(..start);
+}
+
+
+// Protobuf encoding
+
+fn put_protobuf_varint(buffer: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buffer.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    buffer.push(value as u8);
+}
+
+fn put_varint_field(buffer: &mut Vec<u8>, field: u64, value: u64) {
+    put_protobuf_varint(buffer, field << 3);
+    put_protobuf_varint(buffer, value);
+}
+
+fn put_string_field(buffer: &mut Vec<u8>, field: u64, value: &str) {
+    put_protobuf_varint(buffer, (field << 3) | 2);
+    put_protobuf_varint(buffer, value.len() as u64);
+    buffer.extend_from_slice(value.as_bytes());
+}
+
+enum Field<'a> {
+    Varint(u64),
+    Bytes(&'a [u8]),
+}
+
+fn get_protobuf_varint(data: &[u8], pos: &mut usize) -> Option<u64> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let byte = *data.get(*pos)?;
+        *pos += 1;
+        value |= ((byte & 0x7F) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+    }
+    None
+}
+
+/// Parse the varint and length-delimited fields of a message.
+/// Parsing stops at anything else, which is fine
+/// for the messages we are interested in.
+fn parse_fields(data: &[u8]) -> HashMap<u64, Field<'_>> {
+    let mut fields = HashMap::new();
+    let mut pos = 0;
+    while let Some(key) = get_protobuf_varint(data, &mut pos) {
+        let field = key >> 3;
+        match key & 7 {
+            0 => match get_protobuf_varint(data, &mut pos) {
+                Some(value) => { fields.insert(field, Field::Varint(value)); },
+                None => break,
+            },
+            2 => match get_protobuf_varint(data, &mut pos) {
+                Some(len) if pos + len as usize <= data.len() => {
+                    fields.insert(field, Field::Bytes(&data[pos .. pos + len as usize]));
+                    pos += len as usize;
+                },
+                _ => break,
+            },
+            _ => break,
+        }
+    }
+    fields
+}
+
+/// Mumble has its own variable length integer encoding
+/// used in voice packets.
+/// Only values below 2^28 are supported here,
+/// which is enough for sequence numbers and lengths.
+fn put_mumble_varint(buffer: &mut Vec<u8>, value: u64) {
+    let value = value & 0x0FFF_FFFF;
+    if value < 0x80 {
+        buffer.push(value as u8);
+    } else if value < 0x4000 {
+        buffer.push(0x80 | (value >> 8) as u8);
+        buffer.push(value as u8);
+    } else if value < 0x20_0000 {
+        buffer.push(0xC0 | (value >> 16) as u8);
+        buffer.push((value >> 8) as u8);
+        buffer.push(value as u8);
+    } else {
+        buffer.push(0xE0 | (value >> 24) as u8);
+        buffer.push((value >> 16) as u8);
+        buffer.push((value >> 8) as u8);
+        buffer.push(value as u8);
+    }
+}
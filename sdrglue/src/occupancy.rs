@@ -0,0 +1,176 @@
+//! Channel occupancy scanning for spectrum monitoring.
+//!
+//! Power of a set of channels is measured directly from
+//! the analysis filter bank, which is cheap enough to monitor
+//! hundreds of channels. Statistics for each channel are
+//! periodically written to a file as CSV or as JSON lines
+//! and emitted as "occupancy" events.
+
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+use crate::Sample;
+use crate::calibration;
+use crate::clock;
+use crate::events::{self, Event};
+use crate::fcfb;
+
+enum Format {
+    Csv,
+    Json,
+}
+
+struct ChannelStats {
+    frequency: f64,
+    /// Value added to reported power levels in dB.
+    /// Converts them to dBm if a calibration table is loaded.
+    offset: Sample,
+    power: fcfb::AnalysisPowerProcessor,
+    /// Number of blocks with power above threshold.
+    blocks_above: usize,
+    power_sum: Sample,
+    power_max: Sample,
+}
+
+pub struct OccupancyParameters<'a> {
+    /// File to write the reports to.
+    /// Reports are written as JSON lines if the name ends with
+    /// .json or .jsonl, and as CSV otherwise.
+    pub path: &'a str,
+    /// Comma-separated list of channel frequencies
+    /// and ranges given as start:step:stop.
+    pub frequencies: &'a str,
+    /// Channel bandwidth in Hertz.
+    pub bandwidth: f64,
+    /// Channel is considered occupied when its power
+    /// is above this level in dB.
+    pub threshold: Sample,
+    /// Reporting interval in seconds.
+    pub interval: f64,
+}
+
+pub struct OccupancyScanner {
+    channels: Vec<ChannelStats>,
+    /// Threshold as linear power.
+    threshold: Sample,
+    blocks_per_report: usize,
+    /// Number of blocks since the previous report.
+    blocks: usize,
+    format: Format,
+    output: BufWriter<File>,
+    /// Whether reported power is calibrated to dBm.
+    calibrated: bool,
+}
+
+impl OccupancyScanner {
+    pub fn new(
+        analysis_params: fcfb::AnalysisInputParameters,
+        parameters: &OccupancyParameters,
+    ) -> Self {
+        let block_duration = (analysis_params.fft_size / 2) as f64 / analysis_params.sample_rate;
+        let format = if parameters.path.ends_with(".json") || parameters.path.ends_with(".jsonl") {
+            Format::Json
+        } else {
+            Format::Csv
+        };
+        // TODO: handle errors more nicely
+        let mut output = BufWriter::new(File::create(parameters.path).unwrap());
+        let calibrated = calibration::loaded();
+        if let Format::Csv = format {
+            let unit = if calibrated { "dbm" } else { "db" };
+            writeln!(output, "time,frequency,duty_cycle,average_{},max_{}", unit, unit).unwrap();
+        }
+        Self {
+            channels: parse_frequencies(parameters.frequencies).into_iter().map(|frequency| ChannelStats {
+                frequency,
+                offset: calibration::dbm_offset(frequency).unwrap_or(0.0),
+                power: fcfb::AnalysisPowerProcessor::new_with_frequency(analysis_params, parameters.bandwidth, frequency),
+                blocks_above: 0,
+                power_sum: 0.0,
+                power_max: 0.0,
+            }).collect(),
+            threshold: (10.0 as Sample).powf(parameters.threshold / 10.0),
+            blocks_per_report: ((parameters.interval / block_duration).round() as usize).max(1),
+            blocks: 0,
+            format,
+            output,
+            calibrated,
+        }
+    }
+
+    pub fn process(&mut self, intermediate_result: &fcfb::AnalysisIntermediateResult) {
+        for channel in self.channels.iter_mut() {
+            let power = channel.power.process(intermediate_result);
+            if power >= self.threshold {
+                channel.blocks_above += 1;
+            }
+            channel.power_sum += power;
+            channel.power_max = channel.power_max.max(power);
+        }
+        self.blocks += 1;
+        if self.blocks >= self.blocks_per_report {
+            // TODO: print a warning or something if writing fails
+            let _ = self.report();
+            self.blocks = 0;
+        }
+    }
+
+    fn report(&mut self) -> std::io::Result<()> {
+        let time = clock::now_seconds();
+        let db = |power: Sample| 10.0 * (power + 1e-20).log10();
+        for channel in self.channels.iter_mut() {
+            let duty_cycle = channel.blocks_above as f64 / self.blocks as f64;
+            let average_db = db(channel.power_sum / self.blocks as Sample) + channel.offset;
+            let max_db = db(channel.power_max) + channel.offset;
+            let event = Event::new("occupancy")
+                .with("frequency", channel.frequency)
+                .with("duty_cycle", duty_cycle)
+                .with(if self.calibrated { "average_dbm" } else { "average_db" }, average_db)
+                .with(if self.calibrated { "max_dbm" } else { "max_db" }, max_db);
+            match self.format {
+                Format::Csv => writeln!(self.output, "{:.0},{},{:.3},{:.1},{:.1}",
+                    time, channel.frequency, duty_cycle, average_db, max_db)?,
+                Format::Json => writeln!(self.output, "{}", event.to_json())?,
+            }
+            // Also let event listeners such as the database log them.
+            events::emit(event);
+            channel.blocks_above = 0;
+            channel.power_sum = 0.0;
+            channel.power_max = 0.0;
+        }
+        self.output.flush()
+    }
+}
+
+/// Parse a comma-separated list of frequencies and
+/// frequency ranges given as start:step:stop.
+fn parse_frequencies(text: &str) -> Vec<f64> {
+    let mut frequencies = Vec::new();
+    for item in text.split(',') {
+        // TODO: handle errors more nicely
+        let parse = |t: &str| t.parse::<f64>().unwrap_or_else(|_| panic!("Invalid frequency {}", t));
+        let parts: Vec<&str> = item.split(':').collect();
+        match parts[..] {
+            [start, step, stop] => {
+                let (start, step, stop) = (parse(start), parse(step), parse(stop));
+                assert!(step > 0.0, "Invalid frequency range {}", item);
+                let n = ((stop - start) / step + 1e-6).floor() as usize;
+                frequencies.extend((0 ..= n).map(|i| start + i as f64 * step));
+            },
+            _ => frequencies.push(parse(item)),
+        }
+    }
+    frequencies
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_frequencies() {
+        assert_eq!(parse_frequencies("145.5e6"), vec![145.5e6]);
+        assert_eq!(parse_frequencies("100:25:200,50"), vec![100.0, 125.0, 150.0, 175.0, 200.0, 50.0]);
+        assert_eq!(parse_frequencies("144e6:12.5e3:144.05e6").len(), 5);
+    }
+}
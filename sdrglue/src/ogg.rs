@@ -0,0 +1,126 @@
+//! Writer for Ogg encapsulated Opus streams.
+//!
+//! Only what is needed to write a single mono Opus stream
+//! is implemented, so no dependency on an Ogg library is needed.
+
+use std::io::Write;
+
+/// Number of Opus packets collected into one Ogg page.
+const PACKETS_PER_PAGE: usize = 10;
+/// Number of samples the decoder should skip at the beginning.
+/// This is a typical value of encoder lookahead.
+const PRE_SKIP: u16 = 312;
+
+pub struct OggOpusWriter<W: Write> {
+    writer: W,
+    /// Serial number of the logical stream.
+    serial: u32,
+    /// Number of the next page.
+    page_sequence: u32,
+    /// Granule position (number of 48 kHz samples) at the end of
+    /// the last packet added.
+    granule_position: u64,
+    /// Packets waiting to be written in the next page.
+    packets: Vec<Vec<u8>>,
+}
+
+impl<W: Write> OggOpusWriter<W> {
+    /// Start a new stream by writing the Opus header pages.
+    pub fn new(mut writer: W, serial: u32, vendor: &str) -> std::io::Result<Self> {
+        let mut head = Vec::with_capacity(19);
+        head.extend_from_slice(b"OpusHead");
+        head.push(1); // version
+        head.push(1); // channels
+        head.extend_from_slice(&PRE_SKIP.to_le_bytes());
+        head.extend_from_slice(&48000u32.to_le_bytes());
+        head.extend_from_slice(&0u16.to_le_bytes()); // output gain
+        head.push(0); // channel mapping family
+
+        let mut tags = Vec::new();
+        tags.extend_from_slice(b"OpusTags");
+        tags.extend_from_slice(&(vendor.len() as u32).to_le_bytes());
+        tags.extend_from_slice(vendor.as_bytes());
+        tags.extend_from_slice(&0u32.to_le_bytes()); // no comments
+
+        write_page(&mut writer, serial, 0, 0, 0x02, &[&head])?;
+        write_page(&mut writer, serial, 1, 0, 0x00, &[&tags])?;
+        Ok(Self {
+            writer,
+            serial,
+            page_sequence: 2,
+            granule_position: 0,
+            packets: Vec::with_capacity(PACKETS_PER_PAGE),
+        })
+    }
+
+    /// Add an Opus packet containing given number of samples
+    /// (at 48 kHz) to the stream.
+    pub fn write_packet(&mut self, packet: &[u8], samples: u64) -> std::io::Result<()> {
+        self.granule_position += samples;
+        self.packets.push(packet.to_vec());
+        if self.packets.len() >= PACKETS_PER_PAGE {
+            self.flush_page(0x00)?;
+        }
+        Ok(())
+    }
+
+    /// End the stream by writing the remaining packets
+    /// in a page marked as the last one.
+    pub fn finish(mut self) -> std::io::Result<()> {
+        self.flush_page(0x04)
+    }
+
+    fn flush_page(&mut self, header_type: u8) -> std::io::Result<()> {
+        let packets: Vec<&[u8]> = self.packets.iter().map(|p| &p[..]).collect();
+        write_page(&mut self.writer, self.serial, self.page_sequence, self.granule_position, header_type, &packets)?;
+        self.page_sequence += 1;
+        self.packets.clear();
+        self.writer.flush()
+    }
+}
+
+fn write_page(
+    writer: &mut impl Write,
+    serial: u32,
+    sequence: u32,
+    granule_position: u64,
+    header_type: u8,
+    packets: &[&[u8]],
+) -> std::io::Result<()> {
+    let mut segments = Vec::new();
+    for packet in packets {
+        // Lacing values: a packet is split into 255-byte segments
+        // and terminated by a segment shorter than 255.
+        segments.extend(std::iter::repeat_n(255u8, packet.len() / 255));
+        segments.push((packet.len() % 255) as u8);
+    }
+    let mut page = Vec::with_capacity(27 + segments.len() + packets.iter().map(|p| p.len()).sum::<usize>());
+    page.extend_from_slice(b"OggS");
+    page.push(0); // version
+    page.push(header_type);
+    page.extend_from_slice(&granule_position.to_le_bytes());
+    page.extend_from_slice(&serial.to_le_bytes());
+    page.extend_from_slice(&sequence.to_le_bytes());
+    page.extend_from_slice(&0u32.to_le_bytes()); // CRC, filled below
+    page.push(segments.len() as u8);
+    page.extend_from_slice(&segments);
+    for packet in packets {
+        page.extend_from_slice(packet);
+    }
+    let crc = crc32(&page);
+    page[22..26].copy_from_slice(&crc.to_le_bytes());
+    writer.write_all(&page)
+}
+
+/// CRC used by Ogg: polynomial 0x04C11DB7, no reflection,
+/// zero initial value and no final XOR.
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0u32;
+    for &byte in data {
+        crc ^= (byte as u32) << 24;
+        for _ in 0..8 {
+            crc = if crc & 0x8000_0000 != 0 { (crc << 1) ^ 0x04C1_1DB7 } else { crc << 1 };
+        }
+    }
+    crc
+}
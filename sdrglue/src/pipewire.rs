@@ -0,0 +1,68 @@
+//! Virtual sound devices for channels in PipeWire.
+//!
+//! Devices are created by loading pipe modules with pactl,
+//! which works with pipewire-pulse (and PulseAudio)
+//! without linking to any additional libraries.
+//! Audio is passed through named pipes created by the modules,
+//! as signed 16-bit little endian samples at 48 kHz.
+//!
+//! Received channels appear as sources (capture devices)
+//! and transmitted channels as sinks (playback devices),
+//! so applications can use them like any sound card.
+
+use std::process::Command;
+
+/// Sample rate of audio passed to and from devices.
+pub const SAMPLE_RATE: f64 = 48000.0;
+
+/// A virtual device, removed when dropped.
+pub struct PipeDevice {
+    /// Index of the loaded module.
+    module: String,
+    /// Path of the named pipe.
+    pub path: String,
+}
+
+impl PipeDevice {
+    /// Create a source that applications can record
+    /// received audio from.
+    pub fn create_source(name: &str, description: &str) -> Self {
+        Self::create("source", name, description)
+    }
+
+    /// Create a sink that applications can play
+    /// audio to be transmitted into.
+    pub fn create_sink(name: &str, description: &str) -> Self {
+        Self::create("sink", name, description)
+    }
+
+    fn create(kind: &str, name: &str, description: &str) -> Self {
+        let path = std::env::temp_dir().join(format!("sdrglue-{}.pipe", name))
+            .to_string_lossy().into_owned();
+        let output = Command::new("pactl")
+            .arg("load-module")
+            .arg(format!("module-pipe-{}", kind))
+            .arg(format!("{}_name=sdrglue_{}", kind, name))
+            .arg(format!("file={}", path))
+            .arg("format=s16le")
+            .arg(format!("rate={}", SAMPLE_RATE))
+            .arg("channels=1")
+            .arg(format!("{}_properties=\"device.description='{}'\"", kind, description))
+            .output();
+        // TODO: handle errors more nicely
+        let module = match output {
+            Ok(output) if output.status.success() =>
+                String::from_utf8_lossy(&output.stdout).trim().to_string(),
+            Ok(output) => panic!("Could not create PipeWire {} {}: {}",
+                kind, name, String::from_utf8_lossy(&output.stderr).trim()),
+            Err(err) => panic!("Could not run pactl: {}", err),
+        };
+        Self { module, path }
+    }
+}
+
+impl Drop for PipeDevice {
+    fn drop(&mut self) {
+        let _ = Command::new("pactl").arg("unload-module").arg(&self.module).status();
+    }
+}
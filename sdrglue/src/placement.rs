@@ -0,0 +1,106 @@
+//! Automatic placement of SDR center frequency and sample rate
+//! from the list of channel frequencies.
+//!
+//! The center frequency is placed so that the DC component
+//! and LO leakage of the SDR do not fall on any channel,
+//! and the sample rate is chosen to be as low as possible
+//! while fitting all channels within the usable bandwidth.
+
+use crate::configuration;
+use crate::rx_dsp;
+use crate::tx_dsp;
+
+/// Minimum distance from center frequency to the edge
+/// of the nearest channel in Hertz.
+const DC_MARGIN: f64 = 10000.0;
+/// Fraction of the sample rate considered usable,
+/// since the anti-aliasing filters of many SDRs
+/// roll off near the edges.
+const USABLE_FRACTION: f64 = 0.8;
+
+/// Center frequency and sample rate for an SDR.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Placement {
+    pub center_frequency: f64,
+    pub sample_rate: f64,
+}
+
+/// Find the placement with lowest sample rate for given channels,
+/// given as pairs of center frequency and bandwidth,
+/// and sample rate granularity.
+/// Sample rate is rounded up to a multiple of granularity.
+/// Return None if there are no channels.
+pub fn place(channels: &[(f64, f64)], granularity: f64) -> Option<Placement> {
+    let mut channels = channels.to_vec();
+    channels.sort_by(|a, b| a.0.total_cmp(&b.0));
+    let (&lowest, &highest) = (channels.first()?, channels.last()?);
+    // Distance the center frequency has to be from a channel.
+    let reach = |(_, bandwidth): (f64, f64)| bandwidth / 2.0 + DC_MARGIN;
+
+    // The best center frequency is either in the middle of all channels
+    // or in the middle of a gap between two neighbouring channels.
+    // If neither avoids channels, place center just outside of them.
+    let mut candidates = vec![(lowest.0 + highest.0) / 2.0, lowest.0 - reach(lowest), highest.0 + reach(highest)];
+    candidates.extend(channels.windows(2).map(|pair| (pair[0].0 + pair[1].0) / 2.0));
+
+    candidates.iter()
+        .filter(|&&center| channels.iter().all(|&channel| (channel.0 - center).abs() >= reach(channel)))
+        .map(|&center| {
+            let offset = channels.iter()
+                .map(|(f, bandwidth)| (f - center).abs() + bandwidth / 2.0).fold(0.0, f64::max);
+            let required = 2.0 * offset / USABLE_FRACTION;
+            Placement {
+                center_frequency: center,
+                sample_rate: (required / granularity).ceil() * granularity,
+            }
+        })
+        .min_by(|a, b| a.sample_rate.total_cmp(&b.sample_rate))
+}
+
+/// Fill in SDR center frequencies and sample rates
+/// not given on command line, based on channel frequencies.
+pub fn apply(cli: &mut configuration::Cli) {
+    let rx = place(&rx_dsp::channel_bands(cli), 2.0 * cli.rx_bin_spacing);
+    let tx = place(&tx_dsp::channel_bands(cli), 2.0 * cli.tx_bin_spacing);
+    // Many SDRs need equal receive and transmit sample rates,
+    // so use the higher one for both.
+    let sample_rate = rx.iter().chain(tx.iter()).map(|p| p.sample_rate).fold(0.0, f64::max);
+    if let Some(rx) = rx {
+        let frequency = *cli.sdr_rx_freq.get_or_insert(rx.center_frequency);
+        let sample_rate = *cli.sdr_rx_fs.get_or_insert(sample_rate);
+        eprintln!("Receiving at {} Hz with sample rate {} Hz", frequency, sample_rate);
+    }
+    if let Some(tx) = tx {
+        let frequency = *cli.sdr_tx_freq.get_or_insert(tx.center_frequency);
+        let sample_rate = *cli.sdr_tx_fs.get_or_insert(sample_rate);
+        eprintln!("Transmitting at {} Hz with sample rate {} Hz", frequency, sample_rate);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_place() {
+        // Center between the channels
+        let p = place(&[(145.0e6, 48000.0), (145.5e6, 48000.0)], 1000.0).unwrap();
+        assert_eq!(p.center_frequency, 145.25e6);
+        assert!(p.sample_rate >= (0.5e6 + 48000.0) / USABLE_FRACTION);
+        // A channel in the middle should not be on DC
+        let p = place(&[(145.0e6, 48000.0), (145.25e6, 48000.0), (145.5e6, 48000.0)], 1000.0).unwrap();
+        for f in [145.0e6, 145.25e6, 145.5e6] {
+            assert!((f - p.center_frequency).abs() >= 24000.0 + DC_MARGIN);
+            assert!((f - p.center_frequency).abs() + 24000.0 <= p.sample_rate / 2.0);
+        }
+        // A single channel
+        let p = place(&[(433.92e6, 48000.0)], 1000.0).unwrap();
+        assert!((433.92e6 - p.center_frequency).abs() >= 24000.0 + DC_MARGIN);
+        assert_eq!(p.sample_rate % 1000.0, 0.0);
+        assert!(place(&[], 1000.0).is_none());
+        // A wideband FM channel needs more room than a narrowband one
+        let p = place(&[(145.0e6, 48000.0), (100.0e6, 288000.0)], 1000.0).unwrap();
+        assert!((100.0e6 - p.center_frequency).abs() >= 144000.0 + DC_MARGIN);
+        assert!((100.0e6 - p.center_frequency).abs() + 144000.0 <= p.sample_rate / 2.0);
+    }
+}
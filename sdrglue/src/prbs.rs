@@ -0,0 +1,141 @@
+//! Pseudorandom binary sequences for bit error rate testing.
+//!
+//! Uses the 2^9-1 sequence (polynomial x^9 + x^5 + 1)
+//! recommended by ITU-T O.150 for low bit rates.
+
+/// Length of the shift register.
+const ORDER: u32 = 9;
+/// Feedback taps as bit positions counted from 1.
+const TAPS: (u32, u32) = (9, 5);
+const MASK: u32 = (1 << ORDER) - 1;
+
+/// Compute the next bit of the sequence from previous bits.
+fn feedback(register: u32) -> bool {
+    ((register >> (TAPS.0 - 1)) ^ (register >> (TAPS.1 - 1))) & 1 != 0
+}
+
+/// Generator of the pseudorandom sequence.
+pub struct Prbs {
+    register: u32,
+}
+
+impl Prbs {
+    pub fn new() -> Self {
+        Self { register: MASK }
+    }
+
+    pub fn next_bit(&mut self) -> bool {
+        let bit = feedback(self.register);
+        self.register = ((self.register << 1) | bit as u32) & MASK;
+        bit
+    }
+}
+
+/// Number of consecutive correctly predicted bits
+/// needed to synchronize to the received sequence.
+const SYNC_BITS: usize = 4 * ORDER as usize;
+/// Number of bits in the window used to detect loss of sync.
+const SYNC_WINDOW: usize = 128;
+/// Synchronization is considered lost if more than this many
+/// bit errors occur within the window.
+const SYNC_LOST_ERRORS: usize = SYNC_WINDOW / 4;
+
+/// Checker of received pseudorandom sequence.
+///
+/// The checker first synchronizes to the received sequence
+/// and then compares received bits against a locally
+/// generated reference, so that each bit error is counted once.
+pub struct PrbsChecker {
+    register: u32,
+    synchronized: bool,
+    /// Number of consecutive correctly predicted bits
+    /// while not synchronized.
+    good_bits: usize,
+    /// Errors in the recent bits, one bit per received bit.
+    recent_errors: u128,
+    /// Number of bits checked while synchronized.
+    pub bits: u64,
+    /// Number of bit errors while synchronized.
+    pub errors: u64,
+}
+
+impl PrbsChecker {
+    pub fn new() -> Self {
+        Self {
+            register: 0,
+            synchronized: false,
+            good_bits: 0,
+            recent_errors: 0,
+            bits: 0,
+            errors: 0,
+        }
+    }
+
+    pub fn synchronized(&self) -> bool {
+        self.synchronized
+    }
+
+    /// Check a received bit.
+    pub fn receive(&mut self, bit: bool) {
+        let expected = feedback(self.register);
+        if self.synchronized {
+            // Keep generating the reference from the
+            // expected bits rather than received ones.
+            self.register = ((self.register << 1) | expected as u32) & MASK;
+            let error = bit != expected;
+            self.bits += 1;
+            self.errors += error as u64;
+            self.recent_errors = (self.recent_errors << 1) | error as u128;
+            if self.recent_errors.count_ones() as usize > SYNC_LOST_ERRORS {
+                self.synchronized = false;
+                self.good_bits = 0;
+            }
+        } else {
+            self.register = ((self.register << 1) | bit as u32) & MASK;
+            if bit == expected {
+                self.good_bits += 1;
+            } else {
+                self.good_bits = 0;
+            }
+            if self.good_bits >= SYNC_BITS {
+                self.synchronized = true;
+                self.recent_errors = 0;
+            }
+        }
+    }
+
+    /// Reset error counters.
+    pub fn reset_counters(&mut self) {
+        self.bits = 0;
+        self.errors = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_checker() {
+        let mut prbs = Prbs::new();
+        let mut checker = PrbsChecker::new();
+        // Start in the middle of the sequence
+        for _ in 0..100 { prbs.next_bit(); }
+        for _ in 0..100 { checker.receive(prbs.next_bit()); }
+        assert!(checker.synchronized());
+        checker.reset_counters();
+        for i in 0..1000 {
+            let bit = prbs.next_bit();
+            // Every 100th bit is wrong
+            checker.receive(bit ^ (i % 100 == 0));
+        }
+        assert!(checker.synchronized());
+        assert_eq!(checker.bits, 1000);
+        assert_eq!(checker.errors, 10);
+        // Random data should lose synchronization
+        for i in 0..1000u32 {
+            checker.receive(i.wrapping_mul(2654435761) & 0x10000 != 0);
+        }
+        assert!(!checker.synchronized());
+    }
+}
@@ -0,0 +1,204 @@
+//! Recommendation of filter bank parameters
+//! for given channels and SDR sample rates.
+//!
+//! Bin spacing affects several things:
+//! - Sample rates must be integer multiples of 2 * bin spacing.
+//! - Channel center frequencies are rounded to a multiple
+//!   of bin spacing from SDR center frequency.
+//! - Channel filter transition bands are a fixed number of bins,
+//!   so larger spacing leaves a narrower passband.
+//! - Latency is roughly the length of one FFT, 1 / bin spacing.
+//! - Larger spacing means smaller FFTs and somewhat lower CPU use.
+//!
+//! The recommended spacing is the largest one that tunes
+//! channels exactly and leaves a wide enough passband.
+
+use std::time::Instant;
+
+use crate::{Sample, ComplexSample};
+use crate::configuration;
+use crate::rx_dsp;
+use crate::tx_dsp;
+
+/// Maximum number of bins in each channel filter transition band,
+/// as in fcfb::raised_cosine_weights.
+const TRANSITION_BINS: f64 = 16.0;
+/// Minimum channel passband width in Hertz,
+/// enough for narrowband FM with some frequency error.
+const MIN_PASSBAND: f64 = 25000.0;
+/// Smallest bin spacing considered.
+const MIN_BIN_SPACING: usize = 50;
+/// FFT sizes above this are slow to plan and use lots of memory,
+/// so two-stage channelization is suggested.
+const LARGE_FFT_SIZE: usize = 1 << 18;
+/// Number of FFTs to run when measuring CPU cost.
+const ITERATIONS: usize = 20;
+
+/// Properties of a bin spacing for given channels.
+#[derive(Clone, Debug)]
+pub struct Candidate {
+    pub bin_spacing: f64,
+    /// FFT size of the SDR side of the filter bank.
+    pub fft_size: usize,
+    /// FFT size of the narrowest channel.
+    pub channel_fft_size: usize,
+    /// Passband width of the narrowest channel in Hertz.
+    pub passband: f64,
+    /// Largest error in channel center frequency
+    /// caused by rounding to bins, in Hertz.
+    pub tuning_error: f64,
+    /// Approximate latency in seconds.
+    pub latency: f64,
+}
+
+/// Find bin spacings usable with given sample rate and channels,
+/// given as pairs of center frequency and channel sample rate,
+/// from the largest to the smallest.
+pub fn candidates(sample_rate: f64, center_frequency: f64, channels: &[(f64, f64)]) -> Vec<Candidate> {
+    if channels.is_empty() {
+        return Vec::new();
+    }
+    let narrowest = channels.iter().map(|&(_, rate)| rate).fold(f64::INFINITY, f64::min);
+    // Channel sample rates must also be multiples of 2 * bin spacing.
+    let half_rates: Vec<usize> = channels.iter().map(|&(_, rate)| (rate / 2.0) as usize).collect();
+    (MIN_BIN_SPACING ..= (narrowest / 2.0) as usize).rev()
+        .filter(|bin_spacing| half_rates.iter().all(|half_rate| half_rate.is_multiple_of(*bin_spacing)))
+        .map(|bin_spacing| bin_spacing as f64)
+        .filter(|bin_spacing| (sample_rate / (2.0 * bin_spacing)).fract() == 0.0)
+        .map(|bin_spacing| Candidate {
+            bin_spacing,
+            fft_size: (sample_rate / bin_spacing).round() as usize,
+            channel_fft_size: (narrowest / bin_spacing).round() as usize,
+            passband: narrowest - 2.0 * TRANSITION_BINS * bin_spacing,
+            tuning_error: channels.iter().map(|(frequency, _)| {
+                let offset = frequency - center_frequency;
+                (offset - (offset / bin_spacing).round() * bin_spacing).abs()
+            }).fold(0.0, f64::max),
+            latency: 1.0 / bin_spacing,
+        })
+        .collect()
+}
+
+/// Pick the recommended candidate: the largest spacing with
+/// enough passband, preferring ones with exact tuning.
+pub fn recommend(candidates: &[Candidate]) -> Option<&Candidate> {
+    let usable = || candidates.iter().filter(|c| c.passband >= MIN_PASSBAND);
+    usable().find(|c| c.tuning_error == 0.0)
+        .or_else(|| usable().min_by(|a, b| a.tuning_error.total_cmp(&b.tuning_error)))
+}
+
+/// Measure time of one FFT of given size in seconds.
+fn fft_time(fft_planner: &mut rustfft::FftPlanner<Sample>, size: usize) -> f64 {
+    let fft = fft_planner.plan_fft_forward(size);
+    let mut buffer = vec![ComplexSample::ZERO; size];
+    fft.process(&mut buffer);
+    let start = Instant::now();
+    for _ in 0 .. ITERATIONS {
+        fft.process(&mut buffer);
+    }
+    start.elapsed().as_secs_f64() / ITERATIONS as f64
+}
+
+/// Fraction of one CPU core used by the filter bank,
+/// measured by running the FFTs it would run.
+/// Channel processors themselves are not included.
+fn cpu_load(fft_planner: &mut rustfft::FftPlanner<Sample>, candidate: &Candidate, channels: &[(f64, f64)]) -> f64 {
+    // 50% overlap gives 2 blocks per FFT length.
+    let blocks_per_second = 2.0 * candidate.bin_spacing;
+    let mut channel_fft_sizes: Vec<usize> = channels.iter()
+        .map(|&(_, rate)| (rate / candidate.bin_spacing).round() as usize).collect();
+    channel_fft_sizes.sort_unstable();
+    let mut channel_time = 0.0;
+    for sizes in channel_fft_sizes.chunk_by(|a, b| a == b) {
+        channel_time += sizes.len() as f64 * fft_time(fft_planner, sizes[0]);
+    }
+    blocks_per_second * (fft_time(fft_planner, candidate.fft_size) + channel_time)
+}
+
+/// Print a table of candidates and the recommendation
+/// for one direction. Return the recommended spacing.
+fn report(
+    fft_planner: &mut rustfft::FftPlanner<Sample>,
+    direction: &str,
+    sample_rate: f64,
+    center_frequency: f64,
+    channels: &[(f64, f64)],
+) -> Option<f64> {
+    println!("{}: {} channels, sample rate {} Hz, center frequency {} Hz",
+        direction, channels.len(), sample_rate, center_frequency);
+    let candidates = candidates(sample_rate, center_frequency, channels);
+    if candidates.is_empty() {
+        println!("  No usable bin spacing. Sample rate should be a multiple of {} Hz.", 2 * MIN_BIN_SPACING);
+        return None;
+    }
+    let recommended = recommend(&candidates).map(|c| c.bin_spacing);
+    println!("  {:>8} {:>9} {:>8} {:>9} {:>10} {:>9} {:>6}",
+        "spacing", "FFT size", "channel", "passband", "tuning err", "latency", "CPU");
+    for candidate in candidates.iter().filter(|c| c.passband > 0.0) {
+        let load = cpu_load(fft_planner, candidate, channels);
+        println!("  {:>8} {:>9} {:>8} {:>9} {:>10} {:>7.1}ms {:>5.1}%{}",
+            candidate.bin_spacing, candidate.fft_size, candidate.channel_fft_size,
+            candidate.passband, candidate.tuning_error, candidate.latency * 1e3, load * 100.0,
+            if Some(candidate.bin_spacing) == recommended { "  <- recommended" } else { "" });
+    }
+    match recommend(&candidates) {
+        Some(candidate) => {
+            if candidate.tuning_error > 0.0 {
+                println!("  Channels are tuned up to {} Hz off. Moving center frequency may help.",
+                    candidate.tuning_error);
+            }
+            if candidate.fft_size > LARGE_FFT_SIZE {
+                println!("  FFT size is large. Consider --rx-segment-sample-rate for two-stage channelization.");
+            }
+        },
+        None => println!("  No bin spacing leaves a passband of {} Hz.", MIN_PASSBAND),
+    }
+    recommended
+}
+
+/// Print recommended parameters for channels given on command line.
+/// SDR sample rates and center frequencies are found
+/// automatically if not given.
+pub fn run(cli: &configuration::Cli, fft_planner: &mut rustfft::FftPlanner<Sample>) {
+    let mut rx_spacing = None;
+    let mut tx_spacing = None;
+    let rx = rx_dsp::channel_bands(cli);
+    if let (Some(sample_rate), Some(center_frequency), false) = (cli.sdr_rx_fs, cli.sdr_rx_freq, rx.is_empty()) {
+        rx_spacing = report(fft_planner, "Receive", sample_rate, center_frequency, &rx);
+    }
+    let tx = tx_dsp::channel_bands(cli);
+    if let (Some(sample_rate), Some(center_frequency), false) = (cli.sdr_tx_fs, cli.sdr_tx_freq, tx.is_empty()) {
+        tx_spacing = report(fft_planner, "Transmit", sample_rate, center_frequency, &tx);
+    }
+    if rx_spacing.is_none() && tx_spacing.is_none() {
+        println!("Nothing to recommend. Give some channels.");
+        return;
+    }
+    let options: Vec<String> = [("rx", rx_spacing), ("tx", tx_spacing)].iter()
+        .filter_map(|(direction, spacing)| spacing.map(|s| format!("--{}-bin-spacing {}", direction, s)))
+        .collect();
+    println!("Recommended: {}", options.join(" "));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_recommend() {
+        // 12.5 kHz channel raster fits the default spacing
+        let found = candidates(1e6, 145.5e6, &[(145.0125e6, 48000.0), (145.025e6, 48000.0)]);
+        assert!(found.iter().all(|c| 1e6 % (2.0 * c.bin_spacing) == 0.0));
+        assert_eq!(recommend(&found).unwrap().bin_spacing, 500.0);
+        // 6.25 kHz raster needs a smaller spacing to tune exactly
+        let found = candidates(1e6, 145.5e6, &[(145.00625e6, 48000.0)]);
+        let recommended = recommend(&found).unwrap();
+        assert_eq!(recommended.bin_spacing, 250.0);
+        assert_eq!(recommended.tuning_error, 0.0);
+        assert!(recommended.passband >= MIN_PASSBAND);
+        // Spacing must also suit the wider sample rate of a WFM channel
+        let found = candidates(2.4e6, 100e6, &[(99.5e6, 288000.0), (100.5e6, 48000.0)]);
+        assert!(found.iter().all(|c| 144000.0 % c.bin_spacing == 0.0 && 24000.0 % c.bin_spacing == 0.0));
+        assert_eq!(found[0].channel_fft_size, (48000.0 / found[0].bin_spacing) as usize);
+    }
+}
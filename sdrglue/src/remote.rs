@@ -0,0 +1,348 @@
+//! Remote head protocol.
+//!
+//! A remote head, such as a lightweight GUI, connects to a TCP
+//! socket and gets spectrum and demodulated audio of channels
+//! added with --demodulate-to-remote, and can send commands
+//! of the control API. The host keeps running without any
+//! connected heads and a head can reconnect at any time:
+//! each connection starts from scratch with a hello message.
+//!
+//! Messages in both directions are frames of a type byte,
+//! payload length as u32 and the payload.
+//! Numbers are little endian and times are nanoseconds
+//! since Unix epoch on the host clock.
+//!
+//! From host to head:
+//! ```text
+//! 0 hello     JSON: protocol version and list of audio channels
+//! 1 spectrum  u64 time, f64 center frequency, f64 span,
+//!             one byte per bin as power in dB + 200
+//! 2 audio     u8 channel, u32 sequence number, u64 time,
+//!             i16 samples at 48 kHz
+//! 3 pong      u64 time from ping, u64 host time
+//! 4 response  JSON response to a control command
+//! ```
+//! From head to host:
+//! ```text
+//! 128 ping       u64 head time
+//! 129 command    JSON control command
+//! 130 subscribe  u8 spectrum on (1) or off (0),
+//!                followed by indices of audio channels to receive
+//! 131 auth       token given with --api-token as UTF-8
+//! ```
+//! With --api-token, the first frame from a head has to be auth
+//! with the right token, and the host sends hello only after it.
+//! Otherwise the connection is closed.
+//! Heads compensate for latency using the timestamps:
+//! ping and pong give the round trip time and clock offset,
+//! so audio can be played out with a constant delay
+//! and sequence numbers show audio lost in between.
+//! Spectrum is sent by default and audio after subscribing.
+//! If a head cannot keep up, frames for it are dropped
+//! instead of delaying signal processing.
+
+use std::io::{BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{mpsc, Arc, Mutex, OnceLock};
+use std::time::SystemTime;
+
+use serde_json::{json, Value};
+
+use crate::Sample;
+use crate::auth;
+use crate::control::Requester;
+
+pub const PROTOCOL_VERSION: u32 = 1;
+/// Number of bins in spectrum frames.
+pub const SPECTRUM_BINS: usize = 1024;
+/// How often spectrum is sent, in seconds.
+pub const SPECTRUM_INTERVAL: f64 = 0.1;
+/// Number of frames that can wait to be sent to a head.
+const MAX_QUEUED: usize = 100;
+
+const HELLO: u8 = 0;
+const SPECTRUM: u8 = 1;
+const AUDIO: u8 = 2;
+const PONG: u8 = 3;
+const RESPONSE: u8 = 4;
+const PING: u8 = 128;
+const COMMAND: u8 = 129;
+const SUBSCRIBE: u8 = 130;
+const AUTH: u8 = 131;
+
+/// What a head wants to receive.
+struct Subscription {
+    spectrum: bool,
+    audio: Vec<u8>,
+}
+
+struct Head {
+    frames: mpsc::SyncSender<Vec<u8>>,
+    subscription: Arc<Mutex<Subscription>>,
+}
+
+struct AudioChannel {
+    frequency: f64,
+    modulation: String,
+    /// Sequence number of the next frame.
+    sequence: u32,
+}
+
+struct Server {
+    heads: Mutex<Vec<Head>>,
+    channels: Mutex<Vec<AudioChannel>>,
+    /// Control API to pass commands to.
+    control: Requester,
+    /// Token required from heads, if any.
+    token: Option<String>,
+}
+
+static SERVER: OnceLock<Server> = OnceLock::new();
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).map(|t| t.as_nanos() as u64).unwrap_or(0)
+}
+
+fn frame(kind: u8, payload: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(payload.len() + 5);
+    frame.push(kind);
+    frame.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    frame.extend_from_slice(payload);
+    frame
+}
+
+/// Start accepting remote heads at given TCP address.
+/// If a token is given, heads have to authenticate with it.
+pub fn start(address: &str, token: Option<String>, control: Requester) {
+    // TODO: handle errors more nicely
+    let listener = TcpListener::bind(address)
+        .unwrap_or_else(|e| panic!("Could not listen on remote head address {}: {}", address, e));
+    let _ = SERVER.set(Server {
+        heads: Mutex::new(Vec::new()),
+        channels: Mutex::new(Vec::new()),
+        control,
+        token,
+    });
+    std::thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            std::thread::spawn(move || {
+                if let Err(err) = serve(stream) {
+                    eprintln!("Remote head connection error: {}", err);
+                }
+            });
+        }
+    });
+}
+
+/// Whether remote heads are enabled.
+pub fn enabled() -> bool {
+    SERVER.get().is_some()
+}
+
+/// Add an audio channel and return its index.
+pub fn register_audio_channel(frequency: f64, modulation: &str) -> u8 {
+    // TODO: handle errors more nicely
+    let server = SERVER.get().expect("Remote audio channels require --remote-head");
+    let mut channels = server.channels.lock().unwrap();
+    // Channels are identified by a byte in audio frames.
+    let index = u8::try_from(channels.len())
+        .unwrap_or_else(|_| panic!("At most {} remote audio channels are supported", u8::MAX as usize + 1));
+    channels.push(AudioChannel { frequency, modulation: modulation.to_string(), sequence: 0 });
+    index
+}
+
+/// Send a frame to heads for which filter returns true.
+fn broadcast(frame: &[u8], filter: impl Fn(&Subscription) -> bool) {
+    let Some(server) = SERVER.get() else { return };
+    let mut heads = server.heads.lock().unwrap();
+    heads.retain(|head| {
+        if !filter(&head.subscription.lock().unwrap()) {
+            return true;
+        }
+        // Frames are dropped if the head is too slow,
+        // and the head is removed when it has disconnected.
+        !matches!(head.frames.try_send(frame.to_vec()), Err(mpsc::TrySendError::Disconnected(_)))
+    });
+}
+
+/// Send demodulated audio of a channel.
+pub fn send_audio(channel: u8, audio: &[Sample]) {
+    let Some(server) = SERVER.get() else { return };
+    let sequence = {
+        let mut channels = server.channels.lock().unwrap();
+        let sequence = channels[channel as usize].sequence;
+        channels[channel as usize].sequence = sequence.wrapping_add(1);
+        sequence
+    };
+    let mut payload = Vec::with_capacity(13 + audio.len() * 2);
+    payload.push(channel);
+    payload.extend_from_slice(&sequence.to_le_bytes());
+    payload.extend_from_slice(&now().to_le_bytes());
+    for &sample in audio {
+        let value = (sample * i16::MAX as Sample).clamp(-i16::MAX as Sample, i16::MAX as Sample) as i16;
+        payload.extend_from_slice(&value.to_le_bytes());
+    }
+    broadcast(&frame(AUDIO, &payload), |subscription| subscription.audio.contains(&channel));
+}
+
+/// Encode a spectrum frame from power of each bin.
+fn spectrum_frame(center_frequency: f64, span: f64, power: &[Sample]) -> Vec<u8> {
+    let mut payload = Vec::with_capacity(24 + power.len());
+    payload.extend_from_slice(&now().to_le_bytes());
+    payload.extend_from_slice(&center_frequency.to_le_bytes());
+    payload.extend_from_slice(&span.to_le_bytes());
+    payload.extend(power.iter().map(|&power| (10.0 * (power + 1e-30).log10() + 200.0).clamp(0.0, 255.0).round() as u8));
+    frame(SPECTRUM, &payload)
+}
+
+/// Averages spectrum of received signal and sends it to heads.
+pub struct SpectrumSender {
+    power: Vec<Sample>,
+    blocks: usize,
+    blocks_per_frame: usize,
+}
+
+impl SpectrumSender {
+    /// Create for analysis filter bank with given parameters.
+    pub fn new(parameters: crate::fcfb::AnalysisInputParameters) -> Self {
+        let block_duration = (parameters.fft_size / 2) as f64 / parameters.sample_rate;
+        Self {
+            power: vec![0.0; SPECTRUM_BINS],
+            blocks: 0,
+            blocks_per_frame: ((SPECTRUM_INTERVAL / block_duration).round() as usize).max(1),
+        }
+    }
+
+    pub fn process(&mut self, ir: &crate::fcfb::AnalysisIntermediateResult, center_frequency: f64, sample_rate: f64) {
+        ir.add_power_spectrum(&mut self.power);
+        self.blocks += 1;
+        if self.blocks >= self.blocks_per_frame {
+            for power in self.power.iter_mut() {
+                *power /= self.blocks as Sample;
+            }
+            broadcast(&spectrum_frame(center_frequency, sample_rate, &self.power), |subscription| subscription.spectrum);
+            self.power.fill(0.0);
+            self.blocks = 0;
+        }
+    }
+}
+
+/// Read a frame from a head.
+fn read_frame(reader: &mut impl Read) -> std::io::Result<(u8, Vec<u8>)> {
+    let mut header = [0u8; 5];
+    reader.read_exact(&mut header)?;
+    let length = u32::from_le_bytes([header[1], header[2], header[3], header[4]]) as usize;
+    if length > 65536 {
+        return Err(std::io::Error::other("too long frame"));
+    }
+    let mut payload = vec![0u8; length];
+    reader.read_exact(&mut payload)?;
+    Ok((header[0], payload))
+}
+
+/// Check that the first frame from a head is auth with given token.
+fn authenticate(reader: &mut impl Read, token: &str) -> std::io::Result<()> {
+    let (kind, payload) = read_frame(reader)?;
+    let given = std::str::from_utf8(&payload).unwrap_or("");
+    if kind != AUTH || !auth::secret_matches(given, token) {
+        return Err(std::io::Error::new(std::io::ErrorKind::PermissionDenied, "missing or wrong token"));
+    }
+    Ok(())
+}
+
+/// Handle a connected head.
+fn serve(stream: TcpStream) -> std::io::Result<()> {
+    let server = SERVER.get().unwrap();
+    stream.set_nodelay(true)?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    if let Some(token) = &server.token {
+        authenticate(&mut reader, token)?;
+    }
+    let (sender, frames) = mpsc::sync_channel::<Vec<u8>>(MAX_QUEUED);
+    let subscription = Arc::new(Mutex::new(Subscription { spectrum: true, audio: Vec::new() }));
+
+    let hello = json!({
+        "version": PROTOCOL_VERSION,
+        "channels": server.channels.lock().unwrap().iter().enumerate().map(|(index, channel)| json!({
+            "index": index,
+            "frequency": channel.frequency,
+            "modulation": channel.modulation,
+        })).collect::<Vec<_>>(),
+    });
+    let _ = sender.send(frame(HELLO, hello.to_string().as_bytes()));
+
+    // Frames are written in another thread so that reading
+    // commands and writing frames do not block each other.
+    let mut writer = stream.try_clone()?;
+    std::thread::spawn(move || {
+        for frame in frames.iter() {
+            if writer.write_all(&frame).is_err() {
+                break;
+            }
+        }
+        let _ = writer.shutdown(std::net::Shutdown::Both);
+    });
+    server.heads.lock().unwrap().push(Head { frames: sender.clone(), subscription: subscription.clone() });
+
+    let result = read_messages(reader, &sender, &subscription);
+    // Remove the head so that the writer thread stops.
+    server.heads.lock().unwrap().retain(|head| !Arc::ptr_eq(&head.subscription, &subscription));
+    result
+}
+
+/// Read and handle messages from a head until it disconnects.
+fn read_messages(
+    mut reader: impl Read,
+    sender: &mpsc::SyncSender<Vec<u8>>,
+    subscription: &Mutex<Subscription>,
+) -> std::io::Result<()> {
+    let server = SERVER.get().unwrap();
+    loop {
+        let (kind, payload) = read_frame(&mut reader)?;
+        match kind {
+            PING if payload.len() >= 8 => {
+                let mut pong = payload[..8].to_vec();
+                pong.extend_from_slice(&now().to_le_bytes());
+                let _ = sender.send(frame(PONG, &pong));
+            },
+            COMMAND => {
+                let response = match serde_json::from_slice::<Value>(&payload) {
+                    Ok(command) => match server.control.request(command) {
+                        Some(response) => response,
+                        None => return Ok(()),
+                    },
+                    Err(err) => json!({ "ok": false, "error": format!("Invalid JSON: {}", err) }),
+                };
+                let _ = sender.send(frame(RESPONSE, response.to_string().as_bytes()));
+            },
+            SUBSCRIBE if !payload.is_empty() => {
+                let mut subscription = subscription.lock().unwrap();
+                subscription.spectrum = payload[0] != 0;
+                subscription.audio = payload[1..].to_vec();
+            },
+            _ => {},
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_spectrum_frame() {
+        let frame = spectrum_frame(145e6, 2e6, &[1.0, 1e-10, 0.0]);
+        assert_eq!(frame[0], SPECTRUM);
+        assert_eq!(u32::from_le_bytes(frame[1..5].try_into().unwrap()), 27);
+        assert_eq!(f64::from_le_bytes(frame[13..21].try_into().unwrap()), 145e6);
+        assert_eq!(&frame[29..], &[200, 100, 0]);
+    }
+
+    #[test]
+    fn test_authenticate() {
+        assert!(authenticate(&mut &frame(AUTH, b"secret")[..], "secret").is_ok());
+        assert!(authenticate(&mut &frame(AUTH, b"wrong!")[..], "secret").is_err());
+        assert!(authenticate(&mut &frame(PING, b"secret")[..], "secret").is_err());
+        assert!(authenticate(&mut &[][..], "secret").is_err());
+    }
+}
@@ -0,0 +1,116 @@
+//! Per-channel signal strength reports over UDP.
+//!
+//! Every interval, mean power of each enabled receive channel
+//! is sent as one JSON datagram, so dashboards can show an
+//! S-meter for each demodulator. Power is in dB relative to
+//! full scale, and with --calibration also in dBm together
+//! with S units. A report looks like:
+//! ```text
+//! {"time":1700000000.1,"channels":[{"channel":"rx0","frequency":145500000.0,
+//!  "rssi_dbfs":-53.2,"rssi_dbm":-90.1,"s_meter":"S6"}]}
+//! ```
+
+use std::net::UdpSocket;
+use std::time::{Duration, Instant};
+
+use serde_json::{json, Value};
+
+use crate::{Sample, ComplexSample};
+use crate::calibration;
+use crate::clock;
+use crate::rxthings::audio_udp_socket;
+
+/// Mean power of a channel since the previous report.
+#[derive(Default)]
+pub struct RssiMeter {
+    power_sum: f64,
+    blocks: usize,
+}
+
+impl RssiMeter {
+    /// Add a block of channel samples.
+    pub fn add(&mut self, samples: &[ComplexSample]) {
+        if samples.is_empty() {
+            return;
+        }
+        self.power_sum += (samples.iter().map(|s| s.norm_sqr()).sum::<Sample>() / samples.len() as Sample) as f64;
+        self.blocks += 1;
+    }
+
+    /// Return mean power in dB and reset,
+    /// or None if no blocks have been added.
+    fn take(&mut self) -> Option<f64> {
+        let power = (self.blocks > 0).then(|| 10.0 * (self.power_sum / self.blocks as f64 + 1e-20).log10());
+        *self = Self::default();
+        power
+    }
+}
+
+/// S-meter reading for a power in dBm. S9 is -73 dBm above 30 MHz
+/// and -93 dBm below, each S unit is 6 dB and readings above S9
+/// are given in dB over S9.
+fn s_meter(frequency: f64, dbm: f64) -> String {
+    let s9 = if frequency >= 30e6 { -73.0 } else { -93.0 };
+    if dbm > s9 {
+        format!("S9+{:.0}", dbm - s9)
+    } else {
+        format!("S{:.0}", (9.0 + (dbm - s9) / 6.0).max(0.0).floor())
+    }
+}
+
+pub struct RssiReporter {
+    socket: UdpSocket,
+    interval: Duration,
+    last_report: Instant,
+}
+
+impl RssiReporter {
+    /// Send reports to given UDP address at given interval in seconds.
+    pub fn new(address: &str, interval: f64) -> Self {
+        Self {
+            socket: audio_udp_socket(address),
+            interval: Duration::from_secs_f64(interval),
+            last_report: Instant::now(),
+        }
+    }
+
+    /// Send a report if interval has passed.
+    /// Channels are given as name, frequency and meter.
+    pub fn update<'a>(&mut self, channels: impl Iterator<Item = (String, f64, &'a mut RssiMeter)>) {
+        if self.last_report.elapsed() < self.interval {
+            return;
+        }
+        self.last_report = Instant::now();
+        let channels: Vec<Value> = channels.filter_map(|(name, frequency, meter)| {
+            let dbfs = meter.take()?;
+            let mut report = json!({ "channel": name, "frequency": frequency, "rssi_dbfs": dbfs });
+            if let Some(offset) = calibration::dbm_offset(frequency) {
+                let dbm = dbfs + offset as f64;
+                report["rssi_dbm"] = json!(dbm);
+                report["s_meter"] = json!(s_meter(frequency, dbm));
+            }
+            Some(report)
+        }).collect();
+        let report = json!({ "time": clock::now_seconds(), "channels": channels });
+        // TODO: print a warning or something if writing to socket fails
+        let _ = self.socket.send(report.to_string().as_bytes());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_s_meter() {
+        assert_eq!(s_meter(145e6, -73.0), "S9");
+        assert_eq!(s_meter(145e6, -80.0), "S7");
+        assert_eq!(s_meter(145e6, -53.0), "S9+20");
+        assert_eq!(s_meter(7e6, -93.0), "S9");
+        assert_eq!(s_meter(7e6, -150.0), "S0");
+        let mut meter = RssiMeter::default();
+        assert_eq!(meter.take(), None);
+        meter.add(&[ComplexSample::new(0.1, 0.0); 10]);
+        assert!((meter.take().unwrap() + 20.0).abs() < 1e-3);
+    }
+}
@@ -1,51 +1,316 @@
 
-use rustfft;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use serde_json::json;
 use crate::{Sample, ComplexSample};
+use crate::afc;
 use crate::configuration;
 use crate::fcfb;
+use crate::filter;
+use crate::graph;
+use crate::injection;
+use crate::metrics;
+use crate::rssi;
+use crate::remote;
+use crate::sigmf;
+use crate::occupancy;
 use crate::rxthings;
 
 
+/// State of a receive channel reported by the control API.
+pub struct ChannelStatus {
+    pub frequency: f64,
+    pub enabled: bool,
+    /// Offset currently corrected by AFC, if enabled.
+    pub afc_offset: Option<f64>,
+}
+
+/// Where a channel gets its signal from.
+enum ChannelSource {
+    /// Output processor of the single-stage analysis filter bank.
+    Output(fcfb::AnalysisOutputProcessor),
+    /// Index of an additional filter bank and index of channel in it.
+    Bank(usize, usize),
+    /// Index of channel in the two-stage channelizer.
+    TwoStage(usize),
+}
+
 struct RxChannel {
-    fcfb_output: fcfb::AnalysisOutputProcessor,
+    source: ChannelSource,
     processor: Box<dyn rxthings::RxChannelProcessor>,
+    /// Disabled channels are not processed.
+    enabled: bool,
+    /// Channels outside of the current span are not processed.
+    /// Only happens when SDR is retuned.
+    in_span: bool,
+    /// Whether a processor that retunes itself, like a scanner,
+    /// has moved outside of the span. It is then given silence
+    /// instead of a signal aliased from another frequency.
+    silent: bool,
+    /// Buffer of zeros for silent channels.
+    silence: Vec<ComplexSample>,
+    /// Center frequency of the channel in received signal.
+    /// Differs from frequency of the processor
+    /// if channel has its own converter offset.
+    center_frequency: f64,
+    /// Converter offset of the channel.
+    lo_offset: f64,
+    metrics: metrics::ChannelMetrics,
+    rssi: rssi::RssiMeter,
+    /// Delay for aligning the channel with other channels.
+    delay: Option<filter::DelayLine<ComplexSample>>,
+    /// Automatic frequency control, if enabled.
+    afc: Option<afc::Afc>,
 }
 
 impl RxChannel {
     fn new(
         fft_planner: &mut rustfft::FftPlanner<Sample>,
-        analysis_in_params: fcfb::AnalysisInputParameters,
+        front_end: &mut RxFrontEnd,
+        bin_spacing: Option<f64>,
+        lo_offset: f64,
+        masks: &[fcfb::FrequencyMask],
         processor: Box<dyn rxthings::RxChannelProcessor>,
     ) -> Self {
+        let center_frequency = processor.input_center_frequency() - lo_offset;
         Self {
-            fcfb_output: fcfb::AnalysisOutputProcessor::new_with_frequency(
+            source: ChannelSource::new(
                 fft_planner,
-                analysis_in_params,
+                front_end,
+                bin_spacing,
                 processor.input_sample_rate(),
-                processor.input_center_frequency(),
+                center_frequency,
+                masks,
             ),
             processor,
+            enabled: true,
+            in_span: true,
+            silent: false,
+            silence: Vec::new(),
+            center_frequency,
+            lo_offset,
+            metrics: metrics::ChannelMetrics::default(),
+            rssi: rssi::RssiMeter::default(),
+            delay: None,
+            afc: None,
         }
     }
 
-    fn process(
-        &mut self,
-        intermediate_result: &fcfb::AnalysisIntermediateResult
-    ) {
-        self.processor.process(self.fcfb_output.process(intermediate_result));
+    /// Retune the channel if its processor has changed
+    /// its input center frequency, as scanners do.
+    /// Only supported for outputs of the single-stage filter bank.
+    /// Frequencies outside of the span given by SDR sample rate
+    /// and center frequency would alias, so the channel is
+    /// silenced until it retunes within the span again.
+    fn follow_processor(&mut self, sdr_sample_rate: f64, sdr_center_frequency: f64) {
+        let center_frequency = self.processor.input_center_frequency() - self.lo_offset;
+        if center_frequency == self.center_frequency {
+            return;
+        }
+        if let ChannelSource::Output(output) = &mut self.source {
+            self.silent = !fcfb::channel_fits(sdr_sample_rate, sdr_center_frequency,
+                self.processor.input_sample_rate(), center_frequency);
+            if !self.silent {
+                output.set_center_frequency(center_frequency);
+            }
+            self.center_frequency = center_frequency;
+        }
+    }
+}
+
+impl ChannelSource {
+    fn new(
+        fft_planner: &mut rustfft::FftPlanner<Sample>,
+        front_end: &mut RxFrontEnd,
+        bin_spacing: Option<f64>,
+        sample_rate: f64,
+        center_frequency: f64,
+        masks: &[fcfb::FrequencyMask],
+    ) -> Self {
+        let source = match (&mut *front_end, bin_spacing) {
+            (RxFrontEnd::Single { analysis_params, .. }, None) =>
+                ChannelSource::Output(fcfb::AnalysisOutputProcessor::new_with_frequency(
+                    fft_planner,
+                    *analysis_params,
+                    sample_rate,
+                    center_frequency,
+                )),
+            (RxFrontEnd::Single { analysis_params, banks, .. }, Some(bin_spacing)) => {
+                let bank = match banks.iter().position(|(spacing, _)| *spacing == bin_spacing) {
+                    Some(bank) => bank,
+                    None => {
+                        banks.push((bin_spacing, fcfb::Channelizer::new(
+                            fft_planner,
+                            analysis_params.sample_rate,
+                            analysis_params.center_frequency,
+                            bin_spacing,
+                        )));
+                        banks.len() - 1
+                    }
+                };
+                ChannelSource::Bank(bank, banks[bank].1.add_channel(fft_planner, sample_rate, center_frequency))
+            },
+            (RxFrontEnd::TwoStage { channelizer, .. }, None) =>
+                ChannelSource::TwoStage(channelizer.add_channel(fft_planner, sample_rate, center_frequency)),
+            (RxFrontEnd::TwoStage { channelizer, .. }, Some(bin_spacing)) =>
+                ChannelSource::TwoStage(channelizer.add_channel_with_bin_spacing(fft_planner, sample_rate, center_frequency, bin_spacing)),
+        };
+        if masks.is_empty() {
+            return source;
+        }
+        match (source, front_end) {
+            (ChannelSource::Output(mut output), _) => {
+                output.apply_mask(masks);
+                ChannelSource::Output(output)
+            },
+            (ChannelSource::Bank(bank, index), RxFrontEnd::Single { banks, .. }) => {
+                banks[bank].1.apply_mask(index, masks);
+                ChannelSource::Bank(bank, index)
+            },
+            (ChannelSource::TwoStage(index), RxFrontEnd::TwoStage { channelizer, .. }) => {
+                channelizer.apply_mask(index, masks);
+                ChannelSource::TwoStage(index)
+            },
+            (source, _) => source,
+        }
     }
 }
 
+/// Analysis filter bank for complex or real received signal.
+enum AnalysisBank {
+    Complex(fcfb::AnalysisInputProcessor),
+    /// Real filter bank with a buffer for the in-phase part of input.
+    Real(fcfb::RealAnalysisInputProcessor, Vec<Sample>),
+}
+
+impl AnalysisBank {
+    fn process(&mut self, input: &[ComplexSample]) -> &fcfb::AnalysisIntermediateResult {
+        match self {
+            AnalysisBank::Complex(bank) => bank.process(input),
+            AnalysisBank::Real(bank, buffer) => {
+                buffer.clear();
+                buffer.extend(input.iter().map(|sample| sample.re));
+                bank.process(buffer)
+            },
+        }
+    }
+}
+
+/// Filter bank structure splitting the received signal into channels.
+enum RxFrontEnd {
+    /// Single analysis filter bank.
+    Single {
+        /// Input parameters for analysis filter bank.
+        analysis_params: fcfb::AnalysisInputParameters,
+        /// Analysis filter bank for received signal.
+        analysis_bank: AnalysisBank,
+        /// Input buffer for signal from SDR to filter bank.
+        input_buffer: fcfb::InputBuffer,
+        /// Additional filter banks with other bin spacings,
+        /// fed with the same input signal.
+        banks: Vec<(f64, fcfb::Channelizer)>,
+    },
+    /// Two-stage channelizer for very wideband signals.
+    TwoStage {
+        channelizer: fcfb::TwoStageChannelizer,
+        /// Input buffer for signal from SDR to channelizer.
+        input_buffer: Vec<ComplexSample>,
+    },
+}
+
+impl RxFrontEnd {
+    fn new(
+        fft_planner: &mut rustfft::FftPlanner<Sample>,
+        cli: &configuration::Cli,
+        sdr_rx_sample_rate: f64,
+        sdr_rx_center_frequency: f64,
+    ) -> Self {
+        let bin_spacing = cli.rx_bin_spacing;
+
+        if let Some(segment_sample_rate) = cli.rx_segment_sample_rate {
+            let channelizer = fcfb::TwoStageChannelizer::new(
+                fft_planner,
+                sdr_rx_sample_rate,
+                sdr_rx_center_frequency,
+                cli.rx_coarse_bin_spacing,
+                segment_sample_rate,
+                bin_spacing,
+            );
+            let input_buffer = vec![ComplexSample::ZERO; channelizer.input_block_size()];
+            RxFrontEnd::TwoStage { channelizer, input_buffer }
+        } else {
+            let analysis_params = fcfb::AnalysisInputParameters {
+                fft_size: (sdr_rx_sample_rate / bin_spacing).round() as usize,
+                sample_rate: sdr_rx_sample_rate,
+                center_frequency: sdr_rx_center_frequency,
+            };
+            // Real input is still buffered as complex samples,
+            // so that recording and other filter banks work the same.
+            let (analysis_bank, input_buffer) = if cli.sdr_rx_real {
+                let bank = fcfb::RealAnalysisInputProcessor::new(fft_planner, analysis_params);
+                let input_buffer = fcfb::InputBuffer::new(bank.input_block_size());
+                (AnalysisBank::Real(bank, Vec::new()), input_buffer)
+            } else {
+                let bank = fcfb::AnalysisInputProcessor::new(fft_planner, analysis_params);
+                let input_buffer = bank.make_input_buffer();
+                (AnalysisBank::Complex(bank), input_buffer)
+            };
+            RxFrontEnd::Single { analysis_params, analysis_bank, input_buffer, banks: Vec::new() }
+        }
+    }
+}
+
+/// Input sample rate of all current receive channel processors.
+pub const CHANNEL_SAMPLE_RATE: f64 = rxthings::demodulator::SAMPLE_RATE;
+
+/// Maximum channel delay in seconds that can be set
+/// using the control API, to limit memory used by delay lines.
+pub const MAX_CHANNEL_DELAY: f64 = 60.0;
+
+/// Number of new samples in each processing block
+/// for given receive sample rate.
+pub fn block_size(cli: &configuration::Cli, sdr_rx_sample_rate: f64) -> usize {
+    let bin_spacing = if cli.rx_segment_sample_rate.is_some() {
+        cli.rx_coarse_bin_spacing
+    } else {
+        cli.rx_bin_spacing
+    };
+    // 50% overlap
+    (sdr_rx_sample_rate / bin_spacing).round() as usize / 2
+}
+
 /// Everything related to received signal processing.
 pub struct RxDsp {
-    /// Input parameters for analysis filter bank.
-    analysis_params: fcfb::AnalysisInputParameters,
-    /// Analysis filter bank for received signal.
-    analysis_bank: fcfb::AnalysisInputProcessor,
-    /// Input buffer for signal from SDR to filter bank.
-    input_buffer: fcfb::InputBuffer,
+    front_end: RxFrontEnd,
     /// Receive channel processors.
     processors: Vec<RxChannel>,
+    /// Channel occupancy scanners.
+    occupancy: Vec<occupancy::OccupancyScanner>,
+    /// Sample rate of received signal.
+    sample_rate: f64,
+    /// Center frequency of received signal.
+    center_frequency: f64,
+    /// Test signals added to received signal.
+    test_signals: Vec<injection::TestSignal>,
+    /// Number of samples written to the current input block so far.
+    input_filled: usize,
+    /// Whether overlap has been moved for the current input block.
+    input_prepared: bool,
+    /// Number of received samples still to be discarded.
+    input_discard: usize,
+    /// Whether samples have been lost since the latest processed block.
+    discontinuity: bool,
+    /// Reporter of channel metrics, if enabled.
+    metrics: Option<metrics::MetricsReporter>,
+    /// Reporter of channel signal strength, if enabled.
+    rssi: Option<rssi::RssiReporter>,
+    /// Spectrum for remote heads, if enabled.
+    spectrum: Option<remote::SpectrumSender>,
+    /// Recorder of received signal, if enabled.
+    input_recorder: Option<sigmf::SigmfRecorder>,
+    /// Whether received signal is real.
+    real_input: bool,
 }
 
 impl RxDsp {
@@ -54,62 +319,1232 @@ impl RxDsp {
         cli: &configuration::Cli,
         sdr_rx_sample_rate: f64,
         sdr_rx_center_frequency: f64,
-    ) -> Self {
-        let bin_spacing = cli.rx_bin_spacing;
-
-        let analysis_params = fcfb::AnalysisInputParameters {
-            fft_size: (sdr_rx_sample_rate / bin_spacing).round() as usize,
+    ) -> Result<Self, String> {
+        let front_end = RxFrontEnd::new(fft_planner, cli, sdr_rx_sample_rate, sdr_rx_center_frequency);
+        let mut self_ = Self {
+            front_end,
+            processors: Vec::new(),
+            occupancy: Vec::new(),
             sample_rate: sdr_rx_sample_rate,
             center_frequency: sdr_rx_center_frequency,
+            test_signals: Vec::new(),
+            input_filled: 0,
+            input_prepared: false,
+            discontinuity: false,
+            input_discard: 0,
+            metrics: cli.metrics_interval.map(metrics::MetricsReporter::new),
+            rssi: cli.rssi_to_udp.as_deref().map(|address| rssi::RssiReporter::new(address, cli.rssi_interval)),
+            spectrum: None,
+            input_recorder: None,
+            real_input: cli.sdr_rx_real,
         };
-        let analysis_bank = fcfb::AnalysisInputProcessor::new(fft_planner, analysis_params);
-        let input_buffer = analysis_bank.make_input_buffer();
-        let mut self_ = Self {
-            analysis_params,
-            analysis_bank,
-            input_buffer,
-            processors: Vec::new(),
+        self_.add_processors_from_cli(fft_planner, cli)?;
+        // With retuning, channels only need to fit in some of the spans.
+        let out_of_span = if cli.rx_hop_frequencies.is_some() {
+            self_.update_in_span();
+            0
+        } else {
+            self_.processors.iter().enumerate()
+                .filter(|(index, _)| !self_.check_span(*index)).count()
         };
-        self_.add_processors_from_cli(fft_planner, cli);
-        self_
+        if out_of_span > 0 {
+            return Err(format!("{} receive channels are outside of SDR span", out_of_span));
+        }
+        for name in cli.disabled_channels.iter() {
+            if let Some(index) = name.strip_prefix("rx").and_then(|index| index.parse().ok()) {
+                self_.set_channel_enabled(index, false);
+            }
+        }
+        self_.add_occupancy_scanners_from_cli(cli)?;
+        if let (true, RxFrontEnd::Single { analysis_params, .. }) = (remote::enabled(), &self_.front_end) {
+            self_.spectrum = Some(remote::SpectrumSender::new(*analysis_params));
+        }
+        if let RxFrontEnd::TwoStage { channelizer, .. } = &self_.front_end {
+            eprintln!("Using {} segments for {} receive channels",
+                channelizer.number_of_segments(), self_.processors.len());
+        }
+        Ok(self_)
+    }
+
+    /// Record received signal before any processing.
+    pub fn record_input(&mut self, recorder: sigmf::SigmfRecorder) {
+        self.input_recorder = Some(recorder);
     }
 
     fn add_processors_from_cli(
         &mut self,
         fft_planner: &mut rustfft::FftPlanner<Sample>,
         cli: &configuration::Cli
-    ) {
+    ) -> Result<(), String> {
         for args in cli.demodulate_to_udp.chunks_exact(3) {
-            self.processors.push(RxChannel::new(
+            self.add_channel(
                 fft_planner,
-                self.analysis_params,
+                cli,
                 Box::new(rxthings::DemodulateToUdp::new(&rxthings::DemodulateToUdpParameters {
-                    center_frequency: args[1].parse().unwrap(),
+                    center_frequency: parse_number(&args[1])?,
+                    address: args[0].as_str(),
+                    modulation: parse_modulation(&args[2])?,
+                    chain: channel_chain(cli, self.processors.len()),
+                    frame_duration: cli.udp_frame_time * 1e-3,
+                    channel_filter: channel_filter(cli, self.processors.len(), parse_modulation(&args[2])?)?,
+                    squelch: channel_squelch(cli, self.processors.len())?,
+                    ctcss: channel_ctcss(cli, self.processors.len())?,
+                    vad: channel_vad(cli, self.processors.len())?,
+                    agc: channel_agc(cli, self.processors.len())?,
+                    squelch_zero_fill: cli.squelch_zero_fill,
+                })),
+            )?;
+        }
+        for args in cli.usrp_gateway.chunks_exact(5) {
+            self.add_channel(
+                fft_planner,
+                cli,
+                Box::new(rxthings::UsrpGatewayRx::new(&rxthings::UsrpGatewayRxParameters {
+                    center_frequency: parse_number(&args[2])?,
+                    address: args[1].as_str(),
+                    squelch_level: parse_number(&args[4])?,
+                })),
+            )?;
+        }
+        for args in cli.ber_test_rx.chunks_exact(3) {
+            self.add_channel(
+                fft_planner,
+                cli,
+                Box::new(rxthings::BerTestRx::new(&rxthings::BerTestParameters {
+                    center_frequency: parse_number(&args[0])?,
+                    symbol_rate: parse_number(&args[1])?,
+                    deviation: parse_number(&args[2])?,
+                })),
+            )?;
+        }
+        for args in cli.response_test_rx.chunks_exact(2) {
+            self.add_channel(
+                fft_planner,
+                cli,
+                Box::new(rxthings::ResponseTestRx::new(parse_number(&args[0])?, &args[1])),
+            )?;
+        }
+        for args in cli.demodulate_to_pipewire.chunks_exact(3) {
+            let modulation = parse_modulation(&args[2])?;
+            self.add_channel(
+                fft_planner,
+                cli,
+                Box::new(rxthings::DemodulateToPipewire::new(&rxthings::DemodulateToPipewireParameters {
+                    name: args[0].as_str(),
+                    center_frequency: parse_number(&args[1])?,
+                    modulation,
+                    channel_filter: channel_filter(cli, self.processors.len(), modulation)?,
+                    chain: channel_chain(cli, self.processors.len()),
+                })),
+            )?;
+        }
+        for args in cli.demodulate_to_remote.chunks_exact(2) {
+            let modulation = parse_modulation(&args[1])?;
+            self.add_channel(
+                fft_planner,
+                cli,
+                Box::new(rxthings::DemodulateToRemote::new(&rxthings::DemodulateToRemoteParameters {
+                    center_frequency: parse_number(&args[0])?,
+                    modulation,
+                    channel_filter: channel_filter(cli, self.processors.len(), modulation)?,
+                    modulation_name: args[1].as_str(),
+                    chain: channel_chain(cli, self.processors.len()),
+                })),
+            )?;
+        }
+        for args in cli.write_to_pipe.chunks_exact(3) {
+            let modulation = parse_output_modulation(&args[2])?;
+            self.add_channel(
+                fft_planner,
+                cli,
+                Box::new(rxthings::WriteToPipe::new(&rxthings::WriteToPipeParameters {
+                    path: args[0].as_str(),
+                    center_frequency: parse_number(&args[1])?,
+                    demodulation: modulation.map(|modulation| channel_filter(cli, self.processors.len(), modulation)
+                        .map(|channel_filter| (modulation, channel_filter))).transpose()?,
+                    chain: channel_chain(cli, self.processors.len()),
+                    iq_format: channel_iq_format(cli, self.processors.len())?,
+                })),
+            )?;
+        }
+        for args in cli.decode_with_process.chunks_exact(3) {
+            let modulation = parse_output_modulation(&args[1])?;
+            self.add_channel(
+                fft_planner,
+                cli,
+                Box::new(rxthings::DecodeWithProcess::new(&rxthings::DecodeWithProcessParameters {
+                    center_frequency: parse_number(&args[0])?,
+                    demodulation: modulation.map(|modulation| channel_filter(cli, self.processors.len(), modulation)
+                        .map(|channel_filter| (modulation, channel_filter))).transpose()?,
+                    command: args[2].as_str(),
+                    chain: channel_chain(cli, self.processors.len()),
+                    iq_format: channel_iq_format(cli, self.processors.len())?,
+                })),
+            )?;
+        }
+        for args in cli.iq_to_udp.chunks_exact(3) {
+            self.add_channel(
+                fft_planner,
+                cli,
+                Box::new(rxthings::IqToUdp::new(
+                    args[0].as_str(),
+                    parse_number(&args[1])?,
+                    parse_number(&args[2])?,
+                    channel_iq_format(cli, self.processors.len())?,
+                )),
+            )?;
+        }
+        for args in cli.iq_to_sigmf.chunks_exact(3) {
+            self.add_channel(
+                fft_planner,
+                cli,
+                Box::new(rxthings::IqToSigmf::new(
+                    args[0].as_str(),
+                    parse_number(&args[1])?,
+                    parse_number(&args[2])?,
+                )),
+            )?;
+        }
+        for args in cli.ka9q_output.chunks_exact(3) {
+            let (modulation, sample_rate) = parse_ka9q_mode(&args[2])?;
+            self.add_channel(
+                fft_planner,
+                cli,
+                Box::new(rxthings::Ka9qOutput::new(rxthings::Ka9qOutputParameters {
                     address: args[0].as_str(),
-                    modulation: match args[2].to_uppercase().as_str() {
-                        "FM"  => rxthings::Modulation::FM,
-                        "USB" => rxthings::Modulation::USB,
-                        "LSB" => rxthings::Modulation::LSB,
-                        // TODO: handle errors more nicely
-                        _ => panic!("Unknown modulation {}", args[2]),
-                    },
+                    frequency: parse_number(&args[1])?,
+                    demodulation: modulation.map(|modulation| channel_filter(cli, self.processors.len(), modulation)
+                        .map(|channel_filter| (modulation, channel_filter))).transpose()?,
+                    sample_rate,
                 })),
-            ));
+            )?;
+        }
+        for (stream_id, args) in cli.difi_to_udp.chunks_exact(3).enumerate() {
+            self.add_channel(
+                fft_planner,
+                cli,
+                Box::new(rxthings::DifiToUdp::new(
+                    args[0].as_str(),
+                    stream_id as u32,
+                    parse_number(&args[1])?,
+                    parse_number(&args[2])?,
+                )),
+            )?;
         }
+        for args in cli.burst_capture.chunks_exact(4) {
+            self.add_channel(
+                fft_planner,
+                cli,
+                Box::new(rxthings::BurstCapture::new(&rxthings::BurstCaptureParameters {
+                    directory: args[0].as_str(),
+                    center_frequency: parse_number(&args[1])?,
+                    sample_rate: parse_number(&args[2])?,
+                    threshold_db: parse_number(&args[3])?,
+                    pretrigger: cli.burst_pretrigger,
+                })),
+            )?;
+        }
+        for args in cli.discriminator_to_udp.chunks_exact(2) {
+            self.add_channel(
+                fft_planner,
+                cli,
+                Box::new(rxthings::DiscriminatorToUdp::new(
+                    args[0].as_str(),
+                    parse_number(&args[1])?,
+                    channel_filter(cli, self.processors.len(), rxthings::Modulation::FM)?,
+                    cli.udp_frame_time * 1e-3,
+                )),
+            )?;
+        }
+        for args in cli.demodulate_wfm_to_udp.chunks_exact(2) {
+            self.add_channel(
+                fft_planner,
+                cli,
+                Box::new(rxthings::DemodulateWfmToUdp::new(
+                    args[0].as_str(),
+                    parse_number(&args[1])?,
+                    cli.udp_frame_time * 1e-3,
+                )),
+            )?;
+        }
+        for args in cli.decode_aprs.chunks_exact(2) {
+            self.add_channel(
+                fft_planner,
+                cli,
+                Box::new(rxthings::DecodeAprs::new(args[0].as_str(), parse_number(&args[1])?)),
+            )?;
+        }
+        for args in cli.decode_rtty.chunks_exact(4) {
+            self.add_channel(
+                fft_planner,
+                cli,
+                Box::new(rxthings::DecodeRtty::new(
+                    args[0].as_str(),
+                    parse_number(&args[1])?,
+                    parse_number(&args[2])?,
+                    parse_number(&args[3])?,
+                )),
+            )?;
+        }
+        for args in cli.decode_m17.chunks_exact(2) {
+            self.add_channel(
+                fft_planner,
+                cli,
+                Box::new(rxthings::DecodeM17::new(args[0].as_str(), parse_number(&args[1])?)),
+            )?;
+        }
+        for args in cli.decode_dtmf.chunks_exact(3) {
+            let modulation = parse_modulation(&args[2])?;
+            self.add_channel(
+                fft_planner,
+                cli,
+                Box::new(rxthings::DecodeDtmf::new(
+                    args[0].as_str(),
+                    parse_number(&args[1])?,
+                    modulation,
+                    channel_filter(cli, self.processors.len(), modulation)?,
+                )),
+            )?;
+        }
+        for args in cli.record_to_wav.chunks_exact(4) {
+            let modulation = parse_modulation(&args[2])?;
+            self.add_channel(
+                fft_planner,
+                cli,
+                Box::new(rxthings::RecordToWav::new(&rxthings::RecordToWavParameters {
+                    directory: args[0].as_str(),
+                    frequency: parse_number(&args[1])?,
+                    modulation,
+                    channel_filter: channel_filter(cli, self.processors.len(), modulation)?,
+                    squelch: rxthings::SquelchSettings::parse(&args[3])
+                        .ok_or_else(|| format!("Invalid squelch {}", args[3]))?,
+                    vad: channel_vad(cli, self.processors.len())?,
+                })),
+            )?;
+        }
+        for args in cli.record_alert.chunks_exact(5) {
+            if !cli.alert.chunks_exact(2).any(|rule| rule[0] == args[0]) {
+                return Err(format!("No --alert rule named {}", args[0]));
+            }
+            let modulation = parse_modulation(&args[3])?;
+            self.add_channel(
+                fft_planner,
+                cli,
+                Box::new(rxthings::RecordAlert::new(&rxthings::RecordAlertParameters {
+                    rule: args[0].as_str(),
+                    directory: args[1].as_str(),
+                    frequency: parse_number(&args[2])?,
+                    modulation,
+                    channel_filter: channel_filter(cli, self.processors.len(), modulation)?,
+                    duration: parse_number(&args[4])?,
+                })),
+            )?;
+        }
+        #[cfg(feature = "mumble")]
+        for args in cli.demodulate_to_mumble.chunks_exact(6) {
+            let modulation = parse_modulation(&args[4])?;
+            self.add_channel(
+                fft_planner,
+                cli,
+                Box::new(rxthings::DemodulateToMumble::new(&rxthings::DemodulateToMumbleParameters {
+                    center_frequency: parse_number(&args[3])?,
+                    modulation,
+                    channel_filter: channel_filter(cli, self.processors.len(), modulation)?,
+                    server: args[0].as_str(),
+                    channel: args[1].as_str(),
+                    label: args[2].as_str(),
+                    squelch_level: parse_number(&args[5])?,
+                })),
+            )?;
+        }
+        #[cfg(feature = "icecast")]
+        for args in cli.demodulate_to_icecast.chunks_exact(4) {
+            let modulation = parse_modulation(&args[2])?;
+            self.add_channel(
+                fft_planner,
+                cli,
+                Box::new(rxthings::DemodulateToIcecast::new(&rxthings::DemodulateToIcecastParameters {
+                    url: args[0].as_str(),
+                    center_frequency: parse_number(&args[1])?,
+                    modulation,
+                    channel_filter: channel_filter(cli, self.processors.len(), modulation)?,
+                    squelch_level: parse_number(&args[3])?,
+                })),
+            )?;
+        }
+        #[cfg(feature = "jack")]
+        for args in cli.demodulate_to_jack.chunks_exact(3) {
+            let modulation = parse_modulation(&args[2])?;
+            self.add_channel(
+                fft_planner,
+                cli,
+                Box::new(rxthings::DemodulateToJack::new(&rxthings::DemodulateToJackParameters {
+                    port: args[0].as_str(),
+                    center_frequency: parse_number(&args[1])?,
+                    modulation,
+                    channel_filter: channel_filter(cli, self.processors.len(), modulation)?,
+                    chain: channel_chain(cli, self.processors.len()),
+                })),
+            )?;
+        }
+        #[cfg(feature = "archive")]
+        for args in cli.record_archive.chunks_exact(5) {
+            let modulation = parse_modulation(&args[2])?;
+            self.add_channel(
+                fft_planner,
+                cli,
+                Box::new(rxthings::RecordArchive::new(&rxthings::RecordArchiveParameters {
+                    directory: args[0].as_str(),
+                    frequency: parse_number(&args[1])?,
+                    modulation,
+                    channel_filter: channel_filter(cli, self.processors.len(), modulation)?,
+                    squelch: rxthings::SquelchSettings::parse(&args[3])
+                        .ok_or_else(|| format!("Invalid squelch {}", args[3]))?,
+                    idle_bitrate: parse_number(&args[4])?,
+                })),
+            )?;
+        }
+        #[cfg(feature = "zmq")]
+        for args in cli.iq_to_zmq.chunks_exact(4) {
+            self.add_channel(
+                fft_planner,
+                cli,
+                Box::new(rxthings::IqToZmq::new(
+                    args[0].as_str(),
+                    zmq_topic(&args[1]),
+                    parse_number(&args[2])?,
+                    parse_number(&args[3])?,
+                    channel_iq_format(cli, self.processors.len())?,
+                )),
+            )?;
+        }
+        #[cfg(feature = "zmq")]
+        for args in cli.demodulate_to_zmq.chunks_exact(4) {
+            let modulation = parse_modulation(&args[3])?;
+            self.add_channel(
+                fft_planner,
+                cli,
+                Box::new(rxthings::DemodulateToZmq::new(&rxthings::DemodulateToZmqParameters {
+                    endpoint: args[0].as_str(),
+                    topic: zmq_topic(&args[1]),
+                    center_frequency: parse_number(&args[2])?,
+                    modulation,
+                    channel_filter: channel_filter(cli, self.processors.len(), modulation)?,
+                    chain: channel_chain(cli, self.processors.len()),
+                })),
+            )?;
+        }
+        for args in cli.vote_to_udp.chunks_exact(3) {
+            let modulation = parse_modulation(&args[1])?;
+            let frequencies: Vec<f64> = args[2].split(',').map(parse_number).collect::<Result<_, _>>()?;
+            let output = rxthings::VoterOutput::udp(args[0].as_str(), cli.udp_frame_time * 1e-3);
+            let voter = Rc::new(RefCell::new(rxthings::Voter::new(output, frequencies.len())));
+            for (index, &frequency) in frequencies.iter().enumerate() {
+                self.add_channel(
+                    fft_planner,
+                    cli,
+                    Box::new(rxthings::VoterReceiver::new(voter.clone(), index, frequency, modulation)),
+                )?;
+            }
+        }
+        for (repeater, args) in cli.vote_to_tx.chunks_exact(4).enumerate() {
+            let modulation = parse_modulation(&args[1])?;
+            let frequencies: Vec<f64> = args[2].split(',').map(parse_number).collect::<Result<_, _>>()?;
+            let squelch = rxthings::SquelchSettings::parse(&args[3])
+                .ok_or_else(|| format!("Invalid squelch {}", args[3]))?;
+            let output = rxthings::VoterOutput::repeater(repeater, parse_number(&args[0])?, squelch);
+            let voter = Rc::new(RefCell::new(rxthings::Voter::new(output, frequencies.len())));
+            for (index, &frequency) in frequencies.iter().enumerate() {
+                self.add_channel(
+                    fft_planner,
+                    cli,
+                    Box::new(rxthings::VoterReceiver::new(voter.clone(), index, frequency, modulation)),
+                )?;
+            }
+        }
+        for args in cli.stereo_to_udp.chunks_exact(3) {
+            let mixer = Rc::new(RefCell::new(rxthings::StereoMixer::new(args[0].as_str(), cli.udp_frame_time * 1e-3)));
+            for (side, channel) in args[1..].iter().enumerate() {
+                let (frequency, modulation) = parse_stereo_channel(channel)?;
+                self.add_channel(
+                    fft_planner,
+                    cli,
+                    Box::new(rxthings::StereoChannel::new(rxthings::StereoChannelParameters {
+                        mixer: mixer.clone(),
+                        side,
+                        center_frequency: frequency,
+                        modulation,
+                        channel_filter: channel_filter(cli, self.processors.len(), modulation)?,
+                        agc: channel_agc(cli, self.processors.len())?,
+                        chain: channel_chain(cli, self.processors.len()),
+                    })),
+                )?;
+            }
+        }
+        for args in cli.scan_to_udp.chunks_exact(5) {
+            let index = self.processors.len();
+            let modulation = parse_modulation(&args[2])?;
+            let frequencies: Vec<f64> = args[3].split(',').map(parse_number).collect::<Result<_, _>>()?;
+            let lo_offset = channel_lo_offset(cli, index)?;
+            // With retuning, frequencies are checked like other channels.
+            if let Some(frequency) = frequencies.iter().find(|&&frequency| cli.rx_hop_frequencies.is_none() && !fcfb::channel_fits(
+                self.sample_rate, self.center_frequency, rxthings::SAMPLE_RATE, frequency - lo_offset)) {
+                return Err(format!("Scanner frequency {} Hz is outside of SDR span", frequency));
+            }
+            self.add_channel(
+                fft_planner,
+                cli,
+                Box::new(rxthings::ScanToUdp::new(rxthings::ScanToUdpParameters {
+                    address: args[0].as_str(),
+                    frequency_destination: args[1].as_str(),
+                    frequencies,
+                    modulation,
+                    channel_filter: channel_filter(cli, index, modulation)?,
+                    squelch: rxthings::SquelchSettings::parse(&args[4])
+                        .ok_or_else(|| format!("Invalid squelch {}", args[4]))?,
+                    frame_duration: cli.udp_frame_time * 1e-3,
+                })),
+            )?;
+            if !matches!(self.processors[index].source, ChannelSource::Output(_)) {
+                return Err("Scanning is only supported on channels of the single-stage filter bank".to_string());
+            }
+        }
+        Ok(())
     }
 
     pub fn prepare_input_buffer(
         &mut self,
     ) -> &mut [ComplexSample] {
-        self.input_buffer.prepare_for_new_samples()
+        match &mut self.front_end {
+            RxFrontEnd::Single { input_buffer, .. } => input_buffer.prepare_for_new_samples(),
+            RxFrontEnd::TwoStage { input_buffer, .. } => &mut input_buffer[..],
+        }
+    }
+
+    /// Return the part of the current input block
+    /// not yet filled with received samples.
+    /// This allows filling a block from reads of any size
+    /// by calling input_written after each read.
+    pub fn input_space(
+        &mut self,
+    ) -> &mut [ComplexSample] {
+        if !self.input_prepared {
+            self.prepare_input_buffer();
+            self.input_prepared = true;
+        }
+        let filled = self.input_filled;
+        match &mut self.front_end {
+            RxFrontEnd::Single { input_buffer, .. } => &mut input_buffer.new_samples_mut()[filled..],
+            RxFrontEnd::TwoStage { input_buffer, .. } => &mut input_buffer[filled..],
+        }
+    }
+
+    /// Mark given number of samples written to the slice
+    /// returned by input_space. When the block becomes full,
+    /// process it and return true.
+    pub fn input_written(
+        &mut self,
+        mut samples: usize,
+    ) -> bool {
+        if self.input_discard > 0 {
+            let discarded = samples.min(self.input_discard);
+            self.input_discard -= discarded;
+            // Move the samples after discarded ones
+            // to where the discarded ones were.
+            self.input_space().copy_within(discarded..samples, 0);
+            samples -= discarded;
+        }
+        self.input_filled += samples;
+        let block_size = match &self.front_end {
+            RxFrontEnd::Single { input_buffer, .. } => input_buffer.new_samples().len(),
+            RxFrontEnd::TwoStage { input_buffer, .. } => input_buffer.len(),
+        };
+        if self.input_filled >= block_size {
+            self.input_filled = 0;
+            self.input_prepared = false;
+            self.process();
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Discard received samples for given time in seconds,
+    /// for example, while SDR settles after starting or retuning.
+    pub fn discard_input(
+        &mut self,
+        time: f64,
+    ) {
+        self.input_discard = (time * self.sample_rate).round() as usize;
+    }
+
+    /// Tell channel processors that samples have been lost,
+    /// for example due to an SDR overflow,
+    /// before they process the next block.
+    pub fn discontinuity(&mut self) {
+        self.discontinuity = true;
+    }
+
+    /// Number of samples written to the current input block so far.
+    pub fn input_filled(&self) -> usize {
+        self.input_filled
     }
 
     pub fn process(
         &mut self,
     ) {
-        let ir = self.analysis_bank.process(self.input_buffer.buffer());
-        for processor in self.processors.iter_mut() {
-            processor.process(ir);
+        let start = std::time::Instant::now();
+        let measure = self.metrics.is_some();
+        let measure_rssi = self.rssi.is_some();
+        let discontinuity = std::mem::take(&mut self.discontinuity);
+        if self.real_input {
+            // Clear the quadrature part so that every channel
+            // sees the same real signal, whichever filter bank it uses.
+            let new_samples = match &mut self.front_end {
+                RxFrontEnd::Single { input_buffer, .. } => input_buffer.new_samples_mut(),
+                RxFrontEnd::TwoStage { input_buffer, .. } => &mut input_buffer[..],
+            };
+            for sample in new_samples.iter_mut() {
+                sample.im = 0.0;
+            }
+        }
+        if let Some(recorder) = &mut self.input_recorder {
+            if discontinuity {
+                recorder.discontinuity();
+            }
+            recorder.write(match &self.front_end {
+                RxFrontEnd::Single { input_buffer, .. } => input_buffer.new_samples(),
+                RxFrontEnd::TwoStage { input_buffer, .. } => &input_buffer[..],
+            });
+        }
+        if !self.test_signals.is_empty() {
+            let new_samples = match &mut self.front_end {
+                RxFrontEnd::Single { input_buffer, .. } => input_buffer.new_samples_mut(),
+                RxFrontEnd::TwoStage { input_buffer, .. } => &mut input_buffer[..],
+            };
+            for signal in self.test_signals.iter_mut() {
+                signal.add_to(new_samples);
+            }
+        }
+        match &mut self.front_end {
+            RxFrontEnd::Single { analysis_bank, input_buffer, banks, .. } => {
+                let ir = analysis_bank.process(input_buffer.buffer());
+                for scanner in self.occupancy.iter_mut() {
+                    scanner.process(ir);
+                }
+                if let Some(spectrum) = &mut self.spectrum {
+                    spectrum.process(ir, self.center_frequency, self.sample_rate);
+                }
+                for (_, bank) in banks.iter_mut() {
+                    bank.push(input_buffer.new_samples());
+                }
+                for channel in self.processors.iter_mut().filter(|p| p.enabled && p.in_span) {
+                    if discontinuity {
+                        channel.processor.discontinuity();
+                    }
+                    let output = match &mut channel.source {
+                        ChannelSource::Output(output) => output.process(ir),
+                        // Banks with a larger bin spacing may not
+                        // produce output every time.
+                        ChannelSource::Bank(bank, index) => banks[*bank].1.output(*index),
+                        ChannelSource::TwoStage(_) => continue,
+                    };
+                    if output.is_empty() {
+                        continue;
+                    }
+                    let output = if channel.silent {
+                        channel.silence.clear();
+                        channel.silence.resize(output.len(), ComplexSample::ZERO);
+                        &channel.silence[..]
+                    } else {
+                        output
+                    };
+                    let output = match &mut channel.afc {
+                        Some(afc) => afc.process(output),
+                        None => output,
+                    };
+                    let output = match &mut channel.delay {
+                        Some(delay) => delay.process(output),
+                        None => output,
+                    };
+                    if measure {
+                        channel.metrics.add(output);
+                    }
+                    if measure_rssi {
+                        channel.rssi.add(output);
+                    }
+                    channel.processor.process(output);
+                    channel.follow_processor(self.sample_rate, self.center_frequency);
+                }
+            },
+            RxFrontEnd::TwoStage { channelizer, input_buffer } => {
+                channelizer.push(input_buffer);
+                for channel in self.processors.iter_mut().filter(|p| p.enabled && p.in_span) {
+                    if let ChannelSource::TwoStage(index) = channel.source {
+                        if discontinuity {
+                            channel.processor.discontinuity();
+                        }
+                        let mut output = channelizer.output(index);
+                        if let Some(afc) = &mut channel.afc {
+                            output = afc.process(output);
+                        }
+                        if let Some(delay) = &mut channel.delay {
+                            output = delay.process(output);
+                        }
+                        if measure {
+                            channel.metrics.add(output);
+                        }
+                        if measure_rssi {
+                            channel.rssi.add(output);
+                        }
+                        channel.processor.process(output);
+                    }
+                }
+            },
+        }
+        if let Some(reporter) = &mut self.metrics {
+            reporter.update(start.elapsed(), self.processors.iter_mut().enumerate()
+                .filter(|(_, channel)| channel.enabled && channel.in_span)
+                .map(|(index, channel)| (format!("rx{}", index), channel.center_frequency, &mut channel.metrics)));
+        }
+        if let Some(reporter) = &mut self.rssi {
+            reporter.update(self.processors.iter_mut().enumerate()
+                .filter(|(_, channel)| channel.enabled && channel.in_span)
+                .map(|(index, channel)| (format!("rx{}", index), channel.center_frequency, &mut channel.rssi)));
+        }
+    }
+
+    fn add_occupancy_scanners_from_cli(
+        &mut self,
+        cli: &configuration::Cli
+    ) -> Result<(), String> {
+        let RxFrontEnd::Single { analysis_params, .. } = &self.front_end else {
+            if !cli.occupancy.is_empty() {
+                eprintln!("Occupancy scanning is not supported with two-stage channelization");
+            }
+            return Ok(());
+        };
+        for args in cli.occupancy.chunks_exact(5) {
+            self.occupancy.push(occupancy::OccupancyScanner::new(*analysis_params, &occupancy::OccupancyParameters {
+                path: args[0].as_str(),
+                frequencies: args[1].as_str(),
+                bandwidth: parse_number(&args[2])?,
+                threshold: parse_number(&args[3])?,
+                interval: parse_number(&args[4])?,
+            }));
+        }
+        Ok(())
+    }
+
+    /// Add a test signal to received signal.
+    /// Level is in dB relative to full scale.
+    pub fn inject(&mut self, frequency: f64, level: Sample, modulation: injection::TestModulation) {
+        self.test_signals.push(injection::TestSignal::new(
+            self.sample_rate,
+            self.center_frequency,
+            frequency,
+            level,
+            modulation,
+        ));
+    }
+
+    /// Remove all test signals.
+    pub fn stop_injection(&mut self) {
+        self.test_signals.clear();
+    }
+
+    /// Add a receive channel for a processor.
+    fn add_channel(
+        &mut self,
+        fft_planner: &mut rustfft::FftPlanner<Sample>,
+        cli: &configuration::Cli,
+        processor: Box<dyn rxthings::RxChannelProcessor>,
+    ) -> Result<(), String> {
+        let bin_spacing = channel_bin_spacing(cli, self.processors.len())?
+            .filter(|&bin_spacing| bin_spacing != cli.rx_bin_spacing);
+        let lo_offset = channel_lo_offset(cli, self.processors.len())?;
+        let masks = channel_masks(cli, self.processors.len(), processor.input_center_frequency())?;
+        let mut channel = RxChannel::new(fft_planner, &mut self.front_end, bin_spacing, lo_offset, &masks, processor);
+        if let Some(args) = cli.channel_delay.chunks_exact(2).find(|args| args[0] == format!("rx{}", self.processors.len())) {
+            let delay: f64 = parse_number(&args[1])?;
+            channel.delay = Some(filter::DelayLine::new((delay * channel.processor.input_sample_rate()).round() as usize));
+        }
+        if let Some(args) = cli.afc.chunks_exact(2).find(|args| args[0] == format!("rx{}", self.processors.len())) {
+            channel.afc = Some(afc::Afc::new(channel.processor.input_sample_rate(), parse_number(&args[1])?));
+        }
+        self.processors.push(channel);
+        Ok(())
+    }
+
+    /// Retune to a new center frequency, keeping channel processors.
+    /// Channels outside of the new span are not processed
+    /// until retuned back to a span containing them.
+    pub fn retune(
+        &mut self,
+        fft_planner: &mut rustfft::FftPlanner<Sample>,
+        cli: &configuration::Cli,
+        center_frequency: f64,
+    ) {
+        self.front_end = RxFrontEnd::new(fft_planner, cli, self.sample_rate, center_frequency);
+        self.center_frequency = center_frequency;
+        if let Some(recorder) = &mut self.input_recorder {
+            recorder.retune(center_frequency);
+        }
+        // Settings of channels were checked when creating them.
+        for (index, channel) in self.processors.iter_mut().enumerate() {
+            let bin_spacing = channel_bin_spacing(cli, index).unwrap_or_default()
+                .filter(|&bin_spacing| bin_spacing != cli.rx_bin_spacing);
+            channel.source = ChannelSource::new(
+                fft_planner,
+                &mut self.front_end,
+                bin_spacing,
+                channel.processor.input_sample_rate(),
+                channel.center_frequency,
+                &channel_masks(cli, index, channel.processor.input_center_frequency()).unwrap_or_default(),
+            );
+        }
+        self.update_in_span();
+        // Scanners would report wrong frequencies after retuning.
+        self.occupancy.clear();
+        self.input_filled = 0;
+        self.input_prepared = false;
+    }
+
+    fn update_in_span(&mut self) {
+        for channel in self.processors.iter_mut() {
+            channel.in_span = fcfb::channel_fits(
+                self.sample_rate,
+                self.center_frequency,
+                channel.processor.input_sample_rate(),
+                channel.center_frequency,
+            );
+            // Filter bank output was created at the channel frequency.
+            channel.silent = false;
+        }
+    }
+
+    /// Check that a channel fits within the received span.
+    /// Print an error and return false if it does not.
+    fn check_span(&self, index: usize) -> bool {
+        let channel = &self.processors[index];
+        let sample_rate = channel.processor.input_sample_rate();
+        let frequency = channel.center_frequency;
+        let fits = fcfb::channel_fits(self.sample_rate, self.center_frequency, sample_rate, frequency);
+        if !fits {
+            eprintln!("Receive channel rx{} at {} Hz with bandwidth {} Hz \
+                is outside of SDR span {} Hz to {} Hz",
+                index, frequency, sample_rate,
+                self.center_frequency - self.sample_rate / 2.0,
+                self.center_frequency + self.sample_rate / 2.0);
+        }
+        fits
+    }
+
+    /// Add receive processing to a graph.
+    pub fn add_to_graph(&self, cli: &configuration::Cli, graph: &mut graph::Graph) {
+        graph.node("sdr_rx", "sdr", json!({
+            "sample_rate": self.sample_rate,
+            "center_frequency": self.center_frequency,
+        }));
+        match &self.front_end {
+            RxFrontEnd::Single { analysis_params, banks, .. } => {
+                graph.node("rx_bank", "analysis_filter_bank", json!({
+                    "fft_size": analysis_params.fft_size,
+                    "bin_spacing": analysis_params.sample_rate / analysis_params.fft_size as f64,
+                }));
+                graph.edge("sdr_rx", "rx_bank");
+                for (index, (bin_spacing, _)) in banks.iter().enumerate() {
+                    let id = format!("rx_bank{}", index + 1);
+                    graph.node(id.clone(), "analysis_filter_bank", json!({ "bin_spacing": bin_spacing }));
+                    graph.edge("sdr_rx", id);
+                }
+                for index in 0..self.occupancy.len() {
+                    let id = format!("occupancy{}", index);
+                    graph.node(id.clone(), "occupancy_scanner", json!({}));
+                    graph.edge("rx_bank", id);
+                }
+            },
+            RxFrontEnd::TwoStage { .. } => {
+                graph.node("rx_channelizer", "two_stage_channelizer", json!({
+                    "coarse_bin_spacing": cli.rx_coarse_bin_spacing,
+                    "segment_sample_rate": cli.rx_segment_sample_rate,
+                    "bin_spacing": cli.rx_bin_spacing,
+                }));
+                graph.edge("sdr_rx", "rx_channelizer");
+            },
+        }
+        let entries = channel_list(cli).unwrap_or_default();
+        for (index, channel) in self.processors.iter().enumerate() {
+            let id = format!("rx{}", index);
+            graph.node(id.clone(), "channel", json!({
+                "frequency": channel.center_frequency,
+                "sample_rate": channel.processor.input_sample_rate(),
+                "enabled": channel.enabled,
+            }));
+            graph.edge(match channel.source {
+                ChannelSource::Output(_) => "rx_bank".to_string(),
+                ChannelSource::Bank(bank, _) => format!("rx_bank{}", bank + 1),
+                ChannelSource::TwoStage(_) => "rx_channelizer".to_string(),
+            }, id.clone());
+            if let Some(entry) = entries.get(index) {
+                let output = format!("{}_output", id);
+                graph.node(output.clone(), entry.option, json!({ "arguments": entry.arguments }));
+                graph.edge(id, output);
+            }
+        }
+    }
+
+    /// Current state of each channel.
+    pub fn channels(&self) -> impl Iterator<Item = ChannelStatus> + '_ {
+        self.processors.iter().map(|channel| ChannelStatus {
+            frequency: channel.processor.input_center_frequency(),
+            enabled: channel.enabled,
+            afc_offset: channel.afc.as_ref().map(|afc| afc.frequency()),
+        })
+    }
+
+    /// Enable or disable a channel by its index.
+    /// Return false if there is no such channel.
+    pub fn set_channel_enabled(&mut self, index: usize, enabled: bool) -> bool {
+        match self.processors.get_mut(index) {
+            Some(channel) => { channel.enabled = enabled; true },
+            None => false,
+        }
+    }
+
+    /// Set delay of a channel in seconds.
+    /// Return the delay rounded to whole samples,
+    /// or None if there is no such channel.
+    pub fn set_channel_delay(&mut self, index: usize, delay: f64) -> Option<f64> {
+        let channel = self.processors.get_mut(index)?;
+        let sample_rate = channel.processor.input_sample_rate();
+        let samples = (delay * sample_rate).round() as usize;
+        match &mut channel.delay {
+            Some(delay) => delay.set_delay(samples),
+            None => channel.delay = Some(filter::DelayLine::new(samples)),
+        }
+        Some(samples as f64 / sample_rate)
+    }
+}
+
+/// Receive channel given on command line.
+pub struct ChannelEntry<'a> {
+    /// Option the channel was given with.
+    pub option: &'static str,
+    /// Arguments given to the option.
+    pub arguments: &'a [String],
+    /// Center frequency of the channel.
+    pub frequency: f64,
+    /// Other center frequencies the channel may retune to,
+    /// like the frequencies of a scanner after the first one.
+    pub other_frequencies: Vec<f64>,
+    /// Input sample rate of the channel.
+    pub sample_rate: f64,
+}
+
+/// Input sample rate of a channel given with an option and arguments.
+fn channel_sample_rate(option: &str, arguments: &[String]) -> Result<f64, String> {
+    match option {
+        "demodulate_wfm_to_udp" => Ok(rxthings::WFM_SAMPLE_RATE),
+        "iq_to_udp" | "iq_to_sigmf" | "difi_to_udp" | "burst_capture" => parse_number(&arguments[2]),
+        "iq_to_zmq" => parse_number(&arguments[3]),
+        "ka9q_output" => Ok(parse_ka9q_mode(&arguments[2])?.1),
+        _ => Ok(CHANNEL_SAMPLE_RATE),
+    }
+}
+
+/// Receive channels given on command line,
+/// in the order channels are created.
+/// Returns an error if a frequency or sample rate is invalid.
+pub fn channel_list<'a>(cli: &'a configuration::Cli) -> Result<Vec<ChannelEntry<'a>>, String> {
+    let mut list = Vec::new();
+    // Add channels of an option taking given number of arguments,
+    // with frequency being the argument at given index.
+    let mut add = |option: &'static str, values: &'a [String], count: usize, frequency: usize| -> Result<(), String> {
+        for arguments in values.chunks_exact(count) {
+            list.push(ChannelEntry {
+                option,
+                arguments,
+                frequency: parse_number(&arguments[frequency])?,
+                other_frequencies: Vec::new(),
+                sample_rate: channel_sample_rate(option, arguments)?,
+            });
+        }
+        Ok(())
+    };
+    add("demodulate_to_udp", &cli.demodulate_to_udp, 3, 1)?;
+    add("usrp_gateway", &cli.usrp_gateway, 5, 2)?;
+    add("ber_test_rx", &cli.ber_test_rx, 3, 0)?;
+    add("response_test_rx", &cli.response_test_rx, 2, 0)?;
+    add("demodulate_to_pipewire", &cli.demodulate_to_pipewire, 3, 1)?;
+    add("demodulate_to_remote", &cli.demodulate_to_remote, 2, 0)?;
+    add("write_to_pipe", &cli.write_to_pipe, 3, 1)?;
+    add("decode_with_process", &cli.decode_with_process, 3, 0)?;
+    add("iq_to_udp", &cli.iq_to_udp, 3, 1)?;
+    add("iq_to_sigmf", &cli.iq_to_sigmf, 3, 1)?;
+    add("ka9q_output", &cli.ka9q_output, 3, 1)?;
+    add("difi_to_udp", &cli.difi_to_udp, 3, 1)?;
+    add("burst_capture", &cli.burst_capture, 4, 1)?;
+    add("discriminator_to_udp", &cli.discriminator_to_udp, 2, 1)?;
+    add("demodulate_wfm_to_udp", &cli.demodulate_wfm_to_udp, 2, 1)?;
+    add("decode_aprs", &cli.decode_aprs, 2, 1)?;
+    add("decode_rtty", &cli.decode_rtty, 4, 1)?;
+    add("decode_m17", &cli.decode_m17, 2, 1)?;
+    add("decode_dtmf", &cli.decode_dtmf, 3, 1)?;
+    add("record_to_wav", &cli.record_to_wav, 4, 1)?;
+    add("record_alert", &cli.record_alert, 5, 2)?;
+    #[cfg(feature = "mumble")]
+    add("demodulate_to_mumble", &cli.demodulate_to_mumble, 6, 3)?;
+    #[cfg(feature = "icecast")]
+    add("demodulate_to_icecast", &cli.demodulate_to_icecast, 4, 1)?;
+    #[cfg(feature = "jack")]
+    add("demodulate_to_jack", &cli.demodulate_to_jack, 3, 1)?;
+    #[cfg(feature = "archive")]
+    add("record_archive", &cli.record_archive, 5, 1)?;
+    #[cfg(feature = "zmq")]
+    add("iq_to_zmq", &cli.iq_to_zmq, 4, 2)?;
+    #[cfg(feature = "zmq")]
+    add("demodulate_to_zmq", &cli.demodulate_to_zmq, 4, 2)?;
+    for arguments in cli.vote_to_udp.chunks_exact(3) {
+        for frequency in arguments[2].split(',') {
+            list.push(ChannelEntry {
+                option: "vote_to_udp",
+                arguments,
+                frequency: parse_number(frequency)?,
+                other_frequencies: Vec::new(),
+                sample_rate: CHANNEL_SAMPLE_RATE,
+            });
+        }
+    }
+    for arguments in cli.vote_to_tx.chunks_exact(4) {
+        for frequency in arguments[2].split(',') {
+            list.push(ChannelEntry {
+                option: "vote_to_tx",
+                arguments,
+                frequency: parse_number(frequency)?,
+                other_frequencies: Vec::new(),
+                sample_rate: CHANNEL_SAMPLE_RATE,
+            });
+        }
+    }
+    for arguments in cli.stereo_to_udp.chunks_exact(3) {
+        for channel in arguments[1..].iter() {
+            list.push(ChannelEntry {
+                option: "stereo_to_udp",
+                arguments,
+                frequency: parse_stereo_channel(channel)?.0,
+                other_frequencies: Vec::new(),
+                sample_rate: CHANNEL_SAMPLE_RATE,
+            });
+        }
+    }
+    // Scanners start at their first frequency.
+    for arguments in cli.scan_to_udp.chunks_exact(5) {
+        let frequencies: Vec<f64> = arguments[3].split(',').map(parse_number).collect::<Result<_, _>>()?;
+        list.push(ChannelEntry {
+            option: "scan_to_udp",
+            arguments,
+            frequency: frequencies[0],
+            other_frequencies: frequencies[1..].to_vec(),
+            sample_rate: CHANNEL_SAMPLE_RATE,
+        });
+    }
+    for (index, channel) in list.iter_mut().enumerate() {
+        let lo_offset = channel_lo_offset(cli, index)?;
+        channel.frequency -= lo_offset;
+        for frequency in channel.other_frequencies.iter_mut() {
+            *frequency -= lo_offset;
         }
     }
+    Ok(list)
+}
+
+/// Center frequencies and sample rates of receive channels
+/// given on command line, including every frequency of scanners.
+/// Channels are checked on startup, so invalid ones are left out.
+pub fn channel_bands(cli: &configuration::Cli) -> Vec<(f64, f64)> {
+    channel_list(cli).unwrap_or_default().iter().flat_map(|channel|
+        std::iter::once(channel.frequency).chain(channel.other_frequencies.iter().copied())
+            .map(|frequency| (frequency, channel.sample_rate))
+    ).collect()
+}
+
+/// Parse a number given in channel options.
+pub fn parse_number<T: std::str::FromStr>(text: &str) -> Result<T, String> {
+    text.parse().map_err(|_| format!("Invalid number {}", text))
+}
+
+/// Parse frequency and modulation of a side of a stereo output.
+fn parse_stereo_channel(text: &str) -> Result<(f64, rxthings::Modulation), String> {
+    let (frequency, modulation) = text.split_once(':')
+        .ok_or_else(|| format!("Invalid stereo channel {}", text))?;
+    Ok((parse_number(frequency)?, parse_modulation(modulation)?))
+}
+
+/// Parse modulation of a ka9q-radio output,
+/// or IQ:RATE giving the sample rate of IQ output.
+fn parse_ka9q_mode(text: &str) -> Result<(Option<rxthings::Modulation>, f64), String> {
+    match text.to_uppercase().strip_prefix("IQ:") {
+        Some(rate) => Ok((None, parse_number(rate)?)),
+        None => Ok((Some(parse_modulation(text)?), rxthings::SAMPLE_RATE)),
+    }
+}
+
+/// Find audio processing chain given for a receive channel.
+fn channel_chain(cli: &configuration::Cli, index: usize) -> Option<&str> {
+    let name = format!("rx{}", index);
+    cli.chain.chunks_exact(2).find(|args| args[0] == name).map(|args| args[1].as_str())
+}
+
+/// Find demodulator channel filter given for a receive channel,
+/// or the default for the modulation if none is given.
+fn channel_filter(cli: &configuration::Cli, index: usize, modulation: rxthings::Modulation) -> Result<rxthings::ChannelFilter, String> {
+    let name = format!("rx{}", index);
+    cli.channel_filter.chunks_exact(2).find(|args| args[0] == name).map_or(
+        Ok(rxthings::ChannelFilter::default_for(modulation)),
+        |args| rxthings::ChannelFilter::parse(&args[1], modulation)
+            .ok_or_else(|| format!("Invalid channel filter {}", args[1])))
+}
+
+/// Topic of a ZeroMQ output, where - means no topic.
+#[cfg(feature = "zmq")]
+fn zmq_topic(topic: &str) -> Option<&str> {
+    (topic != "-").then_some(topic)
+}
+
+/// Find IQ output format given for a receive channel.
+fn channel_iq_format(cli: &configuration::Cli, index: usize) -> Result<rxthings::IqFormat, String> {
+    let name = format!("rx{}", index);
+    cli.iq_format.chunks_exact(2).find(|args| args[0] == name).map_or(Ok(Default::default()), |args|
+        rxthings::IqFormat::parse(&args[1])
+        .ok_or_else(|| format!("Invalid IQ format {}", args[1])))
+}
+
+/// Find squelch settings given for a receive channel.
+fn channel_squelch(cli: &configuration::Cli, index: usize) -> Result<Option<rxthings::SquelchSettings>, String> {
+    let name = format!("rx{}", index);
+    cli.squelch.chunks_exact(2).find(|args| args[0] == name).map(|args|
+        rxthings::SquelchSettings::parse(&args[1])
+        .ok_or_else(|| format!("Invalid squelch {}", args[1]))).transpose()
+}
+
+/// Find CTCSS tone given for a receive channel.
+fn channel_ctcss(cli: &configuration::Cli, index: usize) -> Result<Option<f64>, String> {
+    let name = format!("rx{}", index);
+    cli.ctcss.chunks_exact(2).find(|args| args[0] == name).map(|args| parse_number(&args[1])).transpose()
+}
+
+/// Find voice activity detection settings given for a receive channel.
+fn channel_vad(cli: &configuration::Cli, index: usize) -> Result<Option<rxthings::VadSettings>, String> {
+    let name = format!("rx{}", index);
+    cli.vad.chunks_exact(2).find(|args| args[0] == name).map(|args|
+        rxthings::VadSettings::parse(&args[1])
+        .ok_or_else(|| format!("Invalid voice activity detection settings {}", args[1]))).transpose()
+}
+
+/// Find AGC settings given for a receive channel.
+fn channel_agc(cli: &configuration::Cli, index: usize) -> Result<Option<rxthings::AgcSettings>, String> {
+    let name = format!("rx{}", index);
+    cli.agc.chunks_exact(2).find(|args| args[0] == name).map(|args|
+        rxthings::AgcSettings::parse(&args[1])
+        .ok_or_else(|| format!("Invalid AGC settings {}", args[1]))).transpose()
+}
+
+/// Find converter offset given for a receive channel.
+fn channel_lo_offset(cli: &configuration::Cli, index: usize) -> Result<f64, String> {
+    let name = format!("rx{}", index);
+    cli.channel_lo_offset.chunks_exact(2).find(|args| args[0] == name).map_or(Ok(0.0), |args| parse_number(&args[1]))
+}
+
+/// Find frequency masks given for a receive channel,
+/// converted to be relative to channel center frequency.
+fn channel_masks(cli: &configuration::Cli, index: usize, center_frequency: f64) -> Result<Vec<fcfb::FrequencyMask>, String> {
+    let name = format!("rx{}", index);
+    let Some(args) = cli.channel_mask.chunks_exact(2).find(|args| args[0] == name) else {
+        return Ok(Vec::new());
+    };
+    args[1].split(',').map(|range| {
+        let parts: Vec<f64> = range.split(':').map(parse_number).collect::<Result<_, _>>()?;
+        match parts[..] {
+            [start, end] | [start, end, _] => Ok(fcfb::FrequencyMask {
+                start: start - center_frequency,
+                end: end - center_frequency,
+                gain: parts.get(2).map_or(0.0, |gain| 10.0f64.powf(gain / 20.0) as Sample),
+            }),
+            _ => Err(format!("Invalid channel mask {}", range)),
+        }
+    }).collect()
+}
+
+/// Find bin spacing given for a receive channel.
+fn channel_bin_spacing(cli: &configuration::Cli, index: usize) -> Result<Option<f64>, String> {
+    let name = format!("rx{}", index);
+    cli.channel_bin_spacing.chunks_exact(2).find(|args| args[0] == name).map(|args| parse_number(&args[1])).transpose()
+}
+
+fn parse_modulation(name: &str) -> Result<rxthings::Modulation, String> {
+    if let Some(parameters) = name.to_uppercase().strip_prefix("CW") {
+        return parse_cw(parameters)
+            .ok_or_else(|| format!("Invalid CW modulation {}", name));
+    }
+    match name.to_uppercase().as_str() {
+        "FM"  => Ok(rxthings::Modulation::FM),
+        "USB" => Ok(rxthings::Modulation::USB),
+        "LSB" => Ok(rxthings::Modulation::LSB),
+        "AM"  => Ok(rxthings::Modulation::AM),
+        "SAM" | "SYNCAM" => Ok(rxthings::Modulation::SyncAM),
+        _ => Err(format!("Unknown modulation {}", name)),
+    }
+}
+
+/// Default pitch of CW audio in Hertz.
+const CW_PITCH: f64 = 600.0;
+/// Default CW filter bandwidth in Hertz.
+const CW_BANDWIDTH: f64 = 500.0;
+
+/// Parse optional pitch and bandwidth of CW modulation,
+/// given as ":pitch" or ":pitch:bandwidth" after "CW".
+fn parse_cw(parameters: &str) -> Option<rxthings::Modulation> {
+    let mut values = parameters.split(':').skip(1).map(|value| value.parse::<f64>().ok());
+    if !parameters.is_empty() && !parameters.starts_with(':') {
+        return None;
+    }
+    let pitch = values.next().unwrap_or(Some(CW_PITCH))?;
+    let bandwidth = values.next().unwrap_or(Some(CW_BANDWIDTH))?;
+    // Pitch has to be above half of the bandwidth
+    // for signals below the carrier to be heard correctly.
+    (values.next().is_none() && bandwidth > 0.0 && pitch >= bandwidth / 2.0)
+        .then_some(rxthings::Modulation::CW { pitch, bandwidth })
+}
+
+/// Parse modulation of an output that can also be IQ.
+/// Returns None for IQ.
+fn parse_output_modulation(name: &str) -> Result<Option<rxthings::Modulation>, String> {
+    match name.to_uppercase().as_str() {
+        "IQ" => Ok(None),
+        _ => Ok(Some(parse_modulation(name)?)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+    use super::*;
+
+    #[test]
+    fn test_malformed_channels() {
+        let new = |args: &[&str]| {
+            let cli = configuration::Cli::parse_from([&["sdrglue"], args].concat());
+            RxDsp::new(&mut rustfft::FftPlanner::new(), &cli, 1e6, 145.2e6).err()
+        };
+        assert_eq!(new(&["--demodulate-to-udp", "127.0.0.1:7355", "145.5e6", "FM"]), None);
+        assert_eq!(new(&["--demodulate-to-udp", "127.0.0.1:7355", "145.5MHz", "FM"]),
+            Some("Invalid number 145.5MHz".to_string()));
+        assert_eq!(new(&["--demodulate-to-udp", "127.0.0.1:7355", "145.5e6", "FMM"]),
+            Some("Unknown modulation FMM".to_string()));
+        assert_eq!(new(&["--demodulate-to-udp", "127.0.0.1:7355", "145.5e6", "FM", "--channel-mask", "rx0", "145.5e6"]),
+            Some("Invalid channel mask 145.5e6".to_string()));
+        assert_eq!(new(&["--scan-to-udp", "127.0.0.1:7355", "-", "FM", "145.3e6,150e6", "-40"]),
+            Some("Scanner frequency 150000000 Hz is outside of SDR span".to_string()));
+    }
 }
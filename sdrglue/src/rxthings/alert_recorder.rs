@@ -0,0 +1,182 @@
+//! Recording of audio around watchlist alerts.
+//!
+//! A channel is demodulated continuously and the latest
+//! few seconds of audio are kept in memory. When the given
+//! watchlist rule raises an alert, a WAV file is written
+//! starting from the buffered audio, so the transmission
+//! that triggered the alert is included, and continuing
+//! for a given time after the latest alert.
+//! Files are named like those of --record-to-wav and reported
+//! as "recording" events with the rule name.
+
+use std::collections::VecDeque;
+
+use super::RxChannelProcessor;
+use super::demodulator::{ChannelFilter, Demodulator, Modulation, SAMPLE_RATE};
+use super::wav_recorder::Recording;
+use crate::{Sample, ComplexSample};
+use crate::clock;
+use crate::events::Event;
+use crate::watchlist;
+
+/// Length of audio recorded before an alert, in seconds.
+/// Also covers the delay of alerts being raised
+/// in the event handling thread.
+const PRE_TRIGGER: f64 = 2.0;
+
+pub struct RecordAlertParameters<'a> {
+    /// Name of the watchlist rule.
+    pub rule: &'a str,
+    /// Directory to write files to.
+    pub directory: &'a str,
+    pub frequency: f64,
+    pub modulation: Modulation,
+    pub channel_filter: ChannelFilter,
+    /// Time to record after an alert, in seconds.
+    pub duration: f64,
+}
+
+pub struct RecordAlert {
+    /// Input center frequency
+    center_frequency: f64,
+    frequency: f64,
+    rule: String,
+    directory: String,
+    demodulator: Demodulator,
+    audio_buffer: Vec<Sample>,
+    /// Latest audio, while not recording.
+    pre_trigger: VecDeque<Sample>,
+    /// Number of alerts of the rule seen so far.
+    alerts: u64,
+    /// Samples left to record after the latest alert.
+    remaining: usize,
+    duration: usize,
+    recording: Option<Recording>,
+}
+
+impl RecordAlert {
+    pub fn new(parameters: &RecordAlertParameters) -> Self {
+        Self {
+            center_frequency:
+                parameters.frequency
+                + Demodulator::frequency_offset(parameters.modulation),
+            frequency: parameters.frequency,
+            rule: parameters.rule.to_string(),
+            directory: parameters.directory.to_string(),
+            demodulator: Demodulator::with_filter(parameters.modulation,
+                parameters.channel_filter),
+            audio_buffer: Vec::with_capacity(48),
+            pre_trigger: VecDeque::new(),
+            alerts: watchlist::alert_count(parameters.rule),
+            remaining: 0,
+            duration: (parameters.duration * SAMPLE_RATE) as usize,
+            recording: None,
+        }
+    }
+
+    fn start_recording(&mut self) {
+        let time = clock::now_seconds() - (self.pre_trigger.len() + self.audio_buffer.len()) as f64 / SAMPLE_RATE;
+        let path = format!("{}/{}_{:.0}.wav", self.directory, clock::utc_timestamp(time), self.frequency);
+        match Recording::start(path.clone()) {
+            Ok(mut recording) => {
+                let (first, second) = self.pre_trigger.as_slices();
+                recording.write(first);
+                recording.write(second);
+                self.recording = Some(recording);
+            },
+            Err(err) => eprintln!("Error creating recording {}: {}", path, err),
+        }
+        self.pre_trigger.clear();
+    }
+
+    fn finish_recording(&mut self) {
+        let Some(recording) = self.recording.take() else { return };
+        recording.finish(Event::new("recording")
+            .with("frequency", self.frequency)
+            .with("rule", self.rule.as_str()));
+    }
+}
+
+impl RxChannelProcessor for RecordAlert {
+    fn process(&mut self, samples: &[ComplexSample]) {
+        self.audio_buffer.clear();
+        self.demodulator.process(samples, &mut self.audio_buffer);
+
+        let alerts = watchlist::alert_count(&self.rule);
+        if alerts != self.alerts {
+            self.alerts = alerts;
+            self.remaining = self.duration;
+            if self.recording.is_none() {
+                self.start_recording();
+            }
+        }
+
+        if let Some(recording) = &mut self.recording {
+            recording.write(&self.audio_buffer);
+            self.remaining = self.remaining.saturating_sub(self.audio_buffer.len());
+            if self.remaining == 0 {
+                self.finish_recording();
+            }
+        } else {
+            self.pre_trigger.extend(self.audio_buffer.iter());
+            let excess = self.pre_trigger.len().saturating_sub((PRE_TRIGGER * SAMPLE_RATE) as usize);
+            self.pre_trigger.drain(..excess);
+        }
+    }
+
+    fn input_sample_rate(&self) -> f64 {
+        SAMPLE_RATE
+    }
+
+    fn input_center_frequency(&self) -> f64 {
+        self.center_frequency
+    }
+
+    fn discontinuity(&mut self) {
+        self.demodulator.reset();
+    }
+}
+
+impl Drop for RecordAlert {
+    fn drop(&mut self) {
+        self.finish_recording();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::EventListener;
+
+    #[test]
+    fn test_alert_recording() {
+        let directory = std::env::temp_dir().join(format!("sdrglue-alert-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let rule = "test_alert_recording";
+        let mut recorder = RecordAlert::new(&RecordAlertParameters {
+            rule,
+            directory: directory.to_str().unwrap(),
+            frequency: 145.5e6,
+            modulation: Modulation::FM,
+            channel_filter: ChannelFilter::default_for(Modulation::FM),
+            duration: 1.0,
+        });
+        let mut watchlist = watchlist::Watchlist::from_args(&[rule.to_string(), "event=test".to_string()]);
+        let block = vec![ComplexSample::new(1.0, 0.0); 48];
+        // 3 seconds before the alert, of which 2 are kept.
+        for _ in 0..3000 {
+            recorder.process(&block);
+        }
+        watchlist.event(&Event::new("test"));
+        for _ in 0..3000 {
+            recorder.process(&block);
+        }
+        let files: Vec<_> = std::fs::read_dir(&directory).unwrap().map(|entry| entry.unwrap().path()).collect();
+        let data = std::fs::read(&files[0]).unwrap();
+        std::fs::remove_dir_all(&directory).unwrap();
+
+        assert_eq!(files.len(), 1);
+        // 2 seconds before and 1 second after the alert of 16-bit samples.
+        assert!((data.len() as isize - 44 - 3 * 96000).abs() <= 200, "{}", data.len());
+    }
+}
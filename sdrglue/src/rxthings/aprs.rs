@@ -0,0 +1,401 @@
+//! APRS decoder for AFSK1200 packet radio with a KISS TNC interface.
+//!
+//! Bell 202 AFSK is demodulated from FM audio by comparing
+//! the power of the 1200 Hz mark and 2200 Hz space tones
+//! over one bit. Bits are NRZI decoded and HDLC frames
+//! with a valid checksum are sent to every client connected
+//! to a KISS-over-TCP server, so programs like aprx or Xastir
+//! can use sdrglue as a TNC. Each frame is also emitted as
+//! an "aprs_frame" event in TNC2 text format.
+
+use std::io::Write;
+use std::net::{TcpListener, TcpStream};
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::Duration;
+
+use super::RxChannelProcessor;
+use super::demodulator::{Demodulator, Modulation, SAMPLE_RATE};
+use crate::{Sample, ComplexSample};
+use crate::events;
+
+const MARK_FREQUENCY: f64 = 1200.0;
+const SPACE_FREQUENCY: f64 = 2200.0;
+const BAUD_RATE: f64 = 1200.0;
+const SAMPLES_PER_BIT: usize = (SAMPLE_RATE / BAUD_RATE) as usize;
+/// How much bit timing is corrected at each transition,
+/// as a fraction of the timing error.
+const TIMING_GAIN: f64 = 0.3;
+/// Shortest valid frame: two addresses, control field and checksum.
+const MIN_FRAME_BYTES: usize = 17;
+/// Longer frames are discarded.
+const MAX_FRAME_BYTES: usize = 400;
+/// Number of frames that can wait to be sent to TCP clients.
+const MAX_QUEUED: usize = 100;
+/// Writes to TCP clients taking longer than this disconnect them.
+const WRITE_TIMEOUT: Duration = Duration::from_secs(5);
+
+const KISS_FEND: u8 = 0xC0;
+const KISS_FESC: u8 = 0xDB;
+const KISS_TFEND: u8 = 0xDC;
+const KISS_TFESC: u8 = 0xDD;
+
+/// Frame check sequence of HDLC frames (CRC-16/X.25).
+fn fcs(data: &[u8]) -> u16 {
+    let mut crc: u16 = 0xFFFF;
+    for &byte in data {
+        crc ^= byte as u16;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0x8408 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+/// Demodulator of Bell 202 AFSK tones to bits.
+struct AfskDemodulator {
+    /// Phase of the mark and space oscillators in cycles.
+    mark_phase: f64,
+    space_phase: f64,
+    /// Mixer outputs over the latest bit.
+    mark_history: Vec<ComplexSample>,
+    space_history: Vec<ComplexSample>,
+    position: usize,
+    /// Sums of mixer outputs over the latest bit.
+    mark_sum: ComplexSample,
+    space_sum: ComplexSample,
+    previous_mark: bool,
+    /// Bit clock phase, from 0 to 1.
+    clock: f64,
+}
+
+impl AfskDemodulator {
+    fn new() -> Self {
+        Self {
+            mark_phase: 0.0,
+            space_phase: 0.0,
+            mark_history: vec![ComplexSample::ZERO; SAMPLES_PER_BIT],
+            space_history: vec![ComplexSample::ZERO; SAMPLES_PER_BIT],
+            position: 0,
+            mark_sum: ComplexSample::ZERO,
+            space_sum: ComplexSample::ZERO,
+            previous_mark: false,
+            clock: 0.0,
+        }
+    }
+
+    /// Demodulate audio, calling output for each received tone,
+    /// true for mark and false for space.
+    fn process(&mut self, audio: &[Sample], mut output: impl FnMut(bool)) {
+        for &sample in audio {
+            let oscillator = |phase: f64| ComplexSample::from_polar(sample, (-2.0 * std::f64::consts::PI * phase) as Sample);
+            let mark = oscillator(self.mark_phase);
+            let space = oscillator(self.space_phase);
+            self.mark_phase = (self.mark_phase + MARK_FREQUENCY / SAMPLE_RATE).fract();
+            self.space_phase = (self.space_phase + SPACE_FREQUENCY / SAMPLE_RATE).fract();
+
+            // Sums are updated in place, so they slowly accumulate
+            // rounding errors. Recompute them once per bit.
+            self.mark_sum += mark - self.mark_history[self.position];
+            self.space_sum += space - self.space_history[self.position];
+            self.mark_history[self.position] = mark;
+            self.space_history[self.position] = space;
+            self.position += 1;
+            if self.position >= SAMPLES_PER_BIT {
+                self.position = 0;
+                self.mark_sum = self.mark_history.iter().sum();
+                self.space_sum = self.space_history.iter().sum();
+            }
+
+            // Tone decision changes half a bit after a transition,
+            // when the sums cover half of each bit. The sums cover
+            // only one bit half a bit later, at the clock wrap around.
+            let is_mark = self.mark_sum.norm_sqr() > self.space_sum.norm_sqr();
+            if is_mark != self.previous_mark {
+                self.clock -= TIMING_GAIN * (self.clock - 0.5);
+                self.previous_mark = is_mark;
+            }
+
+            self.clock += BAUD_RATE / SAMPLE_RATE;
+            if self.clock >= 1.0 {
+                self.clock -= 1.0;
+                output(is_mark);
+            }
+        }
+    }
+}
+
+/// Decoder of NRZI coded HDLC frames.
+struct HdlcDecoder {
+    previous_tone: bool,
+    /// Number of consecutive one bits.
+    ones: usize,
+    /// Bits received since the latest flag.
+    bits: Vec<bool>,
+}
+
+impl HdlcDecoder {
+    fn new() -> Self {
+        Self {
+            previous_tone: false,
+            ones: 0,
+            bits: Vec::new(),
+        }
+    }
+
+    /// Decode a tone, returning a frame with a valid checksum
+    /// when one ends. The checksum is removed from the frame.
+    fn tone(&mut self, tone: bool) -> Option<Vec<u8>> {
+        // NRZI: a one is sent as no change in tone.
+        let bit = tone == self.previous_tone;
+        self.previous_tone = tone;
+        if bit {
+            self.ones += 1;
+            if self.ones > 6 {
+                // Abort
+                self.bits.clear();
+            } else {
+                self.bits.push(true);
+            }
+            return None;
+        }
+        let ones = std::mem::replace(&mut self.ones, 0);
+        match ones {
+            // Zero inserted after five ones.
+            5 => None,
+            6 => {
+                // Flag: the frame ended before the zero and six ones of the flag.
+                self.bits.truncate(self.bits.len().saturating_sub(7));
+                let frame = self.frame();
+                self.bits.clear();
+                frame
+            },
+            _ => {
+                if self.bits.len() >= MAX_FRAME_BYTES * 8 {
+                    self.bits.clear();
+                }
+                self.bits.push(false);
+                None
+            },
+        }
+    }
+
+    /// Return received bits as a frame if it has a valid checksum.
+    fn frame(&self) -> Option<Vec<u8>> {
+        if !self.bits.len().is_multiple_of(8) || self.bits.len() < MIN_FRAME_BYTES * 8 {
+            return None;
+        }
+        // Bytes are sent least significant bit first.
+        let bytes: Vec<u8> = self.bits.chunks_exact(8).map(|bits|
+            bits.iter().rev().fold(0, |byte, &bit| (byte << 1) | bit as u8)
+        ).collect();
+        let (data, checksum) = bytes.split_at(bytes.len() - 2);
+        (fcs(data) == u16::from_le_bytes([checksum[0], checksum[1]])).then(|| data.to_vec())
+    }
+}
+
+/// Encode a frame as a KISS data frame for port 0.
+fn kiss_frame(frame: &[u8]) -> Vec<u8> {
+    let mut kiss = vec![KISS_FEND, 0x00];
+    for &byte in frame {
+        match byte {
+            KISS_FEND => kiss.extend_from_slice(&[KISS_FESC, KISS_TFEND]),
+            KISS_FESC => kiss.extend_from_slice(&[KISS_FESC, KISS_TFESC]),
+            byte => kiss.push(byte),
+        }
+    }
+    kiss.push(KISS_FEND);
+    kiss
+}
+
+/// Format an AX.25 address, returning it and whether it is the last one.
+fn ax25_address(address: &[u8]) -> (String, bool) {
+    let callsign: String = address[..6].iter().map(|&byte| (byte >> 1) as char).collect();
+    let ssid = (address[6] >> 1) & 0x0F;
+    let mut text = callsign.trim_end().to_string();
+    if ssid != 0 {
+        text.push_str(&format!("-{}", ssid));
+    }
+    (text, address[6] & 1 != 0)
+}
+
+/// Format an AX.25 UI frame in TNC2 format, like
+/// SOURCE>DESTINATION,PATH:information.
+/// Returns None for other kinds of frames.
+fn ax25_text(frame: &[u8]) -> Option<String> {
+    let mut addresses = Vec::new();
+    let mut rest = frame;
+    loop {
+        if rest.len() < 7 || addresses.len() >= 10 {
+            return None;
+        }
+        let (address, last) = ax25_address(&rest[..7]);
+        // Repeated bit of digipeater addresses
+        let repeated = addresses.len() >= 2 && rest[6] & 0x80 != 0;
+        addresses.push(if repeated { address + "*" } else { address });
+        rest = &rest[7..];
+        if last {
+            break;
+        }
+    }
+    if addresses.len() < 2 || rest.len() < 2 || rest[0] != 0x03 {
+        return None;
+    }
+    let mut text = format!("{}>{}", addresses[1], addresses[0]);
+    for digipeater in addresses[2..].iter() {
+        text.push(',');
+        text.push_str(digipeater);
+    }
+    text.push(':');
+    text.push_str(&String::from_utf8_lossy(&rest[2..]));
+    Some(text)
+}
+
+/// Start a TCP server at given address, used for KISS
+/// and other decoder outputs. Returns a sender for data
+/// to send to all connected clients.
+pub(super) fn start_tcp_server(address: &str) -> mpsc::SyncSender<Vec<u8>> {
+    // TODO: handle errors more nicely
+    let listener = TcpListener::bind(address)
+        .unwrap_or_else(|e| panic!("Could not listen on TCP address {}: {}", address, e));
+    let clients: Arc<Mutex<Vec<TcpStream>>> = Arc::default();
+    let accepted = clients.clone();
+    std::thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let _ = stream.set_write_timeout(Some(WRITE_TIMEOUT));
+            accepted.lock().unwrap().push(stream);
+        }
+    });
+    let (sender, receiver) = mpsc::sync_channel::<Vec<u8>>(MAX_QUEUED);
+    std::thread::spawn(move || {
+        for frame in receiver.iter() {
+            clients.lock().unwrap().retain_mut(|client| client.write_all(&frame).is_ok());
+        }
+    });
+    sender
+}
+
+pub struct DecodeAprs {
+    center_frequency: f64,
+    demodulator: Demodulator,
+    afsk: AfskDemodulator,
+    hdlc: HdlcDecoder,
+    audio_buffer: Vec<Sample>,
+    /// Frames to send to KISS clients.
+    sender: mpsc::SyncSender<Vec<u8>>,
+}
+
+impl DecodeAprs {
+    /// Create a decoder with a KISS server listening at given address.
+    pub fn new(address: &str, center_frequency: f64) -> Self {
+        Self {
+            center_frequency,
+            demodulator: Demodulator::new(Modulation::FM),
+            afsk: AfskDemodulator::new(),
+            hdlc: HdlcDecoder::new(),
+            audio_buffer: Vec::with_capacity(48),
+            sender: start_tcp_server(address),
+        }
+    }
+}
+
+/// Decode frames from demodulated audio.
+fn decode_audio(afsk: &mut AfskDemodulator, hdlc: &mut HdlcDecoder, audio: &[Sample], mut output: impl FnMut(Vec<u8>)) {
+    afsk.process(audio, |tone| {
+        if let Some(frame) = hdlc.tone(tone) {
+            output(frame);
+        }
+    });
+}
+
+impl RxChannelProcessor for DecodeAprs {
+    fn process(&mut self, samples: &[ComplexSample]) {
+        self.audio_buffer.clear();
+        self.demodulator.process(samples, &mut self.audio_buffer);
+        let mut frames = Vec::new();
+        decode_audio(&mut self.afsk, &mut self.hdlc, &self.audio_buffer, |frame| frames.push(frame));
+        for frame in frames {
+            if let Some(text) = ax25_text(&frame) {
+                events::emit(events::Event::new("aprs_frame")
+                    .with("frequency", self.center_frequency)
+                    .with("packet", text));
+            }
+            // If clients are too slow, frames are dropped.
+            let _ = self.sender.try_send(kiss_frame(&frame));
+        }
+    }
+
+    fn input_sample_rate(&self) -> f64 {
+        SAMPLE_RATE
+    }
+
+    fn input_center_frequency(&self) -> f64 {
+        self.center_frequency
+    }
+
+    fn discontinuity(&mut self) {
+        self.demodulator.reset();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Encode an AX.25 address.
+    fn address(callsign: &str, ssid: u8, last: bool) -> Vec<u8> {
+        let mut bytes: Vec<u8> = format!("{:6}", callsign).bytes().map(|byte| byte << 1).collect();
+        bytes.push(0x60 | (ssid << 1) | last as u8);
+        bytes
+    }
+
+    /// Modulate a frame as AFSK audio with flags around it.
+    fn modulate(frame: &[u8]) -> Vec<Sample> {
+        let flag = [false, true, true, true, true, true, true, false];
+        let mut bits: Vec<bool> = flag.repeat(20);
+        let mut ones = 0;
+        let checksum = fcs(frame).to_le_bytes();
+        for &byte in frame.iter().chain(checksum.iter()) {
+            for i in 0..8 {
+                let bit = byte >> i & 1 != 0;
+                bits.push(bit);
+                ones = if bit { ones + 1 } else { 0 };
+                if ones == 5 {
+                    bits.push(false);
+                    ones = 0;
+                }
+            }
+        }
+        bits.extend(flag.repeat(4));
+
+        let mut audio = Vec::new();
+        let (mut tone, mut phase) = (true, 0.0f64);
+        for bit in bits {
+            if !bit {
+                tone = !tone;
+            }
+            let frequency = if tone { MARK_FREQUENCY } else { SPACE_FREQUENCY };
+            for _ in 0..SAMPLES_PER_BIT {
+                audio.push((0.1 * (2.0 * std::f64::consts::PI * phase).sin()) as Sample);
+                phase = (phase + frequency / SAMPLE_RATE).fract();
+            }
+        }
+        audio
+    }
+
+    #[test]
+    fn test_decode() {
+        let mut frame = [address("APRS", 0, false), address("OH2XYZ", 9, false), address("WIDE1", 1, true)].concat();
+        frame.extend_from_slice(&[0x03, 0xF0]);
+        frame.extend_from_slice(b"!6010.00N/02458.00E-Test \xC0");
+        let mut afsk = AfskDemodulator::new();
+        let mut hdlc = HdlcDecoder::new();
+        let mut frames = Vec::new();
+        for block in modulate(&frame).chunks(48) {
+            decode_audio(&mut afsk, &mut hdlc, block, |frame| frames.push(frame));
+        }
+        assert_eq!(frames, vec![frame.clone()]);
+        assert_eq!(ax25_text(&frame).unwrap(), "OH2XYZ-9>APRS,WIDE1-1:!6010.00N/02458.00E-Test \u{FFFD}");
+        let kiss = kiss_frame(&frame);
+        assert_eq!(&kiss[kiss.len() - 4 ..], &[b' ', KISS_FESC, KISS_TFEND, KISS_FEND]);
+    }
+}
@@ -0,0 +1,244 @@
+//! Long-term audio archive with bitrate depending on signal.
+//!
+//! Demodulated audio is recorded as Ogg/Opus files in segments:
+//! a new segment starts whenever squelch opens or closes.
+//! Segments with a signal are encoded at ACTIVE_BITRATE and
+//! segments without at a lower idle bitrate, or not recorded at
+//! all if the idle bitrate is 0. While recording continuously,
+//! each segment starts where the previous one ended, so the
+//! segments can be joined back into gapless audio.
+//!
+//! Each finished segment is listed in manifest.jsonl in the
+//! directory, with its file name, start time, duration in
+//! seconds, frequency, whether it has a signal and its bitrate.
+
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Write};
+
+use serde_json::json;
+
+use super::RxChannelProcessor;
+use super::demodulator::{ChannelFilter, Demodulator, Modulation, Squelch, SquelchSettings, SAMPLE_RATE};
+use crate::{Sample, ComplexSample};
+use crate::clock;
+use crate::ogg::OggOpusWriter;
+
+/// Length of an Opus frame in samples (20 ms).
+const FRAME_SAMPLES: usize = 960;
+/// Maximum size of an encoded Opus frame in bytes.
+const MAX_FRAME_BYTES: usize = 1000;
+/// Opus bitrate while squelch is open, in bits per second.
+const ACTIVE_BITRATE: i32 = 32000;
+/// Segments longer than this in seconds are split,
+/// so that long idle periods do not end up in huge files.
+const MAX_SEGMENT_DURATION: f64 = 3600.0;
+
+pub struct RecordArchiveParameters<'a> {
+    /// Directory to write segments and manifest to.
+    pub directory: &'a str,
+    pub frequency: f64,
+    pub modulation: Modulation,
+    pub channel_filter: ChannelFilter,
+    pub squelch: SquelchSettings,
+    /// Opus bitrate while squelch is closed in bits per second,
+    /// or 0 to record nothing while squelch is closed.
+    pub idle_bitrate: i32,
+}
+
+/// Segment being recorded.
+struct Segment {
+    file_name: String,
+    writer: OggOpusWriter<BufWriter<File>>,
+    encoder: audiopus::coder::Encoder,
+    /// Start time in seconds since Unix epoch.
+    start: f64,
+    /// Number of samples recorded.
+    samples: u64,
+    signal: bool,
+    bitrate: i32,
+}
+
+impl Segment {
+    fn duration(&self) -> f64 {
+        self.samples as f64 / SAMPLE_RATE
+    }
+}
+
+pub struct RecordArchive {
+    /// Input center frequency
+    center_frequency: f64,
+    frequency: f64,
+    directory: String,
+    demodulator: Demodulator,
+    squelch: Squelch,
+    idle_bitrate: i32,
+    /// Demodulated audio waiting to be encoded.
+    audio_buffer: Vec<Sample>,
+    /// Buffer for encoded frame.
+    encoded_buffer: Vec<u8>,
+    segment: Option<Segment>,
+    manifest: BufWriter<File>,
+}
+
+impl RecordArchive {
+    pub fn new(parameters: &RecordArchiveParameters) -> Self {
+        // TODO: handle errors more nicely
+        let manifest = OpenOptions::new().create(true).append(true)
+            .open(format!("{}/manifest.jsonl", parameters.directory))
+            .unwrap_or_else(|e| panic!("Could not open archive manifest in {}: {}", parameters.directory, e));
+        Self {
+            center_frequency:
+                parameters.frequency
+                + Demodulator::frequency_offset(parameters.modulation),
+            frequency: parameters.frequency,
+            directory: parameters.directory.to_string(),
+            demodulator: Demodulator::with_filter(parameters.modulation,
+                parameters.channel_filter),
+            squelch: Squelch::with_settings(parameters.frequency, parameters.squelch),
+            idle_bitrate: parameters.idle_bitrate,
+            audio_buffer: Vec::with_capacity(FRAME_SAMPLES * 2),
+            encoded_buffer: vec![0u8; MAX_FRAME_BYTES],
+            segment: None,
+            manifest: BufWriter::new(manifest),
+        }
+    }
+
+    /// Start a new segment at given time.
+    fn start_segment(&self, start: f64, signal: bool) -> std::io::Result<Segment> {
+        let bitrate = if signal { ACTIVE_BITRATE } else { self.idle_bitrate };
+        let mut encoder = audiopus::coder::Encoder::new(
+            audiopus::SampleRate::Hz48000,
+            audiopus::Channels::Mono,
+            audiopus::Application::Audio,
+        ).map_err(std::io::Error::other)?;
+        encoder.set_bitrate(audiopus::Bitrate::BitsPerSecond(bitrate)).map_err(std::io::Error::other)?;
+        let file_name = format!("{}_{:.0}_{}.opus",
+            clock::utc_timestamp(start), self.frequency, if signal { "signal" } else { "idle" });
+        let file = BufWriter::new(File::create(format!("{}/{}", self.directory, file_name))?);
+        let serial = (start.fract() * 1e9) as u32;
+        Ok(Segment {
+            file_name,
+            writer: OggOpusWriter::new(file, serial, "sdrglue")?,
+            encoder,
+            start,
+            samples: 0,
+            signal,
+            bitrate,
+        })
+    }
+
+    /// Finish a segment and add it to the manifest.
+    fn finish_segment(&mut self, segment: Segment) -> std::io::Result<()> {
+        let duration = segment.duration();
+        segment.writer.finish()?;
+        writeln!(self.manifest, "{}", json!({
+            "file": segment.file_name,
+            "start": segment.start,
+            "duration": duration,
+            "frequency": self.frequency,
+            "signal": segment.signal,
+            "bitrate": segment.bitrate,
+        }))?;
+        self.manifest.flush()
+    }
+
+    /// Record a frame of audio.
+    fn record_frame(&mut self, signal: bool) -> std::io::Result<()> {
+        let mut start = None;
+        if let Some(segment) = self.segment.take_if(|segment|
+            segment.signal != signal || segment.duration() >= MAX_SEGMENT_DURATION
+        ) {
+            // The next segment continues where this one ended.
+            start = Some(segment.start + segment.duration());
+            self.finish_segment(segment)?;
+        }
+        if self.segment.is_none() {
+            if !signal && self.idle_bitrate == 0 {
+                return Ok(());
+            }
+            let start = start.unwrap_or_else(|| clock::now_seconds() - self.audio_buffer.len() as f64 / SAMPLE_RATE);
+            self.segment = Some(self.start_segment(start, signal)?);
+        }
+        let segment = self.segment.as_mut().unwrap();
+        let length = segment.encoder.encode_float(&self.audio_buffer[..FRAME_SAMPLES], &mut self.encoded_buffer)
+            .map_err(std::io::Error::other)?;
+        segment.writer.write_packet(&self.encoded_buffer[..length], FRAME_SAMPLES as u64)?;
+        segment.samples += FRAME_SAMPLES as u64;
+        Ok(())
+    }
+}
+
+impl RxChannelProcessor for RecordArchive {
+    fn process(&mut self, samples: &[ComplexSample]) {
+        self.demodulator.process(samples, &mut self.audio_buffer);
+        let signal = self.squelch.update_block(self.demodulator.power(), samples.len() as f64 / SAMPLE_RATE);
+        while self.audio_buffer.len() >= FRAME_SAMPLES {
+            if let Err(err) = self.record_frame(signal) {
+                eprintln!("Error recording archive in {}: {}", self.directory, err);
+                // Try again with a new segment.
+                self.segment = None;
+            }
+            self.audio_buffer.drain(..FRAME_SAMPLES);
+        }
+    }
+
+    fn input_sample_rate(&self) -> f64 {
+        SAMPLE_RATE
+    }
+
+    fn input_center_frequency(&self) -> f64 {
+        self.center_frequency
+    }
+
+    fn discontinuity(&mut self) {
+        self.demodulator.reset();
+        // Samples were lost, so the next segment
+        // cannot continue seamlessly.
+        if let Some(segment) = self.segment.take() {
+            if let Err(err) = self.finish_segment(segment) {
+                eprintln!("Error recording archive in {}: {}", self.directory, err);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_segments() {
+        let directory = std::env::temp_dir().join(format!("sdrglue-archive-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let mut recorder = RecordArchive::new(&RecordArchiveParameters {
+            directory: directory.to_str().unwrap(),
+            frequency: 145.5e6,
+            modulation: Modulation::FM,
+            channel_filter: ChannelFilter::default_for(Modulation::FM),
+            squelch: SquelchSettings::parse("-20").unwrap(),
+            idle_bitrate: 6000,
+        });
+        let silence = vec![ComplexSample::ZERO; 48];
+        let carrier = vec![ComplexSample::new(1.0, 0.0); 48];
+        for block in [&silence, &carrier, &silence] {
+            for _ in 0..100 {
+                recorder.process(block);
+            }
+        }
+        let manifest = std::fs::read_to_string(directory.join("manifest.jsonl")).unwrap();
+        let entries: Vec<serde_json::Value> = manifest.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+        let files = std::fs::read_dir(&directory).unwrap().count();
+        std::fs::remove_dir_all(&directory).unwrap();
+
+        // The segment still being recorded is not in the manifest yet.
+        assert_eq!(entries.len(), 2);
+        assert_eq!(files, 4);
+        assert_eq!((entries[0]["signal"].as_bool(), entries[0]["bitrate"].as_i64()), (Some(false), Some(6000)));
+        assert_eq!((entries[1]["signal"].as_bool(), entries[1]["bitrate"].as_i64()), (Some(true), Some(ACTIVE_BITRATE as i64)));
+        // Segments are seamless.
+        let end = entries[0]["start"].as_f64().unwrap() + entries[0]["duration"].as_f64().unwrap();
+        assert!((entries[1]["start"].as_f64().unwrap() - end).abs() < 1e-6);
+        assert!((entries[1]["duration"].as_f64().unwrap() - 0.1).abs() <= 0.02);
+        assert!(entries[0]["file"].as_str().unwrap().ends_with("_145500000_idle.opus"));
+    }
+}
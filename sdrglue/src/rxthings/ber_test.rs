@@ -0,0 +1,106 @@
+//! Receiver for bit error rate testing.
+//!
+//! Demodulates binary FSK transmitted by txthings::BerTestTx
+//! and checks the received pseudorandom bit sequence.
+//! Bit error rate is periodically reported as a "ber" event.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use super::RxChannelProcessor;
+use super::fsk::FskDemodulator;
+use crate::ComplexSample;
+use crate::events;
+use crate::prbs::PrbsChecker;
+pub use crate::txthings::ber_test::BerTestParameters;
+
+const SAMPLE_RATE: f64 = 48000.0;
+/// How often bit error rate is reported, in seconds.
+const REPORT_INTERVAL: f64 = 10.0;
+
+/// Bits checked and bit errors in all receivers since start,
+/// used to measure bit error rate in loopback SNR sweeps.
+static TOTAL_BITS: AtomicU64 = AtomicU64::new(0);
+static TOTAL_ERRORS: AtomicU64 = AtomicU64::new(0);
+
+/// Number of bits checked and bit errors
+/// in all receivers since start or previous call.
+pub fn take_totals() -> (u64, u64) {
+    (TOTAL_BITS.swap(0, Ordering::Relaxed), TOTAL_ERRORS.swap(0, Ordering::Relaxed))
+}
+
+pub struct BerTestRx {
+    center_frequency: f64,
+    demodulator: FskDemodulator,
+    checker: PrbsChecker,
+    /// Number of samples since the previous report.
+    samples_since_report: usize,
+}
+
+impl BerTestRx {
+    pub fn new(parameters: &BerTestParameters) -> Self {
+        Self {
+            center_frequency: parameters.center_frequency,
+            demodulator: FskDemodulator::new(SAMPLE_RATE, parameters.symbol_rate, parameters.deviation),
+            checker: PrbsChecker::new(),
+            samples_since_report: 0,
+        }
+    }
+
+    fn report(&mut self) {
+        let checker = &self.checker;
+        events::emit(events::Event::new("ber")
+            .with("frequency", self.center_frequency)
+            .with("synchronized", if checker.synchronized() { "yes" } else { "no" })
+            .with("bits", checker.bits as f64)
+            .with("errors", checker.errors as f64)
+            .with("ber", if checker.bits > 0 { checker.errors as f64 / checker.bits as f64 } else { f64::NAN }));
+        self.checker.reset_counters();
+    }
+}
+
+impl RxChannelProcessor for BerTestRx {
+    fn process(&mut self, samples: &[ComplexSample]) {
+        let checker = &mut self.checker;
+        let (bits, errors) = (checker.bits, checker.errors);
+        self.demodulator.process(samples, |bit| checker.receive(bit));
+        TOTAL_BITS.fetch_add(checker.bits - bits, Ordering::Relaxed);
+        TOTAL_ERRORS.fetch_add(checker.errors - errors, Ordering::Relaxed);
+        self.samples_since_report += samples.len();
+        if self.samples_since_report as f64 >= REPORT_INTERVAL * SAMPLE_RATE {
+            self.samples_since_report = 0;
+            self.report();
+        }
+    }
+
+    fn input_sample_rate(&self) -> f64 {
+        SAMPLE_RATE
+    }
+
+    fn input_center_frequency(&self) -> f64 {
+        self.center_frequency
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::txthings::{BerTestTx, TxChannelProcessor};
+
+    #[test]
+    fn test_loopback() {
+        for (symbol_rate, deviation) in [(1200.0, 1000.0), (9600.0, 2400.0), (4800.0 * 1.01, 2400.0)] {
+            let parameters = BerTestParameters { center_frequency: 0.0, symbol_rate, deviation };
+            let mut tx = BerTestTx::new(&parameters);
+            let mut rx = BerTestRx::new(&parameters);
+            let mut samples = vec![ComplexSample::ZERO; 1000];
+            for _ in 0..100 {
+                tx.process(&mut samples);
+                let checker = &mut rx.checker;
+                rx.demodulator.process(&samples, |bit| checker.receive(bit));
+            }
+            assert!(rx.checker.synchronized());
+            assert!(rx.checker.bits as f64 > symbol_rate * 1.5);
+            assert_eq!(rx.checker.errors, 0);
+        }
+    }
+}
@@ -0,0 +1,187 @@
+//! Capture of short bursts such as keyfob, pager
+//! and telemetry transmissions.
+//!
+//! Channel IQ is continuously buffered and when channel power
+//! exceeds a threshold, the burst is captured including
+//! samples from before the trigger. Each burst is written
+//! to its own file in cf32_le format, with metadata written
+//! to a file with ".json" appended to the name,
+//! and reported as a "burst" event.
+
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::sync::mpsc;
+
+use serde_json::json;
+
+use super::RxChannelProcessor;
+use crate::{Sample, ComplexSample};
+use crate::clock;
+use crate::events;
+
+/// How long power has to stay below threshold
+/// for a burst to end, in seconds.
+const HANG_TIME: f64 = 0.05;
+/// Maximum length of a burst in seconds.
+/// Longer bursts are split into several captures.
+const MAX_DURATION: f64 = 10.0;
+
+struct Burst {
+    samples: Vec<ComplexSample>,
+    /// Time of the first sample, in seconds since Unix epoch.
+    time: f64,
+    /// Index of the sample where power exceeded threshold.
+    trigger: usize,
+    /// Peak block power in dB.
+    peak_db: Sample,
+}
+
+pub struct BurstCapture {
+    center_frequency: f64,
+    sample_rate: f64,
+    threshold_db: Sample,
+    /// Latest samples to be included before a trigger.
+    pretrigger: VecDeque<ComplexSample>,
+    pretrigger_length: usize,
+    /// Burst being captured, if any.
+    burst: Option<Burst>,
+    /// Number of samples since power was above threshold.
+    samples_below: usize,
+    directory: String,
+    /// Completed bursts to be written to files in another thread.
+    /// None in reproducible mode, where bursts are written
+    /// immediately so that files and events are in a fixed order.
+    sender: Option<mpsc::Sender<Burst>>,
+}
+
+pub struct BurstCaptureParameters<'a> {
+    /// Directory to write captures to.
+    pub directory: &'a str,
+    pub center_frequency: f64,
+    pub sample_rate: f64,
+    /// Trigger threshold for channel power in dB.
+    pub threshold_db: Sample,
+    /// Length of capture before trigger, in seconds.
+    pub pretrigger: f64,
+}
+
+/// Write a burst and its metadata to files.
+fn write_burst(path: &str, burst: &Burst, sample_rate: f64, center_frequency: f64) -> std::io::Result<()> {
+    let mut file = BufWriter::new(File::create(path)?);
+    for sample in burst.samples.iter() {
+        file.write_all(&sample.re.to_le_bytes())?;
+        file.write_all(&sample.im.to_le_bytes())?;
+    }
+    file.flush()?;
+    let metadata = json!({
+        "sample_rate": sample_rate,
+        "center_frequency": center_frequency,
+        "format": "cf32_le",
+        "time": burst.time,
+        "trigger_sample": burst.trigger,
+        "samples": burst.samples.len(),
+        "peak_db": burst.peak_db,
+    });
+    std::fs::write(format!("{}.json", path), format!("{}\n", metadata))
+}
+
+/// Write a burst to a file named after its time
+/// and report it as an event.
+fn save_burst(directory: &str, burst: &Burst, sample_rate: f64, center_frequency: f64) {
+    let path = format!("{}/burst_{:.0}_{:.6}.cf32", directory, center_frequency, burst.time);
+    match write_burst(&path, burst, sample_rate, center_frequency) {
+        Ok(()) => events::emit(events::Event::new("burst")
+            .with("frequency", center_frequency)
+            .with("path", path.as_str())
+            .with("duration", burst.samples.len() as f64 / sample_rate)
+            .with("peak_db", burst.peak_db)),
+        Err(err) => eprintln!("Error writing burst {}: {}", path, err),
+    }
+}
+
+impl BurstCapture {
+    pub fn new(parameters: &BurstCaptureParameters) -> Self {
+        // TODO: handle errors more nicely
+        std::fs::create_dir_all(parameters.directory)
+            .unwrap_or_else(|e| panic!("Could not create burst directory {}: {}", parameters.directory, e));
+        let directory = parameters.directory.to_string();
+        // Files are written in another thread so that
+        // slow disk access does not disturb signal processing.
+        let sender = (!clock::reproducible()).then(|| {
+            let (sender, receiver) = mpsc::channel::<Burst>();
+            let directory = directory.clone();
+            let center_frequency = parameters.center_frequency;
+            let sample_rate = parameters.sample_rate;
+            std::thread::spawn(move || {
+                for burst in receiver.iter() {
+                    save_burst(&directory, &burst, sample_rate, center_frequency);
+                }
+            });
+            sender
+        });
+        let pretrigger_length = (parameters.pretrigger * parameters.sample_rate).round() as usize;
+        Self {
+            center_frequency: parameters.center_frequency,
+            sample_rate: parameters.sample_rate,
+            threshold_db: parameters.threshold_db,
+            pretrigger: VecDeque::with_capacity(pretrigger_length),
+            pretrigger_length,
+            burst: None,
+            samples_below: 0,
+            directory,
+            sender,
+        }
+    }
+}
+
+impl RxChannelProcessor for BurstCapture {
+    fn process(&mut self, samples: &[ComplexSample]) {
+        if samples.is_empty() {
+            return;
+        }
+        let power = samples.iter().map(|s| s.norm_sqr()).sum::<Sample>() / samples.len() as Sample;
+        let power_db = 10.0 * (power + 1e-20).log10();
+        let above = power_db >= self.threshold_db;
+
+        if self.burst.is_none() && above {
+            let pretrigger: Vec<ComplexSample> = self.pretrigger.drain(..).collect();
+            self.burst = Some(Burst {
+                time: clock::now_seconds() - pretrigger.len() as f64 / self.sample_rate,
+                trigger: pretrigger.len(),
+                samples: pretrigger,
+                peak_db: power_db,
+            });
+        }
+
+        match &mut self.burst {
+            Some(burst) => {
+                burst.samples.extend_from_slice(samples);
+                burst.peak_db = burst.peak_db.max(power_db);
+                self.samples_below = if above { 0 } else { self.samples_below + samples.len() };
+                if self.samples_below as f64 >= HANG_TIME * self.sample_rate
+                || burst.samples.len() as f64 >= MAX_DURATION * self.sample_rate {
+                    let burst = self.burst.take().unwrap();
+                    match &self.sender {
+                        Some(sender) => { let _ = sender.send(burst); },
+                        None => save_burst(&self.directory, &burst, self.sample_rate, self.center_frequency),
+                    }
+                    self.samples_below = 0;
+                }
+            },
+            None => {
+                self.pretrigger.extend(samples.iter().copied());
+                let excess = self.pretrigger.len().saturating_sub(self.pretrigger_length);
+                self.pretrigger.drain(..excess);
+            },
+        }
+    }
+
+    fn input_sample_rate(&self) -> f64 {
+        self.sample_rate
+    }
+
+    fn input_center_frequency(&self) -> f64 {
+        self.center_frequency
+    }
+}
@@ -0,0 +1,438 @@
+//! Audio processing chains assembled from a text description.
+//!
+//! A chain is described as a list of blocks separated by spaces,
+//! each block being a name optionally followed by an argument
+//! in parentheses, for example:
+//! squelch(-95) vad(10dB) deemph(530us) notch denoise(2) resample(8000)
+//!
+//! Audio is mono, except after a spatial block, which turns it
+//! into interleaved stereo and therefore has to be the last one.
+
+use std::collections::VecDeque;
+
+use super::demodulator::{Squelch, SAMPLE_RATE};
+use super::denoise::DenoiseBlock;
+use super::vad::{VadSettings, VoiceDetector};
+use crate::Sample;
+use crate::filter;
+
+/// Number of taps in the adaptive notch filter.
+const NOTCH_TAPS: usize = 64;
+/// Delay of the reference signal of the adaptive notch filter
+/// in seconds. Speech is mostly uncorrelated over this delay
+/// while carriers are not.
+const NOTCH_DELAY: f64 = 1e-3;
+/// Default adaptation step size of the adaptive notch filter.
+const NOTCH_STEP: f64 = 0.01;
+/// Added to reference power to avoid division by zero in silence.
+const NOTCH_EPSILON: Sample = 1e-6;
+/// Default threshold of voice activity detection in dB.
+const DEFAULT_VAD_THRESHOLD: f64 = 10.0;
+/// Hang time of voice activity detection in seconds.
+const DEFAULT_VAD_HANG_TIME: f64 = 0.5;
+/// Default aggressiveness of noise reduction.
+const DEFAULT_DENOISE: f64 = 2.0;
+/// Default largest phase difference of spatial output in degrees.
+const DEFAULT_SPATIAL_PHASE: f64 = 60.0;
+/// Frequency with the largest phase difference in spatial output,
+/// around the middle of speech on SSB.
+const SPATIAL_CENTER: f64 = 1000.0;
+
+/// Information about the current block given to audio blocks.
+pub struct BlockInfo {
+    /// Channel power of the current block.
+    pub power: Sample,
+}
+
+/// Audio processing block in a chain.
+pub trait AudioBlock {
+    /// Process a block of audio.
+    /// Blocks changing the sample rate or number of channels
+    /// may change the length of audio.
+    fn process(&mut self, audio: &mut Vec<Sample>, info: &BlockInfo);
+}
+
+/// Zero audio while channel power is below a threshold.
+struct SquelchBlock {
+    squelch: Squelch,
+}
+
+impl AudioBlock for SquelchBlock {
+    fn process(&mut self, audio: &mut Vec<Sample>, info: &BlockInfo) {
+        if !self.squelch.update(info.power) {
+            audio.fill(0.0);
+        }
+    }
+}
+
+/// Zero audio while no speech is detected.
+struct VadBlock {
+    detector: VoiceDetector,
+}
+
+impl AudioBlock for VadBlock {
+    fn process(&mut self, audio: &mut Vec<Sample>, _info: &BlockInfo) {
+        if !self.detector.process(audio) {
+            audio.fill(0.0);
+        }
+    }
+}
+
+/// First order lowpass de-emphasis filter.
+struct DeemphasisBlock {
+    /// Filter coefficient.
+    alpha: Sample,
+    state: Sample,
+}
+
+impl AudioBlock for DeemphasisBlock {
+    fn process(&mut self, audio: &mut Vec<Sample>, _info: &BlockInfo) {
+        for sample in audio.iter_mut() {
+            self.state += self.alpha * (*sample - self.state);
+            *sample = self.state;
+        }
+    }
+}
+
+/// First order highpass filter, removing DC and subaudible tones.
+struct HighpassBlock {
+    /// Filter coefficient.
+    alpha: Sample,
+    previous_input: Sample,
+    previous_output: Sample,
+}
+
+impl AudioBlock for HighpassBlock {
+    fn process(&mut self, audio: &mut Vec<Sample>, _info: &BlockInfo) {
+        for sample in audio.iter_mut() {
+            let output = self.alpha * (self.previous_output + *sample - self.previous_input);
+            self.previous_input = *sample;
+            self.previous_output = output;
+            *sample = output;
+        }
+    }
+}
+
+/// Constant gain.
+struct GainBlock {
+    gain: Sample,
+}
+
+impl AudioBlock for GainBlock {
+    fn process(&mut self, audio: &mut Vec<Sample>, _info: &BlockInfo) {
+        for sample in audio.iter_mut() {
+            *sample *= self.gain;
+        }
+    }
+}
+
+/// Adaptive notch filter removing carriers, such as heterodynes
+/// and birdies, from audio. A normalized LMS filter predicts
+/// the audio from delayed samples and the prediction is
+/// subtracted. Only periodic components can be predicted
+/// over the delay, so speech passes mostly unaffected.
+struct NotchBlock {
+    /// Adaptation step size.
+    step: Sample,
+    weights: Vec<Sample>,
+    /// Past audio samples, oldest first. The first
+    /// NOTCH_TAPS samples are the delayed reference.
+    history: VecDeque<Sample>,
+}
+
+impl AudioBlock for NotchBlock {
+    fn process(&mut self, audio: &mut Vec<Sample>, _info: &BlockInfo) {
+        for sample in audio.iter_mut() {
+            let input = *sample;
+            let prediction: Sample = self.weights.iter().zip(self.history.iter()).map(|(w, x)| w * x).sum();
+            let power: Sample = self.history.iter().take(NOTCH_TAPS).map(|x| x * x).sum();
+            let error = input - prediction;
+            let scale = self.step * error / (power + NOTCH_EPSILON);
+            for (weight, x) in self.weights.iter_mut().zip(self.history.iter()) {
+                *weight += scale * x;
+            }
+            self.history.pop_front();
+            self.history.push_back(input);
+            *sample = error;
+        }
+    }
+}
+
+/// First order allpass filter.
+struct Allpass {
+    coefficient: Sample,
+    previous_input: Sample,
+    previous_output: Sample,
+}
+
+impl Allpass {
+    /// Allpass with 90 degree phase shift at given
+    /// frequency relative to sample rate.
+    fn new(frequency: f64) -> Self {
+        let t = (std::f64::consts::PI * frequency).tan();
+        Self {
+            coefficient: ((t - 1.0) / (t + 1.0)) as Sample,
+            previous_input: 0.0,
+            previous_output: 0.0,
+        }
+    }
+
+    fn process(&mut self, input: Sample) -> Sample {
+        let output = self.coefficient * (input - self.previous_output) + self.previous_input;
+        self.previous_input = input;
+        self.previous_output = output;
+        output
+    }
+}
+
+/// Pseudo-stereo output, which many find less tiring to
+/// listen to on SSB. Left and right channels are passed through
+/// allpass filters with corner frequencies on either side of
+/// SPATIAL_CENTER, so their phase difference is zero at low and
+/// high frequencies and largest at SPATIAL_CENTER.
+struct SpatialBlock {
+    left: Allpass,
+    right: Allpass,
+    buffer: Vec<Sample>,
+}
+
+impl SpatialBlock {
+    /// Create with given largest phase difference in degrees.
+    /// Return None if the sample rate is too low for it.
+    fn new(phase: f64, sample_rate: f64) -> Option<Self> {
+        // Phase difference at the geometric mean of the corner
+        // frequencies is 2 * (atan(ratio) - atan(1 / ratio)).
+        let ratio = (std::f64::consts::FRAC_PI_4 + phase.to_radians() / 4.0).tan();
+        (SPATIAL_CENTER * ratio < sample_rate / 2.0).then(|| Self {
+            left: Allpass::new(SPATIAL_CENTER / ratio / sample_rate),
+            right: Allpass::new(SPATIAL_CENTER * ratio / sample_rate),
+            buffer: Vec::new(),
+        })
+    }
+}
+
+impl AudioBlock for SpatialBlock {
+    fn process(&mut self, audio: &mut Vec<Sample>, _info: &BlockInfo) {
+        self.buffer.clear();
+        for &sample in audio.iter() {
+            self.buffer.push(self.left.process(sample));
+            self.buffer.push(self.right.process(sample));
+        }
+        std::mem::swap(audio, &mut self.buffer);
+    }
+}
+
+/// Decimation to a lower sample rate.
+struct ResampleBlock {
+    decimator: filter::Decimator,
+    buffer: Vec<Sample>,
+}
+
+impl AudioBlock for ResampleBlock {
+    fn process(&mut self, audio: &mut Vec<Sample>, _info: &BlockInfo) {
+        self.buffer.clear();
+        self.decimator.process(audio, &mut self.buffer);
+        std::mem::swap(audio, &mut self.buffer);
+    }
+}
+
+/// Parse a number with an optional unit suffix,
+/// returning the value in base units (seconds, Hertz, dB).
+fn parse_value(text: &str) -> Option<f64> {
+    let text = text.trim();
+    let (number, scale) =
+        if let Some(n) = text.strip_suffix("us") { (n, 1e-6) }
+        else if let Some(n) = text.strip_suffix("ms") { (n, 1e-3) }
+        else if let Some(n) = text.strip_suffix("kHz") { (n, 1e3) }
+        else if let Some(n) = text.strip_suffix("Hz") { (n, 1.0) }
+        else if let Some(n) = text.strip_suffix("dB") { (n, 1.0) }
+        else if let Some(n) = text.strip_suffix('s') { (n, 1.0) }
+        else { (text, 1.0) };
+    number.parse::<f64>().ok().map(|v| v * scale)
+}
+
+/// Chain of audio blocks.
+pub struct AudioChain {
+    blocks: Vec<Box<dyn AudioBlock>>,
+    /// Number of interleaved channels in output.
+    channels: usize,
+}
+
+impl AudioChain {
+    /// Build a chain from a description.
+    /// Frequency of the channel is used in squelch events.
+    pub fn parse(description: &str, frequency: f64) -> Result<Self, String> {
+        let mut blocks: Vec<Box<dyn AudioBlock>> = Vec::new();
+        let mut sample_rate = SAMPLE_RATE;
+        let mut channels = 1;
+        for block in description.split_whitespace() {
+            if channels != 1 {
+                return Err(format!("spatial must be the last block, not followed by {}", block));
+            }
+            let (name, argument) = match block.split_once('(') {
+                Some((name, rest)) => (name, Some(rest.strip_suffix(')')
+                    .ok_or_else(|| format!("Missing ) in {}", block))?)),
+                None => (block, None),
+            };
+            let value = match argument {
+                Some(argument) => Some(parse_value(argument)
+                    .ok_or_else(|| format!("Invalid argument in {}", block))?),
+                None => None,
+            };
+            let required = || value.ok_or_else(|| format!("{} requires an argument", name));
+            blocks.push(match name {
+                "squelch" => Box::new(SquelchBlock {
+                    squelch: Squelch::new(frequency, required()? as Sample),
+                }),
+                "vad" => {
+                    if sample_rate != SAMPLE_RATE {
+                        return Err(format!("{} must be before resample", name));
+                    }
+                    Box::new(VadBlock {
+                        detector: VoiceDetector::new(VadSettings {
+                            threshold_db: value.unwrap_or(DEFAULT_VAD_THRESHOLD) as Sample,
+                            hang_time: DEFAULT_VAD_HANG_TIME,
+                        }),
+                    })
+                },
+                "deemph" => {
+                    // Default time constant used in Europe for broadcast FM.
+                    let time_constant = value.unwrap_or(50e-6);
+                    Box::new(DeemphasisBlock {
+                        alpha: (1.0 - (-1.0 / (time_constant * sample_rate)).exp()) as Sample,
+                        state: 0.0,
+                    })
+                },
+                "highpass" => {
+                    let cutoff = value.unwrap_or(300.0);
+                    let rc = 1.0 / (2.0 * std::f64::consts::PI * cutoff);
+                    Box::new(HighpassBlock {
+                        alpha: (rc / (rc + 1.0 / sample_rate)) as Sample,
+                        previous_input: 0.0,
+                        previous_output: 0.0,
+                    })
+                },
+                "gain" => Box::new(GainBlock {
+                    gain: (10.0 as Sample).powf(required()? as Sample / 20.0),
+                }),
+                "notch" => {
+                    let step = value.unwrap_or(NOTCH_STEP);
+                    if !(step > 0.0 && step < 2.0) {
+                        return Err(format!("Notch step size must be between 0 and 2 in {}", block));
+                    }
+                    let delay = ((NOTCH_DELAY * sample_rate).round() as usize).max(1);
+                    Box::new(NotchBlock {
+                        step: step as Sample,
+                        weights: vec![0.0; NOTCH_TAPS],
+                        history: std::iter::repeat_n(0.0, NOTCH_TAPS + delay).collect(),
+                    })
+                },
+                "denoise" => {
+                    let aggressiveness = value.unwrap_or(DEFAULT_DENOISE);
+                    if aggressiveness <= 0.0 {
+                        return Err(format!("Noise reduction aggressiveness must be positive in {}", block));
+                    }
+                    Box::new(DenoiseBlock::new(aggressiveness as Sample, sample_rate))
+                },
+                "spatial" => {
+                    let phase = value.unwrap_or(DEFAULT_SPATIAL_PHASE);
+                    if !(phase > 0.0 && phase < 180.0) {
+                        return Err(format!("Phase difference must be between 0 and 180 degrees in {}", block));
+                    }
+                    channels = 2;
+                    Box::new(SpatialBlock::new(phase, sample_rate)
+                        .ok_or_else(|| format!("Sample rate {} is too low for {}", sample_rate, block))?)
+                },
+                "resample" => {
+                    let new_rate = required()?;
+                    let factor = (sample_rate / new_rate).round() as usize;
+                    if factor < 1 || (sample_rate / factor as f64 - new_rate).abs() > 1e-6 {
+                        return Err(format!("Cannot resample from {} to {}, only integer decimation is supported", sample_rate, new_rate));
+                    }
+                    sample_rate = new_rate;
+                    Box::new(ResampleBlock {
+                        decimator: filter::Decimator::new(factor),
+                        buffer: Vec::new(),
+                    })
+                },
+                _ => return Err(format!("Unknown audio block {}", name)),
+            });
+        }
+        Ok(Self { blocks, channels })
+    }
+
+    /// Build a chain for an output supporting only mono audio.
+    pub fn parse_mono(description: &str, frequency: f64) -> Result<Self, String> {
+        let chain = Self::parse(description, frequency)?;
+        if chain.channels != 1 {
+            return Err("spatial is only supported with --demodulate-to-udp".to_string());
+        }
+        Ok(chain)
+    }
+
+    /// Number of interleaved channels in output.
+    pub fn channels(&self) -> usize {
+        self.channels
+    }
+
+    pub fn process(&mut self, audio: &mut Vec<Sample>, info: &BlockInfo) {
+        for block in self.blocks.iter_mut() {
+            block.process(audio, info);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::loopback::Random;
+
+    #[test]
+    fn test_notch() {
+        // A carrier should be removed while noise,
+        // which cannot be predicted, passes.
+        let mut chain = AudioChain::parse("notch", 145e6).unwrap();
+        let mut random = Random::new(1);
+        let noise: Vec<Sample> = (0..SAMPLE_RATE as usize * 2).map(|_| 0.1 * (random.uniform() - 0.5) as Sample).collect();
+        let mut audio: Vec<Sample> = noise.iter().enumerate().map(|(i, noise)|
+            noise + 0.5 * (2.0 * std::f64::consts::PI * 1234.0 * i as f64 / SAMPLE_RATE).sin() as Sample
+        ).collect();
+        for block in audio.chunks_mut(48) {
+            let mut block_audio = block.to_vec();
+            chain.process(&mut block_audio, &BlockInfo { power: 0.0 });
+            block.copy_from_slice(&block_audio);
+        }
+        let power = |audio: &[Sample]| audio.iter().map(|x| x * x).sum::<Sample>() / audio.len() as Sample;
+        let settled = audio.len() / 2;
+        let ratio = power(&audio[settled..]) / power(&noise[settled..]);
+        assert!(ratio > 0.8 && ratio < 1.5, "{}", ratio);
+        assert!(AudioChain::parse("notch(3)", 145e6).is_err());
+    }
+
+    #[test]
+    fn test_spatial() {
+        let mut chain = AudioChain::parse("spatial(60)", 145e6).unwrap();
+        assert_eq!(chain.channels(), 2);
+        // Returns amplitude of each side and phase difference
+        // in degrees for a tone at given frequency.
+        let mut measure = |frequency: f64| {
+            let mut audio: Vec<Sample> = (0..SAMPLE_RATE as usize).map(|i|
+                (2.0 * std::f64::consts::PI * frequency * i as f64 / SAMPLE_RATE).sin() as Sample
+            ).collect();
+            chain.process(&mut audio, &BlockInfo { power: 0.0 });
+            let settled = &audio[audio.len() / 2..];
+            let sum = |f: &dyn Fn(&[Sample]) -> Sample| settled.chunks_exact(2).map(f).sum::<Sample>() / (settled.len() / 2) as Sample;
+            let left = sum(&|s| s[0] * s[0]);
+            let right = sum(&|s| s[1] * s[1]);
+            let phase = (sum(&|s| s[0] * s[1]) / (left * right).sqrt()).acos().to_degrees();
+            ((2.0 * left).sqrt(), (2.0 * right).sqrt(), phase)
+        };
+        let (left, right, phase) = measure(SPATIAL_CENTER);
+        assert!((left - 1.0).abs() < 0.01 && (right - 1.0).abs() < 0.01, "{} {}", left, right);
+        assert!((phase - 60.0).abs() < 1.0, "{}", phase);
+        let (_, _, phase) = measure(100.0);
+        assert!(phase < 15.0, "{}", phase);
+        assert!(AudioChain::parse("spatial resample(8000)", 145e6).is_err());
+        assert!(AudioChain::parse_mono("spatial", 145e6).is_err());
+    }
+}
@@ -0,0 +1,144 @@
+//! Detection of CTCSS sub-audible tones in demodulated FM audio.
+//!
+//! Audio is decimated by averaging and the power at each
+//! standard CTCSS frequency is measured with a bank of
+//! Goertzel filters over DETECTION_TIME. The configured tone
+//! is detected when it is strong enough and stronger than
+//! any other tone in the bank, so that a channel does not
+//! open for users of a neighbouring tone.
+
+use super::demodulator::SAMPLE_RATE;
+use crate::Sample;
+
+/// Standard CTCSS tone frequencies in Hertz.
+pub const CTCSS_TONES: [f64; 50] = [
+     67.0,  69.3,  71.9,  74.4,  77.0,  79.7,  82.5,  85.4,  88.5,  91.5,
+     94.8,  97.4, 100.0, 103.5, 107.2, 110.9, 114.8, 118.8, 123.0, 127.3,
+    131.8, 136.5, 141.3, 146.2, 151.4, 156.7, 159.8, 162.2, 165.5, 167.9,
+    171.3, 173.8, 177.3, 179.9, 183.5, 186.2, 189.9, 192.8, 196.6, 199.5,
+    203.5, 206.5, 210.7, 218.1, 225.7, 229.1, 233.6, 241.8, 250.3, 254.1,
+];
+
+/// Audio is decimated by this factor before tone detection.
+const DECIMATION: usize = 16;
+/// Length of each detection window in seconds.
+const DETECTION_TIME: f64 = 0.3;
+/// Minimum tone amplitude relative to full scale audio.
+/// For FM, full scale corresponds to a deviation of
+/// half the sample rate, so this is about 120 Hz deviation.
+const MIN_AMPLITUDE: Sample = 0.005;
+
+/// Goertzel filter measuring power at one frequency.
+pub(super) struct Goertzel {
+    coefficient: Sample,
+    s1: Sample,
+    s2: Sample,
+}
+
+impl Goertzel {
+    pub(super) fn new(frequency: f64, sample_rate: f64) -> Self {
+        Self {
+            coefficient: (2.0 * (2.0 * std::f64::consts::PI * frequency / sample_rate).cos()) as Sample,
+            s1: 0.0,
+            s2: 0.0,
+        }
+    }
+
+    pub(super) fn sample(&mut self, x: Sample) {
+        let s = x + self.coefficient * self.s1 - self.s2;
+        self.s2 = self.s1;
+        self.s1 = s;
+    }
+
+    /// Return power accumulated since the previous call and reset.
+    pub(super) fn finish(&mut self) -> Sample {
+        let power = self.s1 * self.s1 + self.s2 * self.s2 - self.coefficient * self.s1 * self.s2;
+        self.s1 = 0.0;
+        self.s2 = 0.0;
+        power
+    }
+}
+
+pub struct CtcssDetector {
+    /// Filters for each tone in the bank.
+    bank: Vec<Goertzel>,
+    /// Index of the configured tone in the bank.
+    tone: usize,
+    /// Sum of audio samples being averaged for decimation.
+    sum: Sample,
+    decimation_phase: usize,
+    /// Decimated samples in the current window.
+    window_samples: usize,
+    window_length: usize,
+    detected: bool,
+}
+
+impl CtcssDetector {
+    /// Create a detector for a tone frequency in Hertz.
+    /// Nonstandard tones are added to the bank.
+    pub fn new(tone: f64) -> Self {
+        let sample_rate = SAMPLE_RATE / DECIMATION as f64;
+        let mut frequencies = CTCSS_TONES.to_vec();
+        let index = match frequencies.iter().position(|&frequency| (frequency - tone).abs() < 0.05) {
+            Some(index) => index,
+            None => { frequencies.push(tone); frequencies.len() - 1 },
+        };
+        Self {
+            bank: frequencies.iter().map(|&frequency| Goertzel::new(frequency, sample_rate)).collect(),
+            tone: index,
+            sum: 0.0,
+            decimation_phase: 0,
+            window_samples: 0,
+            window_length: (DETECTION_TIME * sample_rate).round() as usize,
+            detected: false,
+        }
+    }
+
+    /// Process a block of demodulated audio at SAMPLE_RATE
+    /// and return whether the tone is detected.
+    pub fn process(&mut self, audio: &[Sample]) -> bool {
+        for &sample in audio {
+            self.sum += sample;
+            self.decimation_phase += 1;
+            if self.decimation_phase < DECIMATION {
+                continue;
+            }
+            let decimated = self.sum / DECIMATION as Sample;
+            self.sum = 0.0;
+            self.decimation_phase = 0;
+
+            for filter in self.bank.iter_mut() {
+                filter.sample(decimated);
+            }
+            self.window_samples += 1;
+            if self.window_samples >= self.window_length {
+                self.window_samples = 0;
+                let powers: Vec<Sample> = self.bank.iter_mut().map(Goertzel::finish).collect();
+                let amplitude = 2.0 * powers[self.tone].sqrt() / self.window_length as Sample;
+                self.detected = amplitude >= MIN_AMPLITUDE
+                    && powers.iter().enumerate().all(|(index, &power)| index == self.tone || power < powers[self.tone]);
+            }
+        }
+        self.detected
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ctcss() {
+        let audio = |tone: f64| -> Vec<Sample> { (0..SAMPLE_RATE as usize).map(|i| {
+            let t = i as f64 / SAMPLE_RATE;
+            (0.02 * (2.0 * std::f64::consts::PI * tone * t).sin()
+            + 0.5 * (2.0 * std::f64::consts::PI * 1000.0 * t).sin()) as Sample
+        }).collect() };
+        assert!(CtcssDetector::new(88.5).process(&audio(88.5)));
+        // Neighbouring tone does not open
+        assert!(!CtcssDetector::new(88.5).process(&audio(91.5)));
+        assert!(!CtcssDetector::new(67.0).process(&audio(69.3)));
+        // Nonstandard tone
+        assert!(CtcssDetector::new(60.0).process(&audio(60.0)));
+    }
+}
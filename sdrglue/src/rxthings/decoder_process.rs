@@ -0,0 +1,136 @@
+//! External decoder program run for a channel.
+//!
+//! The decoder is run using sh and gets demodulated audio
+//! or channel IQ in its stdin, in the same format as written
+//! to pipes by --write-to-pipe. Each line the decoder writes
+//! to stdout is emitted as a "decoder_output" event.
+//! If the decoder exits or crashes, it is restarted.
+
+use std::io::{BufRead, BufReader, Write};
+use std::process::{Child, Command, Stdio};
+use std::sync::mpsc;
+use std::time::Duration;
+
+use super::RxChannelProcessor;
+use super::demodulator::{ChannelFilter, Modulation, SAMPLE_RATE};
+use super::iq_output::IqFormat;
+use super::pipe_output::PipeContent;
+use crate::ComplexSample;
+use crate::events;
+
+/// Number of blocks that can wait to be written to the decoder.
+/// If the decoder is too slow, further input is dropped.
+const MAX_QUEUED: usize = 100;
+/// How long to wait before restarting a decoder that has exited.
+const RESTART_DELAY: Duration = Duration::from_secs(1);
+
+pub struct DecodeWithProcess {
+    center_frequency: f64,
+    content: PipeContent,
+    /// Blocks to be written to the decoder.
+    sender: mpsc::SyncSender<Vec<u8>>,
+}
+
+pub struct DecodeWithProcessParameters<'a> {
+    /// Shell command to run the decoder.
+    pub command: &'a str,
+    pub center_frequency: f64,
+    /// Modulation to demodulate and its channel filter, or None for IQ input.
+    pub demodulation: Option<(Modulation, ChannelFilter)>,
+    /// Description of audio processing chain, if any.
+    pub chain: Option<&'a str>,
+    /// Format of IQ input.
+    pub iq_format: IqFormat,
+}
+
+/// Start the decoder and a thread emitting events from its output.
+fn spawn(command: &str, frequency: f64) -> std::io::Result<Child> {
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()?;
+    let stdout = child.stdout.take().unwrap();
+    std::thread::spawn(move || {
+        for line in BufReader::new(stdout).lines().map_while(Result::ok) {
+            if line.trim().is_empty() {
+                continue;
+            }
+            events::emit(events::Event::new("decoder_output")
+                .with("frequency", frequency)
+                .with("output", line));
+        }
+    });
+    Ok(child)
+}
+
+/// Run the decoder, restarting it whenever it exits,
+/// until the channel is dropped.
+fn supervise(command: String, frequency: f64, receiver: mpsc::Receiver<Vec<u8>>) {
+    loop {
+        let mut child = match spawn(&command, frequency) {
+            Ok(child) => child,
+            Err(err) => {
+                eprintln!("Could not start decoder {}: {}", command, err);
+                std::thread::sleep(RESTART_DELAY);
+                continue;
+            },
+        };
+        let mut stdin = child.stdin.take().unwrap();
+        let mut stopped = true;
+        for block in receiver.iter() {
+            if stdin.write_all(&block).is_err() {
+                stopped = false;
+                break;
+            }
+        }
+        drop(stdin);
+        if stopped {
+            // Channel was dropped, so let the decoder finish.
+            let _ = child.wait();
+            return;
+        }
+        let status = child.wait().map(|status| status.to_string()).unwrap_or_default();
+        eprintln!("Decoder {} exited ({}), restarting", command, status);
+        events::emit(events::Event::new("decoder_restart")
+            .with("frequency", frequency)
+            .with("command", command.as_str())
+            .with("status", status));
+        std::thread::sleep(RESTART_DELAY);
+        // Discard input queued while the decoder was not running.
+        while receiver.try_recv().is_ok() {}
+    }
+}
+
+impl DecodeWithProcess {
+    pub fn new(parameters: &DecodeWithProcessParameters) -> Self {
+        let (sender, receiver) = mpsc::sync_channel::<Vec<u8>>(MAX_QUEUED);
+        let command = parameters.command.to_string();
+        let frequency = parameters.center_frequency;
+        std::thread::spawn(move || supervise(command, frequency, receiver));
+        let (center_frequency, content) = PipeContent::new(
+            parameters.center_frequency, parameters.demodulation, parameters.chain, parameters.iq_format);
+        Self {
+            center_frequency,
+            content,
+            sender,
+        }
+    }
+}
+
+impl RxChannelProcessor for DecodeWithProcess {
+    fn process(&mut self, samples: &[ComplexSample]) {
+        let mut output = Vec::with_capacity(samples.len() * 4);
+        self.content.process(samples, &mut output);
+        let _ = self.sender.try_send(output);
+    }
+
+    fn input_sample_rate(&self) -> f64 {
+        SAMPLE_RATE
+    }
+
+    fn input_center_frequency(&self) -> f64 {
+        self.center_frequency
+    }
+}
@@ -1,35 +1,229 @@
 
+use std::time::SystemTime;
+
 use super::RxChannelProcessor;
-use crate::{Sample, ComplexSample, sample_consts};
-use crate::filter;
+use super::chain::{AudioChain, BlockInfo};
+use super::ctcss::CtcssDetector;
+use super::vad::{VadSettings, VoiceDetector};
+use crate::{Sample, ComplexSample};
+use crate::calibration;
+use crate::events;
+pub use crate::demodulation::*;
+
+/// Squelch thresholds of a channel.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct SquelchSettings {
+    /// Opening threshold for channel power in dB.
+    pub open_level: Sample,
+    /// Closing threshold for channel power in dB.
+    /// Set below the opening threshold for hysteresis,
+    /// so that squelch does not chatter on weak signals.
+    pub close_level: Sample,
+    /// Time in seconds squelch stays open after
+    /// power has dropped below the closing threshold.
+    pub hang_time: f64,
+}
+
+impl SquelchSettings {
+    /// Parse "open", "open:close" or "open:close:hang_time".
+    pub fn parse(text: &str) -> Option<Self> {
+        let mut values = text.split(':').map(|value| value.parse::<f64>().ok());
+        let open_level = values.next()?? as Sample;
+        let close_level = values.next().unwrap_or(Some(open_level as f64))? as Sample;
+        let hang_time = values.next().unwrap_or(Some(0.0))?;
+        (values.next().is_none() && close_level <= open_level && hang_time >= 0.0)
+            .then_some(Self { open_level, close_level, hang_time })
+    }
+}
+
+/// Squelch based on channel power.
+/// Emits an event when squelch opens or closes.
+/// Closing events include how long squelch was open
+/// and the peak power during that time.
+pub struct Squelch {
+    /// Frequency of the channel, used in events.
+    frequency: f64,
+    settings: SquelchSettings,
+    open: bool,
+    /// Time in seconds power has been below the closing threshold.
+    below: f64,
+    /// Time when squelch opened.
+    opened: SystemTime,
+    /// Peak power in dB since squelch opened.
+    peak_db: Sample,
+}
+
+impl Squelch {
+    /// Create squelch opening and closing at the same level.
+    pub fn new(frequency: f64, level: Sample) -> Self {
+        Self::with_settings(frequency, SquelchSettings { open_level: level, close_level: level, hang_time: 0.0 })
+    }
+
+    pub fn with_settings(frequency: f64, settings: SquelchSettings) -> Self {
+        Self {
+            frequency,
+            settings,
+            open: false,
+            below: 0.0,
+            opened: SystemTime::UNIX_EPOCH,
+            peak_db: Sample::NEG_INFINITY,
+        }
+    }
+
+    /// Update squelch state from channel power of the latest block.
+    /// Return true if squelch is open.
+    /// Hang time is not counted, so use update_block if there is one.
+    pub fn update(&mut self, power: Sample) -> bool {
+        self.update_block(power, 0.0)
+    }
 
-const SAMPLE_RATE: f64 = 48000.0;
+    /// Update squelch state from channel power of the latest block
+    /// lasting given time in seconds.
+    /// Return true if squelch is open.
+    pub fn update_block(&mut self, power: Sample, duration: f64) -> bool {
+        let power_db = 10.0 * (power + 1e-20).log10();
+        let open = if self.open {
+            if power_db >= self.settings.close_level {
+                self.below = 0.0;
+                true
+            } else {
+                self.below += duration;
+                self.below < self.settings.hang_time
+            }
+        } else {
+            self.below = 0.0;
+            power_db >= self.settings.open_level
+        };
+        if open {
+            self.peak_db = if self.open { self.peak_db.max(power_db) } else { power_db };
+        }
+        if open != self.open {
+            let mut event = events::Event::new(if open { "squelch_open" } else { "squelch_close" })
+                .with("frequency", self.frequency)
+                .with("power_db", power_db);
+            let offset = calibration::dbm_offset(self.frequency);
+            if let Some(offset) = offset {
+                event = event.with("power_dbm", power_db + offset);
+            }
+            if open {
+                self.opened = event.time;
+            } else {
+                let duration = event.time.duration_since(self.opened).unwrap_or_default();
+                event = event
+                    .with("duration", duration.as_secs_f64())
+                    .with("peak_db", self.peak_db);
+                if let Some(offset) = offset {
+                    event = event.with("peak_dbm", self.peak_db + offset);
+                }
+            }
+            events::emit(event);
+        }
+        self.open = open;
+        open
+    }
+}
 
-#[derive(Copy, Clone)]
-pub enum Modulation {
-    FM,
-    USB,
-    LSB,
+/// Convert audio samples to the format sent to UDP sockets,
+/// that is, signed 16-bit little endian.
+/// Converted samples are appended to output.
+pub fn audio_to_s16le(audio: &[Sample], output: &mut Vec<u8>) {
+    let full_scale = i16::MAX as Sample;
+    for &sample in audio {
+        let output_int = ((sample * full_scale).min(full_scale).max(-full_scale)) as i16;
+        output.push((output_int & 0xFF) as u8);
+        output.push((output_int >> 8)   as u8);
+    }
+}
+
+/// Create a UDP socket for sending audio to a given address.
+pub fn audio_udp_socket(address: &str) -> std::net::UdpSocket {
+    // Does the bind address matter if we only send data to the socket?
+    // TODO: handle error somehow if creating the socket or connecting fails
+    let socket = std::net::UdpSocket::bind("0.0.0.0:0").unwrap();
+    socket.connect(address).unwrap();
+    socket
+}
+
+/// Largest payload of a UDP packet over IPv4.
+const MAX_UDP_PAYLOAD: usize = 65507;
+
+/// Size in bytes of audio frames lasting given time in seconds
+/// with given number of interleaved channels.
+fn audio_frame_bytes(frame_duration: f64, channels: usize) -> Result<usize, String> {
+    if frame_duration.is_nan() || frame_duration < 0.0 {
+        return Err(format!("Invalid UDP frame time {} ms", frame_duration * 1e3));
+    }
+    // 2 bytes per sample
+    let bytes = 2.0 * channels as f64 * (frame_duration * SAMPLE_RATE).round();
+    if bytes > MAX_UDP_PAYLOAD as f64 {
+        return Err(format!("UDP frame time {} ms gives {} byte packets, more than the maximum of {} bytes",
+            frame_duration * 1e3, bytes, MAX_UDP_PAYLOAD));
+    }
+    Ok(bytes as usize)
+}
+
+/// Audio output to a UDP socket.
+/// Audio is buffered in a FIFO and sent in fixed size frames,
+/// so that packet size and latency do not depend
+/// on the block length of the filter bank.
+pub struct AudioUdpOutput {
+    socket: std::net::UdpSocket,
+    /// Audio waiting to be sent, in the format sent to the socket.
+    fifo: Vec<u8>,
+    /// Size of each packet in bytes.
+    /// If zero, each block is sent as soon as it is ready.
+    frame_bytes: usize,
+}
+
+impl AudioUdpOutput {
+    /// Create for given address, frame duration in seconds
+    /// and number of interleaved audio channels.
+    pub fn new(address: &str, frame_duration: f64, channels: usize) -> Self {
+        Self {
+            socket: audio_udp_socket(address),
+            fifo: Vec::new(),
+            // TODO: handle errors more nicely
+            frame_bytes: audio_frame_bytes(frame_duration, channels).unwrap_or_else(|err| panic!("{}", err)),
+        }
+    }
+
+    /// Send audio, or buffer it until a full frame is available.
+    pub fn send(&mut self, audio: &[Sample]) {
+        audio_to_s16le(audio, &mut self.fifo);
+        let frame_bytes = if self.frame_bytes == 0 { self.fifo.len() } else { self.frame_bytes };
+        if frame_bytes == 0 {
+            return;
+        }
+        let mut sent = 0;
+        for frame in self.fifo.chunks_exact(frame_bytes) {
+            // TODO: print a warning or something if writing to socket fails
+            let _ = self.socket.send(frame);
+            sent += frame_bytes;
+        }
+        self.fifo.drain(..sent);
+    }
 }
 
 pub struct DemodulateToUdp {
     /// Center frequency to demodulate
     center_frequency: f64,
-    /// Modulation
-    modulation: Modulation,
-    /// Previous sample, used for FM demodulation
-    previous_sample: ComplexSample,
-    /// Used for SSB demodulation.
-    second_mixer_phase: usize,
-    /// Channel filter, used for both FM and SSB
-    /// but with different bandwidth.
-    channel_filter: filter::FirCf32Sym,
-    /// Output buffer.
-    /// Demodulated signal is written here
-    /// in the format that is sent to the UDP socket.
-    output_buffer: Vec<u8>,
-    /// Socket to send demodulated signal to.
-    socket: std::net::UdpSocket,
+    demodulator: Demodulator,
+    /// Optional audio processing chain after demodulator.
+    chain: Option<AudioChain>,
+    /// Optional squelch controlling whether audio is sent.
+    squelch: Option<Squelch>,
+    /// Optional CTCSS tone required for audio to be sent.
+    ctcss: Option<CtcssDetector>,
+    /// Optional voice activity detection required for audio to be sent.
+    vad: Option<VoiceDetector>,
+    /// Optional automatic gain control before the chain.
+    agc: Option<Agc>,
+    /// Send zeros while squelch is closed instead of nothing.
+    squelch_zero_fill: bool,
+    /// Demodulated audio of the latest block.
+    audio_buffer: Vec<Sample>,
+    /// Output to send demodulated signal to.
+    output: AudioUdpOutput,
 }
 
 pub struct DemodulateToUdpParameters<'a> {
@@ -39,95 +233,78 @@ pub struct DemodulateToUdpParameters<'a> {
     pub address: &'a str,
     /// Modulation
     pub modulation: Modulation,
+    /// Description of audio processing chain, if any.
+    pub chain: Option<&'a str>,
+    /// Duration of each UDP packet in seconds,
+    /// or 0 to send each block as it is ready.
+    pub frame_duration: f64,
+    pub channel_filter: ChannelFilter,
+    /// Squelch, or None to always send audio.
+    pub squelch: Option<SquelchSettings>,
+    /// CTCSS tone frequency required for audio to be sent.
+    pub ctcss: Option<f64>,
+    /// Voice activity detection required for audio to be sent.
+    pub vad: Option<VadSettings>,
+    /// Automatic gain control, if enabled.
+    pub agc: Option<AgcSettings>,
+    /// Send zeros while squelch is closed instead of
+    /// not sending packets at all.
+    pub squelch_zero_fill: bool,
 }
 
 impl DemodulateToUdp {
     pub fn new(parameters: &DemodulateToUdpParameters) -> Self {
+        // TODO: handle errors more nicely
+        let chain = parameters.chain.map(|description|
+            AudioChain::parse(description, parameters.center_frequency)
+            .unwrap_or_else(|err| panic!("Invalid audio chain: {}", err)));
+        let channels = chain.as_ref().map_or(1, AudioChain::channels);
         Self {
             center_frequency:
                 parameters.center_frequency
-                + match parameters.modulation {
-                    Modulation::FM => 0.0,
-                    // Weaver method SSB: offset downconverter so we can
-                    // use a channel filter with real-valued taps.
-                    Modulation::USB =>  SSB_WEAVER_OFFSET,
-                    Modulation::LSB => -SSB_WEAVER_OFFSET,
-                },
-            previous_sample: ComplexSample::ZERO,
-            second_mixer_phase: 0,
+                + Demodulator::frequency_offset(parameters.modulation),
+            demodulator: Demodulator::with_filter(parameters.modulation,
+                parameters.channel_filter),
+            chain,
+            squelch: parameters.squelch.map(|settings|
+                Squelch::with_settings(parameters.center_frequency, settings)),
+            ctcss: parameters.ctcss.map(CtcssDetector::new),
+            vad: parameters.vad.map(VoiceDetector::new),
+            agc: parameters.agc.map(Agc::new),
+            squelch_zero_fill: parameters.squelch_zero_fill,
             // Already allocate space for 1 ms block of output signal.
             // Well, the blocks might be longer if bin spacing is reduced,
             // but even if it is, more space will be allocated while
             // processing the first block and no more dynamic allocations
             // are needed after that, so it is not really a problem.
-            output_buffer: Vec::<u8>::with_capacity(96),
-            socket: {
-                // Does the bind address matter if we only send data to the socket?
-                // TODO: handle error somehow if creating the socket or connecting fails
-                let socket = std::net::UdpSocket::bind("0.0.0.0:0").unwrap();
-                socket.connect(parameters.address).unwrap();
-                socket
-            },
-            // Channels filters are the same for all instances with the same modulation,
-            // so memory use could be reduced (which might be good for cache)
-            // by computing them once and sharing them among demodulators.
-            // This can be done later.
-            channel_filter: filter::FirCf32Sym::new(match parameters.modulation {
-                Modulation::FM =>
-                    filter::design_fir_lowpass(SAMPLE_RATE, 8000.0, 32),
-                Modulation::USB | Modulation::LSB =>
-                    filter::design_fir_lowpass(SAMPLE_RATE, 1200.0, 128),
-            }),
-            modulation: parameters.modulation,
+            audio_buffer: Vec::<Sample>::with_capacity(48),
+            output: AudioUdpOutput::new(parameters.address, parameters.frame_duration, channels),
         }
     }
 }
 
 impl RxChannelProcessor for DemodulateToUdp {
     fn process(&mut self, samples: &[ComplexSample]) {
-        self.output_buffer.clear();
-        for &sample in samples {
-            let full_scale = i16::MAX as Sample;
-
-            let filtered = self.channel_filter.sample(sample);
-
-            let output = match self.modulation {
-                Modulation::FM => {
-                    let out = (filtered * self.previous_sample.conj()).arg() * (full_scale * sample_consts::FRAC_1_PI);
-                    self.previous_sample = filtered;
-                    out
-                },
-                Modulation::USB | Modulation::LSB => {
-                    (filtered * SSB_SECOND_MIXER_TABLE[self.second_mixer_phase]).re * full_scale
-                },
-            };
-
-            // All this SSB stuff could be cleaned up a bit...
-
-            match self.modulation {
-                Modulation::USB => {
-                    self.second_mixer_phase += 1;
-                    if self.second_mixer_phase >= SSB_SECOND_MIXER_TABLE.len() {
-                        self.second_mixer_phase = 0;
-                    }
-                },
-                Modulation::LSB => {
-                    if self.second_mixer_phase == 0 {
-                        self.second_mixer_phase = SSB_SECOND_MIXER_TABLE.len() - 1;
-                    } else {
-                        self.second_mixer_phase -= 1;
-                    }
-                },
-                _ => {},
+        self.audio_buffer.clear();
+        self.demodulator.process(samples, &mut self.audio_buffer);
+        // Tone is detected before the chain, which may filter it out.
+        let tone = self.ctcss.as_mut().is_none_or(|ctcss| ctcss.process(&self.audio_buffer));
+        let voice = self.vad.as_mut().is_none_or(|vad| vad.process(&self.audio_buffer));
+        if let Some(agc) = &mut self.agc {
+            agc.process(&mut self.audio_buffer);
+        }
+        if let Some(chain) = &mut self.chain {
+            chain.process(&mut self.audio_buffer, &BlockInfo { power: self.demodulator.power() });
+        }
+        let open = self.squelch.as_mut().is_none_or(|squelch|
+            squelch.update_block(self.demodulator.power(), samples.len() as f64 / SAMPLE_RATE));
+        if !(open && tone && voice) {
+            if !self.squelch_zero_fill {
+                return;
             }
-
-            // Format conversion
-            let output_int = (output.min(full_scale).max(-full_scale)) as i16;
-            self.output_buffer.push((output_int & 0xFF) as u8);
-            self.output_buffer.push((output_int >> 8)   as u8);
+            self.audio_buffer.fill(0.0);
         }
-        // TODO: print a warning or something if writing to socket fails
-        let _ = self.socket.send(&self.output_buffer);
+        self.output.send(&self.audio_buffer);
     }
 
     fn input_sample_rate(&self) -> f64 {
@@ -137,51 +314,62 @@ impl RxChannelProcessor for DemodulateToUdp {
     fn input_center_frequency(&self) -> f64 {
         self.center_frequency
     }
+
+    fn discontinuity(&mut self) {
+        self.demodulator.reset();
+    }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn test_squelch() {
+        assert_eq!(SquelchSettings::parse("-60:-65:0.5"),
+            Some(SquelchSettings { open_level: -60.0, close_level: -65.0, hang_time: 0.5 }));
+        assert_eq!(SquelchSettings::parse("-60:-55"), None);
+        let mut squelch = Squelch::with_settings(145e6, SquelchSettings::parse("-60:-65:0.5").unwrap());
+        let power = |db: Sample| (10.0 as Sample).powf(db / 10.0);
+        assert!(!squelch.update_block(power(-62.0), 0.1));
+        assert!(squelch.update_block(power(-59.0), 0.1));
+        // Stays open between the thresholds
+        assert!(squelch.update_block(power(-62.0), 0.1));
+        // and during hang time below the closing threshold.
+        assert!((0..4).all(|_| squelch.update_block(power(-70.0), 0.1)));
+        assert!(!squelch.update_block(power(-70.0), 0.1));
+    }
+
+    #[test]
+    fn test_malformed_settings() {
+        // Anything may fail, as long as nothing panics.
+        for input in crate::fuzz::inputs(&["-20:-25:0.5", "16000:32"]).iter() {
+            let _ = SquelchSettings::parse(input);
+            let _ = AgcSettings::parse(input);
+            let _ = ChannelFilter::parse(input, Modulation::FM);
+        }
+        assert_eq!(ChannelFilter::parse("16000:18446744073709551615", Modulation::FM), None);
+    }
 
-const SSB_WEAVER_OFFSET: f64 = 1500.0;
-
-/// One cycle of complex sine wave for the second mixer
-/// in Weaver method SSB demodulator.
-/// Computing it at compile time is not possible for floating point
-/// and computing it at run time would unnecessarily complicate the code,
-/// so just put the values here.
-/// Computed in Python with:
-/// import numpy as np
-/// for v in np.exp(1j * np.linspace(0, np.pi*2, 32, endpoint=False)):
-///  print('    ComplexSample { re: %11.8f, im: %11.8f },' % (v.real, v.imag))
-const SSB_SECOND_MIXER_TABLE: [ComplexSample; 32] = [
-    ComplexSample { re:  1.00000000, im:  0.00000000 },
-    ComplexSample { re:  0.98078528, im:  0.19509032 },
-    ComplexSample { re:  0.92387953, im:  0.38268343 },
-    ComplexSample { re:  0.83146961, im:  0.55557023 },
-    ComplexSample { re:  0.70710678, im:  0.70710678 },
-    ComplexSample { re:  0.55557023, im:  0.83146961 },
-    ComplexSample { re:  0.38268343, im:  0.92387953 },
-    ComplexSample { re:  0.19509032, im:  0.98078528 },
-    ComplexSample { re:  0.00000000, im:  1.00000000 },
-    ComplexSample { re: -0.19509032, im:  0.98078528 },
-    ComplexSample { re: -0.38268343, im:  0.92387953 },
-    ComplexSample { re: -0.55557023, im:  0.83146961 },
-    ComplexSample { re: -0.70710678, im:  0.70710678 },
-    ComplexSample { re: -0.83146961, im:  0.55557023 },
-    ComplexSample { re: -0.92387953, im:  0.38268343 },
-    ComplexSample { re: -0.98078528, im:  0.19509032 },
-    ComplexSample { re: -1.00000000, im:  0.00000000 },
-    ComplexSample { re: -0.98078528, im: -0.19509032 },
-    ComplexSample { re: -0.92387953, im: -0.38268343 },
-    ComplexSample { re: -0.83146961, im: -0.55557023 },
-    ComplexSample { re: -0.70710678, im: -0.70710678 },
-    ComplexSample { re: -0.55557023, im: -0.83146961 },
-    ComplexSample { re: -0.38268343, im: -0.92387953 },
-    ComplexSample { re: -0.19509032, im: -0.98078528 },
-    ComplexSample { re: -0.00000000, im: -1.00000000 },
-    ComplexSample { re:  0.19509032, im: -0.98078528 },
-    ComplexSample { re:  0.38268343, im: -0.92387953 },
-    ComplexSample { re:  0.55557023, im: -0.83146961 },
-    ComplexSample { re:  0.70710678, im: -0.70710678 },
-    ComplexSample { re:  0.83146961, im: -0.55557023 },
-    ComplexSample { re:  0.92387953, im: -0.38268343 },
-    ComplexSample { re:  0.98078528, im: -0.19509032 },
-];
+    #[test]
+    fn test_audio_udp_frames() {
+        assert_eq!(audio_frame_bytes(0.0, 1), Ok(0));
+        assert_eq!(audio_frame_bytes(0.2, 2), Ok(2 * 2 * 9600));
+        assert!(audio_frame_bytes(0.5, 2).is_err());
+        assert!(audio_frame_bytes(-0.02, 1).is_err());
+        assert!(audio_frame_bytes(f64::NAN, 1).is_err());
+
+        let receiver = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        receiver.set_read_timeout(Some(std::time::Duration::from_secs(1))).unwrap();
+        let mut output = AudioUdpOutput::new(&receiver.local_addr().unwrap().to_string(), 0.02, 1);
+        // Blocks not matching the frame length are buffered.
+        for _ in 0..30 {
+            output.send(&[0.5; 100]);
+        }
+        let mut packet = [0u8; 4096];
+        for _ in 0..3 {
+            assert_eq!(receiver.recv(&mut packet).unwrap(), 2 * 960);
+        }
+        receiver.set_nonblocking(true).unwrap();
+        assert!(receiver.recv(&mut packet).is_err());
+    }
+}
@@ -0,0 +1,162 @@
+//! Noise reduction of demodulated audio by spectral subtraction.
+//!
+//! Audio is split into half-overlapping frames and the noise
+//! power of each frequency bin is estimated by following the
+//! minimum of its smoothed power. The estimated noise power,
+//! multiplied by an aggressiveness factor, is subtracted from
+//! each bin, leaving at least a spectral floor so that noise
+//! does not turn into "musical" tones. Frames are put back
+//! together by overlap-add, which delays audio by FRAME_LENGTH.
+
+use std::collections::VecDeque;
+use std::sync::Arc;
+
+use super::chain::{AudioBlock, BlockInfo};
+use crate::{Sample, ComplexSample};
+
+/// Length of analysis frames in samples.
+const FRAME_LENGTH: usize = 512;
+const HOP: usize = FRAME_LENGTH / 2;
+/// Smoothing of bin powers for the noise estimate.
+const POWER_SMOOTHING: Sample = 0.9;
+/// How fast the noise estimate of a bin rises in dB per second.
+/// It falls immediately when the smoothed power is lower.
+const NOISE_RISE_DB: f64 = 10.0;
+/// The minimum of smoothed power is below the average
+/// noise power, so the estimate is multiplied by this.
+const NOISE_BIAS: Sample = 2.0;
+/// Smallest gain of a bin, limiting how much noise is removed.
+const GAIN_FLOOR: Sample = 0.1;
+/// Smoothing of bin gains over time.
+const GAIN_SMOOTHING: Sample = 0.5;
+
+pub(super) struct DenoiseBlock {
+    fft: Arc<dyn rustfft::Fft<Sample>>,
+    ifft: Arc<dyn rustfft::Fft<Sample>>,
+    /// Square root of a periodic Hann window, used both
+    /// for analysis and synthesis.
+    window: Vec<Sample>,
+    /// Multiplier of noise power subtracted from each bin.
+    aggressiveness: Sample,
+    /// Noise estimate rise per frame as a power ratio.
+    noise_rise: Sample,
+    /// Input samples of the next frame.
+    input: Vec<Sample>,
+    /// Overlap-add accumulator.
+    overlap: Vec<Sample>,
+    /// Processed samples waiting to be output.
+    output: VecDeque<Sample>,
+    /// Per bin smoothed power, noise estimate and gain.
+    power: Vec<Sample>,
+    noise: Vec<Sample>,
+    gain: Vec<Sample>,
+    /// Whether the noise estimate has been initialized.
+    started: bool,
+    buffer: Vec<ComplexSample>,
+}
+
+impl DenoiseBlock {
+    pub(super) fn new(aggressiveness: Sample, sample_rate: f64) -> Self {
+        let mut planner = rustfft::FftPlanner::new();
+        let frame_time = HOP as f64 / sample_rate;
+        Self {
+            fft: planner.plan_fft_forward(FRAME_LENGTH),
+            ifft: planner.plan_fft_inverse(FRAME_LENGTH),
+            window: (0..FRAME_LENGTH).map(|i|
+                (std::f64::consts::PI * i as f64 / FRAME_LENGTH as f64).sin() as Sample
+            ).collect(),
+            aggressiveness,
+            noise_rise: (10.0f64).powf(NOISE_RISE_DB * frame_time / 10.0) as Sample,
+            input: vec![0.0; FRAME_LENGTH - HOP],
+            overlap: vec![0.0; FRAME_LENGTH],
+            // Keeps output as long as input.
+            output: std::iter::repeat_n(0.0, HOP).collect(),
+            power: vec![0.0; FRAME_LENGTH],
+            noise: vec![0.0; FRAME_LENGTH],
+            gain: vec![1.0; FRAME_LENGTH],
+            started: false,
+            buffer: vec![ComplexSample::ZERO; FRAME_LENGTH],
+        }
+    }
+
+    fn process_frame(&mut self) {
+        for ((value, &sample), &window) in self.buffer.iter_mut().zip(self.input.iter()).zip(self.window.iter()) {
+            *value = ComplexSample::new(sample * window, 0.0);
+        }
+        self.fft.process(&mut self.buffer);
+        for (bin, value) in self.buffer.iter_mut().enumerate() {
+            let power = value.norm_sqr();
+            if self.started {
+                self.power[bin] = POWER_SMOOTHING * self.power[bin] + (1.0 - POWER_SMOOTHING) * power;
+                self.noise[bin] = (self.noise[bin] * self.noise_rise).min(self.power[bin]);
+            } else {
+                self.power[bin] = power;
+                self.noise[bin] = power;
+            }
+            let gain = (1.0 - self.aggressiveness * NOISE_BIAS * self.noise[bin] / (power + 1e-20))
+                .max(GAIN_FLOOR * GAIN_FLOOR).sqrt();
+            self.gain[bin] = GAIN_SMOOTHING * self.gain[bin] + (1.0 - GAIN_SMOOTHING) * gain;
+            *value *= self.gain[bin];
+        }
+        self.started = true;
+        self.ifft.process(&mut self.buffer);
+
+        let scale = 1.0 / FRAME_LENGTH as Sample;
+        for ((sum, value), &window) in self.overlap.iter_mut().zip(self.buffer.iter()).zip(self.window.iter()) {
+            *sum += value.re * window * scale;
+        }
+        self.output.extend(self.overlap.drain(..HOP));
+        self.overlap.resize(FRAME_LENGTH, 0.0);
+        self.input.drain(..HOP);
+    }
+}
+
+impl AudioBlock for DenoiseBlock {
+    fn process(&mut self, audio: &mut Vec<Sample>, _info: &BlockInfo) {
+        for &sample in audio.iter() {
+            self.input.push(sample);
+            if self.input.len() >= FRAME_LENGTH {
+                self.process_frame();
+            }
+        }
+        for sample in audio.iter_mut() {
+            *sample = self.output.pop_front().unwrap_or(0.0);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::loopback::Random;
+    use super::super::demodulator::SAMPLE_RATE;
+
+    #[test]
+    fn test_denoise() {
+        let mut block = DenoiseBlock::new(2.0, SAMPLE_RATE);
+        let mut random = Random::new(1);
+        let length = SAMPLE_RATE as usize * 2;
+        // Syllables of a 1 kHz tone, 0.2 seconds on and off.
+        let tone = |i: usize| if (i * 5 / SAMPLE_RATE as usize).is_multiple_of(2) {
+            0.3 * (2.0 * std::f64::consts::PI * 1000.0 * i as f64 / SAMPLE_RATE).sin() as Sample
+        } else { 0.0 };
+        // Noise alone for a second, then with the tone.
+        let input: Vec<Sample> = (0..length).map(|i|
+            0.3 * (random.uniform() - 0.5) as Sample + if i >= length / 2 { tone(i) } else { 0.0 }
+        ).collect();
+        let mut output = Vec::new();
+        for chunk in input.chunks(48) {
+            let mut audio = chunk.to_vec();
+            block.process(&mut audio, &BlockInfo { power: 0.0 });
+            assert_eq!(audio.len(), chunk.len());
+            output.extend(audio);
+        }
+        let power = |audio: &[Sample]| audio.iter().map(|x| x * x).sum::<Sample>() / audio.len() as Sample;
+        // Noise is reduced by at least 10 dB.
+        let noise_ratio = power(&output[length / 4 .. length / 2]) / power(&input[length / 4 .. length / 2]);
+        assert!(noise_ratio < 0.1, "{}", noise_ratio);
+        // The tone passes.
+        let tone_ratio = power(&output[length / 2..]) / power(&(length / 2 .. length).map(tone).collect::<Vec<_>>());
+        assert!(tone_ratio > 0.7 && tone_ratio < 1.2, "{}", tone_ratio);
+    }
+}
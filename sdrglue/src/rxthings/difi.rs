@@ -0,0 +1,265 @@
+//! Channel IQ output as DIFI packets over UDP.
+//!
+//! DIFI (IEEE-ISTO 4900-2021) packets are VITA 49.2 packets
+//! with a fixed set of fields, supported by gr-difi and
+//! commercial SDR infrastructure. Signal data packets carry
+//! 16-bit big-endian IQ samples with UTC timestamps, and
+//! standard context packets describing frequency and sample
+//! rate are sent when the stream starts and every
+//! CONTEXT_INTERVAL seconds after that.
+
+use std::net::UdpSocket;
+
+use super::RxChannelProcessor;
+use super::demodulator::audio_udp_socket;
+use super::iq_output::{IqEncoder, IqFormat, IqValue};
+use crate::ComplexSample;
+use crate::clock;
+
+/// Maximum number of samples in a data packet,
+/// keeping packets within a 1500-byte MTU.
+const MAX_PACKET_SAMPLES: usize = 360;
+/// Time between context packets in seconds.
+const CONTEXT_INTERVAL: f64 = 1.0;
+
+/// DIFI organizationally unique identifier in class ID.
+const DIFI_OUI: u32 = 0x6A621;
+const PACKET_TYPE_DATA: u32 = 0x1;
+const PACKET_TYPE_CONTEXT: u32 = 0x4;
+const PACKET_CLASS_DATA: u32 = 0x0000;
+const PACKET_CLASS_CONTEXT: u32 = 0x0001;
+/// Context indicator field with the fields of a standard context
+/// packet, without the context field change indicator bit.
+const CONTEXT_FIELDS: u32 = 0x7BB98000;
+const CONTEXT_CHANGED: u32 = 0x80000000;
+/// Data packet payload format for complex 16-bit signed samples.
+const PAYLOAD_FORMAT: u64 = 0xA00003CF_00000000;
+/// Words in header, stream ID, class ID and timestamp.
+const PREFIX_WORDS: usize = 7;
+const CONTEXT_WORDS: usize = 27;
+
+/// Time of a sample as integer seconds and picoseconds.
+#[derive(Copy, Clone, Debug, PartialEq)]
+struct Timestamp {
+    seconds: u32,
+    picoseconds: u64,
+}
+
+impl Timestamp {
+    fn new(seconds: u64, fraction: f64) -> Self {
+        let whole = fraction.floor();
+        Self {
+            seconds: (seconds + whole as u64) as u32,
+            picoseconds: ((fraction - whole) * 1e12) as u64,
+        }
+    }
+}
+
+fn push_u32(packet: &mut Vec<u8>, value: u32) {
+    packet.extend_from_slice(&value.to_be_bytes());
+}
+
+fn push_u64(packet: &mut Vec<u8>, value: u64) {
+    packet.extend_from_slice(&value.to_be_bytes());
+}
+
+/// Push a frequency in Hertz as a 64-bit value
+/// with 20 bits after the radix point.
+fn push_frequency(packet: &mut Vec<u8>, frequency: f64) {
+    push_u64(packet, (frequency * (1 << 20) as f64).round() as i64 as u64);
+}
+
+/// Builder of DIFI packets for one stream.
+struct DifiEncoder {
+    stream_id: u32,
+    center_frequency: f64,
+    sample_rate: f64,
+    encoder: IqEncoder,
+    /// Modulo 16 counts of data and context packets.
+    data_count: u32,
+    context_count: u32,
+    /// Whether a context packet has been built yet.
+    context_sent: bool,
+}
+
+impl DifiEncoder {
+    fn new(stream_id: u32, center_frequency: f64, sample_rate: f64) -> Self {
+        Self {
+            stream_id,
+            center_frequency,
+            sample_rate,
+            encoder: IqEncoder::new(IqFormat { value: IqValue::I16, big_endian: true }),
+            data_count: 0,
+            context_count: 0,
+            context_sent: false,
+        }
+    }
+
+    /// Push header, stream ID, class ID and timestamp.
+    fn push_prefix(&self, packet: &mut Vec<u8>, packet_type: u32, count: u32, words: usize, packet_class: u32, time: Timestamp) {
+        // Class ID present, UTC integer timestamp
+        // and real time fractional timestamp.
+        push_u32(packet, packet_type << 28 | 1 << 27 | 0b01 << 22 | 0b10 << 20 | (count & 0xF) << 16 | words as u32);
+        push_u32(packet, self.stream_id);
+        push_u32(packet, DIFI_OUI);
+        push_u32(packet, packet_class);
+        push_u32(packet, time.seconds);
+        push_u64(packet, time.picoseconds);
+    }
+
+    /// Append a signal data packet to packet.
+    fn data_packet(&mut self, samples: &[ComplexSample], time: Timestamp, packet: &mut Vec<u8>) {
+        let words = PREFIX_WORDS + samples.len();
+        self.push_prefix(packet, PACKET_TYPE_DATA, self.data_count, words, PACKET_CLASS_DATA, time);
+        self.data_count = self.data_count.wrapping_add(1);
+        self.encoder.encode(samples, packet);
+    }
+
+    /// Append a standard context packet to packet.
+    fn context_packet(&mut self, time: Timestamp, packet: &mut Vec<u8>) {
+        self.push_prefix(packet, PACKET_TYPE_CONTEXT, self.context_count, CONTEXT_WORDS, PACKET_CLASS_CONTEXT, time);
+        self.context_count = self.context_count.wrapping_add(1);
+        push_u32(packet, if self.context_sent { CONTEXT_FIELDS } else { CONTEXT_FIELDS | CONTEXT_CHANGED });
+        self.context_sent = true;
+        // Reference point ID
+        push_u32(packet, 0);
+        // Bandwidth
+        push_frequency(packet, self.sample_rate);
+        // IF reference frequency
+        push_frequency(packet, 0.0);
+        // RF reference frequency
+        push_frequency(packet, self.center_frequency);
+        // IF band offset
+        push_frequency(packet, 0.0);
+        // Reference level and gain
+        push_u32(packet, 0);
+        push_u32(packet, 0);
+        // Sample rate
+        push_frequency(packet, self.sample_rate);
+        // Timestamp adjustment and calibration time
+        push_u64(packet, 0);
+        push_u32(packet, 0);
+        // State and event indicators
+        push_u32(packet, 0);
+        push_u64(packet, PAYLOAD_FORMAT);
+    }
+}
+
+pub struct DifiToUdp {
+    encoder: DifiEncoder,
+    /// Integer seconds of stream start time.
+    start_seconds: u64,
+    /// Fractional seconds of stream start time.
+    start_fraction: f64,
+    /// Samples sent since start time, or None before the
+    /// first block and after a discontinuity.
+    samples_sent: Option<u64>,
+    /// Samples sent at the time of the latest context packet.
+    context_samples: u64,
+    buffer: Vec<u8>,
+    socket: UdpSocket,
+}
+
+impl DifiToUdp {
+    pub fn new(address: &str, stream_id: u32, center_frequency: f64, sample_rate: f64) -> Self {
+        Self {
+            encoder: DifiEncoder::new(stream_id, center_frequency, sample_rate),
+            start_seconds: 0,
+            start_fraction: 0.0,
+            samples_sent: None,
+            context_samples: 0,
+            buffer: Vec::new(),
+            socket: audio_udp_socket(address),
+        }
+    }
+
+    fn send_buffer(&mut self) {
+        // TODO: print a warning or something if writing to socket fails
+        let _ = self.socket.send(&self.buffer);
+        self.buffer.clear();
+    }
+}
+
+impl RxChannelProcessor for DifiToUdp {
+    fn process(&mut self, samples: &[ComplexSample]) {
+        if self.samples_sent.is_none() {
+            let now = clock::now_seconds();
+            self.start_seconds = now as u64;
+            self.start_fraction = now.fract();
+            // Context is sent again as the timestamps restart.
+            self.context_samples = 0;
+            self.encoder.context_sent = false;
+        }
+        let samples_sent = self.samples_sent.unwrap_or(0);
+        let (start_seconds, start_fraction, sample_rate) = (self.start_seconds, self.start_fraction, self.encoder.sample_rate);
+        let time = |samples_sent: u64| Timestamp::new(start_seconds, start_fraction + samples_sent as f64 / sample_rate);
+
+        if !self.encoder.context_sent || (samples_sent - self.context_samples) as f64 >= CONTEXT_INTERVAL * sample_rate {
+            self.context_samples = samples_sent;
+            self.encoder.context_packet(time(samples_sent), &mut self.buffer);
+            self.send_buffer();
+        }
+        let mut position = samples_sent;
+        for packet_samples in samples.chunks(MAX_PACKET_SAMPLES) {
+            self.encoder.data_packet(packet_samples, time(position), &mut self.buffer);
+            self.send_buffer();
+            position += packet_samples.len() as u64;
+        }
+        self.samples_sent = Some(position);
+    }
+
+    fn input_sample_rate(&self) -> f64 {
+        self.encoder.sample_rate
+    }
+
+    fn input_center_frequency(&self) -> f64 {
+        self.encoder.center_frequency
+    }
+
+    fn discontinuity(&mut self) {
+        self.samples_sent = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn word(packet: &[u8], index: usize) -> u32 {
+        u32::from_be_bytes(packet[index * 4 .. index * 4 + 4].try_into().unwrap())
+    }
+
+    #[test]
+    fn test_packets() {
+        let mut encoder = DifiEncoder::new(5, 433.92e6, 96000.0);
+        let time = Timestamp::new(1700000000, 2.25);
+        assert_eq!(time, Timestamp { seconds: 1700000002, picoseconds: 250_000_000_000 });
+
+        let mut packet = Vec::new();
+        encoder.context_packet(time, &mut packet);
+        assert_eq!(packet.len(), CONTEXT_WORDS * 4);
+        assert_eq!(word(&packet, 0), 0x4860001B);
+        assert_eq!(word(&packet, 1), 5);
+        assert_eq!(word(&packet, 3), PACKET_CLASS_CONTEXT);
+        assert_eq!(word(&packet, 4), 1700000002);
+        assert_eq!(word(&packet, 7), 0xFBB98000);
+        // RF reference frequency and sample rate
+        let field = |index| (word(&packet, index) as u64) << 32 | word(&packet, index + 1) as u64;
+        assert_eq!(field(13) as f64 / (1 << 20) as f64, 433.92e6);
+        assert_eq!(field(19) as f64 / (1 << 20) as f64, 96000.0);
+        packet.clear();
+        encoder.context_packet(time, &mut packet);
+        assert_eq!(word(&packet, 0), 0x4861001B);
+        assert_eq!(word(&packet, 7), CONTEXT_FIELDS);
+
+        packet.clear();
+        encoder.data_packet(&[ComplexSample::new(1.0, -1.0); 3], time, &mut packet);
+        assert_eq!(packet.len(), (PREFIX_WORDS + 3) * 4);
+        assert_eq!(word(&packet, 0), 0x1860000A);
+        assert_eq!(word(&packet, 2), DIFI_OUI);
+        assert_eq!(word(&packet, 3), PACKET_CLASS_DATA);
+        // Dither changes the values by at most one step.
+        let sample = word(&packet, PREFIX_WORDS);
+        assert!((sample >> 16) as i16 >= i16::MAX - 1 && sample as i16 <= -i16::MAX + 1, "{:08X}", sample);
+    }
+}
@@ -0,0 +1,86 @@
+//! Raw FM discriminator output for external decoders.
+//!
+//! Decoders of digital voice modes such as DSD and dsd-fme
+//! expect the output of an FM discriminator without
+//! de-emphasis, audio filtering or squelch, since those
+//! would distort the symbols. Discriminator output is sent
+//! continuously over UDP as 16-bit signed little-endian
+//! samples at 48 kHz.
+
+use super::RxChannelProcessor;
+use super::demodulator::{AudioUdpOutput, ChannelFilter, Demodulator, Modulation, SAMPLE_RATE};
+use crate::{Sample, ComplexSample};
+
+/// Frequency deviation in Hertz corresponding to full scale.
+/// Leaves headroom for digital modes with deviations up to
+/// a few kHz and for frequency errors.
+const FULL_SCALE_DEVIATION: f64 = 12000.0;
+
+pub struct DiscriminatorToUdp {
+    center_frequency: f64,
+    demodulator: Demodulator,
+    /// Discriminator output of the latest block.
+    audio_buffer: Vec<Sample>,
+    output: AudioUdpOutput,
+}
+
+impl DiscriminatorToUdp {
+    pub fn new(address: &str, center_frequency: f64, channel_filter: ChannelFilter, frame_duration: f64) -> Self {
+        Self {
+            center_frequency,
+            demodulator: Demodulator::with_filter(Modulation::FM,
+                channel_filter),
+            audio_buffer: Vec::with_capacity(48),
+            output: AudioUdpOutput::new(address, frame_duration, 1),
+        }
+    }
+}
+
+/// Scale FM demodulator output, where full scale is
+/// half of the sample rate, to discriminator output.
+fn scale(audio: &mut [Sample]) {
+    let gain = (SAMPLE_RATE / 2.0 / FULL_SCALE_DEVIATION) as Sample;
+    for sample in audio.iter_mut() {
+        *sample *= gain;
+    }
+}
+
+impl RxChannelProcessor for DiscriminatorToUdp {
+    fn process(&mut self, samples: &[ComplexSample]) {
+        self.audio_buffer.clear();
+        self.demodulator.process(samples, &mut self.audio_buffer);
+        scale(&mut self.audio_buffer);
+        self.output.send(&self.audio_buffer);
+    }
+
+    fn input_sample_rate(&self) -> f64 {
+        SAMPLE_RATE
+    }
+
+    fn input_center_frequency(&self) -> f64 {
+        self.center_frequency
+    }
+
+    fn discontinuity(&mut self) {
+        self.demodulator.reset();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scale() {
+        // Carrier 3 kHz above channel center
+        // gives a quarter of full scale.
+        let mut demodulator = Demodulator::new(Modulation::FM);
+        let input: Vec<ComplexSample> = (0..4800).map(|i|
+            ComplexSample::from_polar(1.0, (2.0 * std::f64::consts::PI * 3000.0 * i as f64 / SAMPLE_RATE) as Sample)
+        ).collect();
+        let mut audio = Vec::new();
+        demodulator.process(&input, &mut audio);
+        scale(&mut audio);
+        assert!(audio[100..].iter().all(|&x| (x - 0.25).abs() < 1e-3));
+    }
+}
@@ -0,0 +1,243 @@
+//! Detection of DTMF digits in demodulated audio.
+//!
+//! Audio is decimated by averaging and the power at each
+//! DTMF row and column frequency is measured with a bank of
+//! Goertzel filters over half-overlapping windows of twice
+//! STEP_TIME. A digit is detected in a window when
+//! one row and one column tone are strong enough, dominate the
+//! other tones of their group, have a limited level difference
+//! (twist) and make up most of the audio energy, which rejects
+//! speech. A digit is reported once it has been detected in
+//! two consecutive windows, so digits lasting at least about
+//! 50 ms are detected. The same digit is reported again only
+//! after a window without it.
+
+use super::RxChannelProcessor;
+use super::ctcss::Goertzel;
+use super::demodulator::{ChannelFilter, Demodulator, Modulation, SAMPLE_RATE};
+use super::rtty::TextOutput;
+use crate::{Sample, ComplexSample};
+use crate::{clock, events};
+
+/// Row tone frequencies in Hertz.
+const ROWS: [f64; 4] = [697.0, 770.0, 852.0, 941.0];
+/// Column tone frequencies in Hertz.
+const COLUMNS: [f64; 4] = [1209.0, 1336.0, 1477.0, 1633.0];
+/// Digits by row and column.
+const KEYPAD: &[u8; 16] = b"123A456B789C*0#D";
+
+/// Audio is decimated by this factor before tone detection.
+const DECIMATION: usize = 6;
+/// Time between starts of detection windows in seconds.
+const STEP_TIME: f64 = 0.0128;
+/// Minimum amplitude of each tone relative to full scale audio.
+const MIN_AMPLITUDE: Sample = 0.01;
+/// Strongest tone of a group must have this many times
+/// the power of any other tone in the group.
+const MIN_GROUP_RATIO: Sample = 4.0;
+/// Maximum power ratio between row and column tones.
+const MAX_TWIST: Sample = 6.3;
+/// Minimum fraction of audio energy in the two tones.
+const MIN_ENERGY_FRACTION: Sample = 0.5;
+
+struct DtmfDetector {
+    /// Filters for row tones followed by column tones,
+    /// for the two overlapping windows.
+    banks: [Vec<Goertzel>; 2],
+    /// Sum of audio samples being averaged for decimation.
+    sum: Sample,
+    decimation_phase: usize,
+    /// Energy of decimated samples in each window.
+    energies: [Sample; 2],
+    /// Decimated samples in the current step.
+    step_samples: usize,
+    step_length: usize,
+    /// Index of the window finishing at the end of the current step.
+    finishing: usize,
+    /// Digit detected in the previous window.
+    previous: Option<u8>,
+    /// Digit reported and still being detected.
+    current: Option<u8>,
+}
+
+impl DtmfDetector {
+    fn new() -> Self {
+        let sample_rate = SAMPLE_RATE / DECIMATION as f64;
+        let bank = || ROWS.iter().chain(COLUMNS.iter()).map(|&frequency| Goertzel::new(frequency, sample_rate)).collect();
+        Self {
+            banks: [bank(), bank()],
+            sum: 0.0,
+            decimation_phase: 0,
+            energies: [0.0; 2],
+            step_samples: 0,
+            step_length: (STEP_TIME * sample_rate).round() as usize,
+            finishing: 0,
+            previous: None,
+            current: None,
+        }
+    }
+
+    fn reset(&mut self) {
+        *self = Self::new();
+    }
+
+    /// Process a block of demodulated audio at SAMPLE_RATE
+    /// and call output for each new digit.
+    fn process(&mut self, audio: &[Sample], mut output: impl FnMut(char)) {
+        for &sample in audio {
+            self.sum += sample;
+            self.decimation_phase += 1;
+            if self.decimation_phase < DECIMATION {
+                continue;
+            }
+            let decimated = self.sum / DECIMATION as Sample;
+            self.sum = 0.0;
+            self.decimation_phase = 0;
+
+            for filter in self.banks.iter_mut().flatten() {
+                filter.sample(decimated);
+            }
+            for energy in self.energies.iter_mut() {
+                *energy += decimated * decimated;
+            }
+            self.step_samples += 1;
+            if self.step_samples >= self.step_length {
+                self.step_samples = 0;
+                let detected = self.detect(self.finishing);
+                self.finishing ^= 1;
+                if let Some(digit) = detected {
+                    if detected == self.previous && detected != self.current {
+                        self.current = detected;
+                        output(digit as char);
+                    }
+                } else {
+                    self.current = None;
+                }
+                self.previous = detected;
+            }
+        }
+    }
+
+    /// Return the digit present in a finished window and reset it.
+    fn detect(&mut self, window: usize) -> Option<u8> {
+        let powers: Vec<Sample> = self.banks[window].iter_mut().map(Goertzel::finish).collect();
+        let energy = std::mem::take(&mut self.energies[window]);
+        let length = (2 * self.step_length) as Sample;
+
+        // Index of the dominating tone in a group, if any.
+        let strongest = |group: &[Sample]| -> Option<usize> {
+            let (index, &power) = group.iter().enumerate().max_by(|a, b| a.1.total_cmp(b.1))?;
+            let amplitude = 2.0 * power.sqrt() / length;
+            (amplitude >= MIN_AMPLITUDE
+                && group.iter().enumerate().all(|(other, &p)| other == index || p * MIN_GROUP_RATIO <= power)
+            ).then_some(index)
+        };
+        let row = strongest(&powers[..4])?;
+        let column = strongest(&powers[4..])?;
+        let (row_power, column_power) = (powers[row], powers[4 + column]);
+        // A sinusoid with power P in a window of N samples
+        // has an energy of 2 P / N.
+        let tone_energy = 2.0 * (row_power + column_power) / length;
+        (row_power <= column_power * MAX_TWIST
+            && column_power <= row_power * MAX_TWIST
+            && tone_energy >= MIN_ENERGY_FRACTION * energy
+        ).then_some(KEYPAD[row * 4 + column])
+    }
+}
+
+pub struct DecodeDtmf {
+    /// Frequency of the signal, used in events.
+    frequency: f64,
+    /// Input center frequency
+    center_frequency: f64,
+    demodulator: Demodulator,
+    detector: DtmfDetector,
+    audio_buffer: Vec<Sample>,
+    output: TextOutput,
+}
+
+impl DecodeDtmf {
+    /// Create a decoder sending digits to a udp:// or tcp:// address.
+    pub fn new(destination: &str, frequency: f64, modulation: Modulation, channel_filter: ChannelFilter) -> Self {
+        Self {
+            frequency,
+            center_frequency: frequency + Demodulator::frequency_offset(modulation),
+            demodulator: Demodulator::with_filter(modulation,
+                channel_filter),
+            detector: DtmfDetector::new(),
+            audio_buffer: Vec::with_capacity(48),
+            output: TextOutput::new(destination),
+        }
+    }
+}
+
+impl RxChannelProcessor for DecodeDtmf {
+    fn process(&mut self, samples: &[ComplexSample]) {
+        self.audio_buffer.clear();
+        self.demodulator.process(samples, &mut self.audio_buffer);
+        let mut digits = Vec::new();
+        self.detector.process(&self.audio_buffer, |digit| digits.push(digit));
+        for digit in digits {
+            let time = clock::now_seconds();
+            self.output.send(&format!("{:.3} {}", time, digit));
+            events::emit(events::Event::new("dtmf_digit")
+                .with("frequency", self.frequency)
+                .with("digit", digit.to_string()));
+        }
+    }
+
+    fn input_sample_rate(&self) -> f64 {
+        SAMPLE_RATE
+    }
+
+    fn input_center_frequency(&self) -> f64 {
+        self.center_frequency
+    }
+
+    fn discontinuity(&mut self) {
+        self.demodulator.reset();
+        self.detector.reset();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Audio with each digit for 60 ms followed by 60 ms of silence.
+    fn modulate(digits: &str, amplitude: f64) -> Vec<Sample> {
+        let tone_length = (0.06 * SAMPLE_RATE) as usize;
+        let mut audio = Vec::new();
+        for digit in digits.bytes() {
+            let index = KEYPAD.iter().position(|&d| d == digit).unwrap();
+            let (row, column) = (ROWS[index / 4], COLUMNS[index % 4]);
+            audio.extend((0..tone_length).map(|i| {
+                let t = i as f64 / SAMPLE_RATE;
+                (amplitude * ((2.0 * std::f64::consts::PI * row * t).sin()
+                + (2.0 * std::f64::consts::PI * column * t).sin())) as Sample
+            }));
+            audio.extend(vec![0.0; tone_length]);
+        }
+        audio
+    }
+
+    fn decode(audio: &[Sample]) -> String {
+        let mut detector = DtmfDetector::new();
+        let mut digits = String::new();
+        for block in audio.chunks(48) {
+            detector.process(block, |digit| digits.push(digit));
+        }
+        digits
+    }
+
+    #[test]
+    fn test_decode() {
+        assert_eq!(decode(&modulate("123A456B789C*0#D", 0.1)), "123A456B789C*0#D");
+        // Repeated digits are separated by the pauses.
+        assert_eq!(decode(&modulate("0011", 0.05)), "0011");
+        // Single tones and weak tones are not digits.
+        let tone: Vec<Sample> = (0..4800).map(|i| (0.1 * (2.0 * std::f64::consts::PI * 770.0 * i as f64 / SAMPLE_RATE).sin()) as Sample).collect();
+        assert_eq!(decode(&tone), "");
+        assert_eq!(decode(&modulate("5", 0.002)), "");
+    }
+}
@@ -0,0 +1,72 @@
+//! Binary frequency shift keying demodulator.
+//!
+//! Uses an FM discriminator followed by integrate-and-dump
+//! detection, with symbol timing recovered from transitions
+//! between symbols.
+
+use crate::{Sample, ComplexSample};
+
+/// How much symbol timing is corrected at each transition,
+/// as a fraction of the timing error.
+const TIMING_GAIN: f64 = 0.1;
+
+pub struct FskDemodulator {
+    previous_sample: ComplexSample,
+    /// Scaling from phase difference to symbol value.
+    scaling: Sample,
+    /// Smoothing filter coefficient for discriminator output.
+    alpha: Sample,
+    /// Smoothed discriminator output.
+    smoothed: Sample,
+    /// Symbol clock phase, from 0 to 1.
+    clock: f64,
+    /// Clock phase increment per sample.
+    clock_increment: f64,
+    /// Sum of discriminator output over current symbol.
+    integrator: Sample,
+}
+
+impl FskDemodulator {
+    /// Create a demodulator with given symbol rate and deviation in Hertz.
+    /// A one bit is received at the positive frequency.
+    pub fn new(sample_rate: f64, symbol_rate: f64, deviation: f64) -> Self {
+        let samples_per_symbol = sample_rate / symbol_rate;
+        Self {
+            previous_sample: ComplexSample::ZERO,
+            scaling: (sample_rate / (2.0 * std::f64::consts::PI * deviation)) as Sample,
+            // Time constant of a quarter symbol reduces noise
+            // without smearing symbols too much.
+            alpha: (1.0 - (-4.0 / samples_per_symbol).exp()) as Sample,
+            smoothed: 0.0,
+            clock: 0.0,
+            clock_increment: symbol_rate / sample_rate,
+            integrator: 0.0,
+        }
+    }
+
+    /// Demodulate samples, calling output for each received bit.
+    pub fn process(&mut self, samples: &[ComplexSample], mut output: impl FnMut(bool)) {
+        for &sample in samples {
+            let value = (sample * self.previous_sample.conj()).arg() * self.scaling;
+            self.previous_sample = sample;
+
+            let previous = self.smoothed;
+            self.smoothed += self.alpha * (value - self.smoothed);
+            self.integrator += self.smoothed;
+
+            // A transition should happen at a symbol boundary,
+            // where clock phase wraps around.
+            if (previous < 0.0) != (self.smoothed < 0.0) {
+                let error = if self.clock < 0.5 { self.clock } else { self.clock - 1.0 };
+                self.clock -= TIMING_GAIN * error;
+            }
+
+            self.clock += self.clock_increment;
+            if self.clock >= 1.0 {
+                self.clock -= 1.0;
+                output(self.integrator > 0.0);
+                self.integrator = 0.0;
+            }
+        }
+    }
+}
@@ -0,0 +1,177 @@
+//! Channel IQ sample formats and IQ output to UDP.
+//!
+//! IQ outputs can use any of the sample formats commonly
+//! expected by other programs, named as in SoapySDR:
+//! cf32_le, cf32_be, cs16_le, cs16_be, cs8 or cu8.
+//! Samples are scaled so that 1.0 is full scale.
+//! When converting to integers, samples are dithered
+//! to avoid distortion products from rounding
+//! weak signals to a few quantization levels.
+
+use std::net::UdpSocket;
+
+use super::RxChannelProcessor;
+use super::demodulator::audio_udp_socket;
+use crate::{Sample, ComplexSample};
+use crate::loopback::Random;
+
+/// Type of each I and Q value.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum IqValue {
+    F32,
+    I16,
+    I8,
+    /// Unsigned 8-bit values with zero at 127.5, as used by RTL-SDR.
+    U8,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct IqFormat {
+    pub value: IqValue,
+    pub big_endian: bool,
+}
+
+impl Default for IqFormat {
+    fn default() -> Self {
+        Self { value: IqValue::I16, big_endian: false }
+    }
+}
+
+impl IqFormat {
+    pub fn parse(name: &str) -> Option<Self> {
+        let (value, big_endian) = match name.to_lowercase().as_str() {
+            "cf32_le" => (IqValue::F32, false),
+            "cf32_be" => (IqValue::F32, true),
+            "cs16_le" => (IqValue::I16, false),
+            "cs16_be" => (IqValue::I16, true),
+            "cs8" => (IqValue::I8, false),
+            "cu8" => (IqValue::U8, false),
+            _ => return None,
+        };
+        Some(Self { value, big_endian })
+    }
+
+    /// Number of bytes per complex sample.
+    pub fn sample_bytes(&self) -> usize {
+        match self.value {
+            IqValue::F32 => 8,
+            IqValue::I16 => 4,
+            IqValue::I8 | IqValue::U8 => 2,
+        }
+    }
+}
+
+/// Converter of IQ samples to bytes in a given format.
+pub struct IqEncoder {
+    format: IqFormat,
+    /// Random numbers for dither.
+    random: Random,
+}
+
+impl IqEncoder {
+    pub fn new(format: IqFormat) -> Self {
+        Self {
+            format,
+            random: Random::new(1),
+        }
+    }
+
+    /// Value rounded to an integer after adding
+    /// triangular dither of one quantization step.
+    fn dither(&mut self, value: Sample) -> Sample {
+        (value + (self.random.uniform() + self.random.uniform() - 1.0) as Sample).round()
+    }
+
+    /// Append samples converted to the format to output.
+    pub fn encode(&mut self, samples: &[ComplexSample], output: &mut Vec<u8>) {
+        output.reserve(samples.len() * self.format.sample_bytes());
+        let big_endian = self.format.big_endian;
+        for value in samples.iter().flat_map(|sample| [sample.re, sample.im]) {
+            match self.format.value {
+                IqValue::F32 => output.extend_from_slice(
+                    &if big_endian { value.to_be_bytes() } else { value.to_le_bytes() }),
+                IqValue::I16 => {
+                    let full_scale = i16::MAX as Sample;
+                    let value = self.dither(value * full_scale).clamp(-full_scale, full_scale) as i16;
+                    output.extend_from_slice(&if big_endian { value.to_be_bytes() } else { value.to_le_bytes() });
+                },
+                IqValue::I8 => {
+                    let full_scale = i8::MAX as Sample;
+                    output.push(self.dither(value * full_scale).clamp(-full_scale, full_scale) as i8 as u8);
+                },
+                IqValue::U8 => output.push(self.dither(value * 127.5 + 127.5).clamp(0.0, 255.0) as u8),
+            }
+        }
+    }
+}
+
+/// Channel IQ samples sent to a UDP socket,
+/// one packet for each processed block.
+pub struct IqToUdp {
+    center_frequency: f64,
+    sample_rate: f64,
+    encoder: IqEncoder,
+    /// Encoded samples of the latest block.
+    buffer: Vec<u8>,
+    socket: UdpSocket,
+}
+
+impl IqToUdp {
+    pub fn new(address: &str, center_frequency: f64, sample_rate: f64, format: IqFormat) -> Self {
+        Self {
+            center_frequency,
+            sample_rate,
+            encoder: IqEncoder::new(format),
+            buffer: Vec::new(),
+            socket: audio_udp_socket(address),
+        }
+    }
+}
+
+impl RxChannelProcessor for IqToUdp {
+    fn process(&mut self, samples: &[ComplexSample]) {
+        self.buffer.clear();
+        self.encoder.encode(samples, &mut self.buffer);
+        // TODO: print a warning or something if writing to socket fails
+        let _ = self.socket.send(&self.buffer);
+    }
+
+    fn input_sample_rate(&self) -> f64 {
+        self.sample_rate
+    }
+
+    fn input_center_frequency(&self) -> f64 {
+        self.center_frequency
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode() {
+        let samples = [ComplexSample::new(0.5, -1.5)];
+        let encode = |name: &str| {
+            let mut output = Vec::new();
+            IqEncoder::new(IqFormat::parse(name).unwrap()).encode(&samples, &mut output);
+            output
+        };
+        assert_eq!(encode("cf32_be"), [0.5f32.to_be_bytes(), (-1.5f32).to_be_bytes()].concat());
+        // Dither changes the value by at most one step.
+        let value = i16::from_be_bytes(encode("cs16_be")[..2].try_into().unwrap());
+        assert!((value - 16384).abs() <= 1, "{}", value);
+        assert_eq!(&encode("CS16_LE")[2..], &(-i16::MAX).to_le_bytes());
+        assert_eq!(encode("cs8")[1] as i8, -127);
+        assert_eq!(encode("cu8")[1], 0);
+        assert!(IqFormat::parse("cs12").is_none());
+
+        // Dithered values average to the exact value
+        // even when it is between quantization steps.
+        let mut encoder = IqEncoder::new(IqFormat::parse("cs8").unwrap());
+        let mut output = Vec::new();
+        encoder.encode(&vec![ComplexSample::new(0.3 / 127.0, 0.0); 10000], &mut output);
+        let mean = output.iter().step_by(2).map(|&value| value as i8 as f64).sum::<f64>() / 10000.0;
+        assert!((mean - 0.3).abs() < 0.03, "mean {}", mean);
+    }
+}
@@ -0,0 +1,68 @@
+//! Demodulator with a JACK output port.
+
+use super::RxChannelProcessor;
+use super::chain::{AudioChain, BlockInfo};
+use super::demodulator::{ChannelFilter, Demodulator, Modulation, SAMPLE_RATE};
+use crate::{Sample, ComplexSample};
+use crate::jack;
+
+pub struct DemodulateToJack {
+    center_frequency: f64,
+    demodulator: Demodulator,
+    /// Optional audio processing chain after demodulator.
+    chain: Option<AudioChain>,
+    audio_buffer: Vec<Sample>,
+    /// Audio waiting to be read by the JACK process callback.
+    fifo: jack::AudioFifo,
+}
+
+pub struct DemodulateToJackParameters<'a> {
+    /// Name of the output port.
+    pub port: &'a str,
+    pub center_frequency: f64,
+    pub modulation: Modulation,
+    pub channel_filter: ChannelFilter,
+    /// Description of audio processing chain, if any.
+    pub chain: Option<&'a str>,
+}
+
+impl DemodulateToJack {
+    pub fn new(parameters: &DemodulateToJackParameters) -> Self {
+        Self {
+            center_frequency:
+                parameters.center_frequency
+                + Demodulator::frequency_offset(parameters.modulation),
+            demodulator: Demodulator::with_filter(parameters.modulation,
+                parameters.channel_filter),
+            // TODO: handle errors more nicely
+            chain: parameters.chain.map(|description|
+                AudioChain::parse_mono(description, parameters.center_frequency)
+                .unwrap_or_else(|err| panic!("Invalid audio chain: {}", err))),
+            audio_buffer: Vec::new(),
+            fifo: jack::output_port(parameters.port),
+        }
+    }
+}
+
+impl RxChannelProcessor for DemodulateToJack {
+    fn process(&mut self, samples: &[ComplexSample]) {
+        self.audio_buffer.clear();
+        self.demodulator.process(samples, &mut self.audio_buffer);
+        if let Some(chain) = &mut self.chain {
+            chain.process(&mut self.audio_buffer, &BlockInfo { power: self.demodulator.power() });
+        }
+        jack::push_audio(&mut self.fifo.lock().unwrap(), self.audio_buffer.iter().copied());
+    }
+
+    fn input_sample_rate(&self) -> f64 {
+        SAMPLE_RATE
+    }
+
+    fn input_center_frequency(&self) -> f64 {
+        self.center_frequency
+    }
+
+    fn discontinuity(&mut self) {
+        self.demodulator.reset();
+    }
+}
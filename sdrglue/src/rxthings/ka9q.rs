@@ -0,0 +1,250 @@
+//! Output of channels as ka9q-radio compatible RTP streams.
+//!
+//! ka9q-radio sends each channel as an RTP stream of 16-bit
+//! big-endian samples, usually to a multicast group, with the
+//! SSRC being the channel frequency in kHz. Metadata is sent as
+//! status packets to port STATUS_PORT of the same group, so its
+//! clients such as pcmrecord and monitor find the sample rate,
+//! frequency and number of channels of each stream from them.
+//! Demodulated audio is sent as mono and IQ as stereo.
+
+use std::net::{SocketAddr, UdpSocket};
+
+use super::RxChannelProcessor;
+use super::demodulator::{ChannelFilter, Demodulator, Modulation, SAMPLE_RATE, audio_udp_socket};
+use super::iq_output::{IqEncoder, IqFormat, IqValue};
+use crate::{Sample, ComplexSample};
+
+/// Port for status packets.
+const STATUS_PORT: u16 = 5006;
+/// Time between status packets in seconds.
+const STATUS_INTERVAL: f64 = 1.0;
+/// Maximum RTP payload size in bytes,
+/// keeping packets within a 1500-byte MTU.
+const MAX_PAYLOAD: usize = 1440;
+
+/// RTP payload types for 16-bit big-endian PCM.
+const PT_PCM_STEREO: u8 = 10;
+const PT_PCM_MONO: u8 = 11;
+
+/// Tags of status packet fields, as in ka9q-radio status.h.
+const STATUS_PACKET: u8 = 0;
+const EOL: u8 = 0;
+const DESCRIPTION: u8 = 4;
+const OUTPUT_SSRC: u8 = 18;
+const OUTPUT_SAMPRATE: u8 = 20;
+const OUTPUT_DATA_PACKETS: u8 = 22;
+const RADIO_FREQUENCY: u8 = 33;
+const DEMOD_TYPE: u8 = 48;
+const OUTPUT_CHANNELS: u8 = 49;
+
+/// Demodulator types of ka9q-radio.
+const DEMOD_LINEAR: u64 = 0;
+const DEMOD_FM: u64 = 1;
+
+/// Append a status field with an integer value. Like in
+/// ka9q-radio, leading zero bytes of the value are left out.
+fn push_integer(packet: &mut Vec<u8>, tag: u8, value: u64) {
+    let bytes = value.to_be_bytes();
+    let start = bytes.iter().position(|&byte| byte != 0).unwrap_or(bytes.len());
+    packet.push(tag);
+    packet.push((bytes.len() - start) as u8);
+    packet.extend_from_slice(&bytes[start..]);
+}
+
+/// Append a status field with a double value,
+/// encoded like an integer with the same bits.
+fn push_double(packet: &mut Vec<u8>, tag: u8, value: f64) {
+    push_integer(packet, tag, value.to_bits());
+}
+
+fn push_string(packet: &mut Vec<u8>, tag: u8, value: &str) {
+    packet.push(tag);
+    packet.push(value.len() as u8);
+    packet.extend_from_slice(value.as_bytes());
+}
+
+pub struct Ka9qOutputParameters<'a> {
+    /// Destination address for RTP packets, usually
+    /// a multicast group with port 5004.
+    pub address: &'a str,
+    pub frequency: f64,
+    /// Modulation to demodulate and its channel filter, or None for IQ output.
+    pub demodulation: Option<(Modulation, ChannelFilter)>,
+    /// Sample rate of IQ output. Audio is always at SAMPLE_RATE.
+    pub sample_rate: f64,
+}
+
+pub struct Ka9qOutput {
+    /// Frequency of the signal, reported in status.
+    frequency: f64,
+    /// Input center frequency
+    center_frequency: f64,
+    sample_rate: f64,
+    demodulator: Option<Demodulator>,
+    /// Encoder for IQ output.
+    encoder: IqEncoder,
+    audio_buffer: Vec<Sample>,
+    /// Samples waiting to be sent, in big-endian 16-bit format.
+    fifo: Vec<u8>,
+    /// Payload size of each packet in bytes.
+    frame_bytes: usize,
+    /// 1 for audio, 2 for IQ.
+    channels: usize,
+    demod_type: u64,
+    ssrc: u32,
+    sequence: u16,
+    /// RTP timestamp counting sample frames.
+    timestamp: u32,
+    packets_sent: u64,
+    /// Samples processed since the latest status packet,
+    /// or None if no status has been sent yet.
+    since_status: Option<f64>,
+    socket: UdpSocket,
+    status_socket: UdpSocket,
+}
+
+impl Ka9qOutput {
+    pub fn new(parameters: Ka9qOutputParameters) -> Self {
+        let modulation = parameters.demodulation.map(|(modulation, _)| modulation);
+        let (channels, sample_rate) = match modulation {
+            Some(_) => (1, SAMPLE_RATE),
+            None => (2, parameters.sample_rate),
+        };
+        // TODO: handle errors more nicely
+        let mut status_address: SocketAddr = parameters.address.parse()
+            .unwrap_or_else(|_| panic!("Invalid ka9q-radio output address {}", parameters.address));
+        status_address.set_port(STATUS_PORT);
+        let sample_bytes = 2 * channels;
+        Self {
+            frequency: parameters.frequency,
+            center_frequency: parameters.frequency
+                + modulation.map_or(0.0, Demodulator::frequency_offset),
+            sample_rate,
+            demodulator: parameters.demodulation.map(|(modulation, channel_filter)|
+                Demodulator::with_filter(modulation, channel_filter)),
+            encoder: IqEncoder::new(IqFormat { value: IqValue::I16, big_endian: true }),
+            audio_buffer: Vec::with_capacity(48),
+            fifo: Vec::new(),
+            // 20 ms packets if they fit in MAX_PAYLOAD.
+            frame_bytes: ((0.02 * sample_rate).round() as usize * sample_bytes).min(MAX_PAYLOAD / sample_bytes * sample_bytes),
+            channels,
+            demod_type: if matches!(modulation, Some(Modulation::FM)) { DEMOD_FM } else { DEMOD_LINEAR },
+            ssrc: (parameters.frequency * 1e-3).round() as u32,
+            sequence: 0,
+            timestamp: 0,
+            packets_sent: 0,
+            since_status: None,
+            socket: audio_udp_socket(parameters.address),
+            status_socket: audio_udp_socket(&status_address.to_string()),
+        }
+    }
+
+    fn rtp_header(&self) -> [u8; 12] {
+        let mut header = [0; 12];
+        // Version 2, no padding, extension or CSRCs.
+        header[0] = 0x80;
+        header[1] = if self.channels == 1 { PT_PCM_MONO } else { PT_PCM_STEREO };
+        header[2..4].copy_from_slice(&self.sequence.to_be_bytes());
+        header[4..8].copy_from_slice(&self.timestamp.to_be_bytes());
+        header[8..12].copy_from_slice(&self.ssrc.to_be_bytes());
+        header
+    }
+
+    fn status_packet(&self) -> Vec<u8> {
+        let mut packet = vec![STATUS_PACKET];
+        push_string(&mut packet, DESCRIPTION, "sdrglue");
+        push_integer(&mut packet, OUTPUT_SSRC, self.ssrc as u64);
+        push_integer(&mut packet, OUTPUT_SAMPRATE, self.sample_rate.round() as u64);
+        push_integer(&mut packet, OUTPUT_DATA_PACKETS, self.packets_sent);
+        push_double(&mut packet, RADIO_FREQUENCY, self.frequency);
+        push_integer(&mut packet, DEMOD_TYPE, self.demod_type);
+        push_integer(&mut packet, OUTPUT_CHANNELS, self.channels as u64);
+        packet.push(EOL);
+        packet
+    }
+
+    /// Send full packets from the FIFO.
+    fn send_packets(&mut self) {
+        let mut sent = 0;
+        while self.fifo.len() - sent >= self.frame_bytes {
+            let mut packet = self.rtp_header().to_vec();
+            packet.extend_from_slice(&self.fifo[sent .. sent + self.frame_bytes]);
+            // TODO: print a warning or something if writing to socket fails
+            let _ = self.socket.send(&packet);
+            sent += self.frame_bytes;
+            self.sequence = self.sequence.wrapping_add(1);
+            self.timestamp = self.timestamp.wrapping_add((self.frame_bytes / (2 * self.channels)) as u32);
+            self.packets_sent += 1;
+        }
+        self.fifo.drain(..sent);
+    }
+}
+
+impl RxChannelProcessor for Ka9qOutput {
+    fn process(&mut self, samples: &[ComplexSample]) {
+        match &mut self.demodulator {
+            Some(demodulator) => {
+                self.audio_buffer.clear();
+                demodulator.process(samples, &mut self.audio_buffer);
+                let full_scale = i16::MAX as Sample;
+                for &sample in self.audio_buffer.iter() {
+                    self.fifo.extend_from_slice(&((sample * full_scale).clamp(-full_scale, full_scale) as i16).to_be_bytes());
+                }
+            },
+            None => self.encoder.encode(samples, &mut self.fifo),
+        }
+        self.send_packets();
+
+        let since_status = self.since_status.map_or(f64::INFINITY, |time| time + samples.len() as f64 / self.input_sample_rate());
+        self.since_status = Some(since_status);
+        if since_status >= STATUS_INTERVAL {
+            let _ = self.status_socket.send(&self.status_packet());
+            self.since_status = Some(0.0);
+        }
+    }
+
+    fn input_sample_rate(&self) -> f64 {
+        self.sample_rate
+    }
+
+    fn input_center_frequency(&self) -> f64 {
+        self.center_frequency
+    }
+
+    fn discontinuity(&mut self) {
+        if let Some(demodulator) = &mut self.demodulator {
+            demodulator.reset();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_packets() {
+        let mut output = Ka9qOutput::new(Ka9qOutputParameters {
+            address: "127.0.0.1:5004",
+            frequency: 145.5e6,
+            demodulation: Some((Modulation::FM, ChannelFilter::default_for(Modulation::FM))),
+            sample_rate: SAMPLE_RATE,
+        });
+        assert_eq!(output.ssrc, 145500);
+        let status = output.status_packet();
+        assert_eq!(&status[..11], &[STATUS_PACKET, DESCRIPTION, 7, b's', b'd', b'r', b'g', b'l', b'u', b'e', OUTPUT_SSRC]);
+        // Leading zeros are left out of integer values.
+        assert_eq!(&status[11..20], &[3, 0x02, 0x38, 0x5C, OUTPUT_SAMPRATE, 2, 0xBB, 0x80, OUTPUT_DATA_PACKETS]);
+        assert_eq!(&status[20..22], &[0, RADIO_FREQUENCY]);
+        assert_eq!(status[22], 8);
+        assert_eq!(&status[status.len() - 7..], &[DEMOD_TYPE, 1, 1, OUTPUT_CHANNELS, 1, 1, EOL]);
+
+        // Packets are limited to MAX_PAYLOAD.
+        output.process(&vec![ComplexSample::ZERO; 1000]);
+        assert_eq!((output.sequence, output.timestamp, output.fifo.len()), (1, 720, 560));
+        let header = output.rtp_header();
+        assert_eq!(header[..4], [0x80, PT_PCM_MONO, 0, 1]);
+        assert_eq!(header[4..], [0, 0, 0x02, 0xD0, 0, 0x02, 0x38, 0x5C]);
+    }
+}
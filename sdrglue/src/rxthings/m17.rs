@@ -0,0 +1,698 @@
+//! M17 decoder for 4FSK digital voice streams.
+//!
+//! Symbols are demodulated from FM audio by a root raised cosine
+//! matched filter. Frames are found by correlating with the sync
+//! words, and the symbols of each frame are sampled at the timing
+//! of its sync word. Link setup frames and stream frames are
+//! derandomized, deinterleaved and Viterbi decoded, and stream
+//! frames are sent to a UDP address in the M17 network packet
+//! format used by reflectors, so that M17 network tools can
+//! forward them or decode the Codec2 audio.
+//! If the link setup frame is missed, link setup data
+//! is collected from the LICH of stream frames instead.
+//! Start and end of each stream are emitted as
+//! "m17_stream_start" and "m17_stream_end" events.
+
+use std::collections::VecDeque;
+use std::net::UdpSocket;
+
+use super::RxChannelProcessor;
+use super::demodulator::{ChannelFilter, Demodulator, Modulation, SAMPLE_RATE, audio_udp_socket};
+use crate::{Sample, ComplexSample};
+use crate::events;
+use crate::filter;
+
+const SYMBOL_RATE: f64 = 4800.0;
+const SAMPLES_PER_SYMBOL: usize = (SAMPLE_RATE / SYMBOL_RATE) as usize;
+/// Frequency deviation of symbols +1 and -1 in Hertz.
+const SYMBOL_DEVIATION: f64 = 800.0;
+/// Scaling from FM demodulator output to symbol values.
+const AUDIO_TO_SYMBOL: Sample = (SAMPLE_RATE / 2.0 / SYMBOL_DEVIATION) as Sample;
+/// Roll-off factor of the root raised cosine filter.
+const RRC_ROLLOFF: f64 = 0.5;
+/// Length of each half of the matched filter in symbols.
+const RRC_SPAN: usize = 4;
+/// Channel filter wide enough for the 9 kHz wide signal.
+const CHANNEL_FILTER: ChannelFilter = ChannelFilter { bandwidth: 12000.0, half_length: 32 };
+
+const SYNC_SYMBOLS: usize = 8;
+const PAYLOAD_SYMBOLS: usize = 184;
+const PAYLOAD_BITS: usize = PAYLOAD_SYMBOLS * 2;
+/// Minimum normalized correlation between received symbols
+/// and a sync word for a frame to be received.
+const SYNC_THRESHOLD: Sample = 0.9;
+
+const LSF_SYNC: u16 = 0x55F7;
+const STREAM_SYNC: u16 = 0xFF5D;
+const EOT_SYNC: u16 = 0x555D;
+
+/// Length of link setup data including the CRC.
+const LSF_BYTES: usize = 30;
+/// Number of bits at the start of a stream frame used for LICH.
+const LICH_BITS: usize = 96;
+/// Link setup data is sent in LICH in chunks of this many bytes.
+const LICH_CHUNK_BYTES: usize = 5;
+const LICH_CHUNKS: usize = LSF_BYTES / LICH_CHUNK_BYTES;
+/// Frame number and payload of a stream frame.
+const STREAM_DATA_BYTES: usize = 18;
+/// Bit in frame number marking the last frame of a stream.
+const END_OF_STREAM: u16 = 0x8000;
+/// Golay code words with more errors are rejected.
+const MAX_GOLAY_ERRORS: u32 = 3;
+/// Streams end if no stream frames are received for this many samples.
+const STREAM_TIMEOUT: usize = (SAMPLE_RATE * 0.5) as usize;
+
+/// Sequence XORed with frame payloads to avoid long runs of symbols.
+const RANDOMIZER: [u8; PAYLOAD_BITS / 8] = [
+    0xD6, 0xB5, 0xE2, 0x30, 0x82, 0xFF, 0x84, 0x62, 0xBA, 0x4E, 0x96, 0x90,
+    0xD8, 0x98, 0xDD, 0x5D, 0x0C, 0xC8, 0x52, 0x43, 0x91, 0x1D, 0xF8, 0x6E,
+    0x68, 0x2F, 0x35, 0xDA, 0x14, 0xEA, 0xCD, 0x76, 0x19, 0x8D, 0xD5, 0x80,
+    0xD1, 0x33, 0x87, 0x13, 0x57, 0x18, 0x2D, 0x29, 0x78, 0xC3,
+];
+
+/// Parity bits of the Golay (24, 12) code for each data bit.
+const GOLAY_PARITY: [u32; 12] = [
+    0x8EB, 0x93E, 0xA97, 0xDC6, 0x367, 0x6CD,
+    0xD99, 0x3DA, 0x7B4, 0xF68, 0x63B, 0xC75,
+];
+
+const CALLSIGN_CHARACTERS: &[u8; 40] = b" ABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789-/.";
+
+/// CRC used in link setup data and network packets.
+fn crc(data: &[u8]) -> u16 {
+    let mut crc: u16 = 0xFFFF;
+    for &byte in data {
+        crc ^= (byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 { (crc << 1) ^ 0x5935 } else { crc << 1 };
+        }
+    }
+    crc
+}
+
+/// Symbol value of a dibit.
+fn dibit_symbol(dibit: u16) -> Sample {
+    match dibit & 3 {
+        0b01 => 3.0,
+        0b00 => 1.0,
+        0b10 => -1.0,
+        _ => -3.0,
+    }
+}
+
+fn sync_symbols(sync: u16) -> [Sample; SYNC_SYMBOLS] {
+    core::array::from_fn(|i| dibit_symbol(sync >> (14 - 2 * i)))
+}
+
+fn randomizer_bit(index: usize) -> bool {
+    RANDOMIZER[index / 8] >> (7 - index % 8) & 1 != 0
+}
+
+/// Quadratic permutation polynomial interleaver.
+/// The permutation is its own inverse.
+fn interleave(index: usize) -> usize {
+    (45 * index + 92 * index * index) % PAYLOAD_BITS
+}
+
+/// Whether an encoded bit of a link setup frame is sent:
+/// 46 of every 61 bits are.
+fn lsf_puncture(index: usize) -> bool {
+    let index = index % 61;
+    index == 0 || index % 4 != 2
+}
+
+/// Whether an encoded bit of a stream frame is sent:
+/// 11 of every 12 bits are.
+fn stream_puncture(index: usize) -> bool {
+    index % 12 != 11
+}
+
+/// Outputs of the convolutional encoder for an input bit,
+/// when the previous 4 input bits are in state, latest one highest.
+fn encoder_output(state: usize, bit: usize) -> [bool; 2] {
+    let previous = |age: usize| state >> (4 - age) & 1;
+    [
+        bit ^ previous(3) ^ previous(4) != 0,
+        bit ^ previous(1) ^ previous(2) ^ previous(4) != 0,
+    ]
+}
+
+/// Decode the rate 1/2 convolutional code with constraint length 5
+/// from soft bits, positive meaning a one and zero meaning a
+/// punctured bit. The encoder is assumed to be flushed to zero state.
+fn viterbi(soft: &[Sample]) -> Vec<bool> {
+    let mut metrics = [Sample::INFINITY; 16];
+    metrics[0] = 0.0;
+    // Previous state on the best path to each state after each step.
+    let mut decisions: Vec<[u8; 16]> = Vec::with_capacity(soft.len() / 2);
+    for pair in soft.chunks_exact(2) {
+        let mut next = [Sample::INFINITY; 16];
+        let mut previous = [0u8; 16];
+        for (state, &metric) in metrics.iter().enumerate() {
+            for bit in 0..2 {
+                let cost = metric + encoder_output(state, bit).iter().zip(pair)
+                    .map(|(&expected, &soft)| if expected { -soft } else { soft })
+                    .sum::<Sample>();
+                let to = bit << 3 | state >> 1;
+                if cost < next[to] {
+                    next[to] = cost;
+                    previous[to] = state as u8;
+                }
+            }
+        }
+        metrics = next;
+        decisions.push(previous);
+    }
+    let mut bits = vec![false; decisions.len()];
+    let mut state = 0;
+    for (bit, previous) in bits.iter_mut().zip(decisions.iter()).rev() {
+        *bit = state >> 3 != 0;
+        state = previous[state] as usize;
+    }
+    bits
+}
+
+/// Insert zeros for punctured bits to get given number of encoded bits.
+fn depuncture(bits: &[Sample], length: usize, sent: fn(usize) -> bool) -> Vec<Sample> {
+    let mut bits = bits.iter();
+    (0..length).map(|i| if sent(i) { bits.next().copied().unwrap_or(0.0) } else { 0.0 }).collect()
+}
+
+fn to_bytes(bits: &[bool]) -> Vec<u8> {
+    bits.chunks(8).map(|byte| byte.iter().fold(0, |value, &bit| value << 1 | bit as u8)).collect()
+}
+
+/// Soft bits of frame payload symbols, derandomized and deinterleaved.
+/// Positive values mean a one.
+fn payload_bits(symbols: &[Sample]) -> Vec<Sample> {
+    let mut bits = vec![0.0; PAYLOAD_BITS];
+    for (i, &symbol) in symbols.iter().enumerate() {
+        for (j, soft) in [-symbol, symbol.abs() - 2.0].into_iter().enumerate() {
+            let index = 2 * i + j;
+            bits[interleave(index)] = if randomizer_bit(index) { -soft } else { soft };
+        }
+    }
+    bits
+}
+
+/// Link setup data, if the checksum is valid and it starts a stream.
+fn valid_lsf(lsf: [u8; LSF_BYTES]) -> Option<[u8; LSF_BYTES]> {
+    // Lowest bit of the type field is set for streams.
+    (crc(&lsf) == 0 && lsf[13] & 1 != 0).then_some(lsf)
+}
+
+fn decode_lsf(bits: &[Sample]) -> Option<[u8; LSF_BYTES]> {
+    let decoded = viterbi(&depuncture(bits, 2 * (LSF_BYTES * 8 + 4), lsf_puncture));
+    valid_lsf(to_bytes(&decoded[..LSF_BYTES * 8]).try_into().unwrap())
+}
+
+/// Decode a callsign from a 6 byte address.
+fn callsign(address: &[u8]) -> String {
+    let mut value = address.iter().fold(0u64, |value, &byte| value << 8 | byte as u64);
+    if value == 0xFFFF_FFFF_FFFF {
+        return "@ALL".to_string();
+    }
+    let mut text = String::new();
+    while value > 0 {
+        text.push(CALLSIGN_CHARACTERS[(value % 40) as usize] as char);
+        value /= 40;
+    }
+    text
+}
+
+/// M17 network packet carrying a stream frame.
+fn network_packet(stream_id: u16, lsf: &[u8; LSF_BYTES], data: &[u8]) -> Vec<u8> {
+    let mut packet = b"M17 ".to_vec();
+    packet.extend_from_slice(&stream_id.to_be_bytes());
+    packet.extend_from_slice(&lsf[..LSF_BYTES - 2]);
+    packet.extend_from_slice(data);
+    packet.extend_from_slice(&crc(&packet).to_be_bytes());
+    packet
+}
+
+/// Root raised cosine filter matched to the transmitted pulse shape,
+/// normalized to unity gain at DC.
+fn rrc_taps() -> filter::SymmetricRealTaps {
+    let mut halftaps: Vec<Sample> = (0..RRC_SPAN * SAMPLES_PER_SYMBOL).map(|i| {
+        // Time in symbols. Taps are halfway between samples,
+        // which avoids the singular points of the formula.
+        let t = (i as f64 + 0.5) / SAMPLES_PER_SYMBOL as f64;
+        let pi = std::f64::consts::PI;
+        (((pi * t * (1.0 - RRC_ROLLOFF)).sin() + 4.0 * RRC_ROLLOFF * t * (pi * t * (1.0 + RRC_ROLLOFF)).cos())
+            / (pi * t * (1.0 - (4.0 * RRC_ROLLOFF * t).powi(2)))) as Sample
+    }).collect();
+    let scaling = 0.5 / halftaps.iter().sum::<Sample>();
+    for value in halftaps.iter_mut() {
+        *value *= scaling;
+    }
+    filter::convert_symmetric_real_taps(&halftaps)
+}
+
+/// Kind of a frame, identified by its sync word.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum FrameKind {
+    LinkSetup,
+    Stream,
+    EndOfTransmission,
+}
+
+/// Frame whose payload symbols are being received.
+struct FrameInProgress {
+    kind: FrameKind,
+    /// Scaling of outermost symbols to 3, measured from the sync word.
+    scale: Sample,
+    /// Samples until the next symbol.
+    countdown: usize,
+    symbols: Vec<Sample>,
+}
+
+/// Receiver of frame symbols from FM demodulated audio.
+struct SymbolReceiver {
+    filter: filter::FirCf32Sym,
+    /// Latest filtered samples, spanning one sync word.
+    history: VecDeque<Sample>,
+    sync_words: [(FrameKind, [Sample; SYNC_SYMBOLS]); 3],
+    /// Best match to a sync word not yet accepted:
+    /// kind, correlation, scale and number of samples since it.
+    candidate: Option<(FrameKind, Sample, Sample, usize)>,
+    frame: Option<FrameInProgress>,
+}
+
+impl SymbolReceiver {
+    fn new() -> Self {
+        Self {
+            filter: filter::FirCf32Sym::new(rrc_taps()),
+            history: std::iter::repeat_n(0.0, (SYNC_SYMBOLS - 1) * SAMPLES_PER_SYMBOL + 1).collect(),
+            sync_words: [
+                (FrameKind::LinkSetup, sync_symbols(LSF_SYNC)),
+                (FrameKind::Stream, sync_symbols(STREAM_SYNC)),
+                (FrameKind::EndOfTransmission, sync_symbols(EOT_SYNC)),
+            ],
+            candidate: None,
+            frame: None,
+        }
+    }
+
+    /// Forget any partially received frame and filter state.
+    fn reset(&mut self) {
+        self.filter.reset();
+        self.history.iter_mut().for_each(|value| *value = 0.0);
+        self.candidate = None;
+        self.frame = None;
+    }
+
+    /// Process FM demodulated audio.
+    /// Payload symbols of each received frame are passed to output.
+    fn process(&mut self, audio: &[Sample], mut output: impl FnMut(FrameKind, &[Sample])) {
+        for &sample in audio {
+            let filtered = self.filter.sample(ComplexSample::new(sample * AUDIO_TO_SYMBOL, 0.0)).re;
+            self.history.pop_front();
+            self.history.push_back(filtered);
+
+            if let Some(frame) = &mut self.frame {
+                frame.countdown -= 1;
+                if frame.countdown == 0 {
+                    frame.countdown = SAMPLES_PER_SYMBOL;
+                    frame.symbols.push(filtered * frame.scale);
+                    if frame.symbols.len() == PAYLOAD_SYMBOLS {
+                        output(frame.kind, &frame.symbols);
+                        self.frame = None;
+                    }
+                }
+                continue;
+            }
+
+            let symbols: [Sample; SYNC_SYMBOLS] = core::array::from_fn(|i| self.history[i * SAMPLES_PER_SYMBOL]);
+            let energy = symbols.iter().map(|symbol| symbol * symbol).sum::<Sample>();
+            if let Some(candidate) = &mut self.candidate {
+                candidate.3 += 1;
+            }
+            if energy > 0.0 {
+                for (kind, pattern) in self.sync_words.iter() {
+                    let correlation = symbols.iter().zip(pattern).map(|(a, b)| a * b).sum::<Sample>()
+                        / (energy * pattern.iter().map(|symbol| symbol * symbol).sum::<Sample>()).sqrt();
+                    if correlation > SYNC_THRESHOLD && self.candidate.is_none_or(|candidate| correlation > candidate.1) {
+                        let scale = 3.0 * SYNC_SYMBOLS as Sample / symbols.iter().map(|symbol| symbol.abs()).sum::<Sample>();
+                        self.candidate = Some((*kind, correlation, scale, 0));
+                    }
+                }
+            }
+            // Accept the best match once no better one
+            // has been found within half a symbol.
+            if let Some((kind, _, scale, age)) = self.candidate {
+                if age >= SAMPLES_PER_SYMBOL / 2 {
+                    self.candidate = None;
+                    if kind == FrameKind::EndOfTransmission {
+                        output(kind, &[]);
+                    } else {
+                        self.frame = Some(FrameInProgress {
+                            kind,
+                            scale,
+                            countdown: SAMPLES_PER_SYMBOL - age,
+                            symbols: Vec::with_capacity(PAYLOAD_SYMBOLS),
+                        });
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Decoder of link setup data and stream frames.
+struct StreamDecoder {
+    /// Frequency of the channel, used in events.
+    frequency: f64,
+    /// All Golay code words, for decoding LICH.
+    golay_codewords: Vec<u32>,
+    /// Link setup data of the current stream.
+    lsf: Option<[u8; LSF_BYTES]>,
+    /// Chunks of link setup data received in LICH.
+    lich: [Option<[u8; LICH_CHUNK_BYTES]>; LICH_CHUNKS],
+    stream_id: u16,
+    /// Number of stream frames received in the current stream.
+    frames: usize,
+    /// Number of samples since the latest stream frame.
+    idle: usize,
+    /// Number of streams received, used to make stream IDs unique.
+    streams: u16,
+}
+
+fn golay_encode(data: u16) -> u32 {
+    let parity = (0..12).filter(|i| data >> i & 1 != 0).fold(0, |parity, i| parity ^ GOLAY_PARITY[i]);
+    (data as u32) << 12 | parity
+}
+
+impl StreamDecoder {
+    fn new(frequency: f64) -> Self {
+        Self {
+            frequency,
+            golay_codewords: (0..1 << 12).map(golay_encode).collect(),
+            lsf: None,
+            lich: [None; LICH_CHUNKS],
+            stream_id: 0,
+            frames: 0,
+            idle: 0,
+            streams: 0,
+        }
+    }
+
+    fn golay_decode(&self, word: u32) -> Option<u16> {
+        let (data, errors) = self.golay_codewords.iter().enumerate()
+            .map(|(data, codeword)| (data, (codeword ^ word).count_ones()))
+            .min_by_key(|&(_, errors)| errors)?;
+        (errors <= MAX_GOLAY_ERRORS).then_some(data as u16)
+    }
+
+    /// Decode the 6 bytes of LICH from hard decisions of soft bits.
+    fn decode_lich(&self, bits: &[Sample]) -> Option<[u8; 6]> {
+        let mut value: u64 = 0;
+        for word in bits.chunks_exact(24) {
+            let received = word.iter().fold(0, |received, &bit| received << 1 | (bit > 0.0) as u32);
+            value = value << 12 | self.golay_decode(received)? as u64;
+        }
+        Some(value.to_be_bytes()[2..].try_into().unwrap())
+    }
+
+    /// Link setup data collected from LICH, if complete and valid.
+    fn lich_lsf(&self) -> Option<[u8; LSF_BYTES]> {
+        let mut lsf = [0; LSF_BYTES];
+        for (chunk, received) in lsf.chunks_exact_mut(LICH_CHUNK_BYTES).zip(self.lich.iter()) {
+            chunk.copy_from_slice(received.as_ref()?);
+        }
+        valid_lsf(lsf)
+    }
+
+    fn start(&mut self, lsf: [u8; LSF_BYTES]) {
+        self.end();
+        self.streams = self.streams.wrapping_add(1);
+        self.stream_id = crc(&lsf[..LSF_BYTES - 2]).wrapping_add(self.streams);
+        self.lsf = Some(lsf);
+        self.idle = 0;
+        events::emit(events::Event::new("m17_stream_start")
+            .with("frequency", self.frequency)
+            .with("source", callsign(&lsf[6..12]))
+            .with("destination", callsign(&lsf[0..6])));
+    }
+
+    fn end(&mut self) {
+        if let Some(lsf) = self.lsf.take() {
+            events::emit(events::Event::new("m17_stream_end")
+                .with("frequency", self.frequency)
+                .with("source", callsign(&lsf[6..12]))
+                .with("frames", self.frames));
+        }
+        self.lich = [None; LICH_CHUNKS];
+        self.frames = 0;
+    }
+
+    /// Decode a received frame.
+    /// Network packets of stream frames are passed to output.
+    fn frame(&mut self, kind: FrameKind, symbols: &[Sample], output: impl FnMut(Vec<u8>)) {
+        match kind {
+            FrameKind::LinkSetup => {
+                if let Some(lsf) = decode_lsf(&payload_bits(symbols)) {
+                    if self.lsf != Some(lsf) {
+                        self.start(lsf);
+                    }
+                }
+            },
+            FrameKind::Stream => self.stream_frame(&payload_bits(symbols), output),
+            FrameKind::EndOfTransmission => self.end(),
+        }
+    }
+
+    fn stream_frame(&mut self, bits: &[Sample], mut output: impl FnMut(Vec<u8>)) {
+        let Some(lich) = self.decode_lich(&bits[..LICH_BITS]) else { return };
+        let counter = (lich[LICH_CHUNK_BYTES] >> 5) as usize;
+        if counter >= LICH_CHUNKS {
+            return;
+        }
+        let chunk: [u8; LICH_CHUNK_BYTES] = lich[..LICH_CHUNK_BYTES].try_into().unwrap();
+        self.lich[counter] = Some(chunk);
+        if let Some(lsf) = self.lich_lsf() {
+            if self.lsf != Some(lsf) {
+                self.start(lsf);
+                self.lich[counter] = Some(chunk);
+            }
+        }
+        // Frames not matching the current stream are
+        // probably false syncs or from another transmitter.
+        let Some(lsf) = self.lsf else { return };
+        if lsf[counter * LICH_CHUNK_BYTES..][..LICH_CHUNK_BYTES] != chunk {
+            return;
+        }
+        let decoded = viterbi(&depuncture(&bits[LICH_BITS..], 2 * (STREAM_DATA_BYTES * 8 + 4), stream_puncture));
+        let data = to_bytes(&decoded[..STREAM_DATA_BYTES * 8]);
+        self.idle = 0;
+        self.frames += 1;
+        output(network_packet(self.stream_id, &lsf, &data));
+        if u16::from_be_bytes([data[0], data[1]]) & END_OF_STREAM != 0 {
+            self.end();
+        }
+    }
+
+    /// Count time to end streams which stop without an end marker.
+    fn advance(&mut self, samples: usize) {
+        if self.lsf.is_some() {
+            self.idle += samples;
+            if self.idle > STREAM_TIMEOUT {
+                self.end();
+            }
+        }
+    }
+}
+
+/// Decode frames from FM demodulated audio.
+fn decode_audio(symbols: &mut SymbolReceiver, stream: &mut StreamDecoder, audio: &[Sample], mut output: impl FnMut(Vec<u8>)) {
+    symbols.process(audio, |kind, payload| stream.frame(kind, payload, &mut output));
+    stream.advance(audio.len());
+}
+
+pub struct DecodeM17 {
+    center_frequency: f64,
+    demodulator: Demodulator,
+    symbols: SymbolReceiver,
+    stream: StreamDecoder,
+    audio_buffer: Vec<Sample>,
+    socket: UdpSocket,
+}
+
+impl DecodeM17 {
+    /// Create a decoder sending stream frames to given UDP address.
+    pub fn new(address: &str, center_frequency: f64) -> Self {
+        Self {
+            center_frequency,
+            demodulator: Demodulator::with_filter(Modulation::FM, CHANNEL_FILTER),
+            symbols: SymbolReceiver::new(),
+            stream: StreamDecoder::new(center_frequency),
+            audio_buffer: Vec::with_capacity(48),
+            socket: audio_udp_socket(address),
+        }
+    }
+}
+
+impl RxChannelProcessor for DecodeM17 {
+    fn process(&mut self, samples: &[ComplexSample]) {
+        self.audio_buffer.clear();
+        self.demodulator.process(samples, &mut self.audio_buffer);
+        let socket = &self.socket;
+        decode_audio(&mut self.symbols, &mut self.stream, &self.audio_buffer, |packet| {
+            // TODO: print a warning or something if writing to socket fails
+            let _ = socket.send(&packet);
+        });
+    }
+
+    fn input_sample_rate(&self) -> f64 {
+        SAMPLE_RATE
+    }
+
+    fn input_center_frequency(&self) -> f64 {
+        self.center_frequency
+    }
+
+    fn discontinuity(&mut self) {
+        self.demodulator.reset();
+        self.symbols.reset();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encode_callsign(text: &str) -> [u8; 6] {
+        let value = text.bytes().rev().fold(0u64, |value, character|
+            value * 40 + CALLSIGN_CHARACTERS.iter().position(|&c| c == character).unwrap() as u64);
+        value.to_be_bytes()[2..].try_into().unwrap()
+    }
+
+    fn to_bits(bytes: &[u8]) -> Vec<bool> {
+        bytes.iter().flat_map(|byte| (0..8).rev().map(move |i| byte >> i & 1 != 0)).collect()
+    }
+
+    /// Convolutionally encode and puncture bits.
+    fn encode(bits: &[bool], sent: fn(usize) -> bool) -> Vec<bool> {
+        let mut state = 0;
+        let mut encoded = Vec::new();
+        for &bit in bits.iter().chain([false; 4].iter()) {
+            encoded.extend(encoder_output(state, bit as usize));
+            state = (bit as usize) << 3 | state >> 1;
+        }
+        encoded.into_iter().enumerate().filter(|&(i, _)| sent(i)).map(|(_, bit)| bit).collect()
+    }
+
+    /// Sync word and payload symbols of a frame.
+    fn frame_symbols(sync: u16, bits: &[bool]) -> Vec<Sample> {
+        assert_eq!(bits.len(), PAYLOAD_BITS);
+        let mut symbols = sync_symbols(sync).to_vec();
+        symbols.extend((0..PAYLOAD_SYMBOLS).map(|i| {
+            let bit = |index: usize| (bits[interleave(index)] ^ randomizer_bit(index)) as u16;
+            dibit_symbol(bit(2 * i) << 1 | bit(2 * i + 1))
+        }));
+        symbols
+    }
+
+    fn stream_frame(lsf: &[u8; LSF_BYTES], number: u16, payload: &[u8; 16]) -> Vec<Sample> {
+        let counter = number as usize & 0x7FFF;
+        let mut lich = lsf[counter % LICH_CHUNKS * LICH_CHUNK_BYTES..][..LICH_CHUNK_BYTES].to_vec();
+        lich.push(((counter % LICH_CHUNKS) << 5) as u8);
+        let lich = lich.iter().fold(0u64, |value, &byte| value << 8 | byte as u64);
+        let mut bits = Vec::new();
+        for i in (0..4).rev() {
+            let word = golay_encode((lich >> (12 * i)) as u16 & 0xFFF);
+            bits.extend((0..24).rev().map(|j| word >> j & 1 != 0));
+        }
+        let data = [&number.to_be_bytes()[..], &payload[..]].concat();
+        bits.extend(encode(&to_bits(&data), stream_puncture));
+        frame_symbols(STREAM_SYNC, &bits)
+    }
+
+    /// Pulse shape and FM modulate symbols.
+    fn modulate(symbols: &[Sample]) -> Vec<ComplexSample> {
+        let mut filter = filter::FirCf32Sym::new(rrc_taps());
+        let mut phase = 0.0f64;
+        symbols.iter().chain([0.0; 2 * RRC_SPAN].iter()).flat_map(|&symbol|
+            (0..SAMPLES_PER_SYMBOL).map(move |i| if i == 0 { symbol * SAMPLES_PER_SYMBOL as Sample } else { 0.0 })
+        ).map(|impulse| {
+            let shaped = filter.sample(ComplexSample::new(impulse, 0.0)).re as f64;
+            phase += 2.0 * std::f64::consts::PI * shaped * SYMBOL_DEVIATION / SAMPLE_RATE;
+            ComplexSample::from_polar(1.0, phase as Sample)
+        }).collect()
+    }
+
+    fn decode(signal: &[ComplexSample]) -> Vec<Vec<u8>> {
+        let mut demodulator = Demodulator::with_filter(Modulation::FM, CHANNEL_FILTER);
+        let mut symbols = SymbolReceiver::new();
+        let mut stream = StreamDecoder::new(433.475e6);
+        let mut packets = Vec::new();
+        for block in signal.chunks(48) {
+            let mut audio = Vec::new();
+            demodulator.process(block, &mut audio);
+            decode_audio(&mut symbols, &mut stream, &audio, |packet| packets.push(packet));
+        }
+        packets
+    }
+
+    #[test]
+    fn test_crc() {
+        assert_eq!(crc(b""), 0xFFFF);
+        assert_eq!(crc(b"A"), 0x206E);
+        assert_eq!(crc(b"123456789"), 0x772B);
+        assert_eq!(crc(&[&b"123456789"[..], &[0x77, 0x2B]].concat()), 0);
+    }
+
+    #[test]
+    fn test_golay() {
+        // Minimum distance of the code is 8,
+        // so up to 3 errors can be corrected.
+        assert_eq!((1..1 << 12).map(|data| golay_encode(data).count_ones()).min(), Some(8));
+        let decoder = StreamDecoder::new(0.0);
+        assert_eq!(decoder.golay_decode(golay_encode(0xABC) ^ 0x100401), Some(0xABC));
+        assert_eq!(callsign(&encode_callsign("OH2XYZ")), "OH2XYZ");
+    }
+
+    #[test]
+    fn test_decode() {
+        let mut lsf = [0u8; LSF_BYTES];
+        lsf[..6].copy_from_slice(&[0xFF; 6]);
+        lsf[6..12].copy_from_slice(&encode_callsign("OH2XYZ"));
+        // Voice stream
+        lsf[13] = 0x05;
+        let checksum = crc(&lsf[..LSF_BYTES - 2]).to_be_bytes();
+        lsf[LSF_BYTES - 2..].copy_from_slice(&checksum);
+
+        let frames = 8;
+        let payload = |number: u16| [number as u8; 16];
+        let number = |i: usize| i as u16 | if i == frames - 1 { END_OF_STREAM } else { 0 };
+        let mut symbols: Vec<Sample> = [3.0, -3.0].repeat(96);
+        let preamble_length = symbols.len();
+        symbols.extend(frame_symbols(LSF_SYNC, &encode(&to_bits(&lsf), lsf_puncture)));
+        let lsf_length = symbols.len() - preamble_length;
+        for i in 0..frames {
+            symbols.extend(stream_frame(&lsf, number(i), &payload(i as u16)));
+        }
+        // Errors corrected by the Viterbi decoder
+        for i in [300, 301, 500, 700] {
+            symbols[i] = -symbols[i];
+        }
+        symbols.extend(sync_symbols(EOT_SYNC).repeat(24));
+        symbols.extend([0.0; 100]);
+
+        let check = |packets: Vec<Vec<u8>>, first: usize| {
+            assert_eq!(packets.len(), frames - first);
+            for (i, packet) in (first..).zip(packets) {
+                assert_eq!(packet.len(), 54);
+                assert_eq!(&packet[..4], b"M17 ");
+                assert_eq!(&packet[6..34], &lsf[..LSF_BYTES - 2]);
+                assert_eq!(&packet[34..36], &number(i).to_be_bytes());
+                assert_eq!(&packet[36..52], &payload(i as u16));
+                assert_eq!(crc(&packet), 0);
+            }
+        };
+        check(decode(&modulate(&symbols)), 0);
+        // Without the link setup frame, stream starts
+        // once link setup data has been received in LICH.
+        let late_entry: Vec<Sample> = [&symbols[..preamble_length], &symbols[preamble_length + lsf_length..]].concat();
+        check(decode(&modulate(&late_entry)), LICH_CHUNKS - 1);
+    }
+}
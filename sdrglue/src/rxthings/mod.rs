@@ -2,8 +2,80 @@
 
 use crate::ComplexSample;
 
+pub mod chain;
+pub mod ctcss;
+pub mod denoise;
+pub mod vad;
+pub use vad::VadSettings;
 pub mod demodulator;
 pub use demodulator::*;
+pub mod discriminator;
+pub use discriminator::*;
+pub mod voter;
+pub use voter::*;
+pub mod stereo;
+pub use stereo::*;
+pub mod scanner;
+pub use scanner::*;
+pub mod usrp_gateway;
+pub use usrp_gateway::*;
+pub mod fsk;
+pub mod ber_test;
+pub use ber_test::*;
+pub mod response_test;
+pub use response_test::*;
+pub mod pipewire_output;
+pub use pipewire_output::*;
+pub mod remote_output;
+pub use remote_output::*;
+pub mod iq_output;
+pub use iq_output::*;
+pub mod sigmf_output;
+pub use sigmf_output::*;
+pub mod difi;
+pub use difi::*;
+pub mod ka9q;
+pub use ka9q::*;
+pub mod pipe_output;
+pub use pipe_output::*;
+pub mod decoder_process;
+pub use decoder_process::*;
+pub mod burst_capture;
+pub use burst_capture::*;
+pub mod wfm;
+pub use wfm::*;
+pub mod aprs;
+pub use aprs::*;
+pub mod rtty;
+pub use rtty::*;
+pub mod m17;
+pub use m17::*;
+pub mod dtmf;
+pub use dtmf::*;
+pub mod wav_recorder;
+pub use wav_recorder::*;
+pub mod alert_recorder;
+pub use alert_recorder::*;
+#[cfg(feature = "mumble")]
+pub mod mumble_output;
+#[cfg(feature = "mumble")]
+pub use mumble_output::*;
+#[cfg(feature = "archive")]
+pub mod archive;
+#[cfg(feature = "archive")]
+pub use archive::*;
+#[cfg(feature = "icecast")]
+pub mod icecast_output;
+#[cfg(feature = "icecast")]
+pub use icecast_output::*;
+#[cfg(feature = "jack")]
+pub mod jack_output;
+#[cfg(feature = "jack")]
+pub use jack_output::*;
+#[cfg(feature = "zmq")]
+pub mod zmq_output;
+#[cfg(feature = "zmq")]
+pub use zmq_output::*;
 
 pub trait RxChannelProcessor {
     /// Process a block of input samples.
@@ -14,4 +86,10 @@ pub trait RxChannelProcessor {
 
     /// Return required input center frequency in Hertz.
     fn input_center_frequency(&self) -> f64;
+
+    /// Called after samples have been lost before the latest block,
+    /// for example due to an SDR overflow.
+    /// State depending on continuity of the signal should be reset,
+    /// so that the gap only causes a short glitch.
+    fn discontinuity(&mut self) {}
 }
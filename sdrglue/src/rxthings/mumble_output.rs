@@ -0,0 +1,108 @@
+//! Stream demodulated audio into a Mumble channel.
+
+use super::RxChannelProcessor;
+use super::demodulator::{ChannelFilter, Demodulator, Modulation, Squelch, SAMPLE_RATE};
+use crate::{Sample, ComplexSample};
+use crate::mumble::MumbleConnection;
+
+/// Length of an Opus frame in samples (20 ms).
+const FRAME_SAMPLES: usize = 960;
+/// Maximum size of an encoded Opus frame in bytes.
+const MAX_FRAME_BYTES: usize = 1000;
+/// Opus bitrate in bits per second.
+const BITRATE: i32 = 24000;
+
+pub struct DemodulateToMumble {
+    /// Input center frequency
+    center_frequency: f64,
+    demodulator: Demodulator,
+    encoder: audiopus::coder::Encoder,
+    squelch: Squelch,
+    /// Whether audio is currently being sent.
+    talking: bool,
+    /// Demodulated audio waiting to be encoded.
+    audio_buffer: Vec<Sample>,
+    /// Buffer for encoded frame.
+    encoded_buffer: Vec<u8>,
+    connection: MumbleConnection,
+}
+
+pub struct DemodulateToMumbleParameters<'a> {
+    /// Center frequency to demodulate
+    pub center_frequency: f64,
+    /// Modulation
+    pub modulation: Modulation,
+    pub channel_filter: ChannelFilter,
+    /// Server address as host:port.
+    pub server: &'a str,
+    /// Name of the Mumble channel to join.
+    pub channel: &'a str,
+    /// Username shown in Mumble, used as a label for the channel.
+    pub label: &'a str,
+    /// Squelch opening threshold for channel power in dB.
+    pub squelch_level: Sample,
+}
+
+impl DemodulateToMumble {
+    pub fn new(parameters: &DemodulateToMumbleParameters) -> Self {
+        // TODO: handle errors more nicely
+        let mut encoder = audiopus::coder::Encoder::new(
+            audiopus::SampleRate::Hz48000,
+            audiopus::Channels::Mono,
+            audiopus::Application::Voip,
+        ).unwrap();
+        encoder.set_bitrate(audiopus::Bitrate::BitsPerSecond(BITRATE)).unwrap();
+        Self {
+            center_frequency:
+                parameters.center_frequency
+                + Demodulator::frequency_offset(parameters.modulation),
+            demodulator: Demodulator::with_filter(parameters.modulation,
+                parameters.channel_filter),
+            encoder,
+            squelch: Squelch::new(parameters.center_frequency, parameters.squelch_level),
+            talking: false,
+            audio_buffer: Vec::with_capacity(FRAME_SAMPLES * 2),
+            encoded_buffer: vec![0u8; MAX_FRAME_BYTES],
+            connection: MumbleConnection::start(parameters.server, parameters.label, parameters.channel),
+        }
+    }
+
+    fn send_frame(&mut self, end: bool) {
+        match self.encoder.encode_float(&self.audio_buffer[..FRAME_SAMPLES], &mut self.encoded_buffer) {
+            Ok(len) => self.connection.send_audio(&self.encoded_buffer[..len], end),
+            Err(err) => eprintln!("Opus encoding failed: {}", err),
+        }
+    }
+}
+
+impl RxChannelProcessor for DemodulateToMumble {
+    fn process(&mut self, samples: &[ComplexSample]) {
+        self.demodulator.process(samples, &mut self.audio_buffer);
+
+        let open = self.squelch.update(self.demodulator.power());
+
+        while self.audio_buffer.len() >= FRAME_SAMPLES {
+            if open {
+                self.send_frame(false);
+            } else if self.talking {
+                // Mark the end of transmission
+                // so Mumble clients stop showing us as talking.
+                self.send_frame(true);
+            }
+            self.talking = open;
+            self.audio_buffer.drain(..FRAME_SAMPLES);
+        }
+    }
+
+    fn input_sample_rate(&self) -> f64 {
+        SAMPLE_RATE
+    }
+
+    fn input_center_frequency(&self) -> f64 {
+        self.center_frequency
+    }
+
+    fn discontinuity(&mut self) {
+        self.demodulator.reset();
+    }
+}
@@ -0,0 +1,164 @@
+//! Channel output written to stdout or a named pipe,
+//! for use in Unix pipelines with decoders like
+//! direwolf or multimon-ng.
+//!
+//! Output is either demodulated audio as 16-bit signed
+//! little-endian samples, or channel IQ in a configurable
+//! format, by default interleaved 16-bit signed little-endian
+//! I and Q values, both at 48 kHz.
+
+use std::io::Write;
+use std::sync::mpsc;
+
+use super::RxChannelProcessor;
+use super::chain::{AudioChain, BlockInfo};
+use super::demodulator::{ChannelFilter, Demodulator, Modulation, SAMPLE_RATE, audio_to_s16le};
+use super::iq_output::{IqEncoder, IqFormat};
+use crate::{Sample, ComplexSample};
+
+/// Number of blocks that can wait to be written to the pipe.
+/// If the pipe is not read, further output is dropped.
+const MAX_QUEUED: usize = 100;
+
+/// Demodulated audio or IQ samples of a channel,
+/// encoded in the format written to pipes.
+pub enum PipeContent {
+    Audio {
+        /// Boxed since a demodulator is much larger than an IQ encoder.
+        demodulator: Box<Demodulator>,
+        /// Optional audio processing chain after demodulator.
+        chain: Option<AudioChain>,
+        audio_buffer: Vec<Sample>,
+    },
+    Iq(IqEncoder),
+}
+
+impl PipeContent {
+    /// Create for given modulation and channel filter,
+    /// or IQ output in given format if None.
+    /// Returns also the center frequency of channel input.
+    pub fn new(
+        center_frequency: f64,
+        demodulation: Option<(Modulation, ChannelFilter)>,
+        chain: Option<&str>,
+        iq_format: IqFormat,
+    ) -> (f64, Self) {
+        match demodulation {
+            Some((modulation, channel_filter)) => (
+                center_frequency + Demodulator::frequency_offset(modulation),
+                Self::Audio {
+                    demodulator: Box::new(Demodulator::with_filter(modulation,
+                        channel_filter)),
+                    // TODO: handle errors more nicely
+                    chain: chain.map(|description|
+                        AudioChain::parse_mono(description, center_frequency)
+                        .unwrap_or_else(|err| panic!("Invalid audio chain: {}", err))),
+                    audio_buffer: Vec::new(),
+                },
+            ),
+            None => (center_frequency, Self::Iq(IqEncoder::new(iq_format))),
+        }
+    }
+
+    /// Process channel samples and append encoded output.
+    pub fn process(&mut self, samples: &[ComplexSample], output: &mut Vec<u8>) {
+        match self {
+            Self::Audio { demodulator, chain, audio_buffer } => {
+                audio_buffer.clear();
+                demodulator.process(samples, audio_buffer);
+                if let Some(chain) = chain {
+                    chain.process(audio_buffer, &BlockInfo { power: demodulator.power() });
+                }
+                audio_to_s16le(audio_buffer, output);
+            },
+            Self::Iq(encoder) => encoder.encode(samples, output),
+        }
+    }
+}
+
+pub struct WriteToPipe {
+    center_frequency: f64,
+    content: PipeContent,
+    /// Blocks to be written to the pipe.
+    sender: mpsc::SyncSender<Vec<u8>>,
+}
+
+pub struct WriteToPipeParameters<'a> {
+    /// Path of the named pipe, or "-" for stdout.
+    /// The pipe is created if it does not exist.
+    pub path: &'a str,
+    pub center_frequency: f64,
+    /// Modulation to demodulate and its channel filter, or None for IQ output.
+    pub demodulation: Option<(Modulation, ChannelFilter)>,
+    /// Description of audio processing chain, if any.
+    pub chain: Option<&'a str>,
+    /// Format of IQ output.
+    pub iq_format: IqFormat,
+}
+
+/// Open the output for writing, creating a named pipe if needed.
+fn open_pipe(path: &str) -> std::io::Result<Box<dyn Write>> {
+    if path == "-" {
+        return Ok(Box::new(std::io::stdout()));
+    }
+    if !std::path::Path::new(path).exists() {
+        let status = std::process::Command::new("mkfifo").arg(path).status()?;
+        if !status.success() {
+            return Err(std::io::Error::other("mkfifo failed"));
+        }
+    }
+    // Opening a named pipe blocks until it is opened for reading.
+    Ok(Box::new(std::fs::OpenOptions::new().write(true).open(path)?))
+}
+
+impl WriteToPipe {
+    pub fn new(parameters: &WriteToPipeParameters) -> Self {
+        // Opening and writing may block, so do it in another thread.
+        let (sender, receiver) = mpsc::sync_channel::<Vec<u8>>(MAX_QUEUED);
+        let path = parameters.path.to_string();
+        std::thread::spawn(move || {
+            let mut pipe = match open_pipe(&path) {
+                Ok(pipe) => pipe,
+                Err(err) => {
+                    eprintln!("Could not open {}: {}", path, err);
+                    return;
+                },
+            };
+            for block in receiver.iter() {
+                if let Err(err) = pipe.write_all(&block).and_then(|_| pipe.flush()) {
+                    eprintln!("Error writing to {}: {}", path, err);
+                    break;
+                }
+            }
+        });
+        let (center_frequency, content) = PipeContent::new(
+            parameters.center_frequency, parameters.demodulation, parameters.chain, parameters.iq_format);
+        Self {
+            center_frequency,
+            content,
+            sender,
+        }
+    }
+}
+
+impl RxChannelProcessor for WriteToPipe {
+    fn process(&mut self, samples: &[ComplexSample]) {
+        let mut output = Vec::with_capacity(samples.len() * 4);
+        self.content.process(samples, &mut output);
+        let _ = self.sender.try_send(output);
+    }
+
+    fn input_sample_rate(&self) -> f64 {
+        SAMPLE_RATE
+    }
+
+    fn input_center_frequency(&self) -> f64 {
+        self.center_frequency
+    }
+
+    fn discontinuity(&mut self) {
+        if let PipeContent::Audio { demodulator, .. } = &mut self.content {
+            demodulator.reset();
+        }
+    }
+}
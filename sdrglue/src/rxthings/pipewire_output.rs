@@ -0,0 +1,102 @@
+//! Demodulator with a PipeWire source as output,
+//! so received audio can be recorded by applications
+//! like from any sound card.
+
+use std::io::Write;
+use std::sync::mpsc;
+
+use super::RxChannelProcessor;
+use super::chain::{AudioChain, BlockInfo};
+use super::demodulator::{ChannelFilter, Demodulator, Modulation, SAMPLE_RATE, audio_to_s16le};
+use crate::{Sample, ComplexSample};
+use crate::pipewire::PipeDevice;
+
+/// Number of blocks that can wait to be written to the pipe.
+/// If the device is not read, further audio is dropped.
+const MAX_QUEUED: usize = 100;
+
+pub struct DemodulateToPipewire {
+    center_frequency: f64,
+    demodulator: Demodulator,
+    /// Optional audio processing chain after demodulator.
+    chain: Option<AudioChain>,
+    audio_buffer: Vec<Sample>,
+    /// Audio blocks to be written to the pipe.
+    sender: mpsc::SyncSender<Vec<u8>>,
+    /// Keeps the device alive as long as the demodulator.
+    _device: PipeDevice,
+}
+
+pub struct DemodulateToPipewireParameters<'a> {
+    /// Name of the device, used in its node name.
+    pub name: &'a str,
+    pub center_frequency: f64,
+    pub modulation: Modulation,
+    pub channel_filter: ChannelFilter,
+    /// Description of audio processing chain, if any.
+    pub chain: Option<&'a str>,
+}
+
+impl DemodulateToPipewire {
+    pub fn new(parameters: &DemodulateToPipewireParameters) -> Self {
+        let device = PipeDevice::create_source(parameters.name,
+            &format!("sdrglue {} {} MHz", parameters.name, parameters.center_frequency / 1e6));
+        // Writing to a pipe may block, so do it in another thread.
+        let (sender, receiver) = mpsc::sync_channel::<Vec<u8>>(MAX_QUEUED);
+        let path = device.path.clone();
+        std::thread::spawn(move || {
+            let mut pipe = match std::fs::OpenOptions::new().write(true).open(&path) {
+                Ok(pipe) => pipe,
+                Err(err) => {
+                    eprintln!("Could not open {}: {}", path, err);
+                    return;
+                },
+            };
+            for block in receiver.iter() {
+                if let Err(err) = pipe.write_all(&block) {
+                    eprintln!("Error writing to {}: {}", path, err);
+                    break;
+                }
+            }
+        });
+        Self {
+            center_frequency:
+                parameters.center_frequency
+                + Demodulator::frequency_offset(parameters.modulation),
+            demodulator: Demodulator::with_filter(parameters.modulation,
+                parameters.channel_filter),
+            // TODO: handle errors more nicely
+            chain: parameters.chain.map(|description|
+                AudioChain::parse_mono(description, parameters.center_frequency)
+                .unwrap_or_else(|err| panic!("Invalid audio chain: {}", err))),
+            audio_buffer: Vec::new(),
+            sender,
+            _device: device,
+        }
+    }
+}
+
+impl RxChannelProcessor for DemodulateToPipewire {
+    fn process(&mut self, samples: &[ComplexSample]) {
+        self.audio_buffer.clear();
+        self.demodulator.process(samples, &mut self.audio_buffer);
+        if let Some(chain) = &mut self.chain {
+            chain.process(&mut self.audio_buffer, &BlockInfo { power: self.demodulator.power() });
+        }
+        let mut output = Vec::with_capacity(self.audio_buffer.len() * 2);
+        audio_to_s16le(&self.audio_buffer, &mut output);
+        let _ = self.sender.try_send(output);
+    }
+
+    fn input_sample_rate(&self) -> f64 {
+        SAMPLE_RATE
+    }
+
+    fn input_center_frequency(&self) -> f64 {
+        self.center_frequency
+    }
+
+    fn discontinuity(&mut self) {
+        self.demodulator.reset();
+    }
+}
@@ -0,0 +1,68 @@
+//! Demodulator sending audio to remote heads.
+
+use super::RxChannelProcessor;
+use super::chain::{AudioChain, BlockInfo};
+use super::demodulator::{ChannelFilter, Demodulator, Modulation, SAMPLE_RATE};
+use crate::{Sample, ComplexSample};
+use crate::remote;
+
+pub struct DemodulateToRemote {
+    center_frequency: f64,
+    demodulator: Demodulator,
+    /// Optional audio processing chain after demodulator.
+    chain: Option<AudioChain>,
+    audio_buffer: Vec<Sample>,
+    /// Index of the audio channel in remote head protocol.
+    channel: u8,
+}
+
+pub struct DemodulateToRemoteParameters<'a> {
+    pub center_frequency: f64,
+    pub modulation: Modulation,
+    pub channel_filter: ChannelFilter,
+    /// Name of modulation reported to heads.
+    pub modulation_name: &'a str,
+    /// Description of audio processing chain, if any.
+    pub chain: Option<&'a str>,
+}
+
+impl DemodulateToRemote {
+    pub fn new(parameters: &DemodulateToRemoteParameters) -> Self {
+        Self {
+            center_frequency:
+                parameters.center_frequency
+                + Demodulator::frequency_offset(parameters.modulation),
+            demodulator: Demodulator::with_filter(parameters.modulation,
+                parameters.channel_filter),
+            // TODO: handle errors more nicely
+            chain: parameters.chain.map(|description|
+                AudioChain::parse_mono(description, parameters.center_frequency)
+                .unwrap_or_else(|err| panic!("Invalid audio chain: {}", err))),
+            audio_buffer: Vec::new(),
+            channel: remote::register_audio_channel(parameters.center_frequency, parameters.modulation_name),
+        }
+    }
+}
+
+impl RxChannelProcessor for DemodulateToRemote {
+    fn process(&mut self, samples: &[ComplexSample]) {
+        self.audio_buffer.clear();
+        self.demodulator.process(samples, &mut self.audio_buffer);
+        if let Some(chain) = &mut self.chain {
+            chain.process(&mut self.audio_buffer, &BlockInfo { power: self.demodulator.power() });
+        }
+        remote::send_audio(self.channel, &self.audio_buffer);
+    }
+
+    fn input_sample_rate(&self) -> f64 {
+        SAMPLE_RATE
+    }
+
+    fn input_center_frequency(&self) -> f64 {
+        self.center_frequency
+    }
+
+    fn discontinuity(&mut self) {
+        self.demodulator.reset();
+    }
+}
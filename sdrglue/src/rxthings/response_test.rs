@@ -0,0 +1,152 @@
+//! Receiver for audio frequency response measurement.
+//!
+//! Demodulates tones transmitted by txthings::ResponseTestTx
+//! and measures the level of each tone in short blocks.
+//! The highest level seen for each tone is its response,
+//! so the delay between transmitter and receiver does not matter.
+//! When the whole sequence has been received, the response
+//! is written to a CSV file and a "response_measured" event is emitted.
+
+use std::io::Write;
+
+use super::RxChannelProcessor;
+use super::demodulator::{Demodulator, Modulation};
+use crate::{Sample, ComplexSample};
+use crate::events;
+use crate::txthings::response_test::{TONES, STEP_DURATION, TONE_AMPLITUDE, FM_DEVIATION};
+
+const SAMPLE_RATE: f64 = 48000.0;
+/// Length of measurement blocks in seconds.
+/// Several blocks fit within each tone so that at least one
+/// of them contains only that tone.
+const BLOCK_DURATION: f64 = 0.1;
+/// Extra time to wait after the sequence for delays, in seconds.
+const MARGIN: f64 = 1.0;
+
+pub struct ResponseTestRx {
+    center_frequency: f64,
+    demodulator: Demodulator,
+    /// Path of the CSV file to write.
+    output: String,
+    audio: Vec<Sample>,
+    /// Audio of the current block.
+    block: Vec<Sample>,
+    /// Highest measured gain for each tone.
+    gains: [Sample; TONES.len()],
+    /// Number of samples received.
+    sample_count: usize,
+    /// Whether the result has been written.
+    done: bool,
+}
+
+impl ResponseTestRx {
+    pub fn new(center_frequency: f64, output: &str) -> Self {
+        Self {
+            center_frequency,
+            demodulator: Demodulator::new(Modulation::FM),
+            output: output.to_string(),
+            audio: Vec::new(),
+            block: Vec::new(),
+            gains: [0.0; TONES.len()],
+            sample_count: 0,
+            done: false,
+        }
+    }
+
+    /// Measure amplitude of each tone in the current block
+    /// relative to the transmitted amplitude.
+    fn measure_block(&mut self) {
+        let n = self.block.len();
+        let window: Vec<Sample> = (0..n)
+            .map(|i| (0.5 - 0.5 * (std::f64::consts::TAU * i as f64 / n as f64).cos()) as Sample)
+            .collect();
+        let window_sum: Sample = window.iter().sum();
+        // Demodulator output is full scale at half the sample rate.
+        let scaling = (SAMPLE_RATE / 2.0 / FM_DEVIATION) as Sample / TONE_AMPLITUDE;
+        for (gain, &frequency) in self.gains.iter_mut().zip(TONES.iter()) {
+            let w = -std::f64::consts::TAU * frequency / SAMPLE_RATE;
+            let sum: ComplexSample = self.block.iter().zip(window.iter()).enumerate()
+                .map(|(i, (&sample, &weight))| ComplexSample::from_polar(sample * weight, (w * i as f64) as Sample))
+                .sum();
+            let amplitude = 2.0 * sum.norm() / window_sum;
+            *gain = gain.max(amplitude * scaling);
+        }
+    }
+
+    /// Demodulate samples and measure tones in complete blocks.
+    fn measure(&mut self, samples: &[ComplexSample]) {
+        let mut audio = std::mem::take(&mut self.audio);
+        audio.clear();
+        self.demodulator.process(samples, &mut audio);
+        let block_length = (BLOCK_DURATION * SAMPLE_RATE) as usize;
+        for &sample in audio.iter() {
+            self.block.push(sample);
+            if self.block.len() >= block_length {
+                self.measure_block();
+                self.block.clear();
+            }
+        }
+        self.audio = audio;
+    }
+
+    fn write_result(&self) -> std::io::Result<()> {
+        let mut file = std::fs::File::create(&self.output)?;
+        writeln!(file, "frequency,gain_db")?;
+        for (&frequency, &gain) in TONES.iter().zip(self.gains.iter()) {
+            writeln!(file, "{},{:.2}", frequency, 20.0 * gain.log10())?;
+        }
+        Ok(())
+    }
+}
+
+impl RxChannelProcessor for ResponseTestRx {
+    fn process(&mut self, samples: &[ComplexSample]) {
+        if self.done {
+            return;
+        }
+        self.measure(samples);
+        self.sample_count += samples.len();
+        let duration = STEP_DURATION * (TONES.len() + 1) as f64 + MARGIN;
+        if self.sample_count as f64 >= duration * SAMPLE_RATE {
+            self.done = true;
+            match self.write_result() {
+                Ok(_) => events::emit(events::Event::new("response_measured")
+                    .with("frequency", self.center_frequency)
+                    .with("path", self.output.as_str())),
+                Err(err) => eprintln!("Could not write response to {}: {}", self.output, err),
+            }
+        }
+    }
+
+    fn input_sample_rate(&self) -> f64 {
+        SAMPLE_RATE
+    }
+
+    fn input_center_frequency(&self) -> f64 {
+        self.center_frequency
+    }
+
+    fn discontinuity(&mut self) {
+        self.demodulator.reset();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::txthings::{ResponseTestTx, TxChannelProcessor};
+
+    #[test]
+    fn test_loopback() {
+        let mut tx = ResponseTestTx::new(0.0);
+        let mut rx = ResponseTestRx::new(0.0, "");
+        let mut samples = vec![ComplexSample::ZERO; 1000];
+        for _ in 0..((TONES.len() + 1) as f64 * STEP_DURATION * SAMPLE_RATE / 1000.0) as usize {
+            tx.process(&mut samples);
+            rx.measure(&samples);
+        }
+        for &gain in rx.gains.iter() {
+            assert!((20.0 * gain.log10()).abs() < 1.0, "gain {}", gain);
+        }
+    }
+}
@@ -0,0 +1,389 @@
+//! RTTY decoder for Baudot coded FSK teleprinter signals.
+//!
+//! The signal is demodulated as USB, so mark and space are audio
+//! tones, and the tone with more power over one bit is taken as
+//! the received bit. Characters are received asynchronously, each
+//! starting with a space bit and ending with a mark stop bit.
+//! Decoded text is sent line by line to a UDP address or to every
+//! client connected to a TCP server, and each line is also emitted
+//! as an "rtty_line" event.
+
+use std::net::UdpSocket;
+use std::sync::mpsc;
+
+use super::RxChannelProcessor;
+use super::aprs::start_tcp_server;
+use super::demodulator::{Demodulator, Modulation, SAMPLE_RATE, audio_udp_socket};
+use crate::{Sample, ComplexSample};
+use crate::events;
+
+/// Audio frequency of the center between mark and space tones,
+/// which is the center of the SSB passband.
+const CENTER_FREQUENCY: f64 = 1500.0;
+/// Largest supported shift, which keeps both tones
+/// within the SSB passband.
+const MAX_SHIFT: f64 = 1000.0;
+/// Largest supported baud rate.
+const MAX_BAUD_RATE: f64 = 300.0;
+/// Number of data bits in a Baudot character.
+const DATA_BITS: usize = 5;
+/// Lines are sent when they get this long, even without a line feed.
+const MAX_LINE_LENGTH: usize = 200;
+
+const LTRS: u8 = 0x1F;
+const FIGS: u8 = 0x1B;
+const SPACE: u8 = 0x04;
+
+/// ITA2 characters in letters and figures shift.
+/// Zero is used for codes not producing text,
+/// written as \x00 among figures so it is not mistaken for an octal escape.
+const LETTERS: [u8; 32] = *b"\0E\nA SIU\rDRJNFCKTZLWHYPQOBG\0MXV\0";
+const FIGURES: [u8; 32] = *b"\x003\n- '87\r\x004\x00,\x00:(5+)2\x006019?\x00\x00./=\x00";
+
+/// Demodulator of mark and space tones to bits.
+struct ToneDemodulator {
+    /// Frequencies of the tones in cycles per sample.
+    mark_frequency: f64,
+    space_frequency: f64,
+    /// Phase of the mark and space oscillators in cycles.
+    mark_phase: f64,
+    space_phase: f64,
+    /// Mixer outputs over the latest bit.
+    mark_history: Vec<ComplexSample>,
+    space_history: Vec<ComplexSample>,
+    position: usize,
+    /// Sums of mixer outputs over the latest bit.
+    mark_sum: ComplexSample,
+    space_sum: ComplexSample,
+}
+
+impl ToneDemodulator {
+    /// Create a demodulator for given shift and baud rate.
+    /// With a positive shift, mark is the higher tone.
+    fn new(shift: f64, baud_rate: f64) -> Self {
+        let samples_per_bit = (SAMPLE_RATE / baud_rate).round() as usize;
+        Self {
+            mark_frequency: (CENTER_FREQUENCY + shift / 2.0) / SAMPLE_RATE,
+            space_frequency: (CENTER_FREQUENCY - shift / 2.0) / SAMPLE_RATE,
+            mark_phase: 0.0,
+            space_phase: 0.0,
+            mark_history: vec![ComplexSample::ZERO; samples_per_bit],
+            space_history: vec![ComplexSample::ZERO; samples_per_bit],
+            position: 0,
+            mark_sum: ComplexSample::ZERO,
+            space_sum: ComplexSample::ZERO,
+        }
+    }
+
+    /// Demodulate audio, calling output for each sample
+    /// with the tone received over the latest bit,
+    /// true for mark and false for space.
+    fn process(&mut self, audio: &[Sample], mut output: impl FnMut(bool)) {
+        for &sample in audio {
+            let oscillator = |phase: f64| ComplexSample::from_polar(sample, (-2.0 * std::f64::consts::PI * phase) as Sample);
+            let mark = oscillator(self.mark_phase);
+            let space = oscillator(self.space_phase);
+            self.mark_phase = (self.mark_phase + self.mark_frequency).fract();
+            self.space_phase = (self.space_phase + self.space_frequency).fract();
+
+            // Sums are updated in place, so they slowly accumulate
+            // rounding errors. Recompute them once per bit.
+            self.mark_sum += mark - self.mark_history[self.position];
+            self.space_sum += space - self.space_history[self.position];
+            self.mark_history[self.position] = mark;
+            self.space_history[self.position] = space;
+            self.position += 1;
+            if self.position >= self.mark_history.len() {
+                self.position = 0;
+                self.mark_sum = self.mark_history.iter().sum();
+                self.space_sum = self.space_history.iter().sum();
+            }
+
+            output(self.mark_sum.norm_sqr() > self.space_sum.norm_sqr());
+        }
+    }
+}
+
+/// Receiver of asynchronous characters from received tones.
+struct CharacterReceiver {
+    samples_per_bit: f64,
+    previous_mark: bool,
+    /// Samples until the next bit is sampled,
+    /// or None while waiting for a start bit.
+    countdown: Option<f64>,
+    /// Bits of the current character received so far,
+    /// including the start bit.
+    bits: usize,
+    code: u8,
+}
+
+impl CharacterReceiver {
+    fn new(baud_rate: f64) -> Self {
+        Self {
+            samples_per_bit: SAMPLE_RATE / baud_rate,
+            previous_mark: false,
+            countdown: None,
+            bits: 0,
+            code: 0,
+        }
+    }
+
+    /// Process the tone received over the latest bit,
+    /// returning a character code when one has been received.
+    fn tone(&mut self, mark: bool) -> Option<u8> {
+        let previous_mark = std::mem::replace(&mut self.previous_mark, mark);
+        let Some(countdown) = &mut self.countdown else {
+            // Tone decision changes half a bit after the start
+            // of the start bit, and the tone over a bit is known
+            // at the end of it, half a bit later.
+            if previous_mark && !mark {
+                self.countdown = Some(self.samples_per_bit / 2.0);
+                self.bits = 0;
+                self.code = 0;
+            }
+            return None;
+        };
+        *countdown -= 1.0;
+        if *countdown > 0.0 {
+            return None;
+        }
+        *countdown += self.samples_per_bit;
+        self.bits += 1;
+        match self.bits {
+            // False start if the start bit is not space.
+            1 => if mark { self.countdown = None; },
+            // Data bits are sent least significant bit first.
+            bits if bits <= DATA_BITS + 1 => self.code |= (mark as u8) << (bits - 2),
+            _ => {
+                self.countdown = None;
+                // Framing error if the stop bit is not mark.
+                return mark.then_some(self.code);
+            },
+        }
+        None
+    }
+}
+
+/// Decoder of Baudot character codes to lines of text.
+struct BaudotDecoder {
+    figures: bool,
+    line: String,
+}
+
+impl BaudotDecoder {
+    fn new() -> Self {
+        Self {
+            figures: false,
+            line: String::new(),
+        }
+    }
+
+    /// Decode a character code, returning a line
+    /// when it ends in a line feed or gets too long.
+    fn code(&mut self, code: u8) -> Option<String> {
+        match code {
+            LTRS => self.figures = false,
+            FIGS => self.figures = true,
+            _ => {
+                // Unshift on space, as most transmitting software does.
+                if code == SPACE {
+                    self.figures = false;
+                }
+                let table = if self.figures { &FIGURES } else { &LETTERS };
+                match table[code as usize] {
+                    b'\n' => return Some(std::mem::take(&mut self.line)),
+                    b'\r' | 0 => {},
+                    character => self.line.push(character as char),
+                }
+                if self.line.len() >= MAX_LINE_LENGTH {
+                    return Some(std::mem::take(&mut self.line));
+                }
+            },
+        }
+        None
+    }
+}
+
+/// Destination of decoded lines.
+pub(super) enum TextOutput {
+    Udp(UdpSocket),
+    /// Lines to send to TCP clients.
+    Tcp(mpsc::SyncSender<Vec<u8>>),
+}
+
+impl TextOutput {
+    /// Create an output for a udp:// or tcp:// address.
+    /// For TCP, a server is started at the address.
+    pub(super) fn new(destination: &str) -> Self {
+        // TODO: handle errors more nicely
+        if let Some(address) = destination.strip_prefix("udp://") {
+            Self::Udp(audio_udp_socket(address))
+        } else if let Some(address) = destination.strip_prefix("tcp://") {
+            Self::Tcp(start_tcp_server(address))
+        } else {
+            panic!("Text output {} should start with udp:// or tcp://", destination)
+        }
+    }
+
+    pub(super) fn send(&self, line: &str) {
+        let data = format!("{}\n", line).into_bytes();
+        match self {
+            // TODO: print a warning or something if writing to socket fails
+            Self::Udp(socket) => { let _ = socket.send(&data); },
+            // If clients are too slow, lines are dropped.
+            Self::Tcp(sender) => { let _ = sender.try_send(data); },
+        }
+    }
+}
+
+pub struct DecodeRtty {
+    /// Frequency of the signal, used in events.
+    frequency: f64,
+    /// Center frequency of the SSB channel.
+    center_frequency: f64,
+    demodulator: Demodulator,
+    tones: ToneDemodulator,
+    receiver: CharacterReceiver,
+    baudot: BaudotDecoder,
+    audio_buffer: Vec<Sample>,
+    output: TextOutput,
+}
+
+impl DecodeRtty {
+    /// Create a decoder for a signal centered between mark and space
+    /// at given frequency, with given shift and baud rate.
+    /// With a positive shift, mark is at the higher frequency.
+    /// Decoded lines are sent to a udp:// or tcp:// address.
+    pub fn new(destination: &str, frequency: f64, shift: f64, baud_rate: f64) -> Self {
+        // TODO: handle errors more nicely
+        if !(shift != 0.0 && shift.abs() <= MAX_SHIFT) {
+            panic!("Invalid RTTY shift {}", shift);
+        }
+        if !(baud_rate > 0.0 && baud_rate <= MAX_BAUD_RATE) {
+            panic!("Invalid RTTY baud rate {}", baud_rate);
+        }
+        Self {
+            frequency,
+            // Suppressed carrier is below the signal so that
+            // the signal is at CENTER_FREQUENCY in audio.
+            center_frequency:
+                frequency
+                + Demodulator::frequency_offset(Modulation::USB)
+                - CENTER_FREQUENCY,
+            demodulator: Demodulator::new(Modulation::USB),
+            tones: ToneDemodulator::new(shift, baud_rate),
+            receiver: CharacterReceiver::new(baud_rate),
+            baudot: BaudotDecoder::new(),
+            audio_buffer: Vec::with_capacity(48),
+            output: TextOutput::new(destination),
+        }
+    }
+}
+
+/// Decode lines of text from demodulated audio.
+fn decode_audio(
+    tones: &mut ToneDemodulator,
+    receiver: &mut CharacterReceiver,
+    baudot: &mut BaudotDecoder,
+    audio: &[Sample],
+    mut output: impl FnMut(String),
+) {
+    tones.process(audio, |mark| {
+        if let Some(line) = receiver.tone(mark).and_then(|code| baudot.code(code)) {
+            output(line);
+        }
+    });
+}
+
+impl RxChannelProcessor for DecodeRtty {
+    fn process(&mut self, samples: &[ComplexSample]) {
+        self.audio_buffer.clear();
+        self.demodulator.process(samples, &mut self.audio_buffer);
+        let mut lines = Vec::new();
+        decode_audio(&mut self.tones, &mut self.receiver, &mut self.baudot, &self.audio_buffer, |line| lines.push(line));
+        for line in lines {
+            self.output.send(&line);
+            events::emit(events::Event::new("rtty_line")
+                .with("frequency", self.frequency)
+                .with("text", line));
+        }
+    }
+
+    fn input_sample_rate(&self) -> f64 {
+        SAMPLE_RATE
+    }
+
+    fn input_center_frequency(&self) -> f64 {
+        self.center_frequency
+    }
+
+    fn discontinuity(&mut self) {
+        self.demodulator.reset();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Encode text in Baudot, starting in letters shift.
+    fn encode(text: &str) -> Vec<u8> {
+        let mut codes = vec![LTRS];
+        let mut figures = false;
+        for character in text.bytes() {
+            if let Some(code) = LETTERS.iter().position(|&c| c == character) {
+                if figures && code as u8 != SPACE {
+                    codes.push(LTRS);
+                }
+                figures = false;
+                codes.push(code as u8);
+            } else if let Some(code) = FIGURES.iter().position(|&c| c == character) {
+                if !figures {
+                    codes.push(FIGS);
+                }
+                figures = true;
+                codes.push(code as u8);
+            }
+        }
+        codes
+    }
+
+    /// Modulate codes as audio tones with 1.5 stop bits,
+    /// with idle mark tone around them.
+    fn modulate(codes: &[u8], shift: f64, baud_rate: f64) -> Vec<Sample> {
+        let mut tones: Vec<(bool, f64)> = vec![(true, 5.0)];
+        for &code in codes {
+            tones.push((false, 1.0));
+            tones.extend((0..DATA_BITS).map(|bit| (code >> bit & 1 != 0, 1.0)));
+            tones.push((true, 1.5));
+        }
+        tones.push((true, 5.0));
+
+        let mut audio = Vec::new();
+        let (mut phase, mut time) = (0.0f64, 0.0f64);
+        for (mark, bits) in tones {
+            let frequency = CENTER_FREQUENCY + if mark { shift } else { -shift } / 2.0;
+            time += bits * SAMPLE_RATE / baud_rate;
+            while (audio.len() as f64) < time {
+                audio.push((0.1 * (2.0 * std::f64::consts::PI * phase).sin()) as Sample);
+                phase = (phase + frequency / SAMPLE_RATE).fract();
+            }
+        }
+        audio
+    }
+
+    #[test]
+    fn test_decode() {
+        let text = "RYRYRY CQ DE OH2XYZ 73 (+=/?)\r\n";
+        for (shift, baud_rate) in [(170.0, 45.45), (-850.0, 50.0), (450.0, 75.0)] {
+            let mut tones = ToneDemodulator::new(shift, baud_rate);
+            let mut receiver = CharacterReceiver::new(baud_rate);
+            let mut baudot = BaudotDecoder::new();
+            let mut lines = Vec::new();
+            for block in modulate(&encode(text), shift, baud_rate).chunks(48) {
+                decode_audio(&mut tones, &mut receiver, &mut baudot, block, |line| lines.push(line));
+            }
+            assert_eq!(lines, vec!["RYRYRY CQ DE OH2XYZ 73 (+=/?)".to_string()], "shift {}", shift);
+        }
+    }
+}
@@ -0,0 +1,160 @@
+//! Scanner hopping across a list of frequencies.
+//!
+//! The scanner listens to each frequency for DWELL_TIME and
+//! stops on a frequency where squelch opens. Audio is sent
+//! while stopped, and scanning continues when squelch closes
+//! again after its hang time. Each frequency has its own
+//! squelch, so squelch events report the right frequency.
+//!
+//! The scanner changes its input center frequency and the
+//! receive channel follows it, so it only needs one channel.
+
+use super::RxChannelProcessor;
+use super::demodulator::{AudioUdpOutput, ChannelFilter, Demodulator, Modulation, Squelch, SquelchSettings, SAMPLE_RATE};
+use super::rtty::TextOutput;
+use crate::{Sample, ComplexSample};
+
+/// Time in seconds to listen to each frequency
+/// while squelch is closed.
+const DWELL_TIME: f64 = 0.05;
+
+pub struct ScanToUdpParameters<'a> {
+    /// UDP destination address for audio.
+    pub address: &'a str,
+    /// udp:// or tcp:// address for current frequency.
+    pub frequency_destination: &'a str,
+    pub frequencies: Vec<f64>,
+    pub modulation: Modulation,
+    pub channel_filter: ChannelFilter,
+    pub squelch: SquelchSettings,
+    /// Duration of audio in each UDP packet in seconds.
+    pub frame_duration: f64,
+}
+
+pub struct ScanToUdp {
+    frequencies: Vec<f64>,
+    /// Index of the current frequency.
+    current: usize,
+    /// Offset of input center frequency from signal frequency.
+    frequency_offset: f64,
+    demodulator: Demodulator,
+    /// Squelch for each frequency.
+    squelches: Vec<Squelch>,
+    /// Time in seconds spent on the current frequency.
+    dwell: f64,
+    /// Whether stopped on the current frequency.
+    stopped: bool,
+    /// Demodulated audio of the latest block.
+    audio_buffer: Vec<Sample>,
+    output: AudioUdpOutput,
+    frequency_output: TextOutput,
+}
+
+impl ScanToUdp {
+    pub fn new(parameters: ScanToUdpParameters) -> Self {
+        // TODO: handle errors more nicely
+        if parameters.frequencies.is_empty() {
+            panic!("Scanner needs at least one frequency");
+        }
+        Self {
+            squelches: parameters.frequencies.iter().map(|&frequency| Squelch::with_settings(frequency, parameters.squelch)).collect(),
+            frequencies: parameters.frequencies,
+            current: 0,
+            frequency_offset: Demodulator::frequency_offset(parameters.modulation),
+            demodulator: Demodulator::with_filter(parameters.modulation,
+                parameters.channel_filter),
+            dwell: 0.0,
+            stopped: false,
+            audio_buffer: Vec::with_capacity(48),
+            output: AudioUdpOutput::new(parameters.address, parameters.frame_duration, 1),
+            frequency_output: TextOutput::new(parameters.frequency_destination),
+        }
+    }
+
+    /// Update scanning state after demodulating a block
+    /// lasting given time in seconds.
+    /// Return true if stopped on the current frequency.
+    fn update(&mut self, duration: f64) -> bool {
+        self.dwell += duration;
+        let open = self.squelches[self.current].update_block(self.demodulator.power(), duration);
+        if open != self.stopped {
+            self.stopped = open;
+            // Lines give the frequency while stopped.
+            self.frequency_output.send(&if open { self.frequencies[self.current].to_string() } else { "scanning".to_string() });
+        }
+        if !open && self.dwell >= DWELL_TIME {
+            self.current = (self.current + 1) % self.frequencies.len();
+            self.dwell = 0.0;
+            self.demodulator.reset();
+        }
+        open
+    }
+}
+
+impl RxChannelProcessor for ScanToUdp {
+    fn process(&mut self, samples: &[ComplexSample]) {
+        self.audio_buffer.clear();
+        self.demodulator.process(samples, &mut self.audio_buffer);
+        if self.update(samples.len() as f64 / SAMPLE_RATE) {
+            self.output.send(&self.audio_buffer);
+        }
+    }
+
+    fn input_sample_rate(&self) -> f64 {
+        SAMPLE_RATE
+    }
+
+    fn input_center_frequency(&self) -> f64 {
+        self.frequencies[self.current] + self.frequency_offset
+    }
+
+    fn discontinuity(&mut self) {
+        self.demodulator.reset();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scan() {
+        let mut scanner = ScanToUdp::new(ScanToUdpParameters {
+            address: "127.0.0.1:7800",
+            frequency_destination: "udp://127.0.0.1:7801",
+            frequencies: vec![145.5e6, 145.6e6, 145.7e6],
+            modulation: Modulation::FM,
+            channel_filter: ChannelFilter::default_for(Modulation::FM),
+            squelch: SquelchSettings::parse("-20:-30:0.1").unwrap(),
+            frame_duration: 0.02,
+        });
+        let silence = vec![ComplexSample::ZERO; 48];
+        let carrier = vec![ComplexSample::new(1.0, 0.0); 48];
+        let mut frequencies = Vec::new();
+        for _ in 0..200 {
+            scanner.process(&silence);
+            frequencies.push(scanner.input_center_frequency());
+        }
+        // Each frequency is listened to for DWELL_TIME.
+        let dwell_blocks = (DWELL_TIME * 1000.0) as usize;
+        assert_eq!(frequencies[dwell_blocks - 2], 145.5e6);
+        assert_eq!(frequencies[dwell_blocks], 145.6e6);
+        assert_eq!(frequencies[3 * dwell_blocks + 5], 145.5e6);
+
+        // Stays on a frequency while there is a signal
+        // and for the hang time after it.
+        let frequency = scanner.input_center_frequency();
+        for _ in 0..500 {
+            scanner.process(&carrier);
+            assert_eq!(scanner.input_center_frequency(), frequency);
+        }
+        for _ in 0..90 {
+            scanner.process(&silence);
+            assert_eq!(scanner.input_center_frequency(), frequency);
+        }
+        for _ in 0..20 {
+            scanner.process(&silence);
+        }
+        assert_ne!(scanner.input_center_frequency(), frequency);
+    }
+}
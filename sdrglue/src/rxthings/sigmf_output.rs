@@ -0,0 +1,39 @@
+//! Recording of channel IQ samples in SigMF format.
+
+use super::RxChannelProcessor;
+use crate::ComplexSample;
+use crate::sigmf::SigmfRecorder;
+
+pub struct IqToSigmf {
+    center_frequency: f64,
+    sample_rate: f64,
+    recorder: SigmfRecorder,
+}
+
+impl IqToSigmf {
+    pub fn new(path: &str, center_frequency: f64, sample_rate: f64) -> Self {
+        Self {
+            center_frequency,
+            sample_rate,
+            recorder: SigmfRecorder::start(path, sample_rate, center_frequency, None),
+        }
+    }
+}
+
+impl RxChannelProcessor for IqToSigmf {
+    fn process(&mut self, samples: &[ComplexSample]) {
+        self.recorder.write(samples);
+    }
+
+    fn input_sample_rate(&self) -> f64 {
+        self.sample_rate
+    }
+
+    fn input_center_frequency(&self) -> f64 {
+        self.center_frequency
+    }
+
+    fn discontinuity(&mut self) {
+        self.recorder.discontinuity();
+    }
+}
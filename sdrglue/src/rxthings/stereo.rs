@@ -0,0 +1,165 @@
+//! Stereo output of two receive channels.
+//!
+//! Audio demodulated from two channels is sent as the left and
+//! right channels of one stereo stream, which is convenient for
+//! comparing antennas, channel filters, or the input and output
+//! of a repeater by ear.
+//!
+//! All demodulators produce audio at the same sample rate,
+//! so audio of each side is only buffered until the other side
+//! has produced the same number of samples. This keeps the sides
+//! time-aligned even if the channels produce blocks of different
+//! lengths, for example due to different bin spacings.
+//! Any remaining delay difference can be compensated
+//! with --channel-delay.
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::rc::Rc;
+
+use super::RxChannelProcessor;
+use super::chain::{AudioChain, BlockInfo};
+use super::demodulator::{Agc, AgcSettings, AudioUdpOutput, ChannelFilter, Demodulator, Modulation, SAMPLE_RATE};
+use crate::{Sample, ComplexSample};
+
+/// If one side gets this many seconds ahead of the other,
+/// for example because the other channel has been disabled,
+/// it is sent with silence on the other side.
+const MAX_LAG: f64 = 0.2;
+
+/// Mixer shared between the two channels of a stereo output.
+pub struct StereoMixer {
+    /// Audio of left and right side waiting for the other side.
+    sides: [VecDeque<Sample>; 2],
+    /// Interleaved audio for the current block.
+    audio_buffer: Vec<Sample>,
+    output: AudioUdpOutput,
+}
+
+impl StereoMixer {
+    pub fn new(address: &str, frame_duration: f64) -> Self {
+        Self {
+            sides: [VecDeque::new(), VecDeque::new()],
+            audio_buffer: Vec::new(),
+            output: AudioUdpOutput::new(address, frame_duration, 2),
+        }
+    }
+
+    /// Give a block of audio from the left (0) or right (1) side.
+    fn input(&mut self, side: usize, audio: &[Sample]) {
+        self.align(side, audio);
+        if !self.audio_buffer.is_empty() {
+            self.output.send(&self.audio_buffer);
+        }
+    }
+
+    /// Interleave audio available for both sides to audio_buffer.
+    fn align(&mut self, side: usize, audio: &[Sample]) {
+        self.sides[side].extend(audio);
+        self.audio_buffer.clear();
+        let max_lag = (MAX_LAG * SAMPLE_RATE) as usize;
+        let [left, right] = &mut self.sides;
+        while (!left.is_empty() && !right.is_empty()) || left.len() > max_lag || right.len() > max_lag {
+            self.audio_buffer.push(left.pop_front().unwrap_or(0.0));
+            self.audio_buffer.push(right.pop_front().unwrap_or(0.0));
+        }
+    }
+}
+
+pub struct StereoChannelParameters<'a> {
+    pub mixer: Rc<RefCell<StereoMixer>>,
+    /// 0 for left, 1 for right.
+    pub side: usize,
+    pub center_frequency: f64,
+    pub modulation: Modulation,
+    pub channel_filter: ChannelFilter,
+    /// Automatic gain control, if enabled.
+    pub agc: Option<AgcSettings>,
+    /// Description of audio processing chain, if any.
+    pub chain: Option<&'a str>,
+}
+
+/// One side of a stereo output.
+pub struct StereoChannel {
+    /// Input center frequency
+    center_frequency: f64,
+    demodulator: Demodulator,
+    agc: Option<Agc>,
+    chain: Option<AudioChain>,
+    side: usize,
+    mixer: Rc<RefCell<StereoMixer>>,
+    /// Demodulated audio of the latest block.
+    audio_buffer: Vec<Sample>,
+}
+
+impl StereoChannel {
+    pub fn new(parameters: StereoChannelParameters) -> Self {
+        Self {
+            center_frequency:
+                parameters.center_frequency
+                + Demodulator::frequency_offset(parameters.modulation),
+            demodulator: Demodulator::with_filter(parameters.modulation,
+                parameters.channel_filter),
+            agc: parameters.agc.map(Agc::new),
+            // TODO: handle errors more nicely
+            chain: parameters.chain.map(|description|
+                AudioChain::parse_mono(description, parameters.center_frequency)
+                .unwrap_or_else(|err| panic!("Invalid audio chain: {}", err))),
+            side: parameters.side,
+            mixer: parameters.mixer,
+            audio_buffer: Vec::with_capacity(48),
+        }
+    }
+}
+
+impl RxChannelProcessor for StereoChannel {
+    fn process(&mut self, samples: &[ComplexSample]) {
+        self.audio_buffer.clear();
+        self.demodulator.process(samples, &mut self.audio_buffer);
+        if let Some(agc) = &mut self.agc {
+            agc.process(&mut self.audio_buffer);
+        }
+        if let Some(chain) = &mut self.chain {
+            chain.process(&mut self.audio_buffer, &BlockInfo { power: self.demodulator.power() });
+        }
+        self.mixer.borrow_mut().input(self.side, &self.audio_buffer);
+    }
+
+    fn input_sample_rate(&self) -> f64 {
+        SAMPLE_RATE
+    }
+
+    fn input_center_frequency(&self) -> f64 {
+        self.center_frequency
+    }
+
+    fn discontinuity(&mut self) {
+        self.demodulator.reset();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_align() {
+        let mut mixer = StereoMixer::new("127.0.0.1:7500", 0.0);
+        // Blocks of different lengths are paired sample by sample.
+        mixer.align(0, &[1.0, 2.0, 3.0]);
+        assert!(mixer.audio_buffer.is_empty());
+        mixer.align(1, &[-1.0, -2.0]);
+        assert_eq!(mixer.audio_buffer, [1.0, -1.0, 2.0, -2.0]);
+        mixer.align(1, &[-3.0, -4.0]);
+        assert_eq!(mixer.audio_buffer, [3.0, -3.0]);
+        mixer.align(0, &[4.0]);
+        assert_eq!(mixer.audio_buffer, [4.0, -4.0]);
+        // A side with no audio is filled with silence
+        // once the other one gets too far ahead.
+        let max_lag = (MAX_LAG * SAMPLE_RATE) as usize;
+        mixer.align(0, &vec![0.5; max_lag]);
+        assert!(mixer.audio_buffer.is_empty());
+        mixer.align(0, &[0.5; 10]);
+        assert_eq!(mixer.audio_buffer, [0.5, 0.0].repeat(10));
+    }
+}
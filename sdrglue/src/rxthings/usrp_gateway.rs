@@ -0,0 +1,101 @@
+//! Receive side of a USRP protocol gateway
+//! for linking with AllStarLink/EchoLink nodes.
+
+use super::RxChannelProcessor;
+use super::demodulator::{Demodulator, Modulation, Squelch, SAMPLE_RATE};
+use crate::{Sample, ComplexSample};
+use crate::filter;
+use crate::usrp;
+
+pub struct UsrpGatewayRx {
+    /// Center frequency to demodulate
+    center_frequency: f64,
+    demodulator: Demodulator,
+    /// Decimator from demodulator sample rate to USRP sample rate.
+    decimator: filter::Decimator,
+    squelch: Squelch,
+    /// Whether squelch is currently open, i.e. node is keyed.
+    keyup: bool,
+    /// Sequence number of next packet.
+    sequence: u32,
+    /// Demodulated audio of the latest block.
+    audio_buffer: Vec<Sample>,
+    /// Decimated audio waiting to be sent.
+    frame_buffer: Vec<Sample>,
+    /// Buffer for packet being sent.
+    packet_buffer: Vec<u8>,
+    /// Socket to send packets to the node.
+    socket: std::net::UdpSocket,
+}
+
+pub struct UsrpGatewayRxParameters<'a> {
+    /// Center frequency to demodulate
+    pub center_frequency: f64,
+    /// Address of the node to send packets to.
+    pub address: &'a str,
+    /// Squelch opening threshold for channel power in dB.
+    pub squelch_level: Sample,
+}
+
+impl UsrpGatewayRx {
+    pub fn new(parameters: &UsrpGatewayRxParameters) -> Self {
+        Self {
+            center_frequency: parameters.center_frequency,
+            demodulator: Demodulator::new(Modulation::FM),
+            decimator: filter::Decimator::new((SAMPLE_RATE / usrp::SAMPLE_RATE).round() as usize),
+            squelch: Squelch::new(parameters.center_frequency, parameters.squelch_level),
+            keyup: false,
+            sequence: 0,
+            audio_buffer: Vec::new(),
+            frame_buffer: Vec::with_capacity(usrp::FRAME_SAMPLES * 2),
+            packet_buffer: Vec::new(),
+            socket: super::demodulator::audio_udp_socket(parameters.address),
+        }
+    }
+
+    fn send_frame(&mut self, keyup: bool) {
+        let full_scale = i16::MAX as Sample;
+        let audio: Vec<i16> = self.frame_buffer[..usrp::FRAME_SAMPLES].iter().map(|&sample|
+            (sample * full_scale).min(full_scale).max(-full_scale) as i16
+        ).collect();
+        usrp::write_voice_packet(&mut self.packet_buffer, self.sequence, keyup, &audio);
+        self.sequence = self.sequence.wrapping_add(1);
+        // TODO: print a warning or something if writing to socket fails
+        let _ = self.socket.send(&self.packet_buffer);
+    }
+}
+
+impl RxChannelProcessor for UsrpGatewayRx {
+    fn process(&mut self, samples: &[ComplexSample]) {
+        self.audio_buffer.clear();
+        self.demodulator.process(samples, &mut self.audio_buffer);
+        self.decimator.process(&self.audio_buffer, &mut self.frame_buffer);
+
+        let open = self.squelch.update(self.demodulator.power());
+
+        while self.frame_buffer.len() >= usrp::FRAME_SAMPLES {
+            if open {
+                self.send_frame(true);
+            } else if self.keyup {
+                // Send one more packet with keyup cleared
+                // to let the node know the transmission ended.
+                self.frame_buffer[..usrp::FRAME_SAMPLES].fill(0.0);
+                self.send_frame(false);
+            }
+            self.keyup = open;
+            self.frame_buffer.drain(..usrp::FRAME_SAMPLES);
+        }
+    }
+
+    fn input_sample_rate(&self) -> f64 {
+        SAMPLE_RATE
+    }
+
+    fn input_center_frequency(&self) -> f64 {
+        self.center_frequency
+    }
+
+    fn discontinuity(&mut self) {
+        self.demodulator.reset();
+    }
+}
@@ -0,0 +1,175 @@
+//! Voice activity detection on demodulated audio.
+//!
+//! On noisy channels, RF squelch either opens on noise or
+//! cuts weak speech. The detector looks at the audio instead:
+//! each FRAME_TIME frame counts as speech when its power in the
+//! speech band is enough above the noise floor and most of its
+//! power is in the speech band, which is not the case for the
+//! flat or rising spectrum of noise from a demodulator.
+//! Speech needs to continue for ONSET_FRAMES frames to be
+//! detected and detection is held over pauses for a hang time.
+
+use super::demodulator::SAMPLE_RATE;
+use crate::Sample;
+
+/// Length of analysis frames in seconds.
+const FRAME_TIME: f64 = 0.01;
+/// Number of consecutive speech frames needed to detect speech.
+const ONSET_FRAMES: usize = 3;
+/// Edges of the speech band in Hertz.
+const SPEECH_LOW: f64 = 300.0;
+const SPEECH_HIGH: f64 = 3000.0;
+/// Minimum fraction of frame power in the speech band.
+const MIN_BAND_FRACTION: Sample = 0.4;
+/// Minimum speech band power relative to full scale in dB,
+/// so that the detector does not open on near silence.
+const MIN_POWER_DB: Sample = -70.0;
+/// How fast the noise floor estimate rises in dB per second.
+/// It falls immediately to the power of a quieter frame.
+const FLOOR_RISE_DB: Sample = 3.0;
+
+#[derive(Copy, Clone, Debug)]
+pub struct VadSettings {
+    /// How much speech band power has to exceed
+    /// the noise floor in dB. Lower values are more sensitive.
+    pub threshold_db: Sample,
+    /// Time in seconds detection is held after speech ends.
+    pub hang_time: f64,
+}
+
+impl VadSettings {
+    /// Parse "threshold" or "threshold:hang_time".
+    pub fn parse(text: &str) -> Option<Self> {
+        let mut values = text.split(':').map(|value| value.parse::<f64>().ok());
+        let threshold_db = values.next()?? as Sample;
+        let hang_time = values.next().unwrap_or(Some(0.5))?;
+        (values.next().is_none() && hang_time >= 0.0).then_some(Self { threshold_db, hang_time })
+    }
+}
+
+pub struct VoiceDetector {
+    settings: VadSettings,
+    /// Coefficients of the one-pole filters
+    /// limiting audio to the speech band.
+    lowpass_alpha: Sample,
+    highpass_alpha: Sample,
+    lowpass: Sample,
+    /// Previous lowpass output and highpass output.
+    previous: Sample,
+    highpass: Sample,
+    frame_length: usize,
+    frame_samples: usize,
+    /// Power sums of the current frame.
+    band_sum: Sample,
+    total_sum: Sample,
+    /// Noise floor of speech band power, or None before the first frame.
+    floor: Option<Sample>,
+    /// Noise floor rise per frame as a power ratio.
+    floor_rise: Sample,
+    /// Number of consecutive speech frames.
+    speech_frames: usize,
+    /// Frames since speech was last detected.
+    frames_since_speech: usize,
+    hang_frames: usize,
+    active: bool,
+}
+
+impl VoiceDetector {
+    pub fn new(settings: VadSettings) -> Self {
+        let frame_length = (FRAME_TIME * SAMPLE_RATE).round() as usize;
+        let rc = |cutoff: f64| 1.0 / (2.0 * std::f64::consts::PI * cutoff);
+        let dt = 1.0 / SAMPLE_RATE;
+        Self {
+            settings,
+            lowpass_alpha: (dt / (rc(SPEECH_HIGH) + dt)) as Sample,
+            highpass_alpha: (rc(SPEECH_LOW) / (rc(SPEECH_LOW) + dt)) as Sample,
+            lowpass: 0.0,
+            previous: 0.0,
+            highpass: 0.0,
+            frame_length,
+            frame_samples: 0,
+            band_sum: 0.0,
+            total_sum: 0.0,
+            floor: None,
+            floor_rise: (10.0 as Sample).powf(FLOOR_RISE_DB * FRAME_TIME as Sample / 10.0),
+            speech_frames: 0,
+            frames_since_speech: usize::MAX,
+            hang_frames: (settings.hang_time / FRAME_TIME).round() as usize,
+            active: false,
+        }
+    }
+
+    /// Decide whether a frame with given powers is speech
+    /// and update the noise floor.
+    fn frame(&mut self, band_power: Sample, total_power: Sample) -> bool {
+        let floor = self.floor.map_or(band_power, |floor| (floor * self.floor_rise).min(band_power));
+        self.floor = Some(floor);
+        let db = |power: Sample| 10.0 * (power + 1e-20).log10();
+        db(band_power) - db(floor) >= self.settings.threshold_db
+            && db(band_power) >= MIN_POWER_DB
+            && band_power >= MIN_BAND_FRACTION * total_power
+    }
+
+    /// Process a block of demodulated audio at SAMPLE_RATE
+    /// and return whether speech is detected.
+    pub fn process(&mut self, audio: &[Sample]) -> bool {
+        for &sample in audio {
+            self.lowpass += self.lowpass_alpha * (sample - self.lowpass);
+            self.highpass = self.highpass_alpha * (self.highpass + self.lowpass - self.previous);
+            self.previous = self.lowpass;
+            self.band_sum += self.highpass * self.highpass;
+            self.total_sum += sample * sample;
+            self.frame_samples += 1;
+            if self.frame_samples < self.frame_length {
+                continue;
+            }
+            let length = self.frame_length as Sample;
+            let speech = self.frame(self.band_sum / length, self.total_sum / length);
+            self.frame_samples = 0;
+            self.band_sum = 0.0;
+            self.total_sum = 0.0;
+
+            self.speech_frames = if speech { self.speech_frames + 1 } else { 0 };
+            if self.speech_frames >= ONSET_FRAMES || (self.active && speech) {
+                self.frames_since_speech = 0;
+            } else {
+                self.frames_since_speech = self.frames_since_speech.saturating_add(1);
+            }
+            self.active = self.frames_since_speech <= self.hang_frames;
+        }
+        self.active
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::loopback::Random;
+
+    #[test]
+    fn test_vad() {
+        let mut detector = VoiceDetector::new(VadSettings::parse("10").unwrap());
+        let mut random = Random::new(1);
+        let mut noise = || 0.05 * (random.uniform() - 0.5) as Sample;
+        // Noise alone is not detected, even as it gets louder.
+        for i in 0..100 {
+            let block: Vec<Sample> = (0..480).map(|_| noise() * (1.0 + i as Sample * 0.05)).collect();
+            assert!(!detector.process(&block));
+        }
+        // Syllables of a 500 Hz tone in noise are detected
+        // and detection is held over short pauses.
+        let mut detected = 0;
+        for i in 0..200 {
+            let syllable = (i / 10) % 2 == 0;
+            let block: Vec<Sample> = (0..480).map(|n| {
+                let t = (i * 480 + n) as f64 / SAMPLE_RATE;
+                noise() * 6.0 + if syllable { 0.3 * (2.0 * std::f64::consts::PI * 500.0 * t).sin() as Sample } else { 0.0 }
+            }).collect();
+            if detector.process(&block) {
+                detected += 1;
+            }
+        }
+        assert!(detected >= 195, "{}", detected);
+        assert!(VadSettings::parse("10:-1").is_none());
+    }
+}
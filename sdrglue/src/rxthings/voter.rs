@@ -0,0 +1,216 @@
+//! Signal voting between multiple receivers.
+//!
+//! Several receivers demodulate the same transmission
+//! (for example, on different frequencies of a linked system)
+//! and the voter picks the best one of them to be sent out.
+//! Received signal power in the channel is used as the metric.
+//! For FM this is a reasonable estimate of which receiver has
+//! the best signal-to-noise ratio, as long as noise floors are similar.
+//!
+//! The selected audio is sent to a UDP socket,
+//! or transmitted by a repeater (see txthings::repeater).
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use super::RxChannelProcessor;
+use super::demodulator::{self, Demodulator, Modulation, Squelch, SquelchSettings, SAMPLE_RATE};
+use crate::{Sample, ComplexSample};
+use crate::events;
+use crate::txthings::repeater::{self, RepeaterAudio};
+
+/// How often the best receiver is selected, in seconds.
+const VOTING_INTERVAL: f64 = 0.2;
+/// How much better (in dB) another receiver has to be
+/// before switching to it.
+/// Avoids switching back and forth between receivers
+/// with almost equal signals.
+const VOTING_HYSTERESIS_DB: Sample = 3.0;
+
+struct VoterInput {
+    /// Demodulated audio of the latest block.
+    audio: Vec<Sample>,
+    /// Signal power of the latest block.
+    power: Sample,
+    /// Sum of signal powers since the previous vote.
+    power_sum: Sample,
+}
+
+/// Where the selected audio goes.
+pub enum VoterOutput {
+    Udp(demodulator::AudioUdpOutput),
+    /// Repeater transmitter, passing audio while
+    /// signal of the selected receiver is above squelch.
+    Repeater(RepeaterAudio, Squelch),
+}
+
+impl VoterOutput {
+    pub fn udp(address: &str, frame_duration: f64) -> Self {
+        Self::Udp(demodulator::AudioUdpOutput::new(address, frame_duration, 1))
+    }
+
+    /// Output to the repeater given by its index in --vote-to-tx,
+    /// transmitting at given frequency.
+    pub fn repeater(index: usize, frequency: f64, squelch: SquelchSettings) -> Self {
+        Self::Repeater(repeater::repeater_audio(index), Squelch::with_settings(frequency, squelch))
+    }
+}
+
+/// Voter shared between all receivers in a voting group.
+pub struct Voter {
+    inputs: Vec<VoterInput>,
+    /// Number of inputs received for the current block.
+    received: usize,
+    /// Index of currently selected input.
+    selected: usize,
+    /// Index of previously selected input.
+    /// Differs from selected during the block where switchover happens.
+    previous: usize,
+    /// Number of blocks since the previous vote.
+    blocks_since_vote: usize,
+    /// Selected audio for the current block.
+    audio_buffer: Vec<Sample>,
+    /// Output to send selected audio to.
+    output: VoterOutput,
+}
+
+impl Voter {
+    pub fn new(output: VoterOutput, number_of_inputs: usize) -> Self {
+        Self {
+            inputs: (0..number_of_inputs).map(|_| VoterInput {
+                audio: Vec::new(),
+                power: 0.0,
+                power_sum: 0.0,
+            }).collect(),
+            received: 0,
+            selected: 0,
+            previous: 0,
+            blocks_since_vote: 0,
+            audio_buffer: Vec::new(),
+            output,
+        }
+    }
+
+    /// Give a block of demodulated audio from one of the inputs.
+    /// Once every input has given a block, the selected one is sent out.
+    fn input(&mut self, index: usize, audio: &[Sample], power: Sample) {
+        let input = &mut self.inputs[index];
+        input.audio.clear();
+        input.audio.extend_from_slice(audio);
+        input.power = power;
+        input.power_sum += power;
+        self.received += 1;
+        if self.received >= self.inputs.len() {
+            self.received = 0;
+            self.output();
+        }
+    }
+
+    fn output(&mut self) {
+        let len = self.inputs.iter().map(|input| input.audio.len()).min().unwrap_or(0);
+        self.audio_buffer.clear();
+        let selected = &self.inputs[self.selected].audio;
+        if self.selected == self.previous {
+            self.audio_buffer.extend_from_slice(&selected[..len]);
+        } else {
+            // Crossfade from previously selected input
+            // to avoid a click at switchover.
+            let previous = &self.inputs[self.previous].audio;
+            for i in 0..len {
+                let fade = (i as Sample + 0.5) / len as Sample;
+                self.audio_buffer.push(selected[i] * fade + previous[i] * (1.0 - fade));
+            }
+            self.previous = self.selected;
+        }
+
+        match &mut self.output {
+            VoterOutput::Udp(output) => output.send(&self.audio_buffer),
+            VoterOutput::Repeater(audio, squelch) => {
+                let duration = len as f64 / SAMPLE_RATE;
+                if !squelch.update_block(self.inputs[self.selected].power, duration) {
+                    self.audio_buffer.fill(0.0);
+                }
+                repeater::send_to_repeater(audio, &self.audio_buffer);
+            },
+        }
+
+        self.blocks_since_vote += 1;
+        if (self.blocks_since_vote * len) as f64 >= VOTING_INTERVAL * SAMPLE_RATE {
+            self.vote();
+        }
+    }
+
+    /// Select the input with the highest average power.
+    /// The new selection takes effect on the next block.
+    fn vote(&mut self) {
+        let db = |power_sum: Sample| 10.0 * (power_sum + 1e-20).log10();
+        let mut best = self.selected;
+        let mut best_db = db(self.inputs[self.selected].power_sum) + VOTING_HYSTERESIS_DB;
+        for (index, input) in self.inputs.iter().enumerate() {
+            let input_db = db(input.power_sum);
+            if input_db > best_db {
+                best = index;
+                best_db = input_db;
+            }
+        }
+        if best != self.selected {
+            events::emit(events::Event::new("voter_switch")
+                .with("receiver", best)
+                .with("power_db", best_db));
+        }
+        self.selected = best;
+        for input in self.inputs.iter_mut() {
+            input.power_sum = 0.0;
+        }
+        self.blocks_since_vote = 0;
+    }
+}
+
+/// One receiver in a voting group.
+pub struct VoterReceiver {
+    /// Input center frequency
+    center_frequency: f64,
+    demodulator: Demodulator,
+    /// Index of this receiver in the voter.
+    index: usize,
+    voter: Rc<RefCell<Voter>>,
+    /// Demodulated audio of the latest block.
+    audio_buffer: Vec<Sample>,
+}
+
+impl VoterReceiver {
+    pub fn new(
+        voter: Rc<RefCell<Voter>>,
+        index: usize,
+        center_frequency: f64,
+        modulation: Modulation,
+    ) -> Self {
+        Self {
+            center_frequency: center_frequency + Demodulator::frequency_offset(modulation),
+            demodulator: Demodulator::new(modulation),
+            index,
+            voter,
+            audio_buffer: Vec::new(),
+        }
+    }
+}
+
+impl RxChannelProcessor for VoterReceiver {
+    fn process(&mut self, samples: &[ComplexSample]) {
+        self.audio_buffer.clear();
+        self.demodulator.process(samples, &mut self.audio_buffer);
+        self.voter.borrow_mut().input(self.index, &self.audio_buffer, self.demodulator.power());
+    }
+
+    fn input_sample_rate(&self) -> f64 {
+        SAMPLE_RATE
+    }
+
+    fn input_center_frequency(&self) -> f64 {
+        self.center_frequency
+    }
+
+    fn discontinuity(&mut self) {
+        self.demodulator.reset();
+    }
+}
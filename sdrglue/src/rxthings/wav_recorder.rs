@@ -0,0 +1,209 @@
+//! Recording of demodulated audio to WAV files
+//! while squelch is open, and speech is detected
+//! if voice activity detection is enabled.
+//!
+//! Each squelch opening starts a new file named after
+//! the UTC time and frequency, such as
+//! 20240131T235959.123Z_145500000.wav, containing 16-bit
+//! mono audio at 48 kHz. When the file is finished,
+//! a "recording" event is emitted with its path and duration.
+
+use std::fs::File;
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+
+use super::RxChannelProcessor;
+use super::demodulator::{audio_to_s16le, ChannelFilter, Demodulator, Modulation, Squelch, SquelchSettings, SAMPLE_RATE};
+use super::vad::{VadSettings, VoiceDetector};
+use crate::{Sample, ComplexSample};
+use crate::clock;
+use crate::events::{self, Event};
+
+/// Length of WAV header in bytes.
+const HEADER_LENGTH: u32 = 44;
+
+/// Write a header for 16-bit mono audio
+/// with given length of audio data in bytes.
+fn write_header(writer: &mut impl Write, data_length: u32) -> std::io::Result<()> {
+    let sample_rate = SAMPLE_RATE as u32;
+    writer.write_all(b"RIFF")?;
+    writer.write_all(&(HEADER_LENGTH - 8 + data_length).to_le_bytes())?;
+    writer.write_all(b"WAVEfmt ")?;
+    writer.write_all(&16u32.to_le_bytes())?;
+    writer.write_all(&1u16.to_le_bytes())?; // PCM
+    writer.write_all(&1u16.to_le_bytes())?; // channels
+    writer.write_all(&sample_rate.to_le_bytes())?;
+    writer.write_all(&(sample_rate * 2).to_le_bytes())?; // bytes per second
+    writer.write_all(&2u16.to_le_bytes())?; // bytes per frame
+    writer.write_all(&16u16.to_le_bytes())?; // bits per sample
+    writer.write_all(b"data")?;
+    writer.write_all(&data_length.to_le_bytes())
+}
+
+/// File being recorded.
+pub(super) struct Recording {
+    path: String,
+    writer: BufWriter<File>,
+    /// Length of audio data written in bytes.
+    data_length: u32,
+    /// Audio converted to bytes written to the file.
+    bytes: Vec<u8>,
+}
+
+impl Recording {
+    pub(super) fn start(path: String) -> std::io::Result<Self> {
+        let mut writer = BufWriter::new(File::create(&path)?);
+        // Lengths are filled in when the file is finished.
+        write_header(&mut writer, 0)?;
+        Ok(Self { path, writer, data_length: 0, bytes: Vec::new() })
+    }
+
+    /// Append audio to the file, printing an error if writing fails.
+    pub(super) fn write(&mut self, audio: &[Sample]) {
+        self.bytes.clear();
+        audio_to_s16le(audio, &mut self.bytes);
+        if let Err(err) = self.writer.write_all(&self.bytes) {
+            eprintln!("Error writing recording {}: {}", self.path, err);
+        }
+        self.data_length += self.bytes.len() as u32;
+    }
+
+    fn finish_file(&mut self) -> std::io::Result<()> {
+        self.writer.seek(SeekFrom::Start(0))?;
+        write_header(&mut self.writer, self.data_length)?;
+        self.writer.flush()
+    }
+
+    /// Finish the file and emit given "recording" event
+    /// with its path and duration added.
+    pub(super) fn finish(mut self, event: Event) {
+        let duration = self.data_length as f64 / 2.0 / SAMPLE_RATE;
+        match self.finish_file() {
+            Ok(()) => events::emit(event
+                .with("path", self.path.as_str())
+                .with("duration", duration)),
+            Err(err) => eprintln!("Error writing recording {}: {}", self.path, err),
+        }
+    }
+}
+
+pub struct RecordToWavParameters<'a> {
+    /// Directory to write files to.
+    pub directory: &'a str,
+    pub frequency: f64,
+    pub modulation: Modulation,
+    pub channel_filter: ChannelFilter,
+    pub squelch: SquelchSettings,
+    /// Voice activity detection required for recording, if any.
+    pub vad: Option<VadSettings>,
+}
+
+pub struct RecordToWav {
+    /// Input center frequency
+    center_frequency: f64,
+    frequency: f64,
+    directory: String,
+    demodulator: Demodulator,
+    squelch: Squelch,
+    vad: Option<VoiceDetector>,
+    audio_buffer: Vec<Sample>,
+    /// Recording in progress, if squelch is open.
+    recording: Option<Recording>,
+}
+
+impl RecordToWav {
+    pub fn new(parameters: &RecordToWavParameters) -> Self {
+        Self {
+            center_frequency:
+                parameters.frequency
+                + Demodulator::frequency_offset(parameters.modulation),
+            frequency: parameters.frequency,
+            directory: parameters.directory.to_string(),
+            demodulator: Demodulator::with_filter(parameters.modulation,
+                parameters.channel_filter),
+            squelch: Squelch::with_settings(parameters.frequency, parameters.squelch),
+            vad: parameters.vad.map(VoiceDetector::new),
+            audio_buffer: Vec::with_capacity(48),
+            recording: None,
+        }
+    }
+
+    fn finish_recording(&mut self) {
+        let Some(recording) = self.recording.take() else { return };
+        recording.finish(Event::new("recording").with("frequency", self.frequency));
+    }
+}
+
+impl RxChannelProcessor for RecordToWav {
+    fn process(&mut self, samples: &[ComplexSample]) {
+        self.audio_buffer.clear();
+        self.demodulator.process(samples, &mut self.audio_buffer);
+        let open = self.squelch.update_block(self.demodulator.power(), samples.len() as f64 / SAMPLE_RATE);
+        let voice = self.vad.as_mut().is_none_or(|vad| vad.process(&self.audio_buffer));
+        if !(open && voice) {
+            self.finish_recording();
+            return;
+        }
+        if self.recording.is_none() {
+            let time = clock::now_seconds() - self.audio_buffer.len() as f64 / SAMPLE_RATE;
+            let path = format!("{}/{}_{:.0}.wav", self.directory, clock::utc_timestamp(time), self.frequency);
+            match Recording::start(path.clone()) {
+                Ok(recording) => self.recording = Some(recording),
+                Err(err) => {
+                    eprintln!("Error creating recording {}: {}", path, err);
+                    return;
+                },
+            }
+        }
+        self.recording.as_mut().unwrap().write(&self.audio_buffer);
+    }
+
+    fn input_sample_rate(&self) -> f64 {
+        SAMPLE_RATE
+    }
+
+    fn input_center_frequency(&self) -> f64 {
+        self.center_frequency
+    }
+
+    fn discontinuity(&mut self) {
+        self.demodulator.reset();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_recordings() {
+        let directory = std::env::temp_dir().join(format!("sdrglue-wav-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let mut recorder = RecordToWav::new(&RecordToWavParameters {
+            directory: directory.to_str().unwrap(),
+            frequency: 145.5e6,
+            modulation: Modulation::FM,
+            channel_filter: ChannelFilter::default_for(Modulation::FM),
+            squelch: SquelchSettings::parse("-20").unwrap(),
+            vad: None,
+        });
+        let silence = vec![ComplexSample::ZERO; 48];
+        let carrier = vec![ComplexSample::new(1.0, 0.0); 48];
+        for block in [&silence, &carrier, &silence] {
+            for _ in 0..100 {
+                recorder.process(block);
+            }
+        }
+        let files: Vec<_> = std::fs::read_dir(&directory).unwrap().map(|entry| entry.unwrap().path()).collect();
+        let data = std::fs::read(&files[0]).unwrap();
+        std::fs::remove_dir_all(&directory).unwrap();
+
+        assert_eq!(files.len(), 1);
+        assert!(files[0].to_str().unwrap().ends_with("Z_145500000.wav"));
+        assert_eq!(&data[..4], b"RIFF");
+        assert_eq!(&data[36..40], b"data");
+        let data_length = u32::from_le_bytes(data[40..44].try_into().unwrap()) as usize;
+        assert_eq!(data_length, data.len() - HEADER_LENGTH as usize);
+        // About 100 blocks of 1 ms of 16-bit samples.
+        assert!((data_length as isize - 9600).abs() <= 200, "{}", data_length);
+    }
+}
@@ -0,0 +1,232 @@
+//! Wideband FM broadcast demodulation with stereo decoding.
+//!
+//! The FM discriminator output is a composite signal containing
+//! L+R audio up to 15 kHz, a 19 kHz pilot tone and L-R audio
+//! as a double sideband suppressed carrier signal at 38 kHz.
+//! A PLL locks to the pilot and its doubled phase is used
+//! to demodulate the subcarrier. Without a pilot,
+//! the same mono audio is output on both channels.
+
+use super::RxChannelProcessor;
+use super::demodulator::{AudioUdpOutput, SAMPLE_RATE};
+use crate::{Sample, ComplexSample, sample_consts};
+use crate::events;
+use crate::filter;
+
+/// Sample rate of demodulator input.
+/// Audio is decimated from this to SAMPLE_RATE.
+pub const WFM_SAMPLE_RATE: f64 = 288000.0;
+const DECIMATION: usize = (WFM_SAMPLE_RATE / SAMPLE_RATE) as usize;
+/// Peak deviation of broadcast FM, scaled to full scale audio.
+const DEVIATION: f64 = 75000.0;
+const PILOT_FREQUENCY: f64 = 19000.0;
+/// Nominal pilot amplitude relative to peak deviation,
+/// used to set the PLL loop gains.
+const PILOT_AMPLITUDE: f64 = 0.09;
+/// Loop bandwidth of the pilot PLL in Hertz.
+const PLL_BANDWIDTH: f64 = 20.0;
+/// Pilot level above which stereo is decoded,
+/// relative to peak deviation.
+const STEREO_THRESHOLD: Sample = 0.03;
+/// Coefficient of the filter estimating pilot level.
+const PILOT_LEVEL_ALPHA: Sample = 1e-4;
+/// De-emphasis time constant in seconds.
+/// 50 µs is used in Europe and most of the world, 75 µs in Americas.
+const DEEMPHASIS_TIME: f64 = 50e-6;
+
+/// PLL locking to the 19 kHz pilot tone.
+struct PilotPll {
+    /// Phase in radians.
+    phase: f64,
+    /// Frequency correction in radians per sample.
+    frequency: f64,
+    nominal_increment: f64,
+    proportional_gain: f64,
+    integral_gain: f64,
+    /// Filtered in-phase product, estimating pilot amplitude.
+    level: Sample,
+}
+
+impl PilotPll {
+    fn new() -> Self {
+        // Second order loop with damping factor of 0.707.
+        // Phase detector gain is half of the pilot amplitude.
+        let natural_frequency = 2.0 * std::f64::consts::PI * PLL_BANDWIDTH / WFM_SAMPLE_RATE;
+        let detector_gain = PILOT_AMPLITUDE / 2.0;
+        Self {
+            phase: 0.0,
+            frequency: 0.0,
+            nominal_increment: 2.0 * std::f64::consts::PI * PILOT_FREQUENCY / WFM_SAMPLE_RATE,
+            proportional_gain: 2.0 * 0.707 * natural_frequency / detector_gain,
+            integral_gain: natural_frequency * natural_frequency / detector_gain,
+            level: 0.0,
+        }
+    }
+
+    /// Update the loop with a composite sample
+    /// and return the current pilot phase.
+    fn sample(&mut self, composite: Sample) -> f64 {
+        let phase = self.phase;
+        let (sin, cos) = (phase as Sample).sin_cos();
+        // Pilot locks to the cosine of the phase.
+        let error = (-composite * sin) as f64;
+        self.level += PILOT_LEVEL_ALPHA * (2.0 * composite * cos - self.level);
+        self.frequency += self.integral_gain * error;
+        self.phase = (self.phase + self.nominal_increment + self.frequency + self.proportional_gain * error)
+            .rem_euclid(2.0 * std::f64::consts::PI);
+        phase
+    }
+
+    fn locked(&self) -> bool {
+        self.level >= STEREO_THRESHOLD
+    }
+}
+
+/// Wideband FM demodulator producing interleaved
+/// left and right audio at SAMPLE_RATE.
+pub struct WfmDemodulator {
+    previous_sample: ComplexSample,
+    pll: PilotPll,
+    /// Lowpass filter for L+R in real part and L-R in imaginary part.
+    audio_filter: filter::FirCf32Sym,
+    decimation_phase: usize,
+    /// De-emphasis filter states for left and right.
+    deemphasis: [Sample; 2],
+    deemphasis_alpha: Sample,
+    stereo: bool,
+    /// Frequency of the channel, used in events.
+    frequency: f64,
+}
+
+impl WfmDemodulator {
+    pub fn new(frequency: f64) -> Self {
+        Self {
+            previous_sample: ComplexSample::ZERO,
+            pll: PilotPll::new(),
+            audio_filter: filter::FirCf32Sym::new(
+                filter::design_fir_lowpass(WFM_SAMPLE_RATE, 15000.0, 64)),
+            decimation_phase: 0,
+            deemphasis: [0.0; 2],
+            deemphasis_alpha: (-1.0 / (SAMPLE_RATE * DEEMPHASIS_TIME)).exp() as Sample,
+            stereo: false,
+            frequency,
+        }
+    }
+
+    /// Demodulate a block of samples.
+    /// Interleaved left and right audio is appended to output.
+    pub fn process(&mut self, samples: &[ComplexSample], output: &mut Vec<Sample>) {
+        let scaling = (WFM_SAMPLE_RATE / (2.0 * DEVIATION)) as Sample * sample_consts::FRAC_1_PI;
+        for &sample in samples {
+            let composite = (sample * self.previous_sample.conj()).arg() * scaling;
+            self.previous_sample = sample;
+
+            // Subcarrier is in phase with doubled pilot sine,
+            // which is the negated sine of doubled PLL phase.
+            let pilot_phase = self.pll.sample(composite);
+            let subcarrier = -2.0 * (2.0 * pilot_phase).sin() as Sample;
+            let filtered = self.audio_filter.sample(ComplexSample::new(composite, composite * subcarrier));
+
+            self.decimation_phase += 1;
+            if self.decimation_phase < DECIMATION {
+                continue;
+            }
+            self.decimation_phase = 0;
+
+            let (sum, difference) = (filtered.re, if self.stereo { filtered.im } else { 0.0 });
+            for (state, audio) in self.deemphasis.iter_mut().zip([sum + difference, sum - difference]) {
+                *state = audio + self.deemphasis_alpha * (*state - audio);
+                output.push(*state);
+            }
+        }
+
+        let stereo = self.pll.locked();
+        if stereo != self.stereo {
+            events::emit(events::Event::new(if stereo { "stereo_on" } else { "stereo_off" })
+                .with("frequency", self.frequency));
+            self.stereo = stereo;
+        }
+    }
+
+    /// Reset state depending on continuity of the input signal,
+    /// after samples have been lost.
+    /// Pilot PLL and de-emphasis are kept to avoid
+    /// switching between stereo and mono or clicks in audio.
+    pub fn reset(&mut self) {
+        self.previous_sample = ComplexSample::ZERO;
+        self.audio_filter.reset();
+    }
+}
+
+pub struct DemodulateWfmToUdp {
+    center_frequency: f64,
+    demodulator: WfmDemodulator,
+    /// Demodulated audio of the latest block.
+    audio_buffer: Vec<Sample>,
+    output: AudioUdpOutput,
+}
+
+impl DemodulateWfmToUdp {
+    /// Create for given UDP address, center frequency
+    /// and UDP frame duration in seconds.
+    pub fn new(address: &str, center_frequency: f64, frame_duration: f64) -> Self {
+        Self {
+            center_frequency,
+            demodulator: WfmDemodulator::new(center_frequency),
+            audio_buffer: Vec::new(),
+            output: AudioUdpOutput::new(address, frame_duration, 2),
+        }
+    }
+}
+
+impl RxChannelProcessor for DemodulateWfmToUdp {
+    fn process(&mut self, samples: &[ComplexSample]) {
+        self.audio_buffer.clear();
+        self.demodulator.process(samples, &mut self.audio_buffer);
+        self.output.send(&self.audio_buffer);
+    }
+
+    fn input_sample_rate(&self) -> f64 {
+        WFM_SAMPLE_RATE
+    }
+
+    fn input_center_frequency(&self) -> f64 {
+        self.center_frequency
+    }
+
+    fn discontinuity(&mut self) {
+        self.demodulator.reset();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stereo() {
+        // 1 kHz tone on left channel only
+        let mut phase: f64 = 0.0;
+        let input: Vec<ComplexSample> = (0..WFM_SAMPLE_RATE as usize).map(|i| {
+            let t = i as f64 / WFM_SAMPLE_RATE;
+            let left = 0.5 * (2.0 * std::f64::consts::PI * 1000.0 * t).sin();
+            let pilot = (2.0 * std::f64::consts::PI * PILOT_FREQUENCY * t).sin();
+            let subcarrier = (2.0 * std::f64::consts::PI * 2.0 * PILOT_FREQUENCY * t).sin();
+            let composite = 0.9 * (left / 2.0 + left / 2.0 * subcarrier) + PILOT_AMPLITUDE * pilot;
+            phase += 2.0 * std::f64::consts::PI * composite * DEVIATION / WFM_SAMPLE_RATE;
+            ComplexSample::from_polar(1.0, phase as Sample)
+        }).collect();
+        let mut demodulator = WfmDemodulator::new(100e6);
+        let mut output = Vec::new();
+        for block in input.chunks(1440) {
+            demodulator.process(block, &mut output);
+        }
+        assert!(demodulator.stereo);
+        assert_eq!(output.len(), 2 * SAMPLE_RATE as usize);
+        let peak = |channel: usize| output[SAMPLE_RATE as usize..].iter().skip(channel).step_by(2)
+            .fold(0.0 as Sample, |peak, &x| peak.max(x.abs()));
+        // De-emphasis attenuates 1 kHz by about 0.4 dB.
+        assert!(peak(0) > 0.35, "left {}", peak(0));
+        assert!(peak(1) < 0.1 * peak(0), "right {}", peak(1));
+    }
+}
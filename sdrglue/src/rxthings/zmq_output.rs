@@ -0,0 +1,119 @@
+//! Channel IQ samples and demodulated audio
+//! published on ZeroMQ PUB sockets.
+
+use super::RxChannelProcessor;
+use super::chain::{AudioChain, BlockInfo};
+use super::demodulator::{ChannelFilter, Demodulator, Modulation, SAMPLE_RATE};
+use super::iq_output::{IqEncoder, IqFormat};
+use crate::{Sample, ComplexSample};
+use crate::zmq::Publisher;
+
+/// Channel IQ samples published in the format given
+/// by --iq-format, one message for each processed block.
+pub struct IqToZmq {
+    center_frequency: f64,
+    sample_rate: f64,
+    encoder: IqEncoder,
+    /// Encoded samples of the latest block.
+    buffer: Vec<u8>,
+    publisher: Publisher,
+}
+
+impl IqToZmq {
+    pub fn new(endpoint: &str, topic: Option<&str>, center_frequency: f64, sample_rate: f64, format: IqFormat) -> Self {
+        Self {
+            center_frequency,
+            sample_rate,
+            encoder: IqEncoder::new(format),
+            buffer: Vec::new(),
+            publisher: Publisher::new(endpoint, topic),
+        }
+    }
+}
+
+impl RxChannelProcessor for IqToZmq {
+    fn process(&mut self, samples: &[ComplexSample]) {
+        self.buffer.clear();
+        self.encoder.encode(samples, &mut self.buffer);
+        self.publisher.send(&self.buffer);
+    }
+
+    fn input_sample_rate(&self) -> f64 {
+        self.sample_rate
+    }
+
+    fn input_center_frequency(&self) -> f64 {
+        self.center_frequency
+    }
+}
+
+pub struct DemodulateToZmqParameters<'a> {
+    /// Endpoint to bind the socket to.
+    pub endpoint: &'a str,
+    /// Topic of messages, if any.
+    pub topic: Option<&'a str>,
+    pub center_frequency: f64,
+    pub modulation: Modulation,
+    pub channel_filter: ChannelFilter,
+    /// Description of audio processing chain, if any.
+    pub chain: Option<&'a str>,
+}
+
+/// Demodulated audio published as 32-bit little-endian floats,
+/// one message for each processed block.
+pub struct DemodulateToZmq {
+    center_frequency: f64,
+    demodulator: Demodulator,
+    /// Optional audio processing chain after demodulator.
+    chain: Option<AudioChain>,
+    audio_buffer: Vec<Sample>,
+    /// Audio converted to bytes.
+    buffer: Vec<u8>,
+    publisher: Publisher,
+}
+
+impl DemodulateToZmq {
+    pub fn new(parameters: &DemodulateToZmqParameters) -> Self {
+        Self {
+            center_frequency:
+                parameters.center_frequency
+                + Demodulator::frequency_offset(parameters.modulation),
+            demodulator: Demodulator::with_filter(parameters.modulation,
+                parameters.channel_filter),
+            // TODO: handle errors more nicely
+            chain: parameters.chain.map(|description|
+                AudioChain::parse_mono(description, parameters.center_frequency)
+                .unwrap_or_else(|err| panic!("Invalid audio chain: {}", err))),
+            audio_buffer: Vec::new(),
+            buffer: Vec::new(),
+            publisher: Publisher::new(parameters.endpoint, parameters.topic),
+        }
+    }
+}
+
+impl RxChannelProcessor for DemodulateToZmq {
+    fn process(&mut self, samples: &[ComplexSample]) {
+        self.audio_buffer.clear();
+        self.demodulator.process(samples, &mut self.audio_buffer);
+        if let Some(chain) = &mut self.chain {
+            chain.process(&mut self.audio_buffer, &BlockInfo { power: self.demodulator.power() });
+        }
+        self.buffer.clear();
+        for sample in self.audio_buffer.iter() {
+            self.buffer.extend_from_slice(&sample.to_le_bytes());
+        }
+        self.publisher.send(&self.buffer);
+    }
+
+    fn input_sample_rate(&self) -> f64 {
+        SAMPLE_RATE
+    }
+
+    fn input_center_frequency(&self) -> f64 {
+        self.center_frequency
+    }
+
+    fn discontinuity(&mut self) {
+        self.demodulator.reset();
+    }
+}
@@ -0,0 +1,176 @@
+//! Time-of-day scheduling of channels.
+//!
+//! Channels can be enabled only during given time windows,
+//! for example to follow band openings or to run beacon
+//! transmissions at certain times of day.
+//! Channels are referred to by names like rx0, rx1, tx0,
+//! numbered in the order they are created.
+
+use std::time::{Duration, SystemTime};
+
+use crate::clock;
+use crate::events;
+use crate::rx_dsp::RxDsp;
+use crate::tx_dsp::TxDsp;
+
+/// How often the schedule is checked.
+const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+/// Daily time window in seconds since midnight UTC.
+/// If end is before start, the window continues over midnight.
+struct TimeWindow {
+    start: u64,
+    end: u64,
+}
+
+impl TimeWindow {
+    /// Parse a time window like 06:00-18:30.
+    fn parse(text: &str) -> Option<Self> {
+        let parse_time = |t: &str| -> Option<u64> {
+            let (h, m) = t.split_once(':')?;
+            let (h, m) = (h.parse::<u64>().ok()?, m.parse::<u64>().ok()?);
+            // 24:00 is allowed for the end of a window lasting until midnight.
+            if h > 24 || m >= 60 || (h == 24 && m != 0) { return None; }
+            Some((h * 60 + m) * 60)
+        };
+        let (start, end) = text.split_once('-')?;
+        Some(Self {
+            start: parse_time(start)?,
+            end: parse_time(end)?,
+        })
+    }
+
+    fn contains(&self, time_of_day: u64) -> bool {
+        if self.start <= self.end {
+            time_of_day >= self.start && time_of_day < self.end
+        } else {
+            time_of_day >= self.start || time_of_day < self.end
+        }
+    }
+}
+
+/// Channel referred to by name.
+pub enum ChannelRef {
+    Rx(usize),
+    Tx(usize),
+}
+
+impl ChannelRef {
+    pub fn parse(text: &str) -> Option<Self> {
+        if let Some(index) = text.strip_prefix("rx") {
+            Some(ChannelRef::Rx(index.parse().ok()?))
+        } else if let Some(index) = text.strip_prefix("tx") {
+            Some(ChannelRef::Tx(index.parse().ok()?))
+        } else {
+            None
+        }
+    }
+}
+
+struct Entry {
+    name: String,
+    channel: ChannelRef,
+    /// Channel is enabled if current time is within any of these.
+    windows: Vec<TimeWindow>,
+    /// Whether channel is currently enabled by the schedule.
+    /// None before the first check.
+    enabled: Option<bool>,
+}
+
+pub struct Schedule {
+    entries: Vec<Entry>,
+    last_check: Option<SystemTime>,
+}
+
+impl Schedule {
+    /// Create a schedule from pairs of channel name and
+    /// comma-separated list of time windows.
+    pub fn from_args(args: &[String]) -> Self {
+        // TODO: handle errors more nicely
+        Self {
+            entries: args.chunks_exact(2).map(|entry| Entry {
+                name: entry[0].clone(),
+                channel: ChannelRef::parse(&entry[0])
+                    .unwrap_or_else(|| panic!("Invalid channel name {}", entry[0])),
+                windows: entry[1].split(',').map(|window|
+                    TimeWindow::parse(window).unwrap_or_else(|| panic!("Invalid time window {}", window))
+                ).collect(),
+                enabled: None,
+            }).collect(),
+            last_check: None,
+        }
+    }
+
+    /// Enable and disable channels according to current time.
+    /// Can be called often since the schedule is only
+    /// checked once per CHECK_INTERVAL.
+    pub fn update(&mut self, mut rx_dsp: Option<&mut RxDsp>, mut tx_dsp: Option<&mut TxDsp>) {
+        if self.entries.is_empty() {
+            return;
+        }
+        let now = clock::now();
+        if let Some(last_check) = self.last_check {
+            if now.duration_since(last_check).is_ok_and(|elapsed| elapsed < CHECK_INTERVAL) {
+                return;
+            }
+        }
+        self.last_check = Some(now);
+
+        let time_of_day = now.duration_since(SystemTime::UNIX_EPOCH)
+            .map(|t| t.as_secs()).unwrap_or(0) % SECONDS_PER_DAY;
+
+        for entry in self.entries.iter_mut() {
+            let enable = entry.windows.iter().any(|window| window.contains(time_of_day));
+            if entry.enabled == Some(enable) {
+                continue;
+            }
+            let found = match entry.channel {
+                ChannelRef::Rx(index) => rx_dsp.as_deref_mut().map(|dsp| dsp.set_channel_enabled(index, enable)),
+                ChannelRef::Tx(index) => tx_dsp.as_deref_mut().map(|dsp| dsp.set_channel_enabled(index, enable)),
+            };
+            if found != Some(true) {
+                eprintln!("Scheduled channel {} does not exist", entry.name);
+            }
+            events::emit(events::Event::new(if enable { "channel_enabled" } else { "channel_disabled" })
+                .with("channel", entry.name.as_str()));
+            entry.enabled = Some(enable);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn seconds(h: u64, m: u64) -> u64 {
+        (h * 60 + m) * 60
+    }
+
+    #[test]
+    fn test_time_window() {
+        let window = TimeWindow::parse("06:00-18:30").unwrap();
+        assert!(!window.contains(seconds(5, 59)));
+        assert!(window.contains(seconds(6, 0)));
+        assert!(window.contains(seconds(18, 29)));
+        assert!(!window.contains(seconds(18, 30)));
+
+        // Window continuing over midnight
+        let window = TimeWindow::parse("22:00-06:00").unwrap();
+        assert!(window.contains(seconds(22, 0)));
+        assert!(window.contains(seconds(23, 59)));
+        assert!(window.contains(seconds(0, 0)));
+        assert!(window.contains(seconds(5, 59)));
+        assert!(!window.contains(seconds(6, 0)));
+        assert!(!window.contains(seconds(12, 0)));
+
+        let window = TimeWindow::parse("18:00-24:00").unwrap();
+        assert!(window.contains(seconds(23, 59)));
+        assert!(!window.contains(seconds(0, 0)));
+
+        for invalid in ["24:59-06:00", "25:00-06:00", "06:60-07:00", "06:00", "6-7", ""] {
+            assert!(TimeWindow::parse(invalid).is_none(), "{}", invalid);
+        }
+    }
+}
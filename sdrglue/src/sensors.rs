@@ -0,0 +1,65 @@
+//! Periodic reading of SDR sensors.
+//!
+//! Sensors such as board temperature, LO lock and GPSDO status
+//! are read at a fixed interval and reported as "sensor" events,
+//! one for each sensor, with the sensor name and value.
+//! Numeric values are reported as numbers, others as strings.
+//! Warnings can be raised using watchlist rules, for example:
+//! --alert lo_unlock 'event=sensor name=lo_locked value=false'
+//!
+//! Reading a sensor may take milliseconds or more with some drivers,
+//! so sensors are read in a separate thread to avoid stalling DSP.
+//! Events are emitted from the main loop when the values arrive.
+
+use std::sync::mpsc;
+use std::time::Duration;
+
+use crate::events::{self, Event, Value};
+use crate::soapyconfig::SensorReader;
+
+pub struct SensorMonitor {
+    /// Values read by the reading thread.
+    receiver: mpsc::Receiver<Vec<(String, String)>>,
+    /// Latest values as pairs of sensor name and value.
+    values: Vec<(String, String)>,
+}
+
+impl SensorMonitor {
+    /// Start reading sensors at given interval in seconds.
+    pub fn new(reader: SensorReader, interval: f64) -> Self {
+        let interval = Duration::from_secs_f64(interval);
+        let (sender, receiver) = mpsc::channel();
+        std::thread::spawn(move || {
+            // Stop when the monitor is dropped.
+            while sender.send(reader.read()).is_ok() {
+                std::thread::sleep(interval);
+            }
+        });
+        Self {
+            receiver,
+            values: Vec::new(),
+        }
+    }
+
+    /// Take values read since previous update and emit events for them.
+    /// Does not wait for reading to finish.
+    pub fn update(&mut self) {
+        while let Ok(values) = self.receiver.try_recv() {
+            self.values = values;
+            for (name, value) in self.values.iter() {
+                let value = match value.trim().parse::<f64>() {
+                    Ok(number) => Value::Number(number),
+                    Err(_) => Value::Str(value.trim().to_string()),
+                };
+                events::emit(Event::new("sensor")
+                    .with("name", name.as_str())
+                    .with("value", value));
+            }
+        }
+    }
+
+    /// Latest sensor values as pairs of name and value.
+    pub fn values(&self) -> &[(String, String)] {
+        &self.values
+    }
+}
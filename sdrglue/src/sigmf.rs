@@ -0,0 +1,313 @@
+//! Recording of IQ samples in SigMF format.
+//!
+//! A recording consists of a data file with extension .sigmf-data,
+//! containing samples as interleaved 32-bit float I and Q values
+//! in little-endian byte order, and a metadata file with extension
+//! .sigmf-meta giving the sample rate, center frequency and start
+//! time of each capture. A new capture starts whenever center
+//! frequency changes or samples have been lost, and the metadata
+//! file is rewritten to include it.
+//! Channels in the recorded signal can be described as annotations
+//! covering the samples during which each channel was enabled.
+//!
+//! Files are written in a separate thread so that slow disk access
+//! does not disturb signal processing. If writing falls behind,
+//! blocks are dropped and a new capture starts after them.
+
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::sync::mpsc;
+use std::thread::JoinHandle;
+
+use serde_json::json;
+
+use crate::ComplexSample;
+use crate::clock;
+
+/// Number of blocks that can wait to be written.
+const MAX_QUEUED: usize = 100;
+
+/// Description of a channel in the recorded signal.
+pub struct ChannelInfo {
+    pub name: String,
+    pub center_frequency: f64,
+    pub sample_rate: f64,
+    pub modulation: &'static str,
+    pub enabled: bool,
+}
+
+enum Message {
+    Samples(Vec<ComplexSample>),
+    Metadata(String),
+}
+
+/// Name of a recording without SigMF extensions,
+/// so that either file or the recording name can be given.
+fn base_name(path: &str) -> &str {
+    [".sigmf-data", ".sigmf-meta", ".sigmf"].iter()
+        .find_map(|extension| path.strip_suffix(extension))
+        .unwrap_or(path)
+}
+
+pub struct SigmfRecorder {
+    sender: Option<mpsc::SyncSender<Message>>,
+    thread: Option<JoinHandle<()>>,
+    sample_rate: f64,
+    center_frequency: f64,
+    /// Description of the SDR, if known.
+    hardware: Option<String>,
+    captures: Vec<serde_json::Value>,
+    /// Channels described in annotations.
+    channels: Vec<ChannelInfo>,
+    /// Sample where each currently enabled channel was enabled.
+    channel_starts: Vec<Option<u64>>,
+    /// Annotations for channels no longer enabled.
+    annotations: Vec<serde_json::Value>,
+    /// Number of samples recorded so far.
+    samples_written: u64,
+    /// Whether a new capture starts from the next samples.
+    new_capture: bool,
+    /// Whether metadata has changed since it was last written.
+    metadata_changed: bool,
+    /// Number of blocks dropped because writing fell behind.
+    dropped_blocks: u64,
+}
+
+impl SigmfRecorder {
+    /// Start recording to given path,
+    /// with or without the SigMF extensions.
+    pub fn start(
+        path: &str,
+        sample_rate: f64,
+        center_frequency: f64,
+        hardware: Option<&str>,
+    ) -> Self {
+        let base = base_name(path);
+        let data_path = format!("{}.sigmf-data", base);
+        let metadata_path = format!("{}.sigmf-meta", base);
+        // TODO: handle errors more nicely
+        let mut data_file = BufWriter::new(File::create(&data_path)
+            .unwrap_or_else(|e| panic!("Could not create recording {}: {}", data_path, e)));
+
+        let (sender, receiver) = mpsc::sync_channel::<Message>(MAX_QUEUED);
+        let thread = std::thread::spawn(move || {
+            let mut bytes = Vec::new();
+            for message in receiver.iter() {
+                let result = match message {
+                    Message::Samples(samples) => {
+                        bytes.clear();
+                        for sample in samples.iter() {
+                            bytes.extend_from_slice(&sample.re.to_le_bytes());
+                            bytes.extend_from_slice(&sample.im.to_le_bytes());
+                        }
+                        data_file.write_all(&bytes)
+                    },
+                    Message::Metadata(metadata) => std::fs::write(&metadata_path, metadata),
+                };
+                if let Err(err) = result {
+                    eprintln!("Error writing recording {}: {}", data_path, err);
+                    break;
+                }
+            }
+            let _ = data_file.flush();
+        });
+
+        Self {
+            sender: Some(sender),
+            thread: Some(thread),
+            sample_rate,
+            center_frequency,
+            hardware: hardware.map(str::to_string),
+            captures: Vec::new(),
+            channels: Vec::new(),
+            channel_starts: Vec::new(),
+            annotations: Vec::new(),
+            samples_written: 0,
+            new_capture: true,
+            metadata_changed: false,
+            dropped_blocks: 0,
+        }
+    }
+
+    /// Describe channels in the recorded signal as annotations.
+    pub fn set_channels(&mut self, channels: Vec<ChannelInfo>) {
+        self.channel_starts = channels.iter()
+            .map(|channel| channel.enabled.then_some(self.samples_written)).collect();
+        self.channels = channels;
+        self.metadata_changed = true;
+    }
+
+    /// Update enabled state of channels,
+    /// starting or ending their annotations.
+    pub fn update_channels(&mut self, enabled: impl Iterator<Item = bool>) {
+        for (index, enabled) in enabled.enumerate().take(self.channels.len()) {
+            match (self.channel_starts[index], enabled) {
+                (None, true) => {
+                    self.channel_starts[index] = Some(self.samples_written);
+                    self.metadata_changed = true;
+                },
+                (Some(start), false) => {
+                    self.annotations.push(self.annotation(index, start));
+                    self.channel_starts[index] = None;
+                    self.metadata_changed = true;
+                },
+                _ => {},
+            }
+        }
+    }
+
+    /// Annotation for a channel enabled from given sample
+    /// up to the current one.
+    fn annotation(&self, index: usize, start: u64) -> serde_json::Value {
+        let channel = &self.channels[index];
+        json!({
+            "core:sample_start": start,
+            "core:sample_count": self.samples_written - start,
+            "core:freq_lower_edge": channel.center_frequency - channel.sample_rate / 2.0,
+            "core:freq_upper_edge": channel.center_frequency + channel.sample_rate / 2.0,
+            "core:label": channel.name,
+            "core:comment": channel.modulation,
+        })
+    }
+
+    fn metadata(&self) -> serde_json::Value {
+        let mut global = json!({
+            "core:datatype": "cf32_le",
+            "core:sample_rate": self.sample_rate,
+            "core:version": "1.0.0",
+            "core:recorder": "sdrglue",
+        });
+        if let Some(hardware) = &self.hardware {
+            global["core:hw"] = json!(hardware);
+        }
+        let mut annotations = self.annotations.clone();
+        annotations.extend(self.channel_starts.iter().enumerate()
+            .filter_map(|(index, start)| Some(self.annotation(index, (*start)?))));
+        annotations.sort_by_key(|annotation| annotation["core:sample_start"].as_u64());
+        json!({
+            "global": global,
+            "captures": self.captures,
+            "annotations": annotations,
+        })
+    }
+
+    fn metadata_message(&self) -> Message {
+        // TODO: handle errors more nicely
+        Message::Metadata(serde_json::to_string_pretty(&self.metadata()).unwrap())
+    }
+
+    /// Record a block of samples.
+    pub fn write(&mut self, samples: &[ComplexSample]) {
+        let Some(sender) = &self.sender else {
+            return;
+        };
+        match sender.try_send(Message::Samples(samples.to_vec())) {
+            Ok(()) => {},
+            Err(mpsc::TrySendError::Full(_)) => {
+                if self.dropped_blocks == 0 {
+                    eprintln!("Recording is falling behind, dropping blocks");
+                }
+                self.dropped_blocks += 1;
+                self.new_capture = true;
+                return;
+            },
+            Err(mpsc::TrySendError::Disconnected(_)) => return,
+        }
+        if self.new_capture {
+            self.new_capture = false;
+            // Samples are processed right after being received,
+            // so the first one was received about a block ago.
+            let start = clock::now_seconds() - samples.len() as f64 / self.sample_rate;
+            self.captures.push(json!({
+                "core:sample_start": self.samples_written,
+                "core:frequency": self.center_frequency,
+                "core:datetime": clock::utc_datetime(start),
+            }));
+            self.metadata_changed = true;
+        }
+        self.samples_written += samples.len() as u64;
+        if self.metadata_changed {
+            // If the queue is full, try again with the next block.
+            let message = self.metadata_message();
+            if let Some(sender) = &self.sender {
+                self.metadata_changed = sender.try_send(message).is_err();
+            }
+        }
+    }
+
+    /// Start a new capture at a new center frequency.
+    pub fn retune(&mut self, center_frequency: f64) {
+        self.center_frequency = center_frequency;
+        self.new_capture = true;
+    }
+
+    /// Start a new capture since samples have been lost.
+    pub fn discontinuity(&mut self) {
+        self.new_capture = true;
+    }
+}
+
+impl Drop for SigmfRecorder {
+    /// Write final metadata, including lengths of annotations
+    /// for enabled channels, and wait for everything to be written.
+    fn drop(&mut self) {
+        if self.metadata_changed || self.channel_starts.iter().any(Option::is_some) {
+            let message = self.metadata_message();
+            if let Some(sender) = &self.sender {
+                let _ = sender.send(message);
+            }
+        }
+        if self.dropped_blocks > 0 {
+            eprintln!("Recording dropped {} blocks in total", self.dropped_blocks);
+        }
+        self.sender = None;
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sigmf() {
+        let directory = std::env::temp_dir().join(format!("sdrglue-sigmf-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let path = directory.join("test.sigmf-data");
+        let mut recorder = SigmfRecorder::start(path.to_str().unwrap(), 1e6, 145e6, Some("test"));
+        recorder.set_channels(vec![
+            ChannelInfo { name: "tx0".to_string(), center_frequency: 145.1e6, sample_rate: 48000.0, modulation: "FM", enabled: true },
+            ChannelInfo { name: "tx1".to_string(), center_frequency: 145.2e6, sample_rate: 48000.0, modulation: "USB", enabled: false },
+        ]);
+        recorder.write(&[ComplexSample::new(0.5, -1.5); 10]);
+        recorder.retune(146e6);
+        recorder.update_channels([false, true].into_iter());
+        recorder.write(&[ComplexSample::new(0.0, 0.0); 5]);
+        drop(recorder);
+        let data = std::fs::read(&path).unwrap();
+        let metadata: serde_json::Value = serde_json::from_str(
+            &std::fs::read_to_string(directory.join("test.sigmf-meta")).unwrap()).unwrap();
+        std::fs::remove_dir_all(&directory).unwrap();
+
+        assert_eq!(data.len(), 15 * 8);
+        assert_eq!(&data[..8], &[0.5f32.to_le_bytes(), (-1.5f32).to_le_bytes()].concat());
+        assert_eq!(metadata["global"]["core:datatype"], "cf32_le");
+        assert_eq!(metadata["global"]["core:sample_rate"], 1e6);
+        assert_eq!(metadata["global"]["core:hw"], "test");
+        let captures = metadata["captures"].as_array().unwrap();
+        assert_eq!(captures.len(), 2);
+        assert_eq!(captures[1]["core:sample_start"], 10);
+        assert_eq!(captures[1]["core:frequency"], 146e6);
+        assert!(captures[0]["core:datetime"].as_str().unwrap().ends_with('Z'));
+        let annotations = metadata["annotations"].as_array().unwrap();
+        assert_eq!(annotations.len(), 2);
+        assert_eq!(annotations[0]["core:label"], "tx0");
+        assert_eq!(annotations[0]["core:sample_count"], 10);
+        assert_eq!(annotations[1]["core:label"], "tx1");
+        assert_eq!(annotations[1]["core:sample_start"], 10);
+        assert_eq!(annotations[1]["core:sample_count"], 5);
+        assert_eq!(annotations[1]["core:freq_lower_edge"], 145.176e6);
+    }
+}
@@ -1,5 +1,9 @@
-use soapysdr;
+use std::collections::HashMap;
+
 use crate::configuration;
+use crate::converter::Converter;
+use crate::rx_dsp;
+use crate::tx_dsp;
 
 type StreamType = crate::ComplexSample;
 
@@ -18,6 +22,14 @@ struct SdrDefaults<'a> {
     pub rx_gain: &'a[&'a str],
     /// Transmit gain(s)
     pub tx_gain: &'a[&'a str],
+    /// Receive stream arguments as pairs of name and value.
+    /// Arguments given on command line take precedence.
+    pub rx_args: &'a[&'a str],
+    /// Transmit stream arguments
+    pub tx_args: &'a[&'a str],
+    /// Name of the stream argument setting buffer length in samples,
+    /// if supported by the driver.
+    pub buffer_length_arg: Option<&'a str>,
 }
 
 
@@ -37,6 +49,9 @@ const SDR_DEFAULTS_LIME: SdrDefaults = SdrDefaults {
         "PAD",  "52.0",
         "IAMP",  "3.0",
     ],
+    rx_args: &[],
+    tx_args: &[],
+    buffer_length_arg: Some("bufferLength"),
 };
 
 /// Default settings for SXceiver
@@ -54,6 +69,9 @@ const SDR_DEFAULTS_SX: SdrDefaults = SdrDefaults {
         "DAC",    "9.0",
         "MIXER", "30.0",
     ],
+    rx_args: &[],
+    tx_args: &[],
+    buffer_length_arg: None,
 };
 
 /// Default settings for RTL-SDR
@@ -65,6 +83,100 @@ const SDR_DEFAULTS_RTLSDR: SdrDefaults = SdrDefaults {
     tx_ant: None,
     rx_gain: &["40.0"],
     tx_gain: &[],
+    rx_args: &[],
+    tx_args: &[],
+    buffer_length_arg: None,
+};
+
+/// Default settings for LimeSDR Mini
+const SDR_DEFAULTS_LIME_MINI: SdrDefaults = SdrDefaults {
+    name: "LimeSDR Mini",
+    rx_fs: 8192e3,
+    tx_fs: 8192e3,
+    // LimeSDR Mini has no LNAL input
+    rx_ant: Some("LNAW"),
+    tx_ant: Some("BAND1"),
+    rx_gain: &[
+        "LNA", "20.0",
+        "TIA", "10.0",
+        "PGA", "10.0",
+    ],
+    tx_gain: &[
+        "PAD",  "52.0",
+        "IAMP",  "3.0",
+    ],
+    rx_args: &[],
+    tx_args: &[],
+    buffer_length_arg: Some("bufferLength"),
+};
+
+/// Default settings for XTRX
+const SDR_DEFAULTS_XTRX: SdrDefaults = SdrDefaults {
+    name: "XTRX",
+    rx_fs: 8192e3,
+    tx_fs: 8192e3,
+    rx_ant: Some("LNAW"),
+    tx_ant: Some("TXW"),
+    rx_gain: &[
+        "LNA", "20.0",
+        "TIA", "9.0",
+        "PGA", "10.0",
+    ],
+    tx_gain: &[
+        "PAD", "40.0",
+    ],
+    rx_args: &[],
+    tx_args: &[],
+    buffer_length_arg: None,
+};
+
+/// Default settings for PlutoSDR
+const SDR_DEFAULTS_PLUTO: SdrDefaults = SdrDefaults {
+    name: "PlutoSDR",
+    // Lowest sample rate supported without extra decimation
+    // is a bit above 2 MHz.
+    rx_fs: 2400e3,
+    tx_fs: 2400e3,
+    rx_ant: Some("A_BALANCED"),
+    tx_ant: Some("A"),
+    rx_gain: &["PGA", "40.0"],
+    tx_gain: &["ATT", "-10.0"],
+    rx_args: &[],
+    tx_args: &[],
+    buffer_length_arg: Some("bufflen"),
+};
+
+/// Default settings for USRP
+const SDR_DEFAULTS_USRP: SdrDefaults = SdrDefaults {
+    name: "USRP",
+    rx_fs: 2000e3,
+    tx_fs: 2000e3,
+    rx_ant: Some("RX2"),
+    tx_ant: Some("TX/RX"),
+    rx_gain: &["40.0"],
+    tx_gain: &["50.0"],
+    rx_args: &[],
+    tx_args: &[],
+    buffer_length_arg: None,
+};
+
+/// Default settings for Airspy
+const SDR_DEFAULTS_AIRSPY: SdrDefaults = SdrDefaults {
+    name: "Airspy",
+    // Supported by both Airspy R2 and Mini.
+    rx_fs: 10000e3,
+    tx_fs: 10000e3,
+    rx_ant: Some("RX"),
+    tx_ant: None,
+    rx_gain: &[
+        "LNA", "10.0",
+        "MIX", "10.0",
+        "VGA", "10.0",
+    ],
+    tx_gain: &[],
+    rx_args: &[],
+    tx_args: &[],
+    buffer_length_arg: None,
 };
 
 /// Default settings for any other SDR
@@ -76,8 +188,164 @@ const SDR_DEFAULTS: SdrDefaults = SdrDefaults {
     tx_ant: None,
     rx_gain: &[],
     tx_gain: &[],
+    rx_args: &[],
+    tx_args: &[],
+    buffer_length_arg: None,
 };
 
+/// Select built-in defaults based on driver and hardware keys.
+fn builtin_defaults(driver: &str, hardware: &str) -> &'static SdrDefaults<'static> {
+    match (driver, hardware) {
+        // TODO: other LimeSDR models
+        //("FX3", _) => &SDR_DEFAULTS_LIME,
+        (_, "LimeSDR-USB") => &SDR_DEFAULTS_LIME,
+        (_, "LimeSDR-Mini") => &SDR_DEFAULTS_LIME_MINI,
+
+        ("xtrx", _) => &SDR_DEFAULTS_XTRX,
+        // Driver key is the same whether Pluto is connected
+        // by USB or over network using ip: URI.
+        ("PlutoSDR", _) => &SDR_DEFAULTS_PLUTO,
+        ("uhd", _) => &SDR_DEFAULTS_USRP,
+        ("Airspy", _) => &SDR_DEFAULTS_AIRSPY,
+
+        ("sx", _) => &SDR_DEFAULTS_SX,
+        (_, "sx") => &SDR_DEFAULTS_SX,
+
+        // We could also use hardware key to use different defaults
+        // for different RTL-SDR tuner chips.
+        ("RTLSDR", _) => &SDR_DEFAULTS_RTLSDR,
+
+        (_, _) => &SDR_DEFAULTS,
+    }
+}
+
+/// Defaults after applying overrides from a file.
+struct DeviceDefaults {
+    name: String,
+    rx_fs: f64,
+    tx_fs: f64,
+    rx_ant: Option<String>,
+    tx_ant: Option<String>,
+    rx_gain: Vec<String>,
+    tx_gain: Vec<String>,
+    rx_args: Vec<String>,
+    tx_args: Vec<String>,
+    buffer_length_arg: Option<String>,
+}
+
+impl From<&SdrDefaults<'_>> for DeviceDefaults {
+    fn from(defaults: &SdrDefaults) -> Self {
+        Self {
+            name: defaults.name.to_string(),
+            rx_fs: defaults.rx_fs,
+            tx_fs: defaults.tx_fs,
+            rx_ant: defaults.rx_ant.map(str::to_string),
+            tx_ant: defaults.tx_ant.map(str::to_string),
+            rx_gain: defaults.rx_gain.iter().map(|g| g.to_string()).collect(),
+            tx_gain: defaults.tx_gain.iter().map(|g| g.to_string()).collect(),
+            rx_args: defaults.rx_args.iter().map(|a| a.to_string()).collect(),
+            tx_args: defaults.tx_args.iter().map(|a| a.to_string()).collect(),
+            buffer_length_arg: defaults.buffer_length_arg.map(str::to_string),
+        }
+    }
+}
+
+/// Defaults for one device in a defaults file.
+/// Fields that are not given are taken from built-in defaults.
+#[derive(serde::Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct DefaultsOverride {
+    name: Option<String>,
+    rx_fs: Option<f64>,
+    tx_fs: Option<f64>,
+    rx_ant: Option<String>,
+    tx_ant: Option<String>,
+    rx_gain: Option<Vec<String>>,
+    tx_gain: Option<Vec<String>>,
+    rx_args: Option<Vec<String>>,
+    tx_args: Option<Vec<String>>,
+    buffer_length_arg: Option<String>,
+}
+
+impl DefaultsOverride {
+    fn apply(self, defaults: &mut DeviceDefaults) {
+        if let Some(name) = self.name { defaults.name = name; }
+        if let Some(fs) = self.rx_fs { defaults.rx_fs = fs; }
+        if let Some(fs) = self.tx_fs { defaults.tx_fs = fs; }
+        if self.rx_ant.is_some() { defaults.rx_ant = self.rx_ant; }
+        if self.tx_ant.is_some() { defaults.tx_ant = self.tx_ant; }
+        if let Some(gain) = self.rx_gain { defaults.rx_gain = gain; }
+        if let Some(gain) = self.tx_gain { defaults.tx_gain = gain; }
+        if let Some(args) = self.rx_args { defaults.rx_args = args; }
+        if let Some(args) = self.tx_args { defaults.tx_args = args; }
+        if self.buffer_length_arg.is_some() { defaults.buffer_length_arg = self.buffer_length_arg; }
+    }
+}
+
+/// Parse a defaults file. Each table is keyed by a driver key
+/// or a hardware key, for example:
+/// ```text
+/// [HackRF]
+/// name = "HackRF One"
+/// rx_fs = 8e6
+/// rx_gain = ["AMP", "0", "LNA", "24", "VGA", "20"]
+/// ```
+fn parse_defaults_file(text: &str) -> Result<HashMap<String, DefaultsOverride>, toml::de::Error> {
+    toml::from_str(text)
+}
+
+/// Find defaults for a device, using built-in defaults
+/// overridden by those in the defaults file if given.
+/// An entry matching the hardware key is preferred over
+/// one matching the driver key.
+fn find_defaults(
+    mut overrides: HashMap<String, DefaultsOverride>,
+    driver: &str,
+    hardware: &str,
+) -> DeviceDefaults {
+    let mut defaults = DeviceDefaults::from(builtin_defaults(driver, hardware));
+    if let Some(entry) = overrides.remove(hardware).or_else(|| overrides.remove(driver)) {
+        entry.apply(&mut defaults);
+    }
+    defaults
+}
+
+
+/// Reads sensors of a device.
+pub struct SensorReader {
+    dev: soapysdr::Device,
+    /// Receive and transmit channels, None if not in use.
+    rx_ch: Option<usize>,
+    tx_ch: Option<usize>,
+}
+
+impl SensorReader {
+    /// Read all device sensors and sensors of the channels in use.
+    /// Return pairs of sensor name and value.
+    /// Channel sensor names are prefixed with rx_ or tx_.
+    /// Sensors that fail to read are skipped.
+    pub fn read(&self) -> Vec<(String, String)> {
+        let mut values = Vec::new();
+        for key in self.dev.list_sensors().unwrap_or_default() {
+            if let Ok(value) = self.dev.read_sensor(&key) {
+                values.push((key, value));
+            }
+        }
+        for (prefix, direction, channel) in [
+            ("rx_", soapysdr::Direction::Rx, self.rx_ch),
+            ("tx_", soapysdr::Direction::Tx, self.tx_ch),
+        ] {
+            let Some(channel) = channel else { continue };
+            for key in self.dev.list_channel_sensors(direction, channel).unwrap_or_default() {
+                if let Ok(value) = self.dev.read_channel_sensor(direction, channel, &key) {
+                    values.push((format!("{}{}", prefix, key), value));
+                }
+            }
+        }
+        values
+    }
+}
+
 
 pub struct SoapyIo {
     rx_ch:  usize,
@@ -87,6 +355,11 @@ pub struct SoapyIo {
     rx:  Option<soapysdr::RxStream<StreamType>>,
     /// Transmit stream. None if transmitting is disabled.
     tx:  Option<soapysdr::TxStream<StreamType>>,
+    /// External converters between antenna and SDR.
+    rx_converter: Converter,
+    tx_converter: Converter,
+    /// Buffer for spectrum inverted transmit signal.
+    tx_buffer: Vec<StreamType>,
 }
 
 /// Convert command line device arguments to soapysdr::Args.
@@ -98,6 +371,34 @@ fn convert_args(cli_args: &[String]) -> soapysdr::Args {
     args
 }
 
+/// Combine default stream arguments with those given on command line,
+/// so that command line arguments override defaults.
+/// Buffer length is set first, so it can be overridden by either.
+fn stream_args(
+    defaults: &DeviceDefaults,
+    default_args: &[String],
+    cli_args: &[String],
+    buffer_length: usize,
+) -> soapysdr::Args {
+    let mut args = Vec::new();
+    if let Some(arg) = &defaults.buffer_length_arg {
+        args.push(arg.clone());
+        args.push(buffer_length.to_string());
+    }
+    args.extend_from_slice(default_args);
+    args.extend_from_slice(cli_args);
+    convert_args(&args)
+}
+
+/// Warn if buffer length was given for a device
+/// for which it cannot be set.
+fn check_buffer_length(defaults: &DeviceDefaults, buffer_length: Option<usize>) {
+    if buffer_length.is_some() && defaults.buffer_length_arg.is_none() {
+        eprintln!("Setting buffer length is not supported for {}. \
+            Stream arguments may be used instead.", defaults.name);
+    }
+}
+
 /// It is annoying to repeat error handling so do that in a macro.
 /// ? could be used but then it could not print which SoapySDR call failed.
 macro_rules! soapycheck {
@@ -117,6 +418,9 @@ impl SoapyIo {
         let rx_ch = cli.sdr_rx_ch;
         let tx_ch = cli.sdr_tx_ch;
 
+        let rx_converter = Converter { offset: cli.rx_lo_offset, inverted: cli.rx_spectrum_inverted };
+        let tx_converter = Converter { offset: cli.tx_lo_offset, inverted: cli.tx_spectrum_inverted };
+
         let mut dev = soapycheck!("open SoapySDR device",
             soapysdr::Device::new(convert_args(&cli.sdr_device)));
 
@@ -125,23 +429,19 @@ impl SoapyIo {
         let tx_enabled = cli.sdr_tx_freq.is_some()
             && (dev.num_channels(soapysdr::Direction::Tx).unwrap_or(0) > 0);
 
-        let sdr_defaults = match(
-            dev.driver_key()  .unwrap_or("".to_string()).as_str(),
-            dev.hardware_key().unwrap_or("".to_string()).as_str()
-        ) {
-            // TODO: other LimeSDR models
-            //("FX3", _) => &SDR_DEFAULTS_LIME,
-            (_, "LimeSDR-USB") => &SDR_DEFAULTS_LIME,
-
-            ("sx", _) => &SDR_DEFAULTS_SX,
-            (_, "sx") => &SDR_DEFAULTS_SX,
-
-            // We could also use hardware key to use different defaults
-            // for different RTL-SDR tuner chips.
-            ("RTLSDR", _) => &SDR_DEFAULTS_RTLSDR,
-
-            (_, _) => &SDR_DEFAULTS,
+        // TODO: handle errors more nicely
+        let overrides = match &cli.sdr_defaults {
+            Some(path) => parse_defaults_file(
+                &std::fs::read_to_string(path)
+                    .unwrap_or_else(|e| panic!("Could not read SDR defaults file {}: {}", path, e))
+            ).unwrap_or_else(|e| panic!("Invalid SDR defaults file {}: {}", path, e)),
+            None => HashMap::new(),
         };
+        let sdr_defaults = find_defaults(
+            overrides,
+            dev.driver_key().unwrap_or("".to_string()).as_str(),
+            dev.hardware_key().unwrap_or("".to_string()).as_str(),
+        );
         eprintln!("Using default settings for {}", sdr_defaults.name);
 
         // If only one of RX or TX sample rates is set, use the same one for both.
@@ -163,48 +463,62 @@ impl SoapyIo {
             // so unwrap is fine here.
             soapycheck!("set RX center frequency",
             dev.set_frequency(soapysdr::Direction::Rx, rx_ch,
-                cli.sdr_rx_freq.unwrap(),
+                rx_converter.sdr_frequency(cli.sdr_rx_freq.unwrap()),
                 soapysdr::Args::new()));
 
-            if let Some(ant) =
-                if let Some(ant) = &cli.sdr_rx_ant
-                    { Some(ant.as_str()) } else { sdr_defaults.rx_ant }
-            {
+            if let Some(ant) = cli.sdr_rx_ant.as_deref().or(sdr_defaults.rx_ant.as_deref()) {
                 soapycheck!("set RX antenna",
                 dev.set_antenna(soapysdr::Direction::Rx, rx_ch, ant));
             }
 
             set_gains(&mut dev, soapysdr::Direction::Rx, rx_ch,
-                &cli.sdr_rx_gain, sdr_defaults.rx_gain)?;
+                &cli.sdr_rx_gain, &sdr_defaults.rx_gain)?;
         }
 
         if tx_enabled {
             soapycheck!("set TX center frequency",
             dev.set_frequency(soapysdr::Direction::Tx, tx_ch,
-                cli.sdr_tx_freq.unwrap(),
+                tx_converter.sdr_frequency(cli.sdr_tx_freq.unwrap()),
                 soapysdr::Args::new()));
 
-            if let Some(ant) =
-                if let Some(ant) = &cli.sdr_tx_ant
-                    { Some(ant.as_str()) } else { sdr_defaults.tx_ant }
-            {
+            if let Some(ant) = cli.sdr_tx_ant.as_deref().or(sdr_defaults.tx_ant.as_deref()) {
                 soapycheck!("set TX antenna",
                 dev.set_antenna(soapysdr::Direction::Tx, tx_ch, ant));
             }
 
             set_gains(&mut dev, soapysdr::Direction::Tx, tx_ch,
-                &cli.sdr_tx_gain, sdr_defaults.tx_gain)?;
+                &cli.sdr_tx_gain, &sdr_defaults.tx_gain)?;
         }
 
+        // By default, make buffer length equal to processing block size,
+        // so that one read or write transfers one block.
         let mut rx = if rx_enabled {
-            Some(soapycheck!("setup RX stream",
-                dev.rx_stream_args(&[rx_ch], convert_args(&cli.rx_args))))
+            check_buffer_length(&sdr_defaults, cli.sdr_rx_buffer_length);
+            let block_size = rx_dsp::block_size(cli,
+                soapycheck!("get RX sample rate", dev.sample_rate(soapysdr::Direction::Rx, rx_ch)));
+            let rx = soapycheck!("setup RX stream",
+                dev.rx_stream_args(&[rx_ch], stream_args(
+                    &sdr_defaults, &sdr_defaults.rx_args, &cli.rx_args,
+                    cli.sdr_rx_buffer_length.unwrap_or(block_size))));
+            if let Ok(mtu) = rx.mtu() {
+                eprintln!("RX stream MTU {} samples, processing block {} samples", mtu, block_size);
+            }
+            Some(rx)
         } else {
             None
         };
         let mut tx = if tx_enabled {
-            Some(soapycheck!("setup TX stream",
-                dev.tx_stream_args(&[tx_ch], convert_args(&cli.tx_args))))
+            check_buffer_length(&sdr_defaults, cli.sdr_tx_buffer_length);
+            let block_size = tx_dsp::block_size(cli,
+                soapycheck!("get TX sample rate", dev.sample_rate(soapysdr::Direction::Tx, tx_ch)));
+            let tx = soapycheck!("setup TX stream",
+                dev.tx_stream_args(&[tx_ch], stream_args(
+                    &sdr_defaults, &sdr_defaults.tx_args, &cli.tx_args,
+                    cli.sdr_tx_buffer_length.unwrap_or(block_size))));
+            if let Ok(mtu) = tx.mtu() {
+                eprintln!("TX stream MTU {} samples, processing block {} samples", mtu, block_size);
+            }
+            Some(tx)
         } else {
             None
         };
@@ -222,13 +536,19 @@ impl SoapyIo {
             dev,
             rx,
             tx,
+            rx_converter,
+            tx_converter,
+            tx_buffer: Vec::new(),
         })
     }
 
     pub fn receive(&mut self, buffer: &mut [StreamType]) -> Result<soapysdr::StreamResult, soapysdr::Error> {
         if let Some(rx) = &mut self.rx {
-            // TODO: implement read_exact and use that
-            rx.read_ext(&mut [buffer], soapysdr::StreamFlags::default(), None, 1000000)
+            // May return fewer samples than the buffer fits.
+            // Caller should check the length from the result.
+            let result = rx.read_ext(&mut [&mut buffer[..]], soapysdr::StreamFlags::default(), None, 1000000)?;
+            self.rx_converter.correct_spectrum(&mut buffer[..result.len]);
+            Ok(result)
         } else {
             Err(soapysdr::Error {
                 code: soapysdr::ErrorCode::StreamError,
@@ -239,7 +559,14 @@ impl SoapyIo {
 
     pub fn transmit(&mut self, buffer: &[StreamType], timestamp: Option<i64>) -> Result<(), soapysdr::Error> {
         if let Some(tx) = &mut self.tx {
-            tx.write_all(&[buffer], timestamp, false, 1000000)
+            if self.tx_converter.inverted {
+                self.tx_buffer.clear();
+                self.tx_buffer.extend_from_slice(buffer);
+                self.tx_converter.correct_spectrum(&mut self.tx_buffer);
+                tx.write_all(&[&self.tx_buffer[..]], timestamp, false, 1000000)
+            } else {
+                tx.write_all(&[buffer], timestamp, false, 1000000)
+            }
         } else {
             Err(soapysdr::Error {
                 code: soapysdr::ErrorCode::StreamError,
@@ -256,12 +583,63 @@ impl SoapyIo {
         self.dev.sample_rate(soapysdr::Direction::Tx, self.tx_ch)
     }
 
+    /// Receive center frequency on air,
+    /// taking external converter into account.
     pub fn rx_center_frequency(&self) -> Result<f64, soapysdr::Error> {
-        self.dev.frequency(soapysdr::Direction::Rx, self.rx_ch)
+        Ok(self.rx_converter.air_frequency(self.dev.frequency(soapysdr::Direction::Rx, self.rx_ch)?))
     }
 
+    /// Transmit center frequency on air,
+    /// taking external converter into account.
     pub fn tx_center_frequency(&self) -> Result<f64, soapysdr::Error> {
-        self.dev.frequency(soapysdr::Direction::Tx, self.tx_ch)
+        Ok(self.tx_converter.air_frequency(self.dev.frequency(soapysdr::Direction::Tx, self.tx_ch)?))
+    }
+
+    pub fn set_rx_center_frequency(&mut self, frequency: f64) -> Result<(), soapysdr::Error> {
+        self.dev.set_frequency(soapysdr::Direction::Rx, self.rx_ch,
+            self.rx_converter.sdr_frequency(frequency), soapysdr::Args::new())
+    }
+
+    pub fn set_rx_antenna(&mut self, name: &str) -> Result<(), soapysdr::Error> {
+        self.dev.set_antenna(soapysdr::Direction::Rx, self.rx_ch, name)
+    }
+
+    pub fn write_setting(&mut self, key: &str, value: &str) -> Result<(), soapysdr::Error> {
+        self.dev.write_setting(key, value)
+    }
+
+    /// Write bits of a GPIO bank selected by mask.
+    pub fn write_gpio(&mut self, bank: &str, value: u32, mask: u32) -> Result<(), soapysdr::Error> {
+        self.dev.write_gpio_masked(bank, value, mask)
+    }
+
+    /// Reader for device sensors and sensors of the channels in use.
+    /// It shares the device handle, so it can be used from another thread
+    /// without blocking streaming.
+    pub fn sensor_reader(&self) -> SensorReader {
+        SensorReader {
+            dev: self.dev.clone(),
+            rx_ch: self.rx.as_ref().map(|_| self.rx_ch),
+            tx_ch: self.tx.as_ref().map(|_| self.tx_ch),
+        }
+    }
+    /// Activate or deactivate the enabled streams.
+    /// While deactivated, the SDR may be able to save power.
+    pub fn set_active(&mut self, active: bool) -> Result<(), soapysdr::Error> {
+        if let Some(rx) = &mut self.rx {
+            if active { rx.activate(None)?; } else { rx.deactivate(None)?; }
+        }
+        if let Some(tx) = &mut self.tx {
+            if active { tx.activate(None)?; } else { tx.deactivate(None)?; }
+        }
+        Ok(())
+    }
+
+    /// Description of the SDR for recording metadata.
+    pub fn hardware(&self) -> String {
+        format!("{} {}",
+            self.dev.driver_key().unwrap_or_default(),
+            self.dev.hardware_key().unwrap_or_default())
     }
 
     pub fn rx_enabled(&self) -> bool {
@@ -279,16 +657,13 @@ fn set_gains(
     dev: &mut soapysdr::Device,
     direction: soapysdr::Direction,
     channel: usize,
-    cli_gains: &Vec<String>,
-    defaults: &[&str]
+    cli_gains: &[String],
+    defaults: &[String]
 ) -> Result<(), soapysdr::Error> {
-    // Clap uses String but that cannot be used in default structs,
-    // so we need some extra conversion here to make them the same type.
-    // Maybe there would be some cleaner way to do it.
-    let gains: &[String] = if cli_gains.len() > 0 {
-        &cli_gains[..]
+    let gains: &[String] = if !cli_gains.is_empty() {
+        cli_gains
     } else {
-        &defaults.iter().map(|g| g.to_string()).collect::<Vec<String>>()[..]
+        defaults
     };
 
     let element_gains = if gains.len() % 2 == 1 {
@@ -322,3 +697,36 @@ fn set_gains(
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_defaults_file() {
+        let overrides = parse_defaults_file(r#"
+            [HackRF]
+            name = "HackRF One"
+            rx_fs = 8e6
+            rx_gain = ["AMP", "0", "LNA", "24"]
+
+            [RTLSDR]
+            rx_gain = ["30.0"]
+        "#).unwrap();
+        let defaults = find_defaults(overrides, "RTLSDR", "");
+        // Fields not in the file come from built-in defaults
+        assert_eq!(defaults.name, "RTL-SDR");
+        assert_eq!(defaults.rx_fs, 2400e3);
+        assert_eq!(defaults.rx_gain, vec!["30.0"]);
+
+        let overrides = parse_defaults_file("[HackRF]\nrx_fs = 8e6\n").unwrap();
+        let defaults = find_defaults(overrides, "hackrf", "HackRF");
+        assert_eq!(defaults.rx_fs, 8e6);
+        assert_eq!(defaults.rx_ant, None);
+
+        let defaults = find_defaults(HashMap::new(), "PlutoSDR", "");
+        assert_eq!(defaults.buffer_length_arg.as_deref(), Some("bufflen"));
+
+        assert!(parse_defaults_file("[HackRF]\nrx_sample_rate = 8e6\n").is_err());
+    }
+}
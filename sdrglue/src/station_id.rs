@@ -0,0 +1,230 @@
+//! Automatic station identification on transmit channels.
+//!
+//! Activity is tracked on every transmit channel, whichever
+//! processor produces it, so beacons, repeaters and other
+//! transmissions are all covered. Once a channel has transmitted,
+//! it is identified on its own frequency within the configured
+//! interval: in the first pause of at least IDLE_GAP after the ID
+//! becomes due, or over the channel output at the end of the
+//! interval if there is no pause. The ID is sent in CW as an
+//! FM modulated tone, or as recorded voice audio if given.
+
+use std::rc::Rc;
+
+use crate::{Sample, ComplexSample};
+use crate::configuration;
+use crate::events;
+use crate::txthings::modulator::FmModulator;
+
+/// Output power in dBFS above which a channel is transmitting.
+const ACTIVE_LEVEL: Sample = -60.0;
+/// How long before the end of the interval an ID becomes due,
+/// in seconds, so that it can be sent in a pause.
+const DUE_WINDOW: f64 = 60.0;
+/// Length of a pause in seconds in which a due ID is sent.
+const IDLE_GAP: f64 = 1.0;
+/// Speed of CW ID in words per minute.
+const CW_WPM: f64 = 20.0;
+const CW_TONE_FREQUENCY: f64 = 800.0;
+/// Level of CW tone relative to full scale audio.
+const CW_LEVEL: Sample = 0.5;
+/// FM deviation in Hertz for full scale ID audio.
+const DEVIATION: f64 = 3000.0;
+/// Sample rate of voice ID audio.
+const VOICE_SAMPLE_RATE: f64 = 48000.0;
+
+/// Morse code for a character, as dots and dashes.
+fn morse(character: char) -> Option<&'static str> {
+    Some(match character.to_ascii_uppercase() {
+        'A' => ".-", 'B' => "-...", 'C' => "-.-.", 'D' => "-..", 'E' => ".",
+        'F' => "..-.", 'G' => "--.", 'H' => "....", 'I' => "..", 'J' => ".---",
+        'K' => "-.-", 'L' => ".-..", 'M' => "--", 'N' => "-.", 'O' => "---",
+        'P' => ".--.", 'Q' => "--.-", 'R' => ".-.", 'S' => "...", 'T' => "-",
+        'U' => "..-", 'V' => "...-", 'W' => ".--", 'X' => "-..-", 'Y' => "-.--",
+        'Z' => "--..", '0' => "-----", '1' => ".----", '2' => "..---",
+        '3' => "...--", '4' => "....-", '5' => ".....", '6' => "-....",
+        '7' => "--...", '8' => "---..", '9' => "----.", '/' => "-..-.",
+        _ => return None,
+    })
+}
+
+/// Keying of CW text with one value per dot length.
+pub fn cw_keying(text: &str) -> Vec<bool> {
+    let mut keying = Vec::new();
+    for word in text.split_whitespace() {
+        if !keying.is_empty() {
+            // Word space is 7 dots, including the letter space already added.
+            keying.extend([false; 4]);
+        }
+        for code in word.chars().filter_map(morse) {
+            for symbol in code.chars() {
+                keying.extend(std::iter::repeat_n(true, if symbol == '-' { 3 } else { 1 }));
+                keying.push(false);
+            }
+            // Letter space is 3 dots, including the symbol space.
+            keying.extend([false; 2]);
+        }
+    }
+    keying
+}
+
+/// Audio sent as the ID.
+enum IdAudio {
+    Cw(Rc<[bool]>),
+    Voice(Rc<[Sample]>),
+}
+
+impl IdAudio {
+    /// Audio sample at given time in seconds from the start,
+    /// or None after the end.
+    fn sample(&self, time: f64) -> Option<Sample> {
+        match self {
+            Self::Cw(keying) => {
+                let dot = 1.2 / CW_WPM;
+                let keyed = *keying.get((time / dot) as usize)?;
+                Some(if keyed {
+                    CW_LEVEL * (2.0 * std::f64::consts::PI * CW_TONE_FREQUENCY * time).sin() as Sample
+                } else { 0.0 })
+            },
+            Self::Voice(audio) => audio.get((time * VOICE_SAMPLE_RATE) as usize).copied(),
+        }
+    }
+}
+
+/// ID being sent on a channel.
+struct Player {
+    fm: FmModulator,
+    /// Time from the start of the ID in seconds.
+    time: f64,
+}
+
+#[derive(Default)]
+struct ChannelState {
+    /// Time since the first transmission after the previous ID,
+    /// or None if there is nothing to identify.
+    unidentified: Option<f64>,
+    /// Time since the channel last transmitted.
+    idle: f64,
+    player: Option<Player>,
+}
+
+pub struct StationId {
+    callsign: String,
+    audio: IdAudio,
+    /// Maximum time between IDs in seconds.
+    interval: f64,
+    channels: Vec<ChannelState>,
+}
+
+/// Read voice ID audio from a file of 16-bit signed
+/// little-endian mono samples.
+fn read_voice(path: &str) -> std::io::Result<Rc<[Sample]>> {
+    let data = std::fs::read(path)?;
+    Ok(data.chunks_exact(2)
+        .map(|bytes| i16::from_le_bytes([bytes[0], bytes[1]]) as Sample / i16::MAX as Sample)
+        .collect())
+}
+
+impl StationId {
+    /// Create the ID manager for given number of transmit channels,
+    /// or return None if --station-id is not given.
+    pub fn from_cli(cli: &configuration::Cli, channels: usize) -> Result<Option<Self>, String> {
+        let Some(callsign) = cli.station_id.clone() else { return Ok(None) };
+        let audio = match &cli.station_id_audio {
+            Some(path) => IdAudio::Voice(read_voice(path)
+                .map_err(|err| format!("Could not read station ID audio {}: {}", path, err))?),
+            None => IdAudio::Cw(cw_keying(&callsign).into()),
+        };
+        Ok(Some(Self::new(callsign, audio, cli.station_id_interval, channels)))
+    }
+
+    fn new(callsign: String, audio: IdAudio, interval: f64, channels: usize) -> Self {
+        Self {
+            callsign,
+            audio,
+            interval,
+            channels: (0..channels).map(|_| ChannelState::default()).collect(),
+        }
+    }
+
+    /// Track activity of a block of channel output and replace it
+    /// with the ID when one is being sent.
+    pub fn process(&mut self, index: usize, frequency: f64, samples: &mut [ComplexSample], sample_rate: f64) {
+        let duration = samples.len() as f64 / sample_rate;
+        let channel = &mut self.channels[index];
+
+        if let Some(player) = &mut channel.player {
+            let mut finished = false;
+            for sample in samples.iter_mut() {
+                *sample = match self.audio.sample(player.time) {
+                    Some(audio) => player.fm.sample(audio),
+                    None => { finished = true; ComplexSample::ZERO },
+                };
+                player.time += 1.0 / sample_rate;
+            }
+            if finished {
+                channel.player = None;
+                channel.unidentified = None;
+                channel.idle = 0.0;
+            }
+            return;
+        }
+
+        let power = samples.iter().map(|s| s.norm_sqr()).sum::<Sample>() / samples.len().max(1) as Sample;
+        if 10.0 * (power + 1e-20).log10() > ACTIVE_LEVEL {
+            channel.unidentified.get_or_insert(0.0);
+            channel.idle = 0.0;
+        } else {
+            channel.idle += duration;
+        }
+        let Some(unidentified) = &mut channel.unidentified else { return };
+        *unidentified += duration;
+        let due = *unidentified >= self.interval - DUE_WINDOW;
+        if (due && channel.idle >= IDLE_GAP) || *unidentified >= self.interval {
+            events::emit(events::Event::new("station_id")
+                .with("channel", format!("tx{}", index))
+                .with("frequency", frequency)
+                .with("callsign", self.callsign.as_str()));
+            channel.player = Some(Player { fm: FmModulator::new(sample_rate, DEVIATION), time: 0.0 });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cw_keying() {
+        // E is a dot, T is a dash
+        assert_eq!(cw_keying("E T"), [
+            true, false, false, false,
+            false, false, false, false,
+            true, true, true, false, false, false,
+        ]);
+    }
+
+    #[test]
+    fn test_station_id() {
+        let mut id = StationId::new("OH2XX".to_string(), IdAudio::Cw(cw_keying("OH2XX").into()), 600.0, 2);
+        let block = |power: Sample| vec![ComplexSample::new(power, 0.0); 480];
+        let sample_rate = 48000.0;
+        // Nothing to identify before transmitting
+        for _ in 0..70000 {
+            let mut samples = block(0.0);
+            id.process(0, 145e6, &mut samples, sample_rate);
+            assert!(samples.iter().all(|s| *s == ComplexSample::ZERO));
+        }
+        // Transmit continuously on channel 1
+        let mut sent_at = None;
+        for n in 0..70000 {
+            let mut samples = block(1.0);
+            id.process(1, 145e6, &mut samples, sample_rate);
+            if sent_at.is_none() && samples.iter().any(|s| *s != ComplexSample::new(1.0, 0.0)) {
+                sent_at = Some(n);
+            }
+        }
+        // Sent over the transmission at the end of the interval
+        assert!(sent_at.is_some_and(|n| (59999..=60001).contains(&n)), "{:?}", sent_at);
+    }
+}
@@ -0,0 +1,193 @@
+//! Control of antenna switches, filters and other external
+//! hardware depending on the band being received.
+//!
+//! Output actions are written as text:
+//! ```text
+//! setting:KEY=VALUE      write a SoapySDR device setting
+//! gpio:BANK=VALUE/MASK   write GPIO bank bits (mask is optional)
+//! antenna:NAME           select receive antenna
+//! exec:COMMAND           run a command using the system shell
+//! ```
+//! Actions can be bound to frequency ranges, in which case they are
+//! applied when the receiver is tuned within the range,
+//! or given a name with --output-action. Named actions can be
+//! applied with the control API "output" command, which cannot
+//! run anything not configured on the command line.
+
+use crate::configuration;
+use crate::events::{self, Event};
+use crate::soapyconfig::SoapyIo;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum OutputAction {
+    Setting { key: String, value: String },
+    Gpio { bank: String, value: u32, mask: u32 },
+    Antenna(String),
+    Exec(String),
+}
+
+/// Parse an integer given in decimal or as 0x-prefixed hexadecimal.
+fn parse_u32(text: &str) -> Option<u32> {
+    match text.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+        None => text.parse().ok(),
+    }
+}
+
+impl OutputAction {
+    pub fn parse(text: &str) -> Option<Self> {
+        let (kind, argument) = text.split_once(':')?;
+        match kind {
+            "setting" => {
+                let (key, value) = argument.split_once('=')?;
+                Some(OutputAction::Setting { key: key.to_string(), value: value.to_string() })
+            },
+            "gpio" => {
+                let (bank, value) = argument.split_once('=')?;
+                let (value, mask) = match value.split_once('/') {
+                    Some((value, mask)) => (parse_u32(value)?, parse_u32(mask)?),
+                    None => (parse_u32(value)?, u32::MAX),
+                };
+                Some(OutputAction::Gpio { bank: bank.to_string(), value, mask })
+            },
+            "antenna" => Some(OutputAction::Antenna(argument.to_string())),
+            "exec" => Some(OutputAction::Exec(argument.to_string())),
+            _ => None,
+        }
+    }
+
+    /// Whether the action may key a transmitter or amplifier,
+    /// so that applying it needs transmitting to be armed.
+    pub fn needs_arming(&self) -> bool {
+        matches!(self, OutputAction::Gpio { .. } | OutputAction::Exec(_))
+    }
+
+    pub fn apply(&self, sdr: &mut SoapyIo) -> Result<(), String> {
+        match self {
+            OutputAction::Setting { key, value } =>
+                sdr.write_setting(key, value).map_err(|e| e.to_string()),
+            OutputAction::Gpio { bank, value, mask } =>
+                sdr.write_gpio(bank, *value, *mask).map_err(|e| e.to_string()),
+            OutputAction::Antenna(name) =>
+                sdr.set_rx_antenna(name).map_err(|e| e.to_string()),
+            OutputAction::Exec(command) => {
+                let status = if cfg!(windows) {
+                    std::process::Command::new("cmd").arg("/C").arg(command).status()
+                } else {
+                    std::process::Command::new("sh").arg("-c").arg(command).status()
+                };
+                match status {
+                    Ok(status) if status.success() => Ok(()),
+                    Ok(status) => Err(format!("Command {} failed: {}", command, status)),
+                    Err(err) => Err(format!("Could not run {}: {}", command, err)),
+                }
+            },
+        }
+    }
+}
+
+/// Parse space-separated output actions.
+fn parse_actions(text: &str) -> Vec<OutputAction> {
+    // TODO: handle errors more nicely
+    text.split_whitespace().map(|action|
+        OutputAction::parse(action).unwrap_or_else(|| panic!("Invalid output action {}", action))
+    ).collect()
+}
+
+struct BandOutput {
+    low: f64,
+    high: f64,
+    actions: Vec<OutputAction>,
+}
+
+/// Output actions bound to receive frequency ranges.
+pub struct BandOutputs {
+    bands: Vec<BandOutput>,
+    /// Index of the band whose actions were applied last.
+    active: Option<usize>,
+}
+
+impl BandOutputs {
+    pub fn from_cli(cli: &configuration::Cli) -> Self {
+        Self {
+            bands: cli.band_output.chunks_exact(2).map(|args| {
+                // TODO: handle errors more nicely
+                let (low, high) = args[0].split_once('-')
+                    .unwrap_or_else(|| panic!("Invalid frequency range {}", args[0]));
+                BandOutput {
+                    low: low.parse().unwrap(),
+                    high: high.parse().unwrap(),
+                    actions: parse_actions(&args[1]),
+                }
+            }).collect(),
+            active: None,
+        }
+    }
+
+    /// Apply actions for the band containing given receive
+    /// center frequency, unless they were already applied.
+    pub fn update(&mut self, sdr: &mut SoapyIo, frequency: f64) {
+        let band = self.bands.iter().position(|band| band.low <= frequency && frequency <= band.high);
+        if band.is_none() || band == self.active {
+            return;
+        }
+        self.active = band;
+        for action in self.bands[band.unwrap()].actions.iter() {
+            if let Err(err) = action.apply(sdr) {
+                eprintln!("Failed to apply output action: {}", err);
+            }
+        }
+        events::emit(Event::new("band_output").with("frequency", frequency));
+    }
+}
+
+/// Output actions given names with --output-action,
+/// so that they can be applied using the control API.
+pub struct NamedOutputs {
+    outputs: Vec<(String, Vec<OutputAction>)>,
+}
+
+impl NamedOutputs {
+    pub fn from_cli(cli: &configuration::Cli) -> Self {
+        Self {
+            outputs: cli.output_action.chunks_exact(2)
+                .map(|args| (args[0].clone(), parse_actions(&args[1])))
+                .collect(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.outputs.is_empty()
+    }
+
+    /// Actions with given name, if configured.
+    pub fn get(&self, name: &str) -> Option<&[OutputAction]> {
+        self.outputs.iter().find(|(output, _)| output == name).map(|(_, actions)| actions.as_slice())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(OutputAction::parse("gpio:MAIN=0x5/0xf"),
+            Some(OutputAction::Gpio { bank: "MAIN".to_string(), value: 5, mask: 15 }));
+        assert_eq!(OutputAction::parse("setting:ANT=2"),
+            Some(OutputAction::Setting { key: "ANT".to_string(), value: "2".to_string() }));
+        assert_eq!(OutputAction::parse("antenna:LNAL"), Some(OutputAction::Antenna("LNAL".to_string())));
+        assert_eq!(OutputAction::parse("gpio:MAIN"), None);
+        assert_eq!(OutputAction::parse("relay:1"), None);
+    }
+
+    #[test]
+    fn test_named_outputs() {
+        let cli = configuration::Cli::parse_from(["sdrglue",
+            "--output-action", "preamp", "gpio:MAIN=0x1/0x1 antenna:LNAH"]);
+        let outputs = NamedOutputs::from_cli(&cli);
+        assert_eq!(outputs.get("preamp").map(<[_]>::len), Some(2));
+        assert_eq!(outputs.get("exec:reboot"), None);
+    }
+}
@@ -0,0 +1,215 @@
+//! Channel templates for configurations with many similar channels.
+//!
+//! A template is a channel option with its arguments,
+//! containing placeholders replaced for each channel using it:
+//! {name} is the channel name, {frequency} the channel frequency
+//! and {index} the number of the channel among --channel entries.
+//! {modulation} is the modulation of channels imported from
+//! other programs, like with --import-chirp or --import-gqrx.
+//! Numbers can be added to the index, like {7300+index},
+//! and {port} is replaced by the next port number
+//! counting from --template-port-base, so that each channel
+//! gets its own port without manual bookkeeping.
+//! Ports of addresses given directly on command line are skipped.
+//! For example:
+//! ```text
+//! --template nbfm 'demodulate-to-udp 127.0.0.1:{port} {frequency} FM'
+//! --channel nbfm ch1 145.5e6 --channel nbfm ch2 145.525e6
+//! ```
+//! Expanded channels can be listed with the control API
+//! "channels" command.
+
+use std::collections::HashSet;
+
+use serde_json::Value;
+
+use crate::configuration;
+use crate::import;
+
+/// Channel added using a template.
+pub struct ExpandedChannel {
+    pub name: String,
+    pub template: String,
+    pub frequency: String,
+    /// Modulation of an imported channel.
+    pub modulation: Option<String>,
+    /// Option the channel was added to.
+    pub option: String,
+    /// Arguments after replacing placeholders.
+    pub arguments: Vec<String>,
+}
+
+/// Evaluate the text inside a placeholder,
+/// which is a sum of numbers and index.
+fn evaluate(expression: &str, index: usize) -> Option<i64> {
+    expression.split('+').try_fold(0i64, |sum, term| match term.trim() {
+        "index" => sum.checked_add(i64::try_from(index).ok()?),
+        number => sum.checked_add(number.parse::<i64>().ok()?),
+    })
+}
+
+/// Port of an argument looking like host:port.
+fn address_port(argument: &str) -> Option<u16> {
+    let (host, port) = argument.rsplit_once(':')?;
+    // Skip times of day like 22:00.
+    if host.is_empty() || host.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    port.parse().ok()
+}
+
+/// Ports of all addresses in option values.
+fn used_ports(value: &Value, ports: &mut HashSet<u16>) {
+    match value {
+        Value::String(argument) => ports.extend(address_port(argument)),
+        Value::Array(values) => values.iter().for_each(|value| used_ports(value, ports)),
+        Value::Object(values) => values.values().for_each(|value| used_ports(value, ports)),
+        _ => {},
+    }
+}
+
+/// Replace placeholders in a template argument.
+fn substitute(
+    argument: &str,
+    channel: &ExpandedChannel,
+    index: usize,
+    next_port: &mut u16,
+    used_ports: &HashSet<u16>,
+) -> Result<String, String> {
+    let mut result = String::new();
+    let mut rest = argument;
+    while let Some(start) = rest.find('{') {
+        let Some(end) = rest[start..].find('}').map(|end| start + end) else {
+            break;
+        };
+        result.push_str(&rest[..start]);
+        let placeholder = &rest[start + 1 .. end];
+        match placeholder {
+            "name" => result.push_str(&channel.name),
+            "frequency" => result.push_str(&channel.frequency),
+            "modulation" => result.push_str(channel.modulation.as_deref()
+                .ok_or_else(|| format!("Template {} uses {{modulation}} for a channel without one", channel.template))?),
+            "port" => {
+                while used_ports.contains(next_port) {
+                    *next_port = next_port.checked_add(1).ok_or("Ran out of template port numbers")?;
+                }
+                result.push_str(&next_port.to_string());
+                *next_port = next_port.checked_add(1).ok_or("Ran out of template port numbers")?;
+            },
+            _ => match evaluate(placeholder, index) {
+                Some(value) => result.push_str(&value.to_string()),
+                None => return Err(format!("Invalid placeholder {{{}}} in template {}", placeholder, channel.template)),
+            },
+        }
+        rest = &rest[end + 1 ..];
+    }
+    result.push_str(rest);
+    Ok(result)
+}
+
+/// Channels to expand, as template name, channel name,
+/// frequency and modulation. Channels given with --channel
+/// come first, followed by imported channels.
+fn channel_list(cli: &configuration::Cli) -> Vec<(String, String, String, Option<String>)> {
+    let mut list: Vec<_> = cli.channel.chunks_exact(3)
+        .map(|args| (args[0].clone(), args[1].clone(), args[2].clone(), None))
+        .collect();
+    let imports = cli.import_chirp.chunks_exact(2).map(|args| (args, import::parse_chirp as fn(&str) -> _))
+        .chain(cli.import_gqrx.chunks_exact(2).map(|args| (args, import::parse_gqrx as fn(&str) -> _)));
+    for (args, parse) in imports {
+        list.extend(import::read(&args[0], parse).into_iter().map(|channel|
+            (args[1].clone(), channel.name, channel.frequency.to_string(), Some(channel.modulation.to_string()))));
+    }
+    list
+}
+
+/// Expand channels given with --channel or imported into the options
+/// given in their templates. Return the expanded channels.
+pub fn expand(cli: &mut configuration::Cli) -> Result<Vec<ExpandedChannel>, String> {
+    let list = channel_list(cli);
+    if list.is_empty() {
+        return Ok(Vec::new());
+    }
+    // Options are modified through JSON values, since
+    // they can be accessed by name there.
+    let mut options = serde_json::to_value(&*cli).unwrap();
+    let mut next_port = cli.template_port_base;
+    let mut ports = HashSet::new();
+    used_ports(&options, &mut ports);
+    let mut channels = Vec::new();
+    for (index, (template_name, name, frequency, modulation)) in list.into_iter().enumerate() {
+        let template = cli.template.chunks_exact(2).find(|template| template[0] == template_name)
+            .ok_or_else(|| format!("Unknown channel template {}", template_name))?;
+        let mut words = template[1].split_whitespace();
+        let option = words.next()
+            .ok_or_else(|| format!("Empty channel template {}", template_name))?
+            .trim_start_matches('-').replace('-', "_");
+        let mut channel = ExpandedChannel {
+            template: template_name,
+            name,
+            frequency,
+            modulation,
+            option,
+            arguments: Vec::new(),
+        };
+        channel.arguments = words.map(|word| substitute(word, &channel, index, &mut next_port, &ports))
+            .collect::<Result<_, _>>()?;
+        ports.extend(channel.arguments.iter().filter_map(|argument| address_port(argument)));
+        let Some(Value::Array(values)) = options.get_mut(&channel.option) else {
+            return Err(format!("Template {} uses {} which is not a channel option", channel.template, channel.option));
+        };
+        values.extend(channel.arguments.iter().cloned().map(Value::String));
+        channels.push(channel);
+    }
+    *cli = serde_json::from_value(options).map_err(|err| format!("Invalid arguments from templates: {}", err))?;
+    Ok(channels)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::configuration::Parser;
+    use crate::fuzz;
+
+    #[test]
+    fn test_expand() {
+        let mut cli = configuration::Cli::parse_from([
+            "sdrglue",
+            "--demodulate-to-udp", "127.0.0.1:7300", "144.8e6", "FM",
+            "--template", "nbfm", "demodulate-to-udp 127.0.0.1:{port} {frequency} FM",
+            "--template", "usb", "demodulate-to-udp 127.0.0.1:{7400+index} {frequency} USB",
+            "--channel", "nbfm", "ch1", "145.5e6",
+            "--channel", "usb", "ch2", "144.3e6",
+            "--channel", "nbfm", "ch3", "145.525e6",
+        ]);
+        let channels = expand(&mut cli).unwrap();
+        assert_eq!(cli.demodulate_to_udp, vec![
+            "127.0.0.1:7300", "144.8e6", "FM",
+            "127.0.0.1:7301", "145.5e6", "FM",
+            "127.0.0.1:7401", "144.3e6", "USB",
+            "127.0.0.1:7302", "145.525e6", "FM",
+        ]);
+        assert_eq!(channels[2].name, "ch3");
+        assert_eq!(channels[2].arguments[0], "127.0.0.1:7302");
+    }
+
+    #[test]
+    fn test_malformed_templates() {
+        let templates = fuzz::inputs(&["demodulate-to-udp 127.0.0.1:{7300+index} {frequency} {modulation}"]);
+        let channels = fuzz::inputs(&["145.5e6"]);
+        for (template, frequency) in templates.iter().zip(channels.iter().cycle()) {
+            let Ok(mut cli) = configuration::Cli::try_parse_from([
+                "sdrglue", "--template", "t", template, "--channel", "t", "ch1", frequency,
+            ]) else {
+                continue;
+            };
+            // Anything may fail, as long as nothing panics.
+            let _ = expand(&mut cli);
+        }
+        let mut cli = configuration::Cli::parse_from([
+            "sdrglue", "--template", "t", "demodulate-to-udp 127.0.0.1:{9223372036854775807+index} {frequency} FM",
+            "--channel", "t", "ch1", "145e6", "--channel", "t", "ch2", "145.1e6",
+        ]);
+        assert!(expand(&mut cli).is_err());
+    }
+}
@@ -0,0 +1,227 @@
+//! Decoder for tone remote control used by dispatch consoles.
+//!
+//! A console keys a base station by sending a high level
+//! guard tone (2175 Hz) for about 120 ms, then a function tone
+//! for about 40 ms selecting what to do, and then a low level
+//! guard tone, about 30 dB below the high level one,
+//! mixed with voice for as long as the transmitter is keyed.
+//! Function tones F1 to F10 go down from 1950 Hz in 100 Hz steps.
+
+use crate::Sample;
+use crate::events;
+
+/// Guard tone frequency in Hertz.
+pub const GUARD_FREQUENCY: f64 = 2175.0;
+/// Function tone frequencies in Hertz, starting from F1.
+pub const FUNCTION_FREQUENCIES: [f64; 10] = [
+    1950.0, 1850.0, 1750.0, 1650.0, 1550.0, 1450.0, 1350.0, 1250.0, 1150.0, 1050.0,
+];
+/// Length of the analysis window in seconds.
+/// Function tones are short, so at least one window has
+/// to fit within a tone, but windows also have to be long enough
+/// to tell tones 100 Hz apart.
+const WINDOW_LENGTH: f64 = 0.02;
+/// Fraction of power in a window that has to be in a tone
+/// for it to be detected as a high level tone.
+const TONE_FRACTION: Sample = 0.5;
+/// Minimum power of high level tones.
+const MIN_POWER: Sample = 1e-4;
+/// Number of windows high level guard tone has to last.
+const HIGH_GUARD_WINDOWS: usize = 4;
+/// Number of windows to wait for a function tone
+/// after the high level guard tone.
+const FUNCTION_TIMEOUT_WINDOWS: usize = 5;
+/// Power of low level guard tone needed to stay keyed,
+/// relative to the high level guard tone.
+const LOW_GUARD_POWER: Sample = 1e-4;
+/// Number of windows without the low level guard tone
+/// after which the transmitter is unkeyed.
+const UNKEY_WINDOWS: usize = 3;
+/// Quality factor of the notch filter removing the guard tone.
+const NOTCH_Q: f64 = 10.0;
+
+enum State {
+    Idle {
+        /// Number of consecutive windows with high level guard tone.
+        guard_windows: usize,
+    },
+    WaitFunction {
+        /// Power of the high level guard tone.
+        guard_power: Sample,
+        /// Number of windows since the guard tone.
+        windows: usize,
+    },
+    Keyed {
+        /// Index of the function tone, 0 for F1.
+        function: usize,
+        guard_power: Sample,
+        /// Number of consecutive windows without the guard tone.
+        missing_windows: usize,
+    },
+}
+
+/// Second order IIR notch filter.
+struct Notch {
+    b: [Sample; 3],
+    a: [Sample; 2],
+    x: [Sample; 2],
+    y: [Sample; 2],
+}
+
+impl Notch {
+    fn new(sample_rate: f64, frequency: f64, q: f64) -> Self {
+        let w0 = std::f64::consts::TAU * frequency / sample_rate;
+        let alpha = w0.sin() / (2.0 * q);
+        let a0 = 1.0 + alpha;
+        Self {
+            b: [(1.0 / a0) as Sample, (-2.0 * w0.cos() / a0) as Sample, (1.0 / a0) as Sample],
+            a: [(-2.0 * w0.cos() / a0) as Sample, ((1.0 - alpha) / a0) as Sample],
+            x: [0.0; 2],
+            y: [0.0; 2],
+        }
+    }
+
+    fn sample(&mut self, input: Sample) -> Sample {
+        let output = self.b[0] * input + self.b[1] * self.x[0] + self.b[2] * self.x[1]
+            - self.a[0] * self.y[0] - self.a[1] * self.y[1];
+        self.x = [input, self.x[0]];
+        self.y = [output, self.y[0]];
+        output
+    }
+}
+
+/// Power of a sinusoid at given frequency in a block of samples,
+/// computed using the Goertzel algorithm.
+fn tone_power(samples: &[Sample], frequency: f64, sample_rate: f64) -> Sample {
+    let coefficient = (2.0 * (std::f64::consts::TAU * frequency / sample_rate).cos()) as Sample;
+    let (mut s1, mut s2): (Sample, Sample) = (0.0, 0.0);
+    for &sample in samples {
+        let s0 = sample + coefficient * s1 - s2;
+        s2 = s1;
+        s1 = s0;
+    }
+    let magnitude_squared = s1 * s1 + s2 * s2 - coefficient * s1 * s2;
+    2.0 * magnitude_squared / (samples.len() * samples.len()) as Sample
+}
+
+pub struct ToneRemoteDecoder {
+    sample_rate: f64,
+    state: State,
+    /// Samples of the current analysis window.
+    window: Vec<Sample>,
+    window_length: usize,
+    notch: Notch,
+}
+
+impl ToneRemoteDecoder {
+    pub fn new(sample_rate: f64) -> Self {
+        let window_length = (WINDOW_LENGTH * sample_rate).round() as usize;
+        Self {
+            sample_rate,
+            state: State::Idle { guard_windows: 0 },
+            window: Vec::with_capacity(window_length),
+            window_length,
+            notch: Notch::new(sample_rate, GUARD_FREQUENCY, NOTCH_Q),
+        }
+    }
+
+    /// Decode a block of console audio
+    /// and remove the guard tone from it.
+    pub fn process(&mut self, audio: &mut [Sample]) {
+        for sample in audio.iter_mut() {
+            self.window.push(*sample);
+            if self.window.len() >= self.window_length {
+                self.analyze_window();
+                self.window.clear();
+            }
+            *sample = self.notch.sample(*sample);
+        }
+    }
+
+    /// Function selected by the console while it keys
+    /// the transmitter, counting from 0 for F1.
+    pub fn keyed_function(&self) -> Option<usize> {
+        match self.state {
+            State::Keyed { function, .. } => Some(function),
+            _ => None,
+        }
+    }
+
+    fn analyze_window(&mut self) {
+        let total_power = self.window.iter().map(|sample| sample * sample).sum::<Sample>()
+            / self.window.len() as Sample;
+        let guard_power = tone_power(&self.window, GUARD_FREQUENCY, self.sample_rate);
+        let high_level = |power: Sample| power >= MIN_POWER && power >= TONE_FRACTION * total_power;
+
+        self.state = match self.state {
+            State::Idle { guard_windows } => {
+                if high_level(guard_power) {
+                    if guard_windows + 1 >= HIGH_GUARD_WINDOWS {
+                        State::WaitFunction { guard_power, windows: 0 }
+                    } else {
+                        State::Idle { guard_windows: guard_windows + 1 }
+                    }
+                } else {
+                    State::Idle { guard_windows: 0 }
+                }
+            },
+            State::WaitFunction { guard_power: high_guard_power, windows } => {
+                let function = FUNCTION_FREQUENCIES.iter()
+                    .position(|&frequency| high_level(tone_power(&self.window, frequency, self.sample_rate)));
+                if let Some(function) = function {
+                    events::emit(events::Event::new("tone_remote_key")
+                        .with("function", function + 1));
+                    State::Keyed { function, guard_power: high_guard_power, missing_windows: 0 }
+                } else if high_level(guard_power) {
+                    // Guard tone is still going on
+                    State::WaitFunction { guard_power: high_guard_power, windows: 0 }
+                } else if windows + 1 >= FUNCTION_TIMEOUT_WINDOWS {
+                    State::Idle { guard_windows: 0 }
+                } else {
+                    State::WaitFunction { guard_power: high_guard_power, windows: windows + 1 }
+                }
+            },
+            State::Keyed { function, guard_power: high_guard_power, missing_windows } => {
+                if guard_power >= LOW_GUARD_POWER * high_guard_power {
+                    State::Keyed { function, guard_power: high_guard_power, missing_windows: 0 }
+                } else if missing_windows + 1 >= UNKEY_WINDOWS {
+                    events::emit(events::Event::new("tone_remote_unkey")
+                        .with("function", function + 1));
+                    State::Idle { guard_windows: 0 }
+                } else {
+                    State::Keyed { function, guard_power: high_guard_power, missing_windows: missing_windows + 1 }
+                }
+            },
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode() {
+        let sample_rate = 48000.0;
+        let mut decoder = ToneRemoteDecoder::new(sample_rate);
+        let mut time = 0.0;
+        let mut tone = |duration: f64, tones: &[(f64, f64)]| {
+            let mut audio: Vec<Sample> = (0..(duration * sample_rate) as usize).map(|i| {
+                let t = time + i as f64 / sample_rate;
+                tones.iter().map(|&(frequency, amplitude)|
+                    amplitude * (std::f64::consts::TAU * frequency * t).sin()).sum::<f64>() as Sample
+            }).collect();
+            time += duration;
+            decoder.process(&mut audio);
+            decoder.keyed_function()
+        };
+        assert_eq!(tone(0.1, &[(1000.0, 0.3)]), None);
+        // High level guard tone and F2
+        assert_eq!(tone(0.12, &[(GUARD_FREQUENCY, 0.5)]), None);
+        assert_eq!(tone(0.04, &[(FUNCTION_FREQUENCIES[1], 0.5)]), Some(1));
+        // Low level guard tone with voice
+        assert_eq!(tone(0.5, &[(GUARD_FREQUENCY, 0.016), (800.0, 0.3)]), Some(1));
+        // Voice without guard tone unkeys
+        assert_eq!(tone(0.2, &[(800.0, 0.3)]), None);
+    }
+}
@@ -1,8 +1,17 @@
 
-use rustfft;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use serde_json::json;
 use crate::{Sample, ComplexSample};
 use crate::configuration;
 use crate::fcfb;
+use crate::graph;
+use crate::interlock::Interlock;
+use crate::rx_dsp::parse_number;
+use crate::sigmf;
+use crate::station_id::StationId;
+use crate::tx_limits::TxLimits;
 use crate::txthings;
 
 
@@ -11,33 +20,66 @@ struct TxChannel {
     processor: Box<dyn txthings::TxChannelProcessor>,
     /// Buffer to transfer samples from channel processor to filter bank.
     buffer: fcfb::InputBuffer,
+    /// Disabled channels are not processed.
+    enabled: bool,
+    /// Center frequency of the channel in transmitted signal.
+    /// Differs from frequency of the processor
+    /// if channel has its own converter offset.
+    center_frequency: f64,
+    /// Limits on transmission time, if any.
+    limits: Option<TxLimits>,
 }
 
 impl TxChannel {
     fn new(
         fft_planner: &mut rustfft::FftPlanner<Sample>,
         synth_params: fcfb::SynthesisOutputParameters,
+        lo_offset: f64,
         processor: Box<dyn txthings::TxChannelProcessor>,
     ) -> Self {
+        let center_frequency = processor.output_center_frequency() - lo_offset;
         let fcfb_input = fcfb::SynthesisInputProcessor::new_with_frequency(
             fft_planner,
             synth_params,
             processor.output_sample_rate(),
-            processor.output_center_frequency(),
+            center_frequency,
         );
         let buffer = fcfb_input.make_input_buffer();
         Self {
             synth_input: fcfb_input,
             processor,
             buffer,
+            enabled: true,
+            center_frequency,
+            limits: None,
         }
     }
 
+    /// Process a block and add it to the synthesis bank,
+    /// unless transmission limits or the interlock block it.
     fn process(
         &mut self,
         synth: &mut fcfb::SynthesisOutputProcessor,
+        index: usize,
+        interlock: Option<&mut Interlock>,
+        station_id: Option<&mut StationId>,
     ) {
         self.processor.process(self.buffer.prepare_for_new_samples());
+        if let Some(station_id) = station_id {
+            station_id.process(index, self.processor.output_center_frequency(),
+                self.buffer.new_samples_mut(), self.processor.output_sample_rate());
+        }
+        if let Some(limits) = &mut self.limits {
+            let samples = self.buffer.new_samples();
+            if !limits.permits(samples, samples.len() as f64 / self.processor.output_sample_rate()) {
+                return;
+            }
+        }
+        if let Some(interlock) = interlock {
+            if !interlock.permits(index, self.processor.output_center_frequency(), self.buffer.new_samples()) {
+                return;
+            }
+        }
         synth.add(self.synth_input.process(self.buffer.buffer()));
     }
 }
@@ -50,6 +92,69 @@ pub struct TxDsp {
     synth_bank: fcfb::SynthesisOutputProcessor,
     /// Transmit channel processors.
     processors: Vec<TxChannel>,
+    /// Safety interlock, if in use.
+    interlock: Option<Interlock>,
+    /// Station identification, if in use.
+    station_id: Option<StationId>,
+}
+
+/// Output sample rate of all current transmit channel processors.
+pub const CHANNEL_SAMPLE_RATE: f64 = 48000.0;
+
+/// Number of samples produced in each processing block
+/// for given transmit sample rate.
+pub fn block_size(cli: &configuration::Cli, sdr_tx_sample_rate: f64) -> usize {
+    // 50% overlap
+    (sdr_tx_sample_rate / cli.tx_bin_spacing).round() as usize / 2
+}
+
+/// Center frequencies of transmit channels given on command line,
+/// in the order channels are created.
+/// Returns an error if a frequency is invalid.
+pub fn channel_frequencies(cli: &configuration::Cli) -> Result<Vec<f64>, String> {
+    let mut frequencies: Vec<f64> = Vec::new();
+    for args in cli.usrp_gateway.chunks_exact(5) {
+        frequencies.push(parse_number(&args[3])?);
+    }
+    for args in cli.ber_test_tx.chunks_exact(3) {
+        frequencies.push(parse_number(&args[0])?);
+    }
+    for frequency in cli.response_test_tx.iter() {
+        frequencies.push(parse_number(frequency)?);
+    }
+    for args in cli.modulate_from_pipewire.chunks_exact(2) {
+        frequencies.push(parse_number(&args[1])?);
+    }
+    #[cfg(feature = "jack")]
+    for args in cli.modulate_from_jack.chunks_exact(2) {
+        frequencies.push(parse_number(&args[1])?);
+    }
+    for args in cli.tone_remote.chunks_exact(2) {
+        for frequency in args[1].split(',') {
+            frequencies.push(parse_number(frequency)?);
+        }
+    }
+    for args in cli.vote_to_tx.chunks_exact(4) {
+        frequencies.push(parse_number(&args[0])?);
+    }
+    for (index, frequency) in frequencies.iter_mut().enumerate() {
+        *frequency -= channel_lo_offset(cli, index)?;
+    }
+    Ok(frequencies)
+}
+
+/// Center frequencies and sample rates of transmit channels
+/// given on command line, in the order channels are created.
+/// Channels are checked on startup, so invalid ones are left out.
+pub fn channel_bands(cli: &configuration::Cli) -> Vec<(f64, f64)> {
+    channel_frequencies(cli).unwrap_or_default().into_iter()
+        .map(|frequency| (frequency, CHANNEL_SAMPLE_RATE)).collect()
+}
+
+/// Find converter offset given for a transmit channel.
+fn channel_lo_offset(cli: &configuration::Cli, index: usize) -> Result<f64, String> {
+    let name = format!("tx{}", index);
+    cli.channel_lo_offset.chunks_exact(2).find(|args| args[0] == name).map_or(Ok(0.0), |args| parse_number(&args[1]))
 }
 
 impl TxDsp {
@@ -58,7 +163,7 @@ impl TxDsp {
         cli: &configuration::Cli,
         sdr_tx_sample_rate: f64,
         sdr_tx_center_frequency: f64,
-    ) -> Self {
+    ) -> Result<Self, String> {
         let bin_spacing = cli.tx_bin_spacing;
 
         let synth_params = fcfb::SynthesisOutputParameters {
@@ -72,16 +177,233 @@ impl TxDsp {
             synth_params,
             synth_bank,
             processors: Vec::new(),
+            interlock: None,
+            station_id: None,
         };
-        self_
+        self_.add_processors_from_cli(fft_planner, cli)?;
+        let frequencies: Vec<f64> = self_.processors.iter()
+            .map(|channel| channel.processor.output_center_frequency()).collect();
+        self_.interlock = Interlock::from_cli(cli, &frequencies)?;
+        self_.station_id = StationId::from_cli(cli, frequencies.len())?;
+        for (index, channel) in self_.processors.iter_mut().enumerate() {
+            channel.limits = TxLimits::from_cli(cli, index, frequencies[index])?;
+        }
+        let out_of_span: Vec<String> = (0..self_.processors.len())
+            .filter(|&index| !self_.check_span(index))
+            .map(|index| format!("tx{}", index)).collect();
+        if !out_of_span.is_empty() {
+            return Err(format!("Transmit channels outside of SDR span: {}", out_of_span.join(", ")));
+        }
+        for name in cli.disabled_channels.iter() {
+            if let Some(index) = name.strip_prefix("tx").and_then(|index| index.parse().ok()) {
+                self_.set_channel_enabled(index, false);
+            }
+        }
+        Ok(self_)
+    }
+
+    fn add_processors_from_cli(
+        &mut self,
+        fft_planner: &mut rustfft::FftPlanner<Sample>,
+        cli: &configuration::Cli
+    ) -> Result<(), String> {
+        for args in cli.usrp_gateway.chunks_exact(5) {
+            self.processors.push(TxChannel::new(
+                fft_planner,
+                self.synth_params,
+                channel_lo_offset(cli, self.processors.len())?,
+                Box::new(txthings::UsrpGatewayTx::new(&txthings::UsrpGatewayTxParameters {
+                    center_frequency: parse_number(&args[3])?,
+                    address: args[0].as_str(),
+                })),
+            ));
+        }
+        for args in cli.ber_test_tx.chunks_exact(3) {
+            self.processors.push(TxChannel::new(
+                fft_planner,
+                self.synth_params,
+                channel_lo_offset(cli, self.processors.len())?,
+                Box::new(txthings::BerTestTx::new(&txthings::BerTestParameters {
+                    center_frequency: parse_number(&args[0])?,
+                    symbol_rate: parse_number(&args[1])?,
+                    deviation: parse_number(&args[2])?,
+                })),
+            ));
+        }
+        for frequency in cli.response_test_tx.iter() {
+            self.processors.push(TxChannel::new(
+                fft_planner,
+                self.synth_params,
+                channel_lo_offset(cli, self.processors.len())?,
+                Box::new(txthings::ResponseTestTx::new(parse_number(frequency)?)),
+            ));
+        }
+        for args in cli.modulate_from_pipewire.chunks_exact(2) {
+            self.processors.push(TxChannel::new(
+                fft_planner,
+                self.synth_params,
+                channel_lo_offset(cli, self.processors.len())?,
+                Box::new(txthings::ModulateFromPipewire::new(&args[0], parse_number(&args[1])?)),
+            ));
+        }
+        #[cfg(feature = "jack")]
+        for args in cli.modulate_from_jack.chunks_exact(2) {
+            self.processors.push(TxChannel::new(
+                fft_planner,
+                self.synth_params,
+                channel_lo_offset(cli, self.processors.len())?,
+                Box::new(txthings::ModulateFromJack::new(&args[0], parse_number(&args[1])?)),
+            ));
+        }
+        for args in cli.tone_remote.chunks_exact(2) {
+            let frequencies: Vec<f64> = args[1].split(',').map(parse_number).collect::<Result<_, _>>()?;
+            let remote = Rc::new(RefCell::new(txthings::ToneRemote::new(args[0].as_str())));
+            for (function, &frequency) in frequencies.iter().enumerate() {
+                self.processors.push(TxChannel::new(
+                    fft_planner,
+                    self.synth_params,
+                    channel_lo_offset(cli, self.processors.len())?,
+                    Box::new(txthings::ToneRemoteTx::new(remote.clone(), function, frequency)),
+                ));
+            }
+        }
+        for (repeater, args) in cli.vote_to_tx.chunks_exact(4).enumerate() {
+            self.processors.push(TxChannel::new(
+                fft_planner,
+                self.synth_params,
+                channel_lo_offset(cli, self.processors.len())?,
+                Box::new(txthings::RepeaterTx::new(txthings::repeater_audio(repeater), parse_number(&args[0])?)),
+            ));
+        }
+        Ok(())
     }
 
     pub fn process(
         &mut self,
     ) -> &[ComplexSample] {
-        for processor in self.processors.iter_mut() {
-            processor.process(&mut self.synth_bank);
+        for (index, processor) in self.processors.iter_mut().enumerate().filter(|(_, p)| p.enabled) {
+            processor.process(&mut self.synth_bank, index, self.interlock.as_mut(), self.station_id.as_mut());
         }
         self.synth_bank.process()
     }
+
+    /// Check that a channel fits within the transmitted span.
+    /// Print an error and return false if it does not.
+    fn check_span(&self, index: usize) -> bool {
+        let channel = &self.processors[index];
+        let sample_rate = channel.processor.output_sample_rate();
+        let frequency = channel.center_frequency;
+        let fits = fcfb::channel_fits(
+            self.synth_params.sample_rate, self.synth_params.center_frequency,
+            sample_rate, frequency);
+        if !fits {
+            eprintln!("Transmit channel tx{} at {} Hz with bandwidth {} Hz \
+                is outside of SDR span {} Hz to {} Hz",
+                index, frequency, sample_rate,
+                self.synth_params.center_frequency - self.synth_params.sample_rate / 2.0,
+                self.synth_params.center_frequency + self.synth_params.sample_rate / 2.0);
+        }
+        fits
+    }
+
+    /// Describe channels for recording metadata.
+    pub fn channel_info(&self) -> Vec<sigmf::ChannelInfo> {
+        self.processors.iter().enumerate().map(|(index, channel)| sigmf::ChannelInfo {
+            name: format!("tx{}", index),
+            center_frequency: channel.center_frequency,
+            sample_rate: channel.processor.output_sample_rate(),
+            modulation: channel.processor.modulation(),
+            enabled: channel.enabled,
+        }).collect()
+    }
+
+    /// Add transmit processing to a graph.
+    pub fn add_to_graph(&self, graph: &mut graph::Graph) {
+        graph.node("sdr_tx", "sdr", json!({
+            "sample_rate": self.synth_params.sample_rate,
+            "center_frequency": self.synth_params.center_frequency,
+        }));
+        graph.node("tx_bank", "synthesis_filter_bank", json!({
+            "ifft_size": self.synth_params.ifft_size,
+            "bin_spacing": self.synth_params.sample_rate / self.synth_params.ifft_size as f64,
+        }));
+        graph.edge("tx_bank", "sdr_tx");
+        for (index, channel) in self.processors.iter().enumerate() {
+            let id = format!("tx{}", index);
+            graph.node(id.clone(), "channel", json!({
+                "frequency": channel.center_frequency,
+                "sample_rate": channel.processor.output_sample_rate(),
+                "modulation": channel.processor.modulation(),
+                "enabled": channel.enabled,
+            }));
+            graph.edge(id, "tx_bank");
+        }
+    }
+
+    /// Enabled state of each channel.
+    pub fn channels_enabled(&self) -> impl Iterator<Item = bool> + '_ {
+        self.processors.iter().map(|channel| channel.enabled)
+    }
+
+    /// Safety interlock, if in use.
+    pub fn interlock_mut(&mut self) -> Option<&mut Interlock> {
+        self.interlock.as_mut()
+    }
+
+    /// Enable or disable a channel by its index.
+    /// Return false if there is no such channel.
+    pub fn set_channel_enabled(&mut self, index: usize, enabled: bool) -> bool {
+        match self.processors.get_mut(index) {
+            Some(channel) => { channel.enabled = enabled; true },
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+    use super::*;
+    use crate::fuzz;
+
+    #[test]
+    fn test_malformed_channels() {
+        let new = |args: &[&str]| {
+            let cli = configuration::Cli::parse_from([&["sdrglue"], args].concat());
+            TxDsp::new(&mut rustfft::FftPlanner::new(), &cli, 1e6, 145.2e6).err()
+        };
+        assert_eq!(new(&["--ber-test-tx", "145.5e6", "1200", "1000"]), None);
+        assert_eq!(new(&["--ber-test-tx", "145.5e6", "1200", "1kHz"]),
+            Some("Invalid number 1kHz".to_string()));
+        assert_eq!(new(&["--usrp-gateway", "0.0.0.0:0", "127.0.0.1:0", "145.6e6", "145.0MHz", "-60"]),
+            Some("Invalid number 145.0MHz".to_string()));
+        assert_eq!(new(&["--tone-remote", "0.0.0.0:0", "145.5e6,,145.525e6"]),
+            Some("Invalid number ".to_string()));
+        assert_eq!(new(&["--ber-test-tx", "145.5e6", "1200", "1000", "--channel-lo-offset", "tx0", "x"]),
+            Some("Invalid number x".to_string()));
+        assert_eq!(new(&["--ber-test-tx", "145.5e6", "1200", "1000", "--tx-timeout", "tx0", "3min"]),
+            Some("Invalid number 3min".to_string()));
+        assert_eq!(new(&["--ber-test-tx", "145.5e6", "1200", "1000", "--tx-interlock", "--tx-allow", "tx0", "bad"]),
+            Some("Invalid allowed range bad".to_string()));
+        assert_eq!(new(&["--ber-test-tx", "145.5e6", "1200", "1000", "--tx-interlock", "--tx-arm-timeout", "10"]),
+            Some("--tx-arm-timeout requires --tx-arm-key".to_string()));
+        assert_eq!(new(&["--ber-test-tx", "145.5e6", "1200", "1000", "--response-test-tx", "144e6", "146e6"]),
+            Some("Transmit channels outside of SDR span: tx1, tx2".to_string()));
+
+        for input in fuzz::inputs(&["145.5e6"]).iter() {
+            let Ok(cli) = configuration::Cli::try_parse_from(["sdrglue", "--ber-test-tx", input, "1200", "1000",
+                "--response-test-tx", input, "--channel-lo-offset", "tx1", input]) else {
+                continue;
+            };
+            // Anything may fail, as long as nothing panics.
+            let _ = TxDsp::new(&mut rustfft::FftPlanner::new(), &cli, 1e6, 145.2e6);
+        }
+        for input in fuzz::inputs(&["144e6:146e6:-10,430e6:440e6"]).iter() {
+            let Ok(cli) = configuration::Cli::try_parse_from(["sdrglue", "--ber-test-tx", "145.5e6", "1200", "1000",
+                "--tx-interlock", "--tx-allow", "tx0", input, "--tx-timeout", "tx0", input, "--tx-duty-cycle", "tx0", input]) else {
+                continue;
+            };
+            let _ = TxDsp::new(&mut rustfft::FftPlanner::new(), &cli, 1e6, 145.2e6);
+        }
+    }
 }
@@ -0,0 +1,153 @@
+//! Limits on transmission time of transmit channels.
+//!
+//! A channel is considered to be transmitting while its output
+//! power is above ACTIVE_LEVEL. Time is counted from the number
+//! of processed samples, so it follows the SDR sample clock.
+//!
+//! A time-out cuts off a transmission that has lasted too long.
+//! The channel stays cut off until its output goes quiet,
+//! like releasing PTT on a radio with a time-out timer.
+//! A duty cycle limit cuts off the channel while it has
+//! transmitted for too large a fraction of the past hour.
+
+use std::collections::VecDeque;
+
+use crate::{Sample, ComplexSample};
+use crate::configuration;
+use crate::events;
+use crate::rx_dsp::parse_number;
+
+/// Output power in dBFS above which a channel is transmitting.
+const ACTIVE_LEVEL: Sample = -60.0;
+/// Length of the duty cycle window in seconds.
+const DUTY_CYCLE_WINDOW: f64 = 3600.0;
+/// Duty cycle is tracked in intervals of this many seconds.
+const DUTY_CYCLE_INTERVAL: f64 = 60.0;
+
+pub struct TxLimits {
+    /// Channel name and frequency, used in events.
+    name: String,
+    frequency: f64,
+    /// Maximum continuous transmission in seconds.
+    timeout: Option<f64>,
+    /// Maximum fraction of time transmitting within the window.
+    duty_cycle: Option<f64>,
+    /// Length of current transmission in seconds.
+    continuous: f64,
+    timed_out: bool,
+    /// Transmitted time in each interval of the window,
+    /// the latest interval being last.
+    intervals: VecDeque<f64>,
+    /// Elapsed time in the latest interval.
+    interval_time: f64,
+    duty_cycle_exceeded: bool,
+}
+
+/// Find a limit given as channel name and value pairs.
+fn channel_limit(values: &[String], name: &str) -> Result<Option<f64>, String> {
+    values.chunks_exact(2).find(|args| args[0] == name)
+        .map(|args| parse_number(&args[1])).transpose()
+}
+
+impl TxLimits {
+    /// Create limits for a transmit channel,
+    /// or return None if it has no limits.
+    pub fn from_cli(cli: &configuration::Cli, index: usize, frequency: f64) -> Result<Option<Self>, String> {
+        let name = format!("tx{}", index);
+        let timeout = channel_limit(&cli.tx_timeout, &name)?;
+        let duty_cycle = channel_limit(&cli.tx_duty_cycle, &name)?.map(|percent| percent / 100.0);
+        if timeout.is_none() && duty_cycle.is_none() {
+            return Ok(None);
+        }
+        Ok(Some(Self::new(name, frequency, timeout, duty_cycle)))
+    }
+
+    pub fn new(name: String, frequency: f64, timeout: Option<f64>, duty_cycle: Option<f64>) -> Self {
+        Self {
+            name,
+            frequency,
+            timeout,
+            duty_cycle,
+            continuous: 0.0,
+            timed_out: false,
+            intervals: VecDeque::from([0.0]),
+            interval_time: 0.0,
+            duty_cycle_exceeded: false,
+        }
+    }
+
+    fn emit(&self, kind: &'static str) {
+        events::emit(events::Event::new(kind)
+            .with("channel", self.name.as_str())
+            .with("frequency", self.frequency));
+    }
+
+    /// Update with a block of channel output lasting given time
+    /// in seconds and return whether it may be transmitted.
+    pub fn permits(&mut self, samples: &[ComplexSample], duration: f64) -> bool {
+        let power = samples.iter().map(|s| s.norm_sqr()).sum::<Sample>() / samples.len().max(1) as Sample;
+        let active = 10.0 * (power + 1e-20).log10() > ACTIVE_LEVEL;
+
+        if active {
+            self.continuous += duration;
+        } else {
+            self.continuous = 0.0;
+            if self.timed_out {
+                self.timed_out = false;
+                self.emit("tx_timeout_reset");
+            }
+        }
+        if self.timeout.is_some_and(|timeout| self.continuous > timeout) && !self.timed_out {
+            self.timed_out = true;
+            self.emit("tx_timeout");
+        }
+
+        if let Some(duty_cycle) = self.duty_cycle {
+            let used: f64 = self.intervals.iter().sum();
+            let exceeded = used >= duty_cycle * DUTY_CYCLE_WINDOW;
+            if exceeded != self.duty_cycle_exceeded {
+                self.duty_cycle_exceeded = exceeded;
+                self.emit(if exceeded { "tx_duty_cycle_exceeded" } else { "tx_duty_cycle_ok" });
+            }
+        }
+
+        let permitted = !self.timed_out && !self.duty_cycle_exceeded;
+        if active && permitted {
+            *self.intervals.back_mut().unwrap() += duration;
+        }
+        self.interval_time += duration;
+        if self.interval_time >= DUTY_CYCLE_INTERVAL {
+            self.interval_time -= DUTY_CYCLE_INTERVAL;
+            self.intervals.push_back(0.0);
+            if self.intervals.len() as f64 > DUTY_CYCLE_WINDOW / DUTY_CYCLE_INTERVAL {
+                self.intervals.pop_front();
+            }
+        }
+        permitted
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_limits() {
+        let active = [ComplexSample::new(0.5, 0.0); 10];
+        let quiet = [ComplexSample::ZERO; 10];
+        // Cut off after 3 minutes, until output goes quiet
+        let mut limits = TxLimits::new("tx0".to_string(), 145e6, Some(180.0), None);
+        assert!((0..180).all(|_| limits.permits(&active, 1.0)));
+        assert!(!limits.permits(&active, 1.0));
+        assert!(!limits.permits(&active, 1.0));
+        assert!(limits.permits(&quiet, 1.0));
+        assert!(limits.permits(&active, 1.0));
+
+        // 10 % duty cycle allows 6 minutes per hour
+        let mut limits = TxLimits::new("tx0".to_string(), 145e6, None, Some(0.1));
+        let transmitted = (0..3600).filter(|_| limits.permits(&active, 1.0)).count();
+        assert!((360..=361).contains(&transmitted));
+        // Allowed again after the transmissions leave the window
+        assert!((0..3600).any(|_| limits.permits(&active, 1.0)));
+    }
+}
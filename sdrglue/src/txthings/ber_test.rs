@@ -0,0 +1,55 @@
+//! Transmitter for bit error rate testing.
+//!
+//! Continuously transmits a pseudorandom bit sequence
+//! using binary FSK, to be received by rxthings::BerTestRx.
+
+use super::TxChannelProcessor;
+use super::modulator::FskModulator;
+use crate::ComplexSample;
+use crate::prbs::Prbs;
+
+const SAMPLE_RATE: f64 = 48000.0;
+
+pub struct BerTestTx {
+    center_frequency: f64,
+    modulator: FskModulator,
+    prbs: Prbs,
+}
+
+pub struct BerTestParameters {
+    pub center_frequency: f64,
+    /// Symbol rate in bauds.
+    pub symbol_rate: f64,
+    /// FSK deviation in Hertz.
+    pub deviation: f64,
+}
+
+impl BerTestTx {
+    pub fn new(parameters: &BerTestParameters) -> Self {
+        Self {
+            center_frequency: parameters.center_frequency,
+            modulator: FskModulator::new(SAMPLE_RATE, parameters.symbol_rate, parameters.deviation),
+            prbs: Prbs::new(),
+        }
+    }
+}
+
+impl TxChannelProcessor for BerTestTx {
+    fn process(&mut self, samples: &mut [ComplexSample]) {
+        for sample in samples.iter_mut() {
+            *sample = self.modulator.sample(|| self.prbs.next_bit());
+        }
+    }
+
+    fn output_sample_rate(&self) -> f64 {
+        SAMPLE_RATE
+    }
+
+    fn output_center_frequency(&self) -> f64 {
+        self.center_frequency
+    }
+
+    fn modulation(&self) -> &'static str {
+        "FSK"
+    }
+}
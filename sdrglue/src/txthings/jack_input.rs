@@ -0,0 +1,51 @@
+//! FM transmitter with a JACK input port.
+//! Transmission is voice operated.
+
+use super::TxChannelProcessor;
+use super::modulator::VoxFmModulator;
+use crate::ComplexSample;
+use crate::jack;
+
+const SAMPLE_RATE: f64 = jack::SAMPLE_RATE;
+/// Peak deviation of FM transmitter in Hertz.
+const FM_DEVIATION: f64 = 2500.0;
+
+pub struct ModulateFromJack {
+    center_frequency: f64,
+    modulator: VoxFmModulator,
+    /// Audio written by the JACK process callback.
+    fifo: jack::AudioFifo,
+}
+
+impl ModulateFromJack {
+    /// Create a transmitter with given input port name and frequency.
+    pub fn new(port: &str, center_frequency: f64) -> Self {
+        Self {
+            center_frequency,
+            modulator: VoxFmModulator::new(SAMPLE_RATE, FM_DEVIATION),
+            fifo: jack::input_port(port),
+        }
+    }
+}
+
+impl TxChannelProcessor for ModulateFromJack {
+    fn process(&mut self, samples: &mut [ComplexSample]) {
+        let mut fifo = self.fifo.lock().unwrap();
+        for sample in samples.iter_mut() {
+            // Fill gaps in audio with silence.
+            *sample = self.modulator.sample(fifo.pop_front().unwrap_or(0.0));
+        }
+    }
+
+    fn output_sample_rate(&self) -> f64 {
+        SAMPLE_RATE
+    }
+
+    fn output_center_frequency(&self) -> f64 {
+        self.center_frequency
+    }
+
+    fn modulation(&self) -> &'static str {
+        "FM"
+    }
+}
@@ -2,6 +2,24 @@
 
 use crate::ComplexSample;
 
+pub mod modulator;
+pub mod usrp_gateway;
+pub use usrp_gateway::*;
+pub mod ber_test;
+pub use ber_test::*;
+pub mod response_test;
+pub use response_test::*;
+pub mod pipewire_input;
+pub use pipewire_input::*;
+pub mod tone_remote;
+pub use tone_remote::*;
+pub mod repeater;
+pub use repeater::*;
+#[cfg(feature = "jack")]
+pub mod jack_input;
+#[cfg(feature = "jack")]
+pub use jack_input::*;
+
 pub trait TxChannelProcessor {
     /// Produce a block of transmit samples.
     /// The function should always fill the whole buffer
@@ -13,4 +31,7 @@ pub trait TxChannelProcessor {
 
     /// Return output center frequency in Hertz.
     fn output_center_frequency(&self) -> f64;
+
+    /// Return name of the modulation, used in recording metadata.
+    fn modulation(&self) -> &'static str;
 }
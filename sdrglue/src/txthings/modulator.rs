@@ -0,0 +1,105 @@
+//! Modulators for transmit channel processors.
+
+use crate::{Sample, ComplexSample, sample_consts};
+
+/// Frequency modulator.
+pub struct FmModulator {
+    /// Phase accumulator.
+    phase: Sample,
+    /// Phase change per sample for full scale input,
+    /// in radians.
+    deviation: Sample,
+}
+
+impl FmModulator {
+    /// Create a frequency modulator with given peak deviation
+    /// in Hertz for a full scale (1.0) input.
+    pub fn new(sample_rate: f64, deviation: f64) -> Self {
+        Self {
+            phase: 0.0,
+            deviation: (std::f64::consts::PI * 2.0 * deviation / sample_rate) as Sample,
+        }
+    }
+
+    pub fn sample(&mut self, input: Sample) -> ComplexSample {
+        self.phase = (self.phase + input * self.deviation).rem_euclid(sample_consts::PI * 2.0);
+        ComplexSample { re: self.phase.cos(), im: self.phase.sin() }
+    }
+}
+
+/// Continuous-phase binary frequency shift keying modulator.
+pub struct FskModulator {
+    fm: FmModulator,
+    /// Symbol clock phase, from 0 to 1.
+    clock: f64,
+    /// Clock phase increment per sample.
+    clock_increment: f64,
+    /// Current symbol as frequency, +1.0 or -1.0.
+    symbol: Sample,
+}
+
+impl FskModulator {
+    /// Create a modulator with given symbol rate and deviation in Hertz.
+    /// A one bit is sent at the positive frequency.
+    pub fn new(sample_rate: f64, symbol_rate: f64, deviation: f64) -> Self {
+        Self {
+            fm: FmModulator::new(sample_rate, deviation),
+            // Start at the end of a symbol so that the
+            // first sample begins a new one.
+            clock: 1.0,
+            clock_increment: symbol_rate / sample_rate,
+            symbol: 0.0,
+        }
+    }
+
+    /// Produce a sample. next_bit is called whenever a new symbol begins.
+    pub fn sample(&mut self, mut next_bit: impl FnMut() -> bool) -> ComplexSample {
+        if self.clock >= 1.0 {
+            self.clock -= 1.0;
+            self.symbol = if next_bit() { 1.0 } else { -1.0 };
+        }
+        self.clock += self.clock_increment;
+        self.fm.sample(self.symbol)
+    }
+}
+
+/// Audio level keying VoxFmModulator, relative to full scale.
+const VOX_THRESHOLD: Sample = 0.01;
+/// Time to keep transmitting after audio goes below threshold,
+/// in seconds.
+const VOX_HANG_TIME: f64 = 1.0;
+
+/// Frequency modulator keyed by the audio itself:
+/// output is on while the audio exceeds a threshold
+/// and for a while after that.
+pub struct VoxFmModulator {
+    fm: FmModulator,
+    /// VOX_HANG_TIME in samples.
+    hang: usize,
+    /// Number of samples since audio exceeded the threshold.
+    samples_since_audio: usize,
+}
+
+impl VoxFmModulator {
+    pub fn new(sample_rate: f64, deviation: f64) -> Self {
+        let hang = (VOX_HANG_TIME * sample_rate) as usize;
+        Self {
+            fm: FmModulator::new(sample_rate, deviation),
+            hang,
+            samples_since_audio: hang,
+        }
+    }
+
+    pub fn sample(&mut self, input: Sample) -> ComplexSample {
+        if input.abs() > VOX_THRESHOLD {
+            self.samples_since_audio = 0;
+        } else {
+            self.samples_since_audio = self.samples_since_audio.saturating_add(1);
+        }
+        if self.samples_since_audio < self.hang {
+            self.fm.sample(input)
+        } else {
+            ComplexSample::ZERO
+        }
+    }
+}
@@ -0,0 +1,96 @@
+//! FM transmitter with a PipeWire sink as input,
+//! so applications can transmit by playing audio
+//! like to any sound card.
+//! Transmission is voice operated.
+
+use std::collections::VecDeque;
+use std::io::Read;
+use std::sync::{Arc, Mutex};
+
+use super::TxChannelProcessor;
+use super::modulator::VoxFmModulator;
+use crate::{Sample, ComplexSample};
+use crate::pipewire::{self, PipeDevice};
+
+const SAMPLE_RATE: f64 = pipewire::SAMPLE_RATE;
+/// Peak deviation of FM transmitter in Hertz.
+const FM_DEVIATION: f64 = 2500.0;
+/// Maximum amount of audio buffered, in samples.
+/// If more arrives than is transmitted, the oldest audio is dropped
+/// to keep latency bounded.
+const MAX_BUFFERED: usize = 9600;
+
+pub struct ModulateFromPipewire {
+    center_frequency: f64,
+    modulator: VoxFmModulator,
+    /// Audio read from the pipe, waiting to be transmitted.
+    audio_fifo: Arc<Mutex<VecDeque<Sample>>>,
+    /// Keeps the device alive as long as the transmitter.
+    _device: PipeDevice,
+}
+
+impl ModulateFromPipewire {
+    /// Create a transmitter with given device name and frequency.
+    pub fn new(name: &str, center_frequency: f64) -> Self {
+        let device = PipeDevice::create_sink(name,
+            &format!("sdrglue {} {} MHz", name, center_frequency / 1e6));
+        let audio_fifo = Arc::new(Mutex::new(VecDeque::with_capacity(MAX_BUFFERED)));
+        let fifo = audio_fifo.clone();
+        let path = device.path.clone();
+        std::thread::spawn(move || {
+            let mut pipe = match std::fs::File::open(&path) {
+                Ok(pipe) => pipe,
+                Err(err) => {
+                    eprintln!("Could not open {}: {}", path, err);
+                    return;
+                },
+            };
+            let mut buffer = [0u8; 1920];
+            loop {
+                let len = match pipe.read(&mut buffer) {
+                    Ok(0) => break,
+                    Ok(len) => len,
+                    Err(err) => {
+                        eprintln!("Error reading {}: {}", path, err);
+                        break;
+                    },
+                };
+                let mut fifo = fifo.lock().unwrap();
+                for bytes in buffer[..len].chunks_exact(2) {
+                    if fifo.len() >= MAX_BUFFERED {
+                        fifo.pop_front();
+                    }
+                    fifo.push_back(i16::from_le_bytes([bytes[0], bytes[1]]) as Sample / i16::MAX as Sample);
+                }
+            }
+        });
+        Self {
+            center_frequency,
+            modulator: VoxFmModulator::new(SAMPLE_RATE, FM_DEVIATION),
+            audio_fifo,
+            _device: device,
+        }
+    }
+}
+
+impl TxChannelProcessor for ModulateFromPipewire {
+    fn process(&mut self, samples: &mut [ComplexSample]) {
+        let mut fifo = self.audio_fifo.lock().unwrap();
+        for sample in samples.iter_mut() {
+            // Fill gaps in audio with silence.
+            *sample = self.modulator.sample(fifo.pop_front().unwrap_or(0.0));
+        }
+    }
+
+    fn output_sample_rate(&self) -> f64 {
+        SAMPLE_RATE
+    }
+
+    fn output_center_frequency(&self) -> f64 {
+        self.center_frequency
+    }
+
+    fn modulation(&self) -> &'static str {
+        "FM"
+    }
+}
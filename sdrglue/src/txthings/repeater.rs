@@ -0,0 +1,109 @@
+//! Repeater transmitter fed by a receive voting group.
+//!
+//! Voting groups given with --vote-to-tx put the selected audio
+//! into a buffer shared with a transmit channel, which transmits it
+//! as FM. Audio is only passed while the selected receiver
+//! has a signal above the squelch level, and transmission
+//! is voice operated, so the repeater keys up when a signal is heard.
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+use super::TxChannelProcessor;
+use super::modulator::VoxFmModulator;
+use crate::{Sample, ComplexSample};
+
+const SAMPLE_RATE: f64 = 48000.0;
+/// Peak deviation of FM transmitter in Hertz.
+const FM_DEVIATION: f64 = 2500.0;
+/// Maximum amount of audio buffered, in samples.
+/// If receive processing runs ahead of transmit processing,
+/// the oldest audio is dropped to keep latency bounded.
+const MAX_BUFFERED: usize = 9600;
+
+/// Audio passed from a voter to a repeater transmitter.
+pub type RepeaterAudio = Arc<Mutex<VecDeque<Sample>>>;
+
+/// Audio buffers of repeaters, one for each --vote-to-tx group.
+static REPEATER_AUDIO: Mutex<Vec<RepeaterAudio>> = Mutex::new(Vec::new());
+
+/// Audio buffer of the repeater given by its index in --vote-to-tx.
+/// Receive and transmit processing get the same buffer.
+pub fn repeater_audio(index: usize) -> RepeaterAudio {
+    let mut buffers = REPEATER_AUDIO.lock().unwrap();
+    while buffers.len() <= index {
+        buffers.push(Arc::new(Mutex::new(VecDeque::with_capacity(MAX_BUFFERED))));
+    }
+    buffers[index].clone()
+}
+
+/// Append audio to be transmitted by a repeater.
+pub fn send_to_repeater(buffer: &RepeaterAudio, audio: &[Sample]) {
+    let mut buffer = buffer.lock().unwrap();
+    for &sample in audio {
+        if buffer.len() >= MAX_BUFFERED {
+            buffer.pop_front();
+        }
+        buffer.push_back(sample);
+    }
+}
+
+pub struct RepeaterTx {
+    center_frequency: f64,
+    modulator: VoxFmModulator,
+    audio: RepeaterAudio,
+}
+
+impl RepeaterTx {
+    pub fn new(audio: RepeaterAudio, center_frequency: f64) -> Self {
+        Self {
+            center_frequency,
+            modulator: VoxFmModulator::new(SAMPLE_RATE, FM_DEVIATION),
+            audio,
+        }
+    }
+}
+
+impl TxChannelProcessor for RepeaterTx {
+    fn process(&mut self, samples: &mut [ComplexSample]) {
+        let mut audio = self.audio.lock().unwrap();
+        for sample in samples.iter_mut() {
+            // Fill gaps in audio with silence.
+            *sample = self.modulator.sample(audio.pop_front().unwrap_or(0.0));
+        }
+    }
+
+    fn output_sample_rate(&self) -> f64 {
+        SAMPLE_RATE
+    }
+
+    fn output_center_frequency(&self) -> f64 {
+        self.center_frequency
+    }
+
+    fn modulation(&self) -> &'static str {
+        "FM"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_repeater_audio() {
+        let audio = repeater_audio(7);
+        assert!(Arc::ptr_eq(&audio, &repeater_audio(7)));
+        let mut tx = RepeaterTx::new(audio.clone(), 0.0);
+        let mut samples = vec![ComplexSample::ZERO; 100];
+        // Nothing is transmitted without audio.
+        tx.process(&mut samples);
+        assert!(samples.iter().all(|sample| *sample == ComplexSample::ZERO));
+        send_to_repeater(&audio, &[0.5; 50]);
+        tx.process(&mut samples);
+        assert!(samples.iter().all(|sample| (sample.norm() - 1.0).abs() < 1e-3));
+        assert!(audio.lock().unwrap().is_empty());
+        send_to_repeater(&audio, &vec![0.5; MAX_BUFFERED + 10]);
+        assert_eq!(audio.lock().unwrap().len(), MAX_BUFFERED);
+    }
+}
@@ -0,0 +1,78 @@
+//! Transmitter for audio frequency response measurement.
+//!
+//! Transmits a sequence of audio tones using FM,
+//! to be received by rxthings::ResponseTestRx
+//! which measures the level of each tone.
+//! The sequence starts with a silent step to give
+//! the receiver time to start, and is transmitted once.
+
+use super::TxChannelProcessor;
+use super::modulator::FmModulator;
+use crate::{Sample, ComplexSample};
+
+const SAMPLE_RATE: f64 = 48000.0;
+/// Audio tone frequencies in Hertz, in one third octave steps.
+pub const TONES: [f64; 19] = [
+    100.0, 125.0, 160.0, 200.0, 250.0, 315.0, 400.0, 500.0, 630.0, 800.0,
+    1000.0, 1250.0, 1600.0, 2000.0, 2500.0, 3150.0, 4000.0, 5000.0, 6300.0,
+];
+/// Duration of each tone in seconds.
+pub const STEP_DURATION: f64 = 0.5;
+/// Amplitude of tones relative to full scale.
+pub const TONE_AMPLITUDE: Sample = 0.5;
+/// Peak deviation in Hertz for a full scale tone.
+pub const FM_DEVIATION: f64 = 2500.0;
+
+pub struct ResponseTestTx {
+    center_frequency: f64,
+    modulator: FmModulator,
+    /// Number of samples transmitted.
+    sample_count: usize,
+    /// Tone phase in cycles.
+    tone_phase: f64,
+}
+
+impl ResponseTestTx {
+    pub fn new(center_frequency: f64) -> Self {
+        Self {
+            center_frequency,
+            modulator: FmModulator::new(SAMPLE_RATE, FM_DEVIATION),
+            sample_count: 0,
+            tone_phase: 0.0,
+        }
+    }
+
+    fn audio_sample(&mut self) -> Sample {
+        let step = (self.sample_count as f64 / (STEP_DURATION * SAMPLE_RATE)) as usize;
+        self.sample_count += 1;
+        // Step 0 is silence
+        match step.checked_sub(1).and_then(|tone| TONES.get(tone)) {
+            Some(&frequency) => {
+                self.tone_phase = (self.tone_phase + frequency / SAMPLE_RATE).fract();
+                TONE_AMPLITUDE * (std::f64::consts::TAU * self.tone_phase).sin() as Sample
+            },
+            None => 0.0,
+        }
+    }
+}
+
+impl TxChannelProcessor for ResponseTestTx {
+    fn process(&mut self, samples: &mut [ComplexSample]) {
+        for sample in samples.iter_mut() {
+            let audio = self.audio_sample();
+            *sample = self.modulator.sample(audio);
+        }
+    }
+
+    fn output_sample_rate(&self) -> f64 {
+        SAMPLE_RATE
+    }
+
+    fn output_center_frequency(&self) -> f64 {
+        self.center_frequency
+    }
+
+    fn modulation(&self) -> &'static str {
+        "FM"
+    }
+}
@@ -0,0 +1,126 @@
+//! Transmitters keyed by a dispatch console using tone remote control.
+//!
+//! Console audio is received as UDP packets of signed 16-bit
+//! little endian samples at 48 kHz, the same format sent
+//! by receive channels with UDP output.
+//! Several transmitters share a console: function tone F1 keys
+//! the first one, F2 the second one and so on.
+//! The guard tone is removed from the transmitted audio.
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::rc::Rc;
+
+use super::TxChannelProcessor;
+use super::modulator::FmModulator;
+use crate::{Sample, ComplexSample};
+use crate::tone_remote::ToneRemoteDecoder;
+
+const SAMPLE_RATE: f64 = 48000.0;
+/// Peak deviation of FM transmitter in Hertz.
+const FM_DEVIATION: f64 = 2500.0;
+/// Maximum amount of audio buffered from the console, in samples.
+/// If more arrives than is transmitted, the oldest audio is dropped
+/// to keep latency bounded.
+const MAX_BUFFERED: usize = 9600;
+
+/// Console input shared between transmitters.
+pub struct ToneRemote {
+    decoder: ToneRemoteDecoder,
+    /// Socket to receive console audio from.
+    socket: std::net::UdpSocket,
+    packet_buffer: Vec<u8>,
+    /// Audio received from the console, waiting to be transmitted.
+    audio_fifo: VecDeque<Sample>,
+    /// Audio for the current block, with guard tone removed.
+    audio: Vec<Sample>,
+    /// Number of the current block.
+    block: usize,
+}
+
+impl ToneRemote {
+    /// Receive console audio at given local address.
+    pub fn new(address: &str) -> Self {
+        // TODO: handle error somehow if binding the socket fails
+        let socket = std::net::UdpSocket::bind(address).unwrap();
+        socket.set_nonblocking(true).unwrap();
+        Self {
+            decoder: ToneRemoteDecoder::new(SAMPLE_RATE),
+            socket,
+            packet_buffer: vec![0u8; 4096],
+            audio_fifo: VecDeque::with_capacity(MAX_BUFFERED),
+            audio: Vec::new(),
+            block: 0,
+        }
+    }
+
+    /// Move on to given block, processing audio for it
+    /// unless another transmitter already did.
+    fn advance(&mut self, block: usize, length: usize) {
+        if self.block >= block {
+            return;
+        }
+        self.block = block;
+        while let Ok(len) = self.socket.recv(&mut self.packet_buffer) {
+            for bytes in self.packet_buffer[..len].chunks_exact(2) {
+                if self.audio_fifo.len() >= MAX_BUFFERED {
+                    self.audio_fifo.pop_front();
+                }
+                self.audio_fifo.push_back(i16::from_le_bytes([bytes[0], bytes[1]]) as Sample / i16::MAX as Sample);
+            }
+        }
+        self.audio.clear();
+        // Fill gaps in received audio with silence.
+        self.audio.extend((0..length).map(|_| self.audio_fifo.pop_front().unwrap_or(0.0)));
+        self.decoder.process(&mut self.audio);
+    }
+}
+
+pub struct ToneRemoteTx {
+    remote: Rc<RefCell<ToneRemote>>,
+    /// Index of the function tone keying this transmitter.
+    function: usize,
+    center_frequency: f64,
+    modulator: FmModulator,
+    /// Number of blocks processed.
+    block: usize,
+}
+
+impl ToneRemoteTx {
+    pub fn new(remote: Rc<RefCell<ToneRemote>>, function: usize, center_frequency: f64) -> Self {
+        Self {
+            remote,
+            function,
+            center_frequency,
+            modulator: FmModulator::new(SAMPLE_RATE, FM_DEVIATION),
+            block: 0,
+        }
+    }
+}
+
+impl TxChannelProcessor for ToneRemoteTx {
+    fn process(&mut self, samples: &mut [ComplexSample]) {
+        self.block += 1;
+        let mut remote = self.remote.borrow_mut();
+        remote.advance(self.block, samples.len());
+        if remote.decoder.keyed_function() != Some(self.function) {
+            samples.fill(ComplexSample::ZERO);
+            return;
+        }
+        for (sample, &audio) in samples.iter_mut().zip(remote.audio.iter()) {
+            *sample = self.modulator.sample(audio);
+        }
+    }
+
+    fn output_sample_rate(&self) -> f64 {
+        SAMPLE_RATE
+    }
+
+    fn output_center_frequency(&self) -> f64 {
+        self.center_frequency
+    }
+
+    fn modulation(&self) -> &'static str {
+        "FM"
+    }
+}
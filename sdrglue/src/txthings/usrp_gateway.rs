@@ -0,0 +1,124 @@
+//! Transmit side of a USRP protocol gateway
+//! for linking with AllStarLink/EchoLink nodes.
+
+use std::collections::VecDeque;
+
+use super::TxChannelProcessor;
+use super::modulator::FmModulator;
+use crate::{Sample, ComplexSample};
+use crate::filter;
+use crate::usrp;
+
+const SAMPLE_RATE: f64 = 48000.0;
+/// Peak deviation of FM transmitter in Hertz.
+const FM_DEVIATION: f64 = 2500.0;
+/// Maximum amount of audio buffered from the node, in samples.
+/// If more arrives than is transmitted, the oldest audio is dropped
+/// to keep latency bounded.
+const MAX_BUFFERED: usize = usrp::FRAME_SAMPLES * 25;
+/// Transmission is stopped if no packets are received
+/// for this many samples at USRP sample rate,
+/// in case the packet clearing keyup was lost.
+const KEYUP_TIMEOUT: usize = usrp::FRAME_SAMPLES * 25;
+
+pub struct UsrpGatewayTx {
+    /// Center frequency to transmit on.
+    center_frequency: f64,
+    modulator: FmModulator,
+    /// Interpolator from USRP sample rate to transmit sample rate.
+    interpolator: filter::Interpolator,
+    /// Whether the node is keyed, i.e. whether to transmit.
+    keyup: bool,
+    /// Number of audio samples transmitted since the previous packet.
+    samples_since_packet: usize,
+    /// Audio received from the node, waiting to be transmitted.
+    audio_fifo: VecDeque<Sample>,
+    /// Interpolated audio for the current block.
+    audio_buffer: Vec<Sample>,
+    /// Buffer for receiving packets.
+    packet_buffer: Vec<u8>,
+    /// Socket to receive packets from the node.
+    socket: std::net::UdpSocket,
+}
+
+pub struct UsrpGatewayTxParameters<'a> {
+    /// Center frequency to transmit on.
+    pub center_frequency: f64,
+    /// Local address to receive packets from the node.
+    pub address: &'a str,
+}
+
+impl UsrpGatewayTx {
+    pub fn new(parameters: &UsrpGatewayTxParameters) -> Self {
+        // TODO: handle error somehow if binding the socket fails
+        let socket = std::net::UdpSocket::bind(parameters.address).unwrap();
+        socket.set_nonblocking(true).unwrap();
+        Self {
+            center_frequency: parameters.center_frequency,
+            modulator: FmModulator::new(SAMPLE_RATE, FM_DEVIATION),
+            interpolator: filter::Interpolator::new((SAMPLE_RATE / usrp::SAMPLE_RATE).round() as usize),
+            keyup: false,
+            samples_since_packet: 0,
+            audio_fifo: VecDeque::with_capacity(MAX_BUFFERED),
+            audio_buffer: Vec::new(),
+            packet_buffer: vec![0u8; 2048],
+            socket,
+        }
+    }
+
+    /// Read all packets that have arrived since the previous block.
+    fn receive_packets(&mut self) {
+        while let Ok(len) = self.socket.recv(&mut self.packet_buffer) {
+            if let Some(packet) = usrp::parse_packet(&self.packet_buffer[..len]) {
+                self.keyup = packet.keyup;
+                self.samples_since_packet = 0;
+                for sample in packet.audio() {
+                    if self.audio_fifo.len() >= MAX_BUFFERED {
+                        self.audio_fifo.pop_front();
+                    }
+                    self.audio_fifo.push_back(sample as Sample / i16::MAX as Sample);
+                }
+            }
+        }
+    }
+}
+
+impl TxChannelProcessor for UsrpGatewayTx {
+    fn process(&mut self, samples: &mut [ComplexSample]) {
+        self.receive_packets();
+
+        if !self.keyup && self.audio_fifo.is_empty() {
+            samples.fill(ComplexSample::ZERO);
+            return;
+        }
+
+        self.audio_buffer.resize(samples.len(), 0.0);
+        let audio_fifo = &mut self.audio_fifo;
+        let samples_since_packet = &mut self.samples_since_packet;
+        self.interpolator.process(|| {
+            *samples_since_packet += 1;
+            // Fill gaps in received audio with silence.
+            audio_fifo.pop_front().unwrap_or(0.0)
+        }, &mut self.audio_buffer);
+
+        if self.samples_since_packet >= KEYUP_TIMEOUT {
+            self.keyup = false;
+        }
+
+        for (sample, &audio) in samples.iter_mut().zip(self.audio_buffer.iter()) {
+            *sample = self.modulator.sample(audio);
+        }
+    }
+
+    fn output_sample_rate(&self) -> f64 {
+        SAMPLE_RATE
+    }
+
+    fn output_center_frequency(&self) -> f64 {
+        self.center_frequency
+    }
+
+    fn modulation(&self) -> &'static str {
+        "FM"
+    }
+}
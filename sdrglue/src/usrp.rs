@@ -0,0 +1,77 @@
+//! "USRP" audio packet protocol.
+//!
+//! This is the UDP protocol used by the chan_usrp channel driver
+//! of Asterisk/AllStarLink and by DVSwitch.
+//! It carries 8 kHz 16-bit audio in 20 ms frames together with
+//! the keying (PTT) state, so it can be used to bridge RF channels
+//! to VoIP linking systems such as AllStarLink and,
+//! through those, EchoLink.
+//!
+//! Each packet starts with a 32-byte header:
+//! the characters "USRP" followed by seven 32-bit big endian words:
+//! sequence number, memory, keyup, talkgroup, type, mpxid, reserved.
+//! Voice packets (type 0) are followed by 160 audio samples
+//! as signed 16-bit little endian.
+
+use byteorder::{ByteOrder, BigEndian, LittleEndian};
+
+/// Sample rate of the audio.
+pub const SAMPLE_RATE: f64 = 8000.0;
+/// Number of audio samples in a voice packet.
+pub const FRAME_SAMPLES: usize = 160;
+
+const HEADER_LEN: usize = 32;
+const MAGIC: &[u8; 4] = b"USRP";
+const TYPE_VOICE: u32 = 0;
+
+/// Information from a received packet.
+pub struct Packet<'a> {
+    /// Keying state of the sender.
+    pub keyup: bool,
+    /// Audio payload of a voice packet as raw bytes.
+    /// Empty for other packet types.
+    audio: &'a [u8],
+}
+
+impl<'a> Packet<'a> {
+    /// Audio samples in a voice packet.
+    pub fn audio(&self) -> impl Iterator<Item = i16> + 'a {
+        self.audio.chunks_exact(2).map(LittleEndian::read_i16)
+    }
+}
+
+/// Parse a received packet.
+/// Return None if the packet is not valid.
+pub fn parse_packet(data: &[u8]) -> Option<Packet<'_>> {
+    if data.len() < HEADER_LEN || &data[0..4] != MAGIC {
+        return None;
+    }
+    let keyup = BigEndian::read_u32(&data[12..16]) != 0;
+    let packet_type = BigEndian::read_u32(&data[20..24]);
+    Some(Packet {
+        keyup,
+        audio: if packet_type == TYPE_VOICE { &data[HEADER_LEN..] } else { &[] },
+    })
+}
+
+/// Write a voice packet to buffer.
+/// Audio should contain FRAME_SAMPLES samples.
+pub fn write_voice_packet(
+    buffer: &mut Vec<u8>,
+    sequence: u32,
+    keyup: bool,
+    audio: &[i16],
+) {
+    buffer.clear();
+    buffer.extend_from_slice(MAGIC);
+    let mut header = [0u8; HEADER_LEN - 4];
+    BigEndian::write_u32(&mut header[0..4], sequence);
+    BigEndian::write_u32(&mut header[8..12], keyup as u32);
+    BigEndian::write_u32(&mut header[16..20], TYPE_VOICE);
+    buffer.extend_from_slice(&header);
+    for &sample in audio {
+        let mut bytes = [0u8; 2];
+        LittleEndian::write_i16(&mut bytes, sample);
+        buffer.extend_from_slice(&bytes);
+    }
+}
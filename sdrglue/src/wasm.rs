@@ -0,0 +1,150 @@
+//! JavaScript bindings for receiving signals in a browser,
+//! for example to channelize and demodulate uploaded IQ files.
+//!
+//! Build without SoapySDR and with the wasm feature:
+//! ```text
+//! cargo build --release --lib --target wasm32-unknown-unknown --no-default-features --features wasm
+//! wasm-bindgen --target web --out-dir pkg target/wasm32-unknown-unknown/release/sdrglue.wasm
+//! ```
+//! Complex signals are passed as Float32Arrays
+//! with interleaved real and imaginary parts.
+
+use wasm_bindgen::prelude::*;
+
+use crate::{Sample, ComplexSample};
+use crate::demodulation::{self, Demodulator, Modulation};
+use crate::fcfb;
+
+/// Default pitch of CW audio in Hertz.
+const CW_PITCH: f64 = 600.0;
+/// Default CW filter bandwidth in Hertz.
+const CW_BANDWIDTH: f64 = 500.0;
+
+/// Sample rate of demodulated audio in Hertz.
+#[wasm_bindgen]
+pub fn audio_sample_rate() -> f64 {
+    demodulation::SAMPLE_RATE
+}
+
+fn parse_modulation(name: &str) -> Option<Modulation> {
+    Some(match name.to_uppercase().as_str() {
+        "FM"  => Modulation::FM,
+        "USB" => Modulation::USB,
+        "LSB" => Modulation::LSB,
+        "AM"  => Modulation::AM,
+        "SAM" | "SYNCAM" => Modulation::SyncAM,
+        "CW"  => Modulation::CW { pitch: CW_PITCH, bandwidth: CW_BANDWIDTH },
+        _ => return None,
+    })
+}
+
+/// Receiver splitting a wideband signal into channels
+/// and demodulating each of them.
+#[wasm_bindgen]
+pub struct Receiver {
+    fft_planner: rustfft::FftPlanner<Sample>,
+    channelizer: fcfb::Channelizer,
+    demodulators: Vec<Demodulator>,
+    /// Input converted to complex samples.
+    input: Vec<ComplexSample>,
+    /// Demodulated audio of each channel
+    /// from the latest call to process.
+    audio: Vec<Vec<Sample>>,
+}
+
+#[wasm_bindgen]
+impl Receiver {
+    /// Create a receiver for input signal with given sample rate,
+    /// center frequency and filter bank bin spacing in Hertz.
+    /// Sample rate should be an integer multiple of 2 * bin spacing.
+    /// If unsure, use a bin spacing of 500 Hz.
+    #[wasm_bindgen(constructor)]
+    pub fn new(sample_rate: f64, center_frequency: f64, bin_spacing: f64) -> Self {
+        let mut fft_planner = rustfft::FftPlanner::new();
+        let channelizer = fcfb::Channelizer::new(&mut fft_planner, sample_rate, center_frequency, bin_spacing);
+        Self {
+            fft_planner,
+            channelizer,
+            demodulators: Vec::new(),
+            input: Vec::new(),
+            audio: Vec::new(),
+        }
+    }
+
+    /// Add a channel demodulating given frequency in Hertz
+    /// with modulation FM, AM, SAM, USB, LSB or CW.
+    /// Return index of the channel.
+    pub fn add_channel(&mut self, frequency: f64, modulation: &str) -> Result<usize, JsError> {
+        let modulation = parse_modulation(modulation)
+            .ok_or_else(|| JsError::new(&format!("Unknown modulation {}", modulation)))?;
+        self.channelizer.add_channel(&mut self.fft_planner,
+            demodulation::SAMPLE_RATE, frequency + Demodulator::frequency_offset(modulation));
+        self.demodulators.push(Demodulator::new(modulation));
+        self.audio.push(Vec::new());
+        Ok(self.demodulators.len() - 1)
+    }
+
+    /// Process any number of interleaved IQ samples.
+    /// Audio demodulated from them can be read with audio.
+    pub fn process(&mut self, samples: &[f32]) {
+        self.input.clear();
+        self.input.extend(samples.chunks_exact(2).map(|iq| ComplexSample { re: iq[0], im: iq[1] }));
+        self.channelizer.push(&self.input);
+        for (channel, (demodulator, audio)) in self.demodulators.iter_mut().zip(self.audio.iter_mut()).enumerate() {
+            audio.clear();
+            demodulator.process(self.channelizer.output(channel), audio);
+        }
+    }
+
+    /// Audio of a channel demodulated by the latest call to process,
+    /// at the sample rate given by audio_sample_rate.
+    pub fn audio(&self, channel: usize) -> Result<Vec<f32>, JsError> {
+        Ok(self.audio[self.check_channel(channel)?].clone())
+    }
+
+    /// Mean power of a channel during the latest processed block.
+    pub fn power(&self, channel: usize) -> Result<f32, JsError> {
+        Ok(self.demodulators[self.check_channel(channel)?].power())
+    }
+}
+
+impl Receiver {
+    fn check_channel(&self, channel: usize) -> Result<usize, JsError> {
+        if channel < self.demodulators.len() {
+            Ok(channel)
+        } else {
+            Err(JsError::new(&format!("No channel {}", channel)))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_receiver() {
+        // FM signal with 3 kHz deviation by a 1 kHz tone
+        // on a channel 20 kHz above center.
+        let sample_rate = 200000.0;
+        let mut receiver = Receiver::new(sample_rate, 145e6, 500.0);
+        let other = receiver.add_channel(145.05e6, "FM").unwrap();
+        let channel = receiver.add_channel(145.02e6, "fm").unwrap();
+        assert_eq!(channel, 1);
+        let mut phase = 0.0f64;
+        let samples: Vec<f32> = (0..200000).flat_map(|i| {
+            let t = i as f64 / sample_rate;
+            phase += 2.0 * std::f64::consts::PI
+                * (20000.0 + 3000.0 * (2.0 * std::f64::consts::PI * 1000.0 * t).sin()) / sample_rate;
+            [phase.cos() as f32, phase.sin() as f32]
+        }).collect();
+        receiver.process(&samples);
+        let audio = receiver.audio(channel).unwrap();
+        assert!(audio.len() > 40000);
+        // Audio is scaled so that half of sample rate is full scale.
+        let peak = audio[24000..].iter().fold(0.0f32, |peak, &x| peak.max(x.abs()));
+        assert!((peak - 0.125).abs() < 0.01, "peak {}", peak);
+        assert!(receiver.power(channel).unwrap() > 0.5);
+        assert!(receiver.power(other).unwrap() < 0.01);
+    }
+}
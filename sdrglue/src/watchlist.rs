@@ -0,0 +1,169 @@
+//! Watchlist of rules raising alerts from other events.
+//!
+//! Each rule has a name and a list of conditions on event fields.
+//! When an event matches all conditions of a rule,
+//! an "alert" event is emitted with the rule name
+//! and the fields of the triggering event,
+//! so that alerts can be handled by event hooks like any other event.
+//! Receive channels given with --record-alert record the audio
+//! around alerts of a rule, seeing them through alert_count.
+
+use std::sync::Mutex;
+
+use crate::events::{self, Event, EventListener, Value};
+
+/// Number of alerts raised by each rule, as pairs of rule name and count.
+static ALERT_COUNTS: Mutex<Vec<(String, u64)>> = Mutex::new(Vec::new());
+
+/// Number of alerts raised by given rule since start.
+pub fn alert_count(rule: &str) -> u64 {
+    ALERT_COUNTS.lock().unwrap().iter()
+        .find(|(name, _)| name == rule)
+        .map_or(0, |(_, count)| *count)
+}
+
+fn count_alert(rule: &str) {
+    let mut counts = ALERT_COUNTS.lock().unwrap();
+    match counts.iter_mut().find(|(name, _)| name == rule) {
+        Some((_, count)) => *count += 1,
+        None => counts.push((rule.to_string(), 1)),
+    }
+}
+
+enum Comparison {
+    /// Field is equal to the value.
+    /// Numbers are compared numerically with a tolerance of 1,
+    /// which is convenient for frequencies in Hertz.
+    Equal,
+    /// Field is greater than the value.
+    Greater,
+    /// Field is less than the value.
+    Less,
+    /// Field contains the value as a substring, ignoring case.
+    Contains,
+}
+
+struct Condition {
+    key: String,
+    comparison: Comparison,
+    value: String,
+}
+
+impl Condition {
+    fn parse(text: &str) -> Option<Self> {
+        let position = text.find(['=', '>', '<', '~'])?;
+        let comparison = match &text[position..position+1] {
+            "=" => Comparison::Equal,
+            ">" => Comparison::Greater,
+            "<" => Comparison::Less,
+            _   => Comparison::Contains,
+        };
+        Some(Self {
+            key: text[..position].to_string(),
+            comparison,
+            value: text[position+1..].to_string(),
+        })
+    }
+
+    fn matches(&self, event: &Event) -> bool {
+        let field = if self.key == "event" {
+            Value::from(event.kind)
+        } else {
+            match event.fields.iter().find(|(key, _)| *key == self.key) {
+                Some((_, value)) => value.clone(),
+                None => return false,
+            }
+        };
+        let number = self.value.parse::<f64>().ok();
+        match (&self.comparison, field, number) {
+            (Comparison::Equal,   Value::Number(f), Some(n)) => (f - n).abs() < 1.0,
+            (Comparison::Greater, Value::Number(f), Some(n)) => f > n,
+            (Comparison::Less,    Value::Number(f), Some(n)) => f < n,
+            (Comparison::Equal,   Value::Str(s), _) => s == self.value,
+            (Comparison::Contains, Value::Str(s), _) =>
+                s.to_lowercase().contains(&self.value.to_lowercase()),
+            _ => false,
+        }
+    }
+}
+
+struct Rule {
+    name: String,
+    conditions: Vec<Condition>,
+}
+
+pub struct Watchlist {
+    rules: Vec<Rule>,
+}
+
+impl Watchlist {
+    /// Create a watchlist from pairs of rule name and conditions.
+    /// Conditions are separated by spaces and look like
+    /// key=value, key>value, key<value or key~substring.
+    pub fn from_args(args: &[String]) -> Self {
+        Self {
+            rules: args.chunks_exact(2).map(|rule| Rule {
+                name: rule[0].clone(),
+                conditions: rule[1].split_whitespace().map(|condition|
+                    // TODO: handle errors more nicely
+                    Condition::parse(condition).unwrap_or_else(|| panic!("Invalid alert condition {}", condition))
+                ).collect(),
+            }).collect(),
+        }
+    }
+}
+
+impl EventListener for Watchlist {
+    fn event(&mut self, event: &Event) {
+        // Do not raise alerts from alerts, since that could end up
+        // in an infinite loop with a rule without an event condition.
+        if event.kind == "alert" {
+            return;
+        }
+        for rule in self.rules.iter() {
+            if rule.conditions.iter().all(|condition| condition.matches(event)) {
+                let mut alert = Event::new("alert")
+                    .with("rule", rule.name.as_str())
+                    .with("trigger", event.kind);
+                alert.fields.extend(event.fields.iter().cloned());
+                events::emit(alert);
+                count_alert(&rule.name);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_conditions() {
+        let event = Event::new("squelch_open")
+            .with("frequency", 145.5e6)
+            .with("power_db", -42.0)
+            .with("text", "CQ de OH2AAA");
+        let check = |condition: &str| Condition::parse(condition).unwrap().matches(&event);
+        assert!(check("event=squelch_open"));
+        assert!(!check("event=squelch_close"));
+        assert!(check("frequency=145.5e6"));
+        assert!(check("frequency=145500000.5"));
+        assert!(!check("frequency=145.6e6"));
+        assert!(check("power_db>-50"));
+        assert!(!check("power_db<-50"));
+        assert!(check("text~oh2aaa"));
+        assert!(!check("text~OH3"));
+        assert!(!check("missing=1"));
+        assert!(Condition::parse("nocomparison").is_none());
+    }
+
+    #[test]
+    fn test_alert_count() {
+        let mut watchlist = Watchlist::from_args(&["test_alert_count".to_string(), "event=test".to_string()]);
+        watchlist.event(&Event::new("other"));
+        assert_eq!(alert_count("test_alert_count"), 0);
+        watchlist.event(&Event::new("test"));
+        watchlist.event(&Event::new("test"));
+        assert_eq!(alert_count("test_alert_count"), 2);
+    }
+}
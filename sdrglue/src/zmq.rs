@@ -0,0 +1,52 @@
+//! ZeroMQ PUB sockets for channel outputs.
+//!
+//! Channels publishing to the same endpoint share one socket,
+//! so that each of them can be given its own topic. Messages
+//! with a topic are sent in two parts, the topic and the data,
+//! as expected by GNU Radio ZMQ SUB Source blocks given the
+//! topic as their key. Without a topic, only data is sent.
+//! Sending never blocks: if a subscriber is too slow,
+//! ZeroMQ drops messages for it.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+thread_local! {
+    static CONTEXT: zmq::Context = zmq::Context::new();
+    /// Sockets by endpoint.
+    static SOCKETS: RefCell<HashMap<String, Rc<zmq::Socket>>> = RefCell::new(HashMap::new());
+}
+
+pub struct Publisher {
+    socket: Rc<zmq::Socket>,
+    topic: Option<Vec<u8>>,
+}
+
+impl Publisher {
+    /// Publish on a socket bound to given endpoint,
+    /// such as tcp://*:5555, with an optional topic.
+    pub fn new(endpoint: &str, topic: Option<&str>) -> Self {
+        let socket = SOCKETS.with_borrow_mut(|sockets| sockets.entry(endpoint.to_string()).or_insert_with(|| {
+            // TODO: handle errors more nicely
+            let socket = CONTEXT.with(|context| context.socket(zmq::PUB))
+                .unwrap_or_else(|e| panic!("Could not create ZeroMQ socket: {}", e));
+            socket.bind(endpoint)
+                .unwrap_or_else(|e| panic!("Could not bind ZeroMQ socket to {}: {}", endpoint, e));
+            Rc::new(socket)
+        }).clone());
+        Self {
+            socket,
+            topic: topic.map(|topic| topic.as_bytes().to_vec()),
+        }
+    }
+
+    pub fn send(&self, data: &[u8]) {
+        // TODO: print a warning or something if sending fails
+        let _ = match &self.topic {
+            Some(topic) => self.socket.send(topic.as_slice(), zmq::SNDMORE | zmq::DONTWAIT)
+                .and_then(|_| self.socket.send(data, zmq::DONTWAIT)),
+            None => self.socket.send(data, zmq::DONTWAIT),
+        };
+    }
+}